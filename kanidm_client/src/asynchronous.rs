@@ -2,6 +2,7 @@ use crate::{ClientError, KanidmClientBuilder, APPLICATION_JSON};
 use reqwest::header::CONTENT_TYPE;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::sync::RwLock;
 
 use kanidm_proto::v1::*;
 
@@ -10,9 +11,29 @@ pub struct KanidmAsyncClient {
     pub(crate) client: reqwest::Client,
     pub(crate) addr: String,
     pub(crate) builder: KanidmClientBuilder,
+    // The bearer token from the last successful authentication.
+    pub(crate) bearer: RwLock<Option<String>>,
 }
 
 impl KanidmAsyncClient {
+    fn with_bearer(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.bearer.read().unwrap().as_ref() {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn perform_auth_request(
+        &self,
+        request: AuthRequest,
+    ) -> Result<AuthResponse, ClientError> {
+        let r: AuthResponse = self.perform_post_request("/v1/auth", request).await?;
+        if r.bearer.is_some() {
+            *self.bearer.write().unwrap() = r.bearer.clone();
+        }
+        Ok(r)
+    }
+
     async fn perform_post_request<R: Serialize, T: DeserializeOwned>(
         &self,
         dest: &str,
@@ -26,8 +47,7 @@ impl KanidmAsyncClient {
         let req_string = serde_json::to_string(&request).unwrap();
 
         let response = self
-            .client
-            .post(dest.as_str())
+            .with_bearer(self.client.post(dest.as_str()))
             .body(req_string)
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .send()
@@ -58,8 +78,7 @@ impl KanidmAsyncClient {
         let req_string = serde_json::to_string(&request).unwrap();
 
         let response = self
-            .client
-            .put(dest.as_str())
+            .with_bearer(self.client.put(dest.as_str()))
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .body(req_string)
             .send()
@@ -82,8 +101,7 @@ impl KanidmAsyncClient {
         debug!("{:?}", dest);
         // let dest = format!("{}{}", self.addr, dest);
         let response = self
-            .client
-            .get(dest.as_str())
+            .with_bearer(self.client.get(dest.as_str()))
             .send()
             .await
             .map_err(ClientError::Transport)?;
@@ -102,8 +120,7 @@ impl KanidmAsyncClient {
     async fn perform_delete_request(&self, dest: &str) -> Result<(), ClientError> {
         let dest = format!("{}{}", self.addr, dest);
         let response = self
-            .client
-            .delete(dest.as_str())
+            .with_bearer(self.client.delete(dest.as_str()))
             .send()
             .await
             .map_err(ClientError::Transport)?;
@@ -125,7 +142,7 @@ impl KanidmAsyncClient {
             step: AuthStep::Init(ident.to_string(), appid.map(|s| s.to_string())),
         };
 
-        let r: Result<AuthResponse, _> = self.perform_post_request("/v1/auth", auth_init).await;
+        let r = self.perform_auth_request(auth_init).await;
         r.map(|v| v.state)
     }
 
@@ -142,7 +159,7 @@ impl KanidmAsyncClient {
         let auth_req = AuthRequest {
             step: AuthStep::Creds(vec![AuthCredential::Password(password.to_string())]),
         };
-        let r: Result<AuthResponse, _> = self.perform_post_request("/v1/auth", auth_req).await;
+        let r = self.perform_auth_request(auth_req).await;
 
         let r = r?;
 
@@ -165,7 +182,7 @@ impl KanidmAsyncClient {
         let auth_anon = AuthRequest {
             step: AuthStep::Creds(vec![AuthCredential::Anonymous]),
         };
-        let r: Result<AuthResponse, _> = self.perform_post_request("/v1/auth", auth_anon).await;
+        let r = self.perform_auth_request(auth_anon).await;
 
         let r = r?;
 
@@ -182,7 +199,11 @@ impl KanidmAsyncClient {
         let whoami_dest = [self.addr.as_str(), "/v1/self"].concat();
         // format!("{}/v1/self", self.addr);
         debug!("{:?}", whoami_dest);
-        let response = self.client.get(whoami_dest.as_str()).send().await.unwrap();
+        let response = self
+            .with_bearer(self.client.get(whoami_dest.as_str()))
+            .send()
+            .await
+            .unwrap();
 
         match response.status() {
             // Continue to process.
@@ -216,6 +237,17 @@ impl KanidmAsyncClient {
             .await
     }
 
+    pub async fn idm_account_set_attr(
+        &self,
+        id: &str,
+        attr: &str,
+        values: Vec<&str>,
+    ) -> Result<(), ClientError> {
+        let m: Vec<_> = values.iter().map(|v| (*v).to_string()).collect();
+        self.perform_put_request(["/v1/account/", id, "/_attr/", attr].concat().as_str(), m)
+            .await
+    }
+
     pub async fn idm_group_delete(&self, id: &str) -> Result<(), ClientError> {
         self.perform_delete_request(["/v1/group/", id].concat().as_str())
             .await
@@ -12,15 +12,21 @@ use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
+use std::sync::RwLock;
 use std::time::Duration;
 use uuid::Uuid;
+use webauthn_rs::proto::{
+    CreationChallengeResponse, PublicKeyCredential, RegisterPublicKeyCredential,
+    RequestChallengeResponse,
+};
 
 use kanidm_proto::v1::{
-    AccountUnixExtend, AuthCredential, AuthRequest, AuthResponse, AuthState, AuthStep,
-    CreateRequest, DeleteRequest, Entry, Filter, GroupUnixExtend, ModifyList, ModifyRequest,
-    OperationError, OperationResponse, RadiusAuthToken, SearchRequest, SearchResponse,
-    SetCredentialRequest, SetCredentialResponse, SingleStringRequest, TOTPSecret, UnixGroupToken,
-    UnixUserToken, UserAuthToken, WhoamiResponse,
+    AccessExplainRequest, AccessExplainResponse, AccessOperation, AccountUnixExtend, AppPassword,
+    AuthAllowed, AuthCredential, AuthRequest, AuthResponse, AuthState, AuthStep, CreateRequest,
+    CredentialInfo, CredentialResetRequest, DeleteRequest, Entry, Filter, GroupUnixExtend,
+    ModifyList, ModifyRequest, OperationError, OperationResponse, RadiusAuthToken, SearchRequest,
+    SearchResponse, SessionInfo, SetCredentialRequest, SetCredentialResponse, SingleStringRequest,
+    TOTPSecret, UnixGroupToken, UnixUserToken, UserAuthToken, WhoamiResponse,
 };
 
 pub mod asynchronous;
@@ -219,6 +225,7 @@ impl KanidmClientBuilder {
             client,
             addr: address,
             builder: self,
+            bearer: RwLock::new(None),
         })
     }
 
@@ -255,6 +262,7 @@ impl KanidmClientBuilder {
             client,
             addr: address,
             builder: self,
+            bearer: RwLock::new(None),
         })
     }
 }
@@ -264,6 +272,8 @@ pub struct KanidmClient {
     client: reqwest::blocking::Client,
     addr: String,
     builder: KanidmClientBuilder,
+    // The bearer token from the last successful authentication.
+    bearer: RwLock<Option<String>>,
 }
 
 impl KanidmClient {
@@ -282,9 +292,39 @@ impl KanidmClient {
         let KanidmClient { mut client, .. } = builder.build()?;
 
         std::mem::swap(&mut self.client, &mut client);
+        self.set_token(None);
         Ok(())
     }
 
+    /// The bearer token of this session, if it has authenticated.
+    pub fn get_token(&self) -> Option<String> {
+        self.bearer.read().unwrap().clone()
+    }
+
+    /// Replace the bearer token that is sent with each request, such as to use a token
+    /// that was issued to another client.
+    pub fn set_token(&self, token: Option<String>) {
+        *self.bearer.write().unwrap() = token;
+    }
+
+    fn with_bearer(
+        &self,
+        request: reqwest::blocking::RequestBuilder,
+    ) -> reqwest::blocking::RequestBuilder {
+        match self.bearer.read().unwrap().as_ref() {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    fn perform_auth_request(&self, request: AuthRequest) -> Result<AuthResponse, ClientError> {
+        let r: AuthResponse = self.perform_post_request("/v1/auth", request)?;
+        if r.bearer.is_some() {
+            self.set_token(r.bearer.clone());
+        }
+        Ok(r)
+    }
+
     fn perform_post_request<R: Serialize, T: DeserializeOwned>(
         &self,
         dest: &str,
@@ -295,8 +335,7 @@ impl KanidmClient {
         let req_string = serde_json::to_string(&request).unwrap();
 
         let response = self
-            .client
-            .post(dest.as_str())
+            .with_bearer(self.client.post(dest.as_str()))
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .body(req_string)
             .send()
@@ -323,8 +362,7 @@ impl KanidmClient {
         let req_string = serde_json::to_string(&request).unwrap();
 
         let response = self
-            .client
-            .put(dest.as_str())
+            .with_bearer(self.client.put(dest.as_str()))
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .body(req_string)
             .send()
@@ -344,8 +382,7 @@ impl KanidmClient {
     fn perform_get_request<T: DeserializeOwned>(&self, dest: &str) -> Result<T, ClientError> {
         let dest = format!("{}{}", self.addr, dest);
         let response = self
-            .client
-            .get(dest.as_str())
+            .with_bearer(self.client.get(dest.as_str()))
             .send()
             .map_err(ClientError::Transport)?;
 
@@ -363,8 +400,7 @@ impl KanidmClient {
     fn perform_delete_request(&self, dest: &str) -> Result<(), ClientError> {
         let dest = format!("{}{}", self.addr, dest);
         let response = self
-            .client
-            .delete(dest.as_str())
+            .with_bearer(self.client.delete(dest.as_str()))
             .send()
             .map_err(ClientError::Transport)?;
 
@@ -380,7 +416,10 @@ impl KanidmClient {
     // Can't use generic get due to possible un-auth case.
     pub fn whoami(&self) -> Result<Option<(Entry, UserAuthToken)>, ClientError> {
         let whoami_dest = format!("{}/v1/self", self.addr);
-        let response = self.client.get(whoami_dest.as_str()).send().unwrap();
+        let response = self
+            .with_bearer(self.client.get(whoami_dest.as_str()))
+            .send()
+            .unwrap();
 
         match response.status() {
             // Continue to process.
@@ -405,7 +444,7 @@ impl KanidmClient {
         let auth_anon = AuthRequest {
             step: AuthStep::Creds(vec![AuthCredential::Anonymous]),
         };
-        let r: Result<AuthResponse, _> = self.perform_post_request("/v1/auth", auth_anon);
+        let r = self.perform_auth_request(auth_anon);
 
         let r = r?;
 
@@ -431,7 +470,7 @@ impl KanidmClient {
         let auth_req = AuthRequest {
             step: AuthStep::Creds(vec![AuthCredential::Password(password.to_string())]),
         };
-        let r: Result<AuthResponse, _> = self.perform_post_request("/v1/auth", auth_req);
+        let r = self.perform_auth_request(auth_req);
 
         let r = r?;
 
@@ -444,6 +483,52 @@ impl KanidmClient {
         }
     }
 
+    /// Authenticate again with the password of the current session, raising it to
+    /// privileged for a short time.
+    pub fn reauth_simple_password(&self, password: &str) -> Result<UserAuthToken, ClientError> {
+        let _state = self.reauth_step_init()?;
+
+        let auth_req = AuthRequest {
+            step: AuthStep::Creds(vec![AuthCredential::Password(password.to_string())]),
+        };
+        let r = self.perform_auth_request(auth_req)?;
+
+        match r.state {
+            AuthState::Success(uat) => {
+                debug!("==> Reauthed as uat; {:?}", uat);
+                Ok(uat)
+            }
+            _ => Err(ClientError::AuthenticationFailed),
+        }
+    }
+
+    /// Authenticate with an application password. This is only valid for the named
+    /// application, and the resulting session can not act on the server.
+    pub fn auth_app_password(
+        &self,
+        ident: &str,
+        application: &str,
+        password: &str,
+    ) -> Result<UserAuthToken, ClientError> {
+        let _state = match self.auth_step_init(ident, Some(application)) {
+            Ok(s) => s,
+            Err(e) => return Err(e),
+        };
+
+        let auth_req = AuthRequest {
+            step: AuthStep::Creds(vec![AuthCredential::Password(password.to_string())]),
+        };
+        let r = self.perform_auth_request(auth_req);
+
+        match r?.state {
+            AuthState::Success(uat) => {
+                debug!("==> Authed as uat; {:?}", uat);
+                Ok(uat)
+            }
+            _ => Err(ClientError::AuthenticationFailed),
+        }
+    }
+
     pub fn auth_password_totp(
         &self,
         ident: &str,
@@ -461,7 +546,76 @@ impl KanidmClient {
                 AuthCredential::Password(password.to_string()),
             ]),
         };
-        let r: Result<AuthResponse, _> = self.perform_post_request("/v1/auth", auth_req);
+        let r = self.perform_auth_request(auth_req);
+
+        let r = r?;
+
+        match r.state {
+            AuthState::Success(uat) => {
+                debug!("==> Authed as uat; {:?}", uat);
+                Ok(uat)
+            }
+            _ => Err(ClientError::AuthenticationFailed),
+        }
+    }
+
+    pub fn auth_password_backup_code(
+        &self,
+        ident: &str,
+        password: &str,
+        code: &str,
+    ) -> Result<UserAuthToken, ClientError> {
+        let _state = match self.auth_step_init(ident, None) {
+            Ok(s) => s,
+            Err(e) => return Err(e),
+        };
+
+        let auth_req = AuthRequest {
+            step: AuthStep::Creds(vec![
+                AuthCredential::BackupCode(code.to_string()),
+                AuthCredential::Password(password.to_string()),
+            ]),
+        };
+        let r = self.perform_auth_request(auth_req);
+
+        let r = r?;
+
+        match r.state {
+            AuthState::Success(uat) => {
+                debug!("==> Authed as uat; {:?}", uat);
+                Ok(uat)
+            }
+            _ => Err(ClientError::AuthenticationFailed),
+        }
+    }
+
+    pub fn auth_webauthn_begin(
+        &self,
+        ident: &str,
+    ) -> Result<RequestChallengeResponse, ClientError> {
+        let state = self.auth_step_init(ident, None)?;
+
+        match state {
+            AuthState::Continue(mechs) => mechs
+                .into_iter()
+                .filter_map(|m| match m {
+                    AuthAllowed::Webauthn(chal) => Some(chal),
+                    _ => None,
+                })
+                .next()
+                .ok_or(ClientError::AuthenticationFailed),
+            _ => Err(ClientError::AuthenticationFailed),
+        }
+    }
+
+    pub fn auth_webauthn_complete(
+        &self,
+        pkc: PublicKeyCredential,
+    ) -> Result<UserAuthToken, ClientError> {
+        let auth_req = AuthRequest {
+            step: AuthStep::Creds(vec![AuthCredential::Webauthn(pkc)]),
+        };
+        let r = self.perform_auth_request(auth_req);
 
         let r = r?;
 
@@ -511,6 +665,34 @@ impl KanidmClient {
         r.map(|_| ())
     }
 
+    pub fn idm_account_generate_backup_codes(&self) -> Result<Vec<String>, ClientError> {
+        self.perform_post_request("/v1/self/_credential/primary/backup_codes", ())
+    }
+
+    pub fn idm_self_credential_list(&self) -> Result<Vec<CredentialInfo>, ClientError> {
+        self.perform_get_request("/v1/self/_credential")
+    }
+
+    pub fn idm_self_credential_lock(&self, cid: &str) -> Result<(), ClientError> {
+        self.perform_post_request(format!("/v1/self/_credential/{}/_lock", cid).as_str(), ())
+    }
+
+    pub fn idm_self_credential_unlock(&self, cid: &str) -> Result<(), ClientError> {
+        self.perform_delete_request(format!("/v1/self/_credential/{}/_lock", cid).as_str())
+    }
+
+    pub fn idm_self_credential_delete(&self, cid: &str) -> Result<(), ClientError> {
+        self.perform_delete_request(format!("/v1/self/_credential/{}", cid).as_str())
+    }
+
+    pub fn idm_self_session_list(&self) -> Result<Vec<SessionInfo>, ClientError> {
+        self.perform_get_request("/v1/self/_session")
+    }
+
+    pub fn idm_self_session_revoke(&self, sid: &str) -> Result<(), ClientError> {
+        self.perform_delete_request(format!("/v1/self/_session/{}", sid).as_str())
+    }
+
     pub fn auth_step_init(
         &self,
         ident: &str,
@@ -520,7 +702,16 @@ impl KanidmClient {
             step: AuthStep::Init(ident.to_string(), appid.map(|s| s.to_string())),
         };
 
-        let r: Result<AuthResponse, _> = self.perform_post_request("/v1/auth", auth_init);
+        let r = self.perform_auth_request(auth_init);
+        r.map(|v| v.state)
+    }
+
+    pub fn reauth_step_init(&self) -> Result<AuthState, ClientError> {
+        let auth_init = AuthRequest {
+            step: AuthStep::Reauth,
+        };
+
+        let r = self.perform_auth_request(auth_init);
         r.map(|v| v.state)
     }
 
@@ -557,6 +748,38 @@ impl KanidmClient {
         self.perform_delete_request(format!("/v1/group/{}/_attr/member", id).as_str())
     }
 
+    pub fn idm_group_get_attr(
+        &self,
+        id: &str,
+        attr: &str,
+    ) -> Result<Option<Vec<String>>, ClientError> {
+        self.perform_get_request(format!("/v1/group/{}/_attr/{}", id, attr).as_str())
+    }
+
+    pub fn idm_group_add_attr(
+        &self,
+        id: &str,
+        attr: &str,
+        values: Vec<&str>,
+    ) -> Result<(), ClientError> {
+        let m: Vec<_> = values.iter().map(|v| (*v).to_string()).collect();
+        self.perform_post_request(format!("/v1/group/{}/_attr/{}", id, attr).as_str(), m)
+    }
+
+    pub fn idm_group_set_attr(
+        &self,
+        id: &str,
+        attr: &str,
+        values: Vec<&str>,
+    ) -> Result<(), ClientError> {
+        let m: Vec<_> = values.iter().map(|v| (*v).to_string()).collect();
+        self.perform_put_request(format!("/v1/group/{}/_attr/{}", id, attr).as_str(), m)
+    }
+
+    pub fn idm_group_purge_attr(&self, id: &str, attr: &str) -> Result<(), ClientError> {
+        self.perform_delete_request(format!("/v1/group/{}/_attr/{}", id, attr).as_str())
+    }
+
     pub fn idm_group_unix_token_get(&self, id: &str) -> Result<UnixGroupToken, ClientError> {
         self.perform_get_request(format!("/v1/group/{}/_unix/_token", id).as_str())
     }
@@ -621,6 +844,28 @@ impl KanidmClient {
         self.perform_get_request(format!("/v1/account/{}", id).as_str())
     }
 
+    pub fn idm_account_get_attr(
+        &self,
+        id: &str,
+        attr: &str,
+    ) -> Result<Option<Vec<String>>, ClientError> {
+        self.perform_get_request(format!("/v1/account/{}/_attr/{}", id, attr).as_str())
+    }
+
+    pub fn idm_account_set_attr(
+        &self,
+        id: &str,
+        attr: &str,
+        values: Vec<&str>,
+    ) -> Result<(), ClientError> {
+        let m: Vec<_> = values.iter().map(|v| (*v).to_string()).collect();
+        self.perform_put_request(format!("/v1/account/{}/_attr/{}", id, attr).as_str(), m)
+    }
+
+    pub fn idm_account_purge_attr(&self, id: &str, attr: &str) -> Result<(), ClientError> {
+        self.perform_delete_request(format!("/v1/account/{}/_attr/{}", id, attr).as_str())
+    }
+
     // different ways to set the primary credential?
     // not sure how to best expose this.
     pub fn idm_account_primary_credential_set_password(
@@ -635,6 +880,44 @@ impl KanidmClient {
         )
     }
 
+    pub fn idm_account_credential_list(
+        &self,
+        id: &str,
+    ) -> Result<Vec<CredentialInfo>, ClientError> {
+        self.perform_get_request(format!("/v1/account/{}/_credential", id).as_str())
+    }
+
+    // Set the password of the credential with this uuid or label, creating it if needed.
+    pub fn idm_account_credential_set_password(
+        &self,
+        id: &str,
+        cid: &str,
+        pw: &str,
+    ) -> Result<SetCredentialResponse, ClientError> {
+        let r = SetCredentialRequest::Password(pw.to_string());
+        self.perform_put_request(
+            format!("/v1/account/{}/_credential/{}", id, cid).as_str(),
+            r,
+        )
+    }
+
+    pub fn idm_account_credential_lock(&self, id: &str, cid: &str) -> Result<(), ClientError> {
+        self.perform_post_request(
+            format!("/v1/account/{}/_credential/{}/_lock", id, cid).as_str(),
+            (),
+        )
+    }
+
+    pub fn idm_account_credential_unlock(&self, id: &str, cid: &str) -> Result<(), ClientError> {
+        self.perform_delete_request(
+            format!("/v1/account/{}/_credential/{}/_lock", id, cid).as_str(),
+        )
+    }
+
+    pub fn idm_account_credential_delete(&self, id: &str, cid: &str) -> Result<(), ClientError> {
+        self.perform_delete_request(format!("/v1/account/{}/_credential/{}", id, cid).as_str())
+    }
+
     pub fn idm_account_primary_credential_import_password(
         &self,
         id: &str,
@@ -680,26 +963,133 @@ impl KanidmClient {
         }
     }
 
-    // Verify the totp
+    // Verify the totp. This returns the backup codes issued with it.
     pub fn idm_account_primary_credential_verify_totp(
         &self,
         id: &str,
         otp: u32,
         session: Uuid,
-    ) -> Result<(), ClientError> {
+    ) -> Result<Vec<String>, ClientError> {
         let r = SetCredentialRequest::TOTPVerify(session, otp);
         let res: Result<SetCredentialResponse, ClientError> = self.perform_put_request(
             format!("/v1/account/{}/_credential/primary", id).as_str(),
             r,
         );
         match res {
-            Ok(SetCredentialResponse::Success) => Ok(()),
+            Ok(SetCredentialResponse::BackupCodes(codes)) => Ok(codes),
             Ok(SetCredentialResponse::TOTPCheck(u, s)) => Err(ClientError::TOTPVerifyFailed(u, s)),
             Ok(_) => Err(ClientError::EmptyResponse),
             Err(e) => Err(e),
         }
     }
 
+    pub fn idm_account_primary_credential_list_totp(
+        &self,
+        id: &str,
+    ) -> Result<Vec<String>, ClientError> {
+        self.perform_get_request(format!("/v1/account/{}/_credential/primary/totp", id).as_str())
+    }
+
+    pub fn idm_account_primary_credential_remove_totp(
+        &self,
+        id: &str,
+        label: &str,
+    ) -> Result<(), ClientError> {
+        let r = SetCredentialRequest::TOTPRemove(label.to_string());
+        let res: Result<SetCredentialResponse, ClientError> = self.perform_put_request(
+            format!("/v1/account/{}/_credential/primary", id).as_str(),
+            r,
+        );
+        match res {
+            Ok(SetCredentialResponse::Success) => Ok(()),
+            Ok(_) => Err(ClientError::EmptyResponse),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Reg intent for webauthn
+    pub fn idm_account_primary_credential_register_webauthn(
+        &self,
+        id: &str,
+        label: &str,
+    ) -> Result<(Uuid, CreationChallengeResponse), ClientError> {
+        let r = SetCredentialRequest::WebauthnBegin(label.to_string());
+        let res: Result<SetCredentialResponse, ClientError> = self.perform_put_request(
+            format!("/v1/account/{}/_credential/primary", id).as_str(),
+            r,
+        );
+        match res {
+            Ok(SetCredentialResponse::WebauthnCreateChallenge(u, chal)) => Ok((u, chal)),
+            Ok(_) => Err(ClientError::EmptyResponse),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Submit the authenticators response to the challenge
+    pub fn idm_account_primary_credential_complete_webauthn_registration(
+        &self,
+        id: &str,
+        rego: RegisterPublicKeyCredential,
+        session: Uuid,
+    ) -> Result<(), ClientError> {
+        let r = SetCredentialRequest::WebauthnRegister(session, rego);
+        let res: Result<SetCredentialResponse, ClientError> = self.perform_put_request(
+            format!("/v1/account/{}/_credential/primary", id).as_str(),
+            r,
+        );
+        match res {
+            Ok(SetCredentialResponse::Success) => Ok(()),
+            Ok(_) => Err(ClientError::EmptyResponse),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn idm_account_primary_credential_remove_webauthn(
+        &self,
+        id: &str,
+        label: &str,
+    ) -> Result<(), ClientError> {
+        let r = SetCredentialRequest::WebauthnRemove(label.to_string());
+        let res: Result<SetCredentialResponse, ClientError> = self.perform_put_request(
+            format!("/v1/account/{}/_credential/primary", id).as_str(),
+            r,
+        );
+        match res {
+            Ok(SetCredentialResponse::Success) => Ok(()),
+            Ok(_) => Err(ClientError::EmptyResponse),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn idm_account_credential_create_reset_token(
+        &self,
+        id: &str,
+    ) -> Result<String, ClientError> {
+        self.perform_post_request(
+            format!("/v1/account/{}/_credential/_reset_token", id).as_str(),
+            (),
+        )
+    }
+
+    // Make a change to the primary credential with a reset token. The client doesn't need
+    // to be authenticated for this.
+    pub fn idm_account_credential_reset(
+        &self,
+        id: &str,
+        token: &str,
+        req: SetCredentialRequest,
+    ) -> Result<SetCredentialResponse, ClientError> {
+        let r = CredentialResetRequest {
+            token: token.to_string(),
+            req,
+        };
+        self.perform_put_request(format!("/v1/account/{}/_credential/_reset", id).as_str(), r)
+    }
+
+    pub fn idm_account_unlock(&self, id: &str) -> Result<(), ClientError> {
+        self.perform_post_request(format!("/v1/account/{}/_unlock", id).as_str(), ())
+    }
+
     pub fn idm_account_radius_credential_get(
         &self,
         id: &str,
@@ -722,6 +1112,40 @@ impl KanidmClient {
         self.perform_get_request(format!("/v1/account/{}/_radius/_token", id).as_str())
     }
 
+    pub fn idm_account_app_password_list(&self, id: &str) -> Result<Vec<AppPassword>, ClientError> {
+        self.perform_get_request(format!("/v1/account/{}/_app_password", id).as_str())
+    }
+
+    pub fn idm_account_app_password_create(
+        &self,
+        id: &str,
+        label: &str,
+        application: &str,
+    ) -> Result<String, ClientError> {
+        let ap = (label.to_string(), application.to_string());
+        self.perform_post_request(format!("/v1/account/{}/_app_password", id).as_str(), ap)
+    }
+
+    pub fn idm_account_app_password_revoke(
+        &self,
+        id: &str,
+        label: &str,
+    ) -> Result<(), ClientError> {
+        self.perform_delete_request(format!("/v1/account/{}/_app_password/{}", id, label).as_str())
+    }
+
+    pub fn idm_account_session_list(&self, id: &str) -> Result<Vec<SessionInfo>, ClientError> {
+        self.perform_get_request(format!("/v1/account/{}/_session", id).as_str())
+    }
+
+    pub fn idm_account_session_revoke(&self, id: &str, sid: &str) -> Result<(), ClientError> {
+        self.perform_delete_request(format!("/v1/account/{}/_session/{}", id, sid).as_str())
+    }
+
+    pub fn idm_account_session_revoke_all(&self, id: &str) -> Result<(), ClientError> {
+        self.perform_delete_request(format!("/v1/account/{}/_session", id).as_str())
+    }
+
     pub fn idm_account_unix_extend(
         &self,
         id: &str,
@@ -856,4 +1280,90 @@ impl KanidmClient {
     pub fn recycle_bin_revive(&self, id: &str) -> Result<(), ClientError> {
         self.perform_post_request(format!("/v1/recycle_bin/{}/_revive", id).as_str(), ())
     }
+
+    // ==== access profiles
+    pub fn access_profile_explain(
+        &self,
+        identity: &str,
+        target: &str,
+        operation: AccessOperation,
+    ) -> Result<AccessExplainResponse, ClientError> {
+        let r = AccessExplainRequest::new(identity, target, operation);
+        self.perform_post_request("/v1/access_profile/_explain", r)
+    }
+
+    pub fn access_profile_list(&self) -> Result<Vec<Entry>, ClientError> {
+        self.perform_get_request("/v1/access_profile")
+    }
+
+    pub fn access_profile_get(&self, id: &str) -> Result<Option<Entry>, ClientError> {
+        self.perform_get_request(format!("/v1/access_profile/{}", id).as_str())
+    }
+
+    // The entry's classes select which of search, modify, create and delete
+    // the profile applies to.
+    pub fn access_profile_create(&self, profile: Entry) -> Result<(), ClientError> {
+        self.perform_post_request("/v1/access_profile", profile)
+            .map(|_: OperationResponse| ())
+    }
+
+    pub fn access_profile_delete(&self, id: &str) -> Result<(), ClientError> {
+        self.perform_delete_request(format!("/v1/access_profile/{}", id).as_str())
+    }
+
+    pub fn access_profile_get_attr(
+        &self,
+        id: &str,
+        attr: &str,
+    ) -> Result<Option<Vec<String>>, ClientError> {
+        self.perform_get_request(format!("/v1/access_profile/{}/_attr/{}", id, attr).as_str())
+    }
+
+    pub fn access_profile_set_attr(
+        &self,
+        id: &str,
+        attr: &str,
+        values: Vec<&str>,
+    ) -> Result<(), ClientError> {
+        let m: Vec<_> = values.iter().map(|v| (*v).to_string()).collect();
+        self.perform_put_request(
+            format!("/v1/access_profile/{}/_attr/{}", id, attr).as_str(),
+            m,
+        )
+    }
+
+    pub fn access_profile_add_attr(
+        &self,
+        id: &str,
+        attr: &str,
+        values: Vec<&str>,
+    ) -> Result<(), ClientError> {
+        let m: Vec<_> = values.iter().map(|v| (*v).to_string()).collect();
+        self.perform_post_request(
+            format!("/v1/access_profile/{}/_attr/{}", id, attr).as_str(),
+            m,
+        )
+    }
+
+    pub fn access_profile_purge_attr(&self, id: &str, attr: &str) -> Result<(), ClientError> {
+        self.perform_delete_request(format!("/v1/access_profile/{}/_attr/{}", id, attr).as_str())
+    }
+
+    pub fn access_profile_set_receiver(
+        &self,
+        id: &str,
+        receiver: &Filter,
+    ) -> Result<(), ClientError> {
+        let f = serde_json::to_string(receiver).map_err(|_| ClientError::JsonParse)?;
+        self.access_profile_set_attr(id, "acp_receiver", vec![f.as_str()])
+    }
+
+    pub fn access_profile_set_targetscope(
+        &self,
+        id: &str,
+        targetscope: &Filter,
+    ) -> Result<(), ClientError> {
+        let f = serde_json::to_string(targetscope).map_err(|_| ClientError::JsonParse)?;
+        self.access_profile_set_attr(id, "acp_targetscope", vec![f.as_str()])
+    }
 }
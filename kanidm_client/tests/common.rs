@@ -9,6 +9,7 @@ use kanidm_client::{KanidmClient, KanidmClientBuilder};
 use actix::prelude::*;
 
 pub const ADMIN_TEST_PASSWORD: &str = "integration test admin password";
+pub const WEBAUTHN_TEST_ORIGIN: &str = "https://idm.example.com";
 static PORT_ALLOC: AtomicUsize = AtomicUsize::new(8080);
 
 // Test external behaviours of the service.
@@ -27,6 +28,7 @@ pub fn run_test(test_fn: fn(KanidmClient) -> ()) {
     config.address = format!("127.0.0.1:{}", port);
     config.secure_cookies = false;
     config.integration_test_config = Some(int_config);
+    config.origin = WEBAUTHN_TEST_ORIGIN.to_string();
     // Setup the config ...
 
     thread::spawn(move || {
@@ -1,8 +1,8 @@
 #![deny(warnings)]
 use std::collections::HashSet;
 
-use kanidm_client::KanidmClient;
-use kanidm_proto::v1::{Filter, Modify, ModifyList};
+use kanidm_client::{ClientError, KanidmClient};
+use kanidm_proto::v1::{Filter, Modify, ModifyList, OperationError};
 
 mod common;
 use crate::common::{run_test, ADMIN_TEST_PASSWORD};
@@ -27,7 +27,7 @@ static SELF_WRITEABLE_ATTRS: [&str; 7] = [
     "ssh_publickey",
     "unix_password",
 ];
-static DEFAULT_HP_GROUP_NAMES: [&str; 22] = [
+static DEFAULT_HP_GROUP_NAMES: [&str; 23] = [
     "idm_admins",
     "system_admins",
     "idm_people_manage_priv",
@@ -48,6 +48,7 @@ static DEFAULT_HP_GROUP_NAMES: [&str; 22] = [
     "idm_hp_group_manage_priv",
     "idm_hp_group_write_priv",
     "idm_acp_manage_priv",
+    "idm_acp_explain_priv",
     "domain_admins",
     "idm_high_privilege",
 ];
@@ -100,6 +101,8 @@ fn is_attr_writable(rsclient: &KanidmClient, id: &str, attr: &str) -> Option<boo
             let new_value = match entry {
                 "acp_receiver" => "{\"Eq\":[\"memberof\",\"00000000-0000-0000-0000-000000000011\"]}".to_string(),
                 "acp_targetscope" => "{\"And\": [{\"Eq\": [\"class\",\"access_control_profile\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}".to_string(),
+                "account_valid_from" => "0".to_string(),
+                "account_expire" => "4102444800".to_string(),
                  _ => id.to_string(),
             };
             let m = ModifyList::new_list(vec![
@@ -163,9 +166,11 @@ fn login_account(rsclient: &mut KanidmClient, id: &str) -> () {
         .idm_group_add_members("idm_people_extend_priv", vec!["admin"])
         .unwrap();
 
-    rsclient
-        .idm_account_primary_credential_set_password(id, "eicieY7ahchaoCh0eeTa")
-        .unwrap();
+    match rsclient.idm_account_primary_credential_set_password(id, "eicieY7ahchaoCh0eeTa") {
+        // An earlier login may have set this password already, and it can't be reused.
+        Ok(_) | Err(ClientError::Http(_, Some(OperationError::PasswordReused))) => {}
+        Err(e) => panic!("Failed to set password: {:?}", e),
+    }
 
     let _ = rsclient.logout();
     let res = rsclient.auth_simple_password(id, "eicieY7ahchaoCh0eeTa");
@@ -274,12 +279,14 @@ fn test_default_entries_rbac_account_managers() {
         login_account(&mut rsclient, "account_manager");
 
         test_read_attrs(&rsclient, "test", &USER_READABLE_ATTRS, true);
-        static ACCOUNT_MANAGER_ATTRS: [&str; 5] = [
+        static ACCOUNT_MANAGER_ATTRS: [&str; 7] = [
             "name",
             "displayname",
             "primary_credential",
             "ssh_publickey",
             "mail",
+            "account_valid_from",
+            "account_expire",
         ];
         test_write_attrs(&rsclient, "test", &ACCOUNT_MANAGER_ATTRS, true);
 
@@ -410,6 +417,8 @@ fn test_default_entries_rbac_admins_schema_entries() {
             "posixaccount",
             "posixgroup",
             "system_config",
+            "uniqueconstraint",
+            "password_policy",
         ]
         .iter()
         .map(ToString::to_string)
@@ -472,6 +481,37 @@ fn test_default_entries_rbac_admins_schema_entries() {
             "badlist_password",
             "loginshell",
             "unix_password",
+            "minvalue",
+            "maxvalue",
+            "regex",
+            "minlength",
+            "maxlength",
+            "allowedvalue",
+            "mincardinality",
+            "maxcardinality",
+            "classkind",
+            "systemexcludes",
+            "excludes",
+            "uniqueattr",
+            "uniquescope",
+            "acp_deny",
+            "acp_valid_from",
+            "acp_valid_until",
+            "manager",
+            "owner",
+            "acp_modify_presentvalue",
+            "acp_modify_presentrefer",
+            "account_valid_from",
+            "account_expire",
+            "pw_min_length",
+            "pw_min_score",
+            "pw_badlist",
+            "pw_require_mfa",
+            "pw_max_age",
+            "app_password",
+            "credential_reset_token",
+            "jws_signing_key",
+            "session",
         ]
         .iter()
         .map(ToString::to_string)
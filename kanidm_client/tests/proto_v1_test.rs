@@ -1,18 +1,35 @@
 #![deny(warnings)]
-use std::time::SystemTime;
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 use log::debug;
 
-use kanidm::credential::totp::TOTP;
+use kanidm::credential::totp::{TOTP, TOTP_DEFAULT_STEP};
 use kanidm_client::KanidmClient;
-use kanidm_proto::v1::{Entry, Filter, Modify, ModifyList};
+use kanidm_proto::v1::{
+    Entry, Filter, Modify, ModifyList, SetCredentialRequest, SetCredentialResponse,
+};
+use webauthn_authenticator_rs::{softtok::U2FSoft, WebauthnAuthenticator};
 
 mod common;
-use crate::common::{run_test, ADMIN_TEST_PASSWORD};
+use crate::common::{run_test, ADMIN_TEST_PASSWORD, WEBAUTHN_TEST_ORIGIN};
 
 const ADMIN_TEST_PASSWORD_CHANGE: &str = "integration test admin new🎉";
 const UNIX_TEST_PASSWORD: &str = "unix test user password";
 
+// A totp code can only be used once, so wait for the next window to get a fresh one.
+fn next_totp(r_tok: &TOTP) -> u32 {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap();
+    let wait = TOTP_DEFAULT_STEP - (now.as_secs() % TOTP_DEFAULT_STEP);
+    thread::sleep(Duration::from_secs(wait));
+    r_tok
+        .do_totp(&SystemTime::now())
+        .expect("Failed to do totp?")
+}
+
 #[test]
 fn test_server_create() {
     run_test(|rsclient: KanidmClient| {
@@ -106,6 +123,66 @@ fn test_server_whoami_admin_simple_password() {
     });
 }
 
+#[test]
+fn test_server_whoami_bearer_token() {
+    run_test(|rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+        let token = rsclient.get_token().expect("No bearer token was issued");
+
+        // A new session has no cookies, so only the authorization header is sent.
+        let bearer_client = rsclient.new_session().unwrap();
+        assert!(bearer_client.whoami().unwrap().is_none());
+        bearer_client.set_token(Some(token.clone()));
+        let (_e, uat) = match bearer_client.whoami().unwrap() {
+            Some((e, uat)) => (e, uat),
+            None => panic!(),
+        };
+        assert!(uat.name == "admin");
+
+        // A token that has been tampered with is rejected.
+        let mut tampered = token;
+        tampered.push('A');
+        bearer_client.set_token(Some(tampered));
+        assert!(bearer_client.whoami().unwrap().is_none());
+    });
+}
+
+#[test]
+fn test_server_reauth_privileged_claim() {
+    run_test(|rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        // A normal login is interactive, but not privileged.
+        let (_e, uat) = rsclient.whoami().unwrap().unwrap();
+        assert!(uat.claims.iter().any(|c| c.name == "interactive"));
+        assert!(!uat.claims.iter().any(|c| c.name == "privileged"));
+
+        // The wrong password does not elevate, or end the session.
+        assert!(rsclient.reauth_simple_password("wrong password").is_err());
+        let (_e, uat) = rsclient.whoami().unwrap().unwrap();
+        assert!(uat.name == "admin");
+        assert!(!uat.claims.iter().any(|c| c.name == "privileged"));
+
+        let uat = rsclient
+            .reauth_simple_password(ADMIN_TEST_PASSWORD)
+            .unwrap();
+        assert!(uat
+            .claims
+            .iter()
+            .any(|c| c.name == "privileged" && c.expiry.is_some()));
+        // And the session now carries the claim.
+        let (_e, uat) = rsclient.whoami().unwrap().unwrap();
+        assert!(uat.claims.iter().any(|c| c.name == "privileged"));
+
+        // Anonymous sessions can never be elevated.
+        let rsclient_anon = rsclient.new_session().unwrap();
+        assert!(rsclient_anon.auth_anonymous().is_ok());
+        assert!(rsclient_anon.reauth_simple_password("").is_err());
+    });
+}
+
 #[test]
 fn test_server_search() {
     run_test(|rsclient: KanidmClient| {
@@ -660,6 +737,72 @@ fn test_server_rest_recycle_lifecycle() {
     });
 }
 
+#[test]
+fn test_server_rest_access_profile_lifecycle() {
+    run_test(|rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        let acp_list = rsclient.access_profile_list().unwrap();
+        assert!(!acp_list.is_empty());
+
+        let receiver: Filter = "(name=admin)".parse().unwrap();
+        let targetscope: Filter = "(&(class=group)(name=demo_group))".parse().unwrap();
+
+        let mut acp = Entry {
+            attrs: BTreeMap::new(),
+        };
+        acp.attrs.insert(
+            "class".to_string(),
+            vec!["access_control_search".to_string()],
+        );
+        acp.attrs
+            .insert("name".to_string(), vec!["demo_acp".to_string()]);
+        acp.attrs.insert(
+            "acp_receiver".to_string(),
+            vec![serde_json::to_string(&receiver).unwrap()],
+        );
+        acp.attrs.insert(
+            "acp_targetscope".to_string(),
+            vec![serde_json::to_string(&targetscope).unwrap()],
+        );
+        acp.attrs
+            .insert("acp_search_attr".to_string(), vec!["name".to_string()]);
+        rsclient.access_profile_create(acp).unwrap();
+
+        let acp_list_2 = rsclient.access_profile_list().unwrap();
+        assert!(acp_list_2.len() > acp_list.len());
+        assert!(rsclient.access_profile_get("demo_acp").unwrap().is_some());
+
+        // Filters that don't pass schema are rejected.
+        let bad_targetscope: Filter = "(not_an_attribute=demo)".parse().unwrap();
+        assert!(rsclient
+            .access_profile_set_targetscope("demo_acp", &bad_targetscope)
+            .is_err());
+
+        let new_targetscope: Filter = "(&(class=group)(name=other_group))".parse().unwrap();
+        rsclient
+            .access_profile_set_targetscope("demo_acp", &new_targetscope)
+            .unwrap();
+        let ts = rsclient
+            .access_profile_get_attr("demo_acp", "acp_targetscope")
+            .unwrap()
+            .unwrap();
+        let ts: Filter = serde_json::from_str(ts[0].as_str()).unwrap();
+        assert!(ts == new_targetscope);
+
+        rsclient
+            .access_profile_add_attr("demo_acp", "acp_search_attr", vec!["description"])
+            .unwrap();
+        rsclient
+            .access_profile_purge_attr("demo_acp", "description")
+            .unwrap();
+
+        rsclient.access_profile_delete("demo_acp").unwrap();
+        assert!(rsclient.access_profile_get("demo_acp").unwrap().is_none());
+    });
+}
+
 #[test]
 fn test_server_rest_account_import_password() {
     run_test(|mut rsclient: KanidmClient| {
@@ -737,9 +880,10 @@ fn test_server_rest_totp_auth_lifecycle() {
             )
             .expect("Failed to do totp?");
 
-        rsclient
+        let backup_codes = rsclient
             .idm_account_primary_credential_verify_totp("demo_account", totp, sessionid)
             .unwrap(); // the result
+        assert!(!backup_codes.is_empty());
 
         // Check a bad auth
         // Get a new connection
@@ -748,21 +892,451 @@ fn test_server_rest_totp_auth_lifecycle() {
             .auth_password_totp("demo_account", "sohdi3iuHo6mai7noh0a", 0)
             .is_err());
 
-        // Check a good auth
+        // Check a good auth. The code used to verify the device isn't accepted again.
         let rsclient_good = rsclient.new_session().unwrap();
-        let totp = r_tok
-            .do_totp_duration_from_epoch(
-                &SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap(),
-            )
-            .expect("Failed to do totp?");
+        let totp = next_totp(&r_tok);
         assert!(rsclient_good
             .auth_password_totp("demo_account", "sohdi3iuHo6mai7noh0a", totp)
             .is_ok());
+
+        // Nor is this one.
+        let rsclient_replay = rsclient.new_session().unwrap();
+        assert!(rsclient_replay
+            .auth_password_totp("demo_account", "sohdi3iuHo6mai7noh0a", totp)
+            .is_err());
+
+        // A backup code can be used in place of the totp, but only once.
+        let rsclient_code = rsclient.new_session().unwrap();
+        assert!(rsclient_code
+            .auth_password_backup_code(
+                "demo_account",
+                "sohdi3iuHo6mai7noh0a",
+                backup_codes[0].as_str()
+            )
+            .is_ok());
+        let rsclient_code = rsclient.new_session().unwrap();
+        assert!(rsclient_code
+            .auth_password_backup_code(
+                "demo_account",
+                "sohdi3iuHo6mai7noh0a",
+                backup_codes[0].as_str()
+            )
+            .is_err());
+
+        // The user can replace their codes.
+        let new_codes = rsclient_good.idm_account_generate_backup_codes().unwrap();
+        assert!(new_codes.len() == backup_codes.len());
+        let rsclient_code = rsclient.new_session().unwrap();
+        assert!(rsclient_code
+            .auth_password_backup_code(
+                "demo_account",
+                "sohdi3iuHo6mai7noh0a",
+                backup_codes[1].as_str()
+            )
+            .is_err());
+
+        // If the device is lost, it can be removed without resetting the password.
+        let labels = rsclient
+            .idm_account_primary_credential_list_totp("demo_account")
+            .unwrap();
+        assert!(labels == vec!["demo".to_string()]);
+        assert!(rsclient
+            .idm_account_primary_credential_remove_totp("demo_account", "demo")
+            .is_ok());
+        assert!(rsclient
+            .idm_account_primary_credential_list_totp("demo_account")
+            .unwrap()
+            .is_empty());
+        let rsclient_pw = rsclient.new_session().unwrap();
+        assert!(rsclient_pw
+            .auth_simple_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .is_ok());
+    });
+}
+
+#[test]
+fn test_server_rest_webauthn_auth_lifecycle() {
+    run_test(|rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        // Not recommended in production!
+        rsclient
+            .idm_group_add_members("idm_admins", vec!["admin"])
+            .unwrap();
+
+        // Create a new account with only a token.
+        rsclient
+            .idm_account_create("demo_account", "Deeeeemo")
+            .unwrap();
+
+        let mut wa = WebauthnAuthenticator::new(U2FSoft::new());
+        let (sessionid, chal) = rsclient
+            .idm_account_primary_credential_register_webauthn("demo_account", "softtoken")
+            .unwrap();
+        let rego = wa
+            .do_registration(WEBAUTHN_TEST_ORIGIN, chal)
+            .expect("Failed to register to softtoken");
+        rsclient
+            .idm_account_primary_credential_complete_webauthn_registration(
+                "demo_account",
+                rego,
+                sessionid,
+            )
+            .unwrap();
+
+        // A password is not accepted.
+        let rsclient_bad = rsclient.new_session().unwrap();
+        assert!(rsclient_bad
+            .auth_simple_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .is_err());
+
+        // Check a good auth, twice, so that the stored counter is used.
+        for _ in 0..2 {
+            let rsclient_good = rsclient.new_session().unwrap();
+            let chal = rsclient_good.auth_webauthn_begin("demo_account").unwrap();
+            let pkc = wa
+                .do_authentication(WEBAUTHN_TEST_ORIGIN, chal)
+                .expect("Failed to authenticate with softtoken");
+            assert!(rsclient_good.auth_webauthn_complete(pkc).is_ok());
+        }
+
+        // The only token of the credential can't be removed.
+        assert!(rsclient
+            .idm_account_primary_credential_remove_webauthn("demo_account", "softtoken")
+            .is_err());
+    });
+}
+
+#[test]
+fn test_server_rest_account_softlock() {
+    run_test(|rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        // Not recommended in production!
+        rsclient
+            .idm_group_add_members("idm_admins", vec!["admin"])
+            .unwrap();
+
+        rsclient
+            .idm_account_create("demo_account", "Deeeeemo")
+            .unwrap();
+        assert!(rsclient
+            .idm_account_primary_credential_set_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .is_ok());
+
+        // Fail until the account locks.
+        for _ in 0..3 {
+            let rsclient_bad = rsclient.new_session().unwrap();
+            assert!(rsclient_bad
+                .auth_simple_password("demo_account", "wrong password")
+                .is_err());
+        }
+
+        // Now the correct password is also refused.
+        let rsclient_locked = rsclient.new_session().unwrap();
+        assert!(rsclient_locked
+            .auth_simple_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .is_err());
+
+        // Until an admin unlocks it.
+        assert!(rsclient.idm_account_unlock("demo_account").is_ok());
+
+        let rsclient_good = rsclient.new_session().unwrap();
+        assert!(rsclient_good
+            .auth_simple_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .is_ok());
+    });
+}
+
+#[test]
+fn test_server_rest_group_password_policy() {
+    run_test(|rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        // Not recommended in production!
+        rsclient
+            .idm_group_add_members("idm_admins", vec!["admin"])
+            .unwrap();
+
+        rsclient
+            .idm_account_create("demo_account", "Deeeeemo")
+            .unwrap();
+        rsclient.idm_group_create("demo_group").unwrap();
+        rsclient
+            .idm_group_add_members("demo_group", vec!["demo_account"])
+            .unwrap();
+
+        // Admin is a system admin, so may attach a policy to the group.
+        rsclient
+            .idm_group_add_attr("demo_group", "class", vec!["password_policy"])
+            .unwrap();
+        rsclient
+            .idm_group_set_attr("demo_group", "pw_min_length", vec!["30"])
+            .unwrap();
+        let r = rsclient
+            .idm_group_get_attr("demo_group", "pw_min_length")
+            .unwrap();
+        assert!(r == Some(vec!["30".to_string()]));
+
+        // The default minimum would accept this, but the policy doesn't.
+        assert!(rsclient
+            .idm_account_primary_credential_set_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .is_err());
+        assert!(rsclient
+            .idm_account_primary_credential_set_password(
+                "demo_account",
+                "sohdi3iuHo6mai7noh0a eiQu3eehah3eePh1"
+            )
+            .is_ok());
+    });
+}
+
+#[test]
+fn test_server_rest_app_password_lifecycle() {
+    run_test(|rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        // Not recommended in production!
+        rsclient
+            .idm_group_add_members("idm_admins", vec!["admin"])
+            .unwrap();
+
+        rsclient
+            .idm_account_create("demo_account", "Deeeeemo")
+            .unwrap();
+        rsclient
+            .idm_account_primary_credential_set_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .unwrap();
+
+        let imap_pw = rsclient
+            .idm_account_app_password_create("demo_account", "laptop", "imap")
+            .unwrap();
+        let app_pws = rsclient
+            .idm_account_app_password_list("demo_account")
+            .unwrap();
+        assert!(app_pws.len() == 1);
+        assert!(app_pws[0].label == "laptop");
+        assert!(app_pws[0].application == "imap");
+
+        // The app password is only valid for its application.
+        let rsclient_app = rsclient.new_session().unwrap();
+        let uat = rsclient_app
+            .auth_app_password("demo_account", "imap", imap_pw.as_str())
+            .unwrap();
+        assert!(uat.application.is_some());
+        // And that session can't be used to act on the server.
+        assert!(rsclient_app
+            .idm_account_app_password_list("demo_account")
+            .is_err());
+
+        let rsclient_app = rsclient.new_session().unwrap();
+        assert!(rsclient_app
+            .auth_app_password("demo_account", "caldav", imap_pw.as_str())
+            .is_err());
+        let rsclient_app = rsclient.new_session().unwrap();
+        assert!(rsclient_app
+            .auth_simple_password("demo_account", imap_pw.as_str())
+            .is_err());
+
+        // Once revoked, it no longer works.
+        rsclient
+            .idm_account_app_password_revoke("demo_account", "laptop")
+            .unwrap();
+        assert!(rsclient
+            .idm_account_app_password_list("demo_account")
+            .unwrap()
+            .is_empty());
+        let rsclient_app = rsclient.new_session().unwrap();
+        assert!(rsclient_app
+            .auth_app_password("demo_account", "imap", imap_pw.as_str())
+            .is_err());
+    });
+}
+
+#[test]
+fn test_server_rest_session_lifecycle() {
+    run_test(|rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        // Not recommended in production!
+        rsclient
+            .idm_group_add_members("idm_admins", vec!["admin"])
+            .unwrap();
+
+        rsclient
+            .idm_account_create("demo_account", "Deeeeemo")
+            .unwrap();
+        rsclient
+            .idm_account_primary_credential_set_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .unwrap();
+
+        let rsclient_a = rsclient.new_session().unwrap();
+        assert!(rsclient_a
+            .auth_simple_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .is_ok());
+        let sessions = rsclient_a.idm_self_session_list().unwrap();
+        assert!(sessions.len() == 1);
+        assert!(sessions[0].method == "password");
+        assert!(sessions[0].source.is_some());
+        let sid_a = sessions[0].id.to_string();
+
+        let rsclient_b = rsclient.new_session().unwrap();
+        assert!(rsclient_b
+            .auth_simple_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .is_ok());
+        assert!(rsclient_b.idm_self_session_list().unwrap().len() == 2);
+
+        // A session revoked from elsewhere can no longer be used.
+        rsclient_b.idm_self_session_revoke(sid_a.as_str()).unwrap();
+        assert!(rsclient_a.whoami().unwrap().is_none());
+        assert!(rsclient_b.whoami().unwrap().is_some());
+        assert!(
+            rsclient
+                .idm_account_session_list("demo_account")
+                .unwrap()
+                .len()
+                == 1
+        );
+
+        // An administrator can revoke them all.
+        rsclient
+            .idm_account_session_revoke_all("demo_account")
+            .unwrap();
+        assert!(rsclient_b.whoami().unwrap().is_none());
+        assert!(rsclient
+            .idm_account_session_list("demo_account")
+            .unwrap()
+            .is_empty());
+    });
+}
+
+#[test]
+fn test_server_rest_multiple_credential_lifecycle() {
+    run_test(|rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        // Not recommended in production!
+        rsclient
+            .idm_group_add_members("idm_admins", vec!["admin"])
+            .unwrap();
+
+        rsclient
+            .idm_account_create("demo_account", "Deeeeemo")
+            .unwrap();
+        rsclient
+            .idm_account_primary_credential_set_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .unwrap();
+        rsclient
+            .idm_account_credential_set_password("demo_account", "backup", "eiT4aesh0Phae6ohquoo")
+            .unwrap();
+        assert!(
+            rsclient
+                .idm_account_credential_list("demo_account")
+                .unwrap()
+                .len()
+                == 2
+        );
+
+        // Either password can be used.
+        let rsclient_a = rsclient.new_session().unwrap();
+        assert!(rsclient_a
+            .auth_simple_password("demo_account", "eiT4aesh0Phae6ohquoo")
+            .is_ok());
+
+        // A credential can be locked, but not the last one that isn't.
+        rsclient_a.idm_self_credential_lock("backup").unwrap();
+        let creds = rsclient_a.idm_self_credential_list().unwrap();
+        assert!(creds.len() == 2);
+        assert!(creds.iter().all(|c| c.locked == (c.label == "backup")));
+        assert!(rsclient_a.idm_self_credential_lock("primary").is_err());
+        rsclient_a.idm_self_credential_unlock("backup").unwrap();
+
+        // The same goes for removing them.
+        rsclient
+            .idm_account_credential_delete("demo_account", "primary")
+            .unwrap();
+        assert!(rsclient
+            .idm_account_credential_delete("demo_account", "backup")
+            .is_err());
+        let creds = rsclient
+            .idm_account_credential_list("demo_account")
+            .unwrap();
+        assert!(creds.len() == 1);
+        assert!(creds[0].label == "backup");
+
+        let rsclient_b = rsclient.new_session().unwrap();
+        assert!(rsclient_b
+            .auth_simple_password("demo_account", "eiT4aesh0Phae6ohquoo")
+            .is_ok());
+        let rsclient_b = rsclient.new_session().unwrap();
+        assert!(rsclient_b
+            .auth_simple_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .is_err());
     });
 }
 
 // Test the self version of the radius path.
 
 // Test hitting all auth-required endpoints and assert they give unauthorized.
+
+#[test]
+fn test_server_rest_credential_reset_token_lifecycle() {
+    run_test(|rsclient: KanidmClient| {
+        let res = rsclient.auth_simple_password("admin", ADMIN_TEST_PASSWORD);
+        assert!(res.is_ok());
+
+        // Not recommended in production!
+        rsclient
+            .idm_group_add_members("idm_admins", vec!["admin"])
+            .unwrap();
+
+        // A new account, that has no credentials yet.
+        rsclient
+            .idm_account_create("demo_account", "Deeeeemo")
+            .unwrap();
+        let token = rsclient
+            .idm_account_credential_create_reset_token("demo_account")
+            .unwrap();
+
+        // The token is redeemed without authenticating, but only the issued token works.
+        let rsclient_anon = rsclient.new_session().unwrap();
+        assert!(rsclient_anon
+            .idm_account_credential_reset(
+                "demo_account",
+                "abcd-efgh-ijkl-mnop",
+                SetCredentialRequest::Password("sohdi3iuHo6mai7noh0a".to_string()),
+            )
+            .is_err());
+        match rsclient_anon.idm_account_credential_reset(
+            "demo_account",
+            token.as_str(),
+            SetCredentialRequest::Password("sohdi3iuHo6mai7noh0a".to_string()),
+        ) {
+            Ok(SetCredentialResponse::Success) => {}
+            _ => panic!("reset token was not accepted"),
+        }
+        // It's single use.
+        assert!(rsclient_anon
+            .idm_account_credential_reset(
+                "demo_account",
+                token.as_str(),
+                SetCredentialRequest::Password("eiyoh4ooquaiThaiphee8pah3aiN".to_string()),
+            )
+            .is_err());
+
+        let rsclient_user = rsclient.new_session().unwrap();
+        assert!(rsclient_user
+            .auth_simple_password("demo_account", "sohdi3iuHo6mai7noh0a")
+            .is_ok());
+        // An account can't issue itself a token.
+        assert!(rsclient_user
+            .idm_account_credential_create_reset_token("demo_account")
+            .is_err());
+    });
+}
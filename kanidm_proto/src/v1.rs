@@ -1,6 +1,11 @@
 use std::collections::BTreeMap;
 use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
+use webauthn_rs::proto::{
+    CreationChallengeResponse, PublicKeyCredential, RegisterPublicKeyCredential,
+    RequestChallengeResponse,
+};
 // use zxcvbn::feedback;
 
 // These proto implementations are here because they have public definitions
@@ -17,6 +22,12 @@ pub enum SchemaError {
     EmptyFilter,
     Corrupted,
     PhantomAttribute,
+    // Attribute, Reason
+    ConstraintViolation(String, String),
+    NoStructuralClass,
+    MultipleStructuralClasses(Vec<String>),
+    // Class, Excluded Class
+    ExcludedClass(String, String),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -33,6 +44,8 @@ pub enum ConsistencyError {
     // Class, Attribute
     SchemaClassMissingAttribute(String, String),
     SchemaClassPhantomAttribute(String, String),
+    // Constraint, Attribute
+    SchemaUniqueConstraintMissingAttribute(String, String),
     QueryServerSearchFailure,
     EntryUuidCorrupt(u64),
     UuidIndexCorrupt(String),
@@ -43,6 +56,8 @@ pub enum ConsistencyError {
     DuplicateUniqueAttribute(String),
     InvalidSPN(u64),
     SqliteIntegrityFailure,
+    InvalidClassSet(u64),
+    DuplicateUniqueConstraint(String),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -83,6 +98,9 @@ pub enum OperationError {
     PasswordTooShort(usize),
     PasswordEmpty,
     PasswordBadListed,
+    PasswordReused,
+    Webauthn,
+    CryptographyError,
 }
 
 impl PartialEq for OperationError {
@@ -109,10 +127,18 @@ pub struct Group {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claim {
     pub name: String,
-    pub uuid: String,
-    // These can be ephemeral, or shortlived in a session.
-    // some may even need requesting.
-    // pub expiry: DateTime
+    // Ephemeral claims, such as privileged, are only valid until this time, in
+    // seconds since the unix epoch. Other claims last as long as the session.
+    pub expiry: Option<u64>,
+}
+
+impl fmt::Display for Claim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.expiry {
+            Some(expiry) => write!(f, "{} (expires at {})", self.name, expiry),
+            None => write!(f, "{}", self.name),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -121,6 +147,71 @@ pub struct Application {
     pub uuid: String,
 }
 
+// An application password, which is only valid when authenticating for the named
+// application.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AppPassword {
+    pub label: String,
+    pub application: String,
+}
+
+impl fmt::Display for AppPassword {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.label, self.application)
+    }
+}
+
+// A session that a bearer token was issued for. Times are seconds since the unix epoch.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub issued: u64,
+    pub expiry: u64,
+    pub source: Option<String>,
+    pub method: String,
+}
+
+impl fmt::Display for SessionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} from {} (issued {}, expires {})",
+            self.id,
+            self.method,
+            self.source.as_deref().unwrap_or("unknown"),
+            self.issued,
+            self.expiry
+        )
+    }
+}
+
+// A primary credential of an account. Only what it is made of is shown, never the secrets.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CredentialInfo {
+    pub id: Uuid,
+    pub label: String,
+    pub password: bool,
+    pub totp: Vec<String>,
+    pub webauthn: Vec<String>,
+    pub locked: bool,
+}
+
+impl fmt::Display for CredentialInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if self.password {
+            parts.push("password".to_string());
+        }
+        parts.extend(self.totp.iter().map(|l| format!("totp {}", l)));
+        parts.extend(self.webauthn.iter().map(|l| format!("webauthn {}", l)));
+        write!(f, "{} ({}): {}", self.label, self.id, parts.join(", "))?;
+        if self.locked {
+            write!(f, " (locked)")?;
+        }
+        Ok(())
+    }
+}
+
 // The currently authenticated user, and any required metadata for them
 // to properly authorise them. This is similar in nature to oauth and the krb
 // PAC/PAD structures. Currently we only use this internally, but we should
@@ -143,6 +234,9 @@ pub struct UserAuthToken {
     pub application: Option<Application>,
     pub groups: Vec<Group>,
     pub claims: Vec<Claim>,
+    // Things the user should be told about their credentials, such as running low on
+    // backup codes.
+    pub warnings: Vec<String>,
     // Should we allow supplemental ava's to be added on request?
 }
 
@@ -152,7 +246,13 @@ impl fmt::Display for UserAuthToken {
         writeln!(f, "display: {}", self.displayname)?;
         writeln!(f, "uuid: {}", self.uuid)?;
         writeln!(f, "groups: {:?}", self.groups)?;
-        writeln!(f, "claims: {:?}", self.claims)
+        for c in self.claims.iter() {
+            writeln!(f, "claim: {}", c)?;
+        }
+        for w in self.warnings.iter() {
+            writeln!(f, "warning: {}", w)?;
+        }
+        Ok(())
     }
 }
 
@@ -203,6 +303,9 @@ pub struct UnixUserToken {
     pub shell: Option<String>,
     pub groups: Vec<UnixGroupToken>,
     pub sshkeys: Vec<String>,
+    // Seconds since the unix epoch, bounding when the account may be used.
+    pub valid_from: Option<i64>,
+    pub expire: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -229,11 +332,197 @@ pub enum Filter {
     Eq(String, String),
     Sub(String, String),
     Pres(String),
+    LessThan(String, String),
     Or(Vec<Filter>),
     And(Vec<Filter>),
     AndNot(Box<Filter>),
     #[serde(rename = "Self")]
     SelfUUID,
+    // Entries that reference the requesting identity via this attribute.
+    SelfRef(String),
+    // Members of the entries that reference the requesting identity via this attribute.
+    SelfRefMember(String),
+}
+
+// Filters can also be written in a readable, ldap-like form, which is what the
+// cli accepts and displays. The characters ( ) * and \ in a value are escaped
+// with a \.
+//
+//   (attr=value)    Eq         (attr=*)        Pres
+//   (attr=*value*)  Sub        (attr<value)    LessThan
+//   (&(..)(..))     And        (|(..)(..))     Or
+//   (!(..))         AndNot     (self)          Self
+//   (attr=@self)    SelfRef    (attr=@self.member)   SelfRefMember
+fn escape_filter_value(v: &str) -> String {
+    let mut s = String::with_capacity(v.len());
+    if v == "@self" || v == "@self.member" {
+        s.push('\\');
+    }
+    for c in v.chars() {
+        if c == '(' || c == ')' || c == '*' || c == '\\' {
+            s.push('\\');
+        }
+        s.push(c);
+    }
+    s
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Filter::Eq(a, v) => write!(f, "({}={})", a, escape_filter_value(v)),
+            Filter::Sub(a, v) => write!(f, "({}=*{}*)", a, escape_filter_value(v)),
+            Filter::Pres(a) => write!(f, "({}=*)", a),
+            Filter::LessThan(a, v) => write!(f, "({}<{})", a, escape_filter_value(v)),
+            Filter::Or(l) => {
+                write!(f, "(|")?;
+                for i in l {
+                    write!(f, "{}", i)?;
+                }
+                write!(f, ")")
+            }
+            Filter::And(l) => {
+                write!(f, "(&")?;
+                for i in l {
+                    write!(f, "{}", i)?;
+                }
+                write!(f, ")")
+            }
+            Filter::AndNot(i) => write!(f, "(!{})", i),
+            Filter::SelfUUID => write!(f, "(self)"),
+            Filter::SelfRef(a) => write!(f, "({}=@self)", a),
+            Filter::SelfRefMember(a) => write!(f, "({}=@self.member)", a),
+        }
+    }
+}
+
+struct FilterParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> FilterParser<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.chars.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, e: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(c) if c == e => Ok(()),
+            Some(c) => Err(format!("expected '{}', found '{}'", e, c)),
+            None => Err(format!("expected '{}', found end of filter", e)),
+        }
+    }
+
+    fn parse_filter(&mut self) -> Result<Filter, String> {
+        self.expect('(')?;
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some(&'&') => {
+                self.chars.next();
+                self.parse_list().map(Filter::And)
+            }
+            Some(&'|') => {
+                self.chars.next();
+                self.parse_list().map(Filter::Or)
+            }
+            Some(&'!') => {
+                self.chars.next();
+                let inner = self.parse_filter()?;
+                self.expect(')')?;
+                Ok(Filter::AndNot(Box::new(inner)))
+            }
+            _ => self.parse_item(),
+        }
+    }
+
+    fn parse_list(&mut self) -> Result<Vec<Filter>, String> {
+        let mut list = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() == Some(&')') {
+                self.chars.next();
+                return Ok(list);
+            }
+            list.push(self.parse_filter()?);
+        }
+    }
+
+    fn parse_item(&mut self) -> Result<Filter, String> {
+        let mut attr = String::new();
+        let op = loop {
+            match self.chars.next() {
+                Some(c) if c == '=' || c == '<' || c == ')' => break c,
+                Some('(') => return Err("unexpected '(' in attribute name".to_string()),
+                Some(c) => attr.push(c),
+                None => return Err("unexpected end of filter".to_string()),
+            }
+        };
+        let attr = attr.trim().to_string();
+
+        if op == ')' {
+            return if attr == "self" {
+                Ok(Filter::SelfUUID)
+            } else {
+                Err(format!("missing operator after '{}'", attr))
+            };
+        }
+        if attr.is_empty() {
+            return Err("missing attribute name".to_string());
+        }
+
+        // Each character of the value, and whether it was escaped.
+        let mut value: Vec<(char, bool)> = Vec::new();
+        loop {
+            match self.chars.next() {
+                Some(')') => break,
+                Some('\\') => match self.chars.next() {
+                    Some(c) => value.push((c, true)),
+                    None => return Err("unexpected end of filter".to_string()),
+                },
+                Some(c) => value.push((c, false)),
+                None => return Err("unexpected end of filter".to_string()),
+            }
+        }
+
+        let to_string = |v: &[(char, bool)]| -> String { v.iter().map(|(c, _)| *c).collect() };
+        let is_star = |t: Option<&(char, bool)>| t == Some(&('*', false));
+
+        if op == '<' {
+            Ok(Filter::LessThan(attr, to_string(&value)))
+        } else if value.len() == 1 && is_star(value.first()) {
+            Ok(Filter::Pres(attr))
+        } else if value.len() >= 2 && is_star(value.first()) && is_star(value.last()) {
+            Ok(Filter::Sub(attr, to_string(&value[1..value.len() - 1])))
+        } else if value.iter().all(|(_, escaped)| !escaped) && to_string(&value) == "@self" {
+            Ok(Filter::SelfRef(attr))
+        } else if value.iter().all(|(_, escaped)| !escaped) && to_string(&value) == "@self.member" {
+            Ok(Filter::SelfRefMember(attr))
+        } else {
+            Ok(Filter::Eq(attr, to_string(&value)))
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = FilterParser {
+            chars: s.chars().peekable(),
+        };
+        let f = parser.parse_filter()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            Some(c) => Err(format!("unexpected '{}' after end of filter", c)),
+            None => Ok(f),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -337,6 +626,9 @@ pub enum AuthCredential {
     Anonymous,
     Password(String),
     TOTP(u32),
+    // A backup code, which may be given instead of a TOTP.
+    BackupCode(String),
+    Webauthn(PublicKeyCredential),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -349,6 +641,9 @@ pub enum AuthStep {
     ),
     */
     Creds(Vec<AuthCredential>),
+    // Authenticate again as the account of the current session, to raise it to
+    // privileged for a short time.
+    Reauth,
     // Should we have a "finalise" type to attempt to finish based on
     // what we have given?
 }
@@ -361,17 +656,25 @@ pub struct AuthRequest {
 
 // Respond with the list of auth types and nonce, etc.
 // It can also contain a denied, or success.
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AuthAllowed {
     Anonymous,
     Password,
     TOTP,
-    // Webauthn(String),
+    Webauthn(RequestChallengeResponse),
+}
+
+impl PartialEq for AuthAllowed {
+    fn eq(&self, other: &Self) -> bool {
+        // The webauthn challenge is different on every session, so we only
+        // compare that the same mechanisms are offered.
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum AuthState {
-    // Everything is good, your cookie has been issued, and a token is set here
+    // Everything is good, your bearer token has been issued, and a token is set here
     // for the client to view.
     Success(UserAuthToken),
     // Something was bad, your session is terminated and no cookie.
@@ -384,6 +687,8 @@ pub enum AuthState {
 pub struct AuthResponse {
     pub sessionid: Uuid,
     pub state: AuthState,
+    // On success, a signed token to present as "Authorization: Bearer" on later requests.
+    pub bearer: Option<String>,
 }
 
 // Types needed for setting credentials
@@ -393,8 +698,18 @@ pub enum SetCredentialRequest {
     GeneratePassword,
     TOTPGenerate(String),
     TOTPVerify(Uuid, u32),
-    //
-    // Webauthn(response)
+    TOTPRemove(String),
+    WebauthnBegin(String),
+    WebauthnRegister(Uuid, RegisterPublicKeyCredential),
+    WebauthnRemove(String),
+}
+
+// A credential change, authorised by a reset token that an administrator issued, rather
+// than by authenticating.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CredentialResetRequest {
+    pub token: String,
+    pub req: SetCredentialRequest,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -453,7 +768,10 @@ impl TOTPSecret {
 pub enum SetCredentialResponse {
     Success,
     Token(String),
+    // Single use codes that can be given in place of a totp. These are only shown once.
+    BackupCodes(Vec<String>),
     TOTPCheck(Uuid, TOTPSecret),
+    WebauthnCreateChallenge(Uuid, CreationChallengeResponse),
 }
 
 /* Recycle Requests area */
@@ -505,6 +823,137 @@ impl WhoamiResponse {
     }
 }
 
+/* Access control explain area */
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum AccessOperation {
+    Search,
+    Modify,
+    Create,
+    Delete,
+}
+
+impl fmt::Display for AccessOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessOperation::Search => write!(f, "search"),
+            AccessOperation::Modify => write!(f, "modify"),
+            AccessOperation::Create => write!(f, "create"),
+            AccessOperation::Delete => write!(f, "delete"),
+        }
+    }
+}
+
+impl FromStr for AccessOperation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "search" => Ok(AccessOperation::Search),
+            "modify" => Ok(AccessOperation::Modify),
+            "create" => Ok(AccessOperation::Create),
+            "delete" => Ok(AccessOperation::Delete),
+            _ => Err(format!(
+                "invalid operation {} - expected search, modify, create or delete",
+                s
+            )),
+        }
+    }
+}
+
+// Ask "what could identity do to target" - identity and target are a name or uuid.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessExplainRequest {
+    pub identity: String,
+    pub target: String,
+    pub operation: AccessOperation,
+}
+
+impl AccessExplainRequest {
+    pub fn new(identity: &str, target: &str, operation: AccessOperation) -> Self {
+        AccessExplainRequest {
+            identity: identity.to_string(),
+            target: target.to_string(),
+            operation,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum AccessProfileDecision {
+    Applied,
+    // The profile is outside of its acp_valid_from/acp_valid_until window.
+    Inactive,
+    ReceiverMismatch,
+    TargetMismatch,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccessProfileExplain {
+    pub name: String,
+    pub uuid: String,
+    // Deny profiles are evaluated first, and remove what they describe from
+    // the allowed sets.
+    pub deny: bool,
+    pub decision: AccessProfileDecision,
+    // The attributes and classes this profile grants. Which of these are
+    // populated depends on the operation being explained.
+    pub attrs: Vec<String>,
+    pub presattrs: Vec<String>,
+    pub remattrs: Vec<String>,
+    pub classes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccessExplainResponse {
+    pub identity: String,
+    pub target: String,
+    pub operation: AccessOperation,
+    // For search, modify and delete, this is true if any profile applied. For
+    // create, a single applied profile must cover every attribute and class of
+    // the target entry.
+    pub allowed: bool,
+    pub profiles: Vec<AccessProfileExplain>,
+    // The union of what the applied profiles grant.
+    pub allowed_attrs: Vec<String>,
+    pub allowed_presattrs: Vec<String>,
+    pub allowed_remattrs: Vec<String>,
+    pub allowed_classes: Vec<String>,
+}
+
+impl fmt::Display for AccessExplainResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "identity: {}", self.identity)?;
+        writeln!(f, "target: {}", self.target)?;
+        writeln!(f, "operation: {}", self.operation)?;
+        writeln!(f, "allowed: {}", self.allowed)?;
+        for p in self.profiles.iter() {
+            if p.deny {
+                writeln!(
+                    f,
+                    "deny profile: {} ({}) -> {:?}",
+                    p.name, p.uuid, p.decision
+                )?;
+            } else {
+                writeln!(f, "profile: {} ({}) -> {:?}", p.name, p.uuid, p.decision)?;
+            }
+        }
+        match self.operation {
+            AccessOperation::Search => writeln!(f, "search attrs: {:?}", self.allowed_attrs),
+            AccessOperation::Modify => {
+                writeln!(f, "present attrs: {:?}", self.allowed_presattrs)?;
+                writeln!(f, "removed attrs: {:?}", self.allowed_remattrs)?;
+                writeln!(f, "classes: {:?}", self.allowed_classes)
+            }
+            AccessOperation::Create => {
+                writeln!(f, "create attrs: {:?}", self.allowed_attrs)?;
+                writeln!(f, "classes: {:?}", self.allowed_classes)
+            }
+            AccessOperation::Delete => Ok(()),
+        }
+    }
+}
+
 // Simple string value provision.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SingleStringRequest {
@@ -530,6 +979,46 @@ mod tests {
         println!("{:?}", serde_json::to_string(&pf).expect("JSON failure"));
     }
 
+    #[test]
+    fn test_protofilter_readable() {
+        let pf = ProtoFilter::And(vec![
+            ProtoFilter::Eq("class".to_string(), "person".to_string()),
+            ProtoFilter::Or(vec![
+                ProtoFilter::Sub("name".to_string(), "ali(ce".to_string()),
+                ProtoFilter::SelfRef("manager".to_string()),
+                ProtoFilter::SelfRefMember("owner".to_string()),
+                ProtoFilter::SelfUUID,
+            ]),
+            ProtoFilter::AndNot(Box::new(ProtoFilter::Pres("nsuniqueid".to_string()))),
+            ProtoFilter::LessThan("gidnumber".to_string(), "1000".to_string()),
+            ProtoFilter::Eq("description".to_string(), "@self".to_string()),
+            ProtoFilter::Eq("description".to_string(), "@self.member".to_string()),
+            ProtoFilter::Eq("description".to_string(), "*".to_string()),
+        ]);
+        let s = pf.to_string();
+        assert_eq!(
+            s,
+            "(&(class=person)(|(name=*ali\\(ce*)(manager=@self)(owner=@self.member)(self))(!(nsuniqueid=*))(gidnumber<1000)(description=\\@self)(description=\\@self.member)(description=\\*))"
+        );
+        let pf2: ProtoFilter = s.parse().expect("failed to parse filter");
+        assert_eq!(pf, pf2);
+
+        // Whitespace between terms is fine, but unbalanced filters are not.
+        let pf3: ProtoFilter = " ( & (class=group) (name=demo) ) "
+            .parse()
+            .expect("failed to parse filter");
+        assert_eq!(
+            pf3,
+            ProtoFilter::And(vec![
+                ProtoFilter::Eq("class".to_string(), "group".to_string()),
+                ProtoFilter::Eq("name".to_string(), "demo".to_string()),
+            ])
+        );
+        assert!("(class=group".parse::<ProtoFilter>().is_err());
+        assert!("(class=group))".parse::<ProtoFilter>().is_err());
+        assert!("(class)".parse::<ProtoFilter>().is_err());
+    }
+
     #[test]
     fn totp_to_string() {
         let totp = TOTPSecret {
@@ -0,0 +1,279 @@
+use crate::common::{CommonOpt, Named};
+use kanidm_proto::v1::{AccessOperation, Entry, Filter};
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct AccessExplainOpt {
+    #[structopt()]
+    /// The account to explain the access of
+    identity: String,
+    #[structopt()]
+    /// The entry that would be accessed
+    target: String,
+    #[structopt()]
+    /// One of search, modify, create or delete
+    operation: AccessOperation,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct AccessProfileCreateOpt {
+    #[structopt()]
+    name: String,
+    #[structopt(long = "receiver")]
+    /// Who the profile applies to, ie "(memberof=idm_admins)"
+    receiver: Filter,
+    #[structopt(long = "targetscope")]
+    /// What the profile applies to, ie "(&(class=person)(!(name=admin)))"
+    targetscope: Filter,
+    #[structopt(long = "description")]
+    description: Option<String>,
+    #[structopt(long = "search")]
+    /// Attributes that may be searched and read
+    search_attr: Vec<String>,
+    #[structopt(long = "modify_present")]
+    /// Attributes that may have values added
+    modify_presentattr: Vec<String>,
+    #[structopt(long = "modify_removed")]
+    /// Attributes that may have values removed
+    modify_removedattr: Vec<String>,
+    #[structopt(long = "modify_class")]
+    /// Classes that may be added or removed
+    modify_class: Vec<String>,
+    #[structopt(long = "modify_value")]
+    /// Only allow these values to be added to an attribute, ie "loginshell=/bin/bash"
+    modify_presentvalue: Vec<String>,
+    #[structopt(long = "modify_refer")]
+    /// Only allow references to entries matching a filter, ie "member=(class=person)"
+    modify_presentrefer: Vec<String>,
+    #[structopt(long = "create_attr")]
+    /// Attributes that may be set on created entries
+    create_attr: Vec<String>,
+    #[structopt(long = "create_class")]
+    /// Classes that created entries may have
+    create_class: Vec<String>,
+    #[structopt(long = "delete")]
+    /// Allow matching entries to be deleted
+    delete: bool,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct AccessProfileFilterOpt {
+    #[structopt()]
+    name: String,
+    #[structopt()]
+    filter: Filter,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum AccessProfileOpt {
+    #[structopt(name = "list")]
+    /// List all access profiles
+    List(CommonOpt),
+    #[structopt(name = "get")]
+    /// Show an access profile
+    Get(Named),
+    #[structopt(name = "create")]
+    /// Create an access profile. Filters are written as "(attr=value)", and combined
+    /// with "(&..)", "(|..)" and "(!..)"
+    Create(AccessProfileCreateOpt),
+    #[structopt(name = "delete")]
+    /// Delete an access profile
+    Delete(Named),
+    #[structopt(name = "set_receiver")]
+    /// Replace who an access profile applies to
+    SetReceiver(AccessProfileFilterOpt),
+    #[structopt(name = "set_targetscope")]
+    /// Replace what an access profile applies to
+    SetTargetscope(AccessProfileFilterOpt),
+    #[structopt(name = "explain")]
+    /// Show which access profiles apply to an account acting on an entry, and why
+    Explain(AccessExplainOpt),
+}
+
+// Show the stored json filters in the same readable form that the cli accepts.
+fn display_profile(e: &Entry) {
+    for (attr, values) in e.attrs.iter() {
+        for v in values {
+            match attr.as_str() {
+                "acp_receiver" | "acp_targetscope" => match serde_json::from_str::<Filter>(v) {
+                    Ok(f) => println!("{}: {}", attr, f),
+                    Err(_) => println!("{}: {}", attr, v),
+                },
+                "acp_modify_presentrefer" => {
+                    let mut parts = v.splitn(2, '=');
+                    match (
+                        parts.next(),
+                        parts.next().map(serde_json::from_str::<Filter>),
+                    ) {
+                        (Some(a), Some(Ok(f))) => println!("{}: {}={}", attr, a, f),
+                        _ => println!("{}: {}", attr, v),
+                    }
+                }
+                _ => println!("{}: {}", attr, v),
+            }
+        }
+    }
+}
+
+impl AccessProfileCreateOpt {
+    fn to_entry(&self) -> Result<Entry, String> {
+        let mut classes = vec!["object".to_string(), "access_control_profile".to_string()];
+        if !self.search_attr.is_empty() {
+            classes.push("access_control_search".to_string());
+        }
+        if !(self.modify_presentattr.is_empty()
+            && self.modify_removedattr.is_empty()
+            && self.modify_class.is_empty())
+        {
+            classes.push("access_control_modify".to_string());
+        }
+        if !(self.create_attr.is_empty() && self.create_class.is_empty()) {
+            classes.push("access_control_create".to_string());
+        }
+        if self.delete {
+            classes.push("access_control_delete".to_string());
+        }
+        if classes.len() == 2 {
+            return Err(
+                "A profile must grant at least one of search, modify, create or delete".to_string(),
+            );
+        }
+
+        let receiver = serde_json::to_string(&self.receiver).map_err(|e| e.to_string())?;
+        let targetscope = serde_json::to_string(&self.targetscope).map_err(|e| e.to_string())?;
+
+        let mut attrs = BTreeMap::new();
+        attrs.insert("class".to_string(), classes);
+        attrs.insert("name".to_string(), vec![self.name.clone()]);
+        attrs.insert("acp_receiver".to_string(), vec![receiver]);
+        attrs.insert("acp_targetscope".to_string(), vec![targetscope]);
+        if let Some(d) = &self.description {
+            attrs.insert("description".to_string(), vec![d.clone()]);
+        }
+
+        // The server stores reference filters as json, like the receiver and targetscope.
+        let presentrefer: Result<Vec<String>, String> = self
+            .modify_presentrefer
+            .iter()
+            .map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(attr), Some(f)) => {
+                        let f: Filter = f.parse()?;
+                        let f = serde_json::to_string(&f).map_err(|e| e.to_string())?;
+                        Ok(format!("{}={}", attr, f))
+                    }
+                    _ => Err(format!("{} is not of the form attr=filter", pair)),
+                }
+            })
+            .collect();
+        let presentrefer = presentrefer?;
+
+        let lists = vec![
+            ("acp_search_attr", &self.search_attr),
+            ("acp_modify_presentattr", &self.modify_presentattr),
+            ("acp_modify_removedattr", &self.modify_removedattr),
+            ("acp_modify_class", &self.modify_class),
+            ("acp_modify_presentvalue", &self.modify_presentvalue),
+            ("acp_modify_presentrefer", &presentrefer),
+            ("acp_create_attr", &self.create_attr),
+            ("acp_create_class", &self.create_class),
+        ];
+        for (attr, values) in lists {
+            if !values.is_empty() {
+                attrs.insert(attr.to_string(), values.clone());
+            }
+        }
+
+        Ok(Entry { attrs })
+    }
+}
+
+impl AccessProfileOpt {
+    pub fn debug(&self) -> bool {
+        match self {
+            AccessProfileOpt::List(copt) => copt.debug,
+            AccessProfileOpt::Get(nopt) => nopt.copt.debug,
+            AccessProfileOpt::Create(copt) => copt.copt.debug,
+            AccessProfileOpt::Delete(nopt) => nopt.copt.debug,
+            AccessProfileOpt::SetReceiver(fopt) => fopt.copt.debug,
+            AccessProfileOpt::SetTargetscope(fopt) => fopt.copt.debug,
+            AccessProfileOpt::Explain(aopt) => aopt.copt.debug,
+        }
+    }
+
+    pub fn exec(&self) {
+        match self {
+            AccessProfileOpt::List(copt) => {
+                let client = copt.to_client();
+                match client.access_profile_list() {
+                    Ok(r) => r.iter().for_each(|e| {
+                        display_profile(e);
+                        println!();
+                    }),
+                    Err(e) => println!("Error: {:?}", e),
+                }
+            }
+            AccessProfileOpt::Get(nopt) => {
+                let client = nopt.copt.to_client();
+                match client.access_profile_get(nopt.name.as_str()) {
+                    Ok(Some(e)) => display_profile(&e),
+                    Ok(None) => println!("No matching access profile"),
+                    Err(e) => println!("Error: {:?}", e),
+                }
+            }
+            AccessProfileOpt::Create(copt) => {
+                let entry = match copt.to_entry() {
+                    Ok(e) => e,
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return;
+                    }
+                };
+                let client = copt.copt.to_client();
+                if let Err(e) = client.access_profile_create(entry) {
+                    println!("Error: {:?}", e);
+                }
+            }
+            AccessProfileOpt::Delete(nopt) => {
+                let client = nopt.copt.to_client();
+                if let Err(e) = client.access_profile_delete(nopt.name.as_str()) {
+                    println!("Error: {:?}", e);
+                }
+            }
+            AccessProfileOpt::SetReceiver(fopt) => {
+                let client = fopt.copt.to_client();
+                if let Err(e) = client.access_profile_set_receiver(fopt.name.as_str(), &fopt.filter)
+                {
+                    println!("Error: {:?}", e);
+                }
+            }
+            AccessProfileOpt::SetTargetscope(fopt) => {
+                let client = fopt.copt.to_client();
+                if let Err(e) =
+                    client.access_profile_set_targetscope(fopt.name.as_str(), &fopt.filter)
+                {
+                    println!("Error: {:?}", e);
+                }
+            }
+            AccessProfileOpt::Explain(aopt) => {
+                let client = aopt.copt.to_client();
+                match client.access_profile_explain(
+                    aopt.identity.as_str(),
+                    aopt.target.as_str(),
+                    aopt.operation,
+                ) {
+                    Ok(r) => println!("{}", r),
+                    Err(e) => println!("Error: {:?}", e),
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use crate::common::CommonOpt;
+use kanidm_client::{ClientError, KanidmClient};
+use kanidm_proto::v1::{OperationError, SetCredentialRequest};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -47,6 +49,48 @@ pub struct AccountNamedTagPKOpt {
     pubkey: String,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct AccountAppPasswordCreateOpt {
+    #[structopt(flatten)]
+    aopts: AccountCommonOpt,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+    #[structopt(name = "label")]
+    label: String,
+    #[structopt(name = "application")]
+    application: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct AccountAppPasswordRevokeOpt {
+    #[structopt(flatten)]
+    aopts: AccountCommonOpt,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+    #[structopt(name = "label")]
+    label: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct AccountCredentialLabelOpt {
+    #[structopt(flatten)]
+    aopts: AccountCommonOpt,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+    #[structopt(name = "label")]
+    label: String,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct AccountCredentialResetOpt {
+    #[structopt(flatten)]
+    aopts: AccountCommonOpt,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+    #[structopt(name = "token")]
+    token: String,
+}
+
 #[derive(Debug, StructOpt)]
 pub struct AccountCreateOpt {
     #[structopt(flatten)]
@@ -57,12 +101,31 @@ pub struct AccountCreateOpt {
     copt: CommonOpt,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct AccountValidityTimeOpt {
+    #[structopt(flatten)]
+    aopts: AccountCommonOpt,
+    /// Seconds since the unix epoch, or "clear" to remove the bound.
+    #[structopt(name = "time")]
+    time: String,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+}
+
 #[derive(Debug, StructOpt)]
 pub enum AccountCredential {
     #[structopt(name = "set_password")]
     SetPassword(AccountCredentialSet),
     #[structopt(name = "generate_password")]
     GeneratePassword(AccountCredentialSet),
+    #[structopt(name = "list_totp")]
+    ListTotp(AccountNamedOpt),
+    #[structopt(name = "remove_totp")]
+    RemoveTotp(AccountCredentialLabelOpt),
+    #[structopt(name = "create_reset_token")]
+    CreateResetToken(AccountNamedOpt),
+    #[structopt(name = "use_reset_token")]
+    UseResetToken(AccountCredentialResetOpt),
 }
 
 #[derive(Debug, StructOpt)]
@@ -107,16 +170,40 @@ pub enum AccountSsh {
     Delete(AccountNamedTagOpt),
 }
 
+#[derive(Debug, StructOpt)]
+pub enum AccountAppPassword {
+    #[structopt(name = "list")]
+    List(AccountNamedOpt),
+    #[structopt(name = "create")]
+    Create(AccountAppPasswordCreateOpt),
+    #[structopt(name = "revoke")]
+    Revoke(AccountAppPasswordRevokeOpt),
+}
+
+#[derive(Debug, StructOpt)]
+pub enum AccountValidity {
+    #[structopt(name = "show")]
+    Show(AccountNamedOpt),
+    #[structopt(name = "expire_at")]
+    ExpireAt(AccountValidityTimeOpt),
+    #[structopt(name = "begin_from")]
+    BeginFrom(AccountValidityTimeOpt),
+}
+
 #[derive(Debug, StructOpt)]
 pub enum AccountOpt {
     #[structopt(name = "credential")]
     Credential(AccountCredential),
     #[structopt(name = "radius")]
     Radius(AccountRadius),
+    #[structopt(name = "app_password")]
+    AppPassword(AccountAppPassword),
     #[structopt(name = "posix")]
     Posix(AccountPosix),
     #[structopt(name = "ssh")]
     Ssh(AccountSsh),
+    #[structopt(name = "validity")]
+    Validity(AccountValidity),
     #[structopt(name = "list")]
     List(CommonOpt),
     #[structopt(name = "get")]
@@ -125,6 +212,8 @@ pub enum AccountOpt {
     Create(AccountCreateOpt),
     #[structopt(name = "delete")]
     Delete(AccountNamedOpt),
+    #[structopt(name = "unlock")]
+    Unlock(AccountNamedOpt),
 }
 
 impl AccountOpt {
@@ -133,12 +222,21 @@ impl AccountOpt {
             AccountOpt::Credential(acopt) => match acopt {
                 AccountCredential::SetPassword(acs) => acs.copt.debug,
                 AccountCredential::GeneratePassword(acs) => acs.copt.debug,
+                AccountCredential::ListTotp(acs) => acs.copt.debug,
+                AccountCredential::RemoveTotp(acs) => acs.copt.debug,
+                AccountCredential::CreateResetToken(acs) => acs.copt.debug,
+                AccountCredential::UseResetToken(acs) => acs.copt.debug,
             },
             AccountOpt::Radius(acopt) => match acopt {
                 AccountRadius::Show(aro) => aro.copt.debug,
                 AccountRadius::Generate(aro) => aro.copt.debug,
                 AccountRadius::Delete(aro) => aro.copt.debug,
             },
+            AccountOpt::AppPassword(aapopt) => match aapopt {
+                AccountAppPassword::List(aapo) => aapo.copt.debug,
+                AccountAppPassword::Create(aapo) => aapo.copt.debug,
+                AccountAppPassword::Revoke(aapo) => aapo.copt.debug,
+            },
             AccountOpt::Posix(apopt) => match apopt {
                 AccountPosix::Show(apo) => apo.copt.debug,
                 AccountPosix::Set(apo) => apo.copt.debug,
@@ -149,10 +247,16 @@ impl AccountOpt {
                 AccountSsh::Add(ano) => ano.copt.debug,
                 AccountSsh::Delete(ano) => ano.copt.debug,
             },
+            AccountOpt::Validity(avopt) => match avopt {
+                AccountValidity::Show(ano) => ano.copt.debug,
+                AccountValidity::ExpireAt(ano) => ano.copt.debug,
+                AccountValidity::BeginFrom(ano) => ano.copt.debug,
+            },
             AccountOpt::List(copt) => copt.debug,
             AccountOpt::Get(aopt) => aopt.copt.debug,
             AccountOpt::Delete(aopt) => aopt.copt.debug,
             AccountOpt::Create(aopt) => aopt.copt.debug,
+            AccountOpt::Unlock(aopt) => aopt.copt.debug,
         }
     }
 
@@ -167,12 +271,12 @@ impl AccountOpt {
                     )
                     .unwrap();
 
-                    client
-                        .idm_account_primary_credential_set_password(
-                            acsopt.aopts.account_id.as_str(),
-                            password.as_str(),
-                        )
-                        .unwrap();
+                    if let Err(e) = client.idm_account_primary_credential_set_password(
+                        acsopt.aopts.account_id.as_str(),
+                        password.as_str(),
+                    ) {
+                        print_password_error(e);
+                    }
                 }
                 AccountCredential::GeneratePassword(acsopt) => {
                     let client = acsopt.copt.to_client();
@@ -187,6 +291,53 @@ impl AccountOpt {
                         acsopt.aopts.account_id, npw
                     );
                 }
+                AccountCredential::ListTotp(acsopt) => {
+                    let client = acsopt.copt.to_client();
+
+                    let labels = client
+                        .idm_account_primary_credential_list_totp(acsopt.aopts.account_id.as_str())
+                        .unwrap();
+
+                    for label in labels {
+                        println!("{}", label)
+                    }
+                }
+                AccountCredential::RemoveTotp(acsopt) => {
+                    let client = acsopt.copt.to_client();
+                    client
+                        .idm_account_primary_credential_remove_totp(
+                            acsopt.aopts.account_id.as_str(),
+                            acsopt.label.as_str(),
+                        )
+                        .unwrap();
+                }
+                AccountCredential::CreateResetToken(acsopt) => {
+                    let client = acsopt.copt.to_client();
+
+                    let token = client
+                        .idm_account_credential_create_reset_token(acsopt.aopts.account_id.as_str())
+                        .unwrap();
+                    println!(
+                        "Credential reset token for {}: {}",
+                        acsopt.aopts.account_id, token
+                    );
+                }
+                AccountCredential::UseResetToken(acsopt) => {
+                    // The token is what authorises this, so there is no login.
+                    let client = acsopt.copt.to_unauth_client();
+                    let password = rpassword::prompt_password_stderr(
+                        format!("Enter new password for {}: ", acsopt.aopts.account_id).as_str(),
+                    )
+                    .unwrap();
+
+                    if let Err(e) = client.idm_account_credential_reset(
+                        acsopt.aopts.account_id.as_str(),
+                        acsopt.token.as_str(),
+                        SetCredentialRequest::Password(password),
+                    ) {
+                        print_password_error(e);
+                    }
+                }
             }, // end AccountOpt::Credential
             AccountOpt::Radius(aropt) => match aropt {
                 AccountRadius::Show(aopt) => {
@@ -214,6 +365,43 @@ impl AccountOpt {
                         .unwrap();
                 }
             }, // end AccountOpt::Radius
+            AccountOpt::AppPassword(aapopt) => match aapopt {
+                AccountAppPassword::List(aopt) => {
+                    let client = aopt.copt.to_client();
+
+                    let app_pws = client
+                        .idm_account_app_password_list(aopt.aopts.account_id.as_str())
+                        .unwrap();
+
+                    for app_pw in app_pws {
+                        println!("{}", app_pw)
+                    }
+                }
+                AccountAppPassword::Create(aopt) => {
+                    let client = aopt.copt.to_client();
+
+                    let npw = client
+                        .idm_account_app_password_create(
+                            aopt.aopts.account_id.as_str(),
+                            aopt.label.as_str(),
+                            aopt.application.as_str(),
+                        )
+                        .unwrap();
+                    println!(
+                        "Generated {} password for {}: {}",
+                        aopt.application, aopt.aopts.account_id, npw
+                    );
+                }
+                AccountAppPassword::Revoke(aopt) => {
+                    let client = aopt.copt.to_client();
+                    client
+                        .idm_account_app_password_revoke(
+                            aopt.aopts.account_id.as_str(),
+                            aopt.label.as_str(),
+                        )
+                        .unwrap();
+                }
+            }, // end AccountOpt::AppPassword
             AccountOpt::Posix(apopt) => match apopt {
                 AccountPosix::Show(aopt) => {
                     let client = aopt.copt.to_client();
@@ -237,12 +425,12 @@ impl AccountOpt {
                     let password =
                         rpassword::prompt_password_stderr("Enter new unix (sudo) password: ")
                             .unwrap();
-                    client
-                        .idm_account_unix_cred_put(
-                            aopt.aopts.account_id.as_str(),
-                            password.as_str(),
-                        )
-                        .unwrap();
+                    if let Err(e) = client.idm_account_unix_cred_put(
+                        aopt.aopts.account_id.as_str(),
+                        password.as_str(),
+                    ) {
+                        print_password_error(e);
+                    }
                 }
             }, // end AccountOpt::Posix
             AccountOpt::Ssh(asopt) => match asopt {
@@ -277,6 +465,32 @@ impl AccountOpt {
                         .unwrap();
                 }
             }, // end AccountOpt::Ssh
+            AccountOpt::Validity(avopt) => match avopt {
+                AccountValidity::Show(ano) => {
+                    let client = ano.copt.to_client();
+
+                    let valid_from = client
+                        .idm_account_get_attr(ano.aopts.account_id.as_str(), "account_valid_from")
+                        .unwrap();
+                    let expire = client
+                        .idm_account_get_attr(ano.aopts.account_id.as_str(), "account_expire")
+                        .unwrap();
+
+                    println!(
+                        "valid from: {:?}",
+                        valid_from.and_then(|v| v.first().cloned())
+                    );
+                    println!("expire: {:?}", expire.and_then(|v| v.first().cloned()));
+                }
+                AccountValidity::ExpireAt(ano) => {
+                    let client = ano.copt.to_client();
+                    set_or_purge_validity(&client, ano, "account_expire");
+                }
+                AccountValidity::BeginFrom(ano) => {
+                    let client = ano.copt.to_client();
+                    set_or_purge_validity(&client, ano, "account_valid_from");
+                }
+            }, // end AccountOpt::Validity
             AccountOpt::List(copt) => {
                 let client = copt.to_client();
                 let r = client.idm_account_list().unwrap();
@@ -306,6 +520,51 @@ impl AccountOpt {
                     )
                     .unwrap();
             }
+            AccountOpt::Unlock(aopt) => {
+                let client = aopt.copt.to_client();
+                client
+                    .idm_account_unlock(aopt.aopts.account_id.as_str())
+                    .unwrap();
+            }
+        }
+    }
+}
+
+fn set_or_purge_validity(client: &KanidmClient, ano: &AccountValidityTimeOpt, attr: &str) {
+    if ano.time == "clear" {
+        client
+            .idm_account_purge_attr(ano.aopts.account_id.as_str(), attr)
+            .unwrap();
+    } else if ano.time.parse::<i64>().is_err() {
+        eprintln!("time must be seconds since the unix epoch, or \"clear\"");
+    } else {
+        client
+            .idm_account_set_attr(ano.aopts.account_id.as_str(), attr, vec![ano.time.as_str()])
+            .unwrap();
+    }
+}
+
+// Tell the user why their new password was refused, so they can choose a better one.
+pub(crate) fn print_password_error(e: ClientError) {
+    match e {
+        ClientError::Http(_, Some(OperationError::PasswordTooShort(len))) => {
+            eprintln!(
+                "Password is too short, it must be at least {} characters",
+                len
+            )
+        }
+        ClientError::Http(_, Some(OperationError::PasswordTooWeak)) => {
+            eprintln!("Password is too weak, try a longer or less predictable one")
+        }
+        ClientError::Http(_, Some(OperationError::PasswordBadListed)) => {
+            eprintln!("Password is on the list of known compromised passwords")
+        }
+        ClientError::Http(_, Some(OperationError::PasswordReused)) => {
+            eprintln!("Password has been used recently, and can't be used again")
+        }
+        ClientError::Http(_, Some(OperationError::PasswordEmpty)) => {
+            eprintln!("Password must not be empty")
         }
+        e => eprintln!("Failed to set password: {:?}", e),
     }
 }
@@ -23,7 +23,7 @@ pub struct CommonOpt {
 }
 
 impl CommonOpt {
-    pub fn to_client(&self) -> KanidmClient {
+    pub fn to_unauth_client(&self) -> KanidmClient {
         let config_path: String = shellexpand::tilde("~/.config/kanidm").into_owned();
 
         debug!("Attempting to use config {}", "/etc/kanidm/config");
@@ -48,9 +48,13 @@ impl CommonOpt {
             None => client_builder,
         };
 
-        let client = client_builder
+        client_builder
             .build()
-            .expect("Failed to build client instance");
+            .expect("Failed to build client instance")
+    }
+
+    pub fn to_client(&self) -> KanidmClient {
+        let client = self.to_unauth_client();
 
         let r = if self.username == "anonymous" {
             client.auth_anonymous()
@@ -29,6 +29,40 @@ pub enum GroupPosix {
     Set(GroupPosixOpt),
 }
 
+#[derive(Debug, StructOpt)]
+pub struct GroupPasswordPolicyOpt {
+    #[structopt()]
+    name: String,
+    #[structopt(long = "min_length")]
+    min_length: Option<u32>,
+    #[structopt(long = "min_score")]
+    min_score: Option<u32>,
+    #[structopt(long = "badlist")]
+    badlist: Option<bool>,
+    #[structopt(long = "require_mfa")]
+    require_mfa: Option<bool>,
+    #[structopt(long = "max_age")]
+    max_age: Option<i64>,
+    #[structopt(flatten)]
+    copt: CommonOpt,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum GroupPasswordPolicy {
+    #[structopt(name = "show")]
+    Show(Named),
+    #[structopt(name = "set")]
+    Set(GroupPasswordPolicyOpt),
+}
+
+const PASSWORD_POLICY_ATTRS: [&str; 5] = [
+    "pw_min_length",
+    "pw_min_score",
+    "pw_badlist",
+    "pw_require_mfa",
+    "pw_max_age",
+];
+
 #[derive(Debug, StructOpt)]
 pub enum GroupOpt {
     #[structopt(name = "list")]
@@ -47,6 +81,8 @@ pub enum GroupOpt {
     AddMembers(GroupNamedMembers),
     #[structopt(name = "posix")]
     Posix(GroupPosix),
+    #[structopt(name = "password_policy")]
+    PasswordPolicy(GroupPasswordPolicy),
 }
 
 impl GroupOpt {
@@ -63,6 +99,10 @@ impl GroupOpt {
                 GroupPosix::Show(gcopt) => gcopt.copt.debug,
                 GroupPosix::Set(gcopt) => gcopt.copt.debug,
             },
+            GroupOpt::PasswordPolicy(gpopt) => match gpopt {
+                GroupPasswordPolicy::Show(gcopt) => gcopt.copt.debug,
+                GroupPasswordPolicy::Set(gcopt) => gcopt.copt.debug,
+            },
         }
     }
 
@@ -127,6 +167,41 @@ impl GroupOpt {
                         .unwrap();
                 }
             },
+            GroupOpt::PasswordPolicy(gpopt) => match gpopt {
+                GroupPasswordPolicy::Show(gcopt) => {
+                    let client = gcopt.copt.to_client();
+                    for attr in PASSWORD_POLICY_ATTRS.iter() {
+                        let v = client
+                            .idm_group_get_attr(gcopt.name.as_str(), attr)
+                            .unwrap();
+                        match v {
+                            Some(v) => println!("{}: {}", attr, v.join(", ")),
+                            None => println!("{}: default", attr),
+                        }
+                    }
+                }
+                GroupPasswordPolicy::Set(gcopt) => {
+                    let client = gcopt.copt.to_client();
+                    let name = gcopt.name.as_str();
+                    client
+                        .idm_group_add_attr(name, "class", vec!["password_policy"])
+                        .unwrap();
+                    let values = [
+                        gcopt.min_length.map(|v| v.to_string()),
+                        gcopt.min_score.map(|v| v.to_string()),
+                        gcopt.badlist.map(|v| v.to_string()),
+                        gcopt.require_mfa.map(|v| v.to_string()),
+                        gcopt.max_age.map(|v| v.to_string()),
+                    ];
+                    for (attr, v) in PASSWORD_POLICY_ATTRS.iter().zip(values.iter()) {
+                        if let Some(v) = v {
+                            client
+                                .idm_group_set_attr(name, attr, vec![v.as_str()])
+                                .unwrap();
+                        }
+                    }
+                }
+            },
         } // end match
     }
 }
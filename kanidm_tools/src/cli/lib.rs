@@ -2,13 +2,15 @@
 extern crate log;
 use structopt::StructOpt;
 
+pub mod access_profile;
 pub mod account;
 pub mod common;
 pub mod group;
 pub mod raw;
 pub mod recycle;
 
-use crate::account::AccountOpt;
+use crate::access_profile::AccessProfileOpt;
+use crate::account::{print_password_error, AccountOpt};
 use crate::common::CommonOpt;
 use crate::group::GroupOpt;
 use crate::raw::RawOpt;
@@ -54,7 +56,9 @@ impl SelfOpt {
 
                 let password = rpassword::prompt_password_stderr("Enter new password: ").unwrap();
 
-                client.idm_account_set_password(password).unwrap();
+                if let Err(e) = client.idm_account_set_password(password) {
+                    print_password_error(e);
+                }
             }
         }
     }
@@ -75,6 +79,9 @@ pub enum ClientOpt {
     #[structopt(name = "recycle_bin")]
     /// Recycle Bin operations
     Recycle(RecycleOpt),
+    #[structopt(name = "access_profile")]
+    /// Access profile operations
+    AccessProfile(AccessProfileOpt),
     #[structopt(name = "raw")]
     /// Unsafe - low level, raw database operations.
     Raw(RawOpt),
@@ -88,6 +95,7 @@ impl ClientOpt {
             ClientOpt::Account(aopt) => aopt.debug(),
             ClientOpt::Group(gopt) => gopt.debug(),
             ClientOpt::Recycle(ropt) => ropt.debug(),
+            ClientOpt::AccessProfile(apopt) => apopt.debug(),
         }
     }
 
@@ -98,6 +106,7 @@ impl ClientOpt {
             ClientOpt::Account(aopt) => aopt.exec(),
             ClientOpt::Group(gopt) => gopt.exec(),
             ClientOpt::Recycle(ropt) => ropt.exec(),
+            ClientOpt::AccessProfile(apopt) => apopt.exec(),
         }
     }
 }
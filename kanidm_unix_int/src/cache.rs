@@ -1,5 +1,6 @@
 use crate::db::Db;
 use crate::unix_proto::{NssGroup, NssUser};
+use kanidm::credential::CryptoPolicy;
 use kanidm_client::asynchronous::KanidmAsyncClient;
 use kanidm_client::ClientError;
 use kanidm_proto::v1::{OperationError, UnixGroupToken, UnixUserToken};
@@ -50,7 +51,9 @@ impl CacheLayer {
         client: KanidmAsyncClient,
         pam_allow_groups: Vec<String>,
     ) -> Result<Self, ()> {
-        let db = Db::new(path)?;
+        // Passwords are cached so that we can authenticate while offline, which is
+        // why they need the same care as on the server.
+        let db = Db::new(path, CryptoPolicy::calibrate())?;
 
         // setup and do a migrate.
         {
@@ -631,7 +634,21 @@ impl CacheLayer {
     pub async fn pam_account_allowed(&self, account_id: &str) -> Result<Option<bool>, ()> {
         let token = self.get_usertoken(Id::Name(account_id.to_string())).await?;
 
+        // The window is checked here rather than trusting the server at the time the
+        // token was cached, so that an account expires even while we are offline.
+        let ct = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("Clock failure!")
+            .as_secs() as i64;
+
         Ok(token.map(|tok| {
+            let after_start = tok.valid_from.map(|vf| vf <= ct).unwrap_or(true);
+            let before_end = tok.expire.map(|ex| ct < ex).unwrap_or(true);
+            if !(after_start && before_end) {
+                debug!("Account is not within its valid time -> {:?}", tok.name);
+                return false;
+            }
+
             let user_set: BTreeSet<_> = tok.groups.iter().map(|g| g.name.clone()).collect();
 
             debug!(
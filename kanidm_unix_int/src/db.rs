@@ -9,21 +9,23 @@ use crate::cache::Id;
 use std::sync::{Mutex, MutexGuard};
 
 use kanidm::be::dbvalue::DbPasswordV1;
-use kanidm::credential::Password;
+use kanidm::credential::{CryptoPolicy, Password};
 
 pub struct Db {
     pool: Pool<SqliteConnectionManager>,
     lock: Mutex<()>,
+    crypto_policy: CryptoPolicy,
 }
 
 pub struct DbTxn<'a> {
     _guard: MutexGuard<'a, ()>,
     committed: bool,
     conn: r2d2::PooledConnection<SqliteConnectionManager>,
+    crypto_policy: &'a CryptoPolicy,
 }
 
 impl Db {
-    pub fn new(path: &str) -> Result<Self, ()> {
+    pub fn new(path: &str, crypto_policy: CryptoPolicy) -> Result<Self, ()> {
         let manager = SqliteConnectionManager::file(path);
         // We only build a single thread. If we need more than one, we'll
         // need to re-do this to account for path = "" for debug.
@@ -36,6 +38,7 @@ impl Db {
         Ok(Db {
             pool: pool,
             lock: Mutex::new(()),
+            crypto_policy,
         })
     }
 
@@ -45,7 +48,7 @@ impl Db {
             .pool
             .get()
             .expect("Unable to get connection from pool!!!");
-        DbTxn::new(conn, guard)
+        DbTxn::new(conn, guard, &self.crypto_policy)
     }
 }
 
@@ -59,6 +62,7 @@ impl<'a> DbTxn<'a> {
     pub fn new(
         conn: r2d2::PooledConnection<SqliteConnectionManager>,
         guard: MutexGuard<'a, ()>,
+        crypto_policy: &'a CryptoPolicy,
     ) -> Self {
         // Start the transaction
         // debug!("Starting db WR txn ...");
@@ -68,6 +72,7 @@ impl<'a> DbTxn<'a> {
             committed: false,
             conn,
             _guard: guard,
+            crypto_policy,
         }
     }
 
@@ -427,7 +432,7 @@ impl<'a> DbTxn<'a> {
     }
 
     pub fn update_account_password(&self, a_uuid: &str, cred: &str) -> Result<(), ()> {
-        let pw = Password::new(cred);
+        let pw = Password::new(self.crypto_policy, cred);
         let dbpw = pw.to_dbpasswordv1();
         let data = serde_cbor::to_vec(&dbpw).map_err(|e| {
             error!("cbor error -> {:?}", e);
@@ -483,19 +488,28 @@ impl<'a> DbTxn<'a> {
             return Err(());
         }
 
-        let r: Result<bool, ()> = data
-            .first()
-            .map(|raw| {
-                // Map the option from data.first.
+        let pw = match data.first() {
+            Some(raw) => {
                 let dbpw: DbPasswordV1 = serde_cbor::from_slice(raw.as_slice()).map_err(|e| {
                     error!("cbor error -> {:?}", e);
                     ()
                 })?;
-                let pw = Password::try_from(dbpw)?;
-                Ok(pw.verify(cred))
-            })
-            .unwrap_or(Ok(false));
-        r
+                Password::try_from(dbpw)?
+            }
+            None => return Ok(false),
+        };
+
+        if !pw.verify(cred) {
+            return Ok(false);
+        }
+
+        // If the cached hash is weaker than our policy, replace it now that we know
+        // the password.
+        if pw.requires_upgrade(self.crypto_policy) {
+            debug!("Upgrading cached password hash");
+            self.update_account_password(a_uuid, cred)?;
+        }
+        Ok(true)
     }
 
     fn get_group_data_name(&self, grp_id: &str) -> Result<Vec<(Vec<u8>, i64)>, ()> {
@@ -727,6 +741,7 @@ impl<'a> Drop for DbTxn<'a> {
 mod tests {
     use super::Db;
     use crate::cache::Id;
+    use kanidm::credential::CryptoPolicy;
     use kanidm_proto::v1::{UnixGroupToken, UnixUserToken};
 
     const TESTACCOUNT1_PASSWORD_A: &str = "password a for account1 test";
@@ -735,7 +750,7 @@ mod tests {
     #[test]
     fn test_cache_db_account_basic() {
         let _ = env_logger::builder().is_test(true).try_init();
-        let db = Db::new("").expect("failed to create.");
+        let db = Db::new("", CryptoPolicy::minimum()).expect("failed to create.");
         let dbtxn = db.write();
         assert!(dbtxn.migrate().is_ok());
 
@@ -748,6 +763,8 @@ mod tests {
             shell: None,
             groups: Vec::new(),
             sshkeys: vec!["key-a".to_string()],
+            valid_from: None,
+            expire: None,
         };
 
         let id_name = Id::Name("testuser".to_string());
@@ -818,7 +835,7 @@ mod tests {
     #[test]
     fn test_cache_db_group_basic() {
         let _ = env_logger::builder().is_test(true).try_init();
-        let db = Db::new("").expect("failed to create.");
+        let db = Db::new("", CryptoPolicy::minimum()).expect("failed to create.");
         let dbtxn = db.write();
         assert!(dbtxn.migrate().is_ok());
 
@@ -893,7 +910,7 @@ mod tests {
     #[test]
     fn test_cache_db_account_group_update() {
         let _ = env_logger::builder().is_test(true).try_init();
-        let db = Db::new("").expect("failed to create.");
+        let db = Db::new("", CryptoPolicy::minimum()).expect("failed to create.");
         let dbtxn = db.write();
         assert!(dbtxn.migrate().is_ok());
 
@@ -920,6 +937,8 @@ mod tests {
             shell: None,
             groups: vec![gt1.clone(), gt2],
             sshkeys: vec!["key-a".to_string()],
+            valid_from: None,
+            expire: None,
         };
 
         // First, add the groups.
@@ -960,7 +979,7 @@ mod tests {
     #[test]
     fn test_cache_db_account_password() {
         let _ = env_logger::builder().is_test(true).try_init();
-        let db = Db::new("").expect("failed to create.");
+        let db = Db::new("", CryptoPolicy::minimum()).expect("failed to create.");
         let dbtxn = db.write();
         assert!(dbtxn.migrate().is_ok());
 
@@ -974,6 +993,8 @@ mod tests {
             shell: None,
             groups: Vec::new(),
             sshkeys: vec!["key-a".to_string()],
+            valid_from: None,
+            expire: None,
         };
 
         // Test that with no account, is false
@@ -1008,7 +1029,7 @@ mod tests {
     #[test]
     fn test_cache_db_group_rename_duplicate() {
         let _ = env_logger::builder().is_test(true).try_init();
-        let db = Db::new("").expect("failed to create.");
+        let db = Db::new("", CryptoPolicy::minimum()).expect("failed to create.");
         let dbtxn = db.write();
         assert!(dbtxn.migrate().is_ok());
 
@@ -1063,7 +1084,7 @@ mod tests {
     #[test]
     fn test_cache_db_account_rename_duplicate() {
         let _ = env_logger::builder().is_test(true).try_init();
-        let db = Db::new("").expect("failed to create.");
+        let db = Db::new("", CryptoPolicy::minimum()).expect("failed to create.");
         let dbtxn = db.write();
         assert!(dbtxn.migrate().is_ok());
 
@@ -1076,6 +1097,8 @@ mod tests {
             shell: None,
             groups: Vec::new(),
             sshkeys: vec!["key-a".to_string()],
+            valid_from: None,
+            expire: None,
         };
 
         let ut2 = UnixUserToken {
@@ -1087,6 +1110,8 @@ mod tests {
             shell: None,
             groups: Vec::new(),
             sshkeys: vec!["key-a".to_string()],
+            valid_from: None,
+            expire: None,
         };
 
         let id_name = Id::Name("testuser".to_string());
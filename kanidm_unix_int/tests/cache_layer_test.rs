@@ -481,6 +481,53 @@ fn test_cache_account_pam_allowed() {
     })
 }
 
+#[test]
+fn test_cache_account_pam_expired() {
+    run_test(test_fixture, |cachelayer, adminclient| {
+        let mut rt = Runtime::new().expect("Failed to start tokio");
+        let fut = async move {
+            cachelayer.attempt_online().await;
+
+            adminclient
+                .auth_simple_password("admin", ADMIN_TEST_PASSWORD)
+                .await
+                .expect("failed to auth as admin");
+            adminclient
+                .idm_group_add_members("allowed_group", vec!["testaccount1"])
+                .await
+                .unwrap();
+
+            let a1 = cachelayer
+                .pam_account_allowed("testaccount1")
+                .await
+                .expect("failed to authenticate");
+            assert!(a1 == Some(true));
+
+            // Expire the account, long ago.
+            adminclient
+                .idm_account_set_attr("testaccount1", "account_expire", vec!["1"])
+                .await
+                .unwrap();
+            assert!(cachelayer.invalidate().is_ok());
+
+            let a2 = cachelayer
+                .pam_account_allowed("testaccount1")
+                .await
+                .expect("failed to authenticate");
+            assert!(a2 == Some(false));
+
+            // Even while offline from the cached token.
+            cachelayer.mark_offline().await;
+            let a3 = cachelayer
+                .pam_account_allowed("testaccount1")
+                .await
+                .expect("failed to authenticate");
+            assert!(a3 == Some(false));
+        };
+        rt.block_on(fut);
+    })
+}
+
 #[test]
 fn test_cache_account_pam_nonexist() {
     run_test(test_fixture, |cachelayer, _adminclient| {
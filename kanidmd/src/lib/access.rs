@@ -18,17 +18,22 @@
 use concread::collections::bptree::*;
 use kanidm_proto::v1::Filter as ProtoFilter;
 use kanidm_proto::v1::OperationError;
-use std::collections::BTreeSet;
+use kanidm_proto::v1::{
+    AccessExplainResponse, AccessOperation, AccessProfileDecision, AccessProfileExplain,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
 use uuid::Uuid;
 
 use crate::audit::AuditScope;
 use crate::entry::{Entry, EntryCommitted, EntryInit, EntryNew, EntryReduced, EntrySealed};
 use crate::filter::{Filter, FilterValid};
 use crate::modify::Modify;
+use crate::schema::SchemaTransaction;
 use crate::server::{QueryServerTransaction, QueryServerWriteTransaction};
-use crate::value::PartialValue;
+use crate::value::{PartialValue, SyntaxType, Value};
 
-use crate::event::{CreateEvent, DeleteEvent, EventOrigin, ModifyEvent, SearchEvent};
+use crate::event::{CreateEvent, DeleteEvent, Event, EventOrigin, ModifyEvent, SearchEvent};
 
 lazy_static! {
     static ref CLASS_ACS: PartialValue = PartialValue::new_class("access_control_search");
@@ -50,10 +55,10 @@ pub struct AccessControlSearch {
 }
 
 impl AccessControlSearch {
-    pub fn try_from(
+    pub fn try_from<STATE>(
         audit: &mut AuditScope,
         qs: &mut QueryServerWriteTransaction,
-        value: &Entry<EntrySealed, EntryCommitted>,
+        value: &Entry<EntrySealed, STATE>,
     ) -> Result<Self, OperationError> {
         if !value.attribute_value_pres("class", &CLASS_ACS) {
             audit_log!(audit, "class access_control_search not present.");
@@ -88,6 +93,9 @@ impl AccessControlSearch {
                 uuid: Uuid::parse_str(uuid).unwrap(),
                 receiver,
                 targetscope,
+                deny: false,
+                valid_from: None,
+                valid_until: None,
             },
             attrs: attrs.split_whitespace().map(|s| s.to_string()).collect(),
         }
@@ -100,10 +108,10 @@ pub struct AccessControlDelete {
 }
 
 impl AccessControlDelete {
-    pub fn try_from(
+    pub fn try_from<STATE>(
         audit: &mut AuditScope,
         qs: &mut QueryServerWriteTransaction,
-        value: &Entry<EntrySealed, EntryCommitted>,
+        value: &Entry<EntrySealed, STATE>,
     ) -> Result<Self, OperationError> {
         if !value.attribute_value_pres("class", &CLASS_ACD) {
             audit_log!(audit, "class access_control_delete not present.");
@@ -130,6 +138,9 @@ impl AccessControlDelete {
                 uuid: Uuid::parse_str(uuid).unwrap(),
                 receiver,
                 targetscope,
+                deny: false,
+                valid_from: None,
+                valid_until: None,
             },
         }
     }
@@ -143,10 +154,10 @@ pub struct AccessControlCreate {
 }
 
 impl AccessControlCreate {
-    pub fn try_from(
+    pub fn try_from<STATE>(
         audit: &mut AuditScope,
         qs: &mut QueryServerWriteTransaction,
-        value: &Entry<EntrySealed, EntryCommitted>,
+        value: &Entry<EntrySealed, STATE>,
     ) -> Result<Self, OperationError> {
         if !value.attribute_value_pres("class", &CLASS_ACC) {
             audit_log!(audit, "class access_control_create not present.");
@@ -185,6 +196,9 @@ impl AccessControlCreate {
                 uuid: Uuid::parse_str(uuid).unwrap(),
                 receiver,
                 targetscope,
+                deny: false,
+                valid_from: None,
+                valid_until: None,
             },
             classes: classes.split_whitespace().map(|s| s.to_string()).collect(),
             attrs: attrs.split_whitespace().map(|s| s.to_string()).collect(),
@@ -198,13 +212,47 @@ pub struct AccessControlModify {
     classes: Vec<String>,
     presattrs: Vec<String>,
     remattrs: Vec<String>,
+    // Per attribute restrictions on the values that may be made present. An
+    // attribute that isn't named here may be given any value.
+    presvalues: BTreeMap<String, BTreeSet<PartialValue>>,
+    presrefers: BTreeMap<String, Filter<FilterValid>>,
+}
+
+// Split an "attr=value" pair from acp_modify_presentvalue or acp_modify_presentrefer,
+// checking the attribute exists.
+fn split_attr_value<'a>(
+    audit: &mut AuditScope,
+    qs: &QueryServerWriteTransaction,
+    source: &str,
+    pair: &'a str,
+) -> Result<(String, &'a str), OperationError> {
+    let mut parts = pair.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(a), Some(v)) => match qs.get_schema().normalise_attr_if_exists(a.trim()) {
+            Some(attr) => Ok((attr, v)),
+            None => {
+                audit_log!(audit, "{} names an unknown attribute {:?}", source, a);
+                Err(OperationError::InvalidACPState(format!(
+                    "Unknown attribute in {}",
+                    source
+                )))
+            }
+        },
+        _ => {
+            audit_log!(audit, "{} is not an attr=value pair {:?}", source, pair);
+            Err(OperationError::InvalidACPState(format!(
+                "Invalid attr=value pair in {}",
+                source
+            )))
+        }
+    }
 }
 
 impl AccessControlModify {
-    pub fn try_from(
+    pub fn try_from<STATE>(
         audit: &mut AuditScope,
         qs: &mut QueryServerWriteTransaction,
-        value: &Entry<EntrySealed, EntryCommitted>,
+        value: &Entry<EntrySealed, STATE>,
     ) -> Result<Self, OperationError> {
         if !value.attribute_value_pres("class", &CLASS_ACM) {
             audit_log!(audit, "class access_control_modify not present.");
@@ -225,14 +273,106 @@ impl AccessControlModify {
             .get_ava_opt_string("acp_modify_class")
             .unwrap_or_else(Vec::new);
 
+        let mut presvalues: BTreeMap<String, BTreeSet<PartialValue>> = BTreeMap::new();
+        for pair in value
+            .get_ava_opt_string("acp_modify_presentvalue")
+            .unwrap_or_else(Vec::new)
+        {
+            let (attr, v) = split_attr_value(audit, qs, "acp_modify_presentvalue", &pair)?;
+            // Normalise the value the same way as the attribute it will be compared with.
+            let pv = try_audit!(audit, qs.clone_partialvalue(audit, attr.as_str(), v));
+            presvalues
+                .entry(attr)
+                .or_insert_with(BTreeSet::new)
+                .insert(pv);
+        }
+
+        let mut presrefers: BTreeMap<String, Filter<FilterValid>> = BTreeMap::new();
+        for pair in value
+            .get_ava_opt_string("acp_modify_presentrefer")
+            .unwrap_or_else(Vec::new)
+        {
+            let (attr, f) = split_attr_value(audit, qs, "acp_modify_presentrefer", &pair)?;
+            let is_refer = qs
+                .get_schema()
+                .get_attributes()
+                .get(&attr)
+                .map(|a| a.syntax == SyntaxType::REFERENCE_UUID)
+                .unwrap_or(false);
+            if !is_refer || presrefers.contains_key(&attr) {
+                audit_log!(
+                    audit,
+                    "acp_modify_presentrefer {:?} is not a reference, or is repeated",
+                    attr
+                );
+                return Err(OperationError::InvalidACPState(
+                    "Invalid acp_modify_presentrefer".to_string(),
+                ));
+            }
+            let pf: ProtoFilter = try_audit!(
+                audit,
+                serde_json::from_str(f).map_err(|_| OperationError::InvalidACPState(
+                    "Invalid filter in acp_modify_presentrefer".to_string()
+                ))
+            );
+            let f_i = try_audit!(audit, Filter::from_rw(audit, &pf, qs));
+            let f_v = try_audit!(
+                audit,
+                f_i.validate(qs.get_schema())
+                    .map_err(OperationError::SchemaViolation)
+            );
+            presrefers.insert(attr, f_v);
+        }
+
         Ok(AccessControlModify {
             acp: AccessControlProfile::try_from(audit, qs, value)?,
             classes,
             presattrs,
             remattrs,
+            presvalues,
+            presrefers,
         })
     }
 
+    fn has_value_rules(&self) -> bool {
+        !(self.presvalues.is_empty() && self.presrefers.is_empty())
+    }
+
+    // May this profile make the value present on the attribute? The attribute
+    // itself must be granted, and then any restrictions on it must hold.
+    fn value_allowed(
+        &self,
+        audit: &mut AuditScope,
+        ev: &Event,
+        attr: &str,
+        v: &Value,
+        refer_entries: &[Entry<EntrySealed, EntryCommitted>],
+    ) -> bool {
+        if !self.presattrs.iter().any(|a| a == attr) {
+            return false;
+        }
+        if let Some(allowed) = self.presvalues.get(attr) {
+            if !allowed.contains(&v.to_partialvalue()) {
+                return false;
+            }
+        }
+        if let Some(f) = self.presrefers.get(attr) {
+            let refer = v
+                .to_ref_uuid()
+                .and_then(|u| refer_entries.iter().find(|re| re.get_uuid() == u));
+            match refer {
+                Some(re) => {
+                    if !AccessControlProfile::filter_match(audit, f, ev, re) {
+                        return false;
+                    }
+                }
+                // Refers to something that doesn't exist, refint will reject it anyway.
+                None => return false,
+            }
+        }
+        true
+    }
+
     #[cfg(test)]
     unsafe fn from_raw(
         name: &str,
@@ -249,6 +389,9 @@ impl AccessControlModify {
                 uuid: Uuid::parse_str(uuid).unwrap(),
                 receiver,
                 targetscope,
+                deny: false,
+                valid_from: None,
+                valid_until: None,
             },
             classes: classes.split_whitespace().map(|s| s.to_string()).collect(),
             presattrs: presattrs
@@ -256,6 +399,8 @@ impl AccessControlModify {
                 .map(|s| s.to_string())
                 .collect(),
             remattrs: remattrs.split_whitespace().map(|s| s.to_string()).collect(),
+            presvalues: BTreeMap::new(),
+            presrefers: BTreeMap::new(),
         }
     }
 }
@@ -266,13 +411,20 @@ struct AccessControlProfile {
     uuid: Uuid,
     receiver: Filter<FilterValid>,
     targetscope: Filter<FilterValid>,
+    // Deny profiles remove what they describe from the allowed sets, and are
+    // evaluated before any allow.
+    deny: bool,
+    // The window, in seconds since the epoch, in which this profile is applied.
+    // Outside of it the profile is ignored as though it did not exist.
+    valid_from: Option<Duration>,
+    valid_until: Option<Duration>,
 }
 
 impl AccessControlProfile {
-    fn try_from(
+    fn try_from<STATE>(
         audit: &mut AuditScope,
         qs: &mut QueryServerWriteTransaction,
-        value: &Entry<EntrySealed, EntryCommitted>,
+        value: &Entry<EntrySealed, STATE>,
     ) -> Result<Self, OperationError> {
         // Assert we have class access_control_profile
         if !value.attribute_value_pres("class", &CLASS_ACP) {
@@ -325,12 +477,477 @@ impl AccessControlProfile {
                 .map_err(OperationError::SchemaViolation)
         );
 
+        // Is this a deny profile?
+        let deny = value.get_ava_single_bool("acp_deny").unwrap_or(false);
+
+        // Is this bounded in time? Schema asserts these are not negative.
+        let valid_from = value.get_ava_single_duration("acp_valid_from");
+        let valid_until = value.get_ava_single_duration("acp_valid_until");
+
+        if let (Some(vf), Some(vu)) = (valid_from, valid_until) {
+            if vf > vu {
+                audit_log!(audit, "acp_valid_from is after acp_valid_until");
+                return Err(OperationError::InvalidACPState(
+                    "acp_valid_from is after acp_valid_until".to_string(),
+                ));
+            }
+        }
+
         Ok(AccessControlProfile {
             name,
             uuid,
             receiver,
             targetscope,
+            deny,
+            valid_from,
+            valid_until,
+        })
+    }
+}
+
+impl AccessControlProfile {
+    // Resolve one of our filters in the context of the event, and check if the
+    // entry matches it.
+    fn filter_match<VALID, STATE>(
+        audit: &mut AuditScope,
+        f: &Filter<FilterValid>,
+        ev: &Event,
+        e: &Entry<VALID, STATE>,
+    ) -> bool {
+        match f.resolve(ev, None) {
+            Ok(f_res) => e.entry_match_no_index(&f_res),
+            Err(err) => {
+                audit_log!(
+                    audit,
+                    "A internal filter was passed for resolution!?!? {:?}",
+                    err
+                );
+                false
+            }
+        }
+    }
+
+    fn receiver_match(
+        &self,
+        audit: &mut AuditScope,
+        ev: &Event,
+        rec_entry: &Entry<EntrySealed, EntryCommitted>,
+    ) -> bool {
+        // Okay, so in filter resolution, the primary error case
+        // is that we have a non-user in the event. Callers have already
+        // checked for this BUT we should still check here
+        // properly just in case.
+        //
+        // In this case, we assume that if the event is internal
+        // that the receiver can NOT match because it has no selfuuid
+        // and can as a result, never return true. This leads to this
+        // acp not being considered in that case ... which should never
+        // happen because internal ops bypass access controls!
+        //
+        // A possible solution is to change the filter resolve function
+        // such that it takes an entry, rather than an event, but that
+        // would create issues in search.
+        Self::filter_match(audit, &self.receiver, ev, rec_entry)
+    }
+
+    fn target_match<VALID, STATE>(
+        &self,
+        audit: &mut AuditScope,
+        ev: &Event,
+        e: &Entry<VALID, STATE>,
+    ) -> bool {
+        // We are continually compiling and using these
+        // in a tight loop, so this is a possible oppurtunity
+        // to cache or handle these filters better - filter compiler
+        // cache maybe?
+        Self::filter_match(audit, &self.targetscope, ev, e)
+    }
+
+    fn get_self_ref_member_attrs<'a>(&'a self, r_set: &mut BTreeSet<&'a str>) {
+        self.receiver.get_self_ref_member_attrs(r_set);
+        self.targetscope.get_self_ref_member_attrs(r_set);
+    }
+
+    // Is the transaction time within the window this profile is valid for? As for
+    // account validity, this is from valid_from inclusive, until valid_until exclusive.
+    fn is_active(&self, audit: &mut AuditScope, ts: Duration) -> bool {
+        let after_start = self.valid_from.map(|vf| vf <= ts).unwrap_or(true);
+        let before_end = self.valid_until.map(|vu| ts < vu).unwrap_or(true);
+        if !(after_start && before_end) {
+            audit_log!(
+                audit,
+                "acp {:?} is not valid at {:?}, ignoring",
+                self.name,
+                ts
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    fn explain(
+        &self,
+        audit: &mut AuditScope,
+        ev: &Event,
+        ts: Duration,
+        rec_entry: &Entry<EntrySealed, EntryCommitted>,
+        target: &Entry<EntrySealed, EntryCommitted>,
+    ) -> AccessProfileExplain {
+        let decision = if !self.is_active(audit, ts) {
+            AccessProfileDecision::Inactive
+        } else if !self.receiver_match(audit, ev, rec_entry) {
+            AccessProfileDecision::ReceiverMismatch
+        } else if !self.target_match(audit, ev, target) {
+            AccessProfileDecision::TargetMismatch
+        } else {
+            AccessProfileDecision::Applied
+        };
+
+        AccessProfileExplain {
+            name: self.name.clone(),
+            uuid: self.uuid.to_hyphenated_ref().to_string(),
+            deny: self.deny,
+            decision,
+            attrs: Vec::new(),
+            presattrs: Vec::new(),
+            remattrs: Vec::new(),
+            classes: Vec::new(),
+        }
+    }
+}
+
+// =========================================================================
+// ACP evaluation, shared by enforcement and explain.
+// =========================================================================
+
+// From the acps related to the receiver, the set of attributes that can be
+// searched on this entry. Attributes named by a matching deny are removed, even
+// if an allow grants them.
+fn search_allowed_attrs<'a>(
+    audit: &mut AuditScope,
+    related_acp: &[&'a AccessControlSearch],
+    ev: &Event,
+    e: &Entry<EntrySealed, EntryCommitted>,
+) -> BTreeSet<&'a str> {
+    let scoped_acp: Vec<&AccessControlSearch> = related_acp
+        .iter()
+        .filter(|acs| {
+            // if it applies
+            if acs.acp.target_match(audit, ev, e) {
+                audit_log!(audit, "entry {:?} matches acs {:?}", e.get_uuid(), acs);
+                true
+            } else {
+                audit_log!(
+                    audit,
+                    "entry {:?} DOES NOT match acs {:?}",
+                    e.get_uuid(),
+                    acs
+                );
+                false
+            }
+        })
+        .copied()
+        .collect();
+
+    let denied_attrs: BTreeSet<&str> = scoped_acp
+        .iter()
+        .filter(|acs| acs.acp.deny)
+        .flat_map(|acs| acs.attrs.iter().map(|s| s.as_str()))
+        .collect();
+
+    if !denied_attrs.is_empty() {
+        audit_log!(audit, "denied attributes    --> {:?}", denied_attrs);
+    }
+
+    // add search_attrs to allowed, unless denied.
+    scoped_acp
+        .iter()
+        .filter(|acs| !acs.acp.deny)
+        .flat_map(|acs| acs.attrs.iter().map(|s| s.as_str()))
+        .filter(|a| !denied_attrs.contains(a))
+        .collect()
+}
+
+// From the acps related to the receiver, the sets of present attributes, removed
+// attributes and classes that may be modified on this entry. Anything named by a
+// matching deny is removed, even if an allow grants it.
+fn modify_allowed_sets<'a>(
+    audit: &mut AuditScope,
+    related_acp: &[&'a AccessControlModify],
+    ev: &Event,
+    e: &Entry<EntrySealed, EntryCommitted>,
+) -> (BTreeSet<&'a str>, BTreeSet<&'a str>, BTreeSet<&'a str>) {
+    // For this entry, find the acp's that apply to it from the
+    // set that apply to the entry that is performing the operation
+    let (deny_acp, allow_acp): (Vec<&AccessControlModify>, Vec<&AccessControlModify>) = related_acp
+        .iter()
+        .filter(|acm| acm.acp.target_match(audit, ev, e))
+        .copied()
+        .partition(|acm| acm.acp.deny);
+
+    let denied_pres: BTreeSet<&str> = deny_acp
+        .iter()
+        .flat_map(|acp| acp.presattrs.iter().map(|v| v.as_str()))
+        .collect();
+
+    let denied_rem: BTreeSet<&str> = deny_acp
+        .iter()
+        .flat_map(|acp| acp.remattrs.iter().map(|v| v.as_str()))
+        .collect();
+
+    let denied_classes: BTreeSet<&str> = deny_acp
+        .iter()
+        .flat_map(|acp| acp.classes.iter().map(|v| v.as_str()))
+        .collect();
+
+    if !deny_acp.is_empty() {
+        audit_log!(audit, "Denied present set: {:?}", denied_pres);
+        audit_log!(audit, "Denied remove set: {:?}", denied_rem);
+        audit_log!(audit, "Denied class set: {:?}", denied_classes);
+    }
+
+    let allowed_pres: BTreeSet<&str> = allow_acp
+        .iter()
+        .flat_map(|acp| acp.presattrs.iter().map(|v| v.as_str()))
+        .filter(|a| !denied_pres.contains(a))
+        .collect();
+
+    let allowed_rem: BTreeSet<&str> = allow_acp
+        .iter()
+        .flat_map(|acp| acp.remattrs.iter().map(|v| v.as_str()))
+        .filter(|a| !denied_rem.contains(a))
+        .collect();
+
+    let allowed_classes: BTreeSet<&str> = allow_acp
+        .iter()
+        .flat_map(|acp| acp.classes.iter().map(|v| v.as_str()))
+        .filter(|c| !denied_classes.contains(c))
+        .collect();
+
+    (allowed_pres, allowed_rem, allowed_classes)
+}
+
+// Each value being made present must be permitted by a single allow profile that
+// targets the entry, grants the attribute and whose restrictions on it (if any)
+// accept the value. Unlike the attribute sets these aren't merged between
+// profiles, so one profile's allow-list can't be sidestepped by another granting
+// the same attribute with a different restriction - though it can by one granting
+// the attribute with no restriction at all.
+fn modify_values_allowed(
+    audit: &mut AuditScope,
+    related_acp: &[&AccessControlModify],
+    ev: &Event,
+    e: &Entry<EntrySealed, EntryCommitted>,
+    requested_values: &[(&str, &Value)],
+    refer_entries: &[Entry<EntrySealed, EntryCommitted>],
+) -> bool {
+    let allow_acp: Vec<&AccessControlModify> = related_acp
+        .iter()
+        .filter(|acm| !acm.acp.deny && acm.acp.target_match(audit, ev, e))
+        .copied()
+        .collect();
+
+    requested_values.iter().all(|(attr, v)| {
+        let allowed = allow_acp
+            .iter()
+            .any(|acm| acm.value_allowed(audit, ev, attr, v, refer_entries));
+        if !allowed {
+            audit_log!(
+                audit,
+                "No profile permits {:?} to be present on {}",
+                v,
+                attr
+            );
+        }
+        allowed
+    })
+}
+
+// From the acps related to the receiver, the sets of attributes and classes that
+// may be created on an entry like this one. Anything named by a matching deny is
+// removed, even if an allow grants it.
+fn create_allowed_sets<'a>(
+    audit: &mut AuditScope,
+    related_acp: &[&'a AccessControlCreate],
+    ev: &Event,
+    e: &Entry<EntrySealed, EntryCommitted>,
+) -> (BTreeSet<&'a str>, BTreeSet<&'a str>) {
+    let (deny_acp, allow_acp): (Vec<&AccessControlCreate>, Vec<&AccessControlCreate>) = related_acp
+        .iter()
+        .filter(|acc| acc.acp.target_match(audit, ev, e))
+        .copied()
+        .partition(|acc| acc.acp.deny);
+
+    let denied_attrs: BTreeSet<&str> = deny_acp
+        .iter()
+        .flat_map(|acp| acp.attrs.iter().map(|v| v.as_str()))
+        .collect();
+
+    let denied_classes: BTreeSet<&str> = deny_acp
+        .iter()
+        .flat_map(|acp| acp.classes.iter().map(|v| v.as_str()))
+        .collect();
+
+    if !deny_acp.is_empty() {
+        audit_log!(audit, "Denied attr set: {:?}", denied_attrs);
+        audit_log!(audit, "Denied class set: {:?}", denied_classes);
+    }
+
+    let allowed_attrs: BTreeSet<&str> = allow_acp
+        .iter()
+        .flat_map(|acp| acp.attrs.iter().map(|v| v.as_str()))
+        .filter(|a| !denied_attrs.contains(a))
+        .collect();
+
+    let allowed_classes: BTreeSet<&str> = allow_acp
+        .iter()
+        .flat_map(|acp| acp.classes.iter().map(|v| v.as_str()))
+        .filter(|c| !denied_classes.contains(c))
+        .collect();
+
+    (allowed_attrs, allowed_classes)
+}
+
+// Is there an acp related to the receiver that allows every attribute and class
+// of this entry to be created? A matching deny that names any of the entry's
+// attributes or classes refuses the create before the allows are considered.
+fn create_allowed_entry<VALID, STATE>(
+    audit: &mut AuditScope,
+    related_acp: &[&AccessControlCreate],
+    ev: &Event,
+    e: &Entry<VALID, STATE>,
+) -> bool
+where
+    Entry<VALID, STATE>: std::fmt::Debug,
+{
+    // Build the set of requested classes and attrs here.
+    let create_attrs: BTreeSet<&str> = e.get_ava_names();
+    // If this is empty, we make an empty set, which is fine because
+    // the empty class set despite matching is_subset, will have the
+    // following effect:
+    // * there is no class on entry, so schema will fail
+    // * plugin-base will add object to give a class, but excess
+    //   attrs will cause fail (could this be a weakness?)
+    // * class is a "may", so this could be empty in the rules, so
+    //   if the accr is empty this would not be a true subset,
+    //   so this would "fail", but any content in the accr would
+    //   have to be validated.
+    //
+    // I still think if this is None, we should just fail here ...
+    // because it shouldn't be possible to match.
+
+    let create_classes: BTreeSet<&str> = match e.get_ava_set_str("class") {
+        Some(s) => s,
+        None => {
+            audit_log!(audit, "Class set failed to build - corrupted entry?");
+            return false;
+        }
+    };
+
+    // Denies first.
+    let denied = related_acp.iter().filter(|accr| accr.acp.deny).any(|accr| {
+        if !accr.acp.target_match(audit, ev, e) {
+            return false;
+        }
+        let d = accr.attrs.iter().any(|a| create_attrs.contains(a.as_str()))
+            || accr
+                .classes
+                .iter()
+                .any(|c| create_classes.contains(c.as_str()));
+        if d {
+            audit_log!(audit, "entry {:?} denied by acs {:?}", e, accr);
+        }
+        d
+    });
+    if denied {
+        return false;
+    }
+
+    related_acp
+        .iter()
+        .filter(|accr| !accr.acp.deny)
+        .fold(false, |r_acc, accr| {
+            if r_acc {
+                // Already allowed, continue.
+                r_acc
+            } else if accr.acp.target_match(audit, ev, e) {
+                audit_log!(audit, "entry {:?} matches acs {:?}", e, accr);
+                // It matches, so now we have to check attrs and classes.
+                // Remember, we have to match ALL requested attrs
+                // and classes to pass!
+                let allowed_attrs: BTreeSet<&str> = accr.attrs.iter().map(|s| s.as_str()).collect();
+                let allowed_classes: BTreeSet<&str> =
+                    accr.classes.iter().map(|s| s.as_str()).collect();
+
+                if !create_attrs.is_subset(&allowed_attrs) {
+                    audit_log!(audit, "create_attrs is not a subset of allowed");
+                    audit_log!(audit, "{:?} !⊆ {:?}", create_attrs, allowed_attrs);
+                    return false;
+                }
+                if !create_classes.is_subset(&allowed_classes) {
+                    audit_log!(audit, "create_classes is not a subset of allowed");
+                    audit_log!(audit, "{:?} !⊆ {:?}", create_classes, allowed_classes);
+                    return false;
+                }
+
+                true
+            } else {
+                audit_log!(audit, "entry {:?} DOES NOT match acs {:?}", e, accr);
+                // Does not match, fail this rule.
+                false
+            }
         })
+}
+
+// Is there an acp related to the receiver that allows this entry to be deleted? Any
+// matching deny refuses the delete before the allows are considered.
+fn delete_allowed_entry(
+    audit: &mut AuditScope,
+    related_acp: &[&AccessControlDelete],
+    ev: &Event,
+    e: &Entry<EntrySealed, EntryCommitted>,
+) -> bool {
+    let denied = related_acp
+        .iter()
+        .filter(|acd| acd.acp.deny)
+        .any(|acd| acd.acp.target_match(audit, ev, e));
+    if denied {
+        audit_log!(audit, "entry {:?} delete is denied", e.get_uuid());
+        return false;
+    }
+
+    related_acp
+        .iter()
+        .filter(|acd| !acd.acp.deny)
+        .fold(false, |r_acc, acd| {
+            if r_acc {
+                // If something allowed us to delete, skip doing silly work.
+                r_acc
+            } else if acd.acp.target_match(audit, ev, e) {
+                audit_log!(audit, "entry {:?} matches acs {:?}", e.get_uuid(), acd);
+                // It matches, so we can delete this!
+                true
+            } else {
+                audit_log!(
+                    audit,
+                    "entry {:?} DOES NOT match acs {:?}",
+                    e.get_uuid(),
+                    acd
+                );
+                // Does not match, fail.
+                false
+            }
+        })
+}
+
+// Should this acp count towards the explained result? This must agree with
+// enforcement, which only considers active acps whose receiver matches - an
+// inactive acp grants nothing, even when its receiver matches.
+fn explain_is_related(decision: &AccessProfileDecision) -> bool {
+    match decision {
+        AccessProfileDecision::Applied | AccessProfileDecision::TargetMismatch => true,
+        AccessProfileDecision::Inactive | AccessProfileDecision::ReceiverMismatch => false,
     }
 }
 
@@ -351,6 +968,36 @@ pub trait AccessControlsTransaction {
     fn get_create(&self) -> BptreeMapReadSnapshot<Uuid, AccessControlCreate>;
     fn get_modify(&self) -> BptreeMapReadSnapshot<Uuid, AccessControlModify>;
     fn get_delete(&self) -> BptreeMapReadSnapshot<Uuid, AccessControlDelete>;
+    // The time of this transaction, which time bounded profiles are checked against.
+    fn get_ts(&self) -> Duration;
+
+    // The attributes named by SelfRefMember terms in any profile. Events find the
+    // entries referencing their origin through these so the terms can be resolved.
+    fn get_self_ref_member_attrs(&self) -> BTreeSet<String> {
+        let search_state = self.get_search();
+        let create_state = self.get_create();
+        let modify_state = self.get_modify();
+        let delete_state = self.get_delete();
+
+        let mut r_set: BTreeSet<&str> = BTreeSet::new();
+        search_state
+            .iter()
+            .for_each(|(_, acs)| acs.acp.get_self_ref_member_attrs(&mut r_set));
+        create_state
+            .iter()
+            .for_each(|(_, acc)| acc.acp.get_self_ref_member_attrs(&mut r_set));
+        modify_state.iter().for_each(|(_, acm)| {
+            acm.acp.get_self_ref_member_attrs(&mut r_set);
+            acm.presrefers
+                .values()
+                .for_each(|f| f.get_self_ref_member_attrs(&mut r_set));
+        });
+        delete_state
+            .iter()
+            .for_each(|(_, acd)| acd.acp.get_self_ref_member_attrs(&mut r_set));
+
+        r_set.into_iter().map(|a| a.to_string()).collect()
+    }
 
     // Contains all the way to eval acps to entries
     fn search_filter_entries(
@@ -373,43 +1020,18 @@ pub trait AccessControlsTransaction {
 
         // Some useful references we'll use for the remainder of the operation
         let search_state = self.get_search();
+        let ts = self.get_ts();
 
         // First get the set of acps that apply to this receiver
         let related_acp: Vec<&AccessControlSearch> = search_state
             .iter()
             .filter_map(|(_, acs)| {
-                // Now resolve the receiver filter
-                // Okay, so in filter resolution, the primary error case
-                // is that we have a non-user in the event. We have already
-                // checked for this above BUT we should still check here
-                // properly just in case.
-                //
-                // In this case, we assume that if the event is internal
-                // that the receiver can NOT match because it has no selfuuid
-                // and can as a result, never return true. This leads to this
-                // acp not being considered in that case ... which should never
-                // happen because we already bypassed internal ops above!
-                //
-                // A possible solution is to change the filter resolve function
-                // such that it takes an entry, rather than an event, but that
-                // would create issues in search.
-                let f_val = acs.acp.receiver.clone();
-                match f_val.resolve(&se.event, None) {
-                    Ok(f_res) => {
-                        if rec_entry.entry_match_no_index(&f_res) {
-                            Some(acs)
-                        } else {
-                            None
-                        }
-                    }
-                    Err(e) => {
-                        audit_log!(
-                            audit,
-                            "A internal filter was passed for resolution!?!? {:?}",
-                            e
-                        );
-                        None
-                    }
+                if acs.acp.is_active(audit, ts)
+                    && acs.acp.receiver_match(audit, &se.event, rec_entry)
+                {
+                    Some(acs)
+                } else {
+                    None
                 }
             })
             .collect();
@@ -427,46 +1049,8 @@ pub trait AccessControlsTransaction {
             .into_iter()
             .filter(|e| {
                 // For each acp
-                let allowed_attrs: BTreeSet<&str> = related_acp
-                    .iter()
-                    .filter_map(|acs| {
-                        let f_val = acs.acp.targetscope.clone();
-                        match f_val.resolve(&se.event, None) {
-                            Ok(f_res) => {
-                                // if it applies
-                                if e.entry_match_no_index(&f_res) {
-                                    audit_log!(
-                                        audit,
-                                        "entry {:?} matches acs {:?}",
-                                        e.get_uuid(),
-                                        acs
-                                    );
-                                    // add search_attrs to allowed.
-                                    let r: Vec<&str> =
-                                        acs.attrs.iter().map(|s| s.as_str()).collect();
-                                    Some(r)
-                                } else {
-                                    audit_log!(
-                                        audit,
-                                        "entry {:?} DOES NOT match acs {:?}",
-                                        e.get_uuid(),
-                                        acs
-                                    );
-                                    None
-                                }
-                            }
-                            Err(e) => {
-                                audit_log!(
-                                    audit,
-                                    "A internal filter was passed for resolution!?!? {:?}",
-                                    e
-                                );
-                                None
-                            }
-                        }
-                    })
-                    .flatten()
-                    .collect();
+                let allowed_attrs: BTreeSet<&str> =
+                    search_allowed_attrs(audit, &related_acp, &se.event, e);
 
                 audit_log!(audit, "-- for entry         --> {:?}", e.get_uuid());
                 audit_log!(audit, "allowed attributes   --> {:?}", allowed_attrs);
@@ -524,46 +1108,36 @@ pub trait AccessControlsTransaction {
 
         // Some useful references we'll use for the remainder of the operation
         let search_state = self.get_search();
+        let ts = self.get_ts();
 
         // Get the relevant acps for this receiver.
         let related_acp: Vec<&AccessControlSearch> = search_state
             .iter()
             .filter_map(|(_, acs)| {
-                let f_val = acs.acp.receiver.clone();
-                match f_val.resolve(&se.event, None) {
-                    Ok(f_res) => {
-                        // Is our user covered by this acs?
-                        if rec_entry.entry_match_no_index(&f_res) {
-                            // If so, let's check if the attr request is relevant.
-
-                            // If we have a requested attr set, are any of them
-                            // in the attrs this acs covers?
-                            let acs_target_attrs = match &se.attrs {
-                                Some(r_attrs) => acs.attrs.intersection(r_attrs).count(),
-                                // All attrs requested, do nothing.
-                                None => acs.attrs.len(),
-                            };
-
-                            // There is nothing in the ACS (not possible) or
-                            // no overlap between the requested set and this acs, so it's
-                            // not worth evaling.
-                            if acs_target_attrs == 0 {
-                                None
-                            } else {
-                                Some(acs)
-                            }
-                        } else {
-                            None
-                        }
-                    }
-                    Err(e) => {
-                        audit_log!(
-                            audit,
-                            "A internal filter was passed for resolution!?!? {:?}",
-                            e
-                        );
+                // Is our user covered by this acs?
+                if acs.acp.is_active(audit, ts)
+                    && acs.acp.receiver_match(audit, &se.event, rec_entry)
+                {
+                    // If so, let's check if the attr request is relevant.
+
+                    // If we have a requested attr set, are any of them
+                    // in the attrs this acs covers?
+                    let acs_target_attrs = match &se.attrs {
+                        Some(r_attrs) => acs.attrs.intersection(r_attrs).count(),
+                        // All attrs requested, do nothing.
+                        None => acs.attrs.len(),
+                    };
+
+                    // There is nothing in the ACS (not possible) or
+                    // no overlap between the requested set and this acs, so it's
+                    // not worth evaling.
+                    if acs_target_attrs == 0 {
                         None
+                    } else {
+                        Some(acs)
                     }
+                } else {
+                    None
                 }
             })
             .collect();
@@ -583,46 +1157,8 @@ pub trait AccessControlsTransaction {
             .into_iter()
             .map(|e| {
                 // Get the set of attributes you can see
-                let allowed_attrs: BTreeSet<&str> = related_acp
-                    .iter()
-                    .filter_map(|acs| {
-                        let f_val = acs.acp.targetscope.clone();
-                        match f_val.resolve(&se.event, None) {
-                            Ok(f_res) => {
-                                // if it applies
-                                if e.entry_match_no_index(&f_res) {
-                                    audit_log!(
-                                        audit,
-                                        "entry {:?} matches acs {:?}",
-                                        e.get_uuid(),
-                                        acs
-                                    );
-                                    // add search_attrs to allowed.
-                                    let r: Vec<&str> =
-                                        acs.attrs.iter().map(|s| s.as_str()).collect();
-                                    Some(r)
-                                } else {
-                                    audit_log!(
-                                        audit,
-                                        "entry {:?} DOES NOT match acs {:?}",
-                                        e.get_uuid(),
-                                        acs
-                                    );
-                                    None
-                                }
-                            }
-                            Err(e) => {
-                                audit_log!(
-                                    audit,
-                                    "A internal filter was passed for resolution!?!? {:?}",
-                                    e
-                                );
-                                None
-                            }
-                        }
-                    })
-                    .flatten()
-                    .collect();
+                let allowed_attrs: BTreeSet<&str> =
+                    search_allowed_attrs(audit, &related_acp, &se.event, &e);
 
                 // Remove all others that are present on the entry.
                 audit_log!(audit, "-- for entry         --> {:?}", e.get_uuid());
@@ -642,11 +1178,14 @@ pub trait AccessControlsTransaction {
         Ok(allowed_entries)
     }
 
+    // refer_entries are the entries that reference values being made present point
+    // to, so that acp_modify_presentrefer filters can be checked against them.
     fn modify_allow_operation(
         &self,
         audit: &mut AuditScope,
         me: &ModifyEvent,
         entries: &[Entry<EntrySealed, EntryCommitted>],
+        refer_entries: &[Entry<EntrySealed, EntryCommitted>],
     ) -> Result<bool, OperationError> {
         audit_log!(audit, "Access check for event: {:?}", me);
 
@@ -660,6 +1199,7 @@ pub trait AccessControlsTransaction {
 
         // Some useful references we'll use for the remainder of the operation
         let modify_state = self.get_modify();
+        let ts = self.get_ts();
 
         // Pre-check if the no-no purge class is present
         let disallow = me.modlist.iter().fold(false, |acc, m| {
@@ -681,23 +1221,12 @@ pub trait AccessControlsTransaction {
         let related_acp: Vec<&AccessControlModify> = modify_state
             .iter()
             .filter_map(|(_, acs)| {
-                let f_val = acs.acp.receiver.clone();
-                match f_val.resolve(&me.event, None) {
-                    Ok(f_res) => {
-                        if rec_entry.entry_match_no_index(&f_res) {
-                            Some(acs)
-                        } else {
-                            None
-                        }
-                    }
-                    Err(e) => {
-                        audit_log!(
-                            audit,
-                            "A internal filter was passed for resolution!?!? {:?}",
-                            e
-                        );
-                        None
-                    }
+                if acs.acp.is_active(audit, ts)
+                    && acs.acp.receiver_match(audit, &me.event, rec_entry)
+                {
+                    Some(acs)
+                } else {
+                    None
                 }
             })
             .collect();
@@ -757,6 +1286,20 @@ pub trait AccessControlsTransaction {
             })
             .collect();
 
+        // The values being made present, for profiles that restrict them. Class is
+        // handled by the class set above.
+        let requested_values: Vec<(&str, &Value)> = me
+            .modlist
+            .iter()
+            .filter_map(|m| match m {
+                Modify::Present(a, v) if a.as_str() != "class" => Some((a.as_str(), v)),
+                _ => None,
+            })
+            .collect();
+        let value_rules = related_acp
+            .iter()
+            .any(|acm| !acm.acp.deny && acm.has_value_rules());
+
         audit_log!(audit, "Requested present set: {:?}", requested_pres);
         audit_log!(audit, "Requested remove set: {:?}", requested_rem);
         audit_log!(audit, "Requested class set: {:?}", requested_classes);
@@ -765,51 +1308,10 @@ pub trait AccessControlsTransaction {
             if !acc {
                 false
             } else {
-                // For this entry, find the acp's that apply to it from the
-                // set that apply to the entry that is performing the operation
-                let scoped_acp: Vec<&AccessControlModify> = related_acp
-                    .iter()
-                    .filter_map(|acm: &&AccessControlModify| {
-                        // We are continually compiling and using these
-                        // in a tight loop, so this is a possible oppurtunity
-                        // to cache or handle these filters better - filter compiler
-                        // cache maybe?
-                        let f_val = acm.acp.targetscope.clone();
-                        match f_val.resolve(&me.event, None) {
-                            Ok(f_res) => {
-                                if e.entry_match_no_index(&f_res) {
-                                    Some(*acm)
-                                } else {
-                                    None
-                                }
-                            }
-                            Err(e) => {
-                                audit_log!(
-                                    audit,
-                                    "A internal filter was passed for resolution!?!? {:?}",
-                                    e
-                                );
-                                None
-                            }
-                        }
-                    })
-                    .collect();
                 // Build the sets of classes, pres and rem we are allowed to modify, extend
-                // or use based on the set of matched acps.
-                let allowed_pres: BTreeSet<&str> = scoped_acp
-                    .iter()
-                    .flat_map(|acp| acp.presattrs.iter().map(|v| v.as_str()))
-                    .collect();
-
-                let allowed_rem: BTreeSet<&str> = scoped_acp
-                    .iter()
-                    .flat_map(|acp| acp.remattrs.iter().map(|v| v.as_str()))
-                    .collect();
-
-                let allowed_classes: BTreeSet<&str> = scoped_acp
-                    .iter()
-                    .flat_map(|acp| acp.classes.iter().map(|v| v.as_str()))
-                    .collect();
+                // or use based on the set of acps that match this entry.
+                let (allowed_pres, allowed_rem, allowed_classes) =
+                    modify_allowed_sets(audit, &related_acp, &me.event, e);
 
                 // Now check all the subsets are true. Remember, purge class
                 // is already checked above.
@@ -829,6 +1331,20 @@ pub trait AccessControlsTransaction {
                     audit_log!(audit, "{:?} !⊆ {:?}", requested_classes, allowed_classes);
                     result = false;
                 }
+                // Without any value rules this is implied by the present set above.
+                if result
+                    && value_rules
+                    && !modify_values_allowed(
+                        audit,
+                        &related_acp,
+                        &me.event,
+                        e,
+                        &requested_values,
+                        refer_entries,
+                    )
+                {
+                    result = false;
+                }
                 result
             } // if acc == false
         });
@@ -853,28 +1369,18 @@ pub trait AccessControlsTransaction {
 
         // Some useful references we'll use for the remainder of the operation
         let create_state = self.get_create();
+        let ts = self.get_ts();
 
         // Find the acps that relate to the caller.
         let related_acp: Vec<&AccessControlCreate> = create_state
             .iter()
             .filter_map(|(_, acs)| {
-                let f_val = acs.acp.receiver.clone();
-                match f_val.resolve(&ce.event, None) {
-                    Ok(f_res) => {
-                        if rec_entry.entry_match_no_index(&f_res) {
-                            Some(acs)
-                        } else {
-                            None
-                        }
-                    }
-                    Err(e) => {
-                        audit_log!(
-                            audit,
-                            "A internal filter was passed for resolution!?!? {:?}",
-                            e
-                        );
-                        None
-                    }
+                if acs.acp.is_active(audit, ts)
+                    && acs.acp.receiver_match(audit, &ce.event, rec_entry)
+                {
+                    Some(acs)
+                } else {
+                    None
                 }
             })
             .collect();
@@ -887,100 +1393,7 @@ pub trait AccessControlsTransaction {
                 // We have already failed, move on.
                 false
             } else {
-                // Build the set of requested classes and attrs here.
-                let create_attrs: BTreeSet<&str> = e.get_ava_names();
-                // If this is empty, we make an empty set, which is fine because
-                // the empty class set despite matching is_subset, will have the
-                // following effect:
-                // * there is no class on entry, so schema will fail
-                // * plugin-base will add object to give a class, but excess
-                //   attrs will cause fail (could this be a weakness?)
-                // * class is a "may", so this could be empty in the rules, so
-                //   if the accr is empty this would not be a true subset,
-                //   so this would "fail", but any content in the accr would
-                //   have to be validated.
-                //
-                // I still think if this is None, we should just fail here ...
-                // because it shouldn't be possible to match.
-
-                let create_classes: BTreeSet<&str> = match e.get_ava_set_str("class") {
-                    Some(s) => s,
-                    None => {
-                        audit_log!(audit, "Class set failed to build - corrupted entry?");
-                        return false;
-                    }
-                };
-
-                related_acp.iter().fold(false, |r_acc, accr| {
-                    if r_acc {
-                        // Already allowed, continue.
-                        r_acc
-                    } else {
-                        // Check to see if allowed.
-                        let f_val = accr.acp.targetscope.clone();
-                        match f_val.resolve(&ce.event, None) {
-                            Ok(f_res) => {
-                                if e.entry_match_no_index(&f_res) {
-                                    audit_log!(audit, "entry {:?} matches acs {:?}", e, accr);
-                                    // It matches, so now we have to check attrs and classes.
-                                    // Remember, we have to match ALL requested attrs
-                                    // and classes to pass!
-                                    let allowed_attrs: BTreeSet<&str> =
-                                        accr.attrs.iter().map(|s| s.as_str()).collect();
-                                    let allowed_classes: BTreeSet<&str> =
-                                        accr.classes.iter().map(|s| s.as_str()).collect();
-
-                                    if !create_attrs.is_subset(&allowed_attrs) {
-                                        audit_log!(
-                                            audit,
-                                            "create_attrs is not a subset of allowed"
-                                        );
-                                        audit_log!(
-                                            audit,
-                                            "{:?} !⊆ {:?}",
-                                            create_attrs,
-                                            allowed_attrs
-                                        );
-                                        return false;
-                                    }
-                                    if !create_classes.is_subset(&allowed_classes) {
-                                        audit_log!(
-                                            audit,
-                                            "create_classes is not a subset of allowed"
-                                        );
-                                        audit_log!(
-                                            audit,
-                                            "{:?} !⊆ {:?}",
-                                            create_classes,
-                                            allowed_classes
-                                        );
-                                        return false;
-                                    }
-
-                                    true
-                                } else {
-                                    audit_log!(
-                                        audit,
-                                        "entry {:?} DOES NOT match acs {:?}",
-                                        e,
-                                        accr
-                                    );
-                                    // Does not match, fail this rule.
-                                    false
-                                }
-                            }
-                            Err(e) => {
-                                audit_log!(
-                                    audit,
-                                    "A internal filter was passed for resolution!?!? {:?}",
-                                    e
-                                );
-                                // Default to failing here.
-                                false
-                            }
-                        } // match
-                    }
-                })
+                create_allowed_entry(audit, &related_acp, &ce.event, e)
             }
             //      Find the set of related acps for this entry.
             //
@@ -1012,28 +1425,18 @@ pub trait AccessControlsTransaction {
 
         // Some useful references we'll use for the remainder of the operation
         let delete_state = self.get_delete();
+        let ts = self.get_ts();
 
         // Find the acps that relate to the caller.
         let related_acp: Vec<&AccessControlDelete> = delete_state
             .iter()
             .filter_map(|(_, acs)| {
-                let f_val = acs.acp.receiver.clone();
-                match f_val.resolve(&de.event, None) {
-                    Ok(f_res) => {
-                        if rec_entry.entry_match_no_index(&f_res) {
-                            Some(acs)
-                        } else {
-                            None
-                        }
-                    }
-                    Err(e) => {
-                        audit_log!(
-                            audit,
-                            "A internal filter was passed for resolution!?!? {:?}",
-                            e
-                        );
-                        None
-                    }
+                if acs.acp.is_active(audit, ts)
+                    && acs.acp.receiver_match(audit, &de.event, rec_entry)
+                {
+                    Some(acs)
+                } else {
+                    None
                 }
             })
             .collect();
@@ -1048,50 +1451,132 @@ pub trait AccessControlsTransaction {
                 // Any false, denies the whole operation.
                 false
             } else {
-                related_acp.iter().fold(false, |r_acc, acd| {
-                    if r_acc {
-                        // If something allowed us to delete, skip doing silly work.
-                        r_acc
-                    } else {
-                        let f_val = acd.acp.targetscope.clone();
-                        match f_val.resolve(&de.event, None) {
-                            Ok(f_res) => {
-                                if e.entry_match_no_index(&f_res) {
-                                    audit_log!(
-                                        audit,
-                                        "entry {:?} matches acs {:?}",
-                                        e.get_uuid(),
-                                        acd
-                                    );
-                                    // It matches, so we can delete this!
-                                    true
-                                } else {
-                                    audit_log!(
-                                        audit,
-                                        "entry {:?} DOES NOT match acs {:?}",
-                                        e.get_uuid(),
-                                        acd
-                                    );
-                                    // Does not match, fail.
-                                    false
-                                }
-                            }
-                            Err(e) => {
-                                audit_log!(
-                                    audit,
-                                    "A internal filter was passed for resolution!?!? {:?}",
-                                    e
-                                );
-                                // Default to failing here.
-                                false
-                            }
-                        } // match
-                    } // else
-                }) // fold related_acp
+                delete_allowed_entry(audit, &related_acp, &de.event, e)
             } // if/else
         });
         Ok(r)
     }
+
+    // Given an identity and a target entry, report which profiles of the operation
+    // apply and why the others were skipped. This uses the same receiver, targetscope
+    // and attribute set evaluation as the enforcement paths above, so what is explained
+    // is exactly what would be enforced.
+    fn explain_operation(
+        &self,
+        audit: &mut AuditScope,
+        ev: &Event,
+        operation: AccessOperation,
+        target: &Entry<EntrySealed, EntryCommitted>,
+    ) -> Result<AccessExplainResponse, OperationError> {
+        let rec_entry: &Entry<EntrySealed, EntryCommitted> = match &ev.origin {
+            EventOrigin::Internal => {
+                audit_log!(
+                    audit,
+                    "Internal operations bypass access checks, nothing to explain"
+                );
+                return Err(OperationError::InvalidRequestState);
+            }
+            EventOrigin::User(e) => &e,
+        };
+
+        let ts = self.get_ts();
+        let mut profiles: Vec<AccessProfileExplain> = Vec::new();
+        let mut allowed_attrs: BTreeSet<String> = BTreeSet::new();
+        let mut allowed_presattrs: BTreeSet<String> = BTreeSet::new();
+        let mut allowed_remattrs: BTreeSet<String> = BTreeSet::new();
+        let mut allowed_classes: BTreeSet<String> = BTreeSet::new();
+
+        let allowed = match operation {
+            AccessOperation::Search => {
+                let search_state = self.get_search();
+                let mut related_acp: Vec<&AccessControlSearch> = Vec::new();
+                for (_, acs) in search_state.iter() {
+                    let p = acs.acp.explain(audit, ev, ts, rec_entry, target);
+                    if explain_is_related(&p.decision) {
+                        related_acp.push(acs);
+                    }
+                    profiles.push(AccessProfileExplain {
+                        attrs: acs.attrs.iter().cloned().collect(),
+                        ..p
+                    });
+                }
+
+                let attrs = search_allowed_attrs(audit, &related_acp, ev, target);
+                allowed_attrs.extend(attrs.into_iter().map(|s| s.to_string()));
+                !allowed_attrs.is_empty()
+            }
+            AccessOperation::Modify => {
+                let modify_state = self.get_modify();
+                let mut related_acp: Vec<&AccessControlModify> = Vec::new();
+                for (_, acm) in modify_state.iter() {
+                    let p = acm.acp.explain(audit, ev, ts, rec_entry, target);
+                    if explain_is_related(&p.decision) {
+                        related_acp.push(acm);
+                    }
+                    profiles.push(AccessProfileExplain {
+                        presattrs: acm.presattrs.clone(),
+                        remattrs: acm.remattrs.clone(),
+                        classes: acm.classes.clone(),
+                        ..p
+                    });
+                }
+
+                let (pres, rem, classes) = modify_allowed_sets(audit, &related_acp, ev, target);
+                allowed_presattrs.extend(pres.into_iter().map(|s| s.to_string()));
+                allowed_remattrs.extend(rem.into_iter().map(|s| s.to_string()));
+                allowed_classes.extend(classes.into_iter().map(|s| s.to_string()));
+                !(allowed_presattrs.is_empty() && allowed_remattrs.is_empty())
+            }
+            AccessOperation::Create => {
+                let create_state = self.get_create();
+                let mut related_acp: Vec<&AccessControlCreate> = Vec::new();
+                for (_, acc) in create_state.iter() {
+                    let p = acc.acp.explain(audit, ev, ts, rec_entry, target);
+                    if explain_is_related(&p.decision) {
+                        related_acp.push(acc);
+                    }
+                    profiles.push(AccessProfileExplain {
+                        attrs: acc.attrs.clone(),
+                        classes: acc.classes.clone(),
+                        ..p
+                    });
+                }
+
+                let (attrs, classes) = create_allowed_sets(audit, &related_acp, ev, target);
+                allowed_attrs.extend(attrs.into_iter().map(|s| s.to_string()));
+                allowed_classes.extend(classes.into_iter().map(|s| s.to_string()));
+                // Could the identity create an entry shaped like the target?
+                create_allowed_entry(audit, &related_acp, ev, target)
+            }
+            AccessOperation::Delete => {
+                let delete_state = self.get_delete();
+                let mut related_acp: Vec<&AccessControlDelete> = Vec::new();
+                for (_, acd) in delete_state.iter() {
+                    let p = acd.acp.explain(audit, ev, ts, rec_entry, target);
+                    if explain_is_related(&p.decision) {
+                        related_acp.push(acd);
+                    }
+                    profiles.push(p);
+                }
+
+                delete_allowed_entry(audit, &related_acp, ev, target)
+            }
+        };
+
+        audit_log!(audit, "explain {} -> {:?}", operation, profiles);
+
+        Ok(AccessExplainResponse {
+            identity: rec_entry.get_uuid().to_hyphenated_ref().to_string(),
+            target: target.get_uuid().to_hyphenated_ref().to_string(),
+            operation,
+            allowed,
+            profiles,
+            allowed_attrs: allowed_attrs.into_iter().collect(),
+            allowed_presattrs: allowed_presattrs.into_iter().collect(),
+            allowed_remattrs: allowed_remattrs.into_iter().collect(),
+            allowed_classes: allowed_classes.into_iter().collect(),
+        })
+    }
 }
 
 pub struct AccessControlsWriteTransaction<'a> {
@@ -1100,6 +1585,7 @@ pub struct AccessControlsWriteTransaction<'a> {
     acps_create: BptreeMapWriteTxn<'a, Uuid, AccessControlCreate>,
     acps_modify: BptreeMapWriteTxn<'a, Uuid, AccessControlModify>,
     acps_delete: BptreeMapWriteTxn<'a, Uuid, AccessControlDelete>,
+    ts: Duration,
 }
 
 impl<'a> AccessControlsWriteTransaction<'a> {
@@ -1156,6 +1642,7 @@ impl<'a> AccessControlsWriteTransaction<'a> {
             acps_create,
             acps_modify,
             acps_delete,
+            ts: _,
         } = self;
 
         acps_search.commit();
@@ -1183,6 +1670,10 @@ impl<'a> AccessControlsTransaction for AccessControlsWriteTransaction<'a> {
     fn get_delete(&self) -> BptreeMapReadSnapshot<Uuid, AccessControlDelete> {
         self.acps_delete.to_snapshot()
     }
+
+    fn get_ts(&self) -> Duration {
+        self.ts
+    }
 }
 
 // =========================================================================
@@ -1194,6 +1685,7 @@ pub struct AccessControlsReadTransaction {
     acps_create: BptreeMapReadTxn<Uuid, AccessControlCreate>,
     acps_modify: BptreeMapReadTxn<Uuid, AccessControlModify>,
     acps_delete: BptreeMapReadTxn<Uuid, AccessControlDelete>,
+    ts: Duration,
 }
 
 impl AccessControlsTransaction for AccessControlsReadTransaction {
@@ -1212,6 +1704,10 @@ impl AccessControlsTransaction for AccessControlsReadTransaction {
     fn get_delete(&self) -> BptreeMapReadSnapshot<Uuid, AccessControlDelete> {
         self.acps_delete.to_snapshot()
     }
+
+    fn get_ts(&self) -> Duration {
+        self.ts
+    }
 }
 
 // =========================================================================
@@ -1229,23 +1725,25 @@ impl AccessControls {
         }
     }
 
-    pub fn read(&self) -> AccessControlsReadTransaction {
+    pub fn read(&self, ts: Duration) -> AccessControlsReadTransaction {
         AccessControlsReadTransaction {
             // inner: self.inner.read(),
             acps_search: self.acps_search.read(),
             acps_create: self.acps_create.read(),
             acps_modify: self.acps_modify.read(),
             acps_delete: self.acps_delete.read(),
+            ts,
         }
     }
 
-    pub fn write(&self) -> AccessControlsWriteTransaction {
+    pub fn write(&self, ts: Duration) -> AccessControlsWriteTransaction {
         AccessControlsWriteTransaction {
             // inner: self.inner.write(),
             acps_search: self.acps_search.write(),
             acps_create: self.acps_create.write(),
             acps_modify: self.acps_modify.write(),
             acps_delete: self.acps_delete.write(),
+            ts,
         }
     }
 }
@@ -1257,14 +1755,17 @@ mod tests {
         AccessControlSearch, AccessControls, AccessControlsTransaction,
     };
     use crate::audit::AuditScope;
-    use crate::entry::{Entry, EntryCommitted, EntryInit, EntryNew, EntryReduced};
+    use crate::entry::{Entry, EntryCommitted, EntryInit, EntryNew, EntryReduced, EntrySealed};
     // use crate::server::QueryServerWriteTransaction;
 
-    use crate::event::{CreateEvent, DeleteEvent, ModifyEvent, SearchEvent};
+    use crate::event::{CreateEvent, DeleteEvent, Event, ModifyEvent, SearchEvent};
+    use kanidm_proto::v1::{AccessExplainResponse, AccessOperation, AccessProfileDecision};
     // use crate::filter::Filter;
     // use crate::proto_v1::Filter as ProtoFilter;
     use crate::constants::{JSON_ADMIN_V1, JSON_ANONYMOUS_V1, JSON_TESTPERSON1, JSON_TESTPERSON2};
+    use crate::utils::duration_from_epoch_now;
     use crate::value::{PartialValue, Value};
+    use std::time::Duration;
 
     macro_rules! acp_from_entry_err {
         (
@@ -1378,6 +1879,57 @@ mod tests {
                 }"#,
                 AccessControlProfile
             );
+
+            // A window that closes before it opens is rejected.
+            acp_from_entry_err!(
+                audit,
+                &mut qs_write,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "access_control_profile"],
+                        "name": ["acp_invalid"],
+                        "uuid": ["cc8e95b4-c24f-4d68-ba54-8bed76f63930"],
+                        "acp_receiver": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_targetscope": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_valid_from": ["2000"],
+                        "acp_valid_until": ["1000"]
+                    }
+                }"#,
+                AccessControlProfile
+            );
+
+            let acp = acp_from_entry_ok!(
+                audit,
+                &mut qs_write,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "access_control_profile"],
+                        "name": ["acp_valid"],
+                        "uuid": ["cc8e95b4-c24f-4d68-ba54-8bed76f63930"],
+                        "acp_receiver": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_targetscope": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_valid_from": ["1000"],
+                        "acp_valid_until": ["2000"]
+                    }
+                }"#,
+                AccessControlProfile
+            );
+            assert!(!acp.is_active(audit, Duration::from_secs(999)));
+            assert!(acp.is_active(audit, Duration::from_secs(1000)));
+            assert!(acp.is_active(audit, Duration::from_secs(1999)));
+            assert!(!acp.is_active(audit, Duration::from_secs(2000)));
         })
     }
 
@@ -1603,23 +2155,16 @@ mod tests {
                 }"#,
                 AccessControlModify
             );
-        })
-    }
-
-    #[test]
-    fn test_access_acp_create_parser() {
-        run_test!(|qs: &QueryServer, audit: &mut AuditScope| {
-            // Test that parsing create access controls works.
-            let mut qs_write = qs.write(duration_from_epoch_now());
 
-            acp_from_entry_err!(
+            // Value restrictions are attr=value pairs on attributes that exist ...
+            let acm = acp_from_entry_ok!(
                 audit,
                 &mut qs_write,
                 r#"{
                     "valid": null,
                     "state": null,
                     "attrs": {
-                        "class": ["object", "access_control_profile"],
+                        "class": ["object", "access_control_profile", "access_control_modify"],
                         "name": ["acp_valid"],
                         "uuid": ["cc8e95b4-c24f-4d68-ba54-8bed76f63930"],
                         "acp_receiver": [
@@ -1628,8 +2173,135 @@ mod tests {
                         "acp_targetscope": [
                             "{\"Eq\":[\"name\",\"a\"]}"
                         ],
-                        "acp_create_class": ["object"],
-                        "acp_create_attr": ["name"]
+                        "acp_modify_presentattr": ["loginshell", "member"],
+                        "acp_modify_presentvalue": ["loginshell=/bin/bash", "loginshell=/bin/zsh"],
+                        "acp_modify_presentrefer": ["member={\"Eq\":[\"class\",\"person\"]}"]
+                    }
+                }"#,
+                AccessControlModify
+            );
+            assert!(acm.presvalues.get("loginshell").map(|v| v.len()) == Some(2));
+            assert!(acm.presrefers.contains_key("member"));
+
+            acp_from_entry_err!(
+                audit,
+                &mut qs_write,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "access_control_profile", "access_control_modify"],
+                        "name": ["acp_valid"],
+                        "uuid": ["cc8e95b4-c24f-4d68-ba54-8bed76f63930"],
+                        "acp_receiver": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_targetscope": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_modify_presentattr": ["loginshell", "member"],
+                        "acp_modify_presentvalue": ["not_an_attribute=/bin/bash"]
+                    }
+                }"#,
+                AccessControlModify
+            );
+
+            acp_from_entry_err!(
+                audit,
+                &mut qs_write,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "access_control_profile", "access_control_modify"],
+                        "name": ["acp_valid"],
+                        "uuid": ["cc8e95b4-c24f-4d68-ba54-8bed76f63930"],
+                        "acp_receiver": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_targetscope": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_modify_presentattr": ["loginshell", "member"],
+                        "acp_modify_presentvalue": ["/bin/bash"]
+                    }
+                }"#,
+                AccessControlModify
+            );
+
+            // ... and reference filters must be on a reference, and be valid.
+            acp_from_entry_err!(
+                audit,
+                &mut qs_write,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "access_control_profile", "access_control_modify"],
+                        "name": ["acp_valid"],
+                        "uuid": ["cc8e95b4-c24f-4d68-ba54-8bed76f63930"],
+                        "acp_receiver": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_targetscope": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_modify_presentattr": ["loginshell", "member"],
+                        "acp_modify_presentrefer": ["loginshell={\"Eq\":[\"class\",\"person\"]}"]
+                    }
+                }"#,
+                AccessControlModify
+            );
+
+            acp_from_entry_err!(
+                audit,
+                &mut qs_write,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "access_control_profile", "access_control_modify"],
+                        "name": ["acp_valid"],
+                        "uuid": ["cc8e95b4-c24f-4d68-ba54-8bed76f63930"],
+                        "acp_receiver": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_targetscope": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_modify_presentattr": ["loginshell", "member"],
+                        "acp_modify_presentrefer": ["member={\"Eq\":[\"not_an_attribute\",\"person\"]}"]
+                    }
+                }"#,
+                AccessControlModify
+            );
+        })
+    }
+
+    #[test]
+    fn test_access_acp_create_parser() {
+        run_test!(|qs: &QueryServer, audit: &mut AuditScope| {
+            // Test that parsing create access controls works.
+            let mut qs_write = qs.write(duration_from_epoch_now());
+
+            acp_from_entry_err!(
+                audit,
+                &mut qs_write,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "access_control_profile"],
+                        "name": ["acp_valid"],
+                        "uuid": ["cc8e95b4-c24f-4d68-ba54-8bed76f63930"],
+                        "acp_receiver": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_targetscope": [
+                            "{\"Eq\":[\"name\",\"a\"]}"
+                        ],
+                        "acp_create_class": ["object"],
+                        "acp_create_attr": ["name"]
                     }
                 }"#,
                 AccessControlCreate
@@ -1734,7 +2406,7 @@ mod tests {
             $expect:expr
         ) => {{
             let ac = AccessControls::new();
-            let mut acw = ac.write();
+            let mut acw = ac.write(duration_from_epoch_now());
             acw.update_search($controls).expect("Failed to update");
             let acw = acw;
 
@@ -1837,7 +2509,7 @@ mod tests {
             $expect:expr
         ) => {{
             let ac = AccessControls::new();
-            let mut acw = ac.write();
+            let mut acw = ac.write(duration_from_epoch_now());
             acw.update_search($controls).expect("Failed to update");
             let acw = acw;
 
@@ -1960,19 +2632,39 @@ mod tests {
             $expect:expr
         ) => {{
             let ac = AccessControls::new();
-            let mut acw = ac.write();
+            let mut acw = ac.write(duration_from_epoch_now());
             acw.update_modify($controls).expect("Failed to update");
             let acw = acw;
 
             let mut audit = AuditScope::new("test_acp_modify");
             let res = acw
-                .modify_allow_operation(&mut audit, $me, $entries)
+                .modify_allow_operation(&mut audit, $me, $entries, &[])
                 .expect("op failed");
             println!("result --> {:?}", res);
             println!("expect --> {:?}", $expect);
             // should be ok, and same as expect.
             assert!(res == $expect);
         }};
+        (
+            $me:expr,
+            $controls:expr,
+            $entries:expr,
+            $refer_entries:expr,
+            $expect:expr
+        ) => {{
+            let ac = AccessControls::new();
+            let mut acw = ac.write(duration_from_epoch_now());
+            acw.update_modify($controls).expect("Failed to update");
+            let acw = acw;
+
+            let mut audit = AuditScope::new("test_acp_modify");
+            let res = acw
+                .modify_allow_operation(&mut audit, $me, $entries, $refer_entries)
+                .expect("op failed");
+            println!("result --> {:?}", res);
+            println!("expect --> {:?}", $expect);
+            assert!(res == $expect);
+        }};
     }
 
     #[test]
@@ -2114,6 +2806,63 @@ mod tests {
         test_acp_modify!(&me_rem_class, vec![acp_deny.clone()], &r_set, false);
     }
 
+    #[test]
+    fn test_access_enforce_modify_claim() {
+        let e1: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_TESTPERSON1);
+        let ev1 = unsafe { e1.into_sealed_committed() };
+        let r_set = vec![ev1.clone()];
+
+        let admin: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_ADMIN_V1);
+        let admin = unsafe { admin.into_sealed_committed() };
+
+        // The same modification, from sessions of admin with different claims.
+        let me_none = unsafe {
+            ModifyEvent::new_impersonate_entry(
+                admin.clone().into_session_origin(&[]),
+                filter_all!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                modlist!([m_pres("name", &Value::new_iutf8s("value"))]),
+            )
+        };
+        let me_interactive = unsafe {
+            ModifyEvent::new_impersonate_entry(
+                admin.clone().into_session_origin(&["interactive"]),
+                filter_all!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                modlist!([m_pres("name", &Value::new_iutf8s("value"))]),
+            )
+        };
+        let me_privileged = unsafe {
+            ModifyEvent::new_impersonate_entry(
+                admin.into_session_origin(&["interactive", "privileged"]),
+                filter_all!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                modlist!([m_pres("name", &Value::new_iutf8s("value"))]),
+            )
+        };
+
+        let acp_privileged = unsafe {
+            AccessControlModify::from_raw(
+                "test_modify_privileged",
+                "87bfe9b8-7600-431e-a492-1dde64bbc458",
+                // Apply to admin, only while privileged
+                filter_valid!(f_and!([
+                    f_eq("name", PartialValue::new_iutf8s("admin")),
+                    f_eq("claim", PartialValue::new_iutf8s("privileged")),
+                ])),
+                // To modify testperson
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                // Allow pres name
+                "name",
+                // Allow rem name
+                "name",
+                // And the class allowed is account
+                "account",
+            )
+        };
+
+        test_acp_modify!(&me_none, vec![acp_privileged.clone()], &r_set, false);
+        test_acp_modify!(&me_interactive, vec![acp_privileged.clone()], &r_set, false);
+        test_acp_modify!(&me_privileged, vec![acp_privileged.clone()], &r_set, true);
+    }
+
     macro_rules! test_acp_create {
         (
             $ce:expr,
@@ -2122,7 +2871,7 @@ mod tests {
             $expect:expr
         ) => {{
             let ac = AccessControls::new();
-            let mut acw = ac.write();
+            let mut acw = ac.write(duration_from_epoch_now());
             acw.update_create($controls).expect("Failed to update");
             let acw = acw;
 
@@ -2178,6 +2927,132 @@ mod tests {
         }
     }"#;
 
+    #[test]
+    fn test_access_enforce_modify_values() {
+        let e1: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_TESTPERSON1);
+        let ev1 = unsafe { e1.into_sealed_committed() };
+        let r_set = vec![ev1.clone()];
+
+        let e_person: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "valid": null,
+            "state": null,
+            "attrs": {
+                "class": ["object", "person"],
+                "name": ["refer_person"],
+                "uuid": ["d2f4a6b8-1c3e-4a5b-8d7f-9e0a1b2c3d41"]
+            }
+        }"#,
+        );
+        let e_group: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "valid": null,
+            "state": null,
+            "attrs": {
+                "class": ["object", "group"],
+                "name": ["refer_group"],
+                "uuid": ["d2f4a6b8-1c3e-4a5b-8d7f-9e0a1b2c3d42"]
+            }
+        }"#,
+        );
+        let refer_set = unsafe {
+            vec![
+                e_person.into_sealed_committed(),
+                e_group.into_sealed_committed(),
+            ]
+        };
+
+        let me_shell = |shell: &str| unsafe {
+            ModifyEvent::new_impersonate_entry_ser(
+                JSON_ADMIN_V1,
+                filter_all!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                modlist!([m_pres("loginshell", &Value::new_iutf8s(shell))]),
+            )
+        };
+        let me_member = |u: &str| unsafe {
+            ModifyEvent::new_impersonate_entry_ser(
+                JSON_ADMIN_V1,
+                filter_all!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                modlist!([m_pres("member", &Value::new_refer_s(u).unwrap())]),
+            )
+        };
+
+        // May set loginshell, but only to bash, and add members, but only people.
+        let mut acp_values = unsafe {
+            AccessControlModify::from_raw(
+                "test_modify_values",
+                "87bfe9b8-7600-431e-a492-1dde64bbc460",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "loginshell member",
+                "",
+                "",
+            )
+        };
+        acp_values.presvalues.insert(
+            "loginshell".to_string(),
+            btreeset![PartialValue::new_iutf8s("/bin/bash")],
+        );
+        acp_values.presrefers.insert("member".to_string(), unsafe {
+            filter_valid!(f_eq("class", PartialValue::new_class("person")))
+        });
+        // Grants loginshell with no restriction on the value.
+        let acp_any_shell = unsafe {
+            AccessControlModify::from_raw(
+                "test_modify_any_shell",
+                "87bfe9b8-7600-431e-a492-1dde64bbc461",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "loginshell",
+                "",
+                "",
+            )
+        };
+
+        test_acp_modify!(
+            &me_shell("/bin/bash"),
+            vec![acp_values.clone()],
+            &r_set,
+            true
+        );
+        test_acp_modify!(
+            &me_shell("/bin/sh"),
+            vec![acp_values.clone()],
+            &r_set,
+            false
+        );
+        // An unrestricted profile still allows any value.
+        test_acp_modify!(
+            &me_shell("/bin/sh"),
+            vec![acp_values.clone(), acp_any_shell],
+            &r_set,
+            true
+        );
+
+        test_acp_modify!(
+            &me_member("d2f4a6b8-1c3e-4a5b-8d7f-9e0a1b2c3d41"),
+            vec![acp_values.clone()],
+            &r_set,
+            &refer_set,
+            true
+        );
+        test_acp_modify!(
+            &me_member("d2f4a6b8-1c3e-4a5b-8d7f-9e0a1b2c3d42"),
+            vec![acp_values.clone()],
+            &r_set,
+            &refer_set,
+            false
+        );
+        // A reference to an entry we couldn't find is refused.
+        test_acp_modify!(
+            &me_member("d2f4a6b8-1c3e-4a5b-8d7f-9e0a1b2c3d43"),
+            vec![acp_values],
+            &r_set,
+            &refer_set,
+            false
+        );
+    }
+
     #[test]
     fn test_access_enforce_create() {
         let ev1: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_TEST_CREATE_AC1);
@@ -2248,7 +3123,7 @@ mod tests {
             $expect:expr
         ) => {{
             let ac = AccessControls::new();
-            let mut acw = ac.write();
+            let mut acw = ac.write(duration_from_epoch_now());
             acw.update_delete($controls).expect("Failed to update");
             let acw = acw;
 
@@ -2299,4 +3174,384 @@ mod tests {
         // Test reject delete
         test_acp_delete!(&de_anon, vec![acp], &r_set, false);
     }
+
+    #[test]
+    fn test_access_enforce_deny() {
+        let e1: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_TESTPERSON1);
+        let ev1 = unsafe { e1.into_sealed_committed() };
+        let r_set = vec![ev1.clone()];
+
+        // Search - the deny removes name, so a filter on name can't see the entry.
+        let se_admin = unsafe {
+            SearchEvent::new_impersonate_entry_ser(JSON_ADMIN_V1, filter_all!(f_pres("name")))
+        };
+        let acs_allow = unsafe {
+            AccessControlSearch::from_raw(
+                "test_search_allow",
+                "6d2d7f3c-4b6e-4c6b-9b8e-0c1f7a6f7e01",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "name",
+            )
+        };
+        let mut acs_deny = unsafe {
+            AccessControlSearch::from_raw(
+                "test_search_deny",
+                "6d2d7f3c-4b6e-4c6b-9b8e-0c1f7a6f7e02",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "name",
+            )
+        };
+        acs_deny.acp.deny = true;
+
+        test_acp_search!(
+            &se_admin,
+            vec![acs_allow.clone()],
+            r_set.clone(),
+            vec![ev1.clone()]
+        );
+        let expect: Vec<Entry<EntrySealed, EntryCommitted>> = vec![];
+        test_acp_search!(&se_admin, vec![acs_allow, acs_deny], r_set.clone(), expect);
+
+        // Modify - name is denied, but class remains allowed.
+        let me_pres = unsafe {
+            ModifyEvent::new_impersonate_entry_ser(
+                JSON_ADMIN_V1,
+                filter_all!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                modlist!([m_pres("name", &Value::new_iutf8s("value"))]),
+            )
+        };
+        let me_pres_class = unsafe {
+            ModifyEvent::new_impersonate_entry_ser(
+                JSON_ADMIN_V1,
+                filter_all!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                modlist!([m_pres("class", &Value::new_class("account"))]),
+            )
+        };
+        let acm_allow = unsafe {
+            AccessControlModify::from_raw(
+                "test_modify_allow",
+                "6d2d7f3c-4b6e-4c6b-9b8e-0c1f7a6f7e03",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "name class",
+                "name class",
+                "account",
+            )
+        };
+        let mut acm_deny = unsafe {
+            AccessControlModify::from_raw(
+                "test_modify_deny",
+                "6d2d7f3c-4b6e-4c6b-9b8e-0c1f7a6f7e04",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "name",
+                "name",
+                "",
+            )
+        };
+        acm_deny.acp.deny = true;
+
+        test_acp_modify!(&me_pres, vec![acm_allow.clone()], &r_set, true);
+        test_acp_modify!(
+            &me_pres,
+            vec![acm_allow.clone(), acm_deny.clone()],
+            &r_set,
+            false
+        );
+        test_acp_modify!(&me_pres_class, vec![acm_allow, acm_deny], &r_set, true);
+
+        // Create - a deny on any of the entry's classes refuses the create.
+        let ce_admin = unsafe { CreateEvent::new_impersonate_entry_ser(JSON_ADMIN_V1, vec![]) };
+        let c1: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_TEST_CREATE_AC1);
+        let c_set = vec![c1];
+        let acc_allow = unsafe {
+            AccessControlCreate::from_raw(
+                "test_create_allow",
+                "6d2d7f3c-4b6e-4c6b-9b8e-0c1f7a6f7e05",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "account",
+                "class name uuid",
+            )
+        };
+        let mut acc_deny = unsafe {
+            AccessControlCreate::from_raw(
+                "test_create_deny",
+                "6d2d7f3c-4b6e-4c6b-9b8e-0c1f7a6f7e06",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "account",
+                "",
+            )
+        };
+        acc_deny.acp.deny = true;
+
+        test_acp_create!(&ce_admin, vec![acc_allow.clone()], &c_set, true);
+        test_acp_create!(
+            &ce_admin,
+            vec![acc_allow.clone(), acc_deny.clone()],
+            &c_set,
+            false
+        );
+
+        // Delete - any matching deny refuses the delete.
+        let de_admin = unsafe {
+            DeleteEvent::new_impersonate_entry_ser(
+                JSON_ADMIN_V1,
+                filter_all!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+            )
+        };
+        let acd_allow = unsafe {
+            AccessControlDelete::from_raw(
+                "test_delete_allow",
+                "6d2d7f3c-4b6e-4c6b-9b8e-0c1f7a6f7e07",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+            )
+        };
+        let mut acd_deny = unsafe {
+            AccessControlDelete::from_raw(
+                "test_delete_deny",
+                "6d2d7f3c-4b6e-4c6b-9b8e-0c1f7a6f7e08",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+            )
+        };
+        acd_deny.acp.deny = true;
+
+        test_acp_delete!(&de_admin, vec![acd_allow.clone()], &r_set, true);
+        test_acp_delete!(&de_admin, vec![acd_allow, acd_deny.clone()], &r_set, false);
+
+        // Explain reports the deny and that it overrides the allow.
+        let ev_admin = unsafe { Event::from_impersonate_entry_ser(JSON_ADMIN_V1) };
+        let ac = AccessControls::new();
+        let mut acw = ac.write(duration_from_epoch_now());
+        acw.update_delete(vec![acd_deny]).expect("Failed to update");
+        let acw = acw;
+        let mut audit = AuditScope::new("test_access_enforce_deny");
+        let r = acw
+            .explain_operation(&mut audit, &ev_admin, AccessOperation::Delete, &ev1)
+            .expect("explain failed");
+        assert!(!r.allowed);
+        assert!(r.profiles.len() == 1);
+        assert!(r.profiles[0].deny);
+        assert!(r.profiles[0].decision == AccessProfileDecision::Applied);
+
+        // The create sets leave out what the denies name.
+        let mut acc_deny_attr = unsafe {
+            AccessControlCreate::from_raw(
+                "test_create_deny_attr",
+                "6d2d7f3c-4b6e-4c6b-9b8e-0c1f7a6f7e09",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "",
+                "uuid",
+            )
+        };
+        acc_deny_attr.acp.deny = true;
+        let ac = AccessControls::new();
+        let mut acw = ac.write(duration_from_epoch_now());
+        acw.update_create(vec![acc_allow.clone()])
+            .expect("Failed to update");
+        let r = acw
+            .explain_operation(&mut audit, &ev_admin, AccessOperation::Create, &ev1)
+            .expect("explain failed");
+        assert!(
+            r.allowed_attrs == vec!["class".to_string(), "name".to_string(), "uuid".to_string()]
+        );
+        assert!(r.allowed_classes == vec!["account".to_string()]);
+
+        acw.update_create(vec![acc_allow, acc_deny, acc_deny_attr])
+            .expect("Failed to update");
+        let r = acw
+            .explain_operation(&mut audit, &ev_admin, AccessOperation::Create, &ev1)
+            .expect("explain failed");
+        assert!(!r.allowed);
+        assert!(r.allowed_attrs == vec!["class".to_string(), "name".to_string()]);
+        assert!(r.allowed_classes.is_empty());
+    }
+
+    #[test]
+    fn test_access_enforce_time_bounds() {
+        let e1: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_TESTPERSON1);
+        let ev1 = unsafe { e1.into_sealed_committed() };
+        let r_set = vec![ev1.clone()];
+
+        let de_admin = unsafe {
+            DeleteEvent::new_impersonate_entry_ser(
+                JSON_ADMIN_V1,
+                filter_all!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+            )
+        };
+        let ev_admin = unsafe { Event::from_impersonate_entry_ser(JSON_ADMIN_V1) };
+
+        let mut acp = unsafe {
+            AccessControlDelete::from_raw(
+                "test_delete_bounded",
+                "0b3c1d5e-7c8a-4f0e-9d52-3b1f8e6a2c01",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+            )
+        };
+        acp.acp.valid_from = Some(Duration::from_secs(1000));
+        acp.acp.valid_until = Some(Duration::from_secs(2000));
+
+        let ac = AccessControls::new();
+        let mut acw = ac.write(Duration::from_secs(1500));
+        acw.update_delete(vec![acp]).expect("Failed to update");
+        acw.commit().expect("Failed to commit");
+
+        let mut audit = AuditScope::new("test_access_enforce_time_bounds");
+
+        // Inside the window the profile applies.
+        let acr = ac.read(Duration::from_secs(1500));
+        assert!(acr
+            .delete_allow_operation(&mut audit, &de_admin, &r_set)
+            .expect("op failed"));
+
+        // Before and after it, it's as though it doesn't exist.
+        for t in [999, 2000].iter() {
+            let acr = ac.read(Duration::from_secs(*t));
+            assert!(!acr
+                .delete_allow_operation(&mut audit, &de_admin, &r_set)
+                .expect("op failed"));
+            let r = acr
+                .explain_operation(&mut audit, &ev_admin, AccessOperation::Delete, &ev1)
+                .expect("explain failed");
+            assert!(!r.allowed);
+            assert!(r.profiles[0].decision == AccessProfileDecision::Inactive);
+        }
+    }
+
+    #[test]
+    fn test_access_explain() {
+        let e1: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_TESTPERSON1);
+        let ev1 = unsafe { e1.into_sealed_committed() };
+
+        let ev_admin = unsafe { Event::from_impersonate_entry_ser(JSON_ADMIN_V1) };
+        let ev_anon = unsafe { Event::from_impersonate_entry_ser(JSON_ANONYMOUS_V1) };
+
+        let acs_applied = unsafe {
+            AccessControlSearch::from_raw(
+                "test_explain_applied",
+                "a4e4a2a5-8ab6-4a0f-ad61-d1d9b1a7a0a1",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "name class",
+            )
+        };
+        let acs_target = unsafe {
+            AccessControlSearch::from_raw(
+                "test_explain_target",
+                "a4e4a2a5-8ab6-4a0f-ad61-d1d9b1a7a0a2",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson2"))),
+                "uuid",
+            )
+        };
+        let acs_receiver = unsafe {
+            AccessControlSearch::from_raw(
+                "test_explain_receiver",
+                "a4e4a2a5-8ab6-4a0f-ad61-d1d9b1a7a0a3",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("anonymous"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "description",
+            )
+        };
+        let acm = unsafe {
+            AccessControlModify::from_raw(
+                "test_explain_modify",
+                "a4e4a2a5-8ab6-4a0f-ad61-d1d9b1a7a0a4",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+                "name",
+                "name class",
+                "account",
+            )
+        };
+        let acd = unsafe {
+            AccessControlDelete::from_raw(
+                "test_explain_delete",
+                "a4e4a2a5-8ab6-4a0f-ad61-d1d9b1a7a0a5",
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                filter_valid!(f_eq("name", PartialValue::new_iutf8s("testperson1"))),
+            )
+        };
+
+        let ac = AccessControls::new();
+        let mut acw = ac.write(duration_from_epoch_now());
+        acw.update_search(vec![acs_applied, acs_target, acs_receiver])
+            .expect("Failed to update");
+        acw.update_modify(vec![acm]).expect("Failed to update");
+        acw.update_delete(vec![acd]).expect("Failed to update");
+        let acw = acw;
+
+        let mut audit = AuditScope::new("test_access_explain");
+
+        let decision_of = |r: &AccessExplainResponse, name: &str| {
+            r.profiles
+                .iter()
+                .find(|p| p.name == name)
+                .map(|p| p.decision.clone())
+                .expect("profile missing")
+        };
+
+        // Search reports each profile, and only the applied one grants attrs.
+        let r = acw
+            .explain_operation(&mut audit, &ev_admin, AccessOperation::Search, &ev1)
+            .expect("explain failed");
+        assert!(r.allowed);
+        assert!(r.profiles.len() == 3);
+        assert!(decision_of(&r, "test_explain_applied") == AccessProfileDecision::Applied);
+        assert!(decision_of(&r, "test_explain_target") == AccessProfileDecision::TargetMismatch);
+        assert!(
+            decision_of(&r, "test_explain_receiver") == AccessProfileDecision::ReceiverMismatch
+        );
+        assert!(r.allowed_attrs == vec!["class".to_string(), "name".to_string()]);
+
+        // Modify gives the pres, rem and class sets.
+        let r = acw
+            .explain_operation(&mut audit, &ev_admin, AccessOperation::Modify, &ev1)
+            .expect("explain failed");
+        assert!(r.allowed);
+        assert!(r.allowed_presattrs == vec!["name".to_string()]);
+        assert!(r.allowed_remattrs == vec!["class".to_string(), "name".to_string()]);
+        assert!(r.allowed_classes == vec!["account".to_string()]);
+
+        // Anonymous is not a receiver of the modify or delete.
+        let r = acw
+            .explain_operation(&mut audit, &ev_anon, AccessOperation::Modify, &ev1)
+            .expect("explain failed");
+        assert!(!r.allowed);
+        assert!(r.allowed_presattrs.is_empty());
+        assert!(decision_of(&r, "test_explain_modify") == AccessProfileDecision::ReceiverMismatch);
+
+        let r = acw
+            .explain_operation(&mut audit, &ev_admin, AccessOperation::Delete, &ev1)
+            .expect("explain failed");
+        assert!(r.allowed);
+        let r = acw
+            .explain_operation(&mut audit, &ev_anon, AccessOperation::Delete, &ev1)
+            .expect("explain failed");
+        assert!(!r.allowed);
+
+        // No create profiles exist, so nothing is allowed.
+        let r = acw
+            .explain_operation(&mut audit, &ev_admin, AccessOperation::Create, &ev1)
+            .expect("explain failed");
+        assert!(!r.allowed);
+        assert!(r.profiles.is_empty());
+
+        // Internal events bypass access controls, so there is nothing to explain.
+        assert!(acw
+            .explain_operation(
+                &mut audit,
+                &Event::from_internal(),
+                AccessOperation::Search,
+                &ev1
+            )
+            .is_err());
+    }
 }
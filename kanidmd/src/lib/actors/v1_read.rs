@@ -3,12 +3,16 @@ use std::sync::Arc;
 use crate::audit::AuditScope;
 
 use crate::async_log::EventLog;
-use crate::event::{AuthEvent, SearchEvent, SearchResult, WhoamiResult};
+use crate::event::{AccessExplainEvent, AuthEvent, Event, SearchEvent, SearchResult, WhoamiResult};
 use crate::idm::event::{
-    RadiusAuthTokenEvent, UnixGroupTokenEvent, UnixUserAuthEvent, UnixUserTokenEvent,
+    AppPasswordListEvent, CredentialListEvent, RadiusAuthTokenEvent, SessionListEvent,
+    TOTPListEvent, UnixGroupTokenEvent, UnixUserAuthEvent, UnixUserTokenEvent,
 };
 use crate::value::PartialValue;
-use kanidm_proto::v1::{OperationError, RadiusAuthToken};
+use kanidm_proto::v1::{
+    AccessExplainRequest, AccessExplainResponse, AppPassword, CredentialInfo, OperationError,
+    RadiusAuthToken, SessionInfo,
+};
 
 use crate::filter::{Filter, FilterInvalid};
 use crate::idm::server::IdmServer;
@@ -48,12 +52,27 @@ impl Message for WhoamiMessage {
 #[derive(Debug)]
 pub struct AuthMessage {
     pub sessionid: Option<Uuid>,
+    // The session that is already authenticated, if any. This is only used to
+    // re-authenticate.
+    pub uat: Option<UserAuthToken>,
     pub req: AuthRequest,
+    // The address the request came from, recorded on the session.
+    pub source: Option<String>,
 }
 
 impl AuthMessage {
-    pub fn new(req: AuthRequest, sessionid: Option<Uuid>) -> Self {
-        AuthMessage { sessionid, req }
+    pub fn new(
+        req: AuthRequest,
+        sessionid: Option<Uuid>,
+        uat: Option<UserAuthToken>,
+        source: Option<String>,
+    ) -> Self {
+        AuthMessage {
+            sessionid,
+            uat,
+            req,
+            source,
+        }
     }
 }
 
@@ -132,6 +151,42 @@ impl Message for InternalUnixGroupTokenReadMessage {
     type Result = Result<UnixGroupToken, OperationError>;
 }
 
+pub struct InternalAppPasswordReadMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+}
+
+impl Message for InternalAppPasswordReadMessage {
+    type Result = Result<Vec<AppPassword>, OperationError>;
+}
+
+pub struct InternalSessionReadMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+}
+
+impl Message for InternalSessionReadMessage {
+    type Result = Result<Vec<SessionInfo>, OperationError>;
+}
+
+pub struct InternalCredentialReadMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+}
+
+impl Message for InternalCredentialReadMessage {
+    type Result = Result<Vec<CredentialInfo>, OperationError>;
+}
+
+pub struct InternalTOTPReadMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+}
+
+impl Message for InternalTOTPReadMessage {
+    type Result = Result<Vec<String>, OperationError>;
+}
+
 pub struct InternalSshKeyReadMessage {
     pub uat: Option<UserAuthToken>,
     pub uuid_or_name: String,
@@ -161,6 +216,15 @@ impl Message for IdmAccountUnixAuthMessage {
     type Result = Result<Option<UnixUserToken>, OperationError>;
 }
 
+pub struct InternalAccessExplainMessage {
+    pub uat: Option<UserAuthToken>,
+    pub req: AccessExplainRequest,
+}
+
+impl Message for InternalAccessExplainMessage {
+    type Result = Result<AccessExplainResponse, OperationError>;
+}
+
 // ===========================================================
 
 pub struct QueryServerReadV1 {
@@ -250,6 +314,7 @@ impl Handler<AuthMessage> for QueryServerReadV1 {
 
             let mut idm_write = self.idms.write();
 
+            let source = msg.source.clone();
             let ae = try_audit!(audit, AuthEvent::from_message(msg));
 
             let ct = SystemTime::now()
@@ -260,16 +325,58 @@ impl Handler<AuthMessage> for QueryServerReadV1 {
             // It's important to do this before to ensure that timeouts on
             // the session are enforced.
             idm_write.expire_auth_sessions(ct);
+            idm_write.expire_softlocks(ct);
 
             // Generally things like auth denied are in Ok() msgs
             // so true errors should always trigger a rollback.
-            let r = idm_write
-                .auth(&mut audit, &ae, ct)
-                .and_then(|r| idm_write.commit().map(|_| r));
+            let r = idm_write.auth(&mut audit, &ae, ct).and_then(|r| {
+                idm_write
+                    .issue_bearer(&r, source, ct)
+                    .map(|bearer| r.response(bearer))
+            });
+            let counters = idm_write.take_webauthn_counter_updates();
+            let upgrades = idm_write.take_password_upgrades();
+            let code_uses = idm_write.take_backup_code_uses();
+            let totp_steps = idm_write.take_totp_step_updates();
+            let sessions = idm_write.take_issued_sessions();
+            let mut r = r.and_then(|r| idm_write.commit().map(|_| r));
+
+            // Webauthn signature counters must be stored so that a cloned token can be
+            // detected on its next use, weakly hashed passwords are replaced, and used
+            // backup codes and totp codes can't be used again. This needs a write, which we
+            // only take when there is something to store. A failure here doesn't change the
+            // auth result, unless a backup code or totp code was used, as the write is what
+            // checks that no other session used it first, or the session of the issued
+            // token wasn't stored, as then the token can't be used.
+            if !counters.is_empty()
+                || !upgrades.is_empty()
+                || !code_uses.is_empty()
+                || !totp_steps.is_empty()
+                || !sessions.is_empty()
+            {
+                let mut idms_prox_write = self.idms.proxy_write(ct);
+                let cr = idms_prox_write
+                    .update_webauthn_counters(&mut audit, &counters)
+                    .and_then(|_| idms_prox_write.upgrade_passwords(&mut audit, &upgrades))
+                    .and_then(|_| idms_prox_write.remove_backup_codes(&mut audit, &code_uses))
+                    .and_then(|_| idms_prox_write.update_totp_steps(&mut audit, &totp_steps))
+                    .and_then(|_| idms_prox_write.record_sessions(&mut audit, &sessions, ct))
+                    .and_then(|_| idms_prox_write.commit(&mut audit));
+                if let Err(e) = cr {
+                    audit_log!(audit, "Failed to store credential updates -> {:?}", e);
+                    if !code_uses.is_empty() || !totp_steps.is_empty() || !sessions.is_empty() {
+                        r = Err(e);
+                    }
+                }
+            }
 
-            audit_log!(audit, "Sending result -> {:?}", r);
-            // Build the result.
-            r.map(|r| r.response())
+            // The bearer token is a credential, so it must not be logged.
+            audit_log!(
+                audit,
+                "Sending result -> {:?}",
+                r.as_ref().map(|r| (&r.sessionid, &r.state))
+            );
+            r
         });
         // At the end of the event we send it for logging.
         self.log.do_send(audit);
@@ -490,7 +597,183 @@ impl Handler<InternalRadiusTokenReadMessage> for QueryServerReadV1 {
 
             audit_log!(audit, "Begin event {:?}", rate);
 
-            idm_read.get_radiusauthtoken(&mut audit, &rate)
+            let ct = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .expect("Clock failure!");
+
+            idm_read.get_radiusauthtoken(&mut audit, &rate, ct)
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<InternalAppPasswordReadMessage> for QueryServerReadV1 {
+    type Result = Result<Vec<AppPassword>, OperationError>;
+
+    fn handle(
+        &mut self,
+        msg: InternalAppPasswordReadMessage,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let mut audit = AuditScope::new("internal_app_password_read_message");
+        let res = audit_segment!(&mut audit, || {
+            let mut idm_read = self.idms.proxy_read();
+
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => idm_read
+                    .qs_read
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let aple = match AppPasswordListEvent::from_parts(
+                &mut audit,
+                &mut idm_read.qs_read,
+                msg.uat,
+                target_uuid,
+            ) {
+                Ok(s) => s,
+                Err(e) => {
+                    audit_log!(audit, "Failed to begin search: {:?}", e);
+                    return Err(e);
+                }
+            };
+
+            audit_log!(audit, "Begin event {:?}", aple);
+
+            idm_read.get_app_passwords(&mut audit, &aple)
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<InternalSessionReadMessage> for QueryServerReadV1 {
+    type Result = Result<Vec<SessionInfo>, OperationError>;
+
+    fn handle(&mut self, msg: InternalSessionReadMessage, _: &mut Self::Context) -> Self::Result {
+        let mut audit = AuditScope::new("internal_session_read_message");
+        let res = audit_segment!(&mut audit, || {
+            let mut idm_read = self.idms.proxy_read();
+
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => idm_read
+                    .qs_read
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let sle = match SessionListEvent::from_parts(
+                &mut audit,
+                &mut idm_read.qs_read,
+                msg.uat,
+                target_uuid,
+            ) {
+                Ok(s) => s,
+                Err(e) => {
+                    audit_log!(audit, "Failed to begin search: {:?}", e);
+                    return Err(e);
+                }
+            };
+
+            audit_log!(audit, "Begin event {:?}", sle);
+
+            idm_read.get_sessions(&mut audit, &sle)
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<InternalCredentialReadMessage> for QueryServerReadV1 {
+    type Result = Result<Vec<CredentialInfo>, OperationError>;
+
+    fn handle(
+        &mut self,
+        msg: InternalCredentialReadMessage,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let mut audit = AuditScope::new("internal_credential_read_message");
+        let res = audit_segment!(&mut audit, || {
+            let mut idm_read = self.idms.proxy_read();
+
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => idm_read
+                    .qs_read
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let cle = match CredentialListEvent::from_parts(
+                &mut audit,
+                &mut idm_read.qs_read,
+                msg.uat,
+                target_uuid,
+            ) {
+                Ok(s) => s,
+                Err(e) => {
+                    audit_log!(audit, "Failed to begin search: {:?}", e);
+                    return Err(e);
+                }
+            };
+
+            audit_log!(audit, "Begin event {:?}", cle);
+
+            idm_read.get_credentials(&mut audit, &cle)
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<InternalTOTPReadMessage> for QueryServerReadV1 {
+    type Result = Result<Vec<String>, OperationError>;
+
+    fn handle(&mut self, msg: InternalTOTPReadMessage, _: &mut Self::Context) -> Self::Result {
+        let mut audit = AuditScope::new("internal_totp_read_message");
+        let res = audit_segment!(&mut audit, || {
+            let mut idm_read = self.idms.proxy_read();
+
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => idm_read
+                    .qs_read
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let tle = match TOTPListEvent::from_parts(
+                &mut audit,
+                &mut idm_read.qs_read,
+                msg.uat,
+                target_uuid,
+            ) {
+                Ok(s) => s,
+                Err(e) => {
+                    audit_log!(audit, "Failed to begin search: {:?}", e);
+                    return Err(e);
+                }
+            };
+
+            audit_log!(audit, "Begin event {:?}", tle);
+
+            idm_read.get_totp_labels(&mut audit, &tle)
         });
         self.log.do_send(audit);
         res
@@ -713,6 +996,64 @@ impl Handler<InternalSshKeyTagReadMessage> for QueryServerReadV1 {
     }
 }
 
+impl Handler<InternalAccessExplainMessage> for QueryServerReadV1 {
+    type Result = Result<AccessExplainResponse, OperationError>;
+
+    fn handle(&mut self, msg: InternalAccessExplainMessage, _: &mut Self::Context) -> Self::Result {
+        let mut audit = AuditScope::new("internal_access_explain_message");
+        let res = audit_segment!(&mut audit, || {
+            let mut qs_read = self.qs.read();
+
+            let event = Event::from_ro_uat(&mut audit, &mut qs_read, msg.uat)?;
+            // Checked before resolving any names, so an unprivileged requester can't
+            // use the errors to learn which names exist.
+            qs_read.check_explain_access(&mut audit, &event)?;
+
+            let identity_uuid = match Uuid::parse_str(msg.req.identity.as_str()) {
+                Ok(u) => u,
+                Err(_) => qs_read
+                    .name_to_uuid(&mut audit, msg.req.identity.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to identity");
+                        e
+                    })?,
+            };
+
+            let target_uuid = match Uuid::parse_str(msg.req.target.as_str()) {
+                Ok(u) => u,
+                Err(_) => qs_read
+                    .name_to_uuid(&mut audit, msg.req.target.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            // Make an event from the request
+            let aee = match AccessExplainEvent::from_parts(
+                &mut audit,
+                &mut qs_read,
+                event,
+                identity_uuid,
+                target_uuid,
+                msg.req.operation,
+            ) {
+                Ok(s) => s,
+                Err(e) => {
+                    audit_log!(audit, "Failed to begin access explain: {:?}", e);
+                    return Err(e);
+                }
+            };
+
+            audit_log!(audit, "Begin event {:?}", aee);
+
+            qs_read.explain_access(&mut audit, &aee)
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
 impl Handler<IdmAccountUnixAuthMessage> for QueryServerReadV1 {
     type Result = Result<Option<UnixUserToken>, OperationError>;
 
@@ -752,9 +1093,19 @@ impl Handler<IdmAccountUnixAuthMessage> for QueryServerReadV1 {
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .expect("Clock failure!");
 
-            let r = idm_write
-                .auth_unix(&mut audit, &uuae, ct)
-                .and_then(|r| idm_write.commit().map(|_| r));
+            let r = idm_write.auth_unix(&mut audit, &uuae, ct);
+            let upgrades = idm_write.take_password_upgrades();
+            let r = r.and_then(|r| idm_write.commit().map(|_| r));
+
+            if !upgrades.is_empty() {
+                let mut idms_prox_write = self.idms.proxy_write(ct);
+                let cr = idms_prox_write
+                    .upgrade_passwords(&mut audit, &upgrades)
+                    .and_then(|_| idms_prox_write.commit(&mut audit));
+                if let Err(e) = cr {
+                    audit_log!(audit, "Failed to store password upgrades -> {:?}", e);
+                }
+            }
 
             audit_log!(audit, "Sending result -> {:?}", r);
             r
@@ -1,21 +1,25 @@
 use crate::audit::AuditScope;
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::async_log::EventLog;
 use crate::event::{
-    CreateEvent, DeleteEvent, ModifyEvent, PurgeRecycledEvent, PurgeTombstoneEvent,
-    ReviveRecycledEvent,
+    CreateEvent, DeleteEvent, ModifyEvent, PurgeExpiredAcpEvent, PurgeRecycledEvent,
+    PurgeTombstoneEvent, ReviveRecycledEvent, RotateJwsKeysEvent,
 };
 use crate::idm::event::{
-    GeneratePasswordEvent, GenerateTOTPEvent, PasswordChangeEvent, RegenerateRadiusSecretEvent,
-    UnixPasswordChangeEvent, VerifyTOTPEvent,
+    AppPasswordCreateEvent, AppPasswordRemoveEvent, CredentialLockEvent, CredentialRemoveEvent,
+    CredentialResetTokenCreateEvent, GenerateBackupCodesEvent, GeneratePasswordEvent,
+    GenerateTOTPEvent, PasswordChangeEvent, RegenerateRadiusSecretEvent, SessionRevokeEvent,
+    TOTPRemoveEvent, UnixPasswordChangeEvent, UnlockAccountEvent, VerifyTOTPEvent,
+    WebauthnDoRegisterEvent, WebauthnInitRegisterEvent, WebauthnRemoveEvent,
 };
 use crate::modify::{Modify, ModifyInvalid, ModifyList};
 use crate::value::{PartialValue, Value};
 use kanidm_proto::v1::OperationError;
 
 use crate::filter::{Filter, FilterInvalid};
-use crate::idm::server::IdmServer;
+use crate::idm::server::{IdmServer, IdmServerProxyWriteTransaction};
 use crate::server::{QueryServer, QueryServerTransaction};
 use crate::utils::duration_from_epoch_now;
 
@@ -119,6 +123,20 @@ impl Message for IdmAccountSetPasswordMessage {
     type Result = Result<OperationResponse, OperationError>;
 }
 
+pub struct IdmAccountGenerateBackupCodesMessage {
+    pub uat: Option<UserAuthToken>,
+}
+
+impl IdmAccountGenerateBackupCodesMessage {
+    pub fn new(uat: Option<UserAuthToken>) -> Self {
+        IdmAccountGenerateBackupCodesMessage { uat }
+    }
+}
+
+impl Message for IdmAccountGenerateBackupCodesMessage {
+    type Result = Result<Vec<String>, OperationError>;
+}
+
 pub struct IdmAccountPersonExtendMessage {
     pub uat: Option<UserAuthToken>,
     pub uuid_or_name: String,
@@ -185,7 +203,8 @@ impl Message for IdmAccountUnixSetCredMessage {
 pub struct InternalCredentialSetMessage {
     pub uat: Option<UserAuthToken>,
     pub uuid_or_name: String,
-    pub appid: Option<String>,
+    // The uuid or label of the credential to change, or the primary one.
+    pub cred_id: Option<String>,
     pub sac: SetCredentialRequest,
 }
 
@@ -193,6 +212,26 @@ impl Message for InternalCredentialSetMessage {
     type Result = Result<SetCredentialResponse, OperationError>;
 }
 
+pub struct InternalCredentialResetTokenCreateMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+}
+
+impl Message for InternalCredentialResetTokenCreateMessage {
+    type Result = Result<String, OperationError>;
+}
+
+/// A credential change that is authorised by a reset token rather than a session.
+pub struct InternalCredentialResetMessage {
+    pub uuid_or_name: String,
+    pub token: String,
+    pub sac: SetCredentialRequest,
+}
+
+impl Message for InternalCredentialResetMessage {
+    type Result = Result<SetCredentialResponse, OperationError>;
+}
+
 pub struct InternalRegenerateRadiusMessage {
     pub uat: Option<UserAuthToken>,
     pub uuid_or_name: String,
@@ -208,6 +247,74 @@ impl Message for InternalRegenerateRadiusMessage {
     type Result = Result<String, OperationError>;
 }
 
+pub struct InternalAppPasswordCreateMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub label: String,
+    pub application: String,
+}
+
+impl Message for InternalAppPasswordCreateMessage {
+    type Result = Result<String, OperationError>;
+}
+
+pub struct InternalAppPasswordRemoveMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub label: String,
+}
+
+impl Message for InternalAppPasswordRemoveMessage {
+    type Result = Result<(), OperationError>;
+}
+
+pub struct InternalCredentialLockMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub cred_id: String,
+    pub locked: bool,
+}
+
+impl Message for InternalCredentialLockMessage {
+    type Result = Result<(), OperationError>;
+}
+
+pub struct InternalCredentialRemoveMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    pub cred_id: String,
+}
+
+impl Message for InternalCredentialRemoveMessage {
+    type Result = Result<(), OperationError>;
+}
+
+pub struct InternalSessionRevokeMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+    // The session to revoke, or all of them.
+    pub id: Option<Uuid>,
+}
+
+impl Message for InternalSessionRevokeMessage {
+    type Result = Result<(), OperationError>;
+}
+
+pub struct InternalUnlockAccountMessage {
+    pub uat: Option<UserAuthToken>,
+    pub uuid_or_name: String,
+}
+
+impl InternalUnlockAccountMessage {
+    pub fn new(uat: Option<UserAuthToken>, uuid_or_name: String) -> Self {
+        InternalUnlockAccountMessage { uat, uuid_or_name }
+    }
+}
+
+impl Message for InternalUnlockAccountMessage {
+    type Result = Result<(), OperationError>;
+}
+
 pub struct InternalSshKeyCreateMessage {
     pub uat: Option<UserAuthToken>,
     pub uuid_or_name: String,
@@ -523,6 +630,175 @@ impl Handler<ReviveRecycledMessage> for QueryServerWriteV1 {
     }
 }
 
+// Apply a credential change to the target within the caller's transaction, which is
+// left for the caller to commit.
+fn set_credential(
+    audit: &mut AuditScope,
+    idms_prox_write: &mut IdmServerProxyWriteTransaction,
+    msg: InternalCredentialSetMessage,
+    target_uuid: Uuid,
+    ct: Duration,
+) -> Result<SetCredentialResponse, OperationError> {
+    // What type of auth set did we recieve?
+    match msg.sac {
+        SetCredentialRequest::Password(cleartext) => {
+            let pce = PasswordChangeEvent::from_parts(
+                audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                cleartext,
+                msg.cred_id,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin internal_credential_set_message: {:?}",
+                    e
+                );
+                e
+            })?;
+            idms_prox_write
+                .set_account_password(audit, &pce)
+                .map(|_| SetCredentialResponse::Success)
+        }
+        SetCredentialRequest::GeneratePassword => {
+            let gpe = GeneratePasswordEvent::from_parts(
+                audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                msg.cred_id,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin internal_credential_set_message: {:?}",
+                    e
+                );
+                e
+            })?;
+            idms_prox_write
+                .generate_account_password(audit, &gpe)
+                .map(|s| SetCredentialResponse::Token(s))
+        }
+        SetCredentialRequest::TOTPGenerate(label) => {
+            let gte = GenerateTOTPEvent::from_parts(
+                audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                label,
+                msg.cred_id,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin internal_credential_set_message: {:?}",
+                    e
+                );
+                e
+            })?;
+            idms_prox_write.generate_account_totp(audit, &gte, ct)
+        }
+        SetCredentialRequest::TOTPVerify(uuid, chal) => {
+            let vte = VerifyTOTPEvent::from_parts(
+                audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                uuid,
+                chal,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin internal_credential_set_message: {:?}",
+                    e
+                );
+                e
+            })?;
+            idms_prox_write.verify_account_totp(audit, &vte, ct)
+        }
+        SetCredentialRequest::TOTPRemove(label) => {
+            let tre = TOTPRemoveEvent::from_parts(
+                audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                label,
+                msg.cred_id,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin internal_credential_set_message: {:?}",
+                    e
+                );
+                e
+            })?;
+            idms_prox_write.remove_account_totp(audit, &tre)
+        }
+        SetCredentialRequest::WebauthnBegin(label) => {
+            let wre = WebauthnInitRegisterEvent::from_parts(
+                audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                label,
+                msg.cred_id,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin internal_credential_set_message: {:?}",
+                    e
+                );
+                e
+            })?;
+            idms_prox_write.init_account_webauthn(audit, &wre, ct)
+        }
+        SetCredentialRequest::WebauthnRegister(uuid, reg) => {
+            let wre = WebauthnDoRegisterEvent::from_parts(
+                audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                uuid,
+                reg,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin internal_credential_set_message: {:?}",
+                    e
+                );
+                e
+            })?;
+            idms_prox_write.register_account_webauthn(audit, &wre, ct)
+        }
+        SetCredentialRequest::WebauthnRemove(label) => {
+            let wre = WebauthnRemoveEvent::from_parts(
+                audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                label,
+                msg.cred_id,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin internal_credential_set_message: {:?}",
+                    e
+                );
+                e
+            })?;
+            idms_prox_write.remove_account_webauthn(audit, &wre)
+        }
+    }
+}
+
 // IDM native types for modifications
 impl Handler<InternalCredentialSetMessage> for QueryServerWriteV1 {
     type Result = Result<SetCredentialResponse, OperationError>;
@@ -553,138 +829,23 @@ impl Handler<InternalCredentialSetMessage> for QueryServerWriteV1 {
                     })?,
             };
 
-            // What type of auth set did we recieve?
-            match msg.sac {
-                SetCredentialRequest::Password(cleartext) => {
-                    let pce = PasswordChangeEvent::from_parts(
-                        &mut audit,
-                        &mut idms_prox_write.qs_write,
-                        msg.uat,
-                        target_uuid,
-                        cleartext,
-                        msg.appid,
-                    )
-                    .map_err(|e| {
-                        audit_log!(
-                            audit,
-                            "Failed to begin internal_credential_set_message: {:?}",
-                            e
-                        );
-                        e
-                    })?;
-                    idms_prox_write
-                        .set_account_password(&mut audit, &pce)
-                        .and_then(|_| idms_prox_write.commit(&mut audit))
-                        .map(|_| SetCredentialResponse::Success)
-                }
-                SetCredentialRequest::GeneratePassword => {
-                    let gpe = GeneratePasswordEvent::from_parts(
-                        &mut audit,
-                        &mut idms_prox_write.qs_write,
-                        msg.uat,
-                        target_uuid,
-                        msg.appid,
-                    )
-                    .map_err(|e| {
-                        audit_log!(
-                            audit,
-                            "Failed to begin internal_credential_set_message: {:?}",
-                            e
-                        );
-                        e
-                    })?;
-                    idms_prox_write
-                        .generate_account_password(&mut audit, &gpe)
-                        .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
-                        .map(|s| SetCredentialResponse::Token(s))
-                }
-                SetCredentialRequest::TOTPGenerate(label) => {
-                    let gte = GenerateTOTPEvent::from_parts(
-                        &mut audit,
-                        &mut idms_prox_write.qs_write,
-                        msg.uat,
-                        target_uuid,
-                        label,
-                    )
-                    .map_err(|e| {
-                        audit_log!(
-                            audit,
-                            "Failed to begin internal_credential_set_message: {:?}",
-                            e
-                        );
-                        e
-                    })?;
-                    idms_prox_write
-                        .generate_account_totp(&mut audit, &gte, ct)
-                        .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
-                }
-                SetCredentialRequest::TOTPVerify(uuid, chal) => {
-                    let vte = VerifyTOTPEvent::from_parts(
-                        &mut audit,
-                        &mut idms_prox_write.qs_write,
-                        msg.uat,
-                        target_uuid,
-                        uuid,
-                        chal,
-                    )
-                    .map_err(|e| {
-                        audit_log!(
-                            audit,
-                            "Failed to begin internal_credential_set_message: {:?}",
-                            e
-                        );
-                        e
-                    })?;
-                    idms_prox_write
-                        .verify_account_totp(&mut audit, &vte, ct)
-                        .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
-                }
-            }
-        });
-        self.log.do_send(audit);
-        res
-    }
-}
-
-impl Handler<IdmAccountSetPasswordMessage> for QueryServerWriteV1 {
-    type Result = Result<OperationResponse, OperationError>;
-
-    fn handle(&mut self, msg: IdmAccountSetPasswordMessage, _: &mut Self::Context) -> Self::Result {
-        let mut audit = AuditScope::new("idm_account_set_password");
-        let res = audit_segment!(&mut audit, || {
-            let ct = duration_from_epoch_now();
-            let mut idms_prox_write = self.idms.proxy_write(ct.clone());
-            idms_prox_write.expire_mfareg_sessions(ct);
-
-            let pce = PasswordChangeEvent::from_idm_account_set_password(
-                &mut audit,
-                &mut idms_prox_write.qs_write,
-                msg,
-            )
-            .map_err(|e| {
-                audit_log!(audit, "Failed to begin idm_account_set_password: {:?}", e);
-                e
-            })?;
-
-            idms_prox_write
-                .set_account_password(&mut audit, &pce)
-                .and_then(|_| idms_prox_write.commit(&mut audit))
-                .map(|_| OperationResponse::new(()))
+            set_credential(&mut audit, &mut idms_prox_write, msg, target_uuid, ct)
+                .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
         });
         self.log.do_send(audit);
         res
     }
 }
 
-impl Handler<InternalRegenerateRadiusMessage> for QueryServerWriteV1 {
+impl Handler<InternalCredentialResetTokenCreateMessage> for QueryServerWriteV1 {
     type Result = Result<String, OperationError>;
 
     fn handle(
         &mut self,
-        msg: InternalRegenerateRadiusMessage,
+        msg: InternalCredentialResetTokenCreateMessage,
         _: &mut Self::Context,
     ) -> Self::Result {
-        let mut audit = AuditScope::new("idm_account_regenerate_radius");
+        let mut audit = AuditScope::new("idm_account_credential_reset_token_create");
         let res = audit_segment!(&mut audit, || {
             let ct = duration_from_epoch_now();
             let mut idms_prox_write = self.idms.proxy_write(ct.clone());
@@ -701,7 +862,7 @@ impl Handler<InternalRegenerateRadiusMessage> for QueryServerWriteV1 {
                     })?,
             };
 
-            let rrse = RegenerateRadiusSecretEvent::from_parts(
+            let crte = CredentialResetTokenCreateEvent::from_parts(
                 &mut audit,
                 &mut idms_prox_write.qs_write,
                 msg.uat,
@@ -710,14 +871,14 @@ impl Handler<InternalRegenerateRadiusMessage> for QueryServerWriteV1 {
             .map_err(|e| {
                 audit_log!(
                     audit,
-                    "Failed to begin idm_account_regenerate_radius: {:?}",
+                    "Failed to begin idm_account_credential_reset_token_create: {:?}",
                     e
                 );
                 e
             })?;
 
             idms_prox_write
-                .regenerate_radius_secret(&mut audit, &rrse)
+                .create_credential_reset_token(&mut audit, &crte)
                 .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
         });
         self.log.do_send(audit);
@@ -725,16 +886,26 @@ impl Handler<InternalRegenerateRadiusMessage> for QueryServerWriteV1 {
     }
 }
 
-impl Handler<PurgeAttributeMessage> for QueryServerWriteV1 {
-    type Result = Result<(), OperationError>;
+impl Handler<InternalCredentialResetMessage> for QueryServerWriteV1 {
+    type Result = Result<SetCredentialResponse, OperationError>;
 
-    fn handle(&mut self, msg: PurgeAttributeMessage, _: &mut Self::Context) -> Self::Result {
-        let mut audit = AuditScope::new("purge_attribute");
+    fn handle(
+        &mut self,
+        msg: InternalCredentialResetMessage,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let mut audit = AuditScope::new("internal_credential_reset_message");
         let res = audit_segment!(&mut audit, || {
-            let mut qs_write = self.qs.write(duration_from_epoch_now());
+            // The token is checked, the change made and the token used up in one
+            // transaction, so a token can't be used for more than one change.
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = self.idms.proxy_write(ct.clone());
+            idms_prox_write.expire_mfareg_sessions(ct);
+
             let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
                 Ok(u) => u,
-                Err(_) => qs_write
+                Err(_) => idms_prox_write
+                    .qs_write
                     .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
                     .map_err(|e| {
                         audit_log!(&mut audit, "Error resolving id to target");
@@ -742,24 +913,481 @@ impl Handler<PurgeAttributeMessage> for QueryServerWriteV1 {
                     })?,
             };
 
-            let mdf = match ModifyEvent::from_target_uuid_attr_purge(
+            let uat = idms_prox_write.verify_credential_reset_token(
                 &mut audit,
-                msg.uat,
+                &target_uuid,
+                msg.token.as_str(),
+                &ct,
+            )?;
+
+            // Make the change as the account itself, so it's subject to the same access
+            // controls and checks as if the user had authenticated.
+            let r = set_credential(
+                &mut audit,
+                &mut idms_prox_write,
+                InternalCredentialSetMessage {
+                    uat: Some(uat),
+                    uuid_or_name: target_uuid.to_hyphenated_ref().to_string(),
+                    cred_id: None,
+                    sac: msg.sac,
+                },
                 target_uuid,
-                msg.attr,
-                msg.filter,
-                &mut qs_write,
-            ) {
-                Ok(m) => m,
-                Err(e) => {
-                    audit_log!(audit, "Failed to begin modify: {:?}", e);
-                    return Err(e);
+                ct,
+            )?;
+
+            // Registering a totp or webauthn token takes more than one request, so the
+            // token is only used up once a change is complete.
+            match r {
+                SetCredentialResponse::Success
+                | SetCredentialResponse::Token(_)
+                | SetCredentialResponse::BackupCodes(_) => {
+                    idms_prox_write.remove_credential_reset_token(&mut audit, &target_uuid)?;
                 }
-            };
-
-            audit_log!(audit, "Begin modify event {:?}", mdf);
-
-            qs_write
+                _ => {}
+            }
+            idms_prox_write.commit(&mut audit)?;
+            Ok(r)
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<IdmAccountSetPasswordMessage> for QueryServerWriteV1 {
+    type Result = Result<OperationResponse, OperationError>;
+
+    fn handle(&mut self, msg: IdmAccountSetPasswordMessage, _: &mut Self::Context) -> Self::Result {
+        let mut audit = AuditScope::new("idm_account_set_password");
+        let res = audit_segment!(&mut audit, || {
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = self.idms.proxy_write(ct.clone());
+            idms_prox_write.expire_mfareg_sessions(ct);
+
+            let pce = PasswordChangeEvent::from_idm_account_set_password(
+                &mut audit,
+                &mut idms_prox_write.qs_write,
+                msg,
+            )
+            .map_err(|e| {
+                audit_log!(audit, "Failed to begin idm_account_set_password: {:?}", e);
+                e
+            })?;
+
+            idms_prox_write
+                .set_account_password(&mut audit, &pce)
+                .and_then(|_| idms_prox_write.commit(&mut audit))
+                .map(|_| OperationResponse::new(()))
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<IdmAccountGenerateBackupCodesMessage> for QueryServerWriteV1 {
+    type Result = Result<Vec<String>, OperationError>;
+
+    fn handle(
+        &mut self,
+        msg: IdmAccountGenerateBackupCodesMessage,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let mut audit = AuditScope::new("idm_account_generate_backup_codes");
+        let res = audit_segment!(&mut audit, || {
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = self.idms.proxy_write(ct.clone());
+            idms_prox_write.expire_mfareg_sessions(ct);
+
+            let gbe = GenerateBackupCodesEvent::from_idm_account_generate_backup_codes(
+                &mut audit,
+                &mut idms_prox_write.qs_write,
+                msg,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin idm_account_generate_backup_codes: {:?}",
+                    e
+                );
+                e
+            })?;
+
+            idms_prox_write
+                .generate_backup_codes(&mut audit, &gbe)
+                .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<InternalRegenerateRadiusMessage> for QueryServerWriteV1 {
+    type Result = Result<String, OperationError>;
+
+    fn handle(
+        &mut self,
+        msg: InternalRegenerateRadiusMessage,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let mut audit = AuditScope::new("idm_account_regenerate_radius");
+        let res = audit_segment!(&mut audit, || {
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = self.idms.proxy_write(ct.clone());
+            idms_prox_write.expire_mfareg_sessions(ct);
+
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let rrse = RegenerateRadiusSecretEvent::from_parts(
+                &mut audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin idm_account_regenerate_radius: {:?}",
+                    e
+                );
+                e
+            })?;
+
+            idms_prox_write
+                .regenerate_radius_secret(&mut audit, &rrse)
+                .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<InternalAppPasswordCreateMessage> for QueryServerWriteV1 {
+    type Result = Result<String, OperationError>;
+
+    fn handle(
+        &mut self,
+        msg: InternalAppPasswordCreateMessage,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let mut audit = AuditScope::new("idm_account_app_password_create");
+        let res = audit_segment!(&mut audit, || {
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = self.idms.proxy_write(ct.clone());
+            idms_prox_write.expire_mfareg_sessions(ct);
+
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let apce = AppPasswordCreateEvent::from_parts(
+                &mut audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                msg.label,
+                msg.application,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin idm_account_app_password_create: {:?}",
+                    e
+                );
+                e
+            })?;
+
+            idms_prox_write
+                .generate_app_password(&mut audit, &apce)
+                .and_then(|r| idms_prox_write.commit(&mut audit).map(|_| r))
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<InternalAppPasswordRemoveMessage> for QueryServerWriteV1 {
+    type Result = Result<(), OperationError>;
+
+    fn handle(
+        &mut self,
+        msg: InternalAppPasswordRemoveMessage,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let mut audit = AuditScope::new("idm_account_app_password_remove");
+        let res = audit_segment!(&mut audit, || {
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = self.idms.proxy_write(ct.clone());
+            idms_prox_write.expire_mfareg_sessions(ct);
+
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let apre = AppPasswordRemoveEvent::from_parts(
+                &mut audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                msg.label,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin idm_account_app_password_remove: {:?}",
+                    e
+                );
+                e
+            })?;
+
+            idms_prox_write
+                .remove_app_password(&mut audit, &apre)
+                .and_then(|_| idms_prox_write.commit(&mut audit))
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<InternalCredentialLockMessage> for QueryServerWriteV1 {
+    type Result = Result<(), OperationError>;
+
+    fn handle(
+        &mut self,
+        msg: InternalCredentialLockMessage,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let mut audit = AuditScope::new("idm_account_credential_lock");
+        let res = audit_segment!(&mut audit, || {
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = self.idms.proxy_write(ct.clone());
+            idms_prox_write.expire_mfareg_sessions(ct);
+
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let cle = CredentialLockEvent::from_parts(
+                &mut audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                msg.cred_id,
+                msg.locked,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin idm_account_credential_lock: {:?}",
+                    e
+                );
+                e
+            })?;
+
+            idms_prox_write
+                .lock_credential(&mut audit, &cle)
+                .and_then(|_| idms_prox_write.commit(&mut audit))
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<InternalCredentialRemoveMessage> for QueryServerWriteV1 {
+    type Result = Result<(), OperationError>;
+
+    fn handle(
+        &mut self,
+        msg: InternalCredentialRemoveMessage,
+        _: &mut Self::Context,
+    ) -> Self::Result {
+        let mut audit = AuditScope::new("idm_account_credential_remove");
+        let res = audit_segment!(&mut audit, || {
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = self.idms.proxy_write(ct.clone());
+            idms_prox_write.expire_mfareg_sessions(ct);
+
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let cre = CredentialRemoveEvent::from_parts(
+                &mut audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                msg.cred_id,
+            )
+            .map_err(|e| {
+                audit_log!(
+                    audit,
+                    "Failed to begin idm_account_credential_remove: {:?}",
+                    e
+                );
+                e
+            })?;
+
+            idms_prox_write
+                .remove_credential(&mut audit, &cre)
+                .and_then(|_| idms_prox_write.commit(&mut audit))
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<InternalSessionRevokeMessage> for QueryServerWriteV1 {
+    type Result = Result<(), OperationError>;
+
+    fn handle(&mut self, msg: InternalSessionRevokeMessage, _: &mut Self::Context) -> Self::Result {
+        let mut audit = AuditScope::new("idm_account_session_revoke");
+        let res = audit_segment!(&mut audit, || {
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = self.idms.proxy_write(ct.clone());
+            idms_prox_write.expire_mfareg_sessions(ct);
+
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => idms_prox_write
+                    .qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let sre = SessionRevokeEvent::from_parts(
+                &mut audit,
+                &mut idms_prox_write.qs_write,
+                msg.uat,
+                target_uuid,
+                msg.id,
+            )
+            .map_err(|e| {
+                audit_log!(audit, "Failed to begin idm_account_session_revoke: {:?}", e);
+                e
+            })?;
+
+            idms_prox_write
+                .revoke_sessions(&mut audit, &sre)
+                .and_then(|_| idms_prox_write.commit(&mut audit))
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<InternalUnlockAccountMessage> for QueryServerWriteV1 {
+    type Result = Result<(), OperationError>;
+
+    fn handle(&mut self, msg: InternalUnlockAccountMessage, _: &mut Self::Context) -> Self::Result {
+        let mut audit = AuditScope::new("idm_account_unlock");
+        let res = audit_segment!(&mut audit, || {
+            // Softlocks are held by the auth side of the idm server, not the db.
+            let mut idm_write = self.idms.write();
+
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => idm_write
+                    .qs_read
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let uae = UnlockAccountEvent::from_parts(
+                &mut audit,
+                &mut idm_write.qs_read,
+                msg.uat,
+                target_uuid,
+            )
+            .map_err(|e| {
+                audit_log!(audit, "Failed to begin idm_account_unlock: {:?}", e);
+                e
+            })?;
+
+            idm_write
+                .unlock_account(&mut audit, &uae)
+                .and_then(|_| idm_write.commit())
+        });
+        self.log.do_send(audit);
+        res
+    }
+}
+
+impl Handler<PurgeAttributeMessage> for QueryServerWriteV1 {
+    type Result = Result<(), OperationError>;
+
+    fn handle(&mut self, msg: PurgeAttributeMessage, _: &mut Self::Context) -> Self::Result {
+        let mut audit = AuditScope::new("purge_attribute");
+        let res = audit_segment!(&mut audit, || {
+            let mut qs_write = self.qs.write(duration_from_epoch_now());
+            let target_uuid = match Uuid::parse_str(msg.uuid_or_name.as_str()) {
+                Ok(u) => u,
+                Err(_) => qs_write
+                    .name_to_uuid(&mut audit, msg.uuid_or_name.as_str())
+                    .map_err(|e| {
+                        audit_log!(&mut audit, "Error resolving id to target");
+                        e
+                    })?,
+            };
+
+            let mdf = match ModifyEvent::from_target_uuid_attr_purge(
+                &mut audit,
+                msg.uat,
+                target_uuid,
+                msg.attr,
+                msg.filter,
+                &mut qs_write,
+            ) {
+                Ok(m) => m,
+                Err(e) => {
+                    audit_log!(audit, "Failed to begin modify: {:?}", e);
+                    return Err(e);
+                }
+            };
+
+            audit_log!(audit, "Begin modify event {:?}", mdf);
+
+            qs_write
                 .modify(&mut audit, &mdf)
                 .and_then(|_| qs_write.commit(&mut audit).map(|_| ()))
         });
@@ -1087,3 +1715,44 @@ impl Handler<PurgeRecycledEvent> for QueryServerWriteV1 {
         self.log.do_send(audit);
     }
 }
+
+impl Handler<PurgeExpiredAcpEvent> for QueryServerWriteV1 {
+    type Result = ();
+
+    fn handle(&mut self, msg: PurgeExpiredAcpEvent, _: &mut Self::Context) -> Self::Result {
+        let mut audit = AuditScope::new("purge expired acps");
+        audit_segment!(&mut audit, || {
+            audit_log!(audit, "Begin purge expired acps event {:?}", msg);
+            let mut qs_write = self.qs.write(duration_from_epoch_now());
+
+            let res = qs_write
+                .purge_expired_acps(&mut audit)
+                .and_then(|_| qs_write.commit(&mut audit));
+            audit_log!(audit, "Purge expired acps result: {:?}", res);
+            res.expect("Invalid Server State");
+        });
+        // At the end of the event we send it for logging.
+        self.log.do_send(audit);
+    }
+}
+
+impl Handler<RotateJwsKeysEvent> for QueryServerWriteV1 {
+    type Result = ();
+
+    fn handle(&mut self, msg: RotateJwsKeysEvent, _: &mut Self::Context) -> Self::Result {
+        let mut audit = AuditScope::new("rotate jws keys");
+        audit_segment!(&mut audit, || {
+            audit_log!(audit, "Begin rotate jws keys event {:?}", msg);
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = self.idms.proxy_write(ct);
+
+            let res = idms_prox_write
+                .rotate_jws_keys(&mut audit, ct)
+                .and_then(|_| idms_prox_write.commit(&mut audit));
+            // The current keys remain valid, so this is retried on the next interval.
+            audit_log!(audit, "Rotate jws keys result: {:?}", res);
+        });
+        // At the end of the event we send it for logging.
+        self.log.do_send(audit);
+    }
+}
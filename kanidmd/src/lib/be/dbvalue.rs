@@ -1,5 +1,6 @@
 use std::time::Duration;
 use uuid::Uuid;
+use webauthn_rs::crypto::COSEKey;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DbCidV1 {
@@ -8,9 +9,18 @@ pub struct DbCidV1 {
     pub t: Duration,
 }
 
+#[allow(non_camel_case_types)]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DbPasswordV1 {
     PBKDF2(usize, Vec<u8>, Vec<u8>),
+    //       m_cost, t_cost, p_cost, salt, hash
+    ARGON2ID(u32, u32, u32, Vec<u8>, Vec<u8>),
+    PBKDF2_SHA512(usize, Vec<u8>, Vec<u8>),
+    //    salt,    hash
+    SSHA1(Vec<u8>, Vec<u8>),
+    SSHA512(Vec<u8>, Vec<u8>),
+    CRYPT_SHA512(String),
+    BCRYPT(String),
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -26,14 +36,38 @@ pub struct DbTotpV1 {
     pub k: Vec<u8>,
     pub s: u64,
     pub a: DbTotpAlgoV1,
+    // The last time step a code was accepted for.
+    pub u: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbWebauthnV1 {
+    pub l: String,
+    pub i: Vec<u8>,
+    pub c: COSEKey,
+    pub t: u32,
+    pub v: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct DbCredV1 {
     pub password: Option<DbPasswordV1>,
+    // Seconds since the unix epoch that the password was set, if known.
+    pub password_set: Option<u64>,
+    // Previous passwords, most recent first.
+    pub password_history: Option<Vec<DbPasswordV1>>,
+    // Credentials from before several totps were allowed have their only totp here.
     pub totp: Option<DbTotpV1>,
+    pub totps: Option<Vec<DbTotpV1>>,
+    pub webauthn: Option<Vec<DbWebauthnV1>>,
+    // Hashes of the unused backup codes.
+    pub backup_codes: Option<Vec<DbPasswordV1>>,
     pub claims: Vec<String>,
+    // Only present on application passwords.
+    pub application: Option<String>,
     pub uuid: Uuid,
+    // Credentials from before they could be locked are never locked.
+    pub locked: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -48,6 +82,22 @@ pub struct DbValueTaggedStringV1 {
     pub d: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbJwsKeyV1 {
+    pub k: Uuid,
+    pub i: u64,
+    pub d: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbSessionV1 {
+    pub u: Uuid,
+    pub i: u64,
+    pub e: u64,
+    pub s: Option<String>,
+    pub m: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum DbValueV1 {
     U8(String),
@@ -64,6 +114,9 @@ pub enum DbValueV1 {
     SP(String, String),
     UI(u32),
     CI(DbCidV1),
+    IS(i64),
+    JK(DbJwsKeyV1),
+    SE(DbSessionV1),
 }
 
 #[cfg(test)]
@@ -1,3 +1,4 @@
+use crate::credential::CryptoPolicy;
 use rand::prelude::*;
 use std::fmt;
 use std::path::PathBuf;
@@ -25,6 +26,12 @@ pub struct Configuration {
     pub tls_config: Option<TlsConfiguration>,
     pub cookie_key: [u8; 32],
     pub integration_test_config: Option<Box<IntegrationTestConfig>>,
+    pub purge_expired_acp: bool,
+    // The url that users reach the server on, that webauthn is bound to.
+    pub origin: String,
+    // The argon2id parameters for new password hashes. If None, they are chosen at
+    // startup for this machine.
+    pub crypto_policy: Option<CryptoPolicy>,
 }
 
 impl fmt::Display for Configuration {
@@ -35,6 +42,9 @@ impl fmt::Display for Configuration {
             .and_then(|_| write!(f, "max request size: {}b, ", self.maximum_request))
             .and_then(|_| write!(f, "secure cookies: {}, ", self.secure_cookies))
             .and_then(|_| write!(f, "with TLS: {}, ", self.tls_config.is_some()))
+            .and_then(|_| write!(f, "purge expired acps: {}, ", self.purge_expired_acp))
+            .and_then(|_| write!(f, "origin: {}, ", self.origin))
+            .and_then(|_| write!(f, "crypto policy: {:?}, ", self.crypto_policy))
             .and_then(|_| {
                 write!(
                     f,
@@ -59,6 +69,9 @@ impl Configuration {
             tls_config: None,
             cookie_key: [0; 32],
             integration_test_config: None,
+            purge_expired_acp: false,
+            origin: String::from("https://localhost:8080"),
+            crypto_policy: None,
         };
         let mut rng = StdRng::from_entropy();
         rng.fill(&mut c.cookie_key);
@@ -82,6 +95,38 @@ impl Configuration {
             .unwrap_or_else(|| String::from("127.0.0.1:8080"));
     }
 
+    pub fn update_purge_expired_acp(&mut self, p: bool) {
+        self.purge_expired_acp = p;
+    }
+
+    pub fn update_origin(&mut self, o: &Option<String>) {
+        if let Some(o) = o {
+            self.origin = o.clone();
+        }
+    }
+
+    pub fn update_crypto_policy(
+        &mut self,
+        m_cost: &Option<u32>,
+        t_cost: &Option<u32>,
+        p_cost: &Option<u32>,
+    ) {
+        match (m_cost, t_cost, p_cost) {
+            (None, None, None) => {}
+            (Some(m), Some(t), Some(p)) => match CryptoPolicy::argon2id(*m, *t, *p) {
+                Ok(cp) => self.crypto_policy = Some(cp),
+                Err(_) => {
+                    error!("Invalid argon2 parameters - costs must be non-zero, and m_cost >= 8 * p_cost!");
+                    std::process::exit(1);
+                }
+            },
+            _ => {
+                error!("Invalid argon2 configuration - must provide m_cost, t_cost and p_cost!");
+                std::process::exit(1);
+            }
+        }
+    }
+
     pub fn update_tls(
         &mut self,
         ca: &Option<PathBuf>,
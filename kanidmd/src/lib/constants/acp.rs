@@ -95,9 +95,14 @@ pub const JSON_IDM_SELF_ACP_READ_V1: &str = r#"{
             "class",
             "memberof",
             "radius_secret",
+            "primary_credential",
+            "app_password",
+            "session",
             "gidnumber",
             "loginshell",
-            "uuid"
+            "uuid",
+            "account_valid_from",
+            "account_expire"
         ]
     }
 }"#;
@@ -115,10 +120,10 @@ pub const JSON_IDM_SELF_ACP_WRITE_V1: &str = r#"{
             "\"Self\""
         ],
         "acp_modify_removedattr": [
-            "name", "displayname", "legalname", "radius_secret", "primary_credential", "ssh_publickey", "unix_password"
+            "name", "displayname", "legalname", "radius_secret", "primary_credential", "app_password", "session", "ssh_publickey", "unix_password"
         ],
         "acp_modify_presentattr": [
-            "name", "displayname", "legalname", "radius_secret", "primary_credential", "ssh_publickey", "unix_password"
+            "name", "displayname", "legalname", "radius_secret", "primary_credential", "app_password", "ssh_publickey", "unix_password"
         ]
     }
 }"#;
@@ -146,7 +151,9 @@ pub const JSON_IDM_ALL_ACP_READ_V1: &str = r#"{
             "uuid",
             "gidnumber",
             "loginshell",
-            "ssh_publickey"
+            "ssh_publickey",
+            "account_valid_from",
+            "account_expire"
         ]
     }
 }"#;
@@ -169,7 +176,7 @@ pub const JSON_IDM_ACP_PEOPLE_READ_PRIV_V1: &str = r#"{
             "{\"And\": [{\"Eq\": [\"class\",\"account\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_search_attr": [
-            "name", "displayname", "legalname", "mail"
+            "name", "displayname", "legalname", "mail", "manager"
         ]
     }
 }"#;
@@ -191,10 +198,10 @@ pub const JSON_IDM_ACP_PEOPLE_WRITE_PRIV_V1: &str = r#"{
             "{\"And\": [{\"Eq\": [\"class\",\"person\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_modify_removedattr": [
-            "name", "displayname", "legalname", "mail"
+            "name", "displayname", "legalname", "mail", "manager"
         ],
         "acp_modify_presentattr": [
-            "name", "displayname", "legalname", "mail"
+            "name", "displayname", "legalname", "mail", "manager"
         ]
     }
 }"#;
@@ -304,13 +311,13 @@ pub const JSON_IDM_ACP_GROUP_WRITE_PRIV_V1: &str = r#"{
             "{\"And\": [{\"Eq\": [\"class\",\"group\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_search_attr": [
-            "class", "name", "spn", "uuid", "description", "member"
+            "class", "name", "spn", "uuid", "description", "member", "owner"
         ],
         "acp_modify_removedattr": [
-            "name", "description", "member"
+            "name", "description", "member", "owner"
         ],
         "acp_modify_presentattr": [
-            "name", "description", "member"
+            "name", "description", "member", "owner"
         ]
     }
 }"#;
@@ -332,7 +339,7 @@ pub const JSON_IDM_ACP_ACCOUNT_READ_PRIV_V1: &str = r#"{
             "{\"And\": [{\"Eq\": [\"class\",\"account\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_search_attr": [
-            "class", "name", "spn", "uuid", "displayname", "ssh_publickey", "primary_credential", "memberof", "mail", "gidnumber"
+            "class", "name", "spn", "uuid", "displayname", "ssh_publickey", "primary_credential", "app_password", "session", "memberof", "mail", "gidnumber", "account_valid_from", "account_expire"
         ]
     }
 }"#;
@@ -354,10 +361,10 @@ pub const JSON_IDM_ACP_ACCOUNT_WRITE_PRIV_V1: &str = r#"{
             "{\"And\": [{\"Eq\": [\"class\",\"account\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_modify_removedattr": [
-            "name", "displayname", "ssh_publickey", "primary_credential", "mail"
+            "name", "displayname", "ssh_publickey", "primary_credential", "app_password", "credential_reset_token", "session", "mail", "account_valid_from", "account_expire"
         ],
         "acp_modify_presentattr": [
-            "name", "displayname", "ssh_publickey", "primary_credential", "mail"
+            "name", "displayname", "ssh_publickey", "primary_credential", "app_password", "credential_reset_token", "mail", "account_valid_from", "account_expire"
         ]
     }
 }"#;
@@ -386,7 +393,9 @@ pub const JSON_IDM_ACP_ACCOUNT_MANAGE_PRIV_V1: &str = r#"{
             "description",
             "primary_credential",
             "ssh_publickey",
-            "mail"
+            "mail",
+            "account_valid_from",
+            "account_expire"
         ],
         "acp_create_class": [
             "object", "account"
@@ -413,7 +422,7 @@ pub const JSON_IDM_ACP_RADIUS_SERVERS_V1: &str = r#"{
             "{\"And\": [{\"Pres\": \"class\"}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_search_attr": [
-            "name", "spn", "uuid", "radius_secret"
+            "name", "spn", "uuid", "radius_secret", "account_valid_from", "account_expire"
         ]
     }
 }"#;
@@ -435,7 +444,7 @@ pub const JSON_IDM_ACP_HP_ACCOUNT_READ_PRIV_V1: &str = r#"{
             "{\"And\": [{\"Eq\": [\"class\",\"account\"]}, {\"Eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_search_attr": [
-            "class", "name", "spn", "uuid", "displayname", "ssh_publickey", "primary_credential", "memberof"
+            "class", "name", "spn", "uuid", "displayname", "ssh_publickey", "primary_credential", "app_password", "session", "memberof", "account_valid_from", "account_expire"
         ]
     }
 }"#;
@@ -457,10 +466,10 @@ pub const JSON_IDM_ACP_HP_ACCOUNT_WRITE_PRIV_V1: &str = r#"{
             "{\"And\": [{\"Eq\": [\"class\",\"account\"]}, {\"Eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
         ],
         "acp_modify_removedattr": [
-            "name", "displayname", "ssh_publickey", "primary_credential"
+            "name", "displayname", "ssh_publickey", "primary_credential", "app_password", "credential_reset_token", "session", "account_valid_from", "account_expire"
         ],
         "acp_modify_presentattr": [
-            "name", "displayname", "ssh_publickey", "primary_credential"
+            "name", "displayname", "ssh_publickey", "primary_credential", "app_password", "credential_reset_token", "account_valid_from", "account_expire"
         ]
     }
 }"#;
@@ -578,14 +587,20 @@ pub const JSON_IDM_ACP_ACP_MANAGE_PRIV_V1: &str = r#"{
         "acp_search_attr": [
             "name",
             "class",
+            "uuid",
             "description",
             "acp_enable",
+            "acp_deny",
+            "acp_valid_from",
+            "acp_valid_until",
             "acp_receiver",
             "acp_targetscope",
             "acp_search_attr",
             "acp_modify_removedattr",
             "acp_modify_presentattr",
             "acp_modify_class",
+            "acp_modify_presentvalue",
+            "acp_modify_presentrefer",
             "acp_create_class",
             "acp_create_attr"
         ],
@@ -594,12 +609,16 @@ pub const JSON_IDM_ACP_ACP_MANAGE_PRIV_V1: &str = r#"{
             "class",
             "description",
             "acp_enable",
+            "acp_valid_from",
+            "acp_valid_until",
             "acp_receiver",
             "acp_targetscope",
             "acp_search_attr",
             "acp_modify_removedattr",
             "acp_modify_presentattr",
             "acp_modify_class",
+            "acp_modify_presentvalue",
+            "acp_modify_presentrefer",
             "acp_create_class",
             "acp_create_attr"
         ],
@@ -608,12 +627,16 @@ pub const JSON_IDM_ACP_ACP_MANAGE_PRIV_V1: &str = r#"{
             "class",
             "description",
             "acp_enable",
+            "acp_valid_from",
+            "acp_valid_until",
             "acp_receiver",
             "acp_targetscope",
             "acp_search_attr",
             "acp_modify_removedattr",
             "acp_modify_presentattr",
             "acp_modify_class",
+            "acp_modify_presentvalue",
+            "acp_modify_presentrefer",
             "acp_create_class",
             "acp_create_attr"
         ],
@@ -629,16 +652,21 @@ pub const JSON_IDM_ACP_ACP_MANAGE_PRIV_V1: &str = r#"{
             "class",
             "description",
             "acp_enable",
+            "acp_valid_from",
+            "acp_valid_until",
             "acp_receiver",
             "acp_targetscope",
             "acp_search_attr",
             "acp_modify_removedattr",
             "acp_modify_presentattr",
             "acp_modify_class",
+            "acp_modify_presentvalue",
+            "acp_modify_presentrefer",
             "acp_create_class",
             "acp_create_attr"
         ],
         "acp_create_class": [
+            "object",
             "access_control_profile",
             "access_control_search",
             "access_control_modify",
@@ -674,19 +702,24 @@ pub const JSON_IDM_ACP_SCHEMA_WRITE_CLASSES_PRIV_V1: &str = r#"{
             "may",
             "systemmust",
             "must",
+            "classkind",
+            "systemexcludes",
+            "excludes",
             "uuid"
         ],
         "acp_modify_removedattr": [
             "class",
             "description",
             "may",
-            "must"
+            "must",
+            "excludes"
         ],
         "acp_modify_presentattr": [
             "class",
             "description",
             "may",
-            "must"
+            "must",
+            "excludes"
         ],
         "acp_modify_class":  [],
         "acp_create_attr": [
@@ -695,6 +728,8 @@ pub const JSON_IDM_ACP_SCHEMA_WRITE_CLASSES_PRIV_V1: &str = r#"{
             "classname",
             "may",
             "must",
+            "classkind",
+            "excludes",
             "uuid"
         ],
         "acp_create_class": [
@@ -727,7 +762,8 @@ pub const JSON_IDM_ACP_GROUP_MANAGE_PRIV_V1: &str = r#"{
             "class",
             "name",
             "description",
-            "member"
+            "member",
+            "owner"
         ],
         "acp_create_class": [
             "object", "group"
@@ -759,7 +795,9 @@ pub const JSON_IDM_ACP_HP_ACCOUNT_MANAGE_PRIV_V1: &str = r#"{
             "displayname",
             "description",
             "primary_credential",
-            "ssh_publickey"
+            "ssh_publickey",
+            "account_valid_from",
+            "account_expire"
         ],
         "acp_create_class": [
             "object", "account"
@@ -921,3 +959,174 @@ pub const JSON_IDM_ACP_GROUP_UNIX_EXTEND_PRIV_V1: &str = r#"{
         "acp_modify_class": ["posixgroup"]
     }
 }"#;
+
+// 33 - schema unique constraint management
+pub const JSON_IDM_ACP_SCHEMA_WRITE_UNIQUE_CONSTRAINTS_PRIV_V1: &str = r#"{
+    "attrs": {
+        "class": [
+            "object",
+            "access_control_profile",
+            "access_control_search",
+            "access_control_modify",
+            "access_control_create",
+            "access_control_delete"
+        ],
+        "name": ["idm_acp_schema_write_unique_constraints_priv"],
+        "uuid": ["00000000-0000-0000-0000-ffffff000033"],
+        "description": ["Builtin IDM Control for management of schema unique constraints."],
+        "acp_receiver": [
+            "{\"Eq\":[\"memberof\",\"00000000-0000-0000-0000-000000000010\"]}"
+        ],
+        "acp_targetscope": [
+            "{\"And\": [{\"Eq\": [\"class\",\"uniqueconstraint\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
+        ],
+        "acp_search_attr": [
+            "class",
+            "name",
+            "description",
+            "uniqueattr",
+            "uniquescope",
+            "uuid"
+        ],
+        "acp_modify_removedattr": [
+            "description",
+            "uniqueattr",
+            "uniquescope"
+        ],
+        "acp_modify_presentattr": [
+            "description",
+            "uniqueattr",
+            "uniquescope"
+        ],
+        "acp_modify_class":  [],
+        "acp_create_attr": [
+            "class",
+            "name",
+            "description",
+            "uniqueattr",
+            "uniquescope",
+            "uuid"
+        ],
+        "acp_create_class": [
+            "object", "uniqueconstraint"
+        ]
+    }
+}"#;
+
+// 34 - Only system admins may change the membership of, or delete, system_admins. This is
+// a system object, so that an access profile manager can't disable or remove it.
+pub const JSON_IDM_ACP_DENY_SYSTEM_ADMINS_MANAGE_V1: &str = r#"{
+    "attrs": {
+        "class": [
+            "object",
+            "system",
+            "access_control_profile",
+            "access_control_modify",
+            "access_control_delete"
+        ],
+        "name": ["idm_acp_deny_system_admins_manage"],
+        "uuid": ["00000000-0000-0000-0000-ffffff000034"],
+        "description": ["Builtin IDM Control denying changes to system_admins by anyone who is not a system admin."],
+        "acp_deny": ["true"],
+        "acp_receiver": [
+            "{\"And\": [{\"Pres\": \"class\"}, {\"AndNot\": {\"Eq\": [\"memberof\",\"00000000-0000-0000-0000-000000000019\"]}}]}"
+        ],
+        "acp_targetscope": [
+            "{\"Eq\":[\"uuid\",\"00000000-0000-0000-0000-000000000019\"]}"
+        ],
+        "acp_modify_removedattr": ["member"],
+        "acp_modify_presentattr": ["member"]
+    }
+}"#;
+
+// 35 - Managers may see and update the displayname of the people who report to them.
+pub const JSON_IDM_ACP_MANAGER_WRITE_REPORTS_V1: &str = r#"{
+    "attrs": {
+        "class": [
+            "object",
+            "access_control_profile",
+            "access_control_search",
+            "access_control_modify"
+        ],
+        "name": ["idm_acp_manager_write_reports"],
+        "uuid": ["00000000-0000-0000-0000-ffffff000035"],
+        "description": ["Builtin IDM Control allowing managers to update their reports."],
+        "acp_receiver": [
+            "{\"Eq\":[\"class\",\"account\"]}"
+        ],
+        "acp_targetscope": [
+            "{\"And\": [{\"SelfRef\": \"manager\"}, {\"Eq\": [\"class\",\"person\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
+        ],
+        "acp_search_attr": [
+            "class", "name", "spn", "uuid", "displayname", "manager"
+        ],
+        "acp_modify_removedattr": [
+            "displayname"
+        ],
+        "acp_modify_presentattr": [
+            "displayname"
+        ]
+    }
+}"#;
+
+// 36 - Owners of a group may see and change its membership.
+pub const JSON_IDM_ACP_GROUP_OWNER_MANAGE_MEMBERS_V1: &str = r#"{
+    "attrs": {
+        "class": [
+            "object",
+            "access_control_profile",
+            "access_control_search",
+            "access_control_modify"
+        ],
+        "name": ["idm_acp_group_owner_manage_members"],
+        "uuid": ["00000000-0000-0000-0000-ffffff000036"],
+        "description": ["Builtin IDM Control allowing group owners to manage membership."],
+        "acp_receiver": [
+            "{\"Eq\":[\"class\",\"account\"]}"
+        ],
+        "acp_targetscope": [
+            "{\"And\": [{\"SelfRef\": \"owner\"}, {\"Eq\": [\"class\",\"group\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"memberof\",\"00000000-0000-0000-0000-000000001000\"]}, {\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
+        ],
+        "acp_search_attr": [
+            "class", "name", "spn", "uuid", "description", "member", "owner"
+        ],
+        "acp_modify_removedattr": [
+            "member"
+        ],
+        "acp_modify_presentattr": [
+            "member"
+        ]
+    }
+}"#;
+
+// 37 - group password policy
+pub const JSON_IDM_ACP_GROUP_PASSWORD_POLICY_PRIV_V1: &str = r#"{
+    "attrs": {
+        "class": [
+            "object",
+            "access_control_profile",
+            "access_control_search",
+            "access_control_modify"
+        ],
+        "name": ["idm_acp_group_password_policy_priv"],
+        "uuid": ["00000000-0000-0000-0000-ffffff000037"],
+        "description": ["Builtin IDM Control for managing the password policies of groups"],
+        "acp_receiver": [
+            "{\"Eq\":[\"memberof\",\"00000000-0000-0000-0000-000000000019\"]}"
+        ],
+        "acp_targetscope": [
+            "{\"And\": [{\"Eq\": [\"class\",\"group\"]}, {\"AndNot\": {\"Or\": [{\"Eq\": [\"class\", \"tombstone\"]}, {\"Eq\": [\"class\", \"recycled\"]}]}}]}"
+        ],
+        "acp_search_attr": [
+            "class", "name", "spn", "uuid", "description", "member",
+            "pw_min_length", "pw_min_score", "pw_badlist", "pw_require_mfa", "pw_max_age"
+        ],
+        "acp_modify_removedattr": [
+            "class", "pw_min_length", "pw_min_score", "pw_badlist", "pw_require_mfa", "pw_max_age"
+        ],
+        "acp_modify_presentattr": [
+            "class", "pw_min_length", "pw_min_score", "pw_badlist", "pw_require_mfa", "pw_max_age"
+        ],
+        "acp_modify_class": ["password_policy"]
+    }
+}"#;
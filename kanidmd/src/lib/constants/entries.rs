@@ -255,6 +255,16 @@ pub const JSON_IDM_ACP_MANAGE_PRIV_V1: &str = r#"{
         "member": ["00000000-0000-0000-0000-000000000019"]
     }
 }"#;
+// * ACP explain
+pub const JSON_IDM_ACP_EXPLAIN_PRIV_V1: &str = r#"{
+    "attrs": {
+        "class": ["group", "object"],
+        "name": ["idm_acp_explain_priv"],
+        "uuid": ["00000000-0000-0000-0000-000000000025"],
+        "description": ["Builtin IDM Group for granting the ability to explain which access control profiles apply to an operation."],
+        "member": ["00000000-0000-0000-0000-000000000019"]
+    }
+}"#;
 
 pub const JSON_IDM_HP_GROUP_MANAGE_PRIV_V1: &str = r#"{
     "attrs": {
@@ -317,6 +327,7 @@ pub const JSON_IDM_HIGH_PRIVILEGE_V1: &str = r#"{
             "00000000-0000-0000-0000-000000000020",
             "00000000-0000-0000-0000-000000000023",
             "00000000-0000-0000-0000-000000000024",
+            "00000000-0000-0000-0000-000000000025",
             "00000000-0000-0000-0000-000000001000"
         ]
     }
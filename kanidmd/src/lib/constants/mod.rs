@@ -13,7 +13,7 @@ pub use crate::constants::system_config::*;
 pub use crate::constants::uuids::*;
 
 // Increment this as we add new schema types and values!!!
-pub const SYSTEM_INDEX_VERSION: i64 = 6;
+pub const SYSTEM_INDEX_VERSION: i64 = 7;
 // On test builds, define to 60 seconds
 #[cfg(test)]
 pub const PURGE_FREQUENCY: u64 = 60;
@@ -39,4 +39,32 @@ pub const RECYCLEBIN_MAX_AGE: u64 = 604_800;
 pub const AUTH_SESSION_TIMEOUT: u64 = 300;
 // 5 minute mfa reg window
 pub const MFAREG_SESSION_TIMEOUT: u64 = 300;
+// A credential reset token can be redeemed for a day after it is issued.
+pub const CREDENTIAL_RESET_TOKEN_VALIDITY: u64 = 86400;
+// Re-authenticating raises a session to privileged for 5 minutes.
+pub const PRIVILEGED_CLAIM_LIFETIME: u64 = 300;
+// Issued to sessions that authenticated with the primary credentials of an account.
+pub const CLAIM_INTERACTIVE: &str = "interactive";
+// Issued for a short time when a session re-authenticates.
+pub const CLAIM_PRIVILEGED: &str = "privileged";
+// A bearer token is valid for an hour after authentication.
+pub const BEARER_TOKEN_VALIDITY: u64 = 3600;
+// A new key to sign bearer tokens is created each day.
+pub const JWS_KEY_ROTATION: u64 = 86400;
 pub const PW_MIN_LENGTH: usize = 10;
+// The minimum zxcvbn score of a password, when no password policy applies.
+pub const PW_MIN_SCORE: u8 = 3;
+// A new password can't be any of the last 5 passwords of a credential, including the current one.
+pub const PW_HISTORY_LEN: usize = 5;
+// How many backup codes are issued when a totp is registered, or they are regenerated.
+pub const BACKUP_CODE_COUNT: usize = 8;
+// Once fewer than this many backup codes remain, warn the user on each login.
+pub const BACKUP_CODE_WARN_THRESHOLD: usize = 3;
+// After this many failed authentications an account is softlocked.
+pub const SOFTLOCK_FAILURE_THRESHOLD: u32 = 3;
+// The first softlock lasts 2 seconds, and doubles with each further failure ...
+pub const SOFTLOCK_BASE_DELAY: u64 = 2;
+// ... up to an hour.
+pub const SOFTLOCK_MAX_DELAY: u64 = 3600;
+// Failures are forgotten after a day without any.
+pub const SOFTLOCK_RESET_WINDOW: u64 = 86400;
@@ -119,14 +119,14 @@ pub const JSON_SCHEMA_ATTR_PRIMARY_CREDENTIAL: &str = r#"
         "attributetype"
       ],
       "description": [
-        "Primary credential material of the account for authentication interactively."
+        "Primary credential material of the account for authentication interactively, by label."
       ],
       "index": [],
       "unique": [
         "false"
       ],
       "multivalue": [
-        "false"
+        "true"
       ],
       "attributename": [
         "primary_credential"
@@ -406,6 +406,398 @@ pub const JSON_SCHEMA_ATTR_UNIX_PASSWORD: &str = r#"{
     }
 }"#;
 
+pub const JSON_SCHEMA_ATTR_MANAGER: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The person who manages this person"
+      ],
+      "index": [
+        "EQUALITY"
+      ],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "manager"
+      ],
+      "syntax": [
+        "REFERENCE_UUID"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000084"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_OWNER: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The accounts who own and manage this group"
+      ],
+      "index": [
+        "EQUALITY"
+      ],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "owner"
+      ],
+      "syntax": [
+        "REFERENCE_UUID"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000085"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_ACCOUNT_VALID_FROM: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The time in seconds since the unix epoch from which this account may authenticate"
+      ],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "account_valid_from"
+      ],
+      "syntax": [
+        "INT64"
+      ],
+      "minvalue": [
+        "0"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000088"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_ACCOUNT_EXPIRE: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The time in seconds since the unix epoch from which this account may no longer authenticate"
+      ],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "account_expire"
+      ],
+      "syntax": [
+        "INT64"
+      ],
+      "minvalue": [
+        "0"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000089"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_PW_MIN_LENGTH: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The minimum length of a password set by a member of this password policy"
+      ],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "pw_min_length"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "minvalue": [
+        "1"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000090"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_PW_MIN_SCORE: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The minimum zxcvbn strength score (0 to 4) of a password set by a member of this password policy"
+      ],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "pw_min_score"
+      ],
+      "syntax": [
+        "UINT32"
+      ],
+      "minvalue": [
+        "0"
+      ],
+      "maxvalue": [
+        "4"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000091"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_PW_BADLIST: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "If passwords set by a member of this password policy are checked against the system badlist"
+      ],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "pw_badlist"
+      ],
+      "syntax": [
+        "BOOLEAN"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000092"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_PW_REQUIRE_MFA: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "If members of this password policy must have a second factor on their primary credential to authenticate"
+      ],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "pw_require_mfa"
+      ],
+      "syntax": [
+        "BOOLEAN"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000093"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_PW_MAX_AGE: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The number of seconds after being set that a password of a member of this password policy expires"
+      ],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "pw_max_age"
+      ],
+      "syntax": [
+        "INT64"
+      ],
+      "minvalue": [
+        "1"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000094"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_APP_PASSWORD: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "Application passwords of the account, each only valid for the application it names"
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "app_password"
+      ],
+      "syntax": [
+        "CREDENTIAL"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000096"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_CREDENTIAL_RESET_TOKEN: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "A single use token that allows the credentials of the account to be reset without authenticating"
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "false"
+      ],
+      "attributename": [
+        "credential_reset_token"
+      ],
+      "syntax": [
+        "CREDENTIAL"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000097"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_JWS_SIGNING_KEY: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The keys that bearer tokens of this domain are signed with"
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "jws_signing_key"
+      ],
+      "syntax": [
+        "JWS_KEY"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000098"
+      ]
+    }
+}"#;
+
+pub const JSON_SCHEMA_ATTR_SESSION: &str = r#"{
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "attributetype"
+      ],
+      "description": [
+        "The sessions that bearer tokens have been issued to the account for"
+      ],
+      "index": [],
+      "unique": [
+        "false"
+      ],
+      "multivalue": [
+        "true"
+      ],
+      "attributename": [
+        "session"
+      ],
+      "syntax": [
+        "SESSION"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000099"
+      ]
+    }
+}"#;
+
 pub const JSON_SCHEMA_CLASS_PERSON: &str = r#"
   {
     "valid": {
@@ -424,9 +816,13 @@ pub const JSON_SCHEMA_CLASS_PERSON: &str = r#"
       "classname": [
         "person"
       ],
+      "systemexcludes": [
+        "group"
+      ],
       "systemmay": [
         "mail",
-        "legalname"
+        "legalname",
+        "manager"
       ],
       "systemmust": [
         "displayname",
@@ -457,8 +853,12 @@ pub const JSON_SCHEMA_CLASS_GROUP: &str = r#"
       "classname": [
         "group"
       ],
+      "classkind": [
+        "structural"
+      ],
       "systemmay": [
-        "member"
+        "member",
+        "owner"
       ],
       "systemmust": [
         "name",
@@ -484,10 +884,18 @@ pub const JSON_SCHEMA_CLASS_ACCOUNT: &str = r#"
       "classname": [
         "account"
       ],
+      "classkind": [
+        "structural"
+      ],
       "systemmay": [
         "primary_credential",
+        "app_password",
+        "credential_reset_token",
+        "session",
         "ssh_publickey",
-        "radius_secret"
+        "radius_secret",
+        "account_valid_from",
+        "account_expire"
       ],
       "systemmust": [
         "displayname",
@@ -520,8 +928,12 @@ pub const JSON_SCHEMA_CLASS_DOMAIN_INFO: &str = r#"
       "classname": [
         "domain_info"
       ],
+      "classkind": [
+        "structural"
+      ],
       "systemmay": [
-        "domain_ssid"
+        "domain_ssid",
+        "jws_signing_key"
       ],
       "systemmust": [
         "name",
@@ -573,6 +985,9 @@ pub const JSON_SCHEMA_CLASS_POSIXACCOUNT: &str = r#"
       "classname": [
         "posixaccount"
       ],
+      "systemexcludes": [
+        "posixgroup"
+      ],
       "systemmay": [
         "loginshell",
         "unix_password"
@@ -601,6 +1016,9 @@ pub const JSON_SCHEMA_CLASS_SYSTEM_CONFIG: &str = r#"
       "classname": [
         "system_config"
       ],
+      "classkind": [
+        "structural"
+      ],
       "systemmay": [
         "description",
         "badlist_password"
@@ -611,3 +1029,31 @@ pub const JSON_SCHEMA_CLASS_SYSTEM_CONFIG: &str = r#"
     }
   }
 "#;
+
+pub const JSON_SCHEMA_CLASS_PASSWORD_POLICY: &str = r#"
+  {
+    "attrs": {
+      "class": [
+        "object",
+        "system",
+        "classtype"
+      ],
+      "description": [
+        "Password requirements applied to the members of a group, requires group"
+      ],
+      "classname": [
+        "password_policy"
+      ],
+      "systemmay": [
+        "pw_min_length",
+        "pw_min_score",
+        "pw_badlist",
+        "pw_require_mfa",
+        "pw_max_age"
+      ],
+      "uuid": [
+        "00000000-0000-0000-0000-ffff00000095"
+      ]
+    }
+  }
+"#;
@@ -28,6 +28,7 @@ pub const _UUID_IDM_GROUP_UNIX_EXTEND_PRIV: &str = "00000000-0000-0000-0000-0000
 pub const _UUID_IDM_PEOPLE_ACCOUNT_PASSWORD_IMPORT_PRIV: &str =
     "00000000-0000-0000-0000-000000000023";
 pub const _UUID_IDM_PEOPLE_EXTEND_PRIV: &str = "00000000-0000-0000-0000-000000000024";
+pub const UUID_IDM_ACP_EXPLAIN_PRIV: &str = "00000000-0000-0000-0000-000000000025";
 //
 pub const _UUID_IDM_HIGH_PRIVILEGE: &str = "00000000-0000-0000-0000-000000001000";
 
@@ -101,6 +102,39 @@ pub const UUID_SCHEMA_ATTR_LAST_MOD_CID: &str = "00000000-0000-0000-0000-ffff000
 pub const UUID_SCHEMA_ATTR_PHANTOM: &str = "00000000-0000-0000-0000-ffff00000064";
 pub const UUID_SCHEMA_ATTR_CLAIM: &str = "00000000-0000-0000-0000-ffff00000065";
 pub const UUID_SCHEMA_ATTR_PASSWORD_IMPORT: &str = "00000000-0000-0000-0000-ffff00000066";
+pub const UUID_SCHEMA_ATTR_MINVALUE: &str = "00000000-0000-0000-0000-ffff00000067";
+pub const UUID_SCHEMA_ATTR_MAXVALUE: &str = "00000000-0000-0000-0000-ffff00000068";
+pub const UUID_SCHEMA_ATTR_REGEX: &str = "00000000-0000-0000-0000-ffff00000069";
+pub const UUID_SCHEMA_ATTR_MINLENGTH: &str = "00000000-0000-0000-0000-ffff00000070";
+pub const UUID_SCHEMA_ATTR_MAXLENGTH: &str = "00000000-0000-0000-0000-ffff00000071";
+pub const UUID_SCHEMA_ATTR_ALLOWEDVALUE: &str = "00000000-0000-0000-0000-ffff00000072";
+pub const UUID_SCHEMA_ATTR_MINCARDINALITY: &str = "00000000-0000-0000-0000-ffff00000073";
+pub const UUID_SCHEMA_ATTR_MAXCARDINALITY: &str = "00000000-0000-0000-0000-ffff00000074";
+pub const UUID_SCHEMA_ATTR_CLASSKIND: &str = "00000000-0000-0000-0000-ffff00000075";
+pub const UUID_SCHEMA_ATTR_SYSTEMEXCLUDES: &str = "00000000-0000-0000-0000-ffff00000076";
+pub const UUID_SCHEMA_ATTR_EXCLUDES: &str = "00000000-0000-0000-0000-ffff00000077";
+pub const UUID_SCHEMA_ATTR_UNIQUEATTR: &str = "00000000-0000-0000-0000-ffff00000078";
+pub const UUID_SCHEMA_ATTR_UNIQUESCOPE: &str = "00000000-0000-0000-0000-ffff00000079";
+pub const UUID_SCHEMA_CLASS_UNIQUECONSTRAINT: &str = "00000000-0000-0000-0000-ffff00000080";
+pub const UUID_SCHEMA_ATTR_ACP_DENY: &str = "00000000-0000-0000-0000-ffff00000081";
+pub const UUID_SCHEMA_ATTR_ACP_VALID_FROM: &str = "00000000-0000-0000-0000-ffff00000082";
+pub const UUID_SCHEMA_ATTR_ACP_VALID_UNTIL: &str = "00000000-0000-0000-0000-ffff00000083";
+pub const UUID_SCHEMA_ATTR_MANAGER: &str = "00000000-0000-0000-0000-ffff00000084";
+pub const UUID_SCHEMA_ATTR_OWNER: &str = "00000000-0000-0000-0000-ffff00000085";
+pub const UUID_SCHEMA_ATTR_ACP_MODIFY_PRESENTVALUE: &str = "00000000-0000-0000-0000-ffff00000086";
+pub const UUID_SCHEMA_ATTR_ACP_MODIFY_PRESENTREFER: &str = "00000000-0000-0000-0000-ffff00000087";
+pub const UUID_SCHEMA_ATTR_ACCOUNT_VALID_FROM: &str = "00000000-0000-0000-0000-ffff00000088";
+pub const UUID_SCHEMA_ATTR_ACCOUNT_EXPIRE: &str = "00000000-0000-0000-0000-ffff00000089";
+pub const UUID_SCHEMA_ATTR_PW_MIN_LENGTH: &str = "00000000-0000-0000-0000-ffff00000090";
+pub const UUID_SCHEMA_ATTR_PW_MIN_SCORE: &str = "00000000-0000-0000-0000-ffff00000091";
+pub const UUID_SCHEMA_ATTR_PW_BADLIST: &str = "00000000-0000-0000-0000-ffff00000092";
+pub const UUID_SCHEMA_ATTR_PW_REQUIRE_MFA: &str = "00000000-0000-0000-0000-ffff00000093";
+pub const UUID_SCHEMA_ATTR_PW_MAX_AGE: &str = "00000000-0000-0000-0000-ffff00000094";
+pub const UUID_SCHEMA_CLASS_PASSWORD_POLICY: &str = "00000000-0000-0000-0000-ffff00000095";
+pub const UUID_SCHEMA_ATTR_APP_PASSWORD: &str = "00000000-0000-0000-0000-ffff00000096";
+pub const UUID_SCHEMA_ATTR_CREDENTIAL_RESET_TOKEN: &str = "00000000-0000-0000-0000-ffff00000097";
+pub const UUID_SCHEMA_ATTR_JWS_SIGNING_KEY: &str = "00000000-0000-0000-0000-ffff00000098";
+pub const UUID_SCHEMA_ATTR_SESSION: &str = "00000000-0000-0000-0000-ffff00000099";
 
 // System and domain infos
 // I'd like to strongly criticise william of the past for fucking up these allocations.
@@ -141,6 +175,14 @@ pub const _UUID_IDM_ACP_GROUP_UNIX_EXTEND_PRIV_V1: &str = "00000000-0000-0000-00
 pub const _UUID_IDM_ACP_PEOPLE_ACCOUNT_PASSWORD_IMPORT_PRIV_V1: &str =
     "00000000-0000-0000-0000-ffffff000031";
 pub const _UUID_IDM_ACP_PEOPLE_EXTEND_PRIV_V1: &str = "00000000-0000-0000-0000-ffffff000032";
+pub const _UUID_IDM_ACP_SCHEMA_WRITE_UNIQUE_CONSTRAINTS_PRIV_V1: &str =
+    "00000000-0000-0000-0000-ffffff000033";
+pub const _UUID_IDM_ACP_DENY_SYSTEM_ADMINS_MANAGE_V1: &str = "00000000-0000-0000-0000-ffffff000034";
+pub const _UUID_IDM_ACP_MANAGER_WRITE_REPORTS_V1: &str = "00000000-0000-0000-0000-ffffff000035";
+pub const _UUID_IDM_ACP_GROUP_OWNER_MANAGE_MEMBERS_V1: &str =
+    "00000000-0000-0000-0000-ffffff000036";
+pub const _UUID_IDM_ACP_GROUP_PASSWORD_POLICY_PRIV_V1: &str =
+    "00000000-0000-0000-0000-ffffff000037";
 
 // End of system ranges
 pub const STR_UUID_DOES_NOT_EXIST: &str = "00000000-0000-0000-0000-fffffffffffe";
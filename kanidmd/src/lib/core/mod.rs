@@ -1,33 +1,42 @@
 // use actix_files as fs;
 use actix::prelude::*;
 use actix_session::{CookieSession, Session};
+use actix_web::http::header;
 use actix_web::web::{self, Data, HttpResponse, Json, Path};
-use actix_web::{cookie, error, middleware, App, HttpServer};
+use actix_web::{cookie, error, middleware, App, HttpMessage, HttpRequest, HttpServer};
 
 use std::sync::Arc;
 use time::Duration;
 
 use crate::config::Configuration;
+use crate::constants::BEARER_TOKEN_VALIDITY;
 
 // SearchResult
 use crate::actors::v1_read::QueryServerReadV1;
 use crate::actors::v1_read::{
-    AuthMessage, IdmAccountUnixAuthMessage, InternalRadiusReadMessage,
+    AuthMessage, IdmAccountUnixAuthMessage, InternalAccessExplainMessage,
+    InternalAppPasswordReadMessage, InternalCredentialReadMessage, InternalRadiusReadMessage,
     InternalRadiusTokenReadMessage, InternalSearchMessage, InternalSearchRecycledMessage,
-    InternalSshKeyReadMessage, InternalSshKeyTagReadMessage, InternalUnixGroupTokenReadMessage,
-    InternalUnixUserTokenReadMessage, SearchMessage, WhoamiMessage,
+    InternalSessionReadMessage, InternalSshKeyReadMessage, InternalSshKeyTagReadMessage,
+    InternalTOTPReadMessage, InternalUnixGroupTokenReadMessage, InternalUnixUserTokenReadMessage,
+    SearchMessage, WhoamiMessage,
 };
 use crate::actors::v1_write::QueryServerWriteV1;
 use crate::actors::v1_write::{
-    AppendAttributeMessage, CreateMessage, DeleteMessage, IdmAccountPersonExtendMessage,
-    IdmAccountSetPasswordMessage, IdmAccountUnixExtendMessage, IdmAccountUnixSetCredMessage,
-    IdmGroupUnixExtendMessage, InternalCredentialSetMessage, InternalDeleteMessage,
-    InternalRegenerateRadiusMessage, InternalSshKeyCreateMessage, ModifyMessage,
-    PurgeAttributeMessage, RemoveAttributeValueMessage, ReviveRecycledMessage, SetAttributeMessage,
+    AppendAttributeMessage, CreateMessage, DeleteMessage, IdmAccountGenerateBackupCodesMessage,
+    IdmAccountPersonExtendMessage, IdmAccountSetPasswordMessage, IdmAccountUnixExtendMessage,
+    IdmAccountUnixSetCredMessage, IdmGroupUnixExtendMessage, InternalAppPasswordCreateMessage,
+    InternalAppPasswordRemoveMessage, InternalCredentialLockMessage,
+    InternalCredentialRemoveMessage, InternalCredentialResetMessage,
+    InternalCredentialResetTokenCreateMessage, InternalCredentialSetMessage, InternalDeleteMessage,
+    InternalRegenerateRadiusMessage, InternalSessionRevokeMessage, InternalSshKeyCreateMessage,
+    InternalUnlockAccountMessage, ModifyMessage, PurgeAttributeMessage,
+    RemoveAttributeValueMessage, ReviveRecycledMessage, SetAttributeMessage,
 };
 use crate::async_log;
 use crate::audit::AuditScope;
 use crate::be::{Backend, BackendTransaction};
+use crate::credential::CryptoPolicy;
 use crate::crypto::setup_tls;
 use crate::filter::{Filter, FilterInvalid};
 use crate::idm::server::IdmServer;
@@ -42,23 +51,43 @@ use crate::value::PartialValue;
 use kanidm_proto::v1::Entry as ProtoEntry;
 use kanidm_proto::v1::OperationError;
 use kanidm_proto::v1::{
-    AccountUnixExtend, AuthRequest, AuthState, CreateRequest, DeleteRequest, GroupUnixExtend,
-    ModifyRequest, SearchRequest, SetCredentialRequest, SingleStringRequest, UserAuthToken,
+    AccessExplainRequest, AccountUnixExtend, AuthRequest, AuthState, CreateRequest,
+    CredentialResetRequest, DeleteRequest, GroupUnixExtend, ModifyRequest, SearchRequest,
+    SetCredentialRequest, SingleStringRequest, UserAuthToken,
 };
 
 use uuid::Uuid;
 
+// Clients that can't set an Authorization header are given their bearer token in this cookie.
+const BEARER_COOKIE: &str = "kanidm-bearer";
+const BEARER_PREFIX: &str = "Bearer ";
+
 struct AppState {
     qe_r: Addr<QueryServerReadV1>,
     qe_w: Addr<QueryServerWriteV1>,
     status: Addr<StatusActor>,
+    // Bearer tokens are checked here, without needing to message the actors.
+    idms: Arc<IdmServer>,
+    secure_cookies: bool,
 }
 
-fn get_current_user(session: &Session) -> Option<UserAuthToken> {
-    match session.get::<UserAuthToken>("uat") {
-        Ok(maybe_uat) => maybe_uat,
-        Err(_) => None,
-    }
+fn get_bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .filter(|h| h.starts_with(BEARER_PREFIX))
+        .map(|h| h[BEARER_PREFIX.len()..].to_string())
+        .or_else(|| req.cookie(BEARER_COOKIE).map(|c| c.value().to_string()))
+}
+
+// This is checked on every request, so that a revoked session is refused immediately.
+fn get_current_user(req: &HttpRequest, state: &AppState) -> Option<UserAuthToken> {
+    get_bearer_token(req).and_then(|token| {
+        let mut audit = AuditScope::new("get_current_user");
+        state
+            .idms
+            .validate_bearer(&mut audit, token.as_str(), duration_from_epoch_now())
+    })
 }
 
 fn operation_error_to_response(e: OperationError) -> HttpResponse {
@@ -75,12 +104,12 @@ fn operation_error_to_response(e: OperationError) -> HttpResponse {
 }
 
 macro_rules! json_event_post {
-    ($req:expr, $session:expr, $message_type:ty, $dest:expr) => {{
+    ($obj:expr, $req:expr, $state:expr, $message_type:ty, $dest:expr) => {{
         // Get auth if any?
-        let uat = get_current_user(&$session);
+        let uat = get_current_user(&$req, &$state);
         // Send to the db for handling
         // combine request + uat -> message.
-        let m_obj = <$message_type>::new(uat, $req);
+        let m_obj = <$message_type>::new(uat, $obj);
         match $dest.send(m_obj).await {
             Ok(Ok(r)) => HttpResponse::Ok().json(r),
             Ok(Err(e)) => operation_error_to_response(e),
@@ -90,11 +119,11 @@ macro_rules! json_event_post {
 }
 
 macro_rules! json_event_get {
-    ($session:expr, $state:expr, $message_type:ty) => {{
+    ($req:expr, $state:expr, $message_type:ty) => {{
         // Get current auth data - remember, the QS checks if the
         // none/some is okay, because it's too hard to make it work here
         // with all the async parts.
-        let uat = get_current_user(&$session);
+        let uat = get_current_user(&$req, &$state);
 
         // New event, feed current auth data from the token to it.
         let obj = <$message_type>::new(uat);
@@ -110,42 +139,42 @@ macro_rules! json_event_get {
 // Handle the various end points we need to expose
 
 async fn create(
-    (req, session, state): (Json<CreateRequest>, Session, Data<AppState>),
+    (obj, req, state): (Json<CreateRequest>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    json_event_post!(req.into_inner(), session, CreateMessage, state.qe_w)
+    json_event_post!(obj.into_inner(), req, state, CreateMessage, state.qe_w)
 }
 
 async fn modify(
-    (req, session, state): (Json<ModifyRequest>, Session, Data<AppState>),
+    (obj, req, state): (Json<ModifyRequest>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    json_event_post!(req.into_inner(), session, ModifyMessage, state.qe_w)
+    json_event_post!(obj.into_inner(), req, state, ModifyMessage, state.qe_w)
 }
 
 async fn delete(
-    (req, session, state): (Json<DeleteRequest>, Session, Data<AppState>),
+    (obj, req, state): (Json<DeleteRequest>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    json_event_post!(req.into_inner(), session, DeleteMessage, state.qe_w)
+    json_event_post!(obj.into_inner(), req, state, DeleteMessage, state.qe_w)
 }
 
 async fn search(
-    (req, session, state): (Json<SearchRequest>, Session, Data<AppState>),
+    (obj, req, state): (Json<SearchRequest>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    json_event_post!(req.into_inner(), session, SearchMessage, state.qe_r)
+    json_event_post!(obj.into_inner(), req, state, SearchMessage, state.qe_r)
 }
 
-async fn whoami((session, state): (Session, Data<AppState>)) -> HttpResponse {
-    json_event_get!(session, state, WhoamiMessage)
+async fn whoami((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
+    json_event_get!(req, state, WhoamiMessage)
 }
 
 // =============== REST generics ========================
 
 async fn json_rest_event_get(
-    session: Session,
+    req: HttpRequest,
     state: Data<AppState>,
     filter: Filter<FilterInvalid>,
     attrs: Option<Vec<String>>,
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
 
     let obj = InternalSearchMessage { uat, filter, attrs };
 
@@ -158,12 +187,12 @@ async fn json_rest_event_get(
 
 async fn json_rest_event_get_id(
     path: Path<String>,
-    session: Session,
+    req: HttpRequest,
     state: Data<AppState>,
     filter: Filter<FilterInvalid>,
     attrs: Option<Vec<String>>,
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
 
     let filter = Filter::join_parts_and(filter, filter_all!(f_id(path.as_str())));
 
@@ -178,11 +207,11 @@ async fn json_rest_event_get_id(
 
 async fn json_rest_event_delete_id(
     path: Path<String>,
-    session: Session,
+    req: HttpRequest,
     state: Data<AppState>,
     filter: Filter<FilterInvalid>,
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
 
     let filter = Filter::join_parts_and(filter, filter_all!(f_id(path.as_str())));
 
@@ -197,12 +226,12 @@ async fn json_rest_event_delete_id(
 
 async fn json_rest_event_get_id_attr(
     path: Path<(String, String)>,
-    session: Session,
+    req: HttpRequest,
     state: Data<AppState>,
     filter: Filter<FilterInvalid>,
 ) -> HttpResponse {
     let (id, attr) = path.into_inner();
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
 
     let filter = Filter::join_parts_and(filter, filter_all!(f_id(id.as_str())));
 
@@ -231,12 +260,12 @@ async fn json_rest_event_get_id_attr(
 
 async fn json_rest_event_post(
     mut obj: ProtoEntry,
-    session: Session,
+    req: HttpRequest,
     state: Data<AppState>,
     classes: Vec<String>,
 ) -> HttpResponse {
     // Read the json from the wire.
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
 
     obj.attrs.insert("class".to_string(), classes);
     let m_obj = CreateMessage::new_entry(uat, obj);
@@ -249,12 +278,12 @@ async fn json_rest_event_post(
 
 async fn json_rest_event_post_id_attr(
     path: Path<(String, String)>,
-    session: Session,
+    req: HttpRequest,
     state: Data<AppState>,
     filter: Filter<FilterInvalid>,
     values: Vec<String>,
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let (id, attr) = path.into_inner();
 
     let m_obj = AppendAttributeMessage {
@@ -274,12 +303,12 @@ async fn json_rest_event_post_id_attr(
 
 async fn json_rest_event_put_id_attr(
     path: Path<(String, String)>,
-    session: Session,
+    req: HttpRequest,
     state: Data<AppState>,
     filter: Filter<FilterInvalid>,
     values: Vec<String>,
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let (id, attr) = path.into_inner();
 
     let m_obj = SetAttributeMessage {
@@ -298,11 +327,11 @@ async fn json_rest_event_put_id_attr(
 
 async fn json_rest_event_delete_id_attr(
     path: Path<(String, String)>,
-    session: Session,
+    req: HttpRequest,
     state: Data<AppState>,
     filter: Filter<FilterInvalid>,
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let (id, attr) = path.into_inner();
 
     // TODO: Attempt to get an option Vec<String> here?
@@ -323,16 +352,16 @@ async fn json_rest_event_delete_id_attr(
 async fn json_rest_event_credential_put(
     id: String,
     cred_id: Option<String>,
-    session: Session,
+    req: HttpRequest,
     state: Data<AppState>,
     obj: SetCredentialRequest,
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
 
     let m_obj = InternalCredentialSetMessage {
         uat,
         uuid_or_name: id,
-        appid: cred_id,
+        cred_id,
         sac: obj,
     };
     match state.qe_w.send(m_obj).await {
@@ -342,6 +371,48 @@ async fn json_rest_event_credential_put(
     }
 }
 
+async fn json_rest_event_credential_lock(
+    id: String,
+    cred_id: String,
+    locked: bool,
+    req: HttpRequest,
+    state: Data<AppState>,
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+
+    let m_obj = InternalCredentialLockMessage {
+        uat,
+        uuid_or_name: id,
+        cred_id,
+        locked,
+    };
+    match state.qe_w.send(m_obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn json_rest_event_credential_delete(
+    id: String,
+    cred_id: String,
+    req: HttpRequest,
+    state: Data<AppState>,
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+
+    let m_obj = InternalCredentialRemoveMessage {
+        uat,
+        uuid_or_name: id,
+        cred_id,
+    };
+    match state.qe_w.send(m_obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
 // Okay, so a put normally needs
 //  * filter of what we are working on (id + class)
 //  * a BTreeMap<String, Vec<String>> that we turn into a modlist.
@@ -353,7 +424,7 @@ async fn json_rest_event_credential_put(
 //
 // json_rest_event_put_id(path, req, state
 
-async fn schema_get((session, state): (Session, Data<AppState>)) -> HttpResponse {
+async fn schema_get((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
     // NOTE: This is filter_all, because from_internal_message will still do the alterations
     // needed to make it safe. This is needed because there may be aci's that block access
     // to the recycle/ts types in the filter, and we need the aci to only eval on this
@@ -362,19 +433,19 @@ async fn schema_get((session, state): (Session, Data<AppState>)) -> HttpResponse
         f_eq("class", PartialValue::new_class("attributetype")),
         f_eq("class", PartialValue::new_class("classtype"))
     ]));
-    json_rest_event_get(session, state, filter, None).await
+    json_rest_event_get(req, state, filter, None).await
 }
 
-async fn schema_attributetype_get((session, state): (Session, Data<AppState>)) -> HttpResponse {
+async fn schema_attributetype_get((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("attributetype")));
-    json_rest_event_get(session, state, filter, None).await
+    json_rest_event_get(req, state, filter, None).await
 }
 
 async fn schema_attributetype_get_id(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     // These can't use get_id because they attribute name and class name aren't ... well name.
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
 
     let filter = filter_all!(f_and!([
         f_eq("class", PartialValue::new_class("attributetype")),
@@ -394,16 +465,16 @@ async fn schema_attributetype_get_id(
     }
 }
 
-async fn schema_classtype_get((session, state): (Session, Data<AppState>)) -> HttpResponse {
+async fn schema_classtype_get((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("classtype")));
-    json_rest_event_get(session, state, filter, None).await
+    json_rest_event_get(req, state, filter, None).await
 }
 
 async fn schema_classtype_get_id(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     // These can't use get_id because they attribute name and class name aren't ... well name.
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
 
     let filter = filter_all!(f_and!([
         f_eq("class", PartialValue::new_class("classtype")),
@@ -425,108 +496,212 @@ async fn schema_classtype_get_id(
 
 // == person ==
 
-async fn person_get((session, state): (Session, Data<AppState>)) -> HttpResponse {
+async fn person_get((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("person")));
-    json_rest_event_get(session, state, filter, None).await
+    json_rest_event_get(req, state, filter, None).await
 }
 
 async fn person_post(
-    (obj, session, state): (Json<ProtoEntry>, Session, Data<AppState>),
+    (obj, req, state): (Json<ProtoEntry>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let classes = vec!["account".to_string(), "object".to_string()];
-    json_rest_event_post(obj.into_inner(), session, state, classes).await
+    json_rest_event_post(obj.into_inner(), req, state, classes).await
 }
 
 async fn person_id_get(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("person")));
-    json_rest_event_get_id(path, session, state, filter, None).await
+    json_rest_event_get_id(path, req, state, filter, None).await
 }
 
 // == account ==
 
-async fn account_get((session, state): (Session, Data<AppState>)) -> HttpResponse {
+async fn account_get((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("account")));
-    json_rest_event_get(session, state, filter, None).await
+    json_rest_event_get(req, state, filter, None).await
 }
 
 async fn account_post(
-    (obj, session, state): (Json<ProtoEntry>, Session, Data<AppState>),
+    (obj, req, state): (Json<ProtoEntry>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let classes = vec!["account".to_string(), "object".to_string()];
-    json_rest_event_post(obj.into_inner(), session, state, classes).await
+    json_rest_event_post(obj.into_inner(), req, state, classes).await
 }
 
 async fn account_id_get(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("account")));
-    json_rest_event_get_id(path, session, state, filter, None).await
+    json_rest_event_get_id(path, req, state, filter, None).await
 }
 
 async fn account_id_get_attr(
-    (path, session, state): (Path<(String, String)>, Session, Data<AppState>),
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("account")));
-    json_rest_event_get_id_attr(path, session, state, filter).await
+    json_rest_event_get_id_attr(path, req, state, filter).await
 }
 
 async fn account_id_post_attr(
-    (values, path, session, state): (
+    (values, path, req, state): (
         Json<Vec<String>>,
         Path<(String, String)>,
-        Session,
+        HttpRequest,
         Data<AppState>,
     ),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("account")));
-    json_rest_event_post_id_attr(path, session, state, filter, values.into_inner()).await
+    json_rest_event_post_id_attr(path, req, state, filter, values.into_inner()).await
 }
 
 async fn account_id_delete_attr(
-    (path, session, state): (Path<(String, String)>, Session, Data<AppState>),
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("account")));
-    json_rest_event_delete_id_attr(path, session, state, filter).await
+    json_rest_event_delete_id_attr(path, req, state, filter).await
 }
 
 async fn account_id_put_attr(
-    (values, path, session, state): (
+    (values, path, req, state): (
         Json<Vec<String>>,
         Path<(String, String)>,
-        Session,
+        HttpRequest,
         Data<AppState>,
     ),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("account")));
-    json_rest_event_put_id_attr(path, session, state, filter, values.into_inner()).await
+    json_rest_event_put_id_attr(path, req, state, filter, values.into_inner()).await
 }
 
 async fn account_id_delete(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("account")));
-    json_rest_event_delete_id(path, session, state, filter).await
+    json_rest_event_delete_id(path, req, state, filter).await
 }
 
 async fn account_put_id_credential_primary(
-    (obj, path, session, state): (
+    (obj, path, req, state): (
         Json<SetCredentialRequest>,
         Path<String>,
-        Session,
+        HttpRequest,
+        Data<AppState>,
+    ),
+) -> HttpResponse {
+    let id = path.into_inner();
+    json_rest_event_credential_put(id, None, req, state, obj.into_inner()).await
+}
+
+async fn account_get_id_credentials(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let id = path.into_inner();
+
+    let obj = InternalCredentialReadMessage {
+        uat,
+        uuid_or_name: id,
+    };
+
+    match state.qe_r.send(obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn account_put_id_credential_cid(
+    (obj, path, req, state): (
+        Json<SetCredentialRequest>,
+        Path<(String, String)>,
+        HttpRequest,
         Data<AppState>,
     ),
+) -> HttpResponse {
+    let (id, cid) = path.into_inner();
+    json_rest_event_credential_put(id, Some(cid), req, state, obj.into_inner()).await
+}
+
+async fn account_delete_id_credential_cid(
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let (id, cid) = path.into_inner();
+    json_rest_event_credential_delete(id, cid, req, state).await
+}
+
+async fn account_post_id_credential_cid_lock(
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let (id, cid) = path.into_inner();
+    json_rest_event_credential_lock(id, cid, true, req, state).await
+}
+
+async fn account_delete_id_credential_cid_lock(
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let (id, cid) = path.into_inner();
+    json_rest_event_credential_lock(id, cid, false, req, state).await
+}
+
+async fn account_post_id_credential_reset_token(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let id = path.into_inner();
+
+    let obj = InternalCredentialResetTokenCreateMessage {
+        uat,
+        uuid_or_name: id,
+    };
+
+    match state.qe_w.send(obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+// This is authorised by the reset token in the request, so doesn't need a session.
+async fn account_put_id_credential_reset(
+    (obj, path, state): (Json<CredentialResetRequest>, Path<String>, Data<AppState>),
 ) -> HttpResponse {
     let id = path.into_inner();
-    json_rest_event_credential_put(id, None, session, state, obj.into_inner()).await
+    let CredentialResetRequest { token, req } = obj.into_inner();
+
+    let m_obj = InternalCredentialResetMessage {
+        uuid_or_name: id,
+        token,
+        sac: req,
+    };
+
+    match state.qe_w.send(m_obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn account_post_id_unlock(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let id = path.into_inner();
+
+    let obj = InternalUnlockAccountMessage::new(uat, id);
+
+    match state.qe_w.send(obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
 }
 
 // Return a vec of str
 async fn account_get_id_ssh_pubkeys(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
 
     let obj = InternalSshKeyReadMessage {
@@ -542,14 +717,14 @@ async fn account_get_id_ssh_pubkeys(
 }
 
 async fn account_post_id_ssh_pubkey(
-    (obj, path, session, state): (
+    (obj, path, req, state): (
         Json<(String, String)>,
         Path<String>,
-        Session,
+        HttpRequest,
         Data<AppState>,
     ),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
     let (tag, key) = obj.into_inner();
 
@@ -569,9 +744,9 @@ async fn account_post_id_ssh_pubkey(
 }
 
 async fn account_get_id_ssh_pubkey_tag(
-    (path, session, state): (Path<(String, String)>, Session, Data<AppState>),
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let (id, tag) = path.into_inner();
 
     let obj = InternalSshKeyTagReadMessage {
@@ -588,9 +763,9 @@ async fn account_get_id_ssh_pubkey_tag(
 }
 
 async fn account_delete_id_ssh_pubkey_tag(
-    (path, session, state): (Path<(String, String)>, Session, Data<AppState>),
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let (id, tag) = path.into_inner();
 
     let obj = RemoveAttributeValueMessage {
@@ -610,9 +785,9 @@ async fn account_delete_id_ssh_pubkey_tag(
 
 // Get and return a single str
 async fn account_get_id_radius(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
 
     let obj = InternalRadiusReadMessage {
@@ -628,10 +803,10 @@ async fn account_get_id_radius(
 }
 
 async fn account_post_id_radius_regenerate(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     // Need to to send the regen msg
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
 
     let obj = InternalRegenerateRadiusMessage::new(uat, id);
@@ -644,18 +819,18 @@ async fn account_post_id_radius_regenerate(
 }
 
 async fn account_delete_id_radius(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     // We reconstruct path here to keep json_rest_event_delete_id_attr generic.
     let p = Path::from((path.into_inner(), "radius_secret".to_string()));
     let filter = filter_all!(f_eq("class", PartialValue::new_class("account")));
-    json_rest_event_delete_id_attr(p, session, state, filter).await
+    json_rest_event_delete_id_attr(p, req, state, filter).await
 }
 
 async fn account_get_id_radius_token(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
 
     let obj = InternalRadiusTokenReadMessage {
@@ -670,10 +845,147 @@ async fn account_get_id_radius_token(
     }
 }
 
+async fn account_get_id_credential_primary_totp(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let id = path.into_inner();
+
+    let obj = InternalTOTPReadMessage {
+        uat,
+        uuid_or_name: id,
+    };
+
+    match state.qe_r.send(obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn account_get_id_app_passwords(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let id = path.into_inner();
+
+    let obj = InternalAppPasswordReadMessage {
+        uat,
+        uuid_or_name: id,
+    };
+
+    match state.qe_r.send(obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn account_post_id_app_password(
+    (obj, path, req, state): (
+        Json<(String, String)>,
+        Path<String>,
+        HttpRequest,
+        Data<AppState>,
+    ),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let id = path.into_inner();
+    let (label, application) = obj.into_inner();
+
+    let m_obj = InternalAppPasswordCreateMessage {
+        uat,
+        uuid_or_name: id,
+        label,
+        application,
+    };
+
+    match state.qe_w.send(m_obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn account_delete_id_app_password_label(
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let (id, label) = path.into_inner();
+
+    let m_obj = InternalAppPasswordRemoveMessage {
+        uat,
+        uuid_or_name: id,
+        label,
+    };
+
+    match state.qe_w.send(m_obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn account_get_id_sessions(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let id = path.into_inner();
+
+    let obj = InternalSessionReadMessage {
+        uat,
+        uuid_or_name: id,
+    };
+
+    match state.qe_r.send(obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn account_delete_id_sessions(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let id = path.into_inner();
+
+    let m_obj = InternalSessionRevokeMessage {
+        uat,
+        uuid_or_name: id,
+        id: None,
+    };
+
+    match state.qe_w.send(m_obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn account_delete_id_session_id(
+    (path, req, state): (Path<(String, Uuid)>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let (id, sid) = path.into_inner();
+
+    let m_obj = InternalSessionRevokeMessage {
+        uat,
+        uuid_or_name: id,
+        id: Some(sid),
+    };
+
+    match state.qe_w.send(m_obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
 async fn account_post_id_person_extend(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let uuid_or_name = path.into_inner();
     let m_obj = IdmAccountPersonExtendMessage { uat, uuid_or_name };
     match state.qe_w.send(m_obj).await {
@@ -684,14 +996,14 @@ async fn account_post_id_person_extend(
 }
 
 async fn account_post_id_unix(
-    (obj, path, session, state): (
+    (obj, path, req, state): (
         Json<AccountUnixExtend>,
         Path<String>,
-        Session,
+        HttpRequest,
         Data<AppState>,
     ),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
     let m_obj = IdmAccountUnixExtendMessage::new(uat, id, obj.into_inner());
     match state.qe_w.send(m_obj).await {
@@ -702,9 +1014,9 @@ async fn account_post_id_unix(
 }
 
 async fn account_get_id_unix_token(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
 
     let obj = InternalUnixUserTokenReadMessage {
@@ -720,14 +1032,14 @@ async fn account_get_id_unix_token(
 }
 
 async fn account_post_id_unix_auth(
-    (obj, path, session, state): (
+    (obj, path, req, state): (
         Json<SingleStringRequest>,
         Path<String>,
-        Session,
+        HttpRequest,
         Data<AppState>,
     ),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
     let m_obj = IdmAccountUnixAuthMessage {
         uat,
@@ -742,14 +1054,14 @@ async fn account_post_id_unix_auth(
 }
 
 async fn account_put_id_unix_credential(
-    (obj, path, session, state): (
+    (obj, path, req, state): (
         Json<SingleStringRequest>,
         Path<String>,
-        Session,
+        HttpRequest,
         Data<AppState>,
     ),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
     let m_obj = IdmAccountUnixSetCredMessage {
         uat,
@@ -764,9 +1076,9 @@ async fn account_put_id_unix_credential(
 }
 
 async fn account_delete_id_unix_credential(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
 
     let obj = PurgeAttributeMessage {
@@ -783,74 +1095,79 @@ async fn account_delete_id_unix_credential(
     }
 }
 
-async fn group_get((session, state): (Session, Data<AppState>)) -> HttpResponse {
+async fn group_get((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("group")));
-    json_rest_event_get(session, state, filter, None).await
+    json_rest_event_get(req, state, filter, None).await
 }
 
 async fn group_post(
-    (obj, session, state): (Json<ProtoEntry>, Session, Data<AppState>),
+    (obj, req, state): (Json<ProtoEntry>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let classes = vec!["group".to_string(), "object".to_string()];
-    json_rest_event_post(obj.into_inner(), session, state, classes).await
+    json_rest_event_post(obj.into_inner(), req, state, classes).await
 }
 
 async fn group_id_get(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("group")));
-    json_rest_event_get_id(path, session, state, filter, None).await
+    json_rest_event_get_id(path, req, state, filter, None).await
 }
 
 async fn group_id_get_attr(
-    (path, session, state): (Path<(String, String)>, Session, Data<AppState>),
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("group")));
-    json_rest_event_get_id_attr(path, session, state, filter).await
+    json_rest_event_get_id_attr(path, req, state, filter).await
 }
 
 async fn group_id_post_attr(
-    (values, path, session, state): (
+    (values, path, req, state): (
         Json<Vec<String>>,
         Path<(String, String)>,
-        Session,
+        HttpRequest,
         Data<AppState>,
     ),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("group")));
-    json_rest_event_post_id_attr(path, session, state, filter, values.into_inner()).await
+    json_rest_event_post_id_attr(path, req, state, filter, values.into_inner()).await
 }
 
 async fn group_id_delete_attr(
-    (path, session, state): (Path<(String, String)>, Session, Data<AppState>),
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("group")));
-    json_rest_event_delete_id_attr(path, session, state, filter).await
+    json_rest_event_delete_id_attr(path, req, state, filter).await
 }
 
 async fn group_id_put_attr(
-    (values, path, session, state): (
+    (values, path, req, state): (
         Json<Vec<String>>,
         Path<(String, String)>,
-        Session,
+        HttpRequest,
         Data<AppState>,
     ),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("group")));
-    json_rest_event_put_id_attr(path, session, state, filter, values.into_inner()).await
+    json_rest_event_put_id_attr(path, req, state, filter, values.into_inner()).await
 }
 
 async fn group_id_delete(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("group")));
-    json_rest_event_delete_id(path, session, state, filter).await
+    json_rest_event_delete_id(path, req, state, filter).await
 }
 
 async fn group_post_id_unix(
-    (obj, path, session, state): (Json<GroupUnixExtend>, Path<String>, Session, Data<AppState>),
+    (obj, path, req, state): (
+        Json<GroupUnixExtend>,
+        Path<String>,
+        HttpRequest,
+        Data<AppState>,
+    ),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
     let m_obj = IdmGroupUnixExtendMessage::new(uat, id, obj.into_inner());
     match state.qe_w.send(m_obj).await {
@@ -861,9 +1178,9 @@ async fn group_post_id_unix(
 }
 
 async fn group_get_id_unix_token(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let id = path.into_inner();
 
     let obj = InternalUnixGroupTokenReadMessage {
@@ -878,40 +1195,40 @@ async fn group_get_id_unix_token(
     }
 }
 
-async fn domain_get((session, state): (Session, Data<AppState>)) -> HttpResponse {
+async fn domain_get((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("domain_info")));
-    json_rest_event_get(session, state, filter, None).await
+    json_rest_event_get(req, state, filter, None).await
 }
 
 async fn domain_id_get(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("domain_info")));
-    json_rest_event_get_id(path, session, state, filter, None).await
+    json_rest_event_get_id(path, req, state, filter, None).await
 }
 
 async fn domain_id_get_attr(
-    (path, session, state): (Path<(String, String)>, Session, Data<AppState>),
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("domain_info")));
-    json_rest_event_get_id_attr(path, session, state, filter).await
+    json_rest_event_get_id_attr(path, req, state, filter).await
 }
 
 async fn domain_id_put_attr(
-    (values, path, session, state): (
+    (values, path, req, state): (
         Json<Vec<String>>,
         Path<(String, String)>,
-        Session,
+        HttpRequest,
         Data<AppState>,
     ),
 ) -> HttpResponse {
     let filter = filter_all!(f_eq("class", PartialValue::new_class("domain_info")));
-    json_rest_event_put_id_attr(path, session, state, filter, values.into_inner()).await
+    json_rest_event_put_id_attr(path, req, state, filter, values.into_inner()).await
 }
 
-async fn recycle_bin_get((session, state): (Session, Data<AppState>)) -> HttpResponse {
+async fn recycle_bin_get((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
     let filter = filter_all!(f_pres("class"));
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let attrs = None;
 
     let obj = InternalSearchRecycledMessage { uat, filter, attrs };
@@ -924,9 +1241,9 @@ async fn recycle_bin_get((session, state): (Session, Data<AppState>)) -> HttpRes
 }
 
 async fn recycle_bin_id_get(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let filter = filter_all!(f_id(path.as_str()));
     let attrs = None;
 
@@ -940,9 +1257,9 @@ async fn recycle_bin_id_get(
 }
 
 async fn recycle_bin_revive_id_post(
-    (path, session, state): (Path<String>, Session, Data<AppState>),
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
-    let uat = get_current_user(&session);
+    let uat = get_current_user(&req, &state);
     let filter = filter_all!(f_id(path.as_str()));
 
     let m_obj = ReviveRecycledMessage { uat, filter };
@@ -953,14 +1270,123 @@ async fn recycle_bin_revive_id_post(
     }
 }
 
-async fn do_nothing(_session: Session) -> String {
-    "did nothing".to_string()
+async fn access_profile_get((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
+    let filter = filter_all!(f_eq(
+        "class",
+        PartialValue::new_class("access_control_profile")
+    ));
+    json_rest_event_get(req, state, filter, None).await
+}
+
+async fn access_profile_post(
+    (obj, req, state): (Json<ProtoEntry>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let mut obj = obj.into_inner();
+    // Unlike other types, the caller chooses which of search, modify, create and
+    // delete the profile applies to, so keep the classes they sent.
+    let mut classes = obj.attrs.remove("class").unwrap_or_else(Vec::new);
+    for c in &["object", "access_control_profile"] {
+        if !classes.iter().any(|v| v == c) {
+            classes.push((*c).to_string());
+        }
+    }
+    json_rest_event_post(obj, req, state, classes).await
+}
+
+async fn access_profile_id_get(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let filter = filter_all!(f_eq(
+        "class",
+        PartialValue::new_class("access_control_profile")
+    ));
+    json_rest_event_get_id(path, req, state, filter, None).await
+}
+
+async fn access_profile_id_delete(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let filter = filter_all!(f_eq(
+        "class",
+        PartialValue::new_class("access_control_profile")
+    ));
+    json_rest_event_delete_id(path, req, state, filter).await
+}
+
+async fn access_profile_id_get_attr(
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let filter = filter_all!(f_eq(
+        "class",
+        PartialValue::new_class("access_control_profile")
+    ));
+    json_rest_event_get_id_attr(path, req, state, filter).await
+}
+
+async fn access_profile_id_post_attr(
+    (values, path, req, state): (
+        Json<Vec<String>>,
+        Path<(String, String)>,
+        HttpRequest,
+        Data<AppState>,
+    ),
+) -> HttpResponse {
+    let filter = filter_all!(f_eq(
+        "class",
+        PartialValue::new_class("access_control_profile")
+    ));
+    json_rest_event_post_id_attr(path, req, state, filter, values.into_inner()).await
+}
+
+async fn access_profile_id_put_attr(
+    (values, path, req, state): (
+        Json<Vec<String>>,
+        Path<(String, String)>,
+        HttpRequest,
+        Data<AppState>,
+    ),
+) -> HttpResponse {
+    let filter = filter_all!(f_eq(
+        "class",
+        PartialValue::new_class("access_control_profile")
+    ));
+    json_rest_event_put_id_attr(path, req, state, filter, values.into_inner()).await
+}
+
+async fn access_profile_id_delete_attr(
+    (path, req, state): (Path<(String, String)>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let filter = filter_all!(f_eq(
+        "class",
+        PartialValue::new_class("access_control_profile")
+    ));
+    json_rest_event_delete_id_attr(path, req, state, filter).await
 }
 
-// We probably need an extract auth or similar to handle the different
-// types (cookie, bearer), and to generic this over get/post.
+async fn access_profile_explain_post(
+    (obj, req, state): (Json<AccessExplainRequest>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+
+    let obj = InternalAccessExplainMessage {
+        uat,
+        req: obj.into_inner(),
+    };
 
-async fn auth((obj, session, state): (Json<AuthRequest>, Session, Data<AppState>)) -> HttpResponse {
+    match state.qe_r.send(obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn do_nothing(_req: HttpRequest) -> String {
+    "did nothing".to_string()
+}
+
+async fn auth(
+    (obj, req, session, state): (Json<AuthRequest>, HttpRequest, Session, Data<AppState>),
+) -> HttpResponse {
     // First, deal with some state management.
     // Do anything here first that's needed like getting the session details
     // out of the req cookie.
@@ -974,7 +1400,12 @@ async fn auth((obj, session, state): (Json<AuthRequest>, Session, Data<AppState>
         Err(_e) => return HttpResponse::InternalServerError().json(()),
     };
 
-    let auth_msg = AuthMessage::new(obj.into_inner(), maybe_sessionid);
+    let auth_msg = AuthMessage::new(
+        obj.into_inner(),
+        maybe_sessionid,
+        get_current_user(&req, &state),
+        req.peer_addr().map(|a| a.ip().to_string()),
+    );
 
     // We probably need to know if we allocate the cookie, that this is a
     // new session, and in that case, anything *except* authrequest init is
@@ -987,13 +1418,23 @@ async fn auth((obj, session, state): (Json<AuthRequest>, Session, Data<AppState>
     {
         Ok(Ok(ar)) => {
             match &ar.state {
-                AuthState::Success(uat) => {
+                AuthState::Success(_) => {
                     // Remove the auth-session-id
                     session.remove("auth-session-id");
-                    // Set the uat into the cookie
-                    match session.set("uat", uat) {
-                        Ok(_) => HttpResponse::Ok().json(ar),
-                        Err(_) => HttpResponse::InternalServerError().json(()),
+                    // The token is signed, so unlike the session it remains valid over a
+                    // restart of the server.
+                    match &ar.bearer {
+                        Some(token) => {
+                            let bearer_cookie = cookie::Cookie::build(BEARER_COOKIE, token.clone())
+                                .path("/")
+                                .http_only(true)
+                                .same_site(cookie::SameSite::Strict)
+                                .secure(state.secure_cookies)
+                                .max_age_time(Duration::seconds(BEARER_TOKEN_VALIDITY as i64))
+                                .finish();
+                            HttpResponse::Ok().cookie(bearer_cookie).json(ar)
+                        }
+                        None => HttpResponse::InternalServerError().json(()),
                     }
                 }
                 AuthState::Denied(_) => {
@@ -1016,19 +1457,122 @@ async fn auth((obj, session, state): (Json<AuthRequest>, Session, Data<AppState>
 }
 
 async fn idm_account_set_password(
-    (obj, session, state): (Json<SingleStringRequest>, Session, Data<AppState>),
+    (obj, req, state): (Json<SingleStringRequest>, HttpRequest, Data<AppState>),
 ) -> HttpResponse {
     json_event_post!(
         obj.into_inner(),
-        session,
+        req,
+        state,
         IdmAccountSetPasswordMessage,
         state.qe_w
     )
 }
 
+async fn idm_account_generate_backup_codes(
+    (req, state): (HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let obj = IdmAccountGenerateBackupCodesMessage::new(uat);
+    match state.qe_w.send(obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn idm_account_get_sessions((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let id = match &uat {
+        Some(u) => u.uuid.clone(),
+        None => return operation_error_to_response(OperationError::NotAuthenticated),
+    };
+
+    let obj = InternalSessionReadMessage {
+        uat,
+        uuid_or_name: id,
+    };
+
+    match state.qe_r.send(obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn idm_account_delete_session(
+    (path, req, state): (Path<Uuid>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let id = match &uat {
+        Some(u) => u.uuid.clone(),
+        None => return operation_error_to_response(OperationError::NotAuthenticated),
+    };
+
+    let m_obj = InternalSessionRevokeMessage {
+        uat,
+        uuid_or_name: id,
+        id: Some(path.into_inner()),
+    };
+
+    match state.qe_w.send(m_obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn idm_account_get_credentials((req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
+    let uat = get_current_user(&req, &state);
+    let id = match &uat {
+        Some(u) => u.uuid.clone(),
+        None => return operation_error_to_response(OperationError::NotAuthenticated),
+    };
+
+    let obj = InternalCredentialReadMessage {
+        uat,
+        uuid_or_name: id,
+    };
+
+    match state.qe_r.send(obj).await {
+        Ok(Ok(r)) => HttpResponse::Ok().json(r),
+        Ok(Err(e)) => operation_error_to_response(e),
+        Err(_) => HttpResponse::InternalServerError().json("mailbox failure"),
+    }
+}
+
+async fn idm_account_delete_credential(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let id = match get_current_user(&req, &state) {
+        Some(u) => u.uuid,
+        None => return operation_error_to_response(OperationError::NotAuthenticated),
+    };
+    json_rest_event_credential_delete(id, path.into_inner(), req, state).await
+}
+
+async fn idm_account_post_credential_lock(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let id = match get_current_user(&req, &state) {
+        Some(u) => u.uuid,
+        None => return operation_error_to_response(OperationError::NotAuthenticated),
+    };
+    json_rest_event_credential_lock(id, path.into_inner(), true, req, state).await
+}
+
+async fn idm_account_delete_credential_lock(
+    (path, req, state): (Path<String>, HttpRequest, Data<AppState>),
+) -> HttpResponse {
+    let id = match get_current_user(&req, &state) {
+        Some(u) => u.uuid,
+        None => return operation_error_to_response(OperationError::NotAuthenticated),
+    };
+    json_rest_event_credential_lock(id, path.into_inner(), false, req, state).await
+}
+
 // == Status
 
-async fn status((_session, state): (Session, Data<AppState>)) -> HttpResponse {
+async fn status((_req, state): (HttpRequest, Data<AppState>)) -> HttpResponse {
     let r = state.status.send(StatusRequestEvent {}).await;
     match r {
         Ok(true) => HttpResponse::Ok().json(true),
@@ -1054,6 +1598,7 @@ fn setup_backend(config: &Configuration) -> Result<Backend, OperationError> {
 fn setup_qs_idms(
     audit: &mut AuditScope,
     be: Backend,
+    config: &Configuration,
 ) -> Result<(QueryServer, IdmServer), OperationError> {
     // Create "just enough" schema for us to be able to load from
     // disk ... Schema loading is one time where we validate the
@@ -1079,9 +1624,17 @@ fn setup_qs_idms(
     // Write it out if changes are needed.
     query_server.initialise_helper(audit, duration_from_epoch_now())?;
 
+    // Unless the password hashing parameters were configured, measure this machine
+    // to choose them.
+    let crypto_policy = config
+        .crypto_policy
+        .clone()
+        .unwrap_or_else(CryptoPolicy::calibrate);
+    info!("Hashing new passwords with {:?}", crypto_policy);
+
     // We generate a SINGLE idms only!
 
-    let idms = IdmServer::new(query_server.clone());
+    let idms = IdmServer::new(query_server.clone(), config.origin.as_str(), crypto_policy);
 
     Ok((query_server, idms))
 }
@@ -1145,7 +1698,7 @@ pub fn restore_server_core(config: Configuration, dst_path: &str) {
 
     info!("Attempting to init query server ...");
 
-    let (qs, _idms) = match setup_qs_idms(&mut audit, be) {
+    let (qs, _idms) = match setup_qs_idms(&mut audit, be, &config) {
         Ok(t) => t,
         Err(e) => {
             debug!("{}", audit);
@@ -1210,7 +1763,7 @@ pub fn reindex_server_core(config: Configuration) {
 
     info!("Attempting to init query server ...");
 
-    let (qs, _idms) = match setup_qs_idms(&mut audit, be) {
+    let (qs, _idms) = match setup_qs_idms(&mut audit, be, &config) {
         Ok(t) => t,
         Err(e) => {
             debug!("{}", audit);
@@ -1248,7 +1801,7 @@ pub fn domain_rename_core(config: Configuration, new_domain_name: String) {
         }
     };
     // setup the qs - *with* init of the migrations and schema.
-    let (qs, _idms) = match setup_qs_idms(&mut audit, be) {
+    let (qs, _idms) = match setup_qs_idms(&mut audit, be, &config) {
         Ok(t) => t,
         Err(e) => {
             debug!("{}", audit);
@@ -1336,7 +1889,7 @@ pub fn recover_account_core(config: Configuration, name: String, password: Strin
         }
     };
     // setup the qs - *with* init of the migrations and schema.
-    let (_qs, idms) = match setup_qs_idms(&mut audit, be) {
+    let (_qs, idms) = match setup_qs_idms(&mut audit, be, &config) {
         Ok(t) => t,
         Err(e) => {
             debug!("{}", audit);
@@ -1404,7 +1957,7 @@ pub fn create_server_core(config: Configuration) {
 
     let mut audit = AuditScope::new("setup_qs_idms");
     // Start the IDM server.
-    let (qs, idms) = match setup_qs_idms(&mut audit, be) {
+    let (qs, idms) = match setup_qs_idms(&mut audit, be, &config) {
         Ok(t) => t,
         Err(e) => {
             debug!("{}", audit);
@@ -1413,6 +1966,18 @@ pub fn create_server_core(config: Configuration) {
         }
     };
     // Any pre-start tasks here.
+    // Load the bearer token signing keys, creating or rotating them as needed.
+    let ct = duration_from_epoch_now();
+    let mut idms_prox_write = idms.proxy_write(ct);
+    let r = idms_prox_write
+        .rotate_jws_keys(&mut audit, ct)
+        .and_then(|_| idms_prox_write.commit(&mut audit));
+    if let Err(e) = r {
+        debug!("{}", audit);
+        error!("Unable to load bearer token signing keys -> {:?}", e);
+        return;
+    }
+
     match &config.integration_test_config {
         Some(itc) => {
             let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
@@ -1456,10 +2021,10 @@ pub fn create_server_core(config: Configuration) {
         config.threads,
     );
     // Start the write thread
-    let server_write_addr = QueryServerWriteV1::start(log_addr, qs, idms_arc);
+    let server_write_addr = QueryServerWriteV1::start(log_addr, qs, idms_arc.clone());
 
     // Setup timed events associated to the write thread
-    let _int_addr = IntervalActor::new(server_write_addr.clone()).start();
+    let _int_addr = IntervalActor::new(server_write_addr.clone(), config.purge_expired_acp).start();
 
     // Copy the max size
     let secure_cookies = config.secure_cookies;
@@ -1473,6 +2038,8 @@ pub fn create_server_core(config: Configuration) {
                 qe_r: server_read_addr.clone(),
                 qe_w: server_write_addr.clone(),
                 status: status_addr.clone(),
+                idms: idms_arc.clone(),
+                secure_cookies,
             })
             .wrap(middleware::Logger::default())
             .wrap(
@@ -1535,12 +2102,32 @@ pub fn create_server_core(config: Configuration) {
                 web::scope("/v1/self")
                     .route("", web::get().to(whoami))
                     .route("/_attr/{attr}", web::get().to(do_nothing))
-                    .route("/_credential", web::get().to(do_nothing))
+                    .route("/_credential", web::get().to(idm_account_get_credentials))
                     .route(
                         "/_credential/primary/set_password",
                         web::post().to(idm_account_set_password),
                     )
-                    .route("/_credential/{cid}/_lock", web::get().to(do_nothing))
+                    .route(
+                        "/_credential/primary/backup_codes",
+                        web::post().to(idm_account_generate_backup_codes),
+                    )
+                    .route("/_session", web::get().to(idm_account_get_sessions))
+                    .route(
+                        "/_session/{sid}",
+                        web::delete().to(idm_account_delete_session),
+                    )
+                    .route(
+                        "/_credential/{cid}",
+                        web::delete().to(idm_account_delete_credential),
+                    )
+                    .route(
+                        "/_credential/{cid}/_lock",
+                        web::post().to(idm_account_post_credential_lock),
+                    )
+                    .route(
+                        "/_credential/{cid}/_lock",
+                        web::delete().to(idm_account_delete_credential_lock),
+                    )
                     .route("/_radius", web::get().to(do_nothing))
                     .route("/_radius", web::delete().to(do_nothing))
                     .route("/_radius", web::post().to(do_nothing))
@@ -1584,12 +2171,64 @@ pub fn create_server_core(config: Configuration) {
                         web::post().to(account_post_id_person_extend),
                     )
                     .route("/{id}/_lock", web::get().to(do_nothing))
-                    .route("/{id}/_credential", web::get().to(do_nothing))
+                    .route(
+                        "/{id}/_credential",
+                        web::get().to(account_get_id_credentials),
+                    )
                     .route(
                         "/{id}/_credential/primary",
                         web::put().to(account_put_id_credential_primary),
                     )
-                    .route("/{id}/_credential/{cid}/_lock", web::get().to(do_nothing))
+                    .route(
+                        "/{id}/_credential/primary/totp",
+                        web::get().to(account_get_id_credential_primary_totp),
+                    )
+                    .route(
+                        "/{id}/_credential/_reset_token",
+                        web::post().to(account_post_id_credential_reset_token),
+                    )
+                    .route(
+                        "/{id}/_credential/_reset",
+                        web::put().to(account_put_id_credential_reset),
+                    )
+                    .route(
+                        "/{id}/_credential/{cid}",
+                        web::put().to(account_put_id_credential_cid),
+                    )
+                    .route(
+                        "/{id}/_credential/{cid}",
+                        web::delete().to(account_delete_id_credential_cid),
+                    )
+                    .route(
+                        "/{id}/_credential/{cid}/_lock",
+                        web::post().to(account_post_id_credential_cid_lock),
+                    )
+                    .route(
+                        "/{id}/_credential/{cid}/_lock",
+                        web::delete().to(account_delete_id_credential_cid_lock),
+                    )
+                    .route(
+                        "/{id}/_app_password",
+                        web::get().to(account_get_id_app_passwords),
+                    )
+                    .route(
+                        "/{id}/_app_password",
+                        web::post().to(account_post_id_app_password),
+                    )
+                    .route(
+                        "/{id}/_app_password/{label}",
+                        web::delete().to(account_delete_id_app_password_label),
+                    )
+                    .route("/{id}/_session", web::get().to(account_get_id_sessions))
+                    .route(
+                        "/{id}/_session",
+                        web::delete().to(account_delete_id_sessions),
+                    )
+                    .route(
+                        "/{id}/_session/{sid}",
+                        web::delete().to(account_delete_id_session_id),
+                    )
+                    .route("/{id}/_unlock", web::post().to(account_post_id_unlock))
                     .route(
                         "/{id}/_ssh_pubkeys",
                         web::get().to(account_get_id_ssh_pubkeys),
@@ -1662,9 +2301,27 @@ pub fn create_server_core(config: Configuration) {
             )
             .service(
                 web::scope("/v1/access_profile")
-                    .route("", web::get().to(do_nothing))
-                    .route("/{id}", web::get().to(do_nothing))
-                    .route("/{id}/_attr/{attr}", web::get().to(do_nothing)),
+                    .route("", web::get().to(access_profile_get))
+                    .route("", web::post().to(access_profile_post))
+                    .route("/_explain", web::post().to(access_profile_explain_post))
+                    .route("/{id}", web::get().to(access_profile_id_get))
+                    .route("/{id}", web::delete().to(access_profile_id_delete))
+                    .route(
+                        "/{id}/_attr/{attr}",
+                        web::get().to(access_profile_id_get_attr),
+                    )
+                    .route(
+                        "/{id}/_attr/{attr}",
+                        web::post().to(access_profile_id_post_attr),
+                    )
+                    .route(
+                        "/{id}/_attr/{attr}",
+                        web::put().to(access_profile_id_put_attr),
+                    )
+                    .route(
+                        "/{id}/_attr/{attr}",
+                        web::delete().to(access_profile_id_delete_attr),
+                    ),
             )
     });
 
@@ -1,11 +1,21 @@
-use crate::be::dbvalue::{DbCredV1, DbPasswordV1};
+use crate::be::dbvalue::{DbCredV1, DbPasswordV1, DbWebauthnV1};
+use crate::constants::PW_HISTORY_LEN;
+use argon2::{Config as Argon2Config, ThreadMode, Variant, Version};
+use kanidm_proto::v1::{CredentialInfo, OperationError};
 use openssl::hash::MessageDigest;
 use openssl::pkcs5::pbkdf2_hmac;
+use openssl::sha::{sha1, sha512};
+use pwhash::{bcrypt, sha512_crypt};
 use rand::prelude::*;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
+use webauthn_rs::proto::Credential as WebauthnCredential;
+use webauthn_rs::proto::{Counter, CredentialID};
 
 pub mod totp;
+pub mod webauthn;
 
 use crate::credential::totp::TOTP;
 
@@ -20,26 +30,101 @@ pub enum Policy {
 }
 */
 
-// TODO: Determine this at startup based on a time factor
-const PBKDF2_COST: usize = 10000;
+const PBKDF2_IMPORT_MIN_LEN: usize = 32;
+
+// New passwords are hashed with argon2id. The memory cost is in KiB. The time cost is
+// calibrated at startup so that a hash takes about ARGON2_TIME_TARGET_MS on this machine,
+// unless it is set in the configuration.
+const ARGON2_M_COST: u32 = 19_456;
+const ARGON2_MIN_T_COST: u32 = 2;
+const ARGON2_MAX_T_COST: u32 = 64;
+const ARGON2_P_COST: u32 = 1;
+const ARGON2_TIME_TARGET_MS: u64 = 50;
 // NIST 800-63.b salt should be 112 bits -> 14  8u8.
-// I choose tinfoil hat though ...
-const PBKDF2_SALT_LEN: usize = 24;
-// 64 * u8 -> 512 bits of out.
-const PBKDF2_KEY_LEN: usize = 64;
+const ARGON2_SALT_LEN: usize = 16;
+// 32 * u8 -> 256 bits of out.
+const ARGON2_KEY_LEN: u32 = 32;
 
-const PBKDF2_IMPORT_MIN_LEN: usize = 32;
+/// The parameters that new password hashes are made with. A password hashed with an older
+/// algorithm, or with weaker parameters than these, is rehashed the next time it is used
+/// successfully.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CryptoPolicy {
+    pub argon2_m_cost: u32,
+    pub argon2_t_cost: u32,
+    pub argon2_p_cost: u32,
+}
+
+impl CryptoPolicy {
+    /// A policy with the given argon2id parameters, if they are usable.
+    pub fn argon2id(m_cost: u32, t_cost: u32, p_cost: u32) -> Result<Self, ()> {
+        // These are the lower bounds of argon2 itself.
+        if p_cost == 0 || t_cost == 0 || m_cost < 8 * p_cost {
+            return Err(());
+        }
+        Ok(CryptoPolicy {
+            argon2_m_cost: m_cost,
+            argon2_t_cost: t_cost,
+            argon2_p_cost: p_cost,
+        })
+    }
+
+    /// The cheapest parameters argon2 accepts. This is only suitable for tests.
+    pub fn minimum() -> Self {
+        CryptoPolicy {
+            argon2_m_cost: 8,
+            argon2_t_cost: 1,
+            argon2_p_cost: 1,
+        }
+    }
+
+    /// Measure how long a hash takes on this machine, and choose the time cost that
+    /// makes it take about ARGON2_TIME_TARGET_MS.
+    pub fn calibrate() -> Self {
+        let mut policy = CryptoPolicy {
+            argon2_m_cost: ARGON2_M_COST,
+            argon2_t_cost: ARGON2_MIN_T_COST,
+            argon2_p_cost: ARGON2_P_COST,
+        };
+        let start = Instant::now();
+        let _ = Password::new(&policy, "calibration password");
+        let elapsed = start.elapsed().as_nanos().max(1);
+        let target = Duration::from_millis(ARGON2_TIME_TARGET_MS).as_nanos();
+
+        // The time a hash takes grows linearly with the time cost.
+        let t_cost = (u128::from(ARGON2_MIN_T_COST) * target / elapsed)
+            .max(u128::from(ARGON2_MIN_T_COST))
+            .min(u128::from(ARGON2_MAX_T_COST));
+        policy.argon2_t_cost = t_cost as u32;
+        policy
+    }
+}
 
 // Why PBKDF2? Rust's bcrypt has a number of hardcodings like max pw len of 72
 // I don't really feel like adding in so many restrictions, so I'll use
 // pbkdf2 in openssl because it doesn't have the same limits.
-#[derive(Clone, Debug)]
+//
+// PBKDF2 is no longer used for new passwords, but existing and imported hashes can
+// still be verified, and are upgraded to argon2id when they are. The same is true of
+// the other imported formats below.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq)]
 enum KDF {
     //     cost, salt,   hash
     PBKDF2(usize, Vec<u8>, Vec<u8>),
+    //       m_cost, t_cost, p_cost, salt, hash
+    ARGON2ID(u32, u32, u32, Vec<u8>, Vec<u8>),
+    //            cost, salt,   hash
+    PBKDF2_SHA512(usize, Vec<u8>, Vec<u8>),
+    //    salt,    hash
+    SSHA1(Vec<u8>, Vec<u8>),
+    SSHA512(Vec<u8>, Vec<u8>),
+    // These are the whole crypt(3) string, as the scheme parses its own parameters.
+    CRYPT_SHA512(String),
+    BCRYPT(String),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Password {
     material: KDF,
 }
@@ -52,10 +137,70 @@ impl TryFrom<DbPasswordV1> for Password {
             DbPasswordV1::PBKDF2(c, s, h) => Ok(Password {
                 material: KDF::PBKDF2(c, s, h),
             }),
+            DbPasswordV1::ARGON2ID(m, t, p, s, h) => Ok(Password {
+                material: KDF::ARGON2ID(m, t, p, s, h),
+            }),
+            DbPasswordV1::PBKDF2_SHA512(c, s, h) => Ok(Password {
+                material: KDF::PBKDF2_SHA512(c, s, h),
+            }),
+            DbPasswordV1::SSHA1(s, h) => Ok(Password {
+                material: KDF::SSHA1(s, h),
+            }),
+            DbPasswordV1::SSHA512(s, h) => Ok(Password {
+                material: KDF::SSHA512(s, h),
+            }),
+            DbPasswordV1::CRYPT_SHA512(h) => Ok(Password {
+                material: KDF::CRYPT_SHA512(h),
+            }),
+            DbPasswordV1::BCRYPT(h) => Ok(Password {
+                material: KDF::BCRYPT(h),
+            }),
         }
     }
 }
 
+// Salted sha hashes from LDAP servers are base64(digest || salt).
+fn ssha_from_b64(value: &str, digest_len: usize) -> Result<(Vec<u8>, Vec<u8>), ()> {
+    let mut hash = base64::decode(value).map_err(|_| ())?;
+    // There must be some salt after the digest.
+    if hash.len() <= digest_len {
+        return Err(());
+    }
+    let salt = hash.split_off(digest_len);
+    Ok((salt, hash))
+}
+
+// The "adapted" base64 of passlib and OpenLDAP, which uses '.' in place of '+', and
+// has no padding.
+fn ab64_decode(value: &str) -> Result<Vec<u8>, ()> {
+    let mut b64 = value.replace('.', "+");
+    while b64.len() % 4 != 0 {
+        b64.push('=');
+    }
+    base64::decode(&b64).map_err(|_| ())
+}
+
+// sha512-crypt is $6$[rounds=N$]salt$hash, where the hash is 86 characters.
+fn is_crypt_sha512(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('$').collect();
+    match parts.as_slice() {
+        ["", "6", _salt, hash] => hash.len() == 86,
+        ["", "6", rounds, _salt, hash] => rounds.starts_with("rounds=") && hash.len() == 86,
+        _ => false,
+    }
+}
+
+// bcrypt is $2b$cost$ followed by 22 characters of salt and 31 of hash.
+fn is_bcrypt(value: &str) -> bool {
+    let parts: Vec<&str> = value.split('$').collect();
+    match parts.as_slice() {
+        ["", "2a", cost, rest] | ["", "2b", cost, rest] | ["", "2y", cost, rest] => {
+            cost.len() == 2 && cost.chars().all(|c| c.is_ascii_digit()) && rest.len() == 53
+        }
+        _ => false,
+    }
+}
+
 impl TryFrom<&str> for Password {
     type Error = ();
 
@@ -85,33 +230,108 @@ impl TryFrom<&str> for Password {
             }
         }
 
+        // test ldap - {SCHEME}data, where the scheme is case insensitive.
+        if value.starts_with('{') {
+            if let Some(end) = value.find('}') {
+                let scheme = value[1..end].to_uppercase();
+                let data = &value[end + 1..];
+                return match scheme.as_str() {
+                    "SSHA" => ssha_from_b64(data, 20).map(|(s, h)| Password {
+                        material: KDF::SSHA1(s, h),
+                    }),
+                    "SSHA512" => ssha_from_b64(data, 64).map(|(s, h)| Password {
+                        material: KDF::SSHA512(s, h),
+                    }),
+                    // cost$salt$hash
+                    "PBKDF2-SHA512" => {
+                        let parts: Vec<&str> = data.split('$').collect();
+                        if parts.len() != 3 {
+                            return Err(());
+                        }
+                        let c = usize::from_str_radix(parts[0], 10).map_err(|_| ())?;
+                        // openssl takes the iteration count as a c_int, and needs at
+                        // least one.
+                        if c == 0 || c > i32::max_value() as usize {
+                            return Err(());
+                        }
+                        let s = ab64_decode(parts[1])?;
+                        let h = ab64_decode(parts[2])?;
+                        if h.len() < PBKDF2_IMPORT_MIN_LEN {
+                            return Err(());
+                        }
+                        Ok(Password {
+                            material: KDF::PBKDF2_SHA512(c, s, h),
+                        })
+                    }
+                    // Only the crypt schemes we can verify.
+                    "CRYPT" => Password::try_from_crypt(data),
+                    _ => Err(()),
+                };
+            }
+        }
+
+        // test crypt - $id$...
+        if value.starts_with('$') {
+            return Password::try_from_crypt(value);
+        }
+
         // Nothing matched to this point.
         Err(())
     }
 }
 
+fn argon2_config<'a>(m_cost: u32, t_cost: u32, p_cost: u32, key_len: u32) -> Argon2Config<'a> {
+    Argon2Config {
+        variant: Variant::Argon2id,
+        version: Version::Version13,
+        mem_cost: m_cost,
+        time_cost: t_cost,
+        lanes: p_cost,
+        thread_mode: ThreadMode::Sequential,
+        secret: &[],
+        ad: &[],
+        hash_length: key_len,
+    }
+}
+
 impl Password {
-    fn new_pbkdf2(cleartext: &str) -> KDF {
+    fn try_from_crypt(value: &str) -> Result<Self, ()> {
+        if is_crypt_sha512(value) {
+            Ok(Password {
+                material: KDF::CRYPT_SHA512(value.to_string()),
+            })
+        } else if is_bcrypt(value) {
+            Ok(Password {
+                material: KDF::BCRYPT(value.to_string()),
+            })
+        } else {
+            Err(())
+        }
+    }
+
+    fn new_argon2id(policy: &CryptoPolicy, cleartext: &str) -> KDF {
         let mut rng = rand::thread_rng();
-        let salt: Vec<u8> = (0..PBKDF2_SALT_LEN).map(|_| rng.gen()).collect();
-        // This is 512 bits of output
-        let mut key: Vec<u8> = (0..PBKDF2_KEY_LEN).map(|_| 0).collect();
-
-        pbkdf2_hmac(
-            cleartext.as_bytes(),
-            salt.as_slice(),
-            PBKDF2_COST,
-            MessageDigest::sha256(),
-            key.as_mut_slice(),
+        let salt: Vec<u8> = (0..ARGON2_SALT_LEN).map(|_| rng.gen()).collect();
+        let config = argon2_config(
+            policy.argon2_m_cost,
+            policy.argon2_t_cost,
+            policy.argon2_p_cost,
+            ARGON2_KEY_LEN,
+        );
+        let key = argon2::hash_raw(cleartext.as_bytes(), salt.as_slice(), &config)
+            .expect("Argon2 failure");
+        KDF::ARGON2ID(
+            policy.argon2_m_cost,
+            policy.argon2_t_cost,
+            policy.argon2_p_cost,
+            salt,
+            key,
         )
-        .expect("PBKDF2 failure");
-        // Turn key to a vec.
-        KDF::PBKDF2(PBKDF2_COST, salt, key)
     }
 
-    pub fn new(cleartext: &str) -> Self {
+    pub fn new(policy: &CryptoPolicy, cleartext: &str) -> Self {
         Password {
-            material: Self::new_pbkdf2(cleartext),
+            material: Self::new_argon2id(policy, cleartext),
         }
     }
 
@@ -134,6 +354,58 @@ impl Password {
                 // Actually compare the outputs.
                 &chal_key == key
             }
+            KDF::ARGON2ID(m_cost, t_cost, p_cost, salt, key) => {
+                let config = argon2_config(*m_cost, *t_cost, *p_cost, key.len() as u32);
+                // Parameters argon2 rejects can't have made this hash, so fail rather
+                // than panic on them.
+                argon2::verify_raw(
+                    cleartext.as_bytes(),
+                    salt.as_slice(),
+                    key.as_slice(),
+                    &config,
+                )
+                .unwrap_or(false)
+            }
+            KDF::PBKDF2_SHA512(cost, salt, key) => {
+                let mut chal_key: Vec<u8> = (0..key.len()).map(|_| 0).collect();
+                // This hash was imported, so fail rather than panic if openssl rejects it.
+                pbkdf2_hmac(
+                    cleartext.as_bytes(),
+                    salt.as_slice(),
+                    *cost,
+                    MessageDigest::sha512(),
+                    chal_key.as_mut_slice(),
+                )
+                .is_ok()
+                    && &chal_key == key
+            }
+            KDF::SSHA1(salt, key) => {
+                let mut v = cleartext.as_bytes().to_vec();
+                v.extend_from_slice(salt.as_slice());
+                &sha1(v.as_slice())[..] == key.as_slice()
+            }
+            KDF::SSHA512(salt, key) => {
+                let mut v = cleartext.as_bytes().to_vec();
+                v.extend_from_slice(salt.as_slice());
+                &sha512(v.as_slice())[..] == key.as_slice()
+            }
+            KDF::CRYPT_SHA512(hash) => sha512_crypt::verify(cleartext, hash.as_str()),
+            KDF::BCRYPT(hash) => bcrypt::verify(cleartext, hash.as_str()),
+        }
+    }
+
+    /// Is this hash weaker than the policy asks for? If it is, it should be replaced
+    /// by a new hash of the same password once that has been verified.
+    pub fn requires_upgrade(&self, policy: &CryptoPolicy) -> bool {
+        match &self.material {
+            // A calibrated time cost differs a little on each start, so a hash is only
+            // upgraded once the policy asks for at least twice its time cost. Otherwise
+            // every password would be rehashed after a restart.
+            KDF::ARGON2ID(m_cost, t_cost, _, _, _) => {
+                *m_cost < policy.argon2_m_cost || t_cost.saturating_mul(2) <= policy.argon2_t_cost
+            }
+            // Everything else is an older or imported format.
+            _ => true,
         }
     }
 
@@ -142,6 +414,16 @@ impl Password {
             KDF::PBKDF2(cost, salt, hash) => {
                 DbPasswordV1::PBKDF2(*cost, salt.clone(), hash.clone())
             }
+            KDF::ARGON2ID(m_cost, t_cost, p_cost, salt, hash) => {
+                DbPasswordV1::ARGON2ID(*m_cost, *t_cost, *p_cost, salt.clone(), hash.clone())
+            }
+            KDF::PBKDF2_SHA512(cost, salt, hash) => {
+                DbPasswordV1::PBKDF2_SHA512(*cost, salt.clone(), hash.clone())
+            }
+            KDF::SSHA1(salt, hash) => DbPasswordV1::SSHA1(salt.clone(), hash.clone()),
+            KDF::SSHA512(salt, hash) => DbPasswordV1::SSHA512(salt.clone(), hash.clone()),
+            KDF::CRYPT_SHA512(hash) => DbPasswordV1::CRYPT_SHA512(hash.clone()),
+            KDF::BCRYPT(hash) => DbPasswordV1::BCRYPT(hash.clone()),
         }
     }
 }
@@ -163,15 +445,30 @@ pub struct Credential {
     // Source (machine, user, ....). Strength?
     // policy: Policy,
     pub(crate) password: Option<Password>,
-    // webauthn: Option<NonEmptyVec<Webauthn>>
-    // totp: Option<NonEmptyVec<TOTP>>
-    pub(crate) totp: Option<TOTP>,
+    // When the password was last set, so that password policy can expire it. Imported
+    // and older credentials don't know this, and are None.
+    pub(crate) password_set: Option<Duration>,
+    // The passwords this credential had before the current one, most recent first, so
+    // that they can't be reused.
+    pub(crate) password_history: Vec<Password>,
+    // Webauthn tokens, by their user provided label. This is None rather than empty
+    // when no tokens are registered.
+    pub(crate) webauthn: Option<BTreeMap<String, WebauthnCredential>>,
+    // Totp devices, by their user provided label.
+    pub(crate) totp: BTreeMap<String, TOTP>,
+    // Single use codes that can be given in place of a totp, if its device is lost.
+    // These are hashed like passwords, and removed once used.
+    pub(crate) backup_codes: Vec<Password>,
     pub(crate) claims: Vec<String>,
+    // The application an application password is restricted to. This is None for the
+    // primary credential, which is the only one valid for interactive login.
+    pub(crate) application: Option<String>,
     // Uuid of Credential, used by auth session to lock this specific credential
     // if required.
     pub(crate) uuid: Uuid,
-    // TODO: Add auth policy IE validUntil, lock state ...
-    // locked: bool
+    // A locked credential is kept, but can't be used to authenticate until it's unlocked.
+    // TODO: Add auth policy IE validUntil ...
+    pub(crate) locked: bool,
 }
 
 impl TryFrom<DbCredV1> for Credential {
@@ -181,9 +478,16 @@ impl TryFrom<DbCredV1> for Credential {
         // Work out what the policy is?
         let DbCredV1 {
             password,
+            password_set,
+            password_history,
             totp,
+            totps,
+            webauthn,
+            backup_codes,
             claims,
+            application,
             uuid,
+            locked,
         } = value;
 
         let v_password = match password {
@@ -191,39 +495,152 @@ impl TryFrom<DbCredV1> for Credential {
             None => None,
         };
 
-        let v_totp = match totp {
-            Some(dbt) => Some(TOTP::try_from(dbt)?),
-            None => None,
-        };
+        let v_password_history: Vec<_> = password_history
+            .unwrap_or_else(Vec::new)
+            .into_iter()
+            .map(Password::try_from)
+            .collect::<Result<_, _>>()?;
+
+        let v_backup_codes: Vec<_> = backup_codes
+            .unwrap_or_else(Vec::new)
+            .into_iter()
+            .map(Password::try_from)
+            .collect::<Result<_, _>>()?;
+
+        let v_totp = totp
+            .into_iter()
+            .chain(totps.unwrap_or_else(Vec::new).into_iter())
+            .map(|dbt| TOTP::try_from(dbt).map(|t| (t.label().to_string(), t)))
+            .collect::<Result<_, _>>()?;
+
+        let v_webauthn = webauthn.map(|dbw| {
+            dbw.into_iter()
+                .map(|DbWebauthnV1 { l, i, c, t, v }| {
+                    (
+                        l,
+                        WebauthnCredential {
+                            cred_id: i,
+                            cred: c,
+                            counter: t,
+                            verified: v,
+                        },
+                    )
+                })
+                .collect()
+        });
 
         Ok(Credential {
             password: v_password,
+            password_set: password_set.map(Duration::from_secs),
+            password_history: v_password_history,
+            webauthn: v_webauthn,
             totp: v_totp,
+            backup_codes: v_backup_codes,
             claims,
+            application,
             uuid,
+            locked: locked.unwrap_or(false),
         })
     }
 }
 
 impl Credential {
-    pub fn new_password_only(cleartext: &str) -> Self {
+    pub fn new_password_only(policy: &CryptoPolicy, cleartext: &str) -> Self {
         Credential {
-            password: Some(Password::new(cleartext)),
-            totp: None,
+            password: Some(Password::new(policy, cleartext)),
+            password_set: None,
+            password_history: Vec::new(),
+            webauthn: None,
+            totp: BTreeMap::new(),
+            backup_codes: Vec::new(),
             claims: Vec::new(),
+            application: None,
             uuid: Uuid::new_v4(),
+            locked: false,
         }
     }
 
-    pub fn set_password(&self, cleartext: &str) -> Self {
+    pub fn new_webauthn_only(label: String, cred: WebauthnCredential) -> Self {
+        let mut webauthn = BTreeMap::new();
+        webauthn.insert(label, cred);
         Credential {
-            password: Some(Password::new(cleartext)),
-            totp: self.totp.clone(),
-            claims: self.claims.clone(),
-            uuid: self.uuid,
+            password: None,
+            password_set: None,
+            password_history: Vec::new(),
+            webauthn: Some(webauthn),
+            totp: BTreeMap::new(),
+            backup_codes: Vec::new(),
+            claims: Vec::new(),
+            application: None,
+            uuid: Uuid::new_v4(),
+            locked: false,
+        }
+    }
+
+    pub(crate) fn new_app_password(
+        policy: &CryptoPolicy,
+        application: &str,
+        cleartext: &str,
+    ) -> Self {
+        Credential {
+            password: Some(Password::new(policy, cleartext)),
+            password_set: None,
+            password_history: Vec::new(),
+            webauthn: None,
+            totp: BTreeMap::new(),
+            backup_codes: Vec::new(),
+            claims: Vec::new(),
+            application: Some(application.to_lowercase()),
+            uuid: Uuid::new_v4(),
+            locked: false,
+        }
+    }
+
+    pub fn set_password(&self, policy: &CryptoPolicy, cleartext: &str) -> Self {
+        Credential {
+            password: Some(Password::new(policy, cleartext)),
+            password_set: None,
+            password_history: self.next_password_history(),
+            ..self.clone()
+        }
+    }
+
+    // The current password becomes the most recent in the history.
+    fn next_password_history(&self) -> Vec<Password> {
+        self.password
+            .iter()
+            .chain(self.password_history.iter())
+            .take(PW_HISTORY_LEN - 1)
+            .cloned()
+            .collect()
+    }
+
+    /// Take over the passwords of a credential that is being removed, as the most recent
+    /// in the history, so that they still can't be reused.
+    pub(crate) fn inherit_password_history(&self, other: &Credential) -> Self {
+        let password_history = other
+            .password
+            .iter()
+            .chain(other.password_history.iter())
+            .chain(self.password_history.iter())
+            .take(PW_HISTORY_LEN - 1)
+            .cloned()
+            .collect();
+        Credential {
+            password_history,
+            ..self.clone()
         }
     }
 
+    /// Is this the current password of the credential, or one of the passwords it had
+    /// before?
+    pub(crate) fn is_password_reused(&self, cleartext: &str) -> bool {
+        self.password
+            .iter()
+            .chain(self.password_history.iter())
+            .any(|pw| pw.verify(cleartext))
+    }
+
     #[cfg(test)]
     pub fn verify_password(&self, cleartext: &str) -> bool {
         match &self.password {
@@ -235,37 +652,180 @@ impl Credential {
     pub fn to_db_valuev1(&self) -> DbCredV1 {
         DbCredV1 {
             password: self.password.as_ref().map(|pw| pw.to_dbpasswordv1()),
-            totp: self.totp.as_ref().map(|t| t.to_dbtotpv1()),
+            password_set: self.password_set.map(|t| t.as_secs()),
+            password_history: if self.password_history.is_empty() {
+                None
+            } else {
+                Some(
+                    self.password_history
+                        .iter()
+                        .map(|pw| pw.to_dbpasswordv1())
+                        .collect(),
+                )
+            },
+            totp: None,
+            totps: if self.totp.is_empty() {
+                None
+            } else {
+                Some(self.totp.values().map(|t| t.to_dbtotpv1()).collect())
+            },
+            webauthn: self.webauthn.as_ref().map(|map| {
+                map.iter()
+                    .map(|(k, v)| DbWebauthnV1 {
+                        l: k.clone(),
+                        i: v.cred_id.clone(),
+                        c: v.cred.clone(),
+                        t: v.counter,
+                        v: v.verified,
+                    })
+                    .collect()
+            }),
+            backup_codes: if self.backup_codes.is_empty() {
+                None
+            } else {
+                Some(
+                    self.backup_codes
+                        .iter()
+                        .map(|pw| pw.to_dbpasswordv1())
+                        .collect(),
+                )
+            },
             claims: self.claims.clone(),
+            application: self.application.clone(),
             uuid: self.uuid,
+            locked: if self.locked { Some(true) } else { None },
+        }
+    }
+
+    pub fn to_proto(&self, label: &str) -> CredentialInfo {
+        CredentialInfo {
+            id: self.uuid,
+            label: label.to_string(),
+            password: self.password.is_some(),
+            totp: self.totp.keys().cloned().collect(),
+            webauthn: self
+                .webauthn
+                .as_ref()
+                .map(|map| map.keys().cloned().collect())
+                .unwrap_or_else(Vec::new),
+            locked: self.locked,
         }
     }
 
     pub(crate) fn update_password(&self, pw: Password) -> Self {
         Credential {
             password: Some(pw),
-            totp: self.totp.clone(),
-            claims: self.claims.clone(),
-            uuid: self.uuid.clone(),
+            password_set: None,
+            password_history: self.next_password_history(),
+            ..self.clone()
+        }
+    }
+
+    /// Replace the password hash with a stronger hash of the same password. This is None
+    /// if the password is no longer `old`, because it was changed after being verified.
+    pub(crate) fn upgrade_password(&self, old: &Password, new: Password) -> Option<Self> {
+        if self.password.as_ref() != Some(old) {
+            return None;
+        }
+        Some(Credential {
+            password: Some(new),
+            ..self.clone()
+        })
+    }
+
+    /// Record that the password of this credential was set at ct.
+    pub(crate) fn update_password_set(&self, ct: &Duration) -> Self {
+        Credential {
+            password_set: Some(*ct),
+            ..self.clone()
         }
     }
 
     // We don't make totp accessible from outside the crate for now.
-    pub(crate) fn update_totp(&self, totp: TOTP) -> Self {
+    pub(crate) fn add_totp(&self, totp: TOTP) -> Result<Self, OperationError> {
+        let label = totp.label().to_string();
+        if self.totp.contains_key(&label) {
+            return Err(OperationError::InvalidAttribute(format!(
+                "TOTP label {} already exists",
+                label
+            )));
+        }
+        let mut totp_map = self.totp.clone();
+        totp_map.insert(label, totp);
+        Ok(Credential {
+            totp: totp_map,
+            ..self.clone()
+        })
+    }
+
+    pub(crate) fn remove_totp(&self, label: &str) -> Result<Self, OperationError> {
+        let mut totp = self.totp.clone();
+        if totp.remove(label).is_none() {
+            return Err(OperationError::NoMatchingEntries);
+        }
+        // Backup codes stand in for a totp, so they go with the last one.
+        let backup_codes = if totp.is_empty() {
+            Vec::new()
+        } else {
+            self.backup_codes.clone()
+        };
+        Ok(Credential {
+            totp,
+            backup_codes,
+            ..self.clone()
+        })
+    }
+
+    /// Record the time step of the last accepted code of a totp device. Returns None if
+    /// the device is not part of this credential, or a later step is already recorded.
+    pub(crate) fn update_totp_step(&self, label: &str, counter: u64) -> Option<Self> {
+        let mut totp = self.totp.clone();
+        let updated = totp.get(label)?.update_last_step(counter)?;
+        totp.insert(label.to_string(), updated);
+        Some(Credential {
+            totp,
+            ..self.clone()
+        })
+    }
+
+    /// Replace any backup codes with new ones. The cleartext codes are only ever given to
+    /// the user.
+    pub(crate) fn update_backup_codes(&self, policy: &CryptoPolicy, codes: &[String]) -> Self {
         Credential {
-            password: self.password.clone(),
-            totp: Some(totp),
-            claims: self.claims.clone(),
-            uuid: self.uuid.clone(),
+            backup_codes: codes.iter().map(|c| Password::new(policy, c)).collect(),
+            ..self.clone()
+        }
+    }
+
+    /// Remove a backup code once it has been used. This is None if the code is no longer
+    /// part of this credential.
+    pub(crate) fn remove_backup_code(&self, code: &Password) -> Option<Self> {
+        if !self.backup_codes.contains(code) {
+            return None;
         }
+        Some(Credential {
+            backup_codes: self
+                .backup_codes
+                .iter()
+                .filter(|c| *c != code)
+                .cloned()
+                .collect(),
+            ..self.clone()
+        })
     }
 
     pub(crate) fn new_from_password(pw: Password) -> Self {
         Credential {
             password: Some(pw),
-            totp: None,
+            password_set: None,
+            password_history: Vec::new(),
+            webauthn: None,
+            totp: BTreeMap::new(),
+            backup_codes: Vec::new(),
             claims: Vec::new(),
+            application: None,
             uuid: Uuid::new_v4(),
+            locked: false,
         }
     }
 
@@ -281,23 +841,99 @@ impl Credential {
     pub fn modify_password(&mut self) {
         // Change the password
     }
+    */
 
-    pub fn add_webauthn_token() {
+    pub(crate) fn add_webauthn_token(
+        &self,
+        label: String,
+        cred: WebauthnCredential,
+    ) -> Result<Self, OperationError> {
+        let mut webauthn = self.webauthn.clone().unwrap_or_else(BTreeMap::new);
+        if webauthn.contains_key(&label) {
+            return Err(OperationError::InvalidAttribute(format!(
+                "Webauthn token label {} already exists",
+                label
+            )));
+        }
+        // The same authenticator can't be registered twice under different labels.
+        if webauthn.values().any(|c| c.cred_id == cred.cred_id) {
+            return Err(OperationError::Webauthn);
+        }
+        webauthn.insert(label, cred);
+        Ok(Credential {
+            webauthn: Some(webauthn),
+            ..self.clone()
+        })
     }
 
-    pub fn remove_webauthn_token() {
+    pub(crate) fn remove_webauthn_token(&self, label: &str) -> Result<Self, OperationError> {
+        let mut webauthn = self.webauthn.clone().unwrap_or_else(BTreeMap::new);
+        if webauthn.remove(label).is_none() {
+            return Err(OperationError::NoMatchingEntries);
+        }
+        let webauthn = if webauthn.is_empty() {
+            // Removing the last token from a credential that has nothing else
+            // would lock the account out entirely.
+            if self.password.is_none() {
+                return Err(OperationError::InvalidAccountState(
+                    "Unable to remove the last webauthn token of a passwordless credential"
+                        .to_string(),
+                ));
+            }
+            None
+        } else {
+            Some(webauthn)
+        };
+        Ok(Credential {
+            webauthn,
+            ..self.clone()
+        })
+    }
+
+    /// Record the signature counter an authenticator presented. Returns None if the
+    /// token is not part of this credential, or the stored counter is already current.
+    pub(crate) fn update_webauthn_counter(
+        &self,
+        cid: &CredentialID,
+        counter: Counter,
+    ) -> Option<Self> {
+        let mut webauthn = self.webauthn.clone()?;
+        let cred = webauthn.values_mut().find(|c| &c.cred_id == cid)?;
+        if counter <= cred.counter {
+            return None;
+        }
+        cred.counter = counter;
+        Some(Credential {
+            webauthn: Some(webauthn),
+            ..self.clone()
+        })
+    }
+
+    /// Is this authenticator registered to this credential?
+    pub(crate) fn has_webauthn_token(&self, cid: &CredentialID) -> bool {
+        self.webauthn
+            .as_ref()
+            .map(|map| map.values().any(|c| &c.cred_id == cid))
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn set_locked(&self, locked: bool) -> Self {
+        Credential {
+            locked,
+            ..self.clone()
+        }
     }
-    */
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::constants::PW_HISTORY_LEN;
     use crate::credential::*;
     use std::convert::TryFrom;
 
     #[test]
     fn test_credential_simple() {
-        let c = Credential::new_password_only("password");
+        let c = Credential::new_password_only(&CryptoPolicy::minimum(), "password");
         assert!(c.verify_password("password"));
         assert!(!c.verify_password("password1"));
         assert!(!c.verify_password("Password1"));
@@ -305,6 +941,28 @@ mod tests {
         assert!(!c.verify_password("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"));
     }
 
+    #[test]
+    fn test_credential_password_history() {
+        let p = CryptoPolicy::minimum();
+        let mut c = Credential::new_password_only(&p, "password0");
+        for i in 1..PW_HISTORY_LEN {
+            c = c.set_password(&p, format!("password{}", i).as_str());
+        }
+        // The current and previous passwords are all remembered ...
+        for i in 0..PW_HISTORY_LEN {
+            assert!(c.is_password_reused(format!("password{}", i).as_str()));
+        }
+        assert!(!c.is_password_reused("password_new"));
+        // ... until they fall off the end of the history.
+        let c = c.set_password(&p, "password_new");
+        assert!(!c.is_password_reused("password0"));
+        assert!(c.is_password_reused("password1"));
+        assert!(c.password_history.len() == PW_HISTORY_LEN - 1);
+        // And the history survives the db.
+        let c = Credential::try_from(c.to_db_valuev1()).expect("Failed to load credential");
+        assert!(c.is_password_reused("password1"));
+    }
+
     #[test]
     fn test_password_from_invalid() {
         assert!(Password::try_from("password").is_err())
@@ -317,4 +975,152 @@ mod tests {
         let r = Password::try_from(im_pw).expect("Failed to parse");
         assert!(r.verify(password));
     }
+
+    #[test]
+    fn test_password_from_invalid_imports() {
+        // Digest without salt.
+        assert!(Password::try_from("{SSHA}Z5e6cTAPcbmOot653L+dj6vv97I=").is_err());
+        assert!(Password::try_from("{PBKDF2-SHA512}10000$Dx4tPEtaaXiHlqW0w9Lh8A").is_err());
+        // Iteration counts openssl can't use.
+        let hash = "Dx4tPEtaaXiHlqW0w9Lh8A$6yHzDbOst/s79rZmPLdxsGSbyjDWu7nvnl5CLNUUVFXuvpC8zUtEFcE/YKJb7zTDC7tjlgPNSrihZ5Tw5H9HGQ";
+        assert!(Password::try_from(format!("{{PBKDF2-SHA512}}0${}", hash).as_str()).is_err());
+        assert!(
+            Password::try_from(format!("{{PBKDF2-SHA512}}2147483648${}", hash).as_str()).is_err()
+        );
+        // If one is stored anyway, it fails to verify rather than panicking.
+        let pw = Password::try_from(DbPasswordV1::PBKDF2_SHA512(
+            usize::max_value(),
+            vec![0; 16],
+            vec![0; 64],
+        ))
+        .expect("Failed to load password");
+        assert!(!pw.verify("password"));
+        assert!(Password::try_from("{CRYPT}$1$abc$def").is_err());
+        assert!(Password::try_from("{UNKNOWN}abcdef").is_err());
+        assert!(Password::try_from("$6$salt$tooshort").is_err());
+        assert!(Password::try_from("$2b$05$tooshort").is_err());
+    }
+
+    #[test]
+    fn test_password_argon2id_upgrade() {
+        let weak = CryptoPolicy::minimum();
+        let strong = CryptoPolicy::argon2id(64, 2, 1).expect("Invalid policy");
+        assert!(CryptoPolicy::argon2id(4, 2, 1).is_err());
+
+        // Imported pbkdf2 hashes are always upgraded.
+        let im_pw = "pbkdf2_sha256$36000$xIEozuZVAoYm$uW1b35DUKyhvQAf1mBqMvoBDcqSD06juzyO/nmyV0+w=";
+        let r = Password::try_from(im_pw).expect("Failed to parse");
+        assert!(r.requires_upgrade(&weak));
+
+        // Argon2id hashes only when they are weaker than the policy.
+        let pw = Password::new(&weak, "password");
+        assert!(pw.verify("password"));
+        assert!(!pw.verify("password1"));
+        assert!(!pw.requires_upgrade(&weak));
+        assert!(pw.requires_upgrade(&strong));
+        let pw = Password::new(&strong, "password");
+        assert!(pw.verify("password"));
+        assert!(!pw.requires_upgrade(&weak));
+        assert!(!pw.requires_upgrade(&strong));
+
+        // A small increase in time cost, as from calibrating again, is not an upgrade.
+        let pw = Password::new(&strong, "password");
+        let recalibrated = CryptoPolicy::argon2id(64, 3, 1).expect("Invalid policy");
+        assert!(!pw.requires_upgrade(&recalibrated));
+        let doubled = CryptoPolicy::argon2id(64, 4, 1).expect("Invalid policy");
+        assert!(pw.requires_upgrade(&doubled));
+
+        // The parameters are kept in the db, so the hash can still be verified.
+        let pw = Password::try_from(pw.to_dbpasswordv1()).expect("Failed to load password");
+        assert!(pw.verify("password"));
+        assert!(!pw.requires_upgrade(&strong));
+    }
+
+    #[test]
+    fn test_credential_upgrade_password() {
+        let p = CryptoPolicy::minimum();
+        let c = Credential::new_password_only(&p, "password");
+        let old = c.password.clone().expect("No password");
+        let new = Password::new(&p, "password");
+        let c2 = c
+            .upgrade_password(&old, new.clone())
+            .expect("Failed to upgrade");
+        assert!(c2.password == Some(new.clone()));
+        assert!(c2.uuid == c.uuid);
+        // If the password was changed after the old hash was verified, nothing happens.
+        let c3 = c.set_password(&p, "password_new");
+        assert!(c3.upgrade_password(&old, new).is_none());
+    }
+
+    #[test]
+    fn test_credential_backup_codes() {
+        let p = CryptoPolicy::minimum();
+        let codes = vec!["aaaa-bbbb".to_string(), "cccc-dddd".to_string()];
+        let c = Credential::new_password_only(&p, "password").update_backup_codes(&p, &codes);
+        assert!(c.backup_codes.len() == 2);
+        // The codes are only stored hashed, and survive the db.
+        let c = Credential::try_from(c.to_db_valuev1()).expect("Failed to load credential");
+        let used = c
+            .backup_codes
+            .iter()
+            .find(|bc| bc.verify("aaaa-bbbb"))
+            .cloned()
+            .expect("Backup code not found");
+        let c2 = c.remove_backup_code(&used).expect("Failed to remove");
+        assert!(c2.backup_codes.len() == 1);
+        assert!(!c2.backup_codes.iter().any(|bc| bc.verify("aaaa-bbbb")));
+        assert!(c2.backup_codes.iter().any(|bc| bc.verify("cccc-dddd")));
+        // A code can only be removed once.
+        assert!(c2.remove_backup_code(&used).is_none());
+        // And setting the password keeps them.
+        let c3 = c2.set_password(&p, "password_new");
+        assert!(c3.backup_codes.len() == 1);
+    }
+
+    #[test]
+    fn test_credential_multiple_totp() {
+        let p = CryptoPolicy::minimum();
+        let codes = vec!["aaaa-bbbb".to_string()];
+        let c = Credential::new_password_only(&p, "password")
+            .add_totp(TOTP::generate_secure("phone".to_string(), 30))
+            .expect("Failed to add totp")
+            .add_totp(TOTP::generate_secure("tablet".to_string(), 30))
+            .expect("Failed to add totp")
+            .update_backup_codes(&p, &codes);
+        // Labels must be unique.
+        assert!(c
+            .add_totp(TOTP::generate_secure("phone".to_string(), 30))
+            .is_err());
+        // Every device and its last step survive the db.
+        let c = c
+            .update_totp_step("phone", 10)
+            .expect("Failed to update step");
+        assert!(c.update_totp_step("phone", 10).is_none());
+        assert!(c.update_totp_step("laptop", 10).is_none());
+        let c = Credential::try_from(c.to_db_valuev1()).expect("Failed to load credential");
+        assert!(c.totp.len() == 2);
+        assert!(c.update_totp_step("phone", 10).is_none());
+        // Removing a device keeps the backup codes until the last one is gone.
+        assert!(c.remove_totp("laptop").is_err());
+        let c = c.remove_totp("phone").expect("Failed to remove totp");
+        assert!(c.totp.len() == 1);
+        assert!(c.backup_codes.len() == 1);
+        let c = c.remove_totp("tablet").expect("Failed to remove totp");
+        assert!(c.totp.is_empty());
+        assert!(c.backup_codes.is_empty());
+    }
+
+    #[test]
+    fn test_credential_locked() {
+        let c = Credential::new_password_only(&CryptoPolicy::minimum(), "password");
+        assert!(!c.locked);
+        // The lock survives the db, and doesn't change anything else.
+        let c = Credential::try_from(c.set_locked(true).to_db_valuev1())
+            .expect("Failed to load credential");
+        assert!(c.locked);
+        assert!(c.verify_password("password"));
+        let info = c.to_proto("laptop");
+        assert!(info.locked && info.password && info.label == "laptop");
+        assert!(!c.set_locked(false).locked);
+    }
 }
@@ -64,6 +64,9 @@ pub struct TOTP {
     secret: Vec<u8>,
     step: u64,
     algo: TOTPAlgo,
+    // The time step of the last code that was accepted. A code may only be used once, so
+    // only later time steps are accepted after this.
+    last_step: Option<u64>,
 }
 
 impl TryFrom<DbTotpV1> for TOTP {
@@ -80,6 +83,7 @@ impl TryFrom<DbTotpV1> for TOTP {
             secret: value.k,
             step: value.s,
             algo,
+            last_step: value.u,
         })
     }
 }
@@ -95,6 +99,7 @@ impl From<ProtoTOTP> for TOTP {
                 ProtoTOTPAlgo::Sha512 => TOTPAlgo::Sha512,
             },
             step: value.step,
+            last_step: None,
         }
     }
 }
@@ -106,6 +111,7 @@ impl TOTP {
             secret,
             step,
             algo,
+            last_step: None,
         }
     }
 
@@ -119,9 +125,14 @@ impl TOTP {
             secret,
             step,
             algo,
+            last_step: None,
         }
     }
 
+    pub fn label(&self) -> &str {
+        self.label.as_str()
+    }
+
     pub(crate) fn to_dbtotpv1(&self) -> DbTotpV1 {
         DbTotpV1 {
             l: self.label.clone(),
@@ -132,6 +143,7 @@ impl TOTP {
                 TOTPAlgo::Sha256 => DbTotpAlgoV1::S256,
                 TOTPAlgo::Sha512 => DbTotpAlgoV1::S512,
             },
+            u: self.last_step,
         }
     }
 
@@ -166,11 +178,32 @@ impl TOTP {
     }
 
     pub fn verify(&self, chal: u32, time: &Duration) -> bool {
+        self.verify_step(chal, time).is_some()
+    }
+
+    /// Check a code, and return the time step it was accepted for. A code for a time step
+    /// that is not after the last accepted one is a replay, and is refused.
+    pub(crate) fn verify_step(&self, chal: u32, time: &Duration) -> Option<u64> {
+        let counter = time.as_secs() / self.step;
+        if self.last_step.map(|last| counter <= last).unwrap_or(false) {
+            return None;
+        }
         // Any error becomes a failure.
-        match self.do_totp_duration_from_epoch(time) {
-            Ok(v) => v == chal,
-            Err(_) => false,
+        match self.digest(counter) {
+            Ok(v) if v == chal => Some(counter),
+            _ => None,
+        }
+    }
+
+    /// Record that a code for this time step was accepted. This is None if a later step
+    /// has already been recorded.
+    pub(crate) fn update_last_step(&self, counter: u64) -> Option<Self> {
+        if self.last_step.map(|last| counter <= last).unwrap_or(false) {
+            return None;
         }
+        let mut totp = self.clone();
+        totp.last_step = Some(counter);
+        Some(totp)
     }
 
     pub fn to_proto(&self, accountname: &str, issuer: &str) -> ProtoTOTP {
@@ -220,6 +253,32 @@ mod tests {
         assert!(r == expect);
     }
 
+    #[test]
+    fn totp_replay() {
+        let otp = TOTP::new(
+            "".to_string(),
+            vec![0x00, 0xaa, 0xbb, 0xcc],
+            30,
+            TOTPAlgo::Sha1,
+        );
+        let d = Duration::from_secs(1585369498);
+        let chal = otp
+            .do_totp_duration_from_epoch(&d)
+            .expect("Failed to do totp");
+        let counter = otp.verify_step(chal, &d).expect("Code not accepted");
+        // Once the step is recorded, the same code is refused for the rest of its window.
+        let otp = otp.update_last_step(counter).expect("Step not recorded");
+        assert!(!otp.verify(chal, &d));
+        assert!(!otp.verify(chal, &Duration::from_secs(1585369498 + 1)));
+        assert!(otp.update_last_step(counter).is_none());
+        // The next window is fine.
+        let d = Duration::from_secs(1585369498 + 30);
+        let chal = otp
+            .do_totp_duration_from_epoch(&d)
+            .expect("Failed to do totp");
+        assert!(otp.verify(chal, &d));
+    }
+
     #[test]
     fn totp_sha1_vectors() {
         do_test(
@@ -0,0 +1,57 @@
+use webauthn_rs::WebauthnConfig;
+
+/// The relying party details that webauthn challenges and responses are bound to. The
+/// origin must match what the browser (or authenticator) reports in the client data, and
+/// the rp id is the domain of that origin.
+#[derive(Debug, Clone)]
+pub struct WebauthnDomainConfig {
+    pub rp_name: String,
+    pub origin: String,
+    pub rp_id: String,
+}
+
+impl WebauthnDomainConfig {
+    pub fn new(origin: &str) -> Self {
+        // https://idm.example.com:8443/path -> idm.example.com
+        let rp_id = origin
+            .splitn(2, "://")
+            .last()
+            .and_then(|rest| rest.split('/').next())
+            .and_then(|host| host.split(':').next())
+            .unwrap_or("")
+            .to_string();
+        WebauthnDomainConfig {
+            rp_name: "Kanidm".to_string(),
+            origin: origin.to_string(),
+            rp_id,
+        }
+    }
+}
+
+impl WebauthnConfig for WebauthnDomainConfig {
+    fn get_relying_party_name(&self) -> String {
+        self.rp_name.clone()
+    }
+
+    fn get_origin(&self) -> &str {
+        self.origin.as_str()
+    }
+
+    fn get_relying_party_id(&self) -> String {
+        self.rp_id.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::credential::webauthn::WebauthnDomainConfig;
+
+    #[test]
+    fn test_webauthn_domain_config_rp_id() {
+        assert!(WebauthnDomainConfig::new("https://idm.example.com").rp_id == "idm.example.com");
+        assert!(
+            WebauthnDomainConfig::new("https://idm.example.com:8443/ui").rp_id == "idm.example.com"
+        );
+        assert!(WebauthnDomainConfig::new("localhost").rp_id == "localhost");
+    }
+}
@@ -50,6 +50,7 @@ use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::iter::ExactSizeIterator;
+use std::time::Duration;
 use uuid::Uuid;
 
 // use std::convert::TryFrom;
@@ -364,18 +365,18 @@ impl Entry<EntryInit, EntryNew> {
             .map(|(k, vs)| {
                 let attr = k.to_lowercase();
                 let vv: BTreeSet<Value> = match attr.as_str() {
-                    "name" | "attributename" | "classname" | "version" | "domain" | "domain_name" => {
+                    "name" | "attributename" | "classname" | "classkind" | "version" | "domain" | "domain_name" => {
                         vs.into_iter().map(|v| Value::new_iutf8(v)).collect()
                     }
                     "userid" | "uidnumber" => {
                         warn!("WARNING: Use of unstabilised attributes userid/uidnumber");
                         vs.into_iter().map(|v| Value::new_iutf8(v)).collect()
                     }
-                    "class" | "acp_create_class" | "acp_modify_class"  => {
+                    "class" | "acp_create_class" | "acp_modify_class" | "systemexcludes" | "excludes" => {
                         vs.into_iter().map(|v| Value::new_class(v.as_str())).collect()
                     }
                     "acp_create_attr" | "acp_search_attr" | "acp_modify_removedattr" | "acp_modify_presentattr" |
-                    "systemmay" | "may" | "systemmust" | "must"
+                    "systemmay" | "may" | "systemmust" | "must" | "uniqueattr"
                     => {
                         vs.into_iter().map(|v| Value::new_attr(v.as_str())).collect()
                     }
@@ -387,10 +388,11 @@ impl Entry<EntryInit, EntryNew> {
                             })
                         ).collect()
                     }
-                    "member" | "memberof" | "directmemberof" => {
+                    "member" | "memberof" | "directmemberof" | "manager" | "owner" => {
                         vs.into_iter().map(|v| Value::new_refer_s(v.as_str()).unwrap() ).collect()
                     }
-                    "acp_enable" | "multivalue" | "unique" => {
+                    "acp_enable" | "acp_deny" | "multivalue" | "unique" | "pw_badlist"
+                    | "pw_require_mfa" => {
                         vs.into_iter().map(|v| Value::new_bools(v.as_str())
                             .unwrap_or_else(|| {
                                 warn!("WARNING: Allowing syntax incorrect attribute to be presented UTF8 string");
@@ -414,7 +416,7 @@ impl Entry<EntryInit, EntryNew> {
                             })
                         ).collect()
                     }
-                    "acp_targetscope" | "acp_receiver" => {
+                    "acp_targetscope" | "acp_receiver" | "uniquescope" => {
                         vs.into_iter().map(|v| Value::new_json_filter(v.as_str())
                             .unwrap_or_else(|| {
                                 warn!("WARNING: Allowing syntax incorrect attribute to be presented UTF8 string");
@@ -422,7 +424,9 @@ impl Entry<EntryInit, EntryNew> {
                             })
                         ).collect()
                     }
-                    "displayname" | "description" => {
+                    "displayname" | "description" | "regex" | "allowedvalue" |
+                    "acp_modify_presentvalue" | "acp_modify_presentrefer"
+                    => {
                         vs.into_iter().map(|v| Value::new_utf8(v)).collect()
                     }
                     "spn" => {
@@ -434,7 +438,8 @@ impl Entry<EntryInit, EntryNew> {
                             })
                         }).collect()
                     }
-                    "gidnumber" => {
+                    "gidnumber" | "minlength" | "maxlength" | "mincardinality" | "maxcardinality"
+                    | "pw_min_length" | "pw_min_score" => {
                         vs.into_iter().map(|v| {
                             Value::new_uint32_str(v.as_str())
                             .unwrap_or_else(|| {
@@ -443,6 +448,16 @@ impl Entry<EntryInit, EntryNew> {
                             })
                         }).collect()
                     }
+                    "minvalue" | "maxvalue" | "acp_valid_from" | "acp_valid_until"
+                    | "account_valid_from" | "account_expire" | "pw_max_age" => {
+                        vs.into_iter().map(|v| {
+                            Value::new_int64_str(v.as_str())
+                            .unwrap_or_else(|| {
+                                warn!("WARNING: Allowing syntax incorrect INT64 attribute to be presented UTF8 string");
+                                Value::new_utf8(v)
+                            })
+                        }).collect()
+                    }
                     ia => {
                         warn!("WARNING: Allowing invalid attribute {} to be interpretted as UTF8 string. YOU MAY ENCOUNTER ODD BEHAVIOUR!!!", ia);
                         vs.into_iter().map(|v| Value::new_utf8(v)).collect()
@@ -720,6 +735,9 @@ impl<STATE> Entry<EntryInvalid, STATE> {
                     }
                 }
             }
+
+            // Finally, the classes must be allowed to coexist on one entry.
+            SchemaClass::validate_class_set(classes.as_slice())?;
         } // unborrow ne.
 
         // Well, we got here, so okay!
@@ -902,6 +920,16 @@ impl Entry<EntrySealed, EntryCommitted> {
         compare_attrs(&self.attrs, &rhs.attrs)
     }
 
+    /// Add the claims of a session to the entry of the account it belongs to, so that access
+    /// control receivers can match on them. The result is only an event origin, and must
+    /// never be written back.
+    pub(crate) fn into_session_origin(mut self, claims: &[&str]) -> Self {
+        claims
+            .iter()
+            .for_each(|c| self.add_ava_int("claim", &Value::new_iutf8s(c)));
+        self
+    }
+
     pub fn to_dbentry(&self) -> DbEntry {
         // In the future this will do extra work to process uuid
         // into "attributes" suitable for dbentry storage.
@@ -1414,6 +1442,17 @@ impl<VALID, STATE> Entry<VALID, STATE> {
         }
     }
 
+    pub fn get_ava_single_int64(&self, attr: &str) -> Option<i64> {
+        self.get_ava_single(attr).and_then(|a| a.to_int64())
+    }
+
+    /// Read a single int64 that holds a number of seconds, such as a time since the epoch
+    /// or a maximum age. Schema keeps these attributes from being negative.
+    pub fn get_ava_single_duration(&self, attr: &str) -> Option<Duration> {
+        self.get_ava_single_int64(attr)
+            .map(|t| Duration::from_secs(t as u64))
+    }
+
     pub fn get_ava_single_syntax(&self, attr: &str) -> Option<&SyntaxType> {
         match self.get_ava_single(attr) {
             Some(a) => a.to_syntaxtype(),
@@ -1425,6 +1464,14 @@ impl<VALID, STATE> Entry<VALID, STATE> {
         self.get_ava_single(attr).and_then(|a| a.to_credential())
     }
 
+    /// The credential with this tag, from an attribute that holds several.
+    pub fn get_ava_credential_tag(&self, attr: &str, tag: &str) -> Option<&Credential> {
+        self.attrs
+            .get(attr)
+            .and_then(|vs| vs.get(&PartialValue::new_credential_tag(tag)))
+            .and_then(|v| v.to_credential())
+    }
+
     pub fn get_ava_single_radiuscred(&self, attr: &str) -> Option<&str> {
         self.get_ava_single(attr)
             .and_then(|a| a.get_radius_secret())
@@ -1817,6 +1864,44 @@ impl From<&SchemaAttribute> for Entry<EntryInit, EntryNew> {
         attrs.insert("unique".to_string(), unique_v);
         attrs.insert("index".to_string(), index_v);
         attrs.insert("syntax".to_string(), syntax_v);
+        if let Some(min) = s.minvalue {
+            attrs.insert("minvalue".to_string(), btreeset![Value::new_int64(min)]);
+        }
+        if let Some(max) = s.maxvalue {
+            attrs.insert("maxvalue".to_string(), btreeset![Value::new_int64(max)]);
+        }
+        if let Some(re) = &s.regex {
+            attrs.insert(
+                "regex".to_string(),
+                btreeset![Value::new_utf8s(re.as_str())],
+            );
+        }
+        if let Some(min) = s.minlength {
+            attrs.insert("minlength".to_string(), btreeset![Value::new_uint32(min)]);
+        }
+        if let Some(max) = s.maxlength {
+            attrs.insert("maxlength".to_string(), btreeset![Value::new_uint32(max)]);
+        }
+        if !s.allowedvalue.is_empty() {
+            let allowed_v: BTreeSet<_> = s
+                .allowedvalue
+                .iter()
+                .map(|v| Value::new_utf8s(v.as_str()))
+                .collect();
+            attrs.insert("allowedvalue".to_string(), allowed_v);
+        }
+        if let Some(min) = s.mincardinality {
+            attrs.insert(
+                "mincardinality".to_string(),
+                btreeset![Value::new_uint32(min)],
+            );
+        }
+        if let Some(max) = s.maxcardinality {
+            attrs.insert(
+                "maxcardinality".to_string(),
+                btreeset![Value::new_uint32(max)],
+            );
+        }
         attrs.insert(
             "class".to_string(),
             btreeset![
@@ -1876,6 +1961,21 @@ impl From<&SchemaClass> for Entry<EntryInit, EntryNew> {
             );
         }
 
+        attrs.insert(
+            "classkind".to_string(),
+            btreeset![Value::new_iutf8s(s.kind.as_str())],
+        );
+
+        if !s.systemexcludes.is_empty() {
+            attrs.insert(
+                "systemexcludes".to_string(),
+                s.systemexcludes
+                    .iter()
+                    .map(|se| Value::new_class(se.as_str()))
+                    .collect(),
+            );
+        }
+
         Entry {
             valid: EntryInit,
             state: EntryNew,
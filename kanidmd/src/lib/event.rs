@@ -1,13 +1,16 @@
+use crate::access::AccessControlsTransaction;
 use crate::audit::AuditScope;
+use crate::constants::CLAIM_INTERACTIVE;
 use crate::entry::{Entry, EntryCommitted, EntryInit, EntryNew, EntryReduced, EntrySealed};
 use crate::filter::{Filter, FilterInvalid, FilterValid};
+use crate::idm::claim::valid_claim_names;
 use crate::schema::SchemaTransaction;
 use crate::value::PartialValue;
 use kanidm_proto::v1::Entry as ProtoEntry;
 use kanidm_proto::v1::ModifyList as ProtoModifyList;
 use kanidm_proto::v1::{
-    AuthCredential, AuthResponse, AuthState, AuthStep, SearchResponse, UserAuthToken,
-    WhoamiResponse,
+    AccessOperation, AuthCredential, AuthResponse, AuthState, AuthStep, SearchResponse,
+    UserAuthToken, WhoamiResponse,
 };
 // use error::OperationError;
 use crate::modify::{ModifyInvalid, ModifyList, ModifyValid};
@@ -24,8 +27,10 @@ use crate::actors::v1_write::{CreateMessage, DeleteMessage, ModifyMessage};
 // use crate::schema::SchemaTransaction;
 
 use actix::prelude::*;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use uuid::Uuid;
+#[cfg(test)]
+use webauthn_rs::proto::PublicKeyCredential;
 
 #[derive(Debug)]
 pub struct SearchResult {
@@ -99,6 +104,9 @@ pub struct Event {
     // The event's initiator aka origin source.
     // This importantly, is used for access control!
     pub origin: EventOrigin,
+    // For each attribute named by a SelfRefMember term in the access controls, the
+    // entries that reference the origin through it.
+    referrers: BTreeMap<String, BTreeSet<Uuid>>,
 }
 
 impl Event {
@@ -118,9 +126,7 @@ impl Event {
 
         let e = try_audit!(audit, qs.internal_search_uuid(audit, &u));
 
-        Ok(Event {
-            origin: EventOrigin::User(e),
-        })
+        Self::from_user_entry(audit, qs, e)
     }
 
     pub fn from_ro_uat(
@@ -130,18 +136,23 @@ impl Event {
     ) -> Result<Self, OperationError> {
         audit_log!(audit, "from_ro_uat -> {:?}", uat);
         let uat = uat.ok_or(OperationError::NotAuthenticated)?;
+        // A session from an application password only proves the password to that
+        // application, and may not act on the server.
+        if uat.application.is_some() {
+            audit_log!(audit, "Denying application scoped session");
+            return Err(OperationError::AccessDenied);
+        }
         let u = try_audit!(
             audit,
             Uuid::parse_str(uat.uuid.as_str()).map_err(|_| OperationError::InvalidUuid)
         );
 
         let e = try_audit!(audit, qs.internal_search_uuid(audit, &u));
-        // TODO #64: Now apply claims from the uat into the Entry
-        // to allow filtering.
+        // Apply the claims that are still valid into the Entry to allow filtering.
+        let ct = qs.get_accesscontrols().get_ts();
+        let e = e.into_session_origin(valid_claim_names(uat.claims.as_slice(), &ct).as_slice());
 
-        Ok(Event {
-            origin: EventOrigin::User(e),
-        })
+        Self::from_user_entry(audit, qs, e)
     }
 
     pub fn from_rw_uat(
@@ -151,18 +162,23 @@ impl Event {
     ) -> Result<Self, OperationError> {
         audit_log!(audit, "from_rw_uat -> {:?}", uat);
         let uat = uat.ok_or(OperationError::NotAuthenticated)?;
+        // A session from an application password only proves the password to that
+        // application, and may not act on the server.
+        if uat.application.is_some() {
+            audit_log!(audit, "Denying application scoped session");
+            return Err(OperationError::AccessDenied);
+        }
         let u = try_audit!(
             audit,
             Uuid::parse_str(uat.uuid.as_str()).map_err(|_| OperationError::InvalidUuid)
         );
 
         let e = try_audit!(audit, qs.internal_search_uuid(audit, &u));
-        // TODO #64: Now apply claims from the uat into the Entry
-        // to allow filtering.
+        // Apply the claims that are still valid into the Entry to allow filtering.
+        let ct = qs.get_accesscontrols().get_ts();
+        let e = e.into_session_origin(valid_claim_names(uat.claims.as_slice(), &ct).as_slice());
 
-        Ok(Event {
-            origin: EventOrigin::User(e),
-        })
+        Self::from_user_entry(audit, qs, e)
     }
 
     pub fn from_rw_request(
@@ -180,14 +196,51 @@ impl Event {
         );
         let e = try_audit!(audit, qs.internal_search_uuid(audit, &u));
 
+        Self::from_user_entry(audit, qs, e)
+    }
+
+    // Find the entries that reference the user through the attributes that SelfRefMember
+    // terms name, so that those terms can be resolved for this event.
+    fn from_user_entry<T: QueryServerTransaction>(
+        audit: &mut AuditScope,
+        qs: &mut T,
+        e: Entry<EntrySealed, EntryCommitted>,
+    ) -> Result<Self, OperationError> {
+        let mut referrers: BTreeMap<String, BTreeSet<Uuid>> = qs
+            .get_accesscontrols()
+            .get_self_ref_member_attrs()
+            .into_iter()
+            .map(|a| (a, BTreeSet::new()))
+            .collect();
+
+        if !referrers.is_empty() {
+            let pv = PartialValue::new_refer(*e.get_uuid());
+            let f = filter!(f_or(
+                referrers
+                    .keys()
+                    .map(|a| f_eq(a.as_str(), pv.clone()))
+                    .collect()
+            ));
+            let entries = try_audit!(audit, qs.internal_search(audit, f));
+            entries.iter().for_each(|r| {
+                referrers.iter_mut().for_each(|(a, refs)| {
+                    if r.attribute_value_pres(a.as_str(), &pv) {
+                        refs.insert(*r.get_uuid());
+                    }
+                })
+            });
+        }
+
         Ok(Event {
             origin: EventOrigin::User(e),
+            referrers,
         })
     }
 
     pub fn from_internal() -> Self {
         Event {
             origin: EventOrigin::Internal,
+            referrers: BTreeMap::new(),
         }
     }
 
@@ -195,6 +248,7 @@ impl Event {
     pub fn from_impersonate_entry(e: Entry<EntrySealed, EntryCommitted>) -> Self {
         Event {
             origin: EventOrigin::User(e),
+            referrers: BTreeMap::new(),
         }
     }
 
@@ -212,6 +266,12 @@ impl Event {
         event.clone()
     }
 
+    // The entries referencing the origin through attr, if they were found when the
+    // event was created.
+    pub fn get_referrers(&self, attr: &str) -> Option<&BTreeSet<Uuid>> {
+        self.referrers.get(attr)
+    }
+
     pub fn is_internal(&self) -> bool {
         match self.origin {
             EventOrigin::Internal => true,
@@ -824,6 +884,9 @@ impl ModifyEvent {
 pub struct AuthEventStepInit {
     pub name: String,
     pub appid: Option<String>,
+    // The account of the session that is re-authenticating. It is found by uuid
+    // rather than by name.
+    pub reauth: Option<Uuid>,
 }
 
 #[derive(Debug)]
@@ -839,7 +902,11 @@ pub enum AuthEventStep {
 }
 
 impl AuthEventStep {
-    fn from_authstep(aus: AuthStep, sid: Option<Uuid>) -> Result<Self, OperationError> {
+    fn from_authstep(
+        aus: AuthStep,
+        sid: Option<Uuid>,
+        uat: Option<UserAuthToken>,
+    ) -> Result<Self, OperationError> {
         match aus {
             AuthStep::Init(name, appid) => {
                 if sid.is_some() {
@@ -847,8 +914,32 @@ impl AuthEventStep {
                         "session id present in init".to_string(),
                     ))
                 } else {
-                    Ok(AuthEventStep::Init(AuthEventStepInit { name, appid }))
+                    Ok(AuthEventStep::Init(AuthEventStepInit {
+                        name,
+                        appid,
+                        reauth: None,
+                    }))
+                }
+            }
+            AuthStep::Reauth => {
+                if sid.is_some() {
+                    return Err(OperationError::InvalidAuthState(
+                        "session id present in reauth".to_string(),
+                    ));
                 }
+                let uat = uat.ok_or(OperationError::NotAuthenticated)?;
+                // Only a session from the primary credentials may be elevated, which
+                // excludes anonymous and application sessions.
+                if !uat.claims.iter().any(|c| c.name == CLAIM_INTERACTIVE) {
+                    return Err(OperationError::AccessDenied);
+                }
+                let u =
+                    Uuid::parse_str(uat.uuid.as_str()).map_err(|_| OperationError::InvalidUuid)?;
+                Ok(AuthEventStep::Init(AuthEventStepInit {
+                    name: uat.name,
+                    appid: None,
+                    reauth: Some(u),
+                }))
             }
             AuthStep::Creds(creds) => match sid {
                 Some(ssid) => Ok(AuthEventStep::Creds(AuthEventStepCreds {
@@ -867,6 +958,7 @@ impl AuthEventStep {
         AuthEventStep::Init(AuthEventStepInit {
             name: "anonymous".to_string(),
             appid: None,
+            reauth: None,
         })
     }
 
@@ -875,6 +967,25 @@ impl AuthEventStep {
         AuthEventStep::Init(AuthEventStepInit {
             name: name.to_string(),
             appid: None,
+            reauth: None,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn reauth_init(name: &str, uuid: Uuid) -> Self {
+        AuthEventStep::Init(AuthEventStepInit {
+            name: name.to_string(),
+            appid: None,
+            reauth: Some(uuid),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn named_app_init(name: &str, appid: &str) -> Self {
+        AuthEventStep::Init(AuthEventStepInit {
+            name: name.to_string(),
+            appid: Some(appid.to_string()),
+            reauth: None,
         })
     }
 
@@ -893,6 +1004,36 @@ impl AuthEventStep {
             creds: vec![AuthCredential::Password(pw.to_string())],
         })
     }
+
+    #[cfg(test)]
+    pub fn cred_step_password_backup_code(sid: Uuid, pw: &str, code: &str) -> Self {
+        AuthEventStep::Creds(AuthEventStepCreds {
+            sessionid: sid,
+            creds: vec![
+                AuthCredential::BackupCode(code.to_string()),
+                AuthCredential::Password(pw.to_string()),
+            ],
+        })
+    }
+
+    #[cfg(test)]
+    pub fn cred_step_password_totp(sid: Uuid, pw: &str, totp: u32) -> Self {
+        AuthEventStep::Creds(AuthEventStepCreds {
+            sessionid: sid,
+            creds: vec![
+                AuthCredential::TOTP(totp),
+                AuthCredential::Password(pw.to_string()),
+            ],
+        })
+    }
+
+    #[cfg(test)]
+    pub fn cred_step_webauthn(sid: Uuid, resp: PublicKeyCredential) -> Self {
+        AuthEventStep::Creds(AuthEventStepCreds {
+            sessionid: sid,
+            creds: vec![AuthCredential::Webauthn(resp)],
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -906,7 +1047,7 @@ impl AuthEvent {
     pub fn from_message(msg: AuthMessage) -> Result<Self, OperationError> {
         Ok(AuthEvent {
             event: None,
-            step: AuthEventStep::from_authstep(msg.req.step, msg.sessionid)?,
+            step: AuthEventStep::from_authstep(msg.req.step, msg.sessionid, msg.uat)?,
         })
     }
 
@@ -926,6 +1067,22 @@ impl AuthEvent {
         }
     }
 
+    #[cfg(test)]
+    pub fn reauth_init(name: &str, uuid: Uuid) -> Self {
+        AuthEvent {
+            event: None,
+            step: AuthEventStep::reauth_init(name, uuid),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn named_app_init(name: &str, appid: &str) -> Self {
+        AuthEvent {
+            event: None,
+            step: AuthEventStep::named_app_init(name, appid),
+        }
+    }
+
     #[cfg(test)]
     pub fn cred_step_anonymous(sid: Uuid) -> Self {
         AuthEvent {
@@ -941,6 +1098,30 @@ impl AuthEvent {
             step: AuthEventStep::cred_step_password(sid, pw),
         }
     }
+
+    #[cfg(test)]
+    pub fn cred_step_password_backup_code(sid: Uuid, pw: &str, code: &str) -> Self {
+        AuthEvent {
+            event: None,
+            step: AuthEventStep::cred_step_password_backup_code(sid, pw, code),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn cred_step_password_totp(sid: Uuid, pw: &str, totp: u32) -> Self {
+        AuthEvent {
+            event: None,
+            step: AuthEventStep::cred_step_password_totp(sid, pw, totp),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn cred_step_webauthn(sid: Uuid, resp: PublicKeyCredential) -> Self {
+        AuthEvent {
+            event: None,
+            step: AuthEventStep::cred_step_webauthn(sid, resp),
+        }
+    }
 }
 
 // Probably should be a struct with the session id present.
@@ -951,10 +1132,11 @@ pub struct AuthResult {
 }
 
 impl AuthResult {
-    pub fn response(self) -> AuthResponse {
+    pub fn response(self, bearer: Option<String>) -> AuthResponse {
         AuthResponse {
             sessionid: self.sessionid,
             state: self.state,
+            bearer,
         }
     }
 }
@@ -1019,6 +1201,40 @@ impl PurgeRecycledEvent {
     }
 }
 
+#[derive(Debug)]
+pub struct PurgeExpiredAcpEvent {
+    pub event: Event,
+}
+
+impl Message for PurgeExpiredAcpEvent {
+    type Result = ();
+}
+
+impl PurgeExpiredAcpEvent {
+    pub fn new() -> Self {
+        PurgeExpiredAcpEvent {
+            event: Event::from_internal(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RotateJwsKeysEvent {
+    pub event: Event,
+}
+
+impl Message for RotateJwsKeysEvent {
+    type Result = ();
+}
+
+impl RotateJwsKeysEvent {
+    pub fn new() -> Self {
+        RotateJwsKeysEvent {
+            event: Event::from_internal(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ReviveRecycledEvent {
     pub event: Event,
@@ -1061,3 +1277,71 @@ impl ReviveRecycledEvent {
         }
     }
 }
+
+#[derive(Debug)]
+pub struct AccessExplainEvent {
+    // The identity requesting the explanation.
+    pub event: Event,
+    // The identity whose access is being explained.
+    pub identity: Event,
+    pub target: Entry<EntrySealed, EntryCommitted>,
+    pub operation: AccessOperation,
+}
+
+impl AccessExplainEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerReadTransaction,
+        event: Event,
+        identity: Uuid,
+        target: Uuid,
+        operation: AccessOperation,
+    ) -> Result<Self, OperationError> {
+        // Both entries are found as the requester, so that nothing they can't search
+        // for is explained to them.
+        let ident_entry = Self::search_as(audit, qs, &event, identity)?;
+        let target = Self::search_as(audit, qs, &event, target)?;
+
+        let identity = Event::from_user_entry(audit, qs, ident_entry)?;
+
+        Ok(AccessExplainEvent {
+            event,
+            identity,
+            target,
+            operation,
+        })
+    }
+
+    fn search_as(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerReadTransaction,
+        event: &Event,
+        u: Uuid,
+    ) -> Result<Entry<EntrySealed, EntryCommitted>, OperationError> {
+        let mut entries = try_audit!(
+            audit,
+            qs.impersonate_search(
+                audit,
+                filter!(f_eq("uuid", PartialValue::new_uuid(u))),
+                filter_all!(f_eq("uuid", PartialValue::new_uuid(u))),
+                event,
+            )
+        );
+        entries.pop().ok_or(OperationError::AccessDenied)
+    }
+
+    #[cfg(test)]
+    pub unsafe fn new_impersonate_entry(
+        e: Entry<EntrySealed, EntryCommitted>,
+        identity: Entry<EntrySealed, EntryCommitted>,
+        target: Entry<EntrySealed, EntryCommitted>,
+        operation: AccessOperation,
+    ) -> Self {
+        AccessExplainEvent {
+            event: Event::from_impersonate_entry(e),
+            identity: Event::from_impersonate_entry(identity),
+            target,
+            operation,
+        }
+    }
+}
@@ -14,7 +14,7 @@ use crate::schema::SchemaTransaction;
 use crate::server::{
     QueryServerReadTransaction, QueryServerTransaction, QueryServerWriteTransaction,
 };
-use crate::value::{IndexType, PartialValue};
+use crate::value::{IndexType, PartialValue, SyntaxType};
 use kanidm_proto::v1::Filter as ProtoFilter;
 use kanidm_proto::v1::{OperationError, SchemaError};
 use std::cmp::{Ordering, PartialOrd};
@@ -66,6 +66,16 @@ pub fn f_self<'a>() -> FC<'a> {
     FC::SelfUUID
 }
 
+#[allow(dead_code)]
+pub fn f_self_ref(a: &str) -> FC {
+    FC::SelfRef(a)
+}
+
+#[allow(dead_code)]
+pub fn f_self_ref_member(a: &str) -> FC {
+    FC::SelfRefMember(a)
+}
+
 pub fn f_id(id: &str) -> FC<'static> {
     match Uuid::parse_str(id) {
         Ok(u) => FC::Or(vec![
@@ -88,6 +98,8 @@ pub enum FC<'a> {
     And(Vec<FC<'a>>),
     AndNot(Box<FC<'a>>),
     SelfUUID,
+    SelfRef(&'a str),
+    SelfRefMember(&'a str),
     // Not(Box<FC>),
 }
 
@@ -103,12 +115,18 @@ enum FilterComp {
     And(Vec<FilterComp>),
     AndNot(Box<FilterComp>),
     SelfUUID,
+    // Entries that reference the identity via this attribute.
+    SelfRef(String),
+    // Members of the entries that reference the identity via this attribute. Those
+    // entries need a search, so they are found when the event is created.
+    SelfRefMember(String),
     // Does this mean we can add a true not to the type now?
     // Not(Box<FilterComp>),
 }
 
-// This is the fully resolved internal representation. Note the lack of Not and selfUUID
-// because these are resolved into And(Pres(class), AndNot(term)) and Eq(uuid, ...).
+// This is the fully resolved internal representation. Note the lack of Not, SelfUUID,
+// SelfRef and SelfRefMember because these are resolved into And(Pres(class), AndNot(term)),
+// Eq(uuid, ...), Eq(attr, ...) and Or(Eq(memberof, ...), ...).
 // Importantly, we make this accessible to Entry so that it can then match on filters
 // properly.
 #[derive(Debug, Clone)]
@@ -240,6 +258,12 @@ impl Filter<FilterValid> {
         self.state.inner.get_attr_set(&mut r_set);
         r_set
     }
+
+    // The attributes named by SelfRefMember terms, whose referrers an event must have
+    // found to resolve this filter.
+    pub fn get_self_ref_member_attrs<'a>(&'a self, r_set: &mut BTreeSet<&'a str>) {
+        self.state.inner.get_self_ref_member_attrs(r_set)
+    }
 }
 
 impl Filter<FilterInvalid> {
@@ -408,6 +432,8 @@ impl FilterComp {
             FC::And(v) => FilterComp::And(v.into_iter().map(FilterComp::new).collect()),
             FC::AndNot(b) => FilterComp::AndNot(Box::new(FilterComp::new(*b))),
             FC::SelfUUID => FilterComp::SelfUUID,
+            FC::SelfRef(a) => FilterComp::SelfRef(a.to_string()),
+            FC::SelfRefMember(a) => FilterComp::SelfRefMember(a.to_string()),
         }
     }
 
@@ -448,6 +474,25 @@ impl FilterComp {
             FilterComp::SelfUUID => {
                 r_set.insert("uuid");
             }
+            FilterComp::SelfRef(attr) => {
+                r_set.insert(attr.as_str());
+            }
+            FilterComp::SelfRefMember(_) => {
+                r_set.insert("memberof");
+            }
+        }
+    }
+
+    fn get_self_ref_member_attrs<'a>(&'a self, r_set: &mut BTreeSet<&'a str>) {
+        match self {
+            FilterComp::Or(vs) | FilterComp::And(vs) => {
+                vs.iter().for_each(|f| f.get_self_ref_member_attrs(r_set))
+            }
+            FilterComp::AndNot(f) => f.get_self_ref_member_attrs(r_set),
+            FilterComp::SelfRefMember(attr) => {
+                r_set.insert(attr.as_str());
+            }
+            _ => {}
         }
     }
 
@@ -558,6 +603,35 @@ impl FilterComp {
                 // Pretty hard to mess this one up ;)
                 Ok(FilterComp::SelfUUID)
             }
+            FilterComp::SelfRef(attr) => {
+                let attr_norm = schema.normalise_attr_name(attr);
+                // This resolves to a reference to the identity, so the attr
+                // must be able to hold one.
+                match schema_attributes.get(&attr_norm) {
+                    Some(schema_a) => {
+                        if schema_a.syntax == SyntaxType::REFERENCE_UUID {
+                            Ok(FilterComp::SelfRef(attr_norm))
+                        } else {
+                            Err(SchemaError::InvalidAttributeSyntax)
+                        }
+                    }
+                    None => Err(SchemaError::InvalidAttribute),
+                }
+            }
+            FilterComp::SelfRefMember(attr) => {
+                let attr_norm = schema.normalise_attr_name(attr);
+                // As for SelfRef, the attr must be able to reference the identity.
+                match schema_attributes.get(&attr_norm) {
+                    Some(schema_a) => {
+                        if schema_a.syntax == SyntaxType::REFERENCE_UUID {
+                            Ok(FilterComp::SelfRefMember(attr_norm))
+                        } else {
+                            Err(SchemaError::InvalidAttributeSyntax)
+                        }
+                    }
+                    None => Err(SchemaError::InvalidAttribute),
+                }
+            }
         }
     }
 
@@ -572,6 +646,9 @@ impl FilterComp {
                 FilterComp::Sub(a.clone(), qs.clone_partialvalue(audit, a, v)?)
             }
             ProtoFilter::Pres(a) => FilterComp::Pres(a.clone()),
+            ProtoFilter::LessThan(a, v) => {
+                FilterComp::LessThan(a.clone(), qs.clone_partialvalue(audit, a, v)?)
+            }
             ProtoFilter::Or(l) => FilterComp::Or(
                 l.iter()
                     .map(|f| Self::from_ro(audit, f, qs))
@@ -584,6 +661,8 @@ impl FilterComp {
             ),
             ProtoFilter::AndNot(l) => FilterComp::AndNot(Box::new(Self::from_ro(audit, l, qs)?)),
             ProtoFilter::SelfUUID => FilterComp::SelfUUID,
+            ProtoFilter::SelfRef(a) => FilterComp::SelfRef(a.clone()),
+            ProtoFilter::SelfRefMember(a) => FilterComp::SelfRefMember(a.clone()),
         })
     }
 
@@ -598,6 +677,9 @@ impl FilterComp {
                 FilterComp::Sub(a.clone(), qs.clone_partialvalue(audit, a, v)?)
             }
             ProtoFilter::Pres(a) => FilterComp::Pres(a.clone()),
+            ProtoFilter::LessThan(a, v) => {
+                FilterComp::LessThan(a.clone(), qs.clone_partialvalue(audit, a, v)?)
+            }
             ProtoFilter::Or(l) => FilterComp::Or(
                 l.iter()
                     .map(|f| Self::from_rw(audit, f, qs))
@@ -610,6 +692,8 @@ impl FilterComp {
             ),
             ProtoFilter::AndNot(l) => FilterComp::AndNot(Box::new(Self::from_rw(audit, l, qs)?)),
             ProtoFilter::SelfUUID => FilterComp::SelfUUID,
+            ProtoFilter::SelfRef(a) => FilterComp::SelfRef(a.clone()),
+            ProtoFilter::SelfRefMember(a) => FilterComp::SelfRefMember(a.clone()),
         })
     }
 }
@@ -760,6 +844,10 @@ impl FilterResolved {
                 )))
             }
             FilterComp::SelfUUID => panic!("Not possible to resolve SelfUUID in from_invalid!"),
+            FilterComp::SelfRef(_) => panic!("Not possible to resolve SelfRef in from_invalid!"),
+            FilterComp::SelfRefMember(_) => {
+                panic!("Not possible to resolve SelfRefMember in from_invalid!")
+            }
         }
     }
 
@@ -821,6 +909,34 @@ impl FilterResolved {
                 }
                 _ => None,
             },
+            FilterComp::SelfRef(a) => match &ev.origin {
+                EventOrigin::User(e) => {
+                    let idx = idxmeta.contains(&(&a, &IndexType::EQUALITY));
+                    Some(FilterResolved::Eq(
+                        a,
+                        PartialValue::new_refer(*e.get_uuid()),
+                        idx,
+                    ))
+                }
+                _ => None,
+            },
+            FilterComp::SelfRefMember(a) => {
+                let memberof_s = "memberof".to_string();
+                let idx = idxmeta.contains(&(&memberof_s, &IndexType::EQUALITY));
+                ev.get_referrers(a.as_str()).map(|refs| {
+                    FilterResolved::Or(
+                        refs.iter()
+                            .map(|u| {
+                                FilterResolved::Eq(
+                                    memberof_s.clone(),
+                                    PartialValue::new_refer(*u),
+                                    idx,
+                                )
+                            })
+                            .collect(),
+                    )
+                })
+            }
         }
     }
 
@@ -861,6 +977,27 @@ impl FilterResolved {
                 )),
                 _ => None,
             },
+            FilterComp::SelfRef(a) => match &ev.origin {
+                EventOrigin::User(e) => Some(FilterResolved::Eq(
+                    a,
+                    PartialValue::new_refer(*e.get_uuid()),
+                    false,
+                )),
+                _ => None,
+            },
+            FilterComp::SelfRefMember(a) => ev.get_referrers(a.as_str()).map(|refs| {
+                FilterResolved::Or(
+                    refs.iter()
+                        .map(|u| {
+                            FilterResolved::Eq(
+                                "memberof".to_string(),
+                                PartialValue::new_refer(*u),
+                                false,
+                            )
+                        })
+                        .collect(),
+                )
+            }),
         }
     }
 
@@ -937,8 +1074,10 @@ impl FilterResolved {
 
 #[cfg(test)]
 mod tests {
+    use crate::constants::JSON_ADMIN_V1;
     use crate::entry::{Entry, EntryNew, EntrySealed};
-    use crate::filter::{Filter, FilterInvalid};
+    use crate::event::Event;
+    use crate::filter::{f_self_ref, f_self_ref_member, Filter, FilterInvalid};
     use crate::value::PartialValue;
     use std::cmp::{Ordering, PartialOrd};
     use std::collections::BTreeSet;
@@ -1426,4 +1565,58 @@ mod tests {
 
         assert!(f_t2a.get_attr_set() == f_expect);
     }
+
+    #[test]
+    fn test_self_ref_resolve() {
+        // admin is the manager of e1 but not e2.
+        let e1: Entry<EntrySealed, EntryNew> = unsafe {
+            Entry::unsafe_from_entry_str(
+                r#"{
+            "valid": {
+                "uuid": "db237e8a-0079-4b8c-8a56-593b22aa44d1"
+            },
+            "state": null,
+            "attrs": {
+                "class": ["person"],
+                "uuid": ["db237e8a-0079-4b8c-8a56-593b22aa44d1"],
+                "manager": ["00000000-0000-0000-0000-000000000000"]
+            }
+        }"#,
+            )
+            .into_sealed_new()
+        };
+
+        let e2: Entry<EntrySealed, EntryNew> = unsafe {
+            Entry::unsafe_from_entry_str(
+                r#"{
+            "valid": {
+                "uuid": "4b6228ab-1dbe-42a4-a9f5-f6368222438e"
+            },
+            "state": null,
+            "attrs": {
+                "class": ["person"],
+                "uuid": ["4b6228ab-1dbe-42a4-a9f5-f6368222438e"],
+                "manager": ["db237e8a-0079-4b8c-8a56-593b22aa44d1"]
+            }
+        }"#,
+            )
+            .into_sealed_new()
+        };
+
+        let ev_admin = unsafe { Event::from_impersonate_entry_ser(JSON_ADMIN_V1) };
+        let f_manager = unsafe { filter_valid!(f_self_ref("manager")) };
+
+        let f_res = f_manager.resolve(&ev_admin, None).expect("resolve failed");
+        assert!(e1.entry_match_no_index(&f_res));
+        assert!(!e2.entry_match_no_index(&f_res));
+
+        // An internal event has no identity to be relative to.
+        assert!(f_manager.resolve(&Event::from_internal(), None).is_err());
+
+        // The owned entries were not found when this event was made, so the members of
+        // them can't be resolved.
+        let f_owned = unsafe { filter_valid!(f_self_ref_member("owner")) };
+        assert!(f_owned.resolve(&ev_admin, None).is_err());
+        assert!(f_owned.resolve(&Event::from_internal(), None).is_err());
+    }
 }
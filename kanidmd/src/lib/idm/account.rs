@@ -1,19 +1,24 @@
 use crate::entry::{Entry, EntryCommitted, EntrySealed};
 use kanidm_proto::v1::OperationError;
 
-use kanidm_proto::v1::UserAuthToken;
+use kanidm_proto::v1::{Application, UserAuthToken};
 
 use crate::audit::AuditScope;
-use crate::constants::UUID_ANONYMOUS;
+use crate::constants::{CREDENTIAL_RESET_TOKEN_VALIDITY, UUID_ANONYMOUS};
 use crate::credential::totp::TOTP;
-use crate::credential::Credential;
+use crate::credential::{Credential, CryptoPolicy, Password};
 use crate::idm::claim::Claim;
 use crate::idm::group::Group;
-use crate::modify::{ModifyInvalid, ModifyList};
+use crate::idm::pwpolicy::PasswordPolicy;
+use crate::idm::session::Session;
+use crate::modify::{m_pres, m_purge, m_remove, ModifyInvalid, ModifyList};
 use crate::server::{QueryServerReadTransaction, QueryServerWriteTransaction};
 use crate::value::{PartialValue, Value};
 
+use std::time::Duration;
 use uuid::Uuid;
+use webauthn_rs::proto::Credential as WebauthnCredential;
+use webauthn_rs::proto::{Counter, CredentialID};
 
 lazy_static! {
     static ref PVCLASS_ACCOUNT: PartialValue = PartialValue::new_class("account");
@@ -40,10 +45,45 @@ macro_rules! try_from_entry {
             OperationError::InvalidAccountState("Missing attribute: displayname".to_string()),
         )?;
 
-        let primary = $value
-            .get_ava_single_credential("primary_credential")
+        // The tag of each primary credential is its label.
+        let credentials = $value
+            .get_ava("primary_credential")
+            .map(|vs| {
+                vs.into_iter()
+                    .filter_map(|v| {
+                        v.to_credential()
+                            .map(|c| (v.to_proto_string_clone(), c.clone()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new);
+
+        // The tag of each application password is its label.
+        let app_passwords = $value
+            .get_ava("app_password")
+            .map(|vs| {
+                vs.into_iter()
+                    .filter_map(|v| {
+                        v.to_credential()
+                            .map(|c| (v.to_proto_string_clone(), c.clone()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new);
+
+        let credential_reset_token = $value
+            .get_ava_single_credential("credential_reset_token")
             .map(|v| v.clone());
 
+        let sessions = $value
+            .get_ava("session")
+            .map(|vs| {
+                vs.into_iter()
+                    .filter_map(|v| v.to_session().cloned())
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new);
+
         let spn = $value
             .get_ava_single("spn")
             .map(|s| {
@@ -54,6 +94,10 @@ macro_rules! try_from_entry {
                 "Missing attribute: spn".to_string(),
             ))?;
 
+        let valid_from = $value.get_ava_single_duration("account_valid_from");
+
+        let expire = $value.get_ava_single_duration("account_expire");
+
         // Resolved by the caller
         let groups = $groups;
 
@@ -64,7 +108,12 @@ macro_rules! try_from_entry {
             name: name,
             displayname: displayname,
             groups: groups,
-            primary: primary,
+            credentials: credentials,
+            app_passwords: app_passwords,
+            credential_reset_token: credential_reset_token,
+            sessions: sessions,
+            valid_from: valid_from,
+            expire: expire,
             spn: spn,
         })
     }};
@@ -82,16 +131,37 @@ pub(crate) struct Account {
     pub displayname: String,
     pub uuid: Uuid,
     pub groups: Vec<Group>,
-    pub primary: Option<Credential>,
-    // primary: Credential
-    // app_creds: Vec<Credential>
-    // account expiry? (as opposed to cred expiry)
+    // The credentials valid for interactive login, by their label. Any one of them that
+    // isn't locked may be used, and "primary" is the one changed when no label is given.
+    pub credentials: Vec<(String, Credential)>,
+    // Application passwords, by their label. These are only valid when authenticating
+    // for the application they name.
+    pub app_passwords: Vec<(String, Credential)>,
+    // A token an administrator issued, that lets the credentials be reset without
+    // authenticating. The password set time is when it was issued.
+    pub credential_reset_token: Option<Credential>,
+    // The sessions bearer tokens were issued for, which may have expired.
+    pub sessions: Vec<Session>,
+    pub valid_from: Option<Duration>,
+    pub expire: Option<Duration>,
     pub spn: String,
     // TODO: When you add mail, you should update the check to zxcvbn
     // to include these.
     // pub mail: Vec<String>
 }
 
+/// Is ct within the window that an account may authenticate in? The account is valid from
+/// `valid_from` inclusive, until `expire` exclusive, and a missing bound is unbounded.
+pub(crate) fn is_within_valid_time(
+    valid_from: Option<Duration>,
+    expire: Option<Duration>,
+    ct: &Duration,
+) -> bool {
+    let after_start = valid_from.map(|vf| vf <= *ct).unwrap_or(true);
+    let before_end = expire.map(|ex| *ct < ex).unwrap_or(true);
+    after_start && before_end
+}
+
 impl Account {
     pub(crate) fn try_from_entry_ro(
         au: &mut AuditScope,
@@ -118,8 +188,12 @@ impl Account {
         try_from_entry!(value, vec![])
     }
 
-    // Could this actually take a claims list and application instead?
-    pub(crate) fn to_userauthtoken(&self, claims: Vec<Claim>) -> Option<UserAuthToken> {
+    pub(crate) fn to_userauthtoken(
+        &self,
+        claims: Vec<Claim>,
+        application: Option<Application>,
+        warnings: Vec<String>,
+    ) -> Option<UserAuthToken> {
         // This could consume self?
         // The cred handler provided is what authenticated this user, so we can use it to
         // process what the proper claims should be.
@@ -130,9 +204,10 @@ impl Account {
             name: self.name.clone(),
             displayname: self.name.clone(),
             uuid: self.uuid.to_hyphenated_ref().to_string(),
-            application: None,
+            application,
             groups: self.groups.iter().map(|g| g.to_proto()).collect(),
             claims: claims.iter().map(|c| c.to_proto()).collect(),
+            warnings,
         })
     }
 
@@ -140,46 +215,124 @@ impl Account {
         self.uuid == *UUID_ANONYMOUS
     }
 
+    pub fn is_within_valid_time(&self, ct: &Duration) -> bool {
+        is_within_valid_time(self.valid_from, self.expire, ct)
+    }
+
+    /// Has any credential of the account used this password? A reused password must not be
+    /// accepted just because it's set under a new label.
+    pub(crate) fn is_password_reused(&self, cleartext: &str) -> bool {
+        self.credentials
+            .iter()
+            .any(|(_, cred)| cred.is_password_reused(cleartext))
+    }
+
+    pub(crate) fn credential(&self, label: &str) -> Option<&Credential> {
+        self.credentials
+            .iter()
+            .find(|(l, _)| l == label)
+            .map(|(_, c)| c)
+    }
+
+    /// Find a credential by its uuid or its label.
+    pub(crate) fn find_credential(&self, cred_id: &str) -> Option<&(String, Credential)> {
+        match Uuid::parse_str(cred_id) {
+            Ok(u) => self.credentials.iter().find(|(_, c)| c.uuid == u),
+            Err(_) => {
+                let label = cred_id.to_lowercase();
+                self.credentials.iter().find(|(l, _)| l == &label)
+            }
+        }
+    }
+
+    /// The label of the credential a change applies to. This is the primary credential
+    /// unless one is named by its uuid or label, and a label that doesn't exist yet is
+    /// a new credential.
+    pub(crate) fn credential_label(&self, cred_id: Option<&str>) -> Result<String, OperationError> {
+        let cred_id = match cred_id {
+            Some(cred_id) => cred_id,
+            None => return Ok("primary".to_string()),
+        };
+        if let Some((label, _)) = self.find_credential(cred_id) {
+            return Ok(label.clone());
+        }
+        // Labels beginning with _ would be confused with the other credential routes.
+        if cred_id.is_empty() || cred_id.starts_with('_') || Uuid::parse_str(cred_id).is_ok() {
+            return Err(OperationError::InvalidAttribute(format!(
+                "Invalid credential label {}",
+                cred_id
+            )));
+        }
+        Ok(cred_id.to_lowercase())
+    }
+
+    /// The labels of the totp devices registered to any of the credentials. These are
+    /// unique across the account, so that a code can be traced back to its device.
+    pub(crate) fn totp_labels(&self) -> Vec<String> {
+        self.credentials
+            .iter()
+            .flat_map(|(_, c)| c.totp.keys().cloned())
+            .collect()
+    }
+
+    /// Is token the credential reset token of this account, and was it issued recently
+    /// enough to still be redeemed at ct?
+    pub(crate) fn is_credential_reset_token_valid(&self, token: &str, ct: &Duration) -> bool {
+        match &self.credential_reset_token {
+            // A token without an issue time can't be checked for expiry, so it's never valid.
+            Some(cred) => match (&cred.password, cred.password_set) {
+                (Some(pw), Some(issued)) => {
+                    *ct < issued + Duration::from_secs(CREDENTIAL_RESET_TOKEN_VALIDITY)
+                        && pw.verify(token)
+                }
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    pub(crate) fn password_policy(&self) -> PasswordPolicy {
+        PasswordPolicy::from_groups(self.groups.as_slice())
+    }
+
     pub(crate) fn gen_password_mod(
         &self,
+        label: &str,
         cleartext: &str,
-        appid: &Option<String>,
+        ct: &Duration,
+        crypto_policy: &CryptoPolicy,
     ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
-        // What should this look like? Probablf an appid + stuff -> modify?
-        // then the caller has to apply the modify under the requests event
-        // for proper auth checks.
-        match appid {
-            Some(_) => Err(OperationError::InvalidState),
-            None => {
-                // TODO: Enforce PW policy. Can we allow this change?
-                match &self.primary {
-                    // Change the cred
-                    Some(primary) => {
-                        let ncred = primary.set_password(cleartext);
-                        let vcred = Value::new_credential("primary", ncred);
-                        Ok(ModifyList::new_purge_and_set("primary_credential", vcred))
-                    }
-                    // Make a new credential instead
-                    None => {
-                        let ncred = Credential::new_password_only(cleartext);
-                        let vcred = Value::new_credential("primary", ncred);
-                        Ok(ModifyList::new_purge_and_set("primary_credential", vcred))
-                    }
-                }
-            } // no appid
+        // TODO: Enforce PW policy. Can we allow this change?
+        let ncred = match self.credential(label) {
+            // Change the cred
+            Some(cred) => cred.set_password(crypto_policy, cleartext),
+            // Make a new credential instead
+            None => Credential::new_password_only(crypto_policy, cleartext),
         }
+        .update_password_set(ct);
+        Ok(gen_credential_mod(label, ncred))
     }
 
     pub(crate) fn gen_totp_mod(
         &self,
+        label: &str,
         token: TOTP,
+        backup_codes: &[String],
+        crypto_policy: &CryptoPolicy,
     ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
-        match &self.primary {
+        if self.totp_labels().iter().any(|l| l == token.label()) {
+            return Err(OperationError::InvalidAttribute(format!(
+                "TOTP label {} already exists",
+                token.label()
+            )));
+        }
+        match self.credential(label) {
             // Change the cred
-            Some(primary) => {
-                let ncred = primary.update_totp(token);
-                let vcred = Value::new_credential("primary", ncred);
-                Ok(ModifyList::new_purge_and_set("primary_credential", vcred))
+            Some(cred) => {
+                let ncred = cred
+                    .add_totp(token)?
+                    .update_backup_codes(crypto_policy, backup_codes);
+                Ok(gen_credential_mod(label, ncred))
             }
             None => {
                 // No credential exists, we can't supplementy it.
@@ -188,6 +341,279 @@ impl Account {
         }
     }
 
+    pub(crate) fn gen_totp_remove_mod(
+        &self,
+        label: &str,
+        totp_label: &str,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        match self.credential(label) {
+            Some(cred) => {
+                let ncred = cred.remove_totp(totp_label)?;
+                Ok(gen_credential_mod(label, ncred))
+            }
+            None => Err(OperationError::InvalidState),
+        }
+    }
+
+    pub(crate) fn gen_totp_step_mod(
+        &self,
+        totp_label: &str,
+        counter: u64,
+    ) -> Option<ModifyList<ModifyInvalid>> {
+        self.credentials.iter().find_map(|(label, cred)| {
+            cred.update_totp_step(totp_label, counter)
+                .map(|ncred| gen_credential_mod(label, ncred))
+        })
+    }
+
+    pub(crate) fn gen_backup_codes_mod(
+        &self,
+        label: &str,
+        backup_codes: &[String],
+        crypto_policy: &CryptoPolicy,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        match self.credential(label) {
+            // Backup codes stand in for the totp, so there must be one.
+            Some(cred) if !cred.totp.is_empty() => {
+                let ncred = cred.update_backup_codes(crypto_policy, backup_codes);
+                Ok(gen_credential_mod(label, ncred))
+            }
+            _ => Err(OperationError::InvalidAccountState(
+                "Backup codes require a totp to be registered".to_string(),
+            )),
+        }
+    }
+
+    pub(crate) fn gen_backup_code_remove_mod(
+        &self,
+        code: &Password,
+    ) -> Option<ModifyList<ModifyInvalid>> {
+        // A code is only ever part of one credential.
+        self.credentials.iter().find_map(|(label, cred)| {
+            cred.remove_backup_code(code)
+                .map(|ncred| gen_credential_mod(label, ncred))
+        })
+    }
+
+    /// Is this authenticator registered to any of the credentials?
+    pub(crate) fn has_webauthn_token(&self, cid: &CredentialID) -> bool {
+        self.credentials
+            .iter()
+            .any(|(_, cred)| cred.has_webauthn_token(cid))
+    }
+
+    pub(crate) fn gen_webauthn_mod(
+        &self,
+        label: &str,
+        token_label: String,
+        cred: WebauthnCredential,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        // The same authenticator can't be registered to two credentials.
+        if self.has_webauthn_token(&cred.cred_id) {
+            return Err(OperationError::Webauthn);
+        }
+        let ncred = match self.credential(label) {
+            Some(c) => c.add_webauthn_token(token_label, cred)?,
+            // A token is enough to authenticate by itself.
+            None => Credential::new_webauthn_only(token_label, cred),
+        };
+        Ok(gen_credential_mod(label, ncred))
+    }
+
+    pub(crate) fn gen_webauthn_remove_mod(
+        &self,
+        label: &str,
+        token_label: &str,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        match self.credential(label) {
+            Some(cred) => {
+                let ncred = cred.remove_webauthn_token(token_label)?;
+                Ok(gen_credential_mod(label, ncred))
+            }
+            None => Err(OperationError::InvalidState),
+        }
+    }
+
+    pub(crate) fn gen_webauthn_counter_mod(
+        &self,
+        cid: &CredentialID,
+        counter: Counter,
+    ) -> Option<ModifyList<ModifyInvalid>> {
+        self.credentials.iter().find_map(|(label, cred)| {
+            cred.update_webauthn_counter(cid, counter)
+                .map(|ncred| gen_credential_mod(label, ncred))
+        })
+    }
+
+    pub(crate) fn gen_password_upgrade_mod(
+        &self,
+        old: &Password,
+        new: Password,
+    ) -> Option<ModifyList<ModifyInvalid>> {
+        if let Some(modlist) = self.credentials.iter().find_map(|(label, cred)| {
+            cred.upgrade_password(old, new.clone())
+                .map(|ncred| gen_credential_mod(label, ncred))
+        }) {
+            return Some(modlist);
+        }
+        // Otherwise it may have been an application password.
+        self.app_passwords.iter().find_map(|(label, cred)| {
+            cred.upgrade_password(old, new.clone()).map(|ncred| {
+                ModifyList::new_list(vec![
+                    m_remove("app_password", &PartialValue::new_credential_tag(label)),
+                    m_pres("app_password", &Value::new_credential(label, ncred)),
+                ])
+            })
+        })
+    }
+
+    // Is there a credential other than label that can still be used to authenticate?
+    fn has_other_unlocked_credential(&self, label: &str) -> bool {
+        self.credentials
+            .iter()
+            .any(|(l, c)| l != label && !c.locked)
+    }
+
+    pub(crate) fn gen_credential_lock_mod(
+        &self,
+        cred_id: &str,
+        locked: bool,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        let (label, cred) = self
+            .find_credential(cred_id)
+            .ok_or(OperationError::NoMatchingEntries)?;
+        // Like removing the last webauthn token, this would lock the account out entirely.
+        if locked && !self.has_other_unlocked_credential(label) {
+            return Err(OperationError::InvalidAccountState(
+                "Unable to lock the last unlocked credential".to_string(),
+            ));
+        }
+        Ok(gen_credential_mod(label, cred.set_locked(locked)))
+    }
+
+    pub(crate) fn gen_credential_remove_mod(
+        &self,
+        cred_id: &str,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        let (label, cred) = self
+            .find_credential(cred_id)
+            .ok_or(OperationError::NoMatchingEntries)?;
+        if !cred.locked && !self.has_other_unlocked_credential(label) {
+            return Err(OperationError::InvalidAccountState(
+                "Unable to remove the last unlocked credential".to_string(),
+            ));
+        }
+        let mut mods = vec![m_remove(
+            "primary_credential",
+            &PartialValue::new_credential_tag(label),
+        )];
+        // Keep the removed credential's passwords in the history of a remaining one,
+        // preferring the primary, so that removing it doesn't allow them to be reused.
+        let heir = self
+            .credentials
+            .iter()
+            .filter(|(l, _)| l != label)
+            .min_by_key(|(l, _)| l != "primary");
+        if let Some((heir_label, heir)) = heir {
+            mods.push(m_remove(
+                "primary_credential",
+                &PartialValue::new_credential_tag(heir_label),
+            ));
+            mods.push(m_pres(
+                "primary_credential",
+                &Value::new_credential(heir_label, heir.inherit_password_history(cred)),
+            ));
+        }
+        Ok(ModifyList::new_list(mods))
+    }
+
+    pub(crate) fn gen_app_password_mod(
+        &self,
+        label: &str,
+        application: &str,
+        cleartext: &str,
+        crypto_policy: &CryptoPolicy,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        let label = label.to_lowercase();
+        if self.app_passwords.iter().any(|(l, _)| l == &label) {
+            return Err(OperationError::InvalidAttribute(format!(
+                "Application password label {} already exists",
+                label
+            )));
+        }
+        let ncred = Credential::new_app_password(crypto_policy, application, cleartext);
+        let vcred = Value::new_credential(label.as_str(), ncred);
+        Ok(ModifyList::new_append("app_password", vcred))
+    }
+
+    pub(crate) fn gen_app_password_remove_mod(
+        &self,
+        label: &str,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        let label = label.to_lowercase();
+        if !self.app_passwords.iter().any(|(l, _)| l == &label) {
+            return Err(OperationError::NoMatchingEntries);
+        }
+        Ok(ModifyList::new_list(vec![m_remove(
+            "app_password",
+            &PartialValue::new_credential_tag(label.as_str()),
+        )]))
+    }
+
+    /// Record a new session, removing any that have expired so they don't accumulate.
+    pub(crate) fn gen_session_mod(
+        &self,
+        session: Session,
+        ct: &Duration,
+    ) -> ModifyList<ModifyInvalid> {
+        let mut mods: Vec<_> = self
+            .sessions
+            .iter()
+            .filter(|s| s.is_expired(ct.as_secs()))
+            .map(|s| m_remove("session", &PartialValue::new_session_id(s.id)))
+            .collect();
+        mods.push(m_pres("session", &Value::new_session(session)));
+        ModifyList::new_list(mods)
+    }
+
+    /// Revoke one session by its id, or all of them.
+    pub(crate) fn gen_session_revoke_mod(
+        &self,
+        id: Option<Uuid>,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        match id {
+            Some(id) => {
+                if !self.sessions.iter().any(|s| s.id == id) {
+                    return Err(OperationError::NoMatchingEntries);
+                }
+                Ok(ModifyList::new_list(vec![m_remove(
+                    "session",
+                    &PartialValue::new_session_id(id),
+                )]))
+            }
+            None => Ok(ModifyList::new_list(vec![m_purge("session")])),
+        }
+    }
+
+    pub(crate) fn gen_credential_reset_token_mod(
+        &self,
+        token: &str,
+        ct: &Duration,
+        crypto_policy: &CryptoPolicy,
+    ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
+        // Issuing a new token replaces any that was outstanding.
+        let ncred = Credential::new_password_only(crypto_policy, token).update_password_set(ct);
+        let vcred = Value::new_credential("reset", ncred);
+        Ok(ModifyList::new_purge_and_set(
+            "credential_reset_token",
+            vcred,
+        ))
+    }
+
+    pub(crate) fn gen_credential_reset_token_remove_mod(&self) -> ModifyList<ModifyInvalid> {
+        ModifyList::new_purge("credential_reset_token")
+    }
+
     pub(crate) fn regenerate_radius_secret_mod(
         &self,
         cleartext: &str,
@@ -197,6 +623,17 @@ impl Account {
     }
 }
 
+// Replace the primary credential with this label, or add it if there is none.
+fn gen_credential_mod(label: &str, ncred: Credential) -> ModifyList<ModifyInvalid> {
+    ModifyList::new_list(vec![
+        m_remove(
+            "primary_credential",
+            &PartialValue::new_credential_tag(label),
+        ),
+        m_pres("primary_credential", &Value::new_credential(label, ncred)),
+    ])
+}
+
 // Need to also add a "to UserAuthToken" ...
 
 // Need tests for conversion and the cred validations
@@ -204,6 +641,8 @@ impl Account {
 #[cfg(test)]
 mod tests {
     use crate::constants::JSON_ANONYMOUS_V1;
+    use crate::idm::account::is_within_valid_time;
+    use std::time::Duration;
     // use crate::entry::{Entry, EntryNew, EntrySealed};
     // use crate::idm::account::Account;
 
@@ -225,4 +664,18 @@ mod tests {
         // Using a real entry, set a credential back to it's entry.
         // In the end, this boils down to a modify operation on the Value
     }
+
+    #[test]
+    fn test_idm_account_valid_time() {
+        let vf = Some(Duration::from_secs(100));
+        let ex = Some(Duration::from_secs(200));
+
+        assert!(is_within_valid_time(None, None, &Duration::from_secs(0)));
+        assert!(!is_within_valid_time(vf, ex, &Duration::from_secs(99)));
+        assert!(is_within_valid_time(vf, ex, &Duration::from_secs(100)));
+        assert!(is_within_valid_time(vf, ex, &Duration::from_secs(199)));
+        assert!(!is_within_valid_time(vf, ex, &Duration::from_secs(200)));
+        assert!(is_within_valid_time(vf, None, &Duration::from_secs(10000)));
+        assert!(is_within_valid_time(None, ex, &Duration::from_secs(0)));
+    }
 }
@@ -1,13 +1,19 @@
 use crate::audit::AuditScope;
+use crate::constants::{
+    BACKUP_CODE_WARN_THRESHOLD, CLAIM_INTERACTIVE, CLAIM_PRIVILEGED, PRIVILEGED_CLAIM_LIFETIME,
+};
 use crate::idm::account::Account;
 use crate::idm::claim::Claim;
 use kanidm_proto::v1::OperationError;
-use kanidm_proto::v1::{AuthAllowed, AuthCredential, AuthState};
+use kanidm_proto::v1::{Application, AuthAllowed, AuthCredential, AuthState};
 
-use crate::credential::{totp::TOTP, Credential, Password};
+use crate::credential::webauthn::WebauthnDomainConfig;
+use crate::credential::{totp::TOTP, Credential, CryptoPolicy, Password};
 
-use std::convert::TryFrom;
 use std::time::Duration;
+use uuid::Uuid;
+use webauthn_rs::proto::{Counter, CredentialID, PublicKeyCredential, RequestChallengeResponse};
+use webauthn_rs::{AuthenticationState, Webauthn};
 
 // Each CredHandler takes one or more credentials and determines if the
 // handlers requirements can be 100% fufilled. This is where MFA or other
@@ -16,6 +22,8 @@ use std::time::Duration;
 
 const BAD_PASSWORD_MSG: &str = "incorrect password";
 const BAD_TOTP_MSG: &str = "incorrect totp";
+const BAD_BACKUP_CODE_MSG: &str = "incorrect backup code";
+const BAD_WEBAUTHN_MSG: &str = "invalid webauthn authentication";
 const BAD_AUTH_TYPE_MSG: &str = "invalid authentication method in this context";
 
 enum CredState {
@@ -31,12 +39,211 @@ enum CredVerifyState {
     Fail,
 }
 
+// Check a password. If it is correct, but hashed more weakly than the policy asks for,
+// keep a new hash of it so that the credential can be upgraded.
+fn verify_password(
+    pw: &Password,
+    cleartext: &str,
+    crypto_policy: &CryptoPolicy,
+    pw_upgrade: &mut Option<(Password, Password)>,
+) -> bool {
+    if !pw.verify(cleartext) {
+        return false;
+    }
+    if pw.requires_upgrade(crypto_policy) {
+        *pw_upgrade = Some((pw.clone(), Password::new(crypto_policy, cleartext)));
+    }
+    true
+}
+
+// Check a totp code against each of the devices. A code is only accepted once, so the
+// device and time step it was accepted for are kept to be recorded on the credential.
+fn verify_totp(
+    totps: &[TOTP],
+    chal: u32,
+    ts: &Duration,
+    totp_used: &mut Option<(String, u64)>,
+) -> bool {
+    let used = totps.iter().find_map(|t| {
+        t.verify_step(chal, ts)
+            .map(|step| (t.label().to_string(), step))
+    });
+    match used {
+        Some(used) => {
+            *totp_used = Some(used);
+            true
+        }
+        None => false,
+    }
+}
+
 #[derive(Clone, Debug)]
 struct CredTotpPw {
     pw: Password,
     pw_state: CredVerifyState,
-    totp: TOTP,
+    totps: Vec<TOTP>,
     totp_state: CredVerifyState,
+    // The label and time step of the totp code that was accepted.
+    totp_used: Option<(String, u64)>,
+    // Any backup code can be given in place of the totp. A code is removed from here
+    // once it is used.
+    backup_codes: Vec<Password>,
+    // The backup code that was given, which must be removed from the credential.
+    backup_code_used: Option<Password>,
+}
+
+impl CredTotpPw {
+    fn verify_backup_code(&mut self, code: &str) -> bool {
+        let code = code.trim();
+        match self.backup_codes.iter().position(|bc| bc.verify(code)) {
+            Some(i) => {
+                self.backup_code_used = Some(self.backup_codes.remove(i));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Given the result of the totp or backup code, determine our state from the password.
+    fn totp_result(&mut self, valid: bool, msg: &'static str) -> CredState {
+        if valid {
+            self.totp_state = CredVerifyState::Success;
+            match self.pw_state {
+                CredVerifyState::Init => CredState::Continue(vec![AuthAllowed::Password]),
+                CredVerifyState::Success => CredState::Success(Vec::new()),
+                CredVerifyState::Fail => CredState::Denied(BAD_PASSWORD_MSG),
+            }
+        } else {
+            self.totp_state = CredVerifyState::Fail;
+            CredState::Denied(msg)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CredWebauthn {
+    chal: RequestChallengeResponse,
+    wan_state: AuthenticationState,
+    state: CredVerifyState,
+    // If the authenticator reported a new signature counter, this is what needs
+    // to be written back to the credential.
+    counter: Option<(CredentialID, Counter)>,
+}
+
+impl CredWebauthn {
+    // Whether the token must verify the user was decided when it was registered.
+    fn new(c: &Credential, webauthn: &Webauthn<WebauthnDomainConfig>) -> Option<Self> {
+        let creds = c.webauthn.as_ref()?.values().cloned().collect();
+        webauthn
+            .generate_challenge_authenticate(creds)
+            .map(|(chal, wan_state)| CredWebauthn {
+                chal,
+                wan_state,
+                state: CredVerifyState::Init,
+                counter: None,
+            })
+            .ok()
+    }
+
+    fn verify(
+        &mut self,
+        resp: &PublicKeyCredential,
+        webauthn: &Webauthn<WebauthnDomainConfig>,
+    ) -> bool {
+        // A challenge may only be answered once.
+        match self.state {
+            CredVerifyState::Init => {}
+            _ => {
+                self.state = CredVerifyState::Fail;
+                return false;
+            }
+        }
+        // This checks the signature against the challenge, the origin, and that the
+        // signature counter has advanced from what we have stored.
+        match webauthn.authenticate_credential(resp, self.wan_state.clone()) {
+            Ok(counter) => {
+                self.state = CredVerifyState::Success;
+                self.counter = counter;
+                true
+            }
+            Err(_) => {
+                self.state = CredVerifyState::Fail;
+                false
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CredWebauthnPw {
+    pw: Password,
+    pw_state: CredVerifyState,
+    wan: CredWebauthn,
+    // A credential may also still have totps from before the token was added, in which
+    // case either can be used as the second factor.
+    totps: Vec<TOTP>,
+    totp_used: Option<(String, u64)>,
+    mfa_state: CredVerifyState,
+}
+
+impl CredWebauthnPw {
+    fn mfa_mechs(&self) -> Vec<AuthAllowed> {
+        let mut mechs = vec![AuthAllowed::Webauthn(self.wan.chal.clone())];
+        if !self.totps.is_empty() {
+            mechs.push(AuthAllowed::TOTP);
+        }
+        mechs
+    }
+
+    // Given the result of the second factor, determine our state from the password.
+    fn mfa_result(&mut self, valid: bool, msg: &'static str) -> CredState {
+        if valid {
+            self.mfa_state = CredVerifyState::Success;
+            match self.pw_state {
+                CredVerifyState::Init => CredState::Continue(vec![AuthAllowed::Password]),
+                CredVerifyState::Success => CredState::Success(Vec::new()),
+                CredVerifyState::Fail => CredState::Denied(BAD_PASSWORD_MSG),
+            }
+        } else {
+            self.mfa_state = CredVerifyState::Fail;
+            CredState::Denied(msg)
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CredAppPassword {
+    application: String,
+    // The uuid and password of each application password for this application.
+    pws: Vec<(Uuid, Password)>,
+    // The application password that was used, once one has succeeded.
+    used: Option<Uuid>,
+}
+
+// Add the mechanisms that aren't already offered. Each webauthn challenge is only for the
+// tokens of one credential, so they are all kept.
+fn merge_auth_mechs(allowed: &mut Vec<AuthAllowed>, mechs: Vec<AuthAllowed>) {
+    for m in mechs {
+        match m {
+            AuthAllowed::Webauthn(_) => allowed.push(m),
+            _ if !allowed.contains(&m) => allowed.push(m),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CredMultiple {
+    // The handler of each credential that may be used, and why it was denied once it has been.
+    handlers: Vec<(CredHandler, Option<&'static str>)>,
+    // The handler that succeeded.
+    used: Option<usize>,
+}
+
+impl CredMultiple {
+    fn used_handler(&self) -> Option<&CredHandler> {
+        self.used.map(|i| &self.handlers[i].0)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -44,35 +251,82 @@ enum CredHandler {
     Denied,
     // The bool is a flag if the cred has been authed against.
     Anonymous,
-    // AppPassword
-    // {
+    AppPassword(CredAppPassword),
     // Password
     Password(Password),
-    // Webauthn
-    // Webauthn + Password
+    Webauthn(CredWebauthn),
+    WebauthnPassword(CredWebauthnPw),
     TOTPPassword(CredTotpPw),
+    // The account has several credentials, and any one of them may be used.
+    Multiple(CredMultiple),
 }
 
-impl TryFrom<&Credential> for CredHandler {
-    type Error = ();
-    // Is there a nicer implementation of this?
-    fn try_from(c: &Credential) -> Result<Self, Self::Error> {
-        match (c.password.as_ref(), c.totp.as_ref()) {
-            (Some(pw), None) => Ok(CredHandler::Password(pw.clone())),
-            (Some(pw), Some(totp)) => Ok(CredHandler::TOTPPassword(CredTotpPw {
+impl CredHandler {
+    // Webauthn needs a challenge generated for this session, so we can't use a plain TryFrom.
+    fn try_from_cred(
+        c: &Credential,
+        webauthn: &Webauthn<WebauthnDomainConfig>,
+    ) -> Result<Self, ()> {
+        let totps: Vec<_> = c.totp.values().cloned().collect();
+        match (c.password.as_ref(), totps.is_empty(), c.webauthn.as_ref()) {
+            (Some(pw), true, None) => Ok(CredHandler::Password(pw.clone())),
+            (Some(pw), false, None) => Ok(CredHandler::TOTPPassword(CredTotpPw {
                 pw: pw.clone(),
                 pw_state: CredVerifyState::Init,
-                totp: totp.clone(),
+                totps,
                 totp_state: CredVerifyState::Init,
+                totp_used: None,
+                backup_codes: c.backup_codes.clone(),
+                backup_code_used: None,
             })),
+            (None, true, Some(_)) => CredWebauthn::new(c, webauthn)
+                .map(CredHandler::Webauthn)
+                .ok_or(()),
+            (Some(pw), _, Some(_)) => CredWebauthn::new(c, webauthn)
+                .map(|wan| {
+                    CredHandler::WebauthnPassword(CredWebauthnPw {
+                        pw: pw.clone(),
+                        pw_state: CredVerifyState::Init,
+                        wan,
+                        totps,
+                        totp_used: None,
+                        mfa_state: CredVerifyState::Init,
+                    })
+                })
+                .ok_or(()),
             // Must be an invalid set of credentials. WTF?
             _ => Err(()),
         }
     }
-}
 
-impl CredHandler {
-    pub fn validate(&mut self, creds: &[AuthCredential], ts: &Duration) -> CredState {
+    // Application passwords are only ever offered when an application id is presented,
+    // and then only those restricted to that application.
+    fn from_app_passwords(app_passwords: &[(String, Credential)], application: &str) -> Self {
+        let application = application.to_lowercase();
+        let pws: Vec<_> = app_passwords
+            .iter()
+            .filter(|(_, c)| c.application.as_ref() == Some(&application))
+            .filter_map(|(_, c)| c.password.as_ref().map(|pw| (c.uuid, pw.clone())))
+            .collect();
+        if pws.is_empty() {
+            CredHandler::Denied
+        } else {
+            CredHandler::AppPassword(CredAppPassword {
+                application,
+                pws,
+                used: None,
+            })
+        }
+    }
+
+    pub fn validate(
+        &mut self,
+        creds: &[AuthCredential],
+        ts: &Duration,
+        webauthn: &Webauthn<WebauthnDomainConfig>,
+        crypto_policy: &CryptoPolicy,
+        pw_upgrade: &mut Option<(Password, Password)>,
+    ) -> CredState {
         match self {
             CredHandler::Denied => {
                 // Sad trombone.
@@ -106,6 +360,39 @@ impl CredHandler {
                     },
                 )
             } // end credhandler::anonymous
+            CredHandler::AppPassword(app_pw) => {
+                creds.iter().fold(
+                    CredState::Continue(vec![AuthAllowed::Password]),
+                    |acc, cred| {
+                        match acc {
+                            CredState::Denied(_) => acc,
+                            _ => {
+                                match cred {
+                                    AuthCredential::Password(cleartext) => {
+                                        let used = app_pw.pws.iter().find(|(_, pw)| {
+                                            verify_password(
+                                                pw,
+                                                cleartext.as_str(),
+                                                crypto_policy,
+                                                pw_upgrade,
+                                            )
+                                        });
+                                        match used {
+                                            Some((uuid, _)) => {
+                                                app_pw.used = Some(*uuid);
+                                                CredState::Success(Vec::new())
+                                            }
+                                            None => CredState::Denied(BAD_PASSWORD_MSG),
+                                        }
+                                    }
+                                    // All other cases fail.
+                                    _ => CredState::Denied(BAD_AUTH_TYPE_MSG),
+                                }
+                            }
+                        } // end match acc
+                    },
+                )
+            } // end credhandler::apppassword
             CredHandler::Password(pw) => {
                 creds.iter().fold(
                     // If no creds, remind that we want pw ...
@@ -117,7 +404,12 @@ impl CredHandler {
                             _ => {
                                 match cred {
                                     AuthCredential::Password(cleartext) => {
-                                        if pw.verify(cleartext.as_str()) {
+                                        if verify_password(
+                                            pw,
+                                            cleartext.as_str(),
+                                            crypto_policy,
+                                            pw_upgrade,
+                                        ) {
                                             CredState::Success(Vec::new())
                                         } else {
                                             CredState::Denied(BAD_PASSWORD_MSG)
@@ -143,7 +435,12 @@ impl CredHandler {
                                 match cred {
                                     AuthCredential::Password(cleartext) => {
                                         // if pw -> check
-                                        if pw_totp.pw.verify(cleartext.as_str()) {
+                                        if verify_password(
+                                            &pw_totp.pw,
+                                            cleartext.as_str(),
+                                            crypto_policy,
+                                            pw_upgrade,
+                                        ) {
                                             pw_totp.pw_state = CredVerifyState::Success;
                                             match pw_totp.totp_state {
                                                 CredVerifyState::Init => {
@@ -183,23 +480,17 @@ impl CredHandler {
                                     }
                                     AuthCredential::TOTP(totp_chal) => {
                                         // if totp -> check
-                                        if pw_totp.totp.verify(*totp_chal, ts) {
-                                            pw_totp.totp_state = CredVerifyState::Success;
-                                            match pw_totp.pw_state {
-                                                CredVerifyState::Init => {
-                                                    CredState::Continue(vec![AuthAllowed::Password])
-                                                }
-                                                CredVerifyState::Success => {
-                                                    CredState::Success(Vec::new())
-                                                }
-                                                CredVerifyState::Fail => {
-                                                    CredState::Denied(BAD_PASSWORD_MSG)
-                                                }
-                                            }
-                                        } else {
-                                            pw_totp.totp_state = CredVerifyState::Fail;
-                                            CredState::Denied(BAD_TOTP_MSG)
-                                        }
+                                        let valid = verify_totp(
+                                            pw_totp.totps.as_slice(),
+                                            *totp_chal,
+                                            ts,
+                                            &mut pw_totp.totp_used,
+                                        );
+                                        pw_totp.totp_result(valid, BAD_TOTP_MSG)
+                                    }
+                                    AuthCredential::BackupCode(code) => {
+                                        let valid = pw_totp.verify_backup_code(code.as_str());
+                                        pw_totp.totp_result(valid, BAD_BACKUP_CODE_MSG)
                                     }
                                     // All other cases fail.
                                     _ => CredState::Denied(BAD_AUTH_TYPE_MSG),
@@ -209,6 +500,117 @@ impl CredHandler {
                     },
                 ) // end fold
             } // end CredHandler::TOTPPassword
+            CredHandler::Webauthn(wan_cred) => {
+                creds.iter().fold(
+                    CredState::Continue(vec![AuthAllowed::Webauthn(wan_cred.chal.clone())]),
+                    |acc, cred| {
+                        match acc {
+                            CredState::Denied(_) => acc,
+                            _ => {
+                                match cred {
+                                    AuthCredential::Webauthn(resp) => {
+                                        if wan_cred.verify(resp, webauthn) {
+                                            CredState::Success(Vec::new())
+                                        } else {
+                                            CredState::Denied(BAD_WEBAUTHN_MSG)
+                                        }
+                                    }
+                                    // All other cases fail.
+                                    _ => CredState::Denied(BAD_AUTH_TYPE_MSG),
+                                }
+                            }
+                        } // end match acc
+                    },
+                )
+            } // end CredHandler::Webauthn
+            CredHandler::WebauthnPassword(pw_wan) => {
+                let mut mechs = pw_wan.mfa_mechs();
+                mechs.push(AuthAllowed::Password);
+                creds.iter().fold(CredState::Continue(mechs), |acc, cred| {
+                    match acc {
+                        CredState::Denied(_) => acc,
+                        _ => {
+                            match cred {
+                                AuthCredential::Password(cleartext) => {
+                                    pw_wan.pw_state = if verify_password(
+                                        &pw_wan.pw,
+                                        cleartext.as_str(),
+                                        crypto_policy,
+                                        pw_upgrade,
+                                    ) {
+                                        CredVerifyState::Success
+                                    } else {
+                                        CredVerifyState::Fail
+                                    };
+                                    match (&pw_wan.mfa_state, &pw_wan.pw_state) {
+                                        // Like TOTPPassword, we don't reveal the password
+                                        // result until the second factor is checked.
+                                        (CredVerifyState::Init, _) => {
+                                            CredState::Continue(pw_wan.mfa_mechs())
+                                        }
+                                        (CredVerifyState::Success, CredVerifyState::Success) => {
+                                            CredState::Success(Vec::new())
+                                        }
+                                        (CredVerifyState::Success, _) => {
+                                            CredState::Denied(BAD_PASSWORD_MSG)
+                                        }
+                                        // Should be an impossible state.
+                                        (CredVerifyState::Fail, _) => {
+                                            CredState::Denied(BAD_WEBAUTHN_MSG)
+                                        }
+                                    }
+                                }
+                                AuthCredential::Webauthn(resp) => {
+                                    let valid = pw_wan.wan.verify(resp, webauthn);
+                                    pw_wan.mfa_result(valid, BAD_WEBAUTHN_MSG)
+                                }
+                                AuthCredential::TOTP(totp_chal) => {
+                                    let valid = verify_totp(
+                                        pw_wan.totps.as_slice(),
+                                        *totp_chal,
+                                        ts,
+                                        &mut pw_wan.totp_used,
+                                    );
+                                    pw_wan.mfa_result(valid, BAD_TOTP_MSG)
+                                }
+                                // All other cases fail.
+                                _ => CredState::Denied(BAD_AUTH_TYPE_MSG),
+                            } // end match cred
+                        }
+                    } // end match acc
+                }) // end fold
+            } // end CredHandler::WebauthnPassword
+            CredHandler::Multiple(multi) => {
+                // Each credential is checked on its own, and the first to succeed is the one
+                // that was used. Until then the mechanisms of all that remain are offered.
+                let mut allowed = Vec::new();
+                for (i, (handler, denied)) in multi.handlers.iter_mut().enumerate() {
+                    if denied.is_some() {
+                        continue;
+                    }
+                    match handler.validate(creds, ts, webauthn, crypto_policy, pw_upgrade) {
+                        CredState::Success(claims) => {
+                            multi.used = Some(i);
+                            return CredState::Success(claims);
+                        }
+                        CredState::Continue(mechs) => merge_auth_mechs(&mut allowed, mechs),
+                        CredState::Denied(msg) => *denied = Some(msg),
+                    }
+                }
+                if allowed.is_empty() {
+                    // Every credential was denied, so give the most useful reason.
+                    CredState::Denied(
+                        multi
+                            .handlers
+                            .iter()
+                            .filter_map(|(_, denied)| *denied)
+                            .find(|msg| *msg != BAD_AUTH_TYPE_MSG)
+                            .unwrap_or(BAD_AUTH_TYPE_MSG),
+                    )
+                } else {
+                    CredState::Continue(allowed)
+                }
+            } // end CredHandler::Multiple
         }
     }
 
@@ -216,10 +618,112 @@ impl CredHandler {
         match &self {
             CredHandler::Denied => Vec::new(),
             CredHandler::Anonymous => vec![AuthAllowed::Anonymous],
+            CredHandler::AppPassword(_) => vec![AuthAllowed::Password],
             CredHandler::Password(_) => vec![AuthAllowed::Password],
-            // webauth
-            // mfa
+            CredHandler::Webauthn(wan_cred) => vec![AuthAllowed::Webauthn(wan_cred.chal.clone())],
+            CredHandler::WebauthnPassword(pw_wan) => {
+                let mut mechs = vec![AuthAllowed::Password];
+                mechs.append(&mut pw_wan.mfa_mechs());
+                mechs
+            }
             CredHandler::TOTPPassword(_) => vec![AuthAllowed::Password, AuthAllowed::TOTP],
+            CredHandler::Multiple(multi) => {
+                let mut mechs = Vec::new();
+                multi
+                    .handlers
+                    .iter()
+                    .filter(|(_, denied)| denied.is_none())
+                    .for_each(|(handler, _)| {
+                        merge_auth_mechs(&mut mechs, handler.valid_auth_mechs())
+                    });
+                mechs
+            }
+        }
+    }
+
+    fn take_webauthn_counter(&mut self) -> Option<(CredentialID, Counter)> {
+        match self {
+            CredHandler::Webauthn(wan_cred) => wan_cred.counter.take(),
+            CredHandler::WebauthnPassword(pw_wan) => pw_wan.wan.counter.take(),
+            CredHandler::Multiple(multi) => match multi.used {
+                Some(i) => multi.handlers[i].0.take_webauthn_counter(),
+                None => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn take_totp_used(&mut self) -> Option<(String, u64)> {
+        match self {
+            CredHandler::TOTPPassword(pw_totp) => pw_totp.totp_used.take(),
+            CredHandler::WebauthnPassword(pw_wan) => pw_wan.totp_used.take(),
+            CredHandler::Multiple(multi) => multi
+                .handlers
+                .iter_mut()
+                .find_map(|(handler, _)| handler.take_totp_used()),
+            _ => None,
+        }
+    }
+
+    fn take_backup_code_used(&mut self) -> Option<Password> {
+        match self {
+            CredHandler::TOTPPassword(pw_totp) => pw_totp.backup_code_used.take(),
+            CredHandler::Multiple(multi) => multi
+                .handlers
+                .iter_mut()
+                .find_map(|(handler, _)| handler.take_backup_code_used()),
+            _ => None,
+        }
+    }
+
+    // Anything the user should be told about their credential after they authenticate.
+    fn warnings(&self) -> Vec<String> {
+        match self {
+            CredHandler::TOTPPassword(pw_totp) => {
+                // A code that was used has already been removed.
+                let remaining = pw_totp.backup_codes.len();
+                if remaining < BACKUP_CODE_WARN_THRESHOLD {
+                    vec![format!(
+                        "{} backup codes remain, you should generate new ones",
+                        remaining
+                    )]
+                } else {
+                    Vec::new()
+                }
+            }
+            CredHandler::Multiple(multi) => multi
+                .used_handler()
+                .map(|handler| handler.warnings())
+                .unwrap_or_else(Vec::new),
+            _ => Vec::new(),
+        }
+    }
+
+    // The application this handler authenticated for, if it was an application password.
+    fn application(&self) -> Option<Application> {
+        match self {
+            CredHandler::AppPassword(app_pw) => app_pw.used.map(|uuid| Application {
+                name: app_pw.application.clone(),
+                uuid: uuid.to_hyphenated_ref().to_string(),
+            }),
+            _ => None,
+        }
+    }
+
+    // How the account authenticated, as shown in its sessions.
+    fn auth_method(&self) -> &'static str {
+        match self {
+            CredHandler::Denied => "denied",
+            CredHandler::Anonymous => "anonymous",
+            CredHandler::AppPassword(_) => "app_password",
+            CredHandler::Password(_) => "password",
+            CredHandler::Webauthn(_) => "webauthn",
+            CredHandler::WebauthnPassword(_) => "password_webauthn",
+            CredHandler::TOTPPassword(_) => "password_totp",
+            CredHandler::Multiple(multi) => multi
+                .used_handler()
+                .map(|handler| handler.auth_method())
+                .unwrap_or("denied"),
         }
     }
 
@@ -244,18 +748,30 @@ pub(crate) struct AuthSession {
     handler: CredHandler,
     // Store any related appid we are processing for.
     appid: Option<String>,
+    // Is this a re-authentication that raises the session to privileged?
+    privileged: bool,
     // Store claims related to the handler
     // need to store state somehow?
     finished: bool,
+    // A weakly hashed password that was verified, and its new hash.
+    pw_upgrade: Option<(Password, Password)>,
 }
 
 impl AuthSession {
-    pub fn new(account: Account, appid: Option<String>) -> Self {
+    pub fn new(
+        account: Account,
+        appid: Option<String>,
+        privileged: bool,
+        webauthn: &Webauthn<WebauthnDomainConfig>,
+        ct: &Duration,
+    ) -> Self {
         // During this setup, determine the credential handler that we'll be using
         // for this session. This is currently based on presentation of an application
         // id.
-        let handler = match appid {
-            Some(_) => CredHandler::Denied,
+        let handler = match &appid {
+            // Outside of the accounts validity window, nothing may proceed.
+            _ if !account.is_within_valid_time(ct) => CredHandler::Denied,
+            Some(app) => CredHandler::from_app_passwords(account.app_passwords.as_slice(), app),
             None => {
                 // We want the primary handler - this is where we make a decision
                 // based on the anonymous ... in theory this could be cleaner
@@ -263,24 +779,41 @@ impl AuthSession {
                 if account.is_anonymous() {
                     CredHandler::Anonymous
                 } else {
-                    // Now we see if they have one ...
-                    match &account.primary {
-                        Some(cred) => {
+                    // Now we see which they have that can be used ...
+                    let pwpolicy = account.password_policy();
+                    let mut handlers: Vec<_> = account
+                        .credentials
+                        .iter()
+                        .filter(|(_, cred)| {
+                            !cred.locked && !pwpolicy.is_password_expired(cred.password_set, ct)
+                        })
+                        .filter_map(|(_, cred)| {
                             // TODO: Log this corruption better ... :(
                             // Probably means new authsession has to be failable
-                            CredHandler::try_from(cred).unwrap_or_else(|_| CredHandler::Denied)
-                        }
-                        None => CredHandler::Denied,
+                            match CredHandler::try_from_cred(cred, webauthn) {
+                                // A password alone can't satisfy a policy requiring mfa.
+                                Ok(CredHandler::Password(_)) if pwpolicy.require_mfa => None,
+                                Ok(ch) => Some(ch),
+                                Err(_) => None,
+                            }
+                        })
+                        .collect();
+                    match handlers.len() {
+                        0 => CredHandler::Denied,
+                        1 => handlers.remove(0),
+                        _ => CredHandler::Multiple(CredMultiple {
+                            handlers: handlers.into_iter().map(|h| (h, None)).collect(),
+                            used: None,
+                        }),
                     }
                 }
             }
         };
 
-        // Is this handler locked?
-        // Is the whole account locked?
+        // Locked credentials are never offered, but is the whole account locked?
         // What about in memory account locking? Is that something
         // we store in the account somehow?
-        // TODO #59: Implement handler locking!
+        // TODO #59: Implement account locking!
 
         // if credhandler == deny, finish = true.
         let finished: bool = handler.is_denied();
@@ -289,7 +822,9 @@ impl AuthSession {
             account,
             handler,
             appid,
+            privileged,
             finished,
+            pw_upgrade: None,
         }
     }
 
@@ -299,6 +834,8 @@ impl AuthSession {
         au: &mut AuditScope,
         creds: &[AuthCredential],
         time: &Duration,
+        webauthn: &Webauthn<WebauthnDomainConfig>,
+        crypto_policy: &CryptoPolicy,
     ) -> Result<AuthState, OperationError> {
         if self.finished {
             return Err(OperationError::InvalidAuthState(
@@ -306,13 +843,21 @@ impl AuthSession {
             ));
         }
 
-        match self.handler.validate(creds, time) {
-            CredState::Success(claims) => {
+        match self
+            .handler
+            .validate(creds, time, webauthn, crypto_policy, &mut self.pw_upgrade)
+        {
+            CredState::Success(mut claims) => {
                 audit_log!(au, "Successful cred handling");
                 self.finished = true;
+                claims.append(&mut self.session_claims(time));
+                let warnings = self.handler.warnings();
+                for w in warnings.iter() {
+                    audit_log!(au, "Warning -> {}", w);
+                }
                 let uat = self
                     .account
-                    .to_userauthtoken(claims)
+                    .to_userauthtoken(claims, self.handler.application(), warnings)
                     .ok_or(OperationError::InvalidState)?;
                 Ok(AuthState::Success(uat))
             }
@@ -322,6 +867,8 @@ impl AuthSession {
             }
             CredState::Denied(reason) => {
                 self.finished = true;
+                // Only a successful authentication may change the credential.
+                self.pw_upgrade = None;
                 audit_log!(au, "Credentials denied: {}", reason);
                 Ok(AuthState::Denied(reason.to_string()))
             }
@@ -339,6 +886,23 @@ impl AuthSession {
         //  If success, to authtoken?
     }
 
+    // The claims that come from how the session was established, rather than
+    // from the credential that was used.
+    fn session_claims(&self, ct: &Duration) -> Vec<Claim> {
+        // Anonymous and application sessions are never interactive.
+        if self.appid.is_some() || self.account.is_anonymous() {
+            return Vec::new();
+        }
+        let mut claims = vec![Claim::new(CLAIM_INTERACTIVE)];
+        if self.privileged {
+            claims.push(Claim::new_ephemeral(
+                CLAIM_PRIVILEGED,
+                *ct + Duration::from_secs(PRIVILEGED_CLAIM_LIFETIME),
+            ));
+        }
+        claims
+    }
+
     pub fn valid_auth_mechs(&self) -> Vec<AuthAllowed> {
         if self.finished {
             Vec::new()
@@ -346,6 +910,59 @@ impl AuthSession {
             self.handler.valid_auth_mechs()
         }
     }
+
+    pub fn get_account_uuid(&self) -> Uuid {
+        self.account.uuid
+    }
+
+    pub fn is_anonymous(&self) -> bool {
+        self.account.is_anonymous()
+    }
+
+    pub fn auth_method(&self) -> &'static str {
+        self.handler.auth_method()
+    }
+
+    /// After a successful webauthn authentication, the new signature counter of the
+    /// token that was used, so that it can be persisted to the account.
+    pub fn take_webauthn_counter(&mut self) -> Option<(Uuid, CredentialID, Counter)> {
+        let account_uuid = self.account.uuid;
+        self.handler
+            .take_webauthn_counter()
+            .map(|(cid, counter)| (account_uuid, cid, counter))
+    }
+
+    /// The totp device and time step of a totp code that was accepted, so that the code
+    /// can't be replayed. Like a backup code, this is taken even if the authentication
+    /// was then denied.
+    pub fn take_totp_used(&mut self) -> Option<(Uuid, String, u64)> {
+        let account_uuid = self.account.uuid;
+        self.handler
+            .take_totp_used()
+            .map(|(label, step)| (account_uuid, label, step))
+    }
+
+    /// A backup code that was given, which must be removed from the account so that it
+    /// can't be used again. This is taken even if the authentication was then denied, as
+    /// the code has still been revealed.
+    pub fn take_backup_code_used(&mut self) -> Option<(Uuid, Password)> {
+        let account_uuid = self.account.uuid;
+        self.handler
+            .take_backup_code_used()
+            .map(|code| (account_uuid, code))
+    }
+
+    /// After a successful authentication with a password that is hashed more weakly than
+    /// the crypto policy, the hash that was verified and its replacement.
+    pub fn take_password_upgrade(&mut self) -> Option<(Uuid, Password, Password)> {
+        if !self.finished {
+            return None;
+        }
+        let account_uuid = self.account.uuid;
+        self.pw_upgrade
+            .take()
+            .map(|(old, new)| (account_uuid, old, new))
+    }
 }
 
 #[cfg(test)]
@@ -353,16 +970,49 @@ mod tests {
     use crate::audit::AuditScope;
     use crate::constants::{JSON_ADMIN_V1, JSON_ANONYMOUS_V1};
     use crate::credential::totp::{TOTP, TOTP_DEFAULT_STEP};
-    use crate::credential::Credential;
-    use crate::idm::authsession::{AuthSession, BAD_AUTH_TYPE_MSG, BAD_PASSWORD_MSG, BAD_TOTP_MSG};
+    use crate::credential::webauthn::WebauthnDomainConfig;
+    use crate::credential::{Credential, CryptoPolicy};
+    use crate::idm::account::Account;
+    use crate::idm::authsession::{
+        AuthSession, BAD_AUTH_TYPE_MSG, BAD_BACKUP_CODE_MSG, BAD_PASSWORD_MSG, BAD_TOTP_MSG,
+        BAD_WEBAUTHN_MSG,
+    };
     use kanidm_proto::v1::{AuthAllowed, AuthCredential, AuthState};
     use std::time::Duration;
+    use webauthn_authenticator_rs::{softtok::U2FSoft, WebauthnAuthenticator};
+    use webauthn_rs::proto::{RequestChallengeResponse, UserVerificationPolicy};
+    use webauthn_rs::Webauthn;
+
+    const TEST_ORIGIN: &str = "https://idm.example.com";
+
+    fn create_webauthn() -> Webauthn<WebauthnDomainConfig> {
+        Webauthn::new(WebauthnDomainConfig::new(TEST_ORIGIN))
+    }
+
+    fn webauthn_chal(session: &AuthSession) -> RequestChallengeResponse {
+        session
+            .valid_auth_mechs()
+            .into_iter()
+            .filter_map(|m| match m {
+                AuthAllowed::Webauthn(chal) => Some(chal),
+                _ => None,
+            })
+            .next()
+            .expect("No webauthn challenge offered")
+    }
 
     #[test]
     fn test_idm_authsession_anonymous_auth_mech() {
+        let webauthn = create_webauthn();
         let anon_account = entry_str_to_account!(JSON_ANONYMOUS_V1);
 
-        let session = AuthSession::new(anon_account, None);
+        let session = AuthSession::new(
+            anon_account,
+            None,
+            false,
+            &webauthn,
+            &Duration::from_secs(0),
+        );
 
         let auth_mechs = session.valid_auth_mechs();
 
@@ -376,9 +1026,16 @@ mod tests {
 
     #[test]
     fn test_idm_authsession_missing_appid() {
+        let webauthn = create_webauthn();
         let anon_account = entry_str_to_account!(JSON_ANONYMOUS_V1);
 
-        let session = AuthSession::new(anon_account, Some("NonExistantAppID".to_string()));
+        let session = AuthSession::new(
+            anon_account,
+            Some("NonExistantAppID".to_string()),
+            false,
+            &webauthn,
+            &Duration::from_secs(0),
+        );
 
         let auth_mechs = session.valid_auth_mechs();
 
@@ -388,15 +1045,22 @@ mod tests {
 
     #[test]
     fn test_idm_authsession_simple_password_mech() {
+        let webauthn = create_webauthn();
         let mut audit = AuditScope::new("test_idm_authsession_simple_password_mech");
         // create the ent
         let mut account = entry_str_to_account!(JSON_ADMIN_V1);
         // manually load in a cred
-        let cred = Credential::new_password_only("test_password");
-        account.primary = Some(cred);
+        let cred = Credential::new_password_only(&CryptoPolicy::minimum(), "test_password");
+        account.credentials = vec![("primary".to_string(), cred)];
 
         // now check
-        let mut session = AuthSession::new(account.clone(), None);
+        let mut session = AuthSession::new(
+            account.clone(),
+            None,
+            false,
+            &webauthn,
+            &Duration::from_secs(0),
+        );
         let auth_mechs = session.valid_auth_mechs();
 
         assert!(
@@ -407,14 +1071,27 @@ mod tests {
         );
 
         let attempt = vec![AuthCredential::Password("bad_password".to_string())];
-        match session.validate_creds(&mut audit, &attempt, &Duration::from_secs(0)) {
+        match session.validate_creds(
+            &mut audit,
+            &attempt,
+            &Duration::from_secs(0),
+            &webauthn,
+            &CryptoPolicy::minimum(),
+        ) {
             Ok(AuthState::Denied(_)) => {}
             _ => panic!(),
         };
 
-        let mut session = AuthSession::new(account, None);
+        let mut session =
+            AuthSession::new(account, None, false, &webauthn, &Duration::from_secs(0));
         let attempt = vec![AuthCredential::Password("test_password".to_string())];
-        match session.validate_creds(&mut audit, &attempt, &Duration::from_secs(0)) {
+        match session.validate_creds(
+            &mut audit,
+            &attempt,
+            &Duration::from_secs(0),
+            &webauthn,
+            &CryptoPolicy::minimum(),
+        ) {
             Ok(AuthState::Success(_)) => {}
             _ => panic!(),
         };
@@ -422,8 +1099,101 @@ mod tests {
         println!("{}", audit);
     }
 
+    #[test]
+    fn test_idm_authsession_app_password_mech() {
+        let webauthn = create_webauthn();
+        let ct = Duration::from_secs(0);
+        let mut account = entry_str_to_account!(JSON_ADMIN_V1);
+        account.credentials = vec![(
+            "primary".to_string(),
+            Credential::new_password_only(&CryptoPolicy::minimum(), "test_password"),
+        )];
+        let app_cred = Credential::new_app_password(&CryptoPolicy::minimum(), "IMAP", "imap_pw");
+        let app_uuid = app_cred.uuid;
+        account.app_passwords = vec![("laptop".to_string(), app_cred)];
+
+        let try_auth = |account, appid: Option<&str>, pw: &str| {
+            let mut audit = AuditScope::new("try_auth");
+            let mut session =
+                AuthSession::new(account, appid.map(|s| s.to_string()), false, &webauthn, &ct);
+            let attempt = vec![AuthCredential::Password(pw.to_string())];
+            session.validate_creds(
+                &mut audit,
+                &attempt,
+                &ct,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            )
+        };
+
+        // The application password is never valid for interactive login.
+        match try_auth(account.clone(), None, "imap_pw") {
+            Ok(AuthState::Denied(_)) => {}
+            _ => panic!(),
+        };
+        // Nor for another application.
+        let session = AuthSession::new(
+            account.clone(),
+            Some("caldav".to_string()),
+            false,
+            &webauthn,
+            &ct,
+        );
+        assert!(session.valid_auth_mechs() == Vec::new());
+        // The primary password is not valid for the application.
+        match try_auth(account.clone(), Some("imap"), "test_password") {
+            Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
+            _ => panic!(),
+        };
+        // And the application name is case insensitive.
+        match try_auth(account, Some("Imap"), "imap_pw") {
+            Ok(AuthState::Success(uat)) => {
+                let app = uat.application.expect("no application in uat");
+                assert!(app.name == "imap");
+                assert!(app.uuid == app_uuid.to_hyphenated_ref().to_string());
+            }
+            _ => panic!(),
+        };
+    }
+
+    #[test]
+    fn test_idm_authsession_account_valid_time() {
+        let webauthn = create_webauthn();
+        let mut account = entry_str_to_account!(JSON_ADMIN_V1);
+        account.credentials = vec![(
+            "primary".to_string(),
+            Credential::new_password_only(&CryptoPolicy::minimum(), "test_password"),
+        )];
+        account.valid_from = Some(Duration::from_secs(100));
+        account.expire = Some(Duration::from_secs(200));
+
+        // Before and after the window, no mechanism is offered.
+        let session = AuthSession::new(
+            account.clone(),
+            None,
+            false,
+            &webauthn,
+            &Duration::from_secs(50),
+        );
+        assert!(session.valid_auth_mechs() == Vec::new());
+
+        let session = AuthSession::new(
+            account.clone(),
+            None,
+            false,
+            &webauthn,
+            &Duration::from_secs(200),
+        );
+        assert!(session.valid_auth_mechs() == Vec::new());
+
+        // And within it, we can proceed as normal.
+        let session = AuthSession::new(account, None, false, &webauthn, &Duration::from_secs(150));
+        assert!(session.valid_auth_mechs() == vec![AuthAllowed::Password]);
+    }
+
     #[test]
     fn test_idm_authsession_totp_password_mech() {
+        let webauthn = create_webauthn();
         let mut audit = AuditScope::new("test_idm_authsession_totp_password_mech");
         // create the ent
         let mut account = entry_str_to_account!(JSON_ADMIN_V1);
@@ -445,12 +1215,20 @@ mod tests {
         let pw_good = "test_password";
         let pw_bad = "bad_password";
 
-        let cred = Credential::new_password_only(pw_good).update_totp(totp);
+        let cred = Credential::new_password_only(&CryptoPolicy::minimum(), pw_good)
+            .add_totp(totp)
+            .expect("Failed to add totp");
         // add totp also
-        account.primary = Some(cred);
+        account.credentials = vec![("primary".to_string(), cred)];
 
         // now check
-        let session = AuthSession::new(account.clone(), None);
+        let session = AuthSession::new(
+            account.clone(),
+            None,
+            false,
+            &webauthn,
+            &Duration::from_secs(0),
+        );
         let auth_mechs = session.valid_auth_mechs();
         assert!(auth_mechs.iter().fold(true, |acc, x| match x {
             AuthAllowed::Password => acc,
@@ -462,8 +1240,20 @@ mod tests {
 
         // check send anon (fail)
         {
-            let mut session = AuthSession::new(account.clone(), None);
-            match session.validate_creds(&mut audit, &vec![AuthCredential::Anonymous], &ts) {
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::Anonymous],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_AUTH_TYPE_MSG),
                 _ => panic!(),
             };
@@ -474,16 +1264,30 @@ mod tests {
         // check send bad pw, should get continue (even though denied set)
         //      then send good totp, should fail.
         {
-            let mut session = AuthSession::new(account.clone(), None);
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
             match session.validate_creds(
                 &mut audit,
                 &vec![AuthCredential::Password(pw_bad.to_string())],
                 &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::TOTP]),
                 _ => panic!(),
             };
-            match session.validate_creds(&mut audit, &vec![AuthCredential::TOTP(totp_good)], &ts) {
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::TOTP(totp_good)],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
                 _ => panic!(),
             };
@@ -491,16 +1295,30 @@ mod tests {
         // check send bad pw, should get continue (even though denied set)
         //      then send bad totp, should fail TOTP
         {
-            let mut session = AuthSession::new(account.clone(), None);
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
             match session.validate_creds(
                 &mut audit,
                 &vec![AuthCredential::Password(pw_bad.to_string())],
                 &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::TOTP]),
                 _ => panic!(),
             };
-            match session.validate_creds(&mut audit, &vec![AuthCredential::TOTP(totp_bad)], &ts) {
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::TOTP(totp_bad)],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_TOTP_MSG),
                 _ => panic!(),
             };
@@ -509,16 +1327,30 @@ mod tests {
         // check send good pw, should get continue
         //      then send good totp, success
         {
-            let mut session = AuthSession::new(account.clone(), None);
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
             match session.validate_creds(
                 &mut audit,
                 &vec![AuthCredential::Password(pw_good.to_string())],
                 &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::TOTP]),
                 _ => panic!(),
             };
-            match session.validate_creds(&mut audit, &vec![AuthCredential::TOTP(totp_good)], &ts) {
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::TOTP(totp_good)],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
                 Ok(AuthState::Success(_)) => {}
                 _ => panic!(),
             };
@@ -527,16 +1359,30 @@ mod tests {
         // check send good pw, should get continue
         //      then send bad totp, fail otp
         {
-            let mut session = AuthSession::new(account.clone(), None);
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
             match session.validate_creds(
                 &mut audit,
                 &vec![AuthCredential::Password(pw_good.to_string())],
                 &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
             ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::TOTP]),
                 _ => panic!(),
             };
-            match session.validate_creds(&mut audit, &vec![AuthCredential::TOTP(totp_bad)], &ts) {
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::TOTP(totp_bad)],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_TOTP_MSG),
                 _ => panic!(),
             };
@@ -544,8 +1390,20 @@ mod tests {
 
         // check send bad totp, should fail immediate
         {
-            let mut session = AuthSession::new(account.clone(), None);
-            match session.validate_creds(&mut audit, &vec![AuthCredential::TOTP(totp_bad)], &ts) {
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::TOTP(totp_bad)],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_TOTP_MSG),
                 _ => panic!(),
             };
@@ -554,8 +1412,20 @@ mod tests {
         // check send good totp, should continue
         //      then bad pw, fail pw
         {
-            let mut session = AuthSession::new(account.clone(), None);
-            match session.validate_creds(&mut audit, &vec![AuthCredential::TOTP(totp_good)], &ts) {
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::TOTP(totp_good)],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
                 _ => panic!(),
             };
@@ -563,6 +1433,8 @@ mod tests {
                 &mut audit,
                 &vec![AuthCredential::Password(pw_bad.to_string())],
                 &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
                 _ => panic!(),
@@ -572,8 +1444,20 @@ mod tests {
         // check send good totp, should continue
         //      then good pw, success
         {
-            let mut session = AuthSession::new(account.clone(), None);
-            match session.validate_creds(&mut audit, &vec![AuthCredential::TOTP(totp_good)], &ts) {
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::TOTP(totp_good)],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
                 Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
                 _ => panic!(),
             };
@@ -581,6 +1465,8 @@ mod tests {
                 &mut audit,
                 &vec![AuthCredential::Password(pw_good.to_string())],
                 &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
             ) {
                 Ok(AuthState::Success(_)) => {}
                 _ => panic!(),
@@ -591,7 +1477,13 @@ mod tests {
 
         // check bad totp, bad pw, fail totp.
         {
-            let mut session = AuthSession::new(account.clone(), None);
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
             match session.validate_creds(
                 &mut audit,
                 &vec![
@@ -599,6 +1491,8 @@ mod tests {
                     AuthCredential::TOTP(totp_bad),
                 ],
                 &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_TOTP_MSG),
                 _ => panic!(),
@@ -606,7 +1500,13 @@ mod tests {
         }
         // check send bad pw, good totp fail password
         {
-            let mut session = AuthSession::new(account.clone(), None);
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
             match session.validate_creds(
                 &mut audit,
                 &vec![
@@ -614,6 +1514,8 @@ mod tests {
                     AuthCredential::Password(pw_bad.to_string()),
                 ],
                 &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
                 _ => panic!(),
@@ -621,7 +1523,13 @@ mod tests {
         }
         // check send good pw, bad totp fail totp.
         {
-            let mut session = AuthSession::new(account.clone(), None);
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
             match session.validate_creds(
                 &mut audit,
                 &vec![
@@ -629,6 +1537,8 @@ mod tests {
                     AuthCredential::Password(pw_good.to_string()),
                 ],
                 &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
             ) {
                 Ok(AuthState::Denied(msg)) => assert!(msg == BAD_TOTP_MSG),
                 _ => panic!(),
@@ -636,7 +1546,13 @@ mod tests {
         }
         // check good pw, good totp, success
         {
-            let mut session = AuthSession::new(account.clone(), None);
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
             match session.validate_creds(
                 &mut audit,
                 &vec![
@@ -644,6 +1560,527 @@ mod tests {
                     AuthCredential::Password(pw_good.to_string()),
                 ],
                 &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
+                Ok(AuthState::Success(_)) => {}
+                _ => panic!(),
+            };
+        }
+
+        println!("{}", audit);
+    }
+
+    #[test]
+    fn test_idm_authsession_multiple_credential_mech() {
+        let webauthn = create_webauthn();
+        let mut audit = AuditScope::new("test_idm_authsession_multiple_credential_mech");
+        let mut account = entry_str_to_account!(JSON_ADMIN_V1);
+        let ts = Duration::from_secs(12345);
+        let p = CryptoPolicy::minimum();
+
+        let totp = TOTP::generate_secure("test_totp".to_string(), TOTP_DEFAULT_STEP);
+        let totp_good = totp
+            .do_totp_duration_from_epoch(&ts)
+            .expect("failed to perform totp.");
+        let primary = Credential::new_password_only(&p, "primary_password");
+        let backup = Credential::new_password_only(&p, "backup_password")
+            .add_totp(totp)
+            .expect("Failed to add totp");
+        account.credentials = vec![
+            ("primary".to_string(), primary.clone()),
+            ("backup".to_string(), backup.clone()),
+        ];
+
+        let mut try_auth = |account: &Account, attempt: Vec<AuthCredential>| {
+            let mut session = AuthSession::new(account.clone(), None, false, &webauthn, &ts);
+            let state = session.validate_creds(&mut audit, &attempt, &ts, &webauthn, &p);
+            (state, session)
+        };
+
+        // The mechanisms of both credentials are offered.
+        let session = AuthSession::new(account.clone(), None, false, &webauthn, &ts);
+        let auth_mechs = session.valid_auth_mechs();
+        assert!(auth_mechs.len() == 2);
+        assert!(auth_mechs.contains(&AuthAllowed::Password));
+        assert!(auth_mechs.contains(&AuthAllowed::TOTP));
+
+        // Either credential can be used on its own.
+        let (state, session) = try_auth(
+            &account,
+            vec![AuthCredential::Password("primary_password".to_string())],
+        );
+        match state {
+            Ok(AuthState::Success(_)) => {}
+            _ => panic!(),
+        };
+        assert!(session.auth_method() == "password");
+
+        let (state, session) = try_auth(
+            &account,
+            vec![
+                AuthCredential::Password("backup_password".to_string()),
+                AuthCredential::TOTP(totp_good),
+            ],
+        );
+        match state {
+            Ok(AuthState::Success(_)) => {}
+            _ => panic!(),
+        };
+        assert!(session.auth_method() == "password_totp");
+
+        // A password that only matches the second credential still needs its totp.
+        let (state, _) = try_auth(
+            &account,
+            vec![AuthCredential::Password("backup_password".to_string())],
+        );
+        match state {
+            Ok(AuthState::Continue(mechs)) => assert!(mechs == vec![AuthAllowed::TOTP]),
+            _ => panic!(),
+        };
+
+        // When every credential rejects the attempt, the most useful reason is given.
+        let (state, _) = try_auth(
+            &account,
+            vec![
+                AuthCredential::Password("bad_password".to_string()),
+                AuthCredential::TOTP(totp_good),
+            ],
+        );
+        match state {
+            Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
+            _ => panic!(),
+        };
+
+        // A locked credential is never offered.
+        account.credentials = vec![
+            ("primary".to_string(), primary),
+            ("backup".to_string(), backup.set_locked(true)),
+        ];
+        let session = AuthSession::new(account.clone(), None, false, &webauthn, &ts);
+        assert!(session.valid_auth_mechs() == vec![AuthAllowed::Password]);
+        let (state, _) = try_auth(
+            &account,
+            vec![AuthCredential::Password("backup_password".to_string())],
+        );
+        match state {
+            Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
+            _ => panic!(),
+        };
+
+        println!("{}", audit);
+    }
+
+    #[test]
+    fn test_idm_authsession_backup_code_mech() {
+        let webauthn = create_webauthn();
+        let mut audit = AuditScope::new("test_idm_authsession_backup_code_mech");
+        let mut account = entry_str_to_account!(JSON_ADMIN_V1);
+        let ts = Duration::from_secs(12345);
+        let p = CryptoPolicy::minimum();
+
+        let totp = TOTP::generate_secure("test_totp".to_string(), TOTP_DEFAULT_STEP);
+        let pw_good = "test_password";
+        let pw_bad = "bad_password";
+        let codes = vec!["aaaa-bbbb".to_string(), "cccc-dddd".to_string()];
+        let cred = Credential::new_password_only(&p, pw_good)
+            .add_totp(totp)
+            .expect("Failed to add totp")
+            .update_backup_codes(&p, &codes);
+        account.credentials = vec![("primary".to_string(), cred)];
+
+        // A backup code can be given in place of the totp.
+        {
+            let mut session = AuthSession::new(account.clone(), None, false, &webauthn, &ts);
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::BackupCode(" aaaa-bbbb ".to_string())],
+                &ts,
+                &webauthn,
+                &p,
+            ) {
+                Ok(AuthState::Continue(cont)) => assert!(cont == vec![AuthAllowed::Password]),
+                _ => panic!(),
+            };
+            // The code is used as soon as it's accepted.
+            assert!(session.take_backup_code_used().is_some());
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::Password(pw_good.to_string())],
+                &ts,
+                &webauthn,
+                &p,
+            ) {
+                // Only one code remains, so the user is warned.
+                Ok(AuthState::Success(uat)) => assert!(uat.warnings.len() == 1),
+                _ => panic!(),
+            };
+        }
+
+        // An incorrect code is denied, and nothing is used.
+        {
+            let mut session = AuthSession::new(account.clone(), None, false, &webauthn, &ts);
+            match session.validate_creds(
+                &mut audit,
+                &vec![
+                    AuthCredential::BackupCode("zzzz-zzzz".to_string()),
+                    AuthCredential::Password(pw_good.to_string()),
+                ],
+                &ts,
+                &webauthn,
+                &p,
+            ) {
+                Ok(AuthState::Denied(msg)) => assert!(msg == BAD_BACKUP_CODE_MSG),
+                _ => panic!(),
+            };
+            assert!(session.take_backup_code_used().is_none());
+        }
+
+        // A correct code with the wrong password is still used up, as it was revealed.
+        {
+            let mut session = AuthSession::new(account.clone(), None, false, &webauthn, &ts);
+            match session.validate_creds(
+                &mut audit,
+                &vec![
+                    AuthCredential::BackupCode("cccc-dddd".to_string()),
+                    AuthCredential::Password(pw_bad.to_string()),
+                ],
+                &ts,
+                &webauthn,
+                &p,
+            ) {
+                Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
+                _ => panic!(),
+            };
+            assert!(session.take_backup_code_used().is_some());
+        }
+
+        println!("{}", audit);
+    }
+
+    #[test]
+    fn test_idm_authsession_multiple_totp_replay() {
+        let webauthn = create_webauthn();
+        let mut audit = AuditScope::new("test_idm_authsession_multiple_totp_replay");
+        let mut account = entry_str_to_account!(JSON_ADMIN_V1);
+        let ts = Duration::from_secs(12345);
+        let p = CryptoPolicy::minimum();
+        let pw_good = "test_password";
+
+        let phone = TOTP::generate_secure("phone".to_string(), TOTP_DEFAULT_STEP);
+        let tablet = TOTP::generate_secure("tablet".to_string(), TOTP_DEFAULT_STEP);
+        let tablet_good = tablet
+            .do_totp_duration_from_epoch(&ts)
+            .expect("failed to perform totp.");
+        let cred = Credential::new_password_only(&p, pw_good)
+            .add_totp(phone)
+            .expect("Failed to add totp")
+            .add_totp(tablet)
+            .expect("Failed to add totp");
+        account.credentials = vec![("primary".to_string(), cred)];
+
+        let mut try_auth = |account| {
+            let mut session = AuthSession::new(account, None, false, &webauthn, &ts);
+            let state = session.validate_creds(
+                &mut audit,
+                &vec![
+                    AuthCredential::TOTP(tablet_good),
+                    AuthCredential::Password(pw_good.to_string()),
+                ],
+                &ts,
+                &webauthn,
+                &p,
+            );
+            (state, session.take_totp_used())
+        };
+
+        // A code from any of the devices is accepted, and the step it was for is reported.
+        let (state, used) = try_auth(account.clone());
+        match state {
+            Ok(AuthState::Success(_)) => {}
+            _ => panic!(),
+        };
+        let (uuid, label, step) = used.expect("No totp step reported");
+        assert!(uuid == account.uuid);
+        assert!(label == "tablet");
+        assert!(step == ts.as_secs() / TOTP_DEFAULT_STEP);
+
+        // Once the step is recorded, the same code can't be used again.
+        let cred = account
+            .credential("primary")
+            .and_then(|c| c.update_totp_step(label.as_str(), step))
+            .expect("Unable to update totp step");
+        account.credentials = vec![("primary".to_string(), cred)];
+        let (state, used) = try_auth(account);
+        match state {
+            Ok(AuthState::Denied(msg)) => assert!(msg == BAD_TOTP_MSG),
+            _ => panic!(),
+        };
+        assert!(used.is_none());
+    }
+
+    fn setup_webauthn_softtoken(
+        webauthn: &Webauthn<WebauthnDomainConfig>,
+        name: &str,
+    ) -> (
+        WebauthnAuthenticator<U2FSoft>,
+        webauthn_rs::proto::Credential,
+    ) {
+        let mut wa = WebauthnAuthenticator::new(U2FSoft::new());
+        let (chal, reg_state) = webauthn
+            .generate_challenge_register(
+                &name.to_string(),
+                Some(UserVerificationPolicy::Discouraged),
+            )
+            .expect("Failed to setup webauthn rego challenge");
+
+        let r = wa
+            .do_registration(TEST_ORIGIN, chal)
+            .expect("Failed to create soft token");
+
+        let wan_cred = webauthn
+            .register_credential(&r, reg_state, |_| Ok(false))
+            .expect("Failed to register soft token");
+
+        (wa, wan_cred)
+    }
+
+    #[test]
+    fn test_idm_authsession_webauthn_only_mech() {
+        let webauthn = create_webauthn();
+        let mut audit = AuditScope::new("test_idm_authsession_webauthn_only_mech");
+        let mut account = entry_str_to_account!(JSON_ADMIN_V1);
+        let ts = Duration::from_secs(12345);
+
+        let (mut wa, wan_cred) = setup_webauthn_softtoken(&webauthn, account.name.as_str());
+        account.credentials = vec![(
+            "primary".to_string(),
+            Credential::new_webauthn_only("softtoken".to_string(), wan_cred.clone()),
+        )];
+
+        // check send password (fail)
+        {
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::Password("password".to_string())],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
+                Ok(AuthState::Denied(msg)) => assert!(msg == BAD_AUTH_TYPE_MSG),
+                _ => panic!(),
+            };
+        }
+
+        // check good webauthn, success, and that the counter is reported.
+        let creds = {
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
+            let resp = wa
+                .do_authentication(TEST_ORIGIN, webauthn_chal(&session))
+                .expect("failed to use softtoken to authenticate");
+            let creds = vec![AuthCredential::Webauthn(resp)];
+            match session.validate_creds(
+                &mut audit,
+                &creds,
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
+                Ok(AuthState::Success(_)) => {}
+                _ => panic!(),
+            };
+            match session.take_webauthn_counter() {
+                Some((u, cid, counter)) => {
+                    assert!(u == account.uuid);
+                    assert!(cid == wan_cred.cred_id);
+                    assert!(counter > wan_cred.counter);
+                }
+                None => panic!(),
+            };
+            creds
+        };
+
+        // check that a response can't be replayed to a new session.
+        {
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
+            match session.validate_creds(
+                &mut audit,
+                &creds,
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
+                Ok(AuthState::Denied(msg)) => assert!(msg == BAD_WEBAUTHN_MSG),
+                _ => panic!(),
+            };
+        }
+
+        // check that a token whose counter goes backwards, ie a cloned token, is denied.
+        {
+            let mut cloned_cred = wan_cred.clone();
+            cloned_cred.counter = 1_000_000;
+            let mut cloned_account = account.clone();
+            cloned_account.credentials = vec![(
+                "primary".to_string(),
+                Credential::new_webauthn_only("softtoken".to_string(), cloned_cred),
+            )];
+            let mut session = AuthSession::new(
+                cloned_account,
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
+            let resp = wa
+                .do_authentication(TEST_ORIGIN, webauthn_chal(&session))
+                .expect("failed to use softtoken to authenticate");
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::Webauthn(resp)],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
+                Ok(AuthState::Denied(msg)) => assert!(msg == BAD_WEBAUTHN_MSG),
+                _ => panic!(),
+            };
+        }
+
+        println!("{}", audit);
+    }
+
+    #[test]
+    fn test_idm_authsession_webauthn_password_mech() {
+        let webauthn = create_webauthn();
+        let mut audit = AuditScope::new("test_idm_authsession_webauthn_password_mech");
+        let mut account = entry_str_to_account!(JSON_ADMIN_V1);
+        let ts = Duration::from_secs(12345);
+
+        let pw_good = "test_password";
+        let pw_bad = "bad_password";
+
+        let (mut wa, wan_cred) = setup_webauthn_softtoken(&webauthn, account.name.as_str());
+        let cred = Credential::new_password_only(&CryptoPolicy::minimum(), pw_good)
+            .add_webauthn_token("softtoken".to_string(), wan_cred)
+            .expect("Failed to add webauthn token");
+        account.credentials = vec![("primary".to_string(), cred)];
+
+        let session = AuthSession::new(
+            account.clone(),
+            None,
+            false,
+            &webauthn,
+            &Duration::from_secs(0),
+        );
+        let auth_mechs = session.valid_auth_mechs();
+        assert!(auth_mechs.len() == 2);
+        assert!(auth_mechs.iter().fold(true, |acc, x| match x {
+            AuthAllowed::Password => acc,
+            AuthAllowed::Webauthn(_) => acc,
+            _ => false,
+        }));
+
+        // check send bad pw, should get continue, then good webauthn fails password.
+        {
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::Password(pw_bad.to_string())],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
+                Ok(AuthState::Continue(cont)) => {
+                    assert!(cont.len() == 1);
+                    assert!(cont.iter().all(|m| match m {
+                        AuthAllowed::Webauthn(_) => true,
+                        _ => false,
+                    }));
+                }
+                _ => panic!(),
+            };
+            let resp = wa
+                .do_authentication(TEST_ORIGIN, webauthn_chal(&session))
+                .expect("failed to use softtoken to authenticate");
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::Webauthn(resp)],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
+                Ok(AuthState::Denied(msg)) => assert!(msg == BAD_PASSWORD_MSG),
+                _ => panic!(),
+            };
+        }
+
+        // check a totp is rejected when the credential has none.
+        {
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
+            match session.validate_creds(
+                &mut audit,
+                &vec![AuthCredential::TOTP(0)],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
+            ) {
+                Ok(AuthState::Denied(msg)) => assert!(msg == BAD_TOTP_MSG),
+                _ => panic!(),
+            };
+        }
+
+        // check good webauthn, good pw, success
+        {
+            let mut session = AuthSession::new(
+                account.clone(),
+                None,
+                false,
+                &webauthn,
+                &Duration::from_secs(0),
+            );
+            let resp = wa
+                .do_authentication(TEST_ORIGIN, webauthn_chal(&session))
+                .expect("failed to use softtoken to authenticate");
+            match session.validate_creds(
+                &mut audit,
+                &vec![
+                    AuthCredential::Webauthn(resp),
+                    AuthCredential::Password(pw_good.to_string()),
+                ],
+                &ts,
+                &webauthn,
+                &CryptoPolicy::minimum(),
             ) {
                 Ok(AuthState::Success(_)) => {}
                 _ => panic!(),
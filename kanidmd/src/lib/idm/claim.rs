@@ -1,20 +1,44 @@
 use kanidm_proto::v1::Claim as ProtoClaim;
+use std::time::Duration;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Claim {
-    // For now, empty. Later we'll flesh this out to uuid + name?
+    pub name: String,
+    // Ephemeral claims stop being valid at this time, while the session continues.
+    pub expiry: Option<Duration>,
 }
 
 impl Claim {
-    /*
-    pub fn new() -> Self {
+    pub fn new(name: &str) -> Self {
         Claim {
-            // Fill this in!
+            name: name.to_string(),
+            expiry: None,
+        }
+    }
+
+    pub fn new_ephemeral(name: &str, expiry: Duration) -> Self {
+        Claim {
+            name: name.to_string(),
+            expiry: Some(expiry),
         }
     }
-    */
 
     pub fn to_proto(&self) -> ProtoClaim {
-        unimplemented!();
+        ProtoClaim {
+            name: self.name.clone(),
+            expiry: self.expiry.map(|e| e.as_secs()),
+        }
     }
 }
+
+/// The names of the claims in a session that are still valid at ct.
+pub(crate) fn valid_claim_names<'a>(claims: &'a [ProtoClaim], ct: &Duration) -> Vec<&'a str> {
+    claims
+        .iter()
+        .filter(|c| match c.expiry {
+            Some(expiry) => ct.as_secs() < expiry,
+            None => true,
+        })
+        .map(|c| c.name.as_str())
+        .collect()
+}
@@ -1,4 +1,4 @@
-use crate::actors::v1_write::IdmAccountSetPasswordMessage;
+use crate::actors::v1_write::{IdmAccountGenerateBackupCodesMessage, IdmAccountSetPasswordMessage};
 use crate::audit::AuditScope;
 use crate::event::Event;
 use crate::server::{QueryServerReadTransaction, QueryServerWriteTransaction};
@@ -6,22 +6,24 @@ use crate::server::{QueryServerReadTransaction, QueryServerWriteTransaction};
 use uuid::Uuid;
 
 use kanidm_proto::v1::{OperationError, UserAuthToken};
+use webauthn_rs::proto::RegisterPublicKeyCredential;
 
 #[derive(Debug)]
 pub struct PasswordChangeEvent {
     pub event: Event,
     pub target: Uuid,
     pub cleartext: String,
-    pub appid: Option<String>,
+    // The uuid or label of the credential to change, otherwise the primary credential.
+    pub cred_id: Option<String>,
 }
 
 impl PasswordChangeEvent {
-    pub fn new_internal(target: &Uuid, cleartext: &str, appid: Option<&str>) -> Self {
+    pub fn new_internal(target: &Uuid, cleartext: &str, cred_id: Option<&str>) -> Self {
         PasswordChangeEvent {
             event: Event::from_internal(),
             target: *target,
             cleartext: cleartext.to_string(),
-            appid: appid.map(|v| v.to_string()),
+            cred_id: cred_id.map(|v| v.to_string()),
         }
     }
 
@@ -37,7 +39,7 @@ impl PasswordChangeEvent {
             event: e,
             target: u,
             cleartext: msg.cleartext,
-            appid: None,
+            cred_id: None,
         })
     }
 
@@ -47,7 +49,7 @@ impl PasswordChangeEvent {
         uat: Option<UserAuthToken>,
         target: Uuid,
         cleartext: String,
-        appid: Option<String>,
+        cred_id: Option<String>,
     ) -> Result<Self, OperationError> {
         let e = Event::from_rw_uat(audit, qs, uat)?;
 
@@ -55,7 +57,7 @@ impl PasswordChangeEvent {
             event: e,
             target,
             cleartext,
-            appid,
+            cred_id,
         })
     }
 }
@@ -98,7 +100,7 @@ impl UnixPasswordChangeEvent {
 pub struct GeneratePasswordEvent {
     pub event: Event,
     pub target: Uuid,
-    pub appid: Option<String>,
+    pub cred_id: Option<String>,
 }
 
 impl GeneratePasswordEvent {
@@ -107,14 +109,14 @@ impl GeneratePasswordEvent {
         qs: &mut QueryServerWriteTransaction,
         uat: Option<UserAuthToken>,
         target: Uuid,
-        appid: Option<String>,
+        cred_id: Option<String>,
     ) -> Result<Self, OperationError> {
         let e = Event::from_rw_uat(audit, qs, uat)?;
 
         Ok(GeneratePasswordEvent {
             event: e,
             target,
-            appid,
+            cred_id,
         })
     }
 }
@@ -262,6 +264,7 @@ pub struct GenerateTOTPEvent {
     pub event: Event,
     pub target: Uuid,
     pub label: String,
+    pub cred_id: Option<String>,
 }
 
 impl GenerateTOTPEvent {
@@ -271,6 +274,7 @@ impl GenerateTOTPEvent {
         uat: Option<UserAuthToken>,
         target: Uuid,
         label: String,
+        cred_id: Option<String>,
     ) -> Result<Self, OperationError> {
         let e = Event::from_rw_uat(audit, qs, uat)?;
 
@@ -278,6 +282,7 @@ impl GenerateTOTPEvent {
             event: e,
             target,
             label,
+            cred_id,
         })
     }
 
@@ -289,6 +294,7 @@ impl GenerateTOTPEvent {
             event: e,
             target,
             label: "internal_token".to_string(),
+            cred_id: None,
         }
     }
 }
@@ -332,3 +338,540 @@ impl VerifyTOTPEvent {
         }
     }
 }
+
+#[derive(Debug)]
+pub struct GenerateBackupCodesEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl GenerateBackupCodesEvent {
+    pub fn from_idm_account_generate_backup_codes(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        msg: IdmAccountGenerateBackupCodesMessage,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, msg.uat)?;
+        // Backup codes are only ever generated for yourself.
+        let u = *e.get_uuid().ok_or(OperationError::InvalidState)?;
+
+        Ok(GenerateBackupCodesEvent {
+            event: e,
+            target: u,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        GenerateBackupCodesEvent { event: e, target }
+    }
+}
+
+#[derive(Debug)]
+pub struct WebauthnInitRegisterEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub label: String,
+    pub cred_id: Option<String>,
+}
+
+impl WebauthnInitRegisterEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+        label: String,
+        cred_id: Option<String>,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(WebauthnInitRegisterEvent {
+            event: e,
+            target,
+            label,
+            cred_id,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, label: String) -> Self {
+        let e = Event::from_internal();
+
+        WebauthnInitRegisterEvent {
+            event: e,
+            target,
+            label,
+            cred_id: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WebauthnDoRegisterEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub session: Uuid,
+    pub reg: RegisterPublicKeyCredential,
+}
+
+impl WebauthnDoRegisterEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+        session: Uuid,
+        reg: RegisterPublicKeyCredential,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(WebauthnDoRegisterEvent {
+            event: e,
+            target,
+            session,
+            reg,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, session: Uuid, reg: RegisterPublicKeyCredential) -> Self {
+        let e = Event::from_internal();
+
+        WebauthnDoRegisterEvent {
+            event: e,
+            target,
+            session,
+            reg,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WebauthnRemoveEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub label: String,
+    pub cred_id: Option<String>,
+}
+
+impl WebauthnRemoveEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+        label: String,
+        cred_id: Option<String>,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(WebauthnRemoveEvent {
+            event: e,
+            target,
+            label,
+            cred_id,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, label: String) -> Self {
+        let e = Event::from_internal();
+
+        WebauthnRemoveEvent {
+            event: e,
+            target,
+            label,
+            cred_id: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TOTPRemoveEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub label: String,
+    pub cred_id: Option<String>,
+}
+
+impl TOTPRemoveEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+        label: String,
+        cred_id: Option<String>,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(TOTPRemoveEvent {
+            event: e,
+            target,
+            label,
+            cred_id,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, label: String) -> Self {
+        let e = Event::from_internal();
+
+        TOTPRemoveEvent {
+            event: e,
+            target,
+            label,
+            cred_id: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TOTPListEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl TOTPListEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerReadTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_ro_uat(audit, qs, uat)?;
+
+        Ok(TOTPListEvent { event: e, target })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        TOTPListEvent { event: e, target }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnlockAccountEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl UnlockAccountEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerReadTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_ro_uat(audit, qs, uat)?;
+
+        Ok(UnlockAccountEvent { event: e, target })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        UnlockAccountEvent { event: e, target }
+    }
+}
+
+#[derive(Debug)]
+pub struct AppPasswordListEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl AppPasswordListEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerReadTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_ro_uat(audit, qs, uat)?;
+
+        Ok(AppPasswordListEvent { event: e, target })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        AppPasswordListEvent { event: e, target }
+    }
+}
+
+#[derive(Debug)]
+pub struct AppPasswordCreateEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub label: String,
+    pub application: String,
+}
+
+impl AppPasswordCreateEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+        label: String,
+        application: String,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(AppPasswordCreateEvent {
+            event: e,
+            target,
+            label,
+            application,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, label: &str, application: &str) -> Self {
+        let e = Event::from_internal();
+
+        AppPasswordCreateEvent {
+            event: e,
+            target,
+            label: label.to_string(),
+            application: application.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AppPasswordRemoveEvent {
+    pub event: Event,
+    pub target: Uuid,
+    pub label: String,
+}
+
+impl AppPasswordRemoveEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+        label: String,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(AppPasswordRemoveEvent {
+            event: e,
+            target,
+            label,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, label: &str) -> Self {
+        let e = Event::from_internal();
+
+        AppPasswordRemoveEvent {
+            event: e,
+            target,
+            label: label.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SessionListEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl SessionListEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerReadTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_ro_uat(audit, qs, uat)?;
+
+        Ok(SessionListEvent { event: e, target })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        SessionListEvent { event: e, target }
+    }
+}
+
+#[derive(Debug)]
+pub struct SessionRevokeEvent {
+    pub event: Event,
+    pub target: Uuid,
+    // The session to revoke, or all of them.
+    pub id: Option<Uuid>,
+}
+
+impl SessionRevokeEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+        id: Option<Uuid>,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(SessionRevokeEvent {
+            event: e,
+            target,
+            id,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, id: Option<Uuid>) -> Self {
+        let e = Event::from_internal();
+
+        SessionRevokeEvent {
+            event: e,
+            target,
+            id,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CredentialListEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl CredentialListEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerReadTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_ro_uat(audit, qs, uat)?;
+
+        Ok(CredentialListEvent { event: e, target })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        CredentialListEvent { event: e, target }
+    }
+}
+
+#[derive(Debug)]
+pub struct CredentialLockEvent {
+    pub event: Event,
+    pub target: Uuid,
+    // The uuid or label of the credential.
+    pub cred_id: String,
+    pub locked: bool,
+}
+
+impl CredentialLockEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+        cred_id: String,
+        locked: bool,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(CredentialLockEvent {
+            event: e,
+            target,
+            cred_id,
+            locked,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, cred_id: String, locked: bool) -> Self {
+        let e = Event::from_internal();
+
+        CredentialLockEvent {
+            event: e,
+            target,
+            cred_id,
+            locked,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CredentialRemoveEvent {
+    pub event: Event,
+    pub target: Uuid,
+    // The uuid or label of the credential.
+    pub cred_id: String,
+}
+
+impl CredentialRemoveEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+        cred_id: String,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(CredentialRemoveEvent {
+            event: e,
+            target,
+            cred_id,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid, cred_id: String) -> Self {
+        let e = Event::from_internal();
+
+        CredentialRemoveEvent {
+            event: e,
+            target,
+            cred_id,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CredentialResetTokenCreateEvent {
+    pub event: Event,
+    pub target: Uuid,
+}
+
+impl CredentialResetTokenCreateEvent {
+    pub fn from_parts(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        uat: Option<UserAuthToken>,
+        target: Uuid,
+    ) -> Result<Self, OperationError> {
+        let e = Event::from_rw_uat(audit, qs, uat)?;
+
+        Ok(CredentialResetTokenCreateEvent { event: e, target })
+    }
+
+    #[cfg(test)]
+    pub fn new_internal(target: Uuid) -> Self {
+        let e = Event::from_internal();
+
+        CredentialResetTokenCreateEvent { event: e, target }
+    }
+}
@@ -1,5 +1,6 @@
 use crate::audit::AuditScope;
 use crate::entry::{Entry, EntryCommitted, EntryReduced, EntrySealed};
+use crate::idm::pwpolicy::PasswordPolicy;
 use crate::server::{
     QueryServerReadTransaction, QueryServerTransaction, QueryServerWriteTransaction,
 };
@@ -17,7 +18,8 @@ lazy_static! {
 pub struct Group {
     name: String,
     uuid: Uuid,
-    // We'll probably add policy and claims later to this
+    pwpolicy: Option<PasswordPolicy>,
+    // We'll probably add claims later to this
 }
 
 macro_rules! try_from_account_e {
@@ -93,7 +95,17 @@ impl Group {
 
         let uuid = *value.get_uuid();
 
-        Ok(Group { name, uuid })
+        let pwpolicy = PasswordPolicy::try_from_entry(&value);
+
+        Ok(Group {
+            name,
+            uuid,
+            pwpolicy,
+        })
+    }
+
+    pub(crate) fn password_policy(&self) -> Option<&PasswordPolicy> {
+        self.pwpolicy.as_ref()
     }
 
     pub fn to_proto(&self) -> ProtoGroup {
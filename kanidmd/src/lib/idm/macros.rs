@@ -25,6 +25,7 @@ macro_rules! run_idm_test {
     ($test_fn:expr) => {{
         use crate::audit::AuditScope;
         use crate::be::Backend;
+        use crate::credential::CryptoPolicy;
         use crate::idm::server::IdmServer;
         use crate::schema::Schema;
         use crate::server::QueryServer;
@@ -44,7 +45,11 @@ macro_rules! run_idm_test {
             .initialise_helper(&mut audit, duration_from_epoch_now())
             .expect("init failed");
 
-        let test_idm_server = IdmServer::new(test_server.clone());
+        let test_idm_server = IdmServer::new(
+            test_server.clone(),
+            "https://idm.example.com",
+            CryptoPolicy::minimum(),
+        );
 
         $test_fn(&test_server, &test_idm_server, &mut audit);
         // Any needed teardown?
@@ -1,39 +1,54 @@
 use crate::credential::totp::{TOTP, TOTP_DEFAULT_STEP};
+use crate::credential::webauthn::WebauthnDomainConfig;
 use crate::event::EventOriginId;
 use crate::idm::account::Account;
+use crate::utils::backup_codes_from_random;
 use kanidm_proto::v1::TOTPSecret;
 use kanidm_proto::v1::{OperationError, SetCredentialResponse};
 use std::mem;
 use std::time::Duration;
 use uuid::Uuid;
+use webauthn_rs::proto::Credential as WebauthnCredential;
+use webauthn_rs::proto::{
+    CreationChallengeResponse, RegisterPublicKeyCredential, UserVerificationPolicy,
+};
+use webauthn_rs::{RegistrationState, Webauthn};
 
-// Client requests they want to reg a TOTP to account.
+// Client requests they want to reg a TOTP or webauthn token to account.
 pub(crate) enum MfaReqInit {
     TOTP(String),
-    // Webauthn
+    Webauthn(String),
 }
 
-pub(crate) enum MfaReqStep {
+pub(crate) enum MfaReqStep<'a> {
     TOTPVerify(u32),
+    WebauthnRegister(&'a RegisterPublicKeyCredential),
 }
 
 pub(crate) enum MfaRegCred {
-    TOTP(TOTP),
+    // The token, and the cleartext of the backup codes issued with it.
+    TOTP(TOTP, Vec<String>),
+    Webauthn(String, WebauthnCredential),
 }
 
 pub(crate) enum MfaRegNext {
     Success,
+    BackupCodes(Vec<String>),
     TOTPCheck(TOTPSecret),
-    // Webauthn(chal)
+    WebauthnChallenge(CreationChallengeResponse),
 }
 
 impl MfaRegNext {
     pub fn to_proto(&self, u: &Uuid) -> SetCredentialResponse {
         match self {
             MfaRegNext::Success => SetCredentialResponse::Success,
+            MfaRegNext::BackupCodes(codes) => SetCredentialResponse::BackupCodes(codes.clone()),
             MfaRegNext::TOTPCheck(secret) => {
                 SetCredentialResponse::TOTPCheck(u.clone(), (*secret).clone())
             }
+            MfaRegNext::WebauthnChallenge(ccr) => {
+                SetCredentialResponse::WebauthnCreateChallenge(u.clone(), ccr.clone())
+            }
         }
     }
 }
@@ -42,7 +57,9 @@ impl MfaRegNext {
 enum MfaRegState {
     TOTPInit(TOTP),
     TOTPDone,
-    // Webauthn ...
+    // label, challenge, registration state
+    WebauthnInit(String, CreationChallengeResponse, RegistrationState),
+    WebauthnDone,
 }
 
 #[derive(Clone)]
@@ -52,6 +69,8 @@ pub(crate) struct MfaRegSession {
     origin: EventOriginId,
     // The account that the MFA will be registered to
     pub account: Account,
+    // And the label of the credential of the account it's added to.
+    pub cred_label: String,
     // What state is the reg process in?
     state: MfaRegState,
 }
@@ -60,18 +79,37 @@ impl MfaRegSession {
     pub fn new(
         origin: EventOriginId,
         account: Account,
+        cred_label: String,
         req: MfaReqInit,
+        webauthn: &Webauthn<WebauthnDomainConfig>,
     ) -> Result<(Self, MfaRegNext), OperationError> {
         // Based on the req, init our session, and the return the next step.
         // Store the ID of the event that start's the attempt
         let state = match req {
             MfaReqInit::TOTP(label) => {
+                // Fail early, rather than after the user has setup their device.
+                if account.totp_labels().contains(&label) {
+                    return Err(OperationError::InvalidAttribute(format!(
+                        "TOTP label {} already exists",
+                        label
+                    )));
+                }
                 MfaRegState::TOTPInit(TOTP::generate_secure(label, TOTP_DEFAULT_STEP))
             }
+            MfaReqInit::Webauthn(label) => {
+                let (ccr, rs) = webauthn
+                    .generate_challenge_register(
+                        &account.name,
+                        Some(UserVerificationPolicy::Preferred),
+                    )
+                    .map_err(|_| OperationError::Webauthn)?;
+                MfaRegState::WebauthnInit(label, ccr, rs)
+            }
         };
         let s = MfaRegSession {
             origin,
             account,
+            cred_label,
             state,
         };
         let next = s.next();
@@ -82,8 +120,9 @@ impl MfaRegSession {
         &mut self,
         origin: &EventOriginId,
         target: &Uuid,
-        req: MfaReqStep,
+        req: MfaReqStep<'_>,
         ct: &Duration,
+        webauthn: &Webauthn<WebauthnDomainConfig>,
     ) -> Result<(MfaRegNext, Option<MfaRegCred>), OperationError> {
         if &self.origin != origin || target != &self.account.uuid {
             // Verify that the same event source is the one continuing this attempt
@@ -92,15 +131,20 @@ impl MfaRegSession {
 
         match (req, &self.state) {
             (MfaReqStep::TOTPVerify(chal), MfaRegState::TOTPInit(token)) => {
-                if token.verify(chal, ct) {
-                    let mut nstate = MfaRegState::TOTPDone;
-                    mem::swap(&mut self.state, &mut nstate);
-                    match nstate {
-                        MfaRegState::TOTPInit(token) => {
-                            Ok((MfaRegNext::Success, Some(MfaRegCred::TOTP(token))))
-                        }
-                        _ => Err(OperationError::InvalidState),
-                    }
+                // The code used to verify the device can't then be used to authenticate.
+                let verified = token
+                    .verify_step(chal, ct)
+                    .and_then(|step| token.update_last_step(step));
+                if let Some(token) = verified {
+                    self.state = MfaRegState::TOTPDone;
+                    // Issue backup codes with the token, so that losing the device
+                    // doesn't lock the user out. This is the only time the user
+                    // will see them.
+                    let codes = backup_codes_from_random();
+                    Ok((
+                        MfaRegNext::BackupCodes(codes.clone()),
+                        Some(MfaRegCred::TOTP(token, codes)),
+                    ))
                 } else {
                     // Let them try again?
                     let accountname = self.account.name.as_str();
@@ -111,6 +155,23 @@ impl MfaRegSession {
                     ))
                 }
             }
+            (MfaReqStep::WebauthnRegister(reg), MfaRegState::WebauthnInit(_, _, rs)) => {
+                // This checks the challenge, origin and attestation of the response. Tokens
+                // that are already registered to this account are rejected.
+                let account = &self.account;
+                let cred = webauthn
+                    .register_credential(reg, rs.clone(), |cid| Ok(account.has_webauthn_token(cid)))
+                    .map_err(|_| OperationError::Webauthn)?;
+
+                let mut nstate = MfaRegState::WebauthnDone;
+                mem::swap(&mut self.state, &mut nstate);
+                match nstate {
+                    MfaRegState::WebauthnInit(label, _, _) => {
+                        Ok((MfaRegNext::Success, Some(MfaRegCred::Webauthn(label, cred))))
+                    }
+                    _ => Err(OperationError::InvalidState),
+                }
+            }
             _ => Err(OperationError::InvalidRequestState),
         }
     }
@@ -120,12 +181,13 @@ impl MfaRegSession {
     pub fn next(&self) -> MfaRegNext {
         // Given our current state, what is the next step we need to process or offer?
         match &self.state {
-            MfaRegState::TOTPDone => MfaRegNext::Success,
+            MfaRegState::TOTPDone | MfaRegState::WebauthnDone => MfaRegNext::Success,
             MfaRegState::TOTPInit(token) => {
                 let accountname = self.account.name.as_str();
                 let issuer = self.account.spn.as_str();
                 MfaRegNext::TOTPCheck(token.to_proto(accountname, issuer))
             }
+            MfaRegState::WebauthnInit(_, ccr, _) => MfaRegNext::WebauthnChallenge(ccr.clone()),
         }
     }
 }
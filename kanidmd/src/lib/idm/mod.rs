@@ -7,7 +7,10 @@ pub(crate) mod claim;
 pub(crate) mod event;
 pub(crate) mod group;
 pub(crate) mod mfareg;
+pub(crate) mod pwpolicy;
 pub(crate) mod radius;
 pub(crate) mod server;
+pub(crate) mod session;
+pub(crate) mod softlock;
 pub(crate) mod unix;
 // mod identity;
@@ -0,0 +1,157 @@
+use crate::constants::{PW_MIN_LENGTH, PW_MIN_SCORE};
+use crate::entry::{Entry, EntryCommitted, EntrySealed};
+use crate::idm::group::Group;
+use crate::value::PartialValue;
+
+use std::time::Duration;
+
+lazy_static! {
+    static ref PVCLASS_PASSWORD_POLICY: PartialValue = PartialValue::new_class("password_policy");
+}
+
+/// The password requirements of an account. Policies are attached to groups with the
+/// password_policy class, and an account is held to the strictest combination of the policies
+/// of every group it is a member of. An account in no policy group has the default policy.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PasswordPolicy {
+    pub min_length: usize,
+    pub min_score: u8,
+    pub badlist: bool,
+    pub require_mfa: bool,
+    pub max_age: Option<Duration>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            min_length: PW_MIN_LENGTH,
+            min_score: PW_MIN_SCORE,
+            badlist: true,
+            require_mfa: false,
+            max_age: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// Get the policy of a group entry, if it has one. Any attribute the group does not set
+    /// takes the default.
+    pub fn try_from_entry(value: &Entry<EntrySealed, EntryCommitted>) -> Option<Self> {
+        if !value.attribute_value_pres("class", &PVCLASS_PASSWORD_POLICY) {
+            return None;
+        }
+
+        let d = PasswordPolicy::default();
+        Some(PasswordPolicy {
+            min_length: value
+                .get_ava_single_uint32("pw_min_length")
+                .map(|v| v as usize)
+                .unwrap_or(d.min_length),
+            min_score: value
+                .get_ava_single_uint32("pw_min_score")
+                .map(|v| v as u8)
+                .unwrap_or(d.min_score),
+            badlist: value.get_ava_single_bool("pw_badlist").unwrap_or(d.badlist),
+            require_mfa: value
+                .get_ava_single_bool("pw_require_mfa")
+                .unwrap_or(d.require_mfa),
+            max_age: value.get_ava_single_duration("pw_max_age").or(d.max_age),
+        })
+    }
+
+    /// Resolve the policy that applies to a member of these groups.
+    pub fn from_groups(groups: &[Group]) -> Self {
+        groups
+            .iter()
+            .filter_map(|g| g.password_policy())
+            .fold(None, |acc: Option<PasswordPolicy>, p| match acc {
+                Some(acc) => Some(acc.merge(p)),
+                None => Some(p.clone()),
+            })
+            .unwrap_or_default()
+    }
+
+    /// Combine two policies, keeping the strictest requirement of each.
+    pub fn merge(&self, other: &Self) -> Self {
+        let max_age = match (self.max_age, other.max_age) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        PasswordPolicy {
+            min_length: self.min_length.max(other.min_length),
+            min_score: self.min_score.max(other.min_score),
+            badlist: self.badlist || other.badlist,
+            require_mfa: self.require_mfa || other.require_mfa,
+            max_age,
+        }
+    }
+
+    /// Has a password that was set at `password_set` expired by ct? Passwords that don't know
+    /// when they were set, such as imported ones, never expire.
+    pub fn is_password_expired(&self, password_set: Option<Duration>, ct: &Duration) -> bool {
+        match (self.max_age, password_set) {
+            (Some(max_age), Some(set)) => *ct >= set + max_age,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::idm::pwpolicy::PasswordPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn test_idm_pwpolicy_merge() {
+        let a = PasswordPolicy {
+            min_length: 16,
+            min_score: 2,
+            badlist: false,
+            require_mfa: false,
+            max_age: None,
+        };
+        let b = PasswordPolicy {
+            min_length: 8,
+            min_score: 4,
+            badlist: false,
+            require_mfa: true,
+            max_age: Some(Duration::from_secs(100)),
+        };
+        let c = PasswordPolicy {
+            min_length: 8,
+            min_score: 0,
+            badlist: true,
+            require_mfa: false,
+            max_age: Some(Duration::from_secs(50)),
+        };
+
+        let m = a.merge(&b).merge(&c);
+        assert!(
+            m == PasswordPolicy {
+                min_length: 16,
+                min_score: 4,
+                badlist: true,
+                require_mfa: true,
+                max_age: Some(Duration::from_secs(50)),
+            }
+        );
+        // Order doesn't matter.
+        assert!(m == c.merge(&b).merge(&a));
+    }
+
+    #[test]
+    fn test_idm_pwpolicy_expiry() {
+        let p = PasswordPolicy {
+            max_age: Some(Duration::from_secs(100)),
+            ..Default::default()
+        };
+        let set = Some(Duration::from_secs(1000));
+        assert!(!p.is_password_expired(set, &Duration::from_secs(1099)));
+        assert!(p.is_password_expired(set, &Duration::from_secs(1100)));
+        // An unknown set time never expires.
+        assert!(!p.is_password_expired(None, &Duration::from_secs(1_000_000)));
+        // And no max age never expires.
+        let p = PasswordPolicy::default();
+        assert!(!p.is_password_expired(set, &Duration::from_secs(1_000_000)));
+    }
+}
@@ -1,4 +1,6 @@
+use crate::idm::account::is_within_valid_time;
 use crate::idm::group::Group;
+use std::time::Duration;
 use uuid::Uuid;
 
 use crate::audit::AuditScope;
@@ -19,6 +21,8 @@ pub(crate) struct RadiusAccount {
     pub uuid: Uuid,
     pub groups: Vec<Group>,
     pub radius_secret: String,
+    pub valid_from: Option<Duration>,
+    pub expire: Option<Duration>,
 }
 
 impl RadiusAccount {
@@ -52,15 +56,25 @@ impl RadiusAccount {
 
         let groups = Group::try_from_account_entry_red_ro(au, &value, qs)?;
 
+        let valid_from = value.get_ava_single_duration("account_valid_from");
+
+        let expire = value.get_ava_single_duration("account_expire");
+
         Ok(RadiusAccount {
             name,
             uuid,
             displayname,
             groups,
             radius_secret,
+            valid_from,
+            expire,
         })
     }
 
+    pub fn is_within_valid_time(&self, ct: &Duration) -> bool {
+        is_within_valid_time(self.valid_from, self.expire, ct)
+    }
+
     pub(crate) fn to_radiusauthtoken(&self) -> Result<RadiusAuthToken, OperationError> {
         // If we don't have access/permission, then just error instead.
         // This includes if we don't have the secret.
@@ -1,34 +1,76 @@
+use crate::access::AccessControlsTransaction;
 use crate::audit::AuditScope;
-use crate::constants::UUID_SYSTEM_CONFIG;
-use crate::constants::{AUTH_SESSION_TIMEOUT, MFAREG_SESSION_TIMEOUT, PW_MIN_LENGTH};
-use crate::event::{AuthEvent, AuthEventStep, AuthResult};
+use crate::constants::{AUTH_SESSION_TIMEOUT, MFAREG_SESSION_TIMEOUT};
+use crate::constants::{UUID_ANONYMOUS, UUID_DOMAIN_INFO, UUID_SYSTEM_CONFIG};
+use crate::credential::webauthn::WebauthnDomainConfig;
+use crate::credential::{CryptoPolicy, Password};
+use crate::event::{AuthEvent, AuthEventStep, AuthResult, ModifyEvent};
 use crate::idm::account::Account;
 use crate::idm::authsession::AuthSession;
 use crate::idm::event::{
-    GeneratePasswordEvent, GenerateTOTPEvent, PasswordChangeEvent, RadiusAuthTokenEvent,
-    RegenerateRadiusSecretEvent, UnixGroupTokenEvent, UnixPasswordChangeEvent, UnixUserAuthEvent,
-    UnixUserTokenEvent, VerifyTOTPEvent,
+    AppPasswordCreateEvent, AppPasswordListEvent, AppPasswordRemoveEvent, CredentialListEvent,
+    CredentialLockEvent, CredentialRemoveEvent, CredentialResetTokenCreateEvent,
+    GenerateBackupCodesEvent, GeneratePasswordEvent, GenerateTOTPEvent, PasswordChangeEvent,
+    RadiusAuthTokenEvent, RegenerateRadiusSecretEvent, SessionListEvent, SessionRevokeEvent,
+    TOTPListEvent, TOTPRemoveEvent, UnixGroupTokenEvent, UnixPasswordChangeEvent,
+    UnixUserAuthEvent, UnixUserTokenEvent, UnlockAccountEvent, VerifyTOTPEvent,
+    WebauthnDoRegisterEvent, WebauthnInitRegisterEvent, WebauthnRemoveEvent,
 };
+use crate::idm::group::Group;
 use crate::idm::mfareg::{MfaRegCred, MfaRegNext, MfaRegSession, MfaReqInit, MfaReqStep};
+use crate::idm::pwpolicy::PasswordPolicy;
 use crate::idm::radius::RadiusAccount;
+use crate::idm::session::Session;
+use crate::idm::softlock::{SoftLock, ACCOUNT_SOFTLOCKED_MSG};
 use crate::idm::unix::{UnixGroup, UnixUserAccount};
+use crate::jws::{BearerClaims, JwsKeySet};
+use crate::modify::{Modify, ModifyList};
 use crate::server::QueryServerReadTransaction;
 use crate::server::{QueryServer, QueryServerTransaction, QueryServerWriteTransaction};
-use crate::utils::{password_from_random, readable_password_from_random, uuid_from_duration, SID};
-use crate::value::PartialValue;
+use crate::utils::{
+    backup_codes_from_random, password_from_random, readable_password_from_random,
+    uuid_from_duration, SID,
+};
+use crate::value::{PartialValue, Value};
 
+use kanidm_proto::v1::AppPassword;
 use kanidm_proto::v1::AuthState;
+use kanidm_proto::v1::CredentialInfo;
 use kanidm_proto::v1::OperationError;
 use kanidm_proto::v1::RadiusAuthToken;
+use kanidm_proto::v1::SessionInfo;
 // use kanidm_proto::v1::TOTPSecret as ProtoTOTPSecret;
 use kanidm_proto::v1::SetCredentialResponse;
 use kanidm_proto::v1::UnixGroupToken;
 use kanidm_proto::v1::UnixUserToken;
+use kanidm_proto::v1::UserAuthToken;
 
 use concread::collections::bptree::*;
+use concread::cowcell::*;
 use rand::prelude::*;
 use std::time::Duration;
 use uuid::Uuid;
+use webauthn_rs::proto::{Counter, CredentialID};
+use webauthn_rs::Webauthn;
+
+// The account, token and new signature counter of a webauthn authentication.
+pub type WebauthnCounterUpdate = (Uuid, CredentialID, Counter);
+
+// The account, totp label and time step of a totp code that was accepted.
+pub type TotpStepUpdate = (Uuid, String, u64);
+
+// The account and backup code that was used in an authentication.
+pub type BackupCodeUse = (Uuid, Password);
+
+// The account and session that a bearer token was issued for.
+pub type IssuedSession = (Uuid, Session);
+
+// A password that was verified during an authentication, but is hashed more weakly than
+// the crypto policy. This is the account, the hash that was verified, and its replacement.
+pub enum PasswordUpgrade {
+    Primary(Uuid, Password, Password),
+    Unix(Uuid, Password, Password),
+}
 
 pub struct IdmServer {
     // There is a good reason to keep this single thread - it
@@ -38,8 +80,19 @@ pub struct IdmServer {
     sessions: BptreeMap<Uuid, AuthSession>,
     // Keep a set of inprogress mfa registrations
     mfareg_sessions: BptreeMap<Uuid, MfaRegSession>,
+    // Authentication failures per account uuid. These are in memory and per server
+    // only, so a restart clears them.
+    softlocks: BptreeMap<Uuid, SoftLock>,
     // Need a reference to the query server.
     qs: QueryServer,
+    // The relying party that webauthn challenges are issued for.
+    webauthn: Webauthn<WebauthnDomainConfig>,
+    // How new passwords are hashed.
+    crypto_policy: CryptoPolicy,
+    // The keys that bearer tokens are signed with, as loaded by rotate_jws_keys.
+    jws_keys: CowCell<JwsKeySet>,
+    // Bearer tokens are issued by, and only accepted for, our origin.
+    issuer: String,
 }
 
 pub struct IdmServerWriteTransaction<'a> {
@@ -47,15 +100,32 @@ pub struct IdmServerWriteTransaction<'a> {
     // the idm in memory structures (maybe the query server too). This is
     // things like authentication
     sessions: BptreeMapWriteTxn<'a, Uuid, AuthSession>,
+    softlocks: BptreeMapWriteTxn<'a, Uuid, SoftLock>,
     pub qs_read: QueryServerReadTransaction<'a>,
     // thread/server id
     sid: SID,
+    webauthn: &'a Webauthn<WebauthnDomainConfig>,
+    // Counters from successful webauthn auths, that need to be written back to
+    // the accounts after this transaction.
+    webauthn_counters: Vec<WebauthnCounterUpdate>,
+    crypto_policy: &'a CryptoPolicy,
+    // Likewise, rehashed passwords from successful auths.
+    password_upgrades: Vec<PasswordUpgrade>,
+    // And backup codes that have been used, and must be removed.
+    backup_code_uses: Vec<BackupCodeUse>,
+    // And the time steps of accepted totp codes, so they can't be replayed.
+    totp_steps: Vec<TotpStepUpdate>,
+    // And the sessions of issued bearer tokens, which are only valid once stored.
+    issued_sessions: Vec<IssuedSession>,
+    jws_keys: CowCellReadTxn<JwsKeySet>,
+    issuer: &'a str,
 }
 
 pub struct IdmServerProxyReadTransaction<'a> {
     // This contains read-only methods, like getting users, groups
     // and other structured content.
     pub qs_read: QueryServerReadTransaction<'a>,
+    softlocks: BptreeMapReadTxn<Uuid, SoftLock>,
 }
 
 pub struct IdmServerProxyWriteTransaction<'a> {
@@ -65,15 +135,23 @@ pub struct IdmServerProxyWriteTransaction<'a> {
     // Associate to an event origin ID, which has a TS and a UUID instead
     mfareg_sessions: BptreeMapWriteTxn<'a, Uuid, MfaRegSession>,
     sid: SID,
+    webauthn: &'a Webauthn<WebauthnDomainConfig>,
+    crypto_policy: &'a CryptoPolicy,
+    jws_keys: CowCellWriteTxn<'a, JwsKeySet>,
 }
 
 impl IdmServer {
     // TODO #59: Make number of authsessions configurable!!!
-    pub fn new(qs: QueryServer) -> IdmServer {
+    pub fn new(qs: QueryServer, origin: &str, crypto_policy: CryptoPolicy) -> IdmServer {
         IdmServer {
             sessions: BptreeMap::new(),
             mfareg_sessions: BptreeMap::new(),
+            softlocks: BptreeMap::new(),
             qs,
+            webauthn: Webauthn::new(WebauthnDomainConfig::new(origin)),
+            crypto_policy,
+            jws_keys: CowCell::new(JwsKeySet::default()),
+            issuer: origin.to_string(),
         }
     }
 
@@ -84,15 +162,26 @@ impl IdmServer {
 
         IdmServerWriteTransaction {
             sessions: self.sessions.write(),
+            softlocks: self.softlocks.write(),
             // qs: &self.qs,
             qs_read: self.qs.read(),
             sid,
+            webauthn: &self.webauthn,
+            webauthn_counters: Vec::new(),
+            crypto_policy: &self.crypto_policy,
+            password_upgrades: Vec::new(),
+            backup_code_uses: Vec::new(),
+            totp_steps: Vec::new(),
+            issued_sessions: Vec::new(),
+            jws_keys: self.jws_keys.read(),
+            issuer: self.issuer.as_str(),
         }
     }
 
     pub fn proxy_read(&self) -> IdmServerProxyReadTransaction {
         IdmServerProxyReadTransaction {
             qs_read: self.qs.read(),
+            softlocks: self.softlocks.read(),
         }
     }
 
@@ -105,6 +194,45 @@ impl IdmServer {
             mfareg_sessions: self.mfareg_sessions.write(),
             qs_write: self.qs.write(ts),
             sid,
+            webauthn: &self.webauthn,
+            crypto_policy: &self.crypto_policy,
+            jws_keys: self.jws_keys.write(),
+        }
+    }
+
+    /// The user auth token of a bearer token, if one of our keys signed it, it hasn't
+    /// expired, and its session is still present on the account.
+    pub fn validate_bearer(
+        &self,
+        au: &mut AuditScope,
+        token: &str,
+        ct: Duration,
+    ) -> Option<UserAuthToken> {
+        let claims = self
+            .jws_keys
+            .read()
+            .verify(token, self.issuer.as_str(), &ct)?;
+        let target = Uuid::parse_str(claims.uat.uuid.as_str()).ok()?;
+        // Anonymous sessions are never recorded, as anyone can begin another.
+        if target == *UUID_ANONYMOUS {
+            return Some(claims.uat);
+        }
+
+        let mut qs_read = self.qs.read();
+        match qs_read.internal_search_uuid(au, &target) {
+            Ok(entry) => {
+                if entry.attribute_value_pres("session", &PartialValue::new_session_id(claims.sid))
+                {
+                    Some(claims.uat)
+                } else {
+                    audit_log!(au, "Session {:?} of {:?} is revoked", claims.sid, target);
+                    None
+                }
+            }
+            Err(e) => {
+                audit_log!(au, "Unable to find the account of session -> {:?}", e);
+                None
+            }
         }
     }
 }
@@ -124,6 +252,35 @@ impl<'a> IdmServerWriteTransaction<'a> {
         // expired will now be dropped, and can't be used by future sessions.
     }
 
+    pub fn expire_softlocks(&mut self, ct: Duration) {
+        let expired: Vec<Uuid> = self
+            .softlocks
+            .iter()
+            .filter(|(_, sl)| sl.is_expired(&ct))
+            .map(|(u, _)| *u)
+            .collect();
+        expired.iter().for_each(|u| {
+            self.softlocks.remove(u);
+        });
+    }
+
+    fn is_softlocked(&self, account_uuid: &Uuid, ct: &Duration) -> bool {
+        self.softlocks
+            .get(account_uuid)
+            .map(|sl| sl.is_locked(ct))
+            .unwrap_or(false)
+    }
+
+    fn record_softlock_failure(&mut self, account_uuid: Uuid, ct: &Duration) {
+        if let Some(sl) = self.softlocks.get_mut(&account_uuid) {
+            sl.record_failure(ct);
+            return;
+        }
+        let mut sl = SoftLock::new();
+        sl.record_failure(ct);
+        self.softlocks.insert(account_uuid, sl);
+    }
+
     pub fn auth(
         &mut self,
         au: &mut AuditScope,
@@ -155,12 +312,17 @@ impl<'a> IdmServerWriteTransaction<'a> {
                 // Check anything needed? Get the current auth-session-id from request
                 // because it associates to the nonce's etc which were all cached.
 
-                let filter_entry = filter!(f_or!([
-                    f_eq("name", PartialValue::new_iutf8s(init.name.as_str())),
-                    // This currently says invalid syntax, which is correct, but also
-                    // annoying because it would be nice to search both ...
-                    // f_eq("uuid", name.as_str()),
-                ]));
+                let filter_entry = match &init.reauth {
+                    // A re-authentication is always of the account the session is for,
+                    // even if it has been renamed since.
+                    Some(u) => filter!(f_eq("uuid", PartialValue::new_uuidr(u))),
+                    None => filter!(f_or!([
+                        f_eq("name", PartialValue::new_iutf8s(init.name.as_str())),
+                        // This currently says invalid syntax, which is correct, but also
+                        // annoying because it would be nice to search both ...
+                        // f_eq("uuid", name.as_str()),
+                    ])),
+                };
 
                 // Get the first / single entry we expect here ....
                 let entry = match self.qs_read.internal_search(au, filter_entry) {
@@ -184,7 +346,23 @@ impl<'a> IdmServerWriteTransaction<'a> {
                 // continue, and helps to keep non-needed entry specific data
                 // out of the LRU.
                 let account = Account::try_from_entry_ro(au, entry, &mut self.qs_read)?;
-                let auth_session = AuthSession::new(account, init.appid.clone());
+
+                // The anonymous account can never fail, so is never locked.
+                if !account.is_anonymous() && self.is_softlocked(&account.uuid, &ct) {
+                    audit_log!(au, "Account {:?} is softlocked, denying", account.uuid);
+                    return Ok(AuthResult {
+                        sessionid,
+                        state: AuthState::Denied(ACCOUNT_SOFTLOCKED_MSG.to_string()),
+                    });
+                }
+
+                let auth_session = AuthSession::new(
+                    account,
+                    init.appid.clone(),
+                    init.reauth.is_some(),
+                    self.webauthn,
+                    &ct,
+                );
 
                 // Get the set of mechanisms that can proceed. This is tied
                 // to the session so that it can mutate state and have progression
@@ -208,25 +386,76 @@ impl<'a> IdmServerWriteTransaction<'a> {
             }
             AuthEventStep::Creds(creds) => {
                 // Do we have a session?
-                let auth_session = try_audit!(
+                let (account_uuid, is_anonymous) = try_audit!(
                     au,
                     self.sessions
                         // Why is the session missing?
+                        .get(&creds.sessionid)
+                        .map(|s| (s.get_account_uuid(), s.is_anonymous()))
+                        .ok_or(OperationError::InvalidSessionState)
+                );
+
+                // The account may have been locked by another session since this one
+                // began, so check again before we look at the credentials.
+                if !is_anonymous && self.is_softlocked(&account_uuid, &ct) {
+                    audit_log!(au, "Account {:?} is softlocked, denying", account_uuid);
+                    self.sessions.remove(&creds.sessionid);
+                    return Ok(AuthResult {
+                        sessionid: creds.sessionid,
+                        state: AuthState::Denied(ACCOUNT_SOFTLOCKED_MSG.to_string()),
+                    });
+                }
+
+                let auth_session = try_audit!(
+                    au,
+                    self.sessions
                         .get_mut(&creds.sessionid)
                         .ok_or(OperationError::InvalidSessionState)
                 );
                 // Process the credentials here as required.
                 // Basically throw them at the auth_session and see what
                 // falls out.
-                auth_session
-                    .validate_creds(au, &creds.creds, &ct)
+                let r = auth_session
+                    .validate_creds(au, &creds.creds, &ct, self.webauthn, self.crypto_policy)
                     .map(|aus| {
                         AuthResult {
                             // Is this right?
                             sessionid: creds.sessionid,
                             state: aus,
                         }
-                    })
+                    });
+                // We only hold a read of the db here, so the new counter has to be
+                // stored by a later write.
+                if let Some(update) = auth_session.take_webauthn_counter() {
+                    self.webauthn_counters.push(update);
+                }
+                if let Some((target, old, new)) = auth_session.take_password_upgrade() {
+                    self.password_upgrades
+                        .push(PasswordUpgrade::Primary(target, old, new));
+                }
+                if let Some(code_use) = auth_session.take_backup_code_used() {
+                    self.backup_code_uses.push(code_use);
+                }
+                if let Some(update) = auth_session.take_totp_used() {
+                    self.totp_steps.push(update);
+                }
+
+                if !is_anonymous {
+                    match &r {
+                        Ok(AuthResult {
+                            state: AuthState::Denied(_),
+                            ..
+                        }) => self.record_softlock_failure(account_uuid, &ct),
+                        Ok(AuthResult {
+                            state: AuthState::Success(_),
+                            ..
+                        }) => {
+                            self.softlocks.remove(&account_uuid);
+                        }
+                        _ => {}
+                    }
+                }
+                r
             }
         }
     }
@@ -235,25 +464,178 @@ impl<'a> IdmServerWriteTransaction<'a> {
         &mut self,
         au: &mut AuditScope,
         uae: &UnixUserAuthEvent,
-        _ct: Duration,
+        ct: Duration,
     ) -> Result<Option<UnixUserToken>, OperationError> {
-        // TODO #59: Implement soft lock checking for unix creds here!
+        // Unix auth shares the same softlock as the primary credential, so that it
+        // can't be used to sidestep the backoff.
+        if self.is_softlocked(&uae.target, &ct) {
+            audit_log!(au, "Account {:?} is softlocked, denying", uae.target);
+            return Ok(None);
+        }
 
         // Get the entry/target we are working on.
         let account_entry = try_audit!(au, self.qs_read.internal_search_uuid(au, &uae.target));
 
+        let groups = try_audit!(
+            au,
+            Group::try_from_account_entry_ro(au, &account_entry, &mut self.qs_read)
+        );
+        let pwpolicy = PasswordPolicy::from_groups(groups.as_slice());
+
         // Get their account
         let account = try_audit!(
             au,
             UnixUserAccount::try_from_entry_ro(au, account_entry, &mut self.qs_read)
         );
 
+        if !account.is_within_valid_time(&ct) {
+            audit_log!(au, "Account {:?} is not within its valid time", uae.target);
+            return Ok(None);
+        }
+
+        if account.is_password_expired(&pwpolicy, &ct) {
+            audit_log!(au, "Account {:?} unix password has expired", uae.target);
+            return Ok(None);
+        }
+
         // Validate the unix_pw - this checks the account/cred lock states.
-        account.verify_unix_credential(au, uae.cleartext.as_str())
+        let r = account.verify_unix_credential(au, uae.cleartext.as_str());
+        match &r {
+            Ok(Some(_)) => {
+                self.softlocks.remove(&uae.target);
+                if let Some((old, new)) =
+                    account.password_upgrade(uae.cleartext.as_str(), self.crypto_policy)
+                {
+                    self.password_upgrades
+                        .push(PasswordUpgrade::Unix(uae.target, old, new));
+                }
+            }
+            Ok(None) => self.record_softlock_failure(uae.target, &ct),
+            Err(_) => {}
+        }
+        r
+    }
+
+    pub fn unlock_account(
+        &mut self,
+        au: &mut AuditScope,
+        uae: &UnlockAccountEvent,
+    ) -> Result<(), OperationError> {
+        // Anyone who may reset the primary credential could bypass the lock that way
+        // anyway, so that is the permission we require to unlock.
+        let f_valid = filter!(f_eq("uuid", PartialValue::new_uuid(uae.target)))
+            .validate(self.qs_read.get_schema())
+            .map_err(OperationError::SchemaViolation)?;
+        let f_intent_valid = filter_all!(f_eq("uuid", PartialValue::new_uuid(uae.target)))
+            .validate(self.qs_read.get_schema())
+            .map_err(OperationError::SchemaViolation)?;
+        let modlist = ModifyList::new_purge("primary_credential")
+            .validate(self.qs_read.get_schema())
+            .map_err(OperationError::SchemaViolation)?;
+
+        let entries = try_audit!(
+            au,
+            self.qs_read.impersonate_search_valid(
+                au,
+                f_valid.clone(),
+                f_intent_valid.clone(),
+                &uae.event
+            )
+        );
+        if entries.is_empty() {
+            return Err(OperationError::NoMatchingEntries);
+        }
+
+        let me = ModifyEvent::new_impersonate(&uae.event, f_valid, f_intent_valid, modlist);
+        let mut audit_acp = AuditScope::new("access_control_profiles");
+        let acp_res = self.qs_read.get_accesscontrols().modify_allow_operation(
+            &mut audit_acp,
+            &me,
+            &entries,
+            &[],
+        );
+        au.append_scope(audit_acp);
+        if !try_audit!(au, acp_res) {
+            return Err(OperationError::AccessDenied);
+        }
+
+        audit_log!(au, "Unlocking account {:?}", uae.target);
+        self.softlocks.remove(&uae.target);
+        Ok(())
+    }
+
+    /// The webauthn counters that must be persisted with
+    /// `IdmServerProxyWriteTransaction::update_webauthn_counters`.
+    pub fn take_webauthn_counter_updates(&mut self) -> Vec<WebauthnCounterUpdate> {
+        std::mem::replace(&mut self.webauthn_counters, Vec::new())
+    }
+
+    /// The rehashed passwords that must be persisted with
+    /// `IdmServerProxyWriteTransaction::upgrade_passwords`.
+    pub fn take_password_upgrades(&mut self) -> Vec<PasswordUpgrade> {
+        std::mem::replace(&mut self.password_upgrades, Vec::new())
+    }
+
+    /// The used backup codes that must be removed with
+    /// `IdmServerProxyWriteTransaction::remove_backup_codes` before the auth succeeds, as
+    /// that is where a code used by another session is rejected.
+    pub fn take_backup_code_uses(&mut self) -> Vec<BackupCodeUse> {
+        std::mem::replace(&mut self.backup_code_uses, Vec::new())
+    }
+
+    /// The totp time steps that must be persisted with
+    /// `IdmServerProxyWriteTransaction::update_totp_steps` before the auth succeeds, as
+    /// that is where a code accepted by another session is rejected.
+    pub fn take_totp_step_updates(&mut self) -> Vec<TotpStepUpdate> {
+        std::mem::replace(&mut self.totp_steps, Vec::new())
+    }
+
+    /// Sign a bearer token for a successful authentication, that later requests present
+    /// in place of the user auth token. The session of the token must be stored with
+    /// `IdmServerProxyWriteTransaction::record_sessions` before it can be used.
+    pub fn issue_bearer(
+        &mut self,
+        ar: &AuthResult,
+        source: Option<String>,
+        ct: Duration,
+    ) -> Result<Option<String>, OperationError> {
+        match &ar.state {
+            AuthState::Success(uat) => {
+                let claims = BearerClaims::new(self.issuer, ar.sessionid, uat.clone(), &ct);
+                let token = self
+                    .jws_keys
+                    .sign(&claims)
+                    .ok_or(OperationError::CryptographyError)?;
+                let auth_session = self
+                    .sessions
+                    .get(&ar.sessionid)
+                    .ok_or(OperationError::InvalidSessionState)?;
+                if !auth_session.is_anonymous() {
+                    let session = Session {
+                        id: ar.sessionid,
+                        issued: claims.iat,
+                        expiry: claims.exp,
+                        source,
+                        method: auth_session.auth_method().to_string(),
+                    };
+                    self.issued_sessions
+                        .push((auth_session.get_account_uuid(), session));
+                }
+                Ok(Some(token))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The sessions of issued bearer tokens, that must be persisted with
+    /// `IdmServerProxyWriteTransaction::record_sessions`.
+    pub fn take_issued_sessions(&mut self) -> Vec<IssuedSession> {
+        std::mem::replace(&mut self.issued_sessions, Vec::new())
     }
 
     pub fn commit(self) -> Result<(), OperationError> {
         self.sessions.commit();
+        self.softlocks.commit();
         Ok(())
     }
 }
@@ -263,7 +645,22 @@ impl<'a> IdmServerProxyReadTransaction<'a> {
         &mut self,
         au: &mut AuditScope,
         rate: &RadiusAuthTokenEvent,
+        ct: Duration,
     ) -> Result<RadiusAuthToken, OperationError> {
+        // The radius server checks the secret itself, so we never see the failures, but
+        // we can at least stop handing out the secret of a locked account.
+        if self
+            .softlocks
+            .get(&rate.target)
+            .map(|sl| sl.is_locked(&ct))
+            .unwrap_or(false)
+        {
+            audit_log!(au, "Account {:?} is softlocked, denying", rate.target);
+            return Err(OperationError::InvalidAccountState(
+                ACCOUNT_SOFTLOCKED_MSG.to_string(),
+            ));
+        }
+
         // TODO: This needs to be an impersonate search!
         let account_entry = try_audit!(
             au,
@@ -275,6 +672,13 @@ impl<'a> IdmServerProxyReadTransaction<'a> {
             RadiusAccount::try_from_entry_reduced(au, account_entry, &mut self.qs_read)
         );
 
+        if !account.is_within_valid_time(&ct) {
+            audit_log!(au, "Account {:?} is not within its valid time", rate.target);
+            return Err(OperationError::InvalidAccountState(
+                "account is not within its valid time".to_string(),
+            ));
+        }
+
         account.to_radiusauthtoken()
     }
 
@@ -296,6 +700,95 @@ impl<'a> IdmServerProxyReadTransaction<'a> {
         account.to_unixusertoken()
     }
 
+    pub fn get_app_passwords(
+        &mut self,
+        au: &mut AuditScope,
+        aple: &AppPasswordListEvent,
+    ) -> Result<Vec<AppPassword>, OperationError> {
+        let account_entry = try_audit!(
+            au,
+            self.qs_read
+                .impersonate_search_ext_uuid(au, &aple.target, &aple.event)
+        );
+
+        // Only the label and application are ever shown, never the password.
+        Ok(account_entry
+            .get_ava("app_password")
+            .map(|vs| {
+                vs.into_iter()
+                    .filter_map(|v| {
+                        v.to_credential().map(|c| AppPassword {
+                            label: v.to_proto_string_clone(),
+                            application: c.application.clone().unwrap_or_default(),
+                        })
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new))
+    }
+
+    pub fn get_sessions(
+        &mut self,
+        au: &mut AuditScope,
+        sle: &SessionListEvent,
+    ) -> Result<Vec<SessionInfo>, OperationError> {
+        let account_entry = try_audit!(
+            au,
+            self.qs_read
+                .impersonate_search_ext_uuid(au, &sle.target, &sle.event)
+        );
+
+        Ok(account_entry
+            .get_ava("session")
+            .map(|vs| {
+                vs.into_iter()
+                    .filter_map(|v| v.to_session().map(|s| s.to_proto()))
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new))
+    }
+
+    pub fn get_credentials(
+        &mut self,
+        au: &mut AuditScope,
+        cle: &CredentialListEvent,
+    ) -> Result<Vec<CredentialInfo>, OperationError> {
+        let account_entry = try_audit!(
+            au,
+            self.qs_read
+                .impersonate_search_ext_uuid(au, &cle.target, &cle.event)
+        );
+
+        Ok(account_entry
+            .get_ava("primary_credential")
+            .map(|vs| {
+                vs.into_iter()
+                    .filter_map(|v| {
+                        v.to_credential()
+                            .map(|c| c.to_proto(v.to_proto_string_clone().as_str()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new))
+    }
+
+    pub fn get_totp_labels(
+        &mut self,
+        au: &mut AuditScope,
+        tle: &TOTPListEvent,
+    ) -> Result<Vec<String>, OperationError> {
+        let account_entry = try_audit!(
+            au,
+            self.qs_read
+                .impersonate_search_ext_uuid(au, &tle.target, &tle.event)
+        );
+        // Only the labels are ever shown, never the secrets.
+        Ok(account_entry
+            .get_ava_credential_tag("primary_credential", "primary")
+            .map(|c| c.totp.keys().cloned().collect())
+            .unwrap_or_else(Vec::new))
+    }
+
     pub fn get_unixgrouptoken(
         &mut self,
         au: &mut AuditScope,
@@ -327,14 +820,15 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
         au: &mut AuditScope,
         cleartext: &str,
         related_inputs: &[&str],
+        pwpolicy: &PasswordPolicy,
     ) -> Result<(), OperationError> {
-        // password strength and badlisting is always global, rather than per-pw-policy.
-        // pw-policy as check on the account is about requirements for mfa for example.
-        //
+        // The requirements here come from the password policies of the accounts groups, or
+        // the defaults if it has none. The mfa and age parts of the policy are checked
+        // during authentication instead.
 
-        // is the password at least 10 char?
-        if cleartext.len() < PW_MIN_LENGTH {
-            return Err(OperationError::PasswordTooShort(PW_MIN_LENGTH));
+        // is the password long enough?
+        if cleartext.len() < pwpolicy.min_length {
+            return Err(OperationError::PasswordTooShort(pwpolicy.min_length));
         }
 
         // does the password pass zxcvbn?
@@ -344,9 +838,7 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
             zxcvbn::zxcvbn(cleartext, related_inputs).map_err(|_| OperationError::PasswordEmpty)
         );
 
-        // check account pwpolicy (for 3 or 4)? Do we need pw strength beyond this
-        // or should we be enforcing mfa instead
-        if entropy.score() < 3 {
+        if entropy.score() < pwpolicy.min_score {
             // The password is too week as per:
             // https://docs.rs/zxcvbn/2.0.0/zxcvbn/struct.Entropy.html
             let feedback: zxcvbn::feedback::Feedback = entropy
@@ -355,7 +847,11 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
                 .ok_or(OperationError::InvalidState)
                 .map(|v| v.clone())
                 .map_err(|e| {
-                    audit_log!(au, "zxcvbn returned no feedback when score < 3");
+                    audit_log!(
+                        au,
+                        "zxcvbn returned no feedback when score < {}",
+                        pwpolicy.min_score
+                    );
                     e
                 })?;
 
@@ -365,6 +861,10 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
             return Err(OperationError::PasswordTooWeak);
         }
 
+        if !pwpolicy.badlist {
+            return Ok(());
+        }
+
         // check a password badlist to eliminate more content
         // we check the password as "lower case" to help eliminate possibilities
         let lc_password = PartialValue::new_iutf8s(cleartext);
@@ -421,15 +921,39 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
             account.spn.as_str(),
         ];
 
+        let pwpolicy = account.password_policy();
+
         try_audit!(
             au,
-            self.check_password_quality(au, pce.cleartext.as_str(), related_inputs.as_slice())
+            self.check_password_quality(
+                au,
+                pce.cleartext.as_str(),
+                related_inputs.as_slice(),
+                &pwpolicy
+            )
+        );
+
+        let label = try_audit!(
+            au,
+            account.credential_label(pce.cred_id.as_ref().map(|s| s.as_str()))
         );
 
+        if account.is_password_reused(pce.cleartext.as_str()) {
+            audit_log!(au, "Password was used previously, rejecting");
+            return Err(OperationError::PasswordReused);
+        }
+
+        let ct = self.qs_write.get_curtime();
+
         // it returns a modify
         let modlist = try_audit!(
             au,
-            account.gen_password_mod(pce.cleartext.as_str(), &pce.appid)
+            account.gen_password_mod(
+                label.as_str(),
+                pce.cleartext.as_str(),
+                &ct,
+                self.crypto_policy
+            )
         );
         audit_log!(au, "processing change {:?}", modlist);
         // given the new credential generate a modify
@@ -457,6 +981,12 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
     ) -> Result<(), OperationError> {
         // Get the account
         let account_entry = try_audit!(au, self.qs_write.internal_search_uuid(au, &pce.target));
+        // The unix groups are only the posix ones, but a password policy can be on any group.
+        let groups = try_audit!(
+            au,
+            Group::try_from_account_entry_rw(au, &account_entry, &mut self.qs_write)
+        );
+        let pwpolicy = PasswordPolicy::from_groups(groups.as_slice());
         // Assert the account is unix and valid.
         let account = try_audit!(
             au,
@@ -478,11 +1008,26 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
 
         try_audit!(
             au,
-            self.check_password_quality(au, pce.cleartext.as_str(), related_inputs.as_slice())
+            self.check_password_quality(
+                au,
+                pce.cleartext.as_str(),
+                related_inputs.as_slice(),
+                &pwpolicy
+            )
         );
 
+        if account.is_password_reused(pce.cleartext.as_str()) {
+            audit_log!(au, "Unix password was used previously, rejecting");
+            return Err(OperationError::PasswordReused);
+        }
+
+        let ct = self.qs_write.get_curtime();
+
         // it returns a modify
-        let modlist = try_audit!(au, account.gen_password_mod(pce.cleartext.as_str()));
+        let modlist = try_audit!(
+            au,
+            account.gen_password_mod(pce.cleartext.as_str(), &ct, self.crypto_policy)
+        );
         audit_log!(au, "processing change {:?}", modlist);
         // given the new credential generate a modify
         // We use impersonate here to get the event from ae
@@ -531,8 +1076,17 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
         // check a password badlist - even if generated, we still don't want to
         // reuse something that has been disclosed.
 
+        let ct = self.qs_write.get_curtime();
+        let label = try_audit!(
+            au,
+            account.credential_label(gpe.cred_id.as_ref().map(|s| s.as_str()))
+        );
+
         // it returns a modify
-        let modlist = try_audit!(au, account.gen_password_mod(cleartext.as_str(), &gpe.appid));
+        let modlist = try_audit!(
+            au,
+            account.gen_password_mod(label.as_str(), cleartext.as_str(), &ct, self.crypto_policy)
+        );
         audit_log!(au, "processing change {:?}", modlist);
         // given the new credential generate a modify
         // We use impersonate here to get the event from ae
@@ -553,6 +1107,70 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
         Ok(cleartext)
     }
 
+    pub fn generate_app_password(
+        &mut self,
+        au: &mut AuditScope,
+        apce: &AppPasswordCreateEvent,
+    ) -> Result<String, OperationError> {
+        if apce.label.is_empty() || apce.application.is_empty() {
+            audit_log!(au, "Application password label and application must be set");
+            return Err(OperationError::EmptyRequest);
+        }
+        let account = self.target_to_account(au, &apce.target)?;
+
+        // Like the generated primary password, this is random and so bypasses the policy
+        // checks. It's only ever shown once, to be copied into the application.
+        let cleartext = password_from_random();
+
+        let modlist = try_audit!(
+            au,
+            account.gen_app_password_mod(
+                apce.label.as_str(),
+                apce.application.as_str(),
+                cleartext.as_str(),
+                self.crypto_policy,
+            )
+        );
+        audit_log!(au, "processing change {:?}", modlist);
+        try_audit!(
+            au,
+            self.qs_write.impersonate_modify(
+                au,
+                // Filter as executed
+                filter!(f_eq("uuid", PartialValue::new_uuidr(&apce.target))),
+                // Filter as intended (acp)
+                filter_all!(f_eq("uuid", PartialValue::new_uuidr(&apce.target))),
+                modlist,
+                // Provide the event to impersonate
+                &apce.event,
+            )
+        );
+
+        Ok(cleartext)
+    }
+
+    pub fn remove_app_password(
+        &mut self,
+        au: &mut AuditScope,
+        apre: &AppPasswordRemoveEvent,
+    ) -> Result<(), OperationError> {
+        let account = self.target_to_account(au, &apre.target)?;
+        let modlist = try_audit!(au, account.gen_app_password_remove_mod(apre.label.as_str()));
+        try_audit!(
+            au,
+            self.qs_write.impersonate_modify(
+                au,
+                // Filter as executed
+                filter!(f_eq("uuid", PartialValue::new_uuidr(&apre.target))),
+                // Filter as intended (acp)
+                filter_all!(f_eq("uuid", PartialValue::new_uuidr(&apre.target))),
+                modlist,
+                &apre.event,
+            )
+        );
+        Ok(())
+    }
+
     pub fn regenerate_radius_secret(
         &mut self,
         au: &mut AuditScope,
@@ -586,6 +1204,87 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
         Ok(cleartext)
     }
 
+    pub fn create_credential_reset_token(
+        &mut self,
+        au: &mut AuditScope,
+        crte: &CredentialResetTokenCreateEvent,
+    ) -> Result<String, OperationError> {
+        let account = self.target_to_account(au, &crte.target)?;
+
+        // Like the radius secret, this is likely to be read out or copied by a human.
+        let token = readable_password_from_random();
+        let ct = self.qs_write.get_curtime();
+
+        let modlist = try_audit!(
+            au,
+            account.gen_credential_reset_token_mod(token.as_str(), &ct, self.crypto_policy)
+        );
+        audit_log!(au, "processing change {:?}", modlist);
+        try_audit!(
+            au,
+            self.qs_write.impersonate_modify(
+                au,
+                // Filter as executed
+                filter!(f_eq("uuid", PartialValue::new_uuidr(&crte.target))),
+                // Filter as intended (acp)
+                filter_all!(f_eq("uuid", PartialValue::new_uuidr(&crte.target))),
+                modlist,
+                // Provide the event to impersonate
+                &crte.event,
+            )
+        );
+
+        Ok(token)
+    }
+
+    /// Check a credential reset token for the target account. If it's valid, this returns a
+    /// token for the account, that the credential change can be made as. The token is not
+    /// consumed here, as it is only used up once the change is complete.
+    pub fn verify_credential_reset_token(
+        &mut self,
+        au: &mut AuditScope,
+        target: &Uuid,
+        token: &str,
+        ct: &Duration,
+    ) -> Result<UserAuthToken, OperationError> {
+        let account = self.target_to_account(au, target)?;
+
+        if !account.is_within_valid_time(ct) {
+            audit_log!(au, "Account is not within its valid time, denying reset");
+            return Err(OperationError::AccessDenied);
+        }
+
+        if !account.is_credential_reset_token_valid(token, ct) {
+            audit_log!(au, "Credential reset token is invalid or expired");
+            return Err(OperationError::AccessDenied);
+        }
+
+        account
+            .to_userauthtoken(vec![], None, vec![])
+            .ok_or(OperationError::InvalidState)
+    }
+
+    pub fn remove_credential_reset_token(
+        &mut self,
+        au: &mut AuditScope,
+        target: &Uuid,
+    ) -> Result<(), OperationError> {
+        let account = self.target_to_account(au, target)?;
+        if account.credential_reset_token.is_none() {
+            return Ok(());
+        }
+        let modlist = account.gen_credential_reset_token_remove_mod();
+        try_audit!(
+            au,
+            self.qs_write.internal_modify(
+                au,
+                filter_all!(f_eq("uuid", PartialValue::new_uuidr(target))),
+                modlist,
+            )
+        );
+        Ok(())
+    }
+
     pub fn generate_account_totp(
         &mut self,
         au: &mut AuditScope,
@@ -597,9 +1296,19 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
 
         let origin = (&gte.event.origin).into();
         let label = gte.label.clone();
+        let cred_label = try_audit!(
+            au,
+            account.credential_label(gte.cred_id.as_ref().map(|s| s.as_str()))
+        );
         let (session, next) = try_audit!(
             au,
-            MfaRegSession::new(origin, account, MfaReqInit::TOTP(label))
+            MfaRegSession::new(
+                origin,
+                account,
+                cred_label,
+                MfaReqInit::TOTP(label),
+                self.webauthn
+            )
         );
 
         let next = next.to_proto(&sessionid);
@@ -632,21 +1341,35 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
             );
             try_audit!(
                 au,
-                session.step(&origin, &vte.target, MfaReqStep::TOTPVerify(chal), &ct)
+                session.step(
+                    &origin,
+                    &vte.target,
+                    MfaReqStep::TOTPVerify(chal),
+                    &ct,
+                    self.webauthn
+                )
             )
         };
 
         match (&next, opt_cred) {
-            (MfaRegNext::Success, Some(MfaRegCred::TOTP(token))) => {
+            (MfaRegNext::BackupCodes(_), Some(MfaRegCred::TOTP(token, backup_codes))) => {
                 // Purge the session.
                 let session = self
                     .mfareg_sessions
                     .remove(&sessionid)
                     .expect("Session within transaction vanished!");
                 // reg the token
-                let modlist = try_audit!(au, session.account.gen_totp_mod(token));
-                // Perform the mod
-                try_audit!(
+                let modlist = try_audit!(
+                    au,
+                    session.account.gen_totp_mod(
+                        session.cred_label.as_str(),
+                        token,
+                        backup_codes.as_slice(),
+                        self.crypto_policy
+                    )
+                );
+                // Perform the mod
+                try_audit!(
                     au,
                     self.qs_write.impersonate_modify(
                         au,
@@ -666,403 +1389,1871 @@ impl<'a> IdmServerProxyWriteTransaction<'a> {
         Ok(next)
     }
 
-    pub fn commit(self, au: &mut AuditScope) -> Result<(), OperationError> {
-        self.mfareg_sessions.commit();
-        self.qs_write.commit(au)
+    pub fn remove_account_totp(
+        &mut self,
+        au: &mut AuditScope,
+        tre: &TOTPRemoveEvent,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        let account = self.target_to_account(au, &tre.target)?;
+        let cred_label = try_audit!(
+            au,
+            account.credential_label(tre.cred_id.as_ref().map(|s| s.as_str()))
+        );
+        let modlist = try_audit!(
+            au,
+            account.gen_totp_remove_mod(cred_label.as_str(), tre.label.as_str())
+        );
+        try_audit!(
+            au,
+            self.qs_write.impersonate_modify(
+                au,
+                // Filter as executed
+                filter!(f_eq("uuid", PartialValue::new_uuidr(&tre.target))),
+                // Filter as intended (acp)
+                filter_all!(f_eq("uuid", PartialValue::new_uuidr(&tre.target))),
+                modlist,
+                &tre.event,
+            )
+        );
+        Ok(SetCredentialResponse::Success)
     }
-}
 
-// Need tests of the sessions and the auth ...
+    pub fn generate_backup_codes(
+        &mut self,
+        au: &mut AuditScope,
+        gbe: &GenerateBackupCodesEvent,
+    ) -> Result<Vec<String>, OperationError> {
+        let account = self.target_to_account(au, &gbe.target)?;
+        let backup_codes = backup_codes_from_random();
+        let modlist = try_audit!(
+            au,
+            account.gen_backup_codes_mod("primary", backup_codes.as_slice(), self.crypto_policy)
+        );
+        try_audit!(
+            au,
+            self.qs_write.impersonate_modify(
+                au,
+                // Filter as executed
+                filter!(f_eq("uuid", PartialValue::new_uuidr(&gbe.target))),
+                // Filter as intended (acp)
+                filter_all!(f_eq("uuid", PartialValue::new_uuidr(&gbe.target))),
+                modlist,
+                &gbe.event,
+            )
+        );
+        Ok(backup_codes)
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::constants::{
-        AUTH_SESSION_TIMEOUT, MFAREG_SESSION_TIMEOUT, UUID_ADMIN, UUID_ANONYMOUS,
-    };
-    use crate::credential::totp::TOTP;
-    use crate::credential::Credential;
-    use crate::entry::{Entry, EntryInit, EntryNew};
-    use crate::event::{AuthEvent, AuthResult, CreateEvent, ModifyEvent};
-    use crate::idm::event::{
-        GenerateTOTPEvent, PasswordChangeEvent, RadiusAuthTokenEvent, RegenerateRadiusSecretEvent,
-        UnixGroupTokenEvent, UnixPasswordChangeEvent, UnixUserAuthEvent, UnixUserTokenEvent,
-        VerifyTOTPEvent,
-    };
-    use crate::modify::{Modify, ModifyList};
-    use crate::value::{PartialValue, Value};
-    use kanidm_proto::v1::OperationError;
-    use kanidm_proto::v1::SetCredentialResponse;
-    use kanidm_proto::v1::{AuthAllowed, AuthState};
+    pub fn init_account_webauthn(
+        &mut self,
+        au: &mut AuditScope,
+        wre: &WebauthnInitRegisterEvent,
+        ct: Duration,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        let account = self.target_to_account(au, &wre.target)?;
+        let sessionid = uuid_from_duration(ct, self.sid);
 
-    use crate::audit::AuditScope;
-    use crate::idm::server::IdmServer;
-    use crate::server::QueryServer;
-    use crate::utils::duration_from_epoch_now;
-    use std::time::Duration;
-    use uuid::Uuid;
+        let origin = (&wre.event.origin).into();
+        let label = wre.label.clone();
+        let cred_label = try_audit!(
+            au,
+            account.credential_label(wre.cred_id.as_ref().map(|s| s.as_str()))
+        );
+        let (session, next) = try_audit!(
+            au,
+            MfaRegSession::new(
+                origin,
+                account,
+                cred_label,
+                MfaReqInit::Webauthn(label),
+                self.webauthn
+            )
+        );
 
-    const TEST_PASSWORD: &'static str = "ntaoeuntnaoeuhraohuercahu😍";
-    const TEST_PASSWORD_INC: &'static str = "ntaoentu nkrcgaeunhibwmwmqj;k wqjbkx ";
-    const TEST_CURRENT_TIME: u64 = 6000;
-    const TEST_CURRENT_EXPIRE: u64 = TEST_CURRENT_TIME + AUTH_SESSION_TIMEOUT + 1;
+        let next = next.to_proto(&sessionid);
 
-    #[test]
-    fn test_idm_anonymous_auth() {
-        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
-            let sid = {
-                // Start and test anonymous auth.
-                let mut idms_write = idms.write();
-                // Send the initial auth event for initialising the session
-                let anon_init = AuthEvent::anonymous_init();
-                // Expect success
-                let r1 = idms_write.auth(au, &anon_init, Duration::from_secs(TEST_CURRENT_TIME));
-                /* Some weird lifetime shit happens here ... */
-                // audit_log!(au, "r1 ==> {:?}", r1);
+        // Add session to tree
+        self.mfareg_sessions.insert(sessionid, session);
+        audit_log!(au, "Start mfa reg session -> {:?}", sessionid);
+        Ok(next)
+    }
 
-                let sid = match r1 {
-                    Ok(ar) => {
-                        let AuthResult { sessionid, state } = ar;
-                        match state {
-                            AuthState::Continue(mut conts) => {
-                                // Should only be one auth mech
-                                assert!(conts.len() == 1);
-                                // And it should be anonymous
-                                let m = conts.pop().expect("Should not fail");
-                                assert!(m == AuthAllowed::Anonymous);
-                            }
-                            _ => {
-                                error!(
-                                    "A critical error has occured! We have a non-continue result!"
-                                );
-                                panic!();
-                            }
-                        };
-                        // Now pass back the sessionid, we are good to continue.
-                        sessionid
-                    }
-                    Err(e) => {
-                        // Should not occur!
-                        error!("A critical error has occured! {:?}", e);
-                        panic!();
-                    }
-                };
+    pub fn register_account_webauthn(
+        &mut self,
+        au: &mut AuditScope,
+        wre: &WebauthnDoRegisterEvent,
+        ct: Duration,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        let sessionid = wre.session;
+        let origin = (&wre.event.origin).into();
 
-                println!("sessionid is ==> {:?}", sid);
+        audit_log!(au, "Attempting to find mfareg_session -> {:?}", sessionid);
 
-                idms_write.commit().expect("Must not fail");
+        let (next, opt_cred) = {
+            // bound the life time of the session get_mut
+            let session = try_audit!(
+                au,
+                self.mfareg_sessions
+                    .get_mut(&sessionid)
+                    .ok_or(OperationError::InvalidRequestState)
+            );
+            try_audit!(
+                au,
+                session.step(
+                    &origin,
+                    &wre.target,
+                    MfaReqStep::WebauthnRegister(&wre.reg),
+                    &ct,
+                    self.webauthn
+                )
+            )
+        };
 
-                sid
-            };
-            {
-                let mut idms_write = idms.write();
-                // Now send the anonymous request, given the session id.
-                let anon_step = AuthEvent::cred_step_anonymous(sid);
+        match (&next, opt_cred) {
+            (MfaRegNext::Success, Some(MfaRegCred::Webauthn(label, cred))) => {
+                // Purge the session.
+                let session = self
+                    .mfareg_sessions
+                    .remove(&sessionid)
+                    .expect("Session within transaction vanished!");
+                // reg the token
+                let modlist = try_audit!(
+                    au,
+                    session
+                        .account
+                        .gen_webauthn_mod(session.cred_label.as_str(), label, cred)
+                );
+                // Perform the mod
+                try_audit!(
+                    au,
+                    self.qs_write.impersonate_modify(
+                        au,
+                        // Filter as executed
+                        filter!(f_eq("uuid", PartialValue::new_uuidr(&session.account.uuid))),
+                        // Filter as intended (acp)
+                        filter_all!(f_eq("uuid", PartialValue::new_uuidr(&session.account.uuid))),
+                        modlist,
+                        &wre.event,
+                    )
+                );
+            }
+            _ => {}
+        };
+
+        let next = next.to_proto(&sessionid);
+        Ok(next)
+    }
+
+    pub fn remove_account_webauthn(
+        &mut self,
+        au: &mut AuditScope,
+        wre: &WebauthnRemoveEvent,
+    ) -> Result<SetCredentialResponse, OperationError> {
+        let account = self.target_to_account(au, &wre.target)?;
+        let cred_label = try_audit!(
+            au,
+            account.credential_label(wre.cred_id.as_ref().map(|s| s.as_str()))
+        );
+        let modlist = try_audit!(
+            au,
+            account.gen_webauthn_remove_mod(cred_label.as_str(), wre.label.as_str())
+        );
+        try_audit!(
+            au,
+            self.qs_write.impersonate_modify(
+                au,
+                // Filter as executed
+                filter!(f_eq("uuid", PartialValue::new_uuidr(&wre.target))),
+                // Filter as intended (acp)
+                filter_all!(f_eq("uuid", PartialValue::new_uuidr(&wre.target))),
+                modlist,
+                &wre.event,
+            )
+        );
+        Ok(SetCredentialResponse::Success)
+    }
+
+    pub fn update_webauthn_counters(
+        &mut self,
+        au: &mut AuditScope,
+        updates: &[WebauthnCounterUpdate],
+    ) -> Result<(), OperationError> {
+        for (target, cid, counter) in updates {
+            let account = self.target_to_account(au, target)?;
+            // If the credential changed since the auth, there is nothing to update.
+            if let Some(modlist) = account.gen_webauthn_counter_mod(cid, *counter) {
+                // This is the server maintaining the credential, not the user changing it.
+                try_audit!(
+                    au,
+                    self.qs_write.internal_modify(
+                        au,
+                        filter_all!(f_eq("uuid", PartialValue::new_uuidr(target))),
+                        modlist,
+                    )
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record_sessions(
+        &mut self,
+        au: &mut AuditScope,
+        sessions: &[IssuedSession],
+        ct: Duration,
+    ) -> Result<(), OperationError> {
+        for (target, session) in sessions {
+            let account = self.target_to_account(au, target)?;
+            let modlist = account.gen_session_mod(session.clone(), &ct);
+            audit_log!(au, "Recording session {:?} of {:?}", session.id, target);
+            try_audit!(
+                au,
+                self.qs_write.internal_modify(
+                    au,
+                    filter_all!(f_eq("uuid", PartialValue::new_uuidr(target))),
+                    modlist,
+                )
+            );
+        }
+        Ok(())
+    }
+
+    pub fn revoke_sessions(
+        &mut self,
+        au: &mut AuditScope,
+        sre: &SessionRevokeEvent,
+    ) -> Result<(), OperationError> {
+        let account = self.target_to_account(au, &sre.target)?;
+        let modlist = try_audit!(au, account.gen_session_revoke_mod(sre.id));
+        try_audit!(
+            au,
+            self.qs_write.impersonate_modify(
+                au,
+                // Filter as executed
+                filter!(f_eq("uuid", PartialValue::new_uuidr(&sre.target))),
+                // Filter as intended (acp)
+                filter_all!(f_eq("uuid", PartialValue::new_uuidr(&sre.target))),
+                modlist,
+                &sre.event,
+            )
+        );
+        Ok(())
+    }
+
+    pub fn lock_credential(
+        &mut self,
+        au: &mut AuditScope,
+        cle: &CredentialLockEvent,
+    ) -> Result<(), OperationError> {
+        let account = self.target_to_account(au, &cle.target)?;
+        let modlist = try_audit!(
+            au,
+            account.gen_credential_lock_mod(cle.cred_id.as_str(), cle.locked)
+        );
+        try_audit!(
+            au,
+            self.qs_write.impersonate_modify(
+                au,
+                // Filter as executed
+                filter!(f_eq("uuid", PartialValue::new_uuidr(&cle.target))),
+                // Filter as intended (acp)
+                filter_all!(f_eq("uuid", PartialValue::new_uuidr(&cle.target))),
+                modlist,
+                &cle.event,
+            )
+        );
+        Ok(())
+    }
+
+    pub fn remove_credential(
+        &mut self,
+        au: &mut AuditScope,
+        cre: &CredentialRemoveEvent,
+    ) -> Result<(), OperationError> {
+        let account = self.target_to_account(au, &cre.target)?;
+        let modlist = try_audit!(au, account.gen_credential_remove_mod(cre.cred_id.as_str()));
+        try_audit!(
+            au,
+            self.qs_write.impersonate_modify(
+                au,
+                // Filter as executed
+                filter!(f_eq("uuid", PartialValue::new_uuidr(&cre.target))),
+                // Filter as intended (acp)
+                filter_all!(f_eq("uuid", PartialValue::new_uuidr(&cre.target))),
+                modlist,
+                &cre.event,
+            )
+        );
+        Ok(())
+    }
+
+    pub fn update_totp_steps(
+        &mut self,
+        au: &mut AuditScope,
+        updates: &[TotpStepUpdate],
+    ) -> Result<(), OperationError> {
+        for (target, label, step) in updates {
+            // The auth session checked the step against the device as it was when the
+            // session began, so another session may have accepted the same code since.
+            // Checking the step again here, in the write that records it, is what stops
+            // the replay. If the step is not after the recorded one, or the device was
+            // removed, the auth fails.
+            let account = self.target_to_account(au, target)?;
+            let modlist = account
+                .gen_totp_step_mod(label.as_str(), *step)
+                .ok_or_else(|| {
+                    audit_log!(au, "Totp step of {:?} was already used", target);
+                    OperationError::InvalidAuthState("totp code already used".to_string())
+                })?;
+            try_audit!(
+                au,
+                self.qs_write.internal_modify(
+                    au,
+                    filter_all!(f_eq("uuid", PartialValue::new_uuidr(target))),
+                    modlist,
+                )
+            );
+        }
+        Ok(())
+    }
+
+    pub fn remove_backup_codes(
+        &mut self,
+        au: &mut AuditScope,
+        uses: &[BackupCodeUse],
+    ) -> Result<(), OperationError> {
+        for (target, code) in uses {
+            // The auth session checked the code against the credential as it was when the
+            // session began, so another session may have used it since. Checking it again
+            // here, in the write that removes it, is what makes a code single use. If it
+            // is gone, because it was used or the codes were regenerated, the auth fails.
+            let account = self.target_to_account(au, target)?;
+            let modlist = account.gen_backup_code_remove_mod(code).ok_or_else(|| {
+                audit_log!(au, "Backup code of {:?} was already used", target);
+                OperationError::InvalidAuthState("backup code already used".to_string())
+            })?;
+            audit_log!(au, "Removing used backup code of {:?}", target);
+            try_audit!(
+                au,
+                self.qs_write.internal_modify(
+                    au,
+                    filter_all!(f_eq("uuid", PartialValue::new_uuidr(target))),
+                    modlist,
+                )
+            );
+        }
+        Ok(())
+    }
+
+    pub fn upgrade_passwords(
+        &mut self,
+        au: &mut AuditScope,
+        upgrades: &[PasswordUpgrade],
+    ) -> Result<(), OperationError> {
+        for upgrade in upgrades {
+            let (target, modlist) = match upgrade {
+                PasswordUpgrade::Primary(target, old, new) => {
+                    let account = self.target_to_account(au, target)?;
+                    (target, account.gen_password_upgrade_mod(old, new.clone()))
+                }
+                PasswordUpgrade::Unix(target, old, new) => {
+                    let account_entry =
+                        try_audit!(au, self.qs_write.internal_search_uuid(au, target));
+                    let account = try_audit!(
+                        au,
+                        UnixUserAccount::try_from_entry_rw(au, account_entry, &mut self.qs_write)
+                    );
+                    (target, account.gen_password_upgrade_mod(old, new.clone()))
+                }
+            };
+            // If the password changed since the auth, the new hash is of the wrong password.
+            if let Some(modlist) = modlist {
+                audit_log!(au, "Upgrading password hash of {:?}", target);
+                try_audit!(
+                    au,
+                    self.qs_write.internal_modify(
+                        au,
+                        filter_all!(f_eq("uuid", PartialValue::new_uuidr(target))),
+                        modlist,
+                    )
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Load the bearer token signing keys from the domain info entry. A new key is added
+    /// when the newest is due for rotation, and keys that can't have signed a token that
+    /// is still valid are removed.
+    pub fn rotate_jws_keys(
+        &mut self,
+        au: &mut AuditScope,
+        ct: Duration,
+    ) -> Result<(), OperationError> {
+        let udi = Uuid::parse_str(UUID_DOMAIN_INFO).map_err(|_| OperationError::InvalidUuid)?;
+        let domain_entry = try_audit!(au, self.qs_write.internal_search_uuid(au, &udi));
+        let keys = domain_entry
+            .get_ava("jws_signing_key")
+            .map(|vs| {
+                vs.into_iter()
+                    .filter_map(|v| v.to_jws_key().cloned())
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new);
+
+        let mut keyset = JwsKeySet::new(keys);
+        if keyset.rotate(&ct) {
+            audit_log!(au, "Rotating bearer token signing keys");
+            let mut mods = vec![Modify::Purged("jws_signing_key".to_string())];
+            mods.extend(keyset.keys().iter().map(|k| {
+                Modify::Present("jws_signing_key".to_string(), Value::new_jws_key(k.clone()))
+            }));
+            try_audit!(
+                au,
+                self.qs_write.internal_modify(
+                    au,
+                    filter_all!(f_eq("uuid", PartialValue::new_uuid(udi))),
+                    ModifyList::new_list(mods),
+                )
+            );
+        }
+        *self.jws_keys = keyset;
+        Ok(())
+    }
+
+    pub fn commit(self, au: &mut AuditScope) -> Result<(), OperationError> {
+        self.mfareg_sessions.commit();
+        self.qs_write.commit(au)?;
+        // Only once the keys are stored can tokens be signed with them.
+        self.jws_keys.commit();
+        Ok(())
+    }
+}
+
+// Need tests of the sessions and the auth ...
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{
+        AUTH_SESSION_TIMEOUT, BACKUP_CODE_COUNT, BEARER_TOKEN_VALIDITY, CLAIM_INTERACTIVE,
+        CLAIM_PRIVILEGED, CREDENTIAL_RESET_TOKEN_VALIDITY, MFAREG_SESSION_TIMEOUT,
+        PRIVILEGED_CLAIM_LIFETIME, SOFTLOCK_BASE_DELAY, SOFTLOCK_FAILURE_THRESHOLD, UUID_ADMIN,
+        UUID_ANONYMOUS, UUID_DOMAIN_INFO,
+    };
+    use crate::credential::totp::TOTP;
+    use crate::credential::{Credential, CryptoPolicy, Password};
+    use crate::entry::{Entry, EntryInit, EntryNew};
+    use crate::event::{AuthEvent, AuthResult, CreateEvent, ModifyEvent};
+    use crate::idm::event::{
+        AppPasswordCreateEvent, AppPasswordListEvent, AppPasswordRemoveEvent, CredentialListEvent,
+        CredentialLockEvent, CredentialRemoveEvent, CredentialResetTokenCreateEvent,
+        GenerateBackupCodesEvent, GenerateTOTPEvent, PasswordChangeEvent, RadiusAuthTokenEvent,
+        RegenerateRadiusSecretEvent, SessionListEvent, SessionRevokeEvent, TOTPListEvent,
+        TOTPRemoveEvent, UnixGroupTokenEvent, UnixPasswordChangeEvent, UnixUserAuthEvent,
+        UnixUserTokenEvent, UnlockAccountEvent, VerifyTOTPEvent, WebauthnDoRegisterEvent,
+        WebauthnInitRegisterEvent, WebauthnRemoveEvent,
+    };
+    use crate::idm::softlock::ACCOUNT_SOFTLOCKED_MSG;
+    use crate::modify::{Modify, ModifyList};
+    use crate::value::{PartialValue, Value};
+    use kanidm_proto::v1::OperationError;
+    use kanidm_proto::v1::SetCredentialResponse;
+    use kanidm_proto::v1::{AuthAllowed, AuthState};
+
+    use crate::audit::AuditScope;
+    use crate::idm::server::IdmServer;
+    use crate::server::{QueryServer, QueryServerTransaction};
+    use crate::utils::duration_from_epoch_now;
+    use std::convert::TryFrom;
+    use std::time::Duration;
+    use uuid::Uuid;
+    use webauthn_authenticator_rs::{softtok::U2FSoft, WebauthnAuthenticator};
+
+    const TEST_PASSWORD: &'static str = "ntaoeuntnaoeuhraohuercahu😍";
+    const TEST_PASSWORD_INC: &'static str = "ntaoentu nkrcgaeunhibwmwmqj;k wqjbkx ";
+    const TEST_PASSWORD_HISTORY: &'static str = "eiyoh4ooquaiThaiphee8pah3aiN";
+    const TEST_CURRENT_TIME: u64 = 6000;
+    const TEST_CURRENT_EXPIRE: u64 = TEST_CURRENT_TIME + AUTH_SESSION_TIMEOUT + 1;
+
+    #[test]
+    fn test_idm_anonymous_auth() {
+        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            let sid = {
+                // Start and test anonymous auth.
+                let mut idms_write = idms.write();
+                // Send the initial auth event for initialising the session
+                let anon_init = AuthEvent::anonymous_init();
+                // Expect success
+                let r1 = idms_write.auth(au, &anon_init, Duration::from_secs(TEST_CURRENT_TIME));
+                /* Some weird lifetime shit happens here ... */
+                // audit_log!(au, "r1 ==> {:?}", r1);
+
+                let sid = match r1 {
+                    Ok(ar) => {
+                        let AuthResult { sessionid, state } = ar;
+                        match state {
+                            AuthState::Continue(mut conts) => {
+                                // Should only be one auth mech
+                                assert!(conts.len() == 1);
+                                // And it should be anonymous
+                                let m = conts.pop().expect("Should not fail");
+                                assert!(m == AuthAllowed::Anonymous);
+                            }
+                            _ => {
+                                error!(
+                                    "A critical error has occured! We have a non-continue result!"
+                                );
+                                panic!();
+                            }
+                        };
+                        // Now pass back the sessionid, we are good to continue.
+                        sessionid
+                    }
+                    Err(e) => {
+                        // Should not occur!
+                        error!("A critical error has occured! {:?}", e);
+                        panic!();
+                    }
+                };
+
+                println!("sessionid is ==> {:?}", sid);
+
+                idms_write.commit().expect("Must not fail");
+
+                sid
+            };
+            {
+                let mut idms_write = idms.write();
+                // Now send the anonymous request, given the session id.
+                let anon_step = AuthEvent::cred_step_anonymous(sid);
+
+                // Expect success
+                let r2 = idms_write.auth(au, &anon_step, Duration::from_secs(TEST_CURRENT_TIME));
+                println!("r2 ==> {:?}", r2);
+
+                match r2 {
+                    Ok(ar) => {
+                        let AuthResult {
+                            sessionid: _,
+                            state,
+                        } = ar;
+                        match state {
+                            AuthState::Success(_uat) => {
+                                // Check the uat.
+                            }
+                            _ => {
+                                error!(
+                                    "A critical error has occured! We have a non-succcess result!"
+                                );
+                                panic!();
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("A critical error has occured! {:?}", e);
+                        // Should not occur!
+                        panic!();
+                    }
+                };
+
+                idms_write.commit().expect("Must not fail");
+            }
+        });
+    }
+
+    // Test sending anonymous but with no session init.
+    #[test]
+    fn test_idm_anonymous_auth_invalid_states() {
+        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            {
+                let mut idms_write = idms.write();
+                let sid = Uuid::new_v4();
+                let anon_step = AuthEvent::cred_step_anonymous(sid);
+
+                // Expect failure
+                let r2 = idms_write.auth(au, &anon_step, Duration::from_secs(TEST_CURRENT_TIME));
+                println!("r2 ==> {:?}", r2);
+
+                match r2 {
+                    Ok(_) => {
+                        error!("Auth state machine not correctly enforced!");
+                        panic!();
+                    }
+                    Err(e) => match e {
+                        OperationError::InvalidSessionState => {}
+                        _ => panic!(),
+                    },
+                };
+            }
+        })
+    }
+
+    fn init_admin_w_password(
+        au: &mut AuditScope,
+        qs: &QueryServer,
+        pw: &str,
+    ) -> Result<(), OperationError> {
+        let cred = Credential::new_password_only(&CryptoPolicy::minimum(), pw);
+        let v_cred = Value::new_credential("primary", cred);
+        let mut qs_write = qs.write(duration_from_epoch_now());
+
+        // now modify and provide a primary credential.
+        let me_inv_m = unsafe {
+            ModifyEvent::new_internal_invalid(
+                filter!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                ModifyList::new_list(vec![Modify::Present(
+                    "primary_credential".to_string(),
+                    v_cred,
+                )]),
+            )
+        };
+        // go!
+        assert!(qs_write.modify(au, &me_inv_m).is_ok());
+
+        qs_write.commit(au)
+    }
+
+    // Authenticate as admin and store the session, as the auth actor would. Returns the
+    // bearer token and its session id.
+    fn admin_bearer_session(idms: &IdmServer, au: &mut AuditScope, ct: Duration) -> (String, Uuid) {
+        let sid = init_admin_authsession_sid(idms, au);
+        let mut idms_write = idms.write();
+        let pw_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD);
+        let ar = idms_write
+            .auth(au, &pw_step, ct.clone())
+            .expect("Failed to auth");
+        let token = idms_write
+            .issue_bearer(&ar, Some("127.0.0.1".to_string()), ct.clone())
+            .expect("Failed to issue")
+            .expect("No bearer token");
+        let sessions = idms_write.take_issued_sessions();
+        assert!(sessions.len() == 1);
+        idms_write.commit().expect("Must not fail");
+
+        let mut idms_prox_write = idms.proxy_write(ct.clone());
+        assert!(idms_prox_write.record_sessions(au, &sessions, ct).is_ok());
+        assert!(idms_prox_write.commit(au).is_ok());
+        (token, sid)
+    }
+
+    fn init_admin_authsession_sid(idms: &IdmServer, au: &mut AuditScope) -> Uuid {
+        let mut idms_write = idms.write();
+        let admin_init = AuthEvent::named_init("admin");
+
+        let r1 = idms_write.auth(au, &admin_init, Duration::from_secs(TEST_CURRENT_TIME));
+        let ar = r1.unwrap();
+        let AuthResult { sessionid, state } = ar;
+
+        match state {
+            AuthState::Continue(_) => {}
+            _ => {
+                error!("Sessions was not initialised");
+                panic!();
+            }
+        };
+
+        idms_write.commit().expect("Must not fail");
+
+        sessionid
+    }
+
+    #[test]
+    fn test_idm_simple_password_auth() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let sid = init_admin_authsession_sid(idms, au);
+
+            let mut idms_write = idms.write();
+            let anon_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD);
+
+            // Expect success
+            let r2 = idms_write.auth(au, &anon_step, Duration::from_secs(TEST_CURRENT_TIME));
+            println!("r2 ==> {:?}", r2);
+
+            match r2 {
+                Ok(ar) => {
+                    let AuthResult {
+                        sessionid: _,
+                        state,
+                    } = ar;
+                    match state {
+                        AuthState::Success(_uat) => {
+                            // Check the uat.
+                        }
+                        _ => {
+                            error!("A critical error has occured! We have a non-succcess result!");
+                            panic!();
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("A critical error has occured! {:?}", e);
+                    // Should not occur!
+                    panic!();
+                }
+            };
+
+            idms_write.commit().expect("Must not fail");
+        })
+    }
+
+    #[test]
+    fn test_idm_bearer_token() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+
+            // Until there is a key, no token can be issued.
+            let sid = init_admin_authsession_sid(idms, au);
+            let mut idms_write = idms.write();
+            let pw_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD);
+            let ar = idms_write
+                .auth(au, &pw_step, ct.clone())
+                .expect("Failed to auth");
+            assert!(
+                idms_write.issue_bearer(&ar, None, ct.clone())
+                    == Err(OperationError::CryptographyError)
+            );
+            idms_write.commit().expect("Must not fail");
+
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            assert!(idms_prox_write.rotate_jws_keys(au, ct.clone()).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            // The key is stored on the domain info.
+            {
+                let udi = Uuid::parse_str(UUID_DOMAIN_INFO).unwrap();
+                let mut qs_read = qs.read();
+                let domain_entry = qs_read
+                    .internal_search_uuid(au, &udi)
+                    .expect("Failed to get domain info");
+                assert!(domain_entry.get_ava("jws_signing_key").map(|v| v.len()) == Some(1));
+            }
+
+            let (token, _) = admin_bearer_session(idms, au, ct.clone());
+
+            let uat = idms
+                .validate_bearer(au, token.as_str(), ct.clone())
+                .expect("Failed to validate");
+            assert!(uat.name == "admin");
+            let et = Duration::from_secs(TEST_CURRENT_TIME + BEARER_TOKEN_VALIDITY);
+            assert!(idms.validate_bearer(au, token.as_str(), et).is_none());
+
+            // A restarted server accepts the token once it has loaded the keys.
+            let idms_restart = IdmServer::new(
+                qs.clone(),
+                "https://idm.example.com",
+                CryptoPolicy::minimum(),
+            );
+            assert!(idms_restart
+                .validate_bearer(au, token.as_str(), ct.clone())
+                .is_none());
+            let mut idms_prox_write = idms_restart.proxy_write(ct.clone());
+            assert!(idms_prox_write.rotate_jws_keys(au, ct.clone()).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+            assert!(idms_restart
+                .validate_bearer(au, token.as_str(), ct.clone())
+                .is_some());
+
+            // But not one for another origin.
+            let idms_other = IdmServer::new(
+                qs.clone(),
+                "https://other.example.com",
+                CryptoPolicy::minimum(),
+            );
+            let mut idms_prox_write = idms_other.proxy_write(ct.clone());
+            assert!(idms_prox_write.rotate_jws_keys(au, ct.clone()).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+            assert!(idms_other.validate_bearer(au, token.as_str(), ct).is_none());
+        })
+    }
+
+    #[test]
+    fn test_idm_session_revoke() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            assert!(idms_prox_write.rotate_jws_keys(au, ct.clone()).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let (token_a, sid_a) = admin_bearer_session(idms, au, ct.clone());
+            let (token_b, _) = admin_bearer_session(idms, au, ct.clone());
+            let (token_c, _) = admin_bearer_session(idms, au, ct.clone());
+
+            let sle = SessionListEvent::new_internal(UUID_ADMIN.clone());
+            let sessions = idms
+                .proxy_read()
+                .get_sessions(au, &sle)
+                .expect("Failed to list sessions");
+            assert!(sessions.len() == 3);
+            assert!(sessions.iter().any(|s| s.id == sid_a));
+            assert!(sessions.iter().all(|s| s.method == "password"));
+            assert!(sessions
+                .iter()
+                .all(|s| s.expiry == TEST_CURRENT_TIME + BEARER_TOKEN_VALIDITY));
+
+            // Revoking one session only affects its token.
+            let sre = SessionRevokeEvent::new_internal(UUID_ADMIN.clone(), Some(sid_a));
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            assert!(idms_prox_write.revoke_sessions(au, &sre).is_ok());
+            // It's already gone.
+            assert!(
+                idms_prox_write.revoke_sessions(au, &sre) == Err(OperationError::NoMatchingEntries)
+            );
+            assert!(idms_prox_write.commit(au).is_ok());
+            assert!(idms
+                .validate_bearer(au, token_a.as_str(), ct.clone())
+                .is_none());
+            assert!(idms
+                .validate_bearer(au, token_b.as_str(), ct.clone())
+                .is_some());
+
+            // Or all of them.
+            let sre = SessionRevokeEvent::new_internal(UUID_ADMIN.clone(), None);
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            assert!(idms_prox_write.revoke_sessions(au, &sre).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+            assert!(idms
+                .validate_bearer(au, token_b.as_str(), ct.clone())
+                .is_none());
+            assert!(idms
+                .validate_bearer(au, token_c.as_str(), ct.clone())
+                .is_none());
+
+            // Expired sessions are removed when the next is recorded.
+            let et = Duration::from_secs(TEST_CURRENT_TIME + BEARER_TOKEN_VALIDITY);
+            let _ = admin_bearer_session(idms, au, ct.clone());
+            let _ = admin_bearer_session(idms, au, et.clone());
+            let sessions = idms
+                .proxy_read()
+                .get_sessions(au, &sle)
+                .expect("Failed to list sessions");
+            assert!(sessions.len() == 1);
+        })
+    }
+
+    #[test]
+    fn test_idm_privileged_claim() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+
+            // A normal login is interactive, but not privileged.
+            let sid = init_admin_authsession_sid(idms, au);
+            let mut idms_write = idms.write();
+            let pw_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD);
+            let uat = match idms_write.auth(au, &pw_step, ct.clone()) {
+                Ok(AuthResult {
+                    sessionid: _,
+                    state: AuthState::Success(uat),
+                }) => uat,
+                _ => panic!("login failed"),
+            };
+            assert!(uat
+                .claims
+                .iter()
+                .any(|c| c.name == CLAIM_INTERACTIVE && c.expiry.is_none()));
+            assert!(!uat.claims.iter().any(|c| c.name == CLAIM_PRIVILEGED));
+
+            // Authenticating again raises the session to privileged, for a short time.
+            let reauth = AuthEvent::reauth_init("admin", UUID_ADMIN.clone());
+            let sid = match idms_write.auth(au, &reauth, ct.clone()) {
+                Ok(AuthResult {
+                    sessionid,
+                    state: AuthState::Continue(_),
+                }) => sessionid,
+                _ => panic!("reauth init failed"),
+            };
+            let pw_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD);
+            let uat = match idms_write.auth(au, &pw_step, ct.clone()) {
+                Ok(AuthResult {
+                    sessionid: _,
+                    state: AuthState::Success(uat),
+                }) => uat,
+                _ => panic!("reauth failed"),
+            };
+            assert!(uat.claims.iter().any(|c| c.name == CLAIM_INTERACTIVE));
+            assert!(uat.claims.iter().any(|c| c.name == CLAIM_PRIVILEGED
+                && c.expiry == Some(TEST_CURRENT_TIME + PRIVILEGED_CLAIM_LIFETIME)));
+
+            idms_write.commit().expect("Must not fail");
+        })
+    }
+
+    #[test]
+    fn test_idm_simple_password_invalid() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let sid = init_admin_authsession_sid(idms, au);
+            let mut idms_write = idms.write();
+            let anon_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD_INC);
+
+            // Expect success
+            let r2 = idms_write.auth(au, &anon_step, Duration::from_secs(TEST_CURRENT_TIME));
+            println!("r2 ==> {:?}", r2);
+
+            match r2 {
+                Ok(ar) => {
+                    let AuthResult {
+                        sessionid: _,
+                        state,
+                    } = ar;
+                    match state {
+                        AuthState::Denied(_reason) => {
+                            // Check the uat.
+                        }
+                        _ => {
+                            error!("A critical error has occured! We have a non-denied result!");
+                            panic!();
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("A critical error has occured! {:?}", e);
+                    // Should not occur!
+                    panic!();
+                }
+            };
+
+            idms_write.commit().expect("Must not fail");
+        })
+    }
+
+    #[test]
+    fn test_idm_simple_password_reset() {
+        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+            // Setting the same password again is reuse.
+            assert!(
+                idms_prox_write.set_account_password(au, &pce)
+                    == Err(OperationError::PasswordReused)
+            );
+            // As is going back to an earlier one.
+            let pce_b = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_HISTORY, None);
+            assert!(idms_prox_write.set_account_password(au, &pce_b).is_ok());
+            assert!(
+                idms_prox_write.set_account_password(au, &pce)
+                    == Err(OperationError::PasswordReused)
+            );
+            assert!(idms_prox_write.commit(au).is_ok());
+        })
+    }
+
+    #[test]
+    fn test_idm_anonymous_set_password_denied() {
+        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            let pce = PasswordChangeEvent::new_internal(&UUID_ANONYMOUS, TEST_PASSWORD, None);
+
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            assert!(idms_prox_write.set_account_password(au, &pce).is_err());
+            assert!(idms_prox_write.commit(au).is_ok());
+        })
+    }
+
+    #[test]
+    fn test_idm_session_expire() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let sid = init_admin_authsession_sid(idms, au);
+            let mut idms_write = idms.write();
+            assert!(idms_write.is_sessionid_present(&sid));
+            // Expire like we are currently "now". Should not affect our session.
+            idms_write.expire_auth_sessions(Duration::from_secs(TEST_CURRENT_TIME));
+            assert!(idms_write.is_sessionid_present(&sid));
+            // Expire as though we are in the future.
+            idms_write.expire_auth_sessions(Duration::from_secs(TEST_CURRENT_EXPIRE));
+            assert!(!idms_write.is_sessionid_present(&sid));
+            assert!(idms_write.commit().is_ok());
+            let idms_write = idms.write();
+            assert!(!idms_write.is_sessionid_present(&sid));
+        })
+    }
+
+    #[test]
+    fn test_idm_regenerate_radius_secret() {
+        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            let rrse = RegenerateRadiusSecretEvent::new_internal(UUID_ADMIN.clone());
+
+            // Generates a new credential when none exists
+            let r1 = idms_prox_write
+                .regenerate_radius_secret(au, &rrse)
+                .expect("Failed to reset radius credential 1");
+            // Regenerates and overwrites the radius credential
+            let r2 = idms_prox_write
+                .regenerate_radius_secret(au, &rrse)
+                .expect("Failed to reset radius credential 2");
+            assert!(r1 != r2);
+        })
+    }
+
+    #[test]
+    fn test_idm_radiusauthtoken() {
+        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            let rrse = RegenerateRadiusSecretEvent::new_internal(UUID_ADMIN.clone());
+            let r1 = idms_prox_write
+                .regenerate_radius_secret(au, &rrse)
+                .expect("Failed to reset radius credential 1");
+            idms_prox_write.commit(au).expect("failed to commit");
+
+            let mut idms_prox_read = idms.proxy_read();
+            let rate = RadiusAuthTokenEvent::new_internal(UUID_ADMIN.clone());
+            let tok_r = idms_prox_read
+                .get_radiusauthtoken(au, &rate, Duration::from_secs(TEST_CURRENT_TIME))
+                .expect("Failed to generate radius auth token");
+
+            // view the token?
+            assert!(r1 == tok_r.secret);
+        })
+    }
+
+    #[test]
+    fn test_idm_simple_password_reject_weak() {
+        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            // len check
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, "password", None);
+            let e = idms_prox_write.set_account_password(au, &pce);
+            assert!(e.is_err());
+
+            // zxcvbn check
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, "password1234", None);
+            let e = idms_prox_write.set_account_password(au, &pce);
+            assert!(e.is_err());
+
+            // Check the "name" checking works too (I think admin may hit a common pw rule first)
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, "admin_nta", None);
+            let e = idms_prox_write.set_account_password(au, &pce);
+            assert!(e.is_err());
+
+            // Check that the demo badlist password is rejected.
+            let pce = PasswordChangeEvent::new_internal(
+                &UUID_ADMIN,
+                "demo_badlist_shohfie3aeci2oobur0aru9uushah6EiPi2woh4hohngoighaiRuepieN3ongoo1",
+                None,
+            );
+            let e = idms_prox_write.set_account_password(au, &pce);
+            assert!(e.is_err());
+
+            assert!(idms_prox_write.commit(au).is_ok());
+        })
+    }
+
+    #[test]
+    fn test_idm_unixusertoken() {
+        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            // Modify admin to have posixaccount
+            let me_posix = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                    ModifyList::new_list(vec![
+                        Modify::Present("class".to_string(), Value::new_class("posixaccount")),
+                        Modify::Present("gidnumber".to_string(), Value::new_uint32(2001)),
+                    ]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_posix).is_ok());
+            // Add a posix group that has the admin as a member.
+            let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "group", "posixgroup"],
+                    "name": ["testgroup"],
+                    "uuid": ["01609135-a1c4-43d5-966b-a28227644445"],
+                    "description": ["testgroup"],
+                    "member": ["00000000-0000-0000-0000-000000000000"]
+                }
+            }"#,
+            );
+
+            let ce = CreateEvent::new_internal(vec![e.clone()]);
+
+            assert!(idms_prox_write.qs_write.create(au, &ce).is_ok());
+
+            idms_prox_write.commit(au).expect("failed to commit");
+
+            let mut idms_prox_read = idms.proxy_read();
+
+            let ugte = UnixGroupTokenEvent::new_internal(
+                Uuid::parse_str("01609135-a1c4-43d5-966b-a28227644445")
+                    .expect("failed to parse uuid"),
+            );
+            let tok_g = idms_prox_read
+                .get_unixgrouptoken(au, &ugte)
+                .expect("Failed to generate unix group token");
+
+            assert!(tok_g.name == "testgroup");
+            assert!(tok_g.spn == "testgroup@example.com");
+
+            let uute = UnixUserTokenEvent::new_internal(UUID_ADMIN.clone());
+            let tok_r = idms_prox_read
+                .get_unixusertoken(au, &uute)
+                .expect("Failed to generate unix user token");
+
+            assert!(tok_r.name == "admin");
+            assert!(tok_r.spn == "admin@example.com");
+            assert!(tok_r.groups.len() == 2);
+            assert!(tok_r.groups[0].name == "admin");
+            assert!(tok_r.groups[1].name == "testgroup");
+
+            // Show we can get the admin as a unix group token too
+            let ugte = UnixGroupTokenEvent::new_internal(
+                Uuid::parse_str("00000000-0000-0000-0000-000000000000")
+                    .expect("failed to parse uuid"),
+            );
+            let tok_g = idms_prox_read
+                .get_unixgrouptoken(au, &ugte)
+                .expect("Failed to generate unix group token");
+
+            assert!(tok_g.name == "admin");
+            assert!(tok_g.spn == "admin@example.com");
+        })
+    }
+
+    #[test]
+    fn test_idm_simple_unix_password_reset() {
+        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            // make the admin a valid posix account
+            let me_posix = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                    ModifyList::new_list(vec![
+                        Modify::Present("class".to_string(), Value::new_class("posixaccount")),
+                        Modify::Present("gidnumber".to_string(), Value::new_uint32(2001)),
+                    ]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_posix).is_ok());
+
+            let pce = UnixPasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
+
+            assert!(idms_prox_write.set_unix_account_password(au, &pce).is_ok());
+            assert!(
+                idms_prox_write.set_unix_account_password(au, &pce)
+                    == Err(OperationError::PasswordReused)
+            );
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let mut idms_write = idms.write();
+            // Check auth verification of the password
+
+            let uuae_good = UnixUserAuthEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
+            let a1 = idms_write.auth_unix(au, &uuae_good, Duration::from_secs(TEST_CURRENT_TIME));
+            match a1 {
+                Ok(Some(_tok)) => {}
+                _ => assert!(false),
+            };
+            // Check bad password
+            let uuae_bad = UnixUserAuthEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_INC);
+            let a2 = idms_write.auth_unix(au, &uuae_bad, Duration::from_secs(TEST_CURRENT_TIME));
+            match a2 {
+                Ok(None) => {}
+                _ => assert!(false),
+            };
+            assert!(idms_write.commit().is_ok());
+
+            // Check deleting the password
+            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            let me_purge_up = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                    ModifyList::new_list(vec![Modify::Purged("unix_password".to_string())]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_purge_up).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            // And auth should now fail due to the lack of PW material
+            let mut idms_write = idms.write();
+            let a3 = idms_write.auth_unix(au, &uuae_good, Duration::from_secs(TEST_CURRENT_TIME));
+            match a3 {
+                Ok(None) => {}
+                _ => assert!(false),
+            };
+            assert!(idms_write.commit().is_ok());
+        })
+    }
+
+    #[test]
+    fn test_idm_totp_registration() {
+        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            let ct = duration_from_epoch_now();
+            let expire = Duration::from_secs(ct.as_secs() + MFAREG_SESSION_TIMEOUT + 2);
+            let mut idms_prox_write = idms.proxy_write(ct);
+
+            // verify with no session (fail)
+            let vte1 = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), Uuid::new_v4(), 0);
+
+            match idms_prox_write.verify_account_totp(au, &vte1, ct.clone()) {
+                Err(e) => {
+                    assert!(e == OperationError::InvalidRequestState);
+                }
+                _ => panic!(),
+            };
+
+            // reg, expire session, attempt verify (fail)
+            let gte1 = GenerateTOTPEvent::new_internal(UUID_ADMIN.clone());
+
+            let res = idms_prox_write
+                .generate_account_totp(au, &gte1, ct.clone())
+                .unwrap();
+            let sesid = match res {
+                SetCredentialResponse::TOTPCheck(id, _) => id,
+                _ => panic!("invalid state!"),
+            };
+            idms_prox_write.expire_mfareg_sessions(expire.clone());
+
+            let vte2 = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), sesid, 0);
+
+            match idms_prox_write.verify_account_totp(au, &vte1, ct.clone()) {
+                Err(e) => {
+                    assert!(e == OperationError::InvalidRequestState);
+                }
+                _ => panic!(),
+            };
+
+            // == Test TOTP on account with no password (fail)
+            let res = idms_prox_write
+                .generate_account_totp(au, &gte1, ct.clone())
+                .unwrap();
+            let (sesid, tok) = match res {
+                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
+                _ => panic!("invalid state!"),
+            };
+            // get the correct otp
+            let r_tok: TOTP = tok.into();
+            let chal = r_tok
+                .do_totp_duration_from_epoch(&ct)
+                .expect("Failed to do totp?");
+            // attempt the verify
+            let vte3 = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), sesid, chal);
+
+            match idms_prox_write.verify_account_totp(au, &vte3, ct.clone()) {
+                Err(e) => assert!(e == OperationError::InvalidState),
+                _ => panic!(),
+            };
 
-                // Expect success
-                let r2 = idms_write.auth(au, &anon_step, Duration::from_secs(TEST_CURRENT_TIME));
-                println!("r2 ==> {:?}", r2);
+            // Expire the session to allow it to reset.
+            idms_prox_write.expire_mfareg_sessions(expire.clone());
 
-                match r2 {
-                    Ok(ar) => {
-                        let AuthResult {
-                            sessionid: _,
-                            state,
-                        } = ar;
-                        match state {
-                            AuthState::Success(_uat) => {
-                                // Check the uat.
-                            }
-                            _ => {
-                                error!(
-                                    "A critical error has occured! We have a non-succcess result!"
-                                );
-                                panic!();
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!("A critical error has occured! {:?}", e);
-                        // Should not occur!
-                        panic!();
-                    }
-                };
+            // Set a password.
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
 
-                idms_write.commit().expect("Must not fail");
-            }
-        });
-    }
+            // == reg, but change the event source part way in the process (failure)
+            let res = idms_prox_write
+                .generate_account_totp(au, &gte1, ct.clone())
+                .unwrap();
+            let (sesid, tok) = match res {
+                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
+                _ => panic!("invalid state!"),
+            };
+            // get the correct otp
+            let r_tok: TOTP = tok.into();
+            let chal = r_tok
+                .do_totp_duration_from_epoch(&ct)
+                .expect("Failed to do totp?");
+            // attempt the verify
+            let vte3 = VerifyTOTPEvent::new_internal(UUID_ANONYMOUS.clone(), sesid, chal);
 
-    // Test sending anonymous but with no session init.
-    #[test]
-    fn test_idm_anonymous_auth_invalid_states() {
-        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
-            {
-                let mut idms_write = idms.write();
-                let sid = Uuid::new_v4();
-                let anon_step = AuthEvent::cred_step_anonymous(sid);
+            match idms_prox_write.verify_account_totp(au, &vte3, ct.clone()) {
+                Err(e) => assert!(e == OperationError::InvalidRequestState),
+                _ => panic!(),
+            };
 
-                // Expect failure
-                let r2 = idms_write.auth(au, &anon_step, Duration::from_secs(TEST_CURRENT_TIME));
-                println!("r2 ==> {:?}", r2);
+            // == reg, verify w_ incorrect totp (fail)
+            let res = idms_prox_write
+                .generate_account_totp(au, &gte1, ct.clone())
+                .unwrap();
+            let (_sesid, _tok) = match res {
+                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
+                _ => panic!("invalid state!"),
+            };
 
-                match r2 {
-                    Ok(_) => {
-                        error!("Auth state machine not correctly enforced!");
-                        panic!();
-                    }
-                    Err(e) => match e {
-                        OperationError::InvalidSessionState => {}
-                        _ => panic!(),
-                    },
-                };
-            }
-        })
-    }
+            // We can reuse the OTP/Vte2 from before, since we want the invalid otp.
+            match idms_prox_write.verify_account_totp(au, &vte2, ct.clone()) {
+                // On failure we get back another attempt to setup the token.
+                Ok(SetCredentialResponse::TOTPCheck(_id, _tok)) => {}
+                _ => panic!(),
+            };
+            idms_prox_write.expire_mfareg_sessions(expire.clone());
 
-    fn init_admin_w_password(
-        au: &mut AuditScope,
-        qs: &QueryServer,
-        pw: &str,
-    ) -> Result<(), OperationError> {
-        let cred = Credential::new_password_only(pw);
-        let v_cred = Value::new_credential("primary", cred);
-        let mut qs_write = qs.write(duration_from_epoch_now());
+            // Turn the pts into an otp
+            // == reg, verify w_ correct totp (success)
+            let res = idms_prox_write
+                .generate_account_totp(au, &gte1, ct.clone())
+                .unwrap();
+            let (sesid, tok) = match res {
+                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
+                _ => panic!("invalid state!"),
+            };
+            // We can't reuse the OTP/Vte from before, since the token seed changes
+            let r_tok: TOTP = tok.into();
+            let chal = r_tok
+                .do_totp_duration_from_epoch(&ct)
+                .expect("Failed to do totp?");
+            // attempt the verify
+            let vte3 = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), sesid, chal);
 
-        // now modify and provide a primary credential.
-        let me_inv_m = unsafe {
-            ModifyEvent::new_internal_invalid(
-                filter!(f_eq("name", PartialValue::new_iutf8s("admin"))),
-                ModifyList::new_list(vec![Modify::Present(
-                    "primary_credential".to_string(),
-                    v_cred,
-                )]),
-            )
-        };
-        // go!
-        assert!(qs_write.modify(au, &me_inv_m).is_ok());
+            match idms_prox_write.verify_account_totp(au, &vte3, ct.clone()) {
+                // Backup codes are issued with the token.
+                Ok(SetCredentialResponse::BackupCodes(codes)) => {
+                    assert!(codes.len() == BACKUP_CODE_COUNT)
+                }
+                _ => panic!(),
+            };
+            idms_prox_write.expire_mfareg_sessions(expire.clone());
 
-        qs_write.commit(au)
+            assert!(idms_prox_write.commit(au).is_ok());
+        })
     }
 
-    fn init_admin_authsession_sid(idms: &IdmServer, au: &mut AuditScope) -> Uuid {
+    fn admin_mfa_init(idms: &IdmServer, au: &mut AuditScope, ct: Duration) -> Uuid {
         let mut idms_write = idms.write();
         let admin_init = AuthEvent::named_init("admin");
-
-        let r1 = idms_write.auth(au, &admin_init, Duration::from_secs(TEST_CURRENT_TIME));
-        let ar = r1.unwrap();
-        let AuthResult { sessionid, state } = ar;
-
+        let AuthResult { sessionid, state } = idms_write
+            .auth(au, &admin_init, ct)
+            .expect("Failed to init auth");
         match state {
             AuthState::Continue(_) => {}
-            _ => {
-                error!("Sessions was not initialised");
-                panic!();
-            }
+            _ => panic!("invalid state!"),
         };
+        idms_write.commit().expect("Must not fail");
+        sessionid
+    }
 
+    fn admin_backup_code_step(
+        idms: &IdmServer,
+        au: &mut AuditScope,
+        sessionid: Uuid,
+        code: &str,
+        ct: Duration,
+    ) -> Result<AuthState, OperationError> {
+        let mut idms_write = idms.write();
+        let step = AuthEvent::cred_step_password_backup_code(sessionid, TEST_PASSWORD, code);
+        let state = idms_write
+            .auth(au, &step, ct)
+            .expect("Failed to step auth")
+            .state;
+        let uses = idms_write.take_backup_code_uses();
         idms_write.commit().expect("Must not fail");
+        // As the auth actor does, remove the used codes, and fail if they are gone.
+        let mut idms_prox_write = idms.proxy_write(ct);
+        idms_prox_write
+            .remove_backup_codes(au, &uses)
+            .and_then(|_| idms_prox_write.commit(au))
+            .map(|_| state)
+    }
 
-        sessionid
+    fn admin_backup_code_auth(
+        idms: &IdmServer,
+        au: &mut AuditScope,
+        code: &str,
+        ct: Duration,
+    ) -> AuthState {
+        let sessionid = admin_mfa_init(idms, au, ct);
+        admin_backup_code_step(idms, au, sessionid, code, ct).expect("Failed to remove code")
     }
 
     #[test]
-    fn test_idm_simple_password_auth() {
+    fn test_idm_backup_codes() {
         run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
             init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
-            let sid = init_admin_authsession_sid(idms, au);
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
 
-            let mut idms_write = idms.write();
-            let anon_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD);
-
-            // Expect success
-            let r2 = idms_write.auth(au, &anon_step, Duration::from_secs(TEST_CURRENT_TIME));
-            println!("r2 ==> {:?}", r2);
+            // Without a totp, there is nothing for backup codes to stand in for.
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let gbe = GenerateBackupCodesEvent::new_internal(UUID_ADMIN.clone());
+            assert!(idms_prox_write.generate_backup_codes(au, &gbe).is_err());
 
-            match r2 {
-                Ok(ar) => {
-                    let AuthResult {
-                        sessionid: _,
-                        state,
-                    } = ar;
-                    match state {
-                        AuthState::Success(_uat) => {
-                            // Check the uat.
-                        }
-                        _ => {
-                            error!("A critical error has occured! We have a non-succcess result!");
-                            panic!();
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("A critical error has occured! {:?}", e);
-                    // Should not occur!
-                    panic!();
-                }
+            // Register a totp, which issues the first codes.
+            let gte = GenerateTOTPEvent::new_internal(UUID_ADMIN.clone());
+            let (sesid, tok) = match idms_prox_write.generate_account_totp(au, &gte, ct.clone()) {
+                Ok(SetCredentialResponse::TOTPCheck(id, tok)) => (id, tok),
+                _ => panic!("invalid state!"),
+            };
+            let r_tok: TOTP = tok.into();
+            let chal = r_tok
+                .do_totp_duration_from_epoch(&ct)
+                .expect("Failed to do totp?");
+            let vte = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), sesid, chal);
+            let codes = match idms_prox_write.verify_account_totp(au, &vte, ct.clone()) {
+                Ok(SetCredentialResponse::BackupCodes(codes)) => codes,
+                _ => panic!("invalid state!"),
             };
+            assert!(idms_prox_write.commit(au).is_ok());
 
-            idms_write.commit().expect("Must not fail");
+            // A code can be used in place of the totp ...
+            match admin_backup_code_auth(idms, au, codes[0].as_str(), ct) {
+                AuthState::Success(uat) => assert!(uat.warnings.is_empty()),
+                _ => panic!("backup code auth failed"),
+            }
+            // ... but only once.
+            match admin_backup_code_auth(idms, au, codes[0].as_str(), ct) {
+                AuthState::Denied(_) => {}
+                _ => panic!("backup code reused"),
+            }
+
+            // Regenerating replaces all of the old codes.
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let new_codes = idms_prox_write
+                .generate_backup_codes(au, &gbe)
+                .expect("Failed to generate backup codes");
+            assert!(new_codes.len() == BACKUP_CODE_COUNT);
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            match admin_backup_code_auth(idms, au, new_codes[0].as_str(), ct) {
+                AuthState::Success(_) => {}
+                _ => panic!("backup code auth failed"),
+            }
+            match admin_backup_code_auth(idms, au, codes[1].as_str(), ct) {
+                AuthState::Denied(_) => {}
+                _ => panic!("old backup code accepted"),
+            }
+
+            // Two sessions that began before either used a code both accept it, but only
+            // the first to remove it succeeds.
+            let first = admin_mfa_init(idms, au, ct);
+            let second = admin_mfa_init(idms, au, ct);
+            match admin_backup_code_step(idms, au, first, new_codes[1].as_str(), ct) {
+                Ok(AuthState::Success(_)) => {}
+                _ => panic!("backup code auth failed"),
+            }
+            assert!(admin_backup_code_step(idms, au, second, new_codes[1].as_str(), ct).is_err());
         })
     }
 
+    fn admin_totp_step(
+        idms: &IdmServer,
+        au: &mut AuditScope,
+        sessionid: Uuid,
+        totp: u32,
+        ct: Duration,
+    ) -> Result<AuthState, OperationError> {
+        let mut idms_write = idms.write();
+        let step = AuthEvent::cred_step_password_totp(sessionid, TEST_PASSWORD, totp);
+        let state = idms_write
+            .auth(au, &step, ct)
+            .expect("Failed to step auth")
+            .state;
+        let updates = idms_write.take_totp_step_updates();
+        idms_write.commit().expect("Must not fail");
+        // As the auth actor does, record the accepted steps, and fail if they were used.
+        let mut idms_prox_write = idms.proxy_write(ct);
+        idms_prox_write
+            .update_totp_steps(au, &updates)
+            .and_then(|_| idms_prox_write.commit(au))
+            .map(|_| state)
+    }
+
+    fn admin_totp_auth(
+        idms: &IdmServer,
+        au: &mut AuditScope,
+        totp: u32,
+        ct: Duration,
+    ) -> AuthState {
+        let sessionid = admin_mfa_init(idms, au, ct);
+        admin_totp_step(idms, au, sessionid, totp, ct).expect("Failed to record totp step")
+    }
+
     #[test]
-    fn test_idm_simple_password_invalid() {
+    fn test_idm_totp_replay_and_remove() {
         run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
             init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
-            let sid = init_admin_authsession_sid(idms, au);
-            let mut idms_write = idms.write();
-            let anon_step = AuthEvent::cred_step_password(sid, TEST_PASSWORD_INC);
-
-            // Expect success
-            let r2 = idms_write.auth(au, &anon_step, Duration::from_secs(TEST_CURRENT_TIME));
-            println!("r2 ==> {:?}", r2);
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
 
-            match r2 {
-                Ok(ar) => {
-                    let AuthResult {
-                        sessionid: _,
-                        state,
-                    } = ar;
-                    match state {
-                        AuthState::Denied(_reason) => {
-                            // Check the uat.
-                        }
-                        _ => {
-                            error!("A critical error has occured! We have a non-denied result!");
-                            panic!();
-                        }
-                    }
-                }
-                Err(e) => {
-                    error!("A critical error has occured! {:?}", e);
-                    // Should not occur!
-                    panic!();
-                }
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let gte = GenerateTOTPEvent::new_internal(UUID_ADMIN.clone());
+            let (sesid, tok) = match idms_prox_write.generate_account_totp(au, &gte, ct.clone()) {
+                Ok(SetCredentialResponse::TOTPCheck(id, tok)) => (id, tok),
+                _ => panic!("invalid state!"),
+            };
+            let r_tok: TOTP = tok.into();
+            let chal = r_tok
+                .do_totp_duration_from_epoch(&ct)
+                .expect("Failed to do totp?");
+            let vte = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), sesid, chal);
+            match idms_prox_write.verify_account_totp(au, &vte, ct.clone()) {
+                Ok(SetCredentialResponse::BackupCodes(_)) => {}
+                _ => panic!("invalid state!"),
+            };
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            // The code that verified the device can't then be used to authenticate.
+            match admin_totp_auth(idms, au, chal, ct) {
+                AuthState::Denied(_) => {}
+                _ => panic!("registration totp code reused"),
+            }
+            // A code from the next window is accepted, but only once.
+            let ct = ct + Duration::from_secs(30);
+            let chal = r_tok
+                .do_totp_duration_from_epoch(&ct)
+                .expect("Failed to do totp?");
+            match admin_totp_auth(idms, au, chal, ct) {
+                AuthState::Success(_) => {}
+                _ => panic!("totp auth failed"),
+            }
+            match admin_totp_auth(idms, au, chal, ct) {
+                AuthState::Denied(_) => {}
+                _ => panic!("totp code replayed"),
+            }
+
+            // Two sessions that began before either used a code both accept it, but only
+            // the first to record the step succeeds.
+            let ct = ct + Duration::from_secs(30);
+            let chal = r_tok
+                .do_totp_duration_from_epoch(&ct)
+                .expect("Failed to do totp?");
+            let first = admin_mfa_init(idms, au, ct);
+            let second = admin_mfa_init(idms, au, ct);
+            match admin_totp_step(idms, au, first, chal, ct) {
+                Ok(AuthState::Success(_)) => {}
+                _ => panic!("totp auth failed"),
+            }
+            assert!(admin_totp_step(idms, au, second, chal, ct).is_err());
+
+            // A second device can't reuse the label.
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            match idms_prox_write.generate_account_totp(au, &gte, ct.clone()) {
+                Err(OperationError::InvalidAttribute(_)) => {}
+                _ => panic!("duplicate totp label accepted"),
             };
+            assert!(idms_prox_write.commit(au).is_ok());
 
-            idms_write.commit().expect("Must not fail");
+            {
+                let mut idms_prox_read = idms.proxy_read();
+                let tle = TOTPListEvent::new_internal(UUID_ADMIN.clone());
+                let labels = idms_prox_read
+                    .get_totp_labels(au, &tle)
+                    .expect("Failed to list totps");
+                assert!(labels == vec!["internal_token".to_string()]);
+            }
+
+            // Removing the lost device leaves just the password, and the backup codes go
+            // with it.
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let tre =
+                TOTPRemoveEvent::new_internal(UUID_ADMIN.clone(), "internal_token".to_string());
+            assert!(idms_prox_write.remove_account_totp(au, &tre).is_ok());
+            match idms_prox_write.remove_account_totp(au, &tre) {
+                Err(OperationError::NoMatchingEntries) => {}
+                _ => panic!("missing totp removed"),
+            };
+            let gbe = GenerateBackupCodesEvent::new_internal(UUID_ADMIN.clone());
+            assert!(idms_prox_write.generate_backup_codes(au, &gbe).is_err());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            match admin_password_auth(idms, au, TEST_PASSWORD, ct) {
+                AuthState::Success(_) => {}
+                _ => panic!("password auth failed"),
+            }
         })
     }
 
     #[test]
-    fn test_idm_simple_password_reset() {
-        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
-            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+    fn test_idm_webauthn_registration_and_auth() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+            let mut wa = WebauthnAuthenticator::new(U2FSoft::new());
+
+            // Register a token to admin, which has no password, so it's the only factor.
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let wre =
+                WebauthnInitRegisterEvent::new_internal(UUID_ADMIN.clone(), "soft".to_string());
+            let (sesid, chal) = match idms_prox_write.init_account_webauthn(au, &wre, ct.clone()) {
+                Ok(SetCredentialResponse::WebauthnCreateChallenge(sesid, chal)) => (sesid, chal),
+                _ => panic!("invalid state!"),
+            };
+            let reg = wa
+                .do_registration("https://idm.example.com", chal)
+                .expect("Failed to create soft token");
 
-            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
-            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
-            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+            // Continuing from a different source fails.
+            let wre_bad = WebauthnDoRegisterEvent::new_internal(UUID_ANONYMOUS.clone(), sesid, reg);
+            match idms_prox_write.register_account_webauthn(au, &wre_bad, ct.clone()) {
+                Err(e) => assert!(e == OperationError::InvalidRequestState),
+                _ => panic!(),
+            };
+
+            let wre = WebauthnDoRegisterEvent::new_internal(UUID_ADMIN.clone(), sesid, wre_bad.reg);
+            match idms_prox_write.register_account_webauthn(au, &wre, ct.clone()) {
+                Ok(SetCredentialResponse::Success) => {}
+                _ => panic!(),
+            };
+
+            // A second token can't reuse the label of the first.
+            let wre =
+                WebauthnInitRegisterEvent::new_internal(UUID_ADMIN.clone(), "soft".to_string());
+            let (sesid, chal) = match idms_prox_write.init_account_webauthn(au, &wre, ct.clone()) {
+                Ok(SetCredentialResponse::WebauthnCreateChallenge(sesid, chal)) => (sesid, chal),
+                _ => panic!("invalid state!"),
+            };
+            let reg = wa
+                .do_registration("https://idm.example.com", chal)
+                .expect("Failed to create soft token");
+            let wre = WebauthnDoRegisterEvent::new_internal(UUID_ADMIN.clone(), sesid, reg);
+            match idms_prox_write.register_account_webauthn(au, &wre, ct.clone()) {
+                Err(OperationError::InvalidAttribute(_)) => {}
+                _ => panic!(),
+            };
+
+            // Removing the only token of the credential is denied.
+            let wre = WebauthnRemoveEvent::new_internal(UUID_ADMIN.clone(), "soft".to_string());
+            assert!(idms_prox_write.remove_account_webauthn(au, &wre).is_err());
             assert!(idms_prox_write.commit(au).is_ok());
-        })
-    }
 
-    #[test]
-    fn test_idm_anonymous_set_password_denied() {
-        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
-            let pce = PasswordChangeEvent::new_internal(&UUID_ANONYMOUS, TEST_PASSWORD, None);
+            // Now authenticate with it.
+            let mut idms_write = idms.write();
+            let admin_init = AuthEvent::named_init("admin");
+            let sid = match idms_write.auth(au, &admin_init, ct.clone()) {
+                Ok(AuthResult {
+                    sessionid,
+                    state: AuthState::Continue(mut mechs),
+                }) => {
+                    let chal = match mechs.pop() {
+                        Some(AuthAllowed::Webauthn(chal)) => chal,
+                        _ => panic!(),
+                    };
+                    let resp = wa
+                        .do_authentication("https://idm.example.com", chal)
+                        .expect("failed to use softtoken to authenticate");
+                    let wan_step = AuthEvent::cred_step_webauthn(sessionid, resp);
+                    match idms_write.auth(au, &wan_step, ct.clone()) {
+                        Ok(AuthResult {
+                            sessionid: _,
+                            state: AuthState::Success(_),
+                        }) => {}
+                        _ => panic!(),
+                    };
+                    sessionid
+                }
+                _ => panic!(),
+            };
+            assert!(idms_write.is_sessionid_present(&sid));
+            let counters = idms_write.take_webauthn_counter_updates();
+            assert!(counters.len() == 1);
+            assert!(idms_write.commit().is_ok());
 
-            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
-            assert!(idms_prox_write.set_account_password(au, &pce).is_err());
+            // Write back the counter, and check it was stored.
+            let mut idms_prox_write = idms.proxy_write(ct);
+            assert!(idms_prox_write
+                .update_webauthn_counters(au, &counters)
+                .is_ok());
             assert!(idms_prox_write.commit(au).is_ok());
+
+            let mut qs_read = qs.read();
+            let admin = qs_read
+                .internal_search_uuid(au, &UUID_ADMIN)
+                .expect("Can't access admin entry.");
+            let cred = admin
+                .get_ava_single_credential("primary_credential")
+                .expect("No primary credential");
+            let (_, cid, counter) = &counters[0];
+            assert!(cred
+                .webauthn
+                .as_ref()
+                .expect("No webauthn tokens")
+                .values()
+                .any(|c| &c.cred_id == cid && c.counter == *counter));
         })
     }
 
+    fn admin_password_auth(
+        idms: &IdmServer,
+        au: &mut AuditScope,
+        pw: &str,
+        ct: Duration,
+    ) -> AuthState {
+        let mut idms_write = idms.write();
+        let admin_init = AuthEvent::named_init("admin");
+        let AuthResult { sessionid, state } = idms_write
+            .auth(au, &admin_init, ct)
+            .expect("Failed to init auth");
+        let state = match state {
+            AuthState::Continue(_) => {
+                let pw_step = AuthEvent::cred_step_password(sessionid, pw);
+                idms_write
+                    .auth(au, &pw_step, ct)
+                    .expect("Failed to step auth")
+                    .state
+            }
+            s => s,
+        };
+        idms_write.commit().expect("Must not fail");
+        state
+    }
+
     #[test]
-    fn test_idm_session_expire() {
+    fn test_idm_account_softlock() {
         run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
             init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
-            let sid = init_admin_authsession_sid(idms, au);
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+
+            // Fail enough times to trigger the lock.
+            for _ in 0..SOFTLOCK_FAILURE_THRESHOLD {
+                match admin_password_auth(idms, au, TEST_PASSWORD_INC, ct) {
+                    AuthState::Denied(msg) => assert!(msg != ACCOUNT_SOFTLOCKED_MSG),
+                    _ => assert!(false),
+                }
+            }
+
+            // Now even the correct password is refused, and says why.
+            match admin_password_auth(idms, au, TEST_PASSWORD, ct) {
+                AuthState::Denied(msg) => assert!(msg == ACCOUNT_SOFTLOCKED_MSG),
+                _ => assert!(false),
+            }
+
+            // As is unix auth and the radius token.
             let mut idms_write = idms.write();
-            assert!(idms_write.is_sessionid_present(&sid));
-            // Expire like we are currently "now". Should not affect our session.
-            idms_write.expire_auth_sessions(Duration::from_secs(TEST_CURRENT_TIME));
-            assert!(idms_write.is_sessionid_present(&sid));
-            // Expire as though we are in the future.
-            idms_write.expire_auth_sessions(Duration::from_secs(TEST_CURRENT_EXPIRE));
-            assert!(!idms_write.is_sessionid_present(&sid));
+            let uuae = UnixUserAuthEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
+            match idms_write.auth_unix(au, &uuae, ct) {
+                Ok(None) => {}
+                _ => assert!(false),
+            }
             assert!(idms_write.commit().is_ok());
-            let idms_write = idms.write();
-            assert!(!idms_write.is_sessionid_present(&sid));
-        })
-    }
 
-    #[test]
-    fn test_idm_regenerate_radius_secret() {
-        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
-            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
-            let rrse = RegenerateRadiusSecretEvent::new_internal(UUID_ADMIN.clone());
+            {
+                let mut idms_prox_read = idms.proxy_read();
+                let rate = RadiusAuthTokenEvent::new_internal(UUID_ADMIN.clone());
+                match idms_prox_read.get_radiusauthtoken(au, &rate, ct) {
+                    Err(OperationError::InvalidAccountState(msg)) => {
+                        assert!(msg == ACCOUNT_SOFTLOCKED_MSG)
+                    }
+                    _ => assert!(false),
+                }
+            }
 
-            // Generates a new credential when none exists
-            let r1 = idms_prox_write
-                .regenerate_radius_secret(au, &rrse)
-                .expect("Failed to reset radius credential 1");
-            // Regenerates and overwrites the radius credential
-            let r2 = idms_prox_write
-                .regenerate_radius_secret(au, &rrse)
-                .expect("Failed to reset radius credential 2");
-            assert!(r1 != r2);
-        })
-    }
+            // Anonymous is unaffected by the admin lock.
+            let mut idms_write = idms.write();
+            let anon_init = AuthEvent::anonymous_init();
+            match idms_write.auth(au, &anon_init, ct) {
+                Ok(AuthResult {
+                    state: AuthState::Continue(_),
+                    ..
+                }) => {}
+                _ => assert!(false),
+            }
+            assert!(idms_write.commit().is_ok());
 
-    #[test]
-    fn test_idm_radiusauthtoken() {
-        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
-            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
-            let rrse = RegenerateRadiusSecretEvent::new_internal(UUID_ADMIN.clone());
-            let r1 = idms_prox_write
-                .regenerate_radius_secret(au, &rrse)
-                .expect("Failed to reset radius credential 1");
-            idms_prox_write.commit(au).expect("failed to commit");
+            // Once the backoff passes we can log in, which clears the failures.
+            let ct = ct + Duration::from_secs(SOFTLOCK_BASE_DELAY);
+            match admin_password_auth(idms, au, TEST_PASSWORD, ct) {
+                AuthState::Success(_) => {}
+                _ => assert!(false),
+            }
 
-            let mut idms_prox_read = idms.proxy_read();
-            let rate = RadiusAuthTokenEvent::new_internal(UUID_ADMIN.clone());
-            let tok_r = idms_prox_read
-                .get_radiusauthtoken(au, &rate)
-                .expect("Failed to generate radius auth token");
+            // Lock it again, and this time have it unlocked.
+            for _ in 0..SOFTLOCK_FAILURE_THRESHOLD {
+                let _ = admin_password_auth(idms, au, TEST_PASSWORD_INC, ct);
+            }
+            match admin_password_auth(idms, au, TEST_PASSWORD, ct) {
+                AuthState::Denied(msg) => assert!(msg == ACCOUNT_SOFTLOCKED_MSG),
+                _ => assert!(false),
+            }
 
-            // view the token?
-            assert!(r1 == tok_r.secret);
+            let mut idms_write = idms.write();
+            let uae = UnlockAccountEvent::new_internal(UUID_ADMIN.clone());
+            assert!(idms_write.unlock_account(au, &uae).is_ok());
+            assert!(idms_write.commit().is_ok());
+
+            match admin_password_auth(idms, au, TEST_PASSWORD, ct) {
+                AuthState::Success(_) => {}
+                _ => assert!(false),
+            }
         })
     }
 
     #[test]
-    fn test_idm_simple_password_reject_weak() {
-        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
-            // len check
+    fn test_idm_account_valid_time() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+
+            // Give the admin a unix password and radius secret, and expire it.
             let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
+            let me_expire = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iutf8s("admin"))),
+                    ModifyList::new_list(vec![
+                        Modify::Present("class".to_string(), Value::new_class("posixaccount")),
+                        Modify::Present("gidnumber".to_string(), Value::new_uint32(2001)),
+                        Modify::Present(
+                            "account_expire".to_string(),
+                            Value::new_int64(TEST_CURRENT_TIME as i64),
+                        ),
+                    ]),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_expire).is_ok());
+            let pce = UnixPasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
+            assert!(idms_prox_write.set_unix_account_password(au, &pce).is_ok());
+            let rrse = RegenerateRadiusSecretEvent::new_internal(UUID_ADMIN.clone());
+            assert!(idms_prox_write.regenerate_radius_secret(au, &rrse).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
 
-            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, "password", None);
-            let e = idms_prox_write.set_account_password(au, &pce);
-            assert!(e.is_err());
+            let before = Duration::from_secs(TEST_CURRENT_TIME - 1);
+            let after = Duration::from_secs(TEST_CURRENT_TIME);
 
-            // zxcvbn check
-            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, "password1234", None);
-            let e = idms_prox_write.set_account_password(au, &pce);
-            assert!(e.is_err());
+            // Before the expiry, everything works.
+            match admin_password_auth(idms, au, TEST_PASSWORD, before) {
+                AuthState::Success(_) => {}
+                _ => assert!(false),
+            }
 
-            // Check the "name" checking works too (I think admin may hit a common pw rule first)
-            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, "admin_nta", None);
-            let e = idms_prox_write.set_account_password(au, &pce);
-            assert!(e.is_err());
+            // After it, no mechanism is offered.
+            let mut idms_write = idms.write();
+            let admin_init = AuthEvent::named_init("admin");
+            match idms_write.auth(au, &admin_init, after) {
+                Ok(AuthResult {
+                    state: AuthState::Continue(mechs),
+                    ..
+                }) => assert!(mechs.is_empty()),
+                _ => assert!(false),
+            }
 
-            // Check that the demo badlist password is rejected.
-            let pce = PasswordChangeEvent::new_internal(
-                &UUID_ADMIN,
-                "demo_badlist_shohfie3aeci2oobur0aru9uushah6EiPi2woh4hohngoighaiRuepieN3ongoo1",
-                None,
-            );
-            let e = idms_prox_write.set_account_password(au, &pce);
-            assert!(e.is_err());
+            let uuae = UnixUserAuthEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
+            match idms_write.auth_unix(au, &uuae, before) {
+                Ok(Some(_)) => {}
+                _ => assert!(false),
+            }
+            match idms_write.auth_unix(au, &uuae, after) {
+                Ok(None) => {}
+                _ => assert!(false),
+            }
+            assert!(idms_write.commit().is_ok());
 
-            assert!(idms_prox_write.commit(au).is_ok());
+            let mut idms_prox_read = idms.proxy_read();
+            let rate = RadiusAuthTokenEvent::new_internal(UUID_ADMIN.clone());
+            assert!(idms_prox_read
+                .get_radiusauthtoken(au, &rate, before)
+                .is_ok());
+            assert!(idms_prox_read
+                .get_radiusauthtoken(au, &rate, after)
+                .is_err());
+
+            // The unix token still resolves, but carries the window for clients.
+            let uute = UnixUserTokenEvent::new_internal(UUID_ADMIN.clone());
+            let tok = idms_prox_read
+                .get_unixusertoken(au, &uute)
+                .expect("Failed to get unix token");
+            assert!(tok.expire == Some(TEST_CURRENT_TIME as i64));
+            assert!(tok.valid_from.is_none());
         })
     }
 
     #[test]
-    fn test_idm_unixusertoken() {
+    fn test_idm_password_policy() {
         run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
-            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
-            // Modify admin to have posixaccount
+            let long_password = "ntaoeuntnaoeuhraohuercahu😍ntaoeuntnaoeuhr";
+            let ct = duration_from_epoch_now();
+            let mut idms_prox_write = idms.proxy_write(ct);
             let me_posix = unsafe {
                 ModifyEvent::new_internal_invalid(
                     filter!(f_eq("name", PartialValue::new_iutf8s("admin"))),
@@ -1073,253 +3264,502 @@ mod tests {
                 )
             };
             assert!(idms_prox_write.qs_write.modify(au, &me_posix).is_ok());
-            // Add a posix group that has the admin as a member.
-            let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            // Two policies that the admin is subject to, the strictest parts of each apply.
+            let e1: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
                 r#"{
                 "attrs": {
-                    "class": ["object", "group", "posixgroup"],
-                    "name": ["testgroup"],
-                    "uuid": ["01609135-a1c4-43d5-966b-a28227644445"],
-                    "description": ["testgroup"],
-                    "member": ["00000000-0000-0000-0000-000000000000"]
+                    "class": ["object", "group", "password_policy"],
+                    "name": ["pwpolicy_a"],
+                    "uuid": ["94f0a7c4-7fbd-4a5b-8a5f-9a4b6c7f5e01"],
+                    "member": ["00000000-0000-0000-0000-000000000000"],
+                    "pw_min_length": ["30"],
+                    "pw_require_mfa": ["true"]
                 }
             }"#,
             );
-
-            let ce = CreateEvent::new_internal(vec![e.clone()]);
-
+            let e2: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "group", "password_policy"],
+                    "name": ["pwpolicy_b"],
+                    "uuid": ["94f0a7c4-7fbd-4a5b-8a5f-9a4b6c7f5e02"],
+                    "member": ["00000000-0000-0000-0000-000000000000"],
+                    "pw_min_length": ["12"],
+                    "pw_max_age": ["100"]
+                }
+            }"#,
+            );
+            let ce = CreateEvent::new_internal(vec![e1, e2]);
             assert!(idms_prox_write.qs_write.create(au, &ce).is_ok());
 
-            idms_prox_write.commit(au).expect("failed to commit");
-
-            let mut idms_prox_read = idms.proxy_read();
-
-            let ugte = UnixGroupTokenEvent::new_internal(
-                Uuid::parse_str("01609135-a1c4-43d5-966b-a28227644445")
-                    .expect("failed to parse uuid"),
+            // The default length would allow this, but policy a doesn't.
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+            assert!(
+                idms_prox_write.set_account_password(au, &pce)
+                    == Err(OperationError::PasswordTooShort(30))
+            );
+            let pce = UnixPasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
+            assert!(
+                idms_prox_write.set_unix_account_password(au, &pce)
+                    == Err(OperationError::PasswordTooShort(30))
             );
-            let tok_g = idms_prox_read
-                .get_unixgrouptoken(au, &ugte)
-                .expect("Failed to generate unix group token");
 
-            assert!(tok_g.name == "testgroup");
-            assert!(tok_g.spn == "testgroup@example.com");
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, long_password, None);
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+            let pce = UnixPasswordChangeEvent::new_internal(&UUID_ADMIN, long_password);
+            assert!(idms_prox_write.set_unix_account_password(au, &pce).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
 
-            let uute = UnixUserTokenEvent::new_internal(UUID_ADMIN.clone());
-            let tok_r = idms_prox_read
-                .get_unixusertoken(au, &uute)
-                .expect("Failed to generate unix user token");
+            // A password alone doesn't meet the mfa requirement, so nothing is offered.
+            let mut idms_write = idms.write();
+            let admin_init = AuthEvent::named_init("admin");
+            match idms_write.auth(au, &admin_init, ct) {
+                Ok(AuthResult {
+                    state: AuthState::Continue(mechs),
+                    ..
+                }) => assert!(mechs.is_empty()),
+                _ => assert!(false),
+            }
+            assert!(idms_write.commit().is_ok());
 
-            assert!(tok_r.name == "admin");
-            assert!(tok_r.spn == "admin@example.com");
-            assert!(tok_r.groups.len() == 2);
-            assert!(tok_r.groups[0].name == "admin");
-            assert!(tok_r.groups[1].name == "testgroup");
+            // Drop the mfa requirement, and the password works until it's too old.
+            let mut idms_prox_write = idms.proxy_write(ct);
+            let me_nomfa = unsafe {
+                ModifyEvent::new_internal_invalid(
+                    filter!(f_eq("name", PartialValue::new_iutf8s("pwpolicy_a"))),
+                    ModifyList::new_purge("pw_require_mfa"),
+                )
+            };
+            assert!(idms_prox_write.qs_write.modify(au, &me_nomfa).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
 
-            // Show we can get the admin as a unix group token too
-            let ugte = UnixGroupTokenEvent::new_internal(
-                Uuid::parse_str("00000000-0000-0000-0000-000000000000")
-                    .expect("failed to parse uuid"),
-            );
-            let tok_g = idms_prox_read
-                .get_unixgrouptoken(au, &ugte)
-                .expect("Failed to generate unix group token");
+            let before = ct + Duration::from_secs(99);
+            let after = ct + Duration::from_secs(100);
+            match admin_password_auth(idms, au, long_password, before) {
+                AuthState::Success(_) => {}
+                _ => assert!(false),
+            }
 
-            assert!(tok_g.name == "admin");
-            assert!(tok_g.spn == "admin@example.com");
+            let mut idms_write = idms.write();
+            match idms_write.auth(au, &admin_init, after) {
+                Ok(AuthResult {
+                    state: AuthState::Continue(mechs),
+                    ..
+                }) => assert!(mechs.is_empty()),
+                _ => assert!(false),
+            }
+
+            let uuae = UnixUserAuthEvent::new_internal(&UUID_ADMIN, long_password);
+            match idms_write.auth_unix(au, &uuae, before) {
+                Ok(Some(_)) => {}
+                _ => assert!(false),
+            }
+            match idms_write.auth_unix(au, &uuae, after) {
+                Ok(None) => {}
+                _ => assert!(false),
+            }
+            assert!(idms_write.commit().is_ok());
         })
     }
 
     #[test]
-    fn test_idm_simple_unix_password_reset() {
-        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
-            let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
-            // make the admin a valid posix account
-            let me_posix = unsafe {
+    fn test_idm_password_upgrade() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            // An imported pbkdf2 hash is weaker than any crypto policy.
+            let im_pw =
+                "pbkdf2_sha256$36000$xIEozuZVAoYm$uW1b35DUKyhvQAf1mBqMvoBDcqSD06juzyO/nmyV0+w=";
+            let password = "eicieY7ahchaoCh0eeTa";
+            let pw = Password::try_from(im_pw).expect("Failed to parse");
+            let me_inv_m = unsafe {
                 ModifyEvent::new_internal_invalid(
                     filter!(f_eq("name", PartialValue::new_iutf8s("admin"))),
                     ModifyList::new_list(vec![
                         Modify::Present("class".to_string(), Value::new_class("posixaccount")),
                         Modify::Present("gidnumber".to_string(), Value::new_uint32(2001)),
+                        Modify::Present(
+                            "primary_credential".to_string(),
+                            Value::new_credential(
+                                "primary",
+                                Credential::new_from_password(pw.clone()),
+                            ),
+                        ),
+                        Modify::Present(
+                            "unix_password".to_string(),
+                            Value::new_credential("unix", Credential::new_from_password(pw)),
+                        ),
                     ]),
                 )
             };
-            assert!(idms_prox_write.qs_write.modify(au, &me_posix).is_ok());
-
-            let pce = UnixPasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
-
-            assert!(idms_prox_write.set_unix_account_password(au, &pce).is_ok());
-            assert!(idms_prox_write.set_unix_account_password(au, &pce).is_ok());
-            assert!(idms_prox_write.commit(au).is_ok());
+            let mut qs_write = qs.write(duration_from_epoch_now());
+            assert!(qs_write.modify(au, &me_inv_m).is_ok());
+            assert!(qs_write.commit(au).is_ok());
 
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
             let mut idms_write = idms.write();
-            // Check auth verification of the password
-
-            let uuae_good = UnixUserAuthEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD);
-            let a1 = idms_write.auth_unix(au, &uuae_good, Duration::from_secs(TEST_CURRENT_TIME));
-            match a1 {
-                Ok(Some(_tok)) => {}
+            let admin_init = AuthEvent::named_init("admin");
+            let AuthResult { sessionid, .. } = idms_write
+                .auth(au, &admin_init, ct)
+                .expect("Failed to init auth");
+            let pw_step = AuthEvent::cred_step_password(sessionid, password);
+            match idms_write
+                .auth(au, &pw_step, ct)
+                .expect("Failed to auth")
+                .state
+            {
+                AuthState::Success(_) => {}
                 _ => assert!(false),
             };
-            // Check bad password
-            let uuae_bad = UnixUserAuthEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_INC);
-            let a2 = idms_write.auth_unix(au, &uuae_bad, Duration::from_secs(TEST_CURRENT_TIME));
-            match a2 {
-                Ok(None) => {}
+            let uuae = UnixUserAuthEvent::new_internal(&UUID_ADMIN, password);
+            match idms_write.auth_unix(au, &uuae, ct) {
+                Ok(Some(_)) => {}
                 _ => assert!(false),
             };
+            let upgrades = idms_write.take_password_upgrades();
+            assert!(upgrades.len() == 2);
             assert!(idms_write.commit().is_ok());
 
-            // Check deleting the password
             let mut idms_prox_write = idms.proxy_write(duration_from_epoch_now());
-            let me_purge_up = unsafe {
-                ModifyEvent::new_internal_invalid(
-                    filter!(f_eq("name", PartialValue::new_iutf8s("admin"))),
-                    ModifyList::new_list(vec![Modify::Purged("unix_password".to_string())]),
-                )
-            };
-            assert!(idms_prox_write.qs_write.modify(au, &me_purge_up).is_ok());
+            assert!(idms_prox_write.upgrade_passwords(au, &upgrades).is_ok());
             assert!(idms_prox_write.commit(au).is_ok());
 
-            // And auth should now fail due to the lack of PW material
+            // Both credentials are now argon2id, and still accept the password.
+            {
+                let mut qs_read = qs.read();
+                let admin = qs_read
+                    .internal_search_uuid(au, &UUID_ADMIN)
+                    .expect("Failed to find admin");
+                for attr in &["primary_credential", "unix_password"] {
+                    let cred = admin
+                        .get_ava_single_credential(attr)
+                        .expect("No credential");
+                    let pw = cred.password.as_ref().expect("No password");
+                    assert!(!pw.requires_upgrade(&CryptoPolicy::minimum()));
+                    assert!(pw.verify(password));
+                }
+            }
+
+            // And nothing is upgraded a second time.
+            match admin_password_auth(idms, au, password, ct) {
+                AuthState::Success(_) => {}
+                _ => assert!(false),
+            };
             let mut idms_write = idms.write();
-            let a3 = idms_write.auth_unix(au, &uuae_good, Duration::from_secs(TEST_CURRENT_TIME));
-            match a3 {
-                Ok(None) => {}
+            match idms_write.auth_unix(au, &uuae, ct) {
+                Ok(Some(_)) => {}
                 _ => assert!(false),
             };
+            assert!(idms_write.take_password_upgrades().is_empty());
             assert!(idms_write.commit().is_ok());
         })
     }
 
+    fn admin_app_password_auth(
+        idms: &IdmServer,
+        au: &mut AuditScope,
+        appid: &str,
+        pw: &str,
+        ct: Duration,
+    ) -> AuthState {
+        let mut idms_write = idms.write();
+        let admin_init = AuthEvent::named_app_init("admin", appid);
+        let AuthResult { sessionid, state } = idms_write
+            .auth(au, &admin_init, ct)
+            .expect("Failed to init auth");
+        let state = match state {
+            AuthState::Continue(_) => {
+                let pw_step = AuthEvent::cred_step_password(sessionid, pw);
+                // With no app password for this application the session is denied
+                // from the start, and any step is refused.
+                idms_write
+                    .auth(au, &pw_step, ct)
+                    .map(|ar| ar.state)
+                    .unwrap_or_else(|e| AuthState::Denied(format!("{:?}", e)))
+            }
+            s => s,
+        };
+        idms_write.commit().expect("Must not fail");
+        state
+    }
+
     #[test]
-    fn test_idm_totp_registration() {
-        run_idm_test!(|_qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
-            let ct = duration_from_epoch_now();
-            let expire = Duration::from_secs(ct.as_secs() + MFAREG_SESSION_TIMEOUT + 2);
+    fn test_idm_app_password() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
+
             let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let apce = AppPasswordCreateEvent::new_internal(UUID_ADMIN.clone(), "laptop", "imap");
+            let imap_pw = idms_prox_write
+                .generate_app_password(au, &apce)
+                .expect("Failed to create app password");
+            // Labels are unique.
+            let apce = AppPasswordCreateEvent::new_internal(UUID_ADMIN.clone(), "laptop", "caldav");
+            assert!(idms_prox_write.generate_app_password(au, &apce).is_err());
+            let apce = AppPasswordCreateEvent::new_internal(UUID_ADMIN.clone(), "phone", "caldav");
+            let caldav_pw = idms_prox_write
+                .generate_app_password(au, &apce)
+                .expect("Failed to create app password");
+            assert!(idms_prox_write.commit(au).is_ok());
 
-            // verify with no session (fail)
-            let vte1 = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), Uuid::new_v4(), 0);
+            {
+                let mut idms_prox_read = idms.proxy_read();
+                let aple = AppPasswordListEvent::new_internal(UUID_ADMIN.clone());
+                let mut app_pws: Vec<_> = idms_prox_read
+                    .get_app_passwords(au, &aple)
+                    .expect("Failed to list app passwords")
+                    .into_iter()
+                    .map(|ap| (ap.label, ap.application))
+                    .collect();
+                app_pws.sort();
+                assert!(
+                    app_pws
+                        == vec![
+                            ("laptop".to_string(), "imap".to_string()),
+                            ("phone".to_string(), "caldav".to_string()),
+                        ]
+                );
+            }
 
-            match idms_prox_write.verify_account_totp(au, &vte1, ct.clone()) {
-                Err(e) => {
-                    assert!(e == OperationError::InvalidRequestState);
+            // Each is only valid for its application.
+            match admin_app_password_auth(idms, au, "imap", imap_pw.as_str(), ct) {
+                AuthState::Success(uat) => {
+                    assert!(uat.application.map(|a| a.name) == Some("imap".to_string()))
                 }
-                _ => panic!(),
+                _ => assert!(false),
+            };
+            match admin_app_password_auth(idms, au, "imap", caldav_pw.as_str(), ct) {
+                AuthState::Denied(_) => {}
+                _ => assert!(false),
+            };
+            match admin_app_password_auth(idms, au, "imap", TEST_PASSWORD, ct) {
+                AuthState::Denied(_) => {}
+                _ => assert!(false),
+            };
+            // And never for interactive login. The success first clears the softlock
+            // failures from above.
+            match admin_password_auth(idms, au, TEST_PASSWORD, ct) {
+                AuthState::Success(uat) => assert!(uat.application.is_none()),
+                _ => assert!(false),
+            };
+            match admin_password_auth(idms, au, imap_pw.as_str(), ct) {
+                AuthState::Denied(_) => {}
+                _ => assert!(false),
             };
 
-            // reg, expire session, attempt verify (fail)
-            let gte1 = GenerateTOTPEvent::new_internal(UUID_ADMIN.clone());
+            // Once revoked, it's gone.
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let apre = AppPasswordRemoveEvent::new_internal(UUID_ADMIN.clone(), "laptop");
+            assert!(idms_prox_write.remove_app_password(au, &apre).is_ok());
+            assert!(
+                idms_prox_write.remove_app_password(au, &apre)
+                    == Err(OperationError::NoMatchingEntries)
+            );
+            assert!(idms_prox_write.commit(au).is_ok());
 
-            let res = idms_prox_write
-                .generate_account_totp(au, &gte1, ct.clone())
-                .unwrap();
-            let sesid = match res {
-                SetCredentialResponse::TOTPCheck(id, _) => id,
-                _ => panic!("invalid state!"),
+            match admin_app_password_auth(idms, au, "imap", imap_pw.as_str(), ct) {
+                AuthState::Denied(_) => {}
+                _ => assert!(false),
             };
-            idms_prox_write.expire_mfareg_sessions(expire.clone());
+            match admin_app_password_auth(idms, au, "caldav", caldav_pw.as_str(), ct) {
+                AuthState::Success(_) => {}
+                _ => assert!(false),
+            };
+        })
+    }
 
-            let vte2 = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), sesid, 0);
+    #[test]
+    fn test_idm_multiple_credentials() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
 
-            match idms_prox_write.verify_account_totp(au, &vte1, ct.clone()) {
-                Err(e) => {
-                    assert!(e == OperationError::InvalidRequestState);
-                }
-                _ => panic!(),
+            // Setting the password of a new label creates a second credential.
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let pce =
+                PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_INC, Some("Backup"));
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+            // Labels that look like routes are refused.
+            let pce =
+                PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_INC, Some("_reset"));
+            assert!(idms_prox_write.set_account_password(au, &pce).is_err());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let backup_id = {
+                let mut idms_prox_read = idms.proxy_read();
+                let cle = CredentialListEvent::new_internal(UUID_ADMIN.clone());
+                let mut creds = idms_prox_read
+                    .get_credentials(au, &cle)
+                    .expect("Failed to list credentials");
+                creds.sort_by(|a, b| a.label.cmp(&b.label));
+                assert!(creds.len() == 2);
+                assert!(creds[0].label == "backup" && creds[1].label == "primary");
+                assert!(creds.iter().all(|c| c.password && !c.locked));
+                creds[0].id.to_string()
             };
 
-            // == Test TOTP on account with no password (fail)
-            let res = idms_prox_write
-                .generate_account_totp(au, &gte1, ct.clone())
-                .unwrap();
-            let (sesid, tok) = match res {
-                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
-                _ => panic!("invalid state!"),
+            // Either can be used to log in.
+            match admin_password_auth(idms, au, TEST_PASSWORD, ct) {
+                AuthState::Success(_) => {}
+                _ => panic!("primary password auth failed"),
+            };
+            match admin_password_auth(idms, au, TEST_PASSWORD_INC, ct) {
+                AuthState::Success(_) => {}
+                _ => panic!("backup password auth failed"),
             };
-            // get the correct otp
-            let r_tok: TOTP = tok.into();
-            let chal = r_tok
-                .do_totp_duration_from_epoch(&ct)
-                .expect("Failed to do totp?");
-            // attempt the verify
-            let vte3 = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), sesid, chal);
 
-            match idms_prox_write.verify_account_totp(au, &vte3, ct.clone()) {
-                Err(e) => assert!(e == OperationError::InvalidState),
-                _ => panic!(),
+            // One can be locked, but never the last that is unlocked.
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let cle =
+                CredentialLockEvent::new_internal(UUID_ADMIN.clone(), "primary".to_string(), true);
+            assert!(idms_prox_write.lock_credential(au, &cle).is_ok());
+            let cle =
+                CredentialLockEvent::new_internal(UUID_ADMIN.clone(), backup_id.clone(), true);
+            assert!(idms_prox_write.lock_credential(au, &cle).is_err());
+            let cle =
+                CredentialLockEvent::new_internal(UUID_ADMIN.clone(), "primary".to_string(), false);
+            assert!(idms_prox_write.lock_credential(au, &cle).is_ok());
+            let cle =
+                CredentialLockEvent::new_internal(UUID_ADMIN.clone(), backup_id.clone(), true);
+            assert!(idms_prox_write.lock_credential(au, &cle).is_ok());
+            let cle =
+                CredentialLockEvent::new_internal(UUID_ADMIN.clone(), "nothing".to_string(), true);
+            assert!(
+                idms_prox_write.lock_credential(au, &cle) == Err(OperationError::NoMatchingEntries)
+            );
+
+            // Nor can the last unlocked credential be removed, but a locked one can.
+            let cre =
+                CredentialRemoveEvent::new_internal(UUID_ADMIN.clone(), "primary".to_string());
+            assert!(idms_prox_write.remove_credential(au, &cre).is_err());
+            let cre = CredentialRemoveEvent::new_internal(UUID_ADMIN.clone(), backup_id.clone());
+            assert!(idms_prox_write.remove_credential(au, &cre).is_ok());
+            assert!(
+                idms_prox_write.remove_credential(au, &cre)
+                    == Err(OperationError::NoMatchingEntries)
+            );
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            {
+                let mut idms_prox_read = idms.proxy_read();
+                let cle = CredentialListEvent::new_internal(UUID_ADMIN.clone());
+                let creds = idms_prox_read
+                    .get_credentials(au, &cle)
+                    .expect("Failed to list credentials");
+                assert!(creds.len() == 1);
+                assert!(creds[0].label == "primary" && !creds[0].locked);
+            }
+
+            match admin_password_auth(idms, au, TEST_PASSWORD, ct) {
+                AuthState::Success(_) => {}
+                _ => panic!("primary password auth failed"),
             };
+            match admin_password_auth(idms, au, TEST_PASSWORD_INC, ct) {
+                AuthState::Denied(_) => {}
+                _ => panic!("removed credential accepted"),
+            };
+        })
+    }
 
-            // Expire the session to allow it to reset.
-            idms_prox_write.expire_mfareg_sessions(expire.clone());
+    #[test]
+    fn test_idm_password_reuse_across_credentials() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
 
-            // Set a password.
-            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_HISTORY, None);
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+            // A password from the history of another credential can't be set on a new one.
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, Some("backup"));
+            assert!(
+                idms_prox_write.set_account_password(au, &pce)
+                    == Err(OperationError::PasswordReused)
+            );
+            let pce =
+                PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_INC, Some("backup"));
             assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
 
-            // == reg, but change the event source part way in the process (failure)
-            let res = idms_prox_write
-                .generate_account_totp(au, &gte1, ct.clone())
-                .unwrap();
-            let (sesid, tok) = match res {
-                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
-                _ => panic!("invalid state!"),
-            };
-            // get the correct otp
-            let r_tok: TOTP = tok.into();
-            let chal = r_tok
-                .do_totp_duration_from_epoch(&ct)
-                .expect("Failed to do totp?");
-            // attempt the verify
-            let vte3 = VerifyTOTPEvent::new_internal(UUID_ANONYMOUS.clone(), sesid, chal);
+            // Removing a credential keeps its passwords in the history of the primary.
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let cre = CredentialRemoveEvent::new_internal(UUID_ADMIN.clone(), "backup".to_string());
+            assert!(idms_prox_write.remove_credential(au, &cre).is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
 
-            match idms_prox_write.verify_account_totp(au, &vte3, ct.clone()) {
-                Err(e) => assert!(e == OperationError::InvalidRequestState),
-                _ => panic!(),
-            };
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_INC, None);
+            assert!(
+                idms_prox_write.set_account_password(au, &pce)
+                    == Err(OperationError::PasswordReused)
+            );
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD, None);
+            assert!(
+                idms_prox_write.set_account_password(au, &pce)
+                    == Err(OperationError::PasswordReused)
+            );
+            assert!(idms_prox_write.commit(au).is_ok());
+        })
+    }
 
-            // == reg, verify w_ incorrect totp (fail)
-            let res = idms_prox_write
-                .generate_account_totp(au, &gte1, ct.clone())
-                .unwrap();
-            let (_sesid, _tok) = match res {
-                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
-                _ => panic!("invalid state!"),
-            };
+    #[test]
+    fn test_idm_credential_reset_token() {
+        run_idm_test!(|qs: &QueryServer, idms: &IdmServer, au: &mut AuditScope| {
+            init_admin_w_password(au, qs, TEST_PASSWORD).expect("Failed to setup admin account");
+            let ct = Duration::from_secs(TEST_CURRENT_TIME);
 
-            // We can reuse the OTP/Vte2 from before, since we want the invalid otp.
-            match idms_prox_write.verify_account_totp(au, &vte2, ct.clone()) {
-                // On failure we get back another attempt to setup the token.
-                Ok(SetCredentialResponse::TOTPCheck(_id, _tok)) => {}
-                _ => panic!(),
-            };
-            idms_prox_write.expire_mfareg_sessions(expire.clone());
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            let crte = CredentialResetTokenCreateEvent::new_internal(UUID_ADMIN.clone());
+            let token = idms_prox_write
+                .create_credential_reset_token(au, &crte)
+                .expect("Failed to create reset token");
+            assert!(idms_prox_write.commit(au).is_ok());
 
-            // Turn the pts into an otp
-            // == reg, verify w_ correct totp (success)
-            let res = idms_prox_write
-                .generate_account_totp(au, &gte1, ct.clone())
-                .unwrap();
-            let (sesid, tok) = match res {
-                SetCredentialResponse::TOTPCheck(id, tok) => (id, tok),
-                _ => panic!("invalid state!"),
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            // Only the token that was issued is accepted ...
+            match idms_prox_write.verify_credential_reset_token(au, &UUID_ADMIN, "abcd-efgh", &ct) {
+                Err(OperationError::AccessDenied) => {}
+                _ => panic!("invalid reset token accepted"),
             };
-            // We can't reuse the OTP/Vte from before, since the token seed changes
-            let r_tok: TOTP = tok.into();
-            let chal = r_tok
-                .do_totp_duration_from_epoch(&ct)
-                .expect("Failed to do totp?");
-            // attempt the verify
-            let vte3 = VerifyTOTPEvent::new_internal(UUID_ADMIN.clone(), sesid, chal);
-
-            match idms_prox_write.verify_account_totp(au, &vte3, ct.clone()) {
-                Ok(SetCredentialResponse::Success) => {}
-                _ => panic!(),
+            let uat = idms_prox_write
+                .verify_credential_reset_token(au, &UUID_ADMIN, token.as_str(), &ct)
+                .expect("Reset token denied");
+            assert!(uat.name == "admin");
+            // ... and only until it expires.
+            let expired = ct + Duration::from_secs(CREDENTIAL_RESET_TOKEN_VALIDITY);
+            match idms_prox_write.verify_credential_reset_token(
+                au,
+                &UUID_ADMIN,
+                token.as_str(),
+                &expired,
+            ) {
+                Err(OperationError::AccessDenied) => {}
+                _ => panic!("expired reset token accepted"),
             };
-            idms_prox_write.expire_mfareg_sessions(expire.clone());
 
+            // Once the credential is changed, the token is used up.
+            let pce = PasswordChangeEvent::new_internal(&UUID_ADMIN, TEST_PASSWORD_INC, None);
+            assert!(idms_prox_write.set_account_password(au, &pce).is_ok());
+            assert!(idms_prox_write
+                .remove_credential_reset_token(au, &UUID_ADMIN)
+                .is_ok());
+            assert!(idms_prox_write.commit(au).is_ok());
+
+            let mut idms_prox_write = idms.proxy_write(ct.clone());
+            match idms_prox_write.verify_credential_reset_token(
+                au,
+                &UUID_ADMIN,
+                token.as_str(),
+                &ct,
+            ) {
+                Err(OperationError::AccessDenied) => {}
+                _ => panic!("used reset token accepted"),
+            };
             assert!(idms_prox_write.commit(au).is_ok());
+
+            match admin_password_auth(idms, au, TEST_PASSWORD_INC, ct) {
+                AuthState::Success(_) => {}
+                _ => panic!("password auth failed"),
+            }
         })
     }
 }
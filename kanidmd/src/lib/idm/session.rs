@@ -0,0 +1,57 @@
+use crate::be::dbvalue::DbSessionV1;
+use kanidm_proto::v1::SessionInfo;
+use uuid::Uuid;
+
+/// A record of a bearer token that was issued to an account. These are stored on the
+/// account, and a token is only accepted while its session is present, so removing the
+/// session revokes the token.
+#[derive(Debug, Clone)]
+pub struct Session {
+    // The id of the auth session that issued the token.
+    pub id: Uuid,
+    // Seconds since the unix epoch.
+    pub issued: u64,
+    pub expiry: u64,
+    // The address the authentication came from, if known.
+    pub source: Option<String>,
+    // How the account authenticated, such as "password" or "webauthn".
+    pub method: String,
+}
+
+impl From<DbSessionV1> for Session {
+    fn from(value: DbSessionV1) -> Self {
+        Session {
+            id: value.u,
+            issued: value.i,
+            expiry: value.e,
+            source: value.s,
+            method: value.m,
+        }
+    }
+}
+
+impl Session {
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expiry <= now
+    }
+
+    pub(crate) fn to_dbsessionv1(&self) -> DbSessionV1 {
+        DbSessionV1 {
+            u: self.id,
+            i: self.issued,
+            e: self.expiry,
+            s: self.source.clone(),
+            m: self.method.clone(),
+        }
+    }
+
+    pub fn to_proto(&self) -> SessionInfo {
+        SessionInfo {
+            id: self.id,
+            issued: self.issued,
+            expiry: self.expiry,
+            source: self.source.clone(),
+            method: self.method.clone(),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::constants::{
+    SOFTLOCK_BASE_DELAY, SOFTLOCK_FAILURE_THRESHOLD, SOFTLOCK_MAX_DELAY, SOFTLOCK_RESET_WINDOW,
+};
+use std::time::Duration;
+
+// This is the message returned to clients in AuthState::Denied, so that a locked account
+// can be told apart from a bad credential.
+pub(crate) const ACCOUNT_SOFTLOCKED_MSG: &str =
+    "account temporarily locked due to repeated authentication failures";
+
+/// Tracks the authentication failures of an account on this server. These are in memory
+/// only, and are never replicated, so each server applies its own backoff.
+///
+/// Once SOFTLOCK_FAILURE_THRESHOLD failures have occured, every further failure locks the
+/// account for twice as long as the previous one, up to SOFTLOCK_MAX_DELAY. If no failure
+/// is seen for SOFTLOCK_RESET_WINDOW the count is forgotten.
+#[derive(Clone, Debug)]
+pub(crate) struct SoftLock {
+    failures: u32,
+    last_failure: Duration,
+    unlock_at: Duration,
+}
+
+impl SoftLock {
+    pub fn new() -> Self {
+        SoftLock {
+            failures: 0,
+            last_failure: Duration::from_secs(0),
+            unlock_at: Duration::from_secs(0),
+        }
+    }
+
+    pub fn is_locked(&self, ct: &Duration) -> bool {
+        ct < &self.unlock_at
+    }
+
+    /// True when this lock no longer affects anything, and can be discarded.
+    pub fn is_expired(&self, ct: &Duration) -> bool {
+        !self.is_locked(ct) && *ct >= self.last_failure + Duration::from_secs(SOFTLOCK_RESET_WINDOW)
+    }
+
+    pub fn record_failure(&mut self, ct: &Duration) {
+        if self.is_expired(ct) {
+            self.failures = 0;
+        }
+        self.failures += 1;
+        self.last_failure = *ct;
+
+        if self.failures >= SOFTLOCK_FAILURE_THRESHOLD {
+            // Cap the shift so that a very determined attacker can't overflow it.
+            let exp = (self.failures - SOFTLOCK_FAILURE_THRESHOLD).min(16);
+            let delay = (SOFTLOCK_BASE_DELAY << exp).min(SOFTLOCK_MAX_DELAY);
+            self.unlock_at = *ct + Duration::from_secs(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{
+        SOFTLOCK_BASE_DELAY, SOFTLOCK_FAILURE_THRESHOLD, SOFTLOCK_MAX_DELAY, SOFTLOCK_RESET_WINDOW,
+    };
+    use crate::idm::softlock::SoftLock;
+    use std::time::Duration;
+
+    #[test]
+    fn test_idm_softlock_backoff() {
+        let mut sl = SoftLock::new();
+        let ct = Duration::from_secs(1000);
+
+        // Below the threshold, nothing is locked.
+        for _ in 1..SOFTLOCK_FAILURE_THRESHOLD {
+            sl.record_failure(&ct);
+            assert!(!sl.is_locked(&ct));
+        }
+
+        // At the threshold we lock for the base delay.
+        sl.record_failure(&ct);
+        assert!(sl.is_locked(&ct));
+        assert!(sl.is_locked(&(ct + Duration::from_secs(SOFTLOCK_BASE_DELAY - 1))));
+        assert!(!sl.is_locked(&(ct + Duration::from_secs(SOFTLOCK_BASE_DELAY))));
+
+        // The next failure doubles it.
+        let ct = ct + Duration::from_secs(SOFTLOCK_BASE_DELAY);
+        sl.record_failure(&ct);
+        assert!(sl.is_locked(&(ct + Duration::from_secs(SOFTLOCK_BASE_DELAY * 2 - 1))));
+        assert!(!sl.is_locked(&(ct + Duration::from_secs(SOFTLOCK_BASE_DELAY * 2))));
+
+        // And it never exceeds the max.
+        for _ in 0..64 {
+            sl.record_failure(&ct);
+        }
+        assert!(sl.is_locked(&(ct + Duration::from_secs(SOFTLOCK_MAX_DELAY - 1))));
+        assert!(!sl.is_locked(&(ct + Duration::from_secs(SOFTLOCK_MAX_DELAY))));
+
+        // After the reset window the count starts again.
+        let ct = ct + Duration::from_secs(SOFTLOCK_MAX_DELAY + SOFTLOCK_RESET_WINDOW);
+        assert!(sl.is_expired(&ct));
+        sl.record_failure(&ct);
+        assert!(!sl.is_locked(&ct));
+    }
+}
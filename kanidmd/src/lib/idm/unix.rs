@@ -2,8 +2,10 @@ use uuid::Uuid;
 
 use crate::audit::AuditScope;
 use crate::constants::UUID_ANONYMOUS;
-use crate::credential::Credential;
+use crate::credential::{Credential, CryptoPolicy, Password};
 use crate::entry::{Entry, EntryCommitted, EntryReduced, EntrySealed};
+use crate::idm::account::is_within_valid_time;
+use crate::idm::pwpolicy::PasswordPolicy;
 use crate::modify::{ModifyInvalid, ModifyList};
 use crate::server::{
     QueryServerReadTransaction, QueryServerTransaction, QueryServerWriteTransaction,
@@ -13,6 +15,7 @@ use kanidm_proto::v1::OperationError;
 use kanidm_proto::v1::{UnixGroupToken, UnixUserToken};
 
 use std::iter;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub(crate) struct UnixUserAccount {
@@ -25,6 +28,8 @@ pub(crate) struct UnixUserAccount {
     pub sshkeys: Vec<String>,
     pub groups: Vec<UnixGroup>,
     cred: Option<Credential>,
+    pub valid_from: Option<Duration>,
+    pub expire: Option<Duration>,
 }
 
 lazy_static! {
@@ -77,6 +82,10 @@ macro_rules! try_from_entry {
             .get_ava_single_credential("unix_password")
             .map(|v| v.clone());
 
+        let valid_from = $value.get_ava_single_duration("account_valid_from");
+
+        let expire = $value.get_ava_single_duration("account_expire");
+
         Ok(UnixUserAccount {
             name,
             spn,
@@ -87,6 +96,8 @@ macro_rules! try_from_entry {
             sshkeys,
             groups: $groups,
             cred,
+            valid_from,
+            expire,
         })
     }};
 }
@@ -132,6 +143,10 @@ impl UnixUserAccount {
             shell: self.shell.clone(),
             groups,
             sshkeys: self.sshkeys.clone(),
+            // These are sent rather than checked here, so that clients caching the
+            // token can still enforce them while offline.
+            valid_from: self.valid_from.map(|t| t.as_secs() as i64),
+            expire: self.expire.map(|t| t.as_secs() as i64),
         })
     }
 
@@ -139,15 +154,69 @@ impl UnixUserAccount {
         self.uuid == *UUID_ANONYMOUS
     }
 
+    pub fn is_within_valid_time(&self, ct: &Duration) -> bool {
+        is_within_valid_time(self.valid_from, self.expire, ct)
+    }
+
+    pub(crate) fn is_password_expired(&self, pwpolicy: &PasswordPolicy, ct: &Duration) -> bool {
+        self.cred
+            .as_ref()
+            .map(|cred| pwpolicy.is_password_expired(cred.password_set, ct))
+            .unwrap_or(false)
+    }
+
+    pub(crate) fn is_password_reused(&self, cleartext: &str) -> bool {
+        self.cred
+            .as_ref()
+            .map(|cred| cred.is_password_reused(cleartext))
+            .unwrap_or(false)
+    }
+
     pub(crate) fn gen_password_mod(
         &self,
         cleartext: &str,
+        ct: &Duration,
+        crypto_policy: &CryptoPolicy,
     ) -> Result<ModifyList<ModifyInvalid>, OperationError> {
-        let ncred = Credential::new_password_only(cleartext);
+        // Keep the existing credential, so that its password history carries over.
+        let ncred = match &self.cred {
+            Some(cred) => cred.set_password(crypto_policy, cleartext),
+            None => Credential::new_password_only(crypto_policy, cleartext),
+        }
+        .update_password_set(ct);
         let vcred = Value::new_credential("unix", ncred);
         Ok(ModifyList::new_purge_and_set("unix_password", vcred))
     }
 
+    /// After the unix password was verified as `cleartext`, a stronger hash of it if the
+    /// stored one is weaker than the policy. This is the hash that was verified, and its
+    /// replacement.
+    pub(crate) fn password_upgrade(
+        &self,
+        cleartext: &str,
+        crypto_policy: &CryptoPolicy,
+    ) -> Option<(Password, Password)> {
+        self.cred
+            .as_ref()
+            .and_then(|cred| cred.password.as_ref())
+            .filter(|pw| pw.requires_upgrade(crypto_policy))
+            .map(|pw| (pw.clone(), Password::new(crypto_policy, cleartext)))
+    }
+
+    pub(crate) fn gen_password_upgrade_mod(
+        &self,
+        old: &Password,
+        new: Password,
+    ) -> Option<ModifyList<ModifyInvalid>> {
+        self.cred
+            .as_ref()
+            .and_then(|cred| cred.upgrade_password(old, new))
+            .map(|ncred| {
+                let vcred = Value::new_credential("unix", ncred);
+                ModifyList::new_purge_and_set("unix_password", vcred)
+            })
+    }
+
     pub(crate) fn verify_unix_credential(
         &self,
         _au: &mut AuditScope,
@@ -3,16 +3,23 @@ use std::time::Duration;
 
 use crate::actors::v1_write::QueryServerWriteV1;
 use crate::constants::PURGE_FREQUENCY;
-use crate::event::{PurgeRecycledEvent, PurgeTombstoneEvent};
+use crate::event::{
+    PurgeExpiredAcpEvent, PurgeRecycledEvent, PurgeTombstoneEvent, RotateJwsKeysEvent,
+};
 
 pub struct IntervalActor {
     // Store any addresses we require
     server: actix::Addr<QueryServerWriteV1>,
+    // Should acps past their acp_valid_until be moved to the recycle bin?
+    purge_expired_acp: bool,
 }
 
 impl IntervalActor {
-    pub fn new(server: actix::Addr<QueryServerWriteV1>) -> Self {
-        IntervalActor { server }
+    pub fn new(server: actix::Addr<QueryServerWriteV1>, purge_expired_acp: bool) -> Self {
+        IntervalActor {
+            server,
+            purge_expired_acp,
+        }
     }
 
     // Define new events here
@@ -26,6 +33,16 @@ impl IntervalActor {
         let pe = PurgeRecycledEvent::new();
         self.server.do_send(pe)
     }
+
+    fn purge_expired_acps(&mut self) {
+        let pe = PurgeExpiredAcpEvent::new();
+        self.server.do_send(pe)
+    }
+
+    fn rotate_jws_keys(&mut self) {
+        let re = RotateJwsKeysEvent::new();
+        self.server.do_send(re)
+    }
 }
 
 impl Actor for IntervalActor {
@@ -39,5 +56,13 @@ impl Actor for IntervalActor {
         ctx.run_interval(Duration::from_secs(PURGE_FREQUENCY), move |act, _ctx| {
             act.purge_tombstones();
         });
+        ctx.run_interval(Duration::from_secs(PURGE_FREQUENCY), move |act, _ctx| {
+            act.rotate_jws_keys();
+        });
+        if self.purge_expired_acp {
+            ctx.run_interval(Duration::from_secs(PURGE_FREQUENCY), move |act, _ctx| {
+                act.purge_expired_acps();
+            });
+        }
     }
 }
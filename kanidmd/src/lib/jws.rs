@@ -0,0 +1,292 @@
+use crate::be::dbvalue::DbJwsKeyV1;
+use crate::constants::{BEARER_TOKEN_VALIDITY, JWS_KEY_ROTATION};
+use kanidm_proto::v1::UserAuthToken;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use rand::prelude::*;
+use std::fmt;
+use std::time::Duration;
+use uuid::Uuid;
+
+// 256 bits, the output size of the hmac, as rfc7518 section 3.2 requires.
+const KEY_SIZE_BYTES: usize = 32;
+const JWS_ALG: &str = "HS256";
+const JWS_TYP: &str = "JWT";
+
+/// A key that bearer tokens are signed with. These are stored on the domain info entry
+/// so that tokens remain valid across a restart of the server.
+#[derive(Clone)]
+pub struct JwsKey {
+    kid: Uuid,
+    // Seconds since the unix epoch that this key was created.
+    issued: u64,
+    key: Vec<u8>,
+}
+
+// Entries are logged, so only ever show the key id.
+impl fmt::Debug for JwsKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "JwsKey {{ kid: {}, issued: {} }}", self.kid, self.issued)
+    }
+}
+
+impl From<DbJwsKeyV1> for JwsKey {
+    fn from(value: DbJwsKeyV1) -> Self {
+        JwsKey {
+            kid: value.k,
+            issued: value.i,
+            key: value.d,
+        }
+    }
+}
+
+impl JwsKey {
+    pub fn generate(ct: &Duration) -> Self {
+        let mut rng = StdRng::from_entropy();
+        let mut key = vec![0; KEY_SIZE_BYTES];
+        rng.fill(key.as_mut_slice());
+        JwsKey {
+            kid: Uuid::new_v4(),
+            issued: ct.as_secs(),
+            key,
+        }
+    }
+
+    pub fn kid(&self) -> &Uuid {
+        &self.kid
+    }
+
+    pub(crate) fn to_dbjwskeyv1(&self) -> DbJwsKeyV1 {
+        DbJwsKeyV1 {
+            k: self.kid,
+            i: self.issued,
+            d: self.key.clone(),
+        }
+    }
+
+    fn hmac(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let key = PKey::hmac(self.key.as_slice()).ok()?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key).ok()?;
+        signer.update(data).ok()?;
+        signer.sign_to_vec().ok()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JwsHeader {
+    alg: String,
+    typ: String,
+    kid: Uuid,
+}
+
+/// The content of a bearer token. The user auth token is carried in the token so that
+/// it can be validated without any server side session state.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BearerClaims {
+    pub iss: String,
+    // The authentication session this token was issued by.
+    pub sid: Uuid,
+    pub iat: u64,
+    pub exp: u64,
+    pub uat: UserAuthToken,
+}
+
+impl BearerClaims {
+    pub fn new(issuer: &str, sid: Uuid, uat: UserAuthToken, ct: &Duration) -> Self {
+        BearerClaims {
+            iss: issuer.to_string(),
+            sid,
+            iat: ct.as_secs(),
+            exp: ct.as_secs() + BEARER_TOKEN_VALIDITY,
+            uat,
+        }
+    }
+}
+
+fn b64_encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn b64_decode(data: &str) -> Option<Vec<u8>> {
+    base64::decode_config(data, base64::URL_SAFE_NO_PAD).ok()
+}
+
+/// The set of keys that are currently trusted to have signed a bearer token, oldest
+/// first. Only the newest key signs new tokens.
+#[derive(Debug, Clone, Default)]
+pub struct JwsKeySet {
+    keys: Vec<JwsKey>,
+}
+
+impl JwsKeySet {
+    pub fn new(mut keys: Vec<JwsKey>) -> Self {
+        keys.sort_by_key(|k| k.issued);
+        JwsKeySet { keys }
+    }
+
+    pub fn keys(&self) -> &[JwsKey] {
+        self.keys.as_slice()
+    }
+
+    /// Add a new signing key if there is none, or the newest is due for rotation, and
+    /// remove keys that can no longer have signed a token that is still valid. Returns
+    /// true if the set was changed and must be saved.
+    pub fn rotate(&mut self, ct: &Duration) -> bool {
+        let now = ct.as_secs();
+        let mut changed = match self.keys.last() {
+            Some(k) => k.issued + JWS_KEY_ROTATION <= now,
+            None => true,
+        };
+        if changed {
+            self.keys.push(JwsKey::generate(ct));
+        }
+
+        // A key stopped signing tokens when its successor was created, so once the
+        // successor is older than the token validity nothing signed by it can be valid.
+        let expired = self
+            .keys
+            .windows(2)
+            .take_while(|w| w[1].issued + BEARER_TOKEN_VALIDITY <= now)
+            .count();
+        if expired > 0 {
+            self.keys = self.keys.split_off(expired);
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn sign(&self, claims: &BearerClaims) -> Option<String> {
+        let key = self.keys.last()?;
+        let header = JwsHeader {
+            alg: JWS_ALG.to_string(),
+            typ: JWS_TYP.to_string(),
+            kid: key.kid,
+        };
+        let header = serde_json::to_vec(&header).ok()?;
+        let payload = serde_json::to_vec(claims).ok()?;
+        let signing_input = format!("{}.{}", b64_encode(&header), b64_encode(&payload));
+        let sig = key.hmac(signing_input.as_bytes())?;
+        Some(format!("{}.{}", signing_input, b64_encode(&sig)))
+    }
+
+    /// Check the signature of a token and that it is for this issuer and not yet
+    /// expired. Any failure is None, as the caller can't do anything more with it.
+    pub fn verify(&self, token: &str, issuer: &str, ct: &Duration) -> Option<BearerClaims> {
+        let mut parts = token.rsplitn(2, '.');
+        let sig = b64_decode(parts.next()?)?;
+        let signing_input = parts.next()?;
+        let mut input_parts = signing_input.splitn(2, '.');
+        let header: JwsHeader = serde_json::from_slice(&b64_decode(input_parts.next()?)?).ok()?;
+        let payload = input_parts.next()?;
+
+        if header.alg != JWS_ALG {
+            return None;
+        }
+        let key = self.keys.iter().find(|k| k.kid == header.kid)?;
+        let expect = key.hmac(signing_input.as_bytes())?;
+        if expect.len() != sig.len() || !memcmp::eq(&expect, &sig) {
+            return None;
+        }
+
+        let claims: BearerClaims = serde_json::from_slice(&b64_decode(payload)?).ok()?;
+        if claims.iss != issuer || claims.exp <= ct.as_secs() {
+            return None;
+        }
+        Some(claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{BEARER_TOKEN_VALIDITY, JWS_KEY_ROTATION};
+    use crate::jws::{BearerClaims, JwsKeySet};
+    use kanidm_proto::v1::UserAuthToken;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    const ISSUER: &str = "https://idm.example.com";
+
+    fn test_uat() -> UserAuthToken {
+        UserAuthToken {
+            name: "testperson".to_string(),
+            displayname: "Test Person".to_string(),
+            uuid: "cc8e95b4-c24f-4d68-ba54-8bed76f63930".to_string(),
+            application: None,
+            groups: vec![],
+            claims: vec![],
+            warnings: vec![],
+        }
+    }
+
+    #[test]
+    fn test_jws_sign_verify() {
+        let ct = Duration::from_secs(10);
+        let mut ks = JwsKeySet::default();
+        assert!(ks.rotate(&ct));
+        // Nothing to do until the key is due for rotation.
+        assert!(!ks.rotate(&ct));
+
+        let sid = Uuid::new_v4();
+        let token = ks
+            .sign(&BearerClaims::new(ISSUER, sid, test_uat(), &ct))
+            .expect("failed to sign");
+        let claims = ks.verify(&token, ISSUER, &ct).expect("failed to verify");
+        assert!(claims.sid == sid);
+        assert!(claims.uat.name == "testperson");
+
+        // Wrong issuer
+        assert!(ks
+            .verify(&token, "https://other.example.com", &ct)
+            .is_none());
+        // Expired
+        let exp = Duration::from_secs(10 + BEARER_TOKEN_VALIDITY);
+        assert!(ks.verify(&token, ISSUER, &exp).is_none());
+        // A different key set didn't sign it.
+        let mut ks2 = JwsKeySet::default();
+        ks2.rotate(&ct);
+        assert!(ks2.verify(&token, ISSUER, &ct).is_none());
+        // Tampering with the payload breaks the signature.
+        let mut parts: Vec<&str> = token.split('.').collect();
+        let other = ks
+            .sign(&BearerClaims::new(ISSUER, Uuid::new_v4(), test_uat(), &ct))
+            .expect("failed to sign");
+        let other_payload = other.split('.').nth(1).expect("no payload");
+        parts[1] = other_payload;
+        assert!(ks.verify(&parts.join("."), ISSUER, &ct).is_none());
+        // And garbage is just rejected.
+        assert!(ks.verify("not.a.token", ISSUER, &ct).is_none());
+        assert!(ks.verify("", ISSUER, &ct).is_none());
+    }
+
+    #[test]
+    fn test_jws_key_rotation() {
+        let ct = Duration::from_secs(10);
+        let mut ks = JwsKeySet::default();
+        ks.rotate(&ct);
+        let st = Duration::from_secs(10 + JWS_KEY_ROTATION - 60);
+        let old = ks
+            .sign(&BearerClaims::new(ISSUER, Uuid::new_v4(), test_uat(), &st))
+            .expect("failed to sign");
+
+        // A new key signs from now, but the old token is still accepted.
+        let rt = Duration::from_secs(10 + JWS_KEY_ROTATION);
+        assert!(ks.rotate(&rt));
+        assert!(ks.keys().len() == 2);
+        assert!(ks.verify(&old, ISSUER, &rt).is_some());
+        let new = ks
+            .sign(&BearerClaims::new(ISSUER, Uuid::new_v4(), test_uat(), &rt))
+            .expect("failed to sign");
+        assert!(old.split('.').next() != new.split('.').next());
+        assert!(ks.verify(&new, ISSUER, &rt).is_some());
+
+        // Once nothing the old key signed can be valid, it's removed.
+        let pt = Duration::from_secs(10 + JWS_KEY_ROTATION + BEARER_TOKEN_VALIDITY);
+        assert!(ks.rotate(&pt));
+        assert!(ks.keys().len() == 1);
+        assert!(ks
+            .verify(&new, ISSUER, &(pt - Duration::from_secs(1)))
+            .is_some());
+    }
+}
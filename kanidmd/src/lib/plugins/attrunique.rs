@@ -3,12 +3,16 @@
 // matter a lot when it comes to replication based on first-wins or
 // both change approaches.
 //
+// As well as single attributes flagged unique, schema may define unique
+// constraints over a combination of attributes, optionally limited to the
+// entries matching a scope filter.
 //
 use crate::audit::AuditScope;
-use crate::entry::{Entry, EntryCommitted, EntryInvalid, EntryNew};
-use crate::event::{CreateEvent, ModifyEvent};
+use crate::entry::{Entry, EntryCommitted, EntryInvalid, EntryNew, EntrySealed};
+use crate::event::{CreateEvent, Event, ModifyEvent};
+use crate::filter::{Filter, FilterValidResolved};
 use crate::plugins::Plugin;
-use crate::schema::SchemaTransaction;
+use crate::schema::{SchemaTransaction, SchemaUniqueConstraint};
 use crate::server::{
     QueryServerReadTransaction, QueryServerTransaction, QueryServerWriteTransaction,
 };
@@ -17,6 +21,10 @@ use kanidm_proto::v1::{ConsistencyError, OperationError, PluginError};
 
 use std::collections::BTreeMap;
 
+lazy_static! {
+    static ref PVCLASS_UNIQUECONSTRAINT: PartialValue = PartialValue::new_class("uniqueconstraint");
+}
+
 pub struct AttrUnique;
 
 fn get_cand_attr_set<VALID, STATE>(
@@ -119,6 +127,177 @@ fn enforce_unique<STATE>(
     Ok(())
 }
 
+// Each combination of values across the constraint attributes is a key. An entry
+// missing any one of the attributes has no keys, and so is unconstrained.
+fn get_constraint_keys<VALID, STATE>(
+    e: &Entry<VALID, STATE>,
+    attrs: &[String],
+) -> Vec<Vec<PartialValue>> {
+    attrs
+        .iter()
+        .fold(vec![Vec::new()], |keys, attr| match e.get_ava(attr) {
+            Some(vs) => keys
+                .iter()
+                .flat_map(|k| {
+                    vs.iter().map(move |v| {
+                        let mut nk = k.clone();
+                        nk.push(v.to_partialvalue());
+                        nk
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        })
+}
+
+fn resolve_constraint_scope(
+    uc: &SchemaUniqueConstraint,
+) -> Result<Option<Filter<FilterValidResolved>>, OperationError> {
+    match &uc.scope {
+        Some(f) => f.resolve(&Event::from_internal(), None).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn get_cand_constraint_set<VALID, STATE>(
+    au: &mut AuditScope,
+    cand: &[Entry<VALID, STATE>],
+    uc: &SchemaUniqueConstraint,
+    scope: Option<&Filter<FilterValidResolved>>,
+) -> Result<BTreeMap<Vec<PartialValue>, PartialValue>, OperationError> {
+    let mut cand_keys: BTreeMap<Vec<PartialValue>, PartialValue> = BTreeMap::new();
+
+    for e in cand.iter() {
+        // Entries outside the scope are not bound by this constraint.
+        if let Some(f) = scope {
+            if !e.entry_match_no_index(f) {
+                continue;
+            }
+        }
+
+        let uuid = match e.get_ava_single("uuid") {
+            Some(v) => v.to_partialvalue(),
+            None => {
+                return Err(OperationError::InvalidEntryState);
+            }
+        };
+
+        for k in get_constraint_keys(e, uc.attrs.as_slice()) {
+            if let Some(vr) = cand_keys.insert(k, uuid.clone()) {
+                audit_log!(
+                    au,
+                    "constraint values already exist -> {:?}: {:?} on {:?}",
+                    uc.name,
+                    vr,
+                    uuid
+                );
+                return Err(OperationError::Plugin(PluginError::AttrUnique(format!(
+                    "values already exist for unique constraint {}",
+                    uc.name
+                ))));
+            }
+        }
+    }
+
+    Ok(cand_keys)
+}
+
+fn enforce_unique_constraint<STATE>(
+    au: &mut AuditScope,
+    qs: &mut QueryServerWriteTransaction,
+    cand: &[Entry<EntryInvalid, STATE>],
+    uc: &SchemaUniqueConstraint,
+) -> Result<(), OperationError> {
+    debug!("{:?}", uc.name);
+
+    let scope = try_audit!(au, resolve_constraint_scope(uc));
+
+    // Build a set of all the value combinations -> uuid for the cands.
+    let cand_keys = try_audit!(au, get_cand_constraint_set(au, cand, uc, scope.as_ref()));
+
+    // No candidates to check!
+    if cand_keys.is_empty() {
+        return Ok(());
+    }
+
+    // Or of each combination of values, excluding the entry itself.
+    let filt_in = filter!(f_or(
+        cand_keys
+            .into_iter()
+            .map(|(k, uuid)| {
+                let mut terms: Vec<_> = uc
+                    .attrs
+                    .iter()
+                    .zip(k.into_iter())
+                    .map(|(a, v)| FC::Eq(a.as_str(), v))
+                    .collect();
+                terms.push(f_andnot(FC::Eq("uuid", uuid)));
+                f_and(terms)
+            })
+            .collect()
+    ));
+
+    debug!("{:?}", filt_in);
+
+    let conflict_cand = try_audit!(au, qs.internal_search(au, filt_in));
+
+    // Only entries within the scope can conflict.
+    let conflict = conflict_cand.iter().any(|e| match &scope {
+        Some(f) => e.entry_match_no_index(f),
+        None => true,
+    });
+
+    if conflict {
+        return Err(OperationError::Plugin(PluginError::AttrUnique(format!(
+            "duplicate values detected for unique constraint {}",
+            uc.name
+        ))));
+    }
+
+    Ok(())
+}
+
+fn enforce_unique_constraints<STATE>(
+    au: &mut AuditScope,
+    qs: &mut QueryServerWriteTransaction,
+    cand: &[Entry<EntryInvalid, STATE>],
+) -> Result<(), OperationError> {
+    let constraints: Vec<SchemaUniqueConstraint> = {
+        let schema = qs.get_schema();
+        schema.get_uniqueconstraints().values().cloned().collect()
+    };
+
+    constraints
+        .iter()
+        .try_for_each(|uc| enforce_unique_constraint(au, qs, cand, uc))
+}
+
+// A constraint that is created or changed must already hold for the entries in its
+// scope. This runs once the candidates are written, so entries created or changed
+// alongside the constraint are checked too.
+fn check_new_constraints(
+    au: &mut AuditScope,
+    qs: &mut QueryServerWriteTransaction,
+    cand: &[Entry<EntrySealed, EntryCommitted>],
+) -> Result<(), OperationError> {
+    let constraints: Vec<SchemaUniqueConstraint> = cand
+        .iter()
+        .filter(|e| e.attribute_value_pres("class", &PVCLASS_UNIQUECONSTRAINT))
+        .map(|e| SchemaUniqueConstraint::try_from(au, qs, e))
+        .collect::<Result<_, _>>()?;
+
+    if constraints.is_empty() {
+        return Ok(());
+    }
+
+    let all_cand = try_audit!(au, qs.internal_search(au, filter!(f_pres("class"))));
+
+    constraints.iter().try_for_each(|uc| {
+        let scope = resolve_constraint_scope(uc)?;
+        get_cand_constraint_set(au, &all_cand, uc, scope.as_ref()).map(|_| ())
+    })
+}
+
 impl Plugin for AttrUnique {
     fn id() -> &'static str {
         "plugin_attrunique"
@@ -140,7 +319,9 @@ impl Plugin for AttrUnique {
             .iter()
             .map(|attr| enforce_unique(au, qs, cand, attr.as_str()))
             .collect();
-        r
+        r?;
+
+        enforce_unique_constraints(au, qs, cand)
     }
 
     fn pre_modify(
@@ -159,7 +340,28 @@ impl Plugin for AttrUnique {
             .iter()
             .map(|attr| enforce_unique(au, qs, cand, attr.as_str()))
             .collect();
-        r
+        r?;
+
+        enforce_unique_constraints(au, qs, cand)
+    }
+
+    fn post_create(
+        au: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        cand: &[Entry<EntrySealed, EntryCommitted>],
+        _ce: &CreateEvent,
+    ) -> Result<(), OperationError> {
+        check_new_constraints(au, qs, cand)
+    }
+
+    fn post_modify(
+        au: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        _pre_cand: &[Entry<EntrySealed, EntryCommitted>],
+        cand: &[Entry<EntrySealed, EntryCommitted>],
+        _me: &ModifyEvent,
+    ) -> Result<(), OperationError> {
+        check_new_constraints(au, qs, cand)
     }
 
     fn verify(
@@ -193,6 +395,21 @@ impl Plugin for AttrUnique {
             }
         }
 
+        let constraints: Vec<SchemaUniqueConstraint> = {
+            let schema = qs.get_schema();
+            schema.get_uniqueconstraints().values().cloned().collect()
+        };
+
+        for uc in constraints.iter() {
+            let dup = resolve_constraint_scope(uc)
+                .and_then(|scope| get_cand_constraint_set(au, &all_cand, uc, scope.as_ref()));
+            if dup.is_err() {
+                res.push(Err(ConsistencyError::DuplicateUniqueConstraint(
+                    uc.name.clone(),
+                )))
+            }
+        }
+
         debug!("{:?}", res);
 
         res
@@ -366,4 +583,207 @@ mod tests {
     fn test_verify_name_unique() {
         // Can we preload two dups and verify to show we detect?
     }
+
+    const JSON_UNIQUE_CONSTRAINT: &'static str = r#"{
+        "valid": null,
+        "state": null,
+        "attrs": {
+            "class": ["object", "uniqueconstraint"],
+            "name": ["person_legalname_description"],
+            "uniqueattr": ["legalname", "description"],
+            "uniquescope": ["{\"Eq\":[\"class\",\"person\"]}"]
+        }
+    }"#;
+
+    fn entry_with(
+        class: &str,
+        name: &str,
+        legalname: &str,
+        description: &str,
+    ) -> Entry<EntryInit, EntryNew> {
+        let mut e: Entry<EntryInit, EntryNew> = Entry::new();
+        e.add_ava("class", &Value::new_class(class));
+        e.add_ava("name", &Value::new_iutf8s(name));
+        e.add_ava("displayname", &Value::new_utf8s(name));
+        e.add_ava("legalname", &Value::new_utf8s(legalname));
+        e.add_ava("description", &Value::new_utf8s(description));
+        e
+    }
+
+    // Same combination of values as an existing entry, reject.
+    #[test]
+    fn test_pre_create_constraint_unique() {
+        let uc: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_UNIQUE_CONSTRAINT);
+        let preload = vec![
+            uc,
+            entry_with("person", "testperson_a", "Test Person", "staff"),
+        ];
+        let create = vec![entry_with("person", "testperson_b", "Test Person", "staff")];
+
+        run_create_test!(
+            Err(OperationError::Plugin(PluginError::AttrUnique(
+                "duplicate values detected for unique constraint person_legalname_description"
+                    .to_string()
+            ))),
+            preload,
+            create,
+            None,
+            |_, _| {}
+        );
+    }
+
+    // Only part of the combination matches, accept.
+    #[test]
+    fn test_pre_create_constraint_partial() {
+        let uc: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_UNIQUE_CONSTRAINT);
+        let preload = vec![
+            uc,
+            entry_with("person", "testperson_a", "Test Person", "staff"),
+        ];
+        let create = vec![entry_with(
+            "person",
+            "testperson_b",
+            "Test Person",
+            "contractor",
+        )];
+
+        run_create_test!(Ok(()), preload, create, None, |_, _| {});
+    }
+
+    // Entries outside of the scope are not constrained, accept.
+    #[test]
+    fn test_pre_create_constraint_scope() {
+        let uc: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_UNIQUE_CONSTRAINT);
+        let preload = vec![
+            uc,
+            entry_with("person", "testperson_a", "Test Person", "staff"),
+        ];
+
+        let create = vec![entry_with(
+            "extensibleobject",
+            "testobject",
+            "Test Person",
+            "staff",
+        )];
+
+        run_create_test!(Ok(()), preload, create, None, |_, _| {});
+    }
+
+    // Two entries modded to the same combination, reject.
+    #[test]
+    fn test_pre_modify_constraint_unique() {
+        let uc: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_UNIQUE_CONSTRAINT);
+        let preload = vec![
+            uc,
+            entry_with("person", "testperson_a", "Test Person", "staff"),
+            entry_with("person", "testperson_b", "Test Person", "contractor"),
+        ];
+
+        run_modify_test!(
+            Err(OperationError::Plugin(PluginError::AttrUnique(
+                "duplicate values detected for unique constraint person_legalname_description"
+                    .to_string()
+            ))),
+            preload,
+            filter!(f_eq("name", PartialValue::new_iutf8s("testperson_b"))),
+            ModifyList::new_list(vec![
+                Modify::Purged("description".to_string()),
+                Modify::Present("description".to_string(), Value::new_utf8s("staff"))
+            ]),
+            None,
+            |_, _| {}
+        );
+    }
+
+    // A constraint that existing entries already break, reject.
+    #[test]
+    fn test_pre_create_constraint_existing_dup() {
+        let preload = vec![
+            entry_with("person", "testperson_a", "Test Person", "staff"),
+            entry_with("person", "testperson_b", "Test Person", "staff"),
+        ];
+        let uc: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_UNIQUE_CONSTRAINT);
+        let create = vec![uc];
+
+        run_create_test!(
+            Err(OperationError::Plugin(PluginError::AttrUnique(
+                "values already exist for unique constraint person_legalname_description"
+                    .to_string()
+            ))),
+            preload,
+            create,
+            None,
+            |_, _| {}
+        );
+    }
+
+    // Narrowing a constraint so that existing entries break it, reject.
+    #[test]
+    fn test_modify_constraint_existing_dup() {
+        let uc: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_UNIQUE_CONSTRAINT);
+        let preload = vec![
+            uc,
+            entry_with("person", "testperson_a", "Test Person", "staff"),
+            entry_with("person", "testperson_b", "Test Person", "contractor"),
+        ];
+
+        run_modify_test!(
+            Err(OperationError::Plugin(PluginError::AttrUnique(
+                "values already exist for unique constraint person_legalname_description"
+                    .to_string()
+            ))),
+            preload,
+            filter!(f_eq(
+                "name",
+                PartialValue::new_iutf8s("person_legalname_description")
+            )),
+            ModifyList::new_list(vec![
+                Modify::Purged("uniqueattr".to_string()),
+                Modify::Present("uniqueattr".to_string(), Value::new_iutf8s("legalname"))
+            ]),
+            None,
+            |_, _| {}
+        );
+    }
+
+    #[test]
+    fn test_verify_constraint_unique() {
+        use crate::audit::AuditScope;
+        use crate::be::Backend;
+        use crate::schema::Schema;
+        use crate::server::{QueryServer, QueryServerTransaction};
+        use crate::utils::duration_from_epoch_now;
+        use kanidm_proto::v1::ConsistencyError;
+
+        let mut au = AuditScope::new("test_verify_constraint_unique");
+        let uc: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_UNIQUE_CONSTRAINT);
+        let preload = vec![
+            uc,
+            entry_with("person", "testperson_a", "Test Person", "staff"),
+        ];
+        let qs = setup_test!(&mut au, preload);
+
+        // The plugin refuses the duplicate, so write it straight to the backend.
+        let mut e = entry_with("person", "testperson_b", "Test Person", "staff");
+        e.add_ava("class", &Value::new_class("object"));
+        e.add_ava(
+            "uuid",
+            &Value::new_uuids("a3b8e6d2-91c4-4f0a-8c5e-2d7f1b6e9a40").unwrap(),
+        );
+        {
+            let mut qs_write = qs.write(duration_from_epoch_now());
+            qs_write
+                .get_be_txn()
+                .create(&mut au, vec![unsafe { e.into_sealed_new() }])
+                .expect("Failed to write entry");
+            assert!(qs_write.commit(&mut au).is_ok());
+        }
+
+        let ver = qs.verify(&mut au);
+        assert!(
+            ver == vec![Err(ConsistencyError::DuplicateUniqueConstraint(
+                "person_legalname_description".to_string()
+            ))]
+        );
+    }
 }
@@ -332,12 +332,13 @@ impl Plugins {
             ce,
             refint::ReferentialIntegrity
         )
+        .and_then(|_| run_post_create_plugin!(au, qs, cand, ce, memberof::MemberOf))
         .and_then(|_| run_post_create_plugin!(
             au,
             qs,
             cand,
             ce,
-            memberof::MemberOf
+            attrunique::AttrUnique
         )))
     }
 
@@ -376,13 +377,14 @@ impl Plugins {
             refint::ReferentialIntegrity
         )
         .and_then(|_| run_post_modify_plugin!(au, qs, pre_cand, cand, me, memberof::MemberOf))
+        .and_then(|_| run_post_modify_plugin!(au, qs, pre_cand, cand, me, spn::Spn))
         .and_then(|_| run_post_modify_plugin!(
             au,
             qs,
             pre_cand,
             cand,
             me,
-            spn::Spn
+            attrunique::AttrUnique
         )))
     }
 
@@ -5,8 +5,9 @@ use crate::entry::{Entry, EntryCommitted, EntryInvalid, EntryNew};
 use crate::event::{CreateEvent, ModifyEvent};
 use crate::plugins::Plugin;
 use crate::server::QueryServerWriteTransaction;
-use crate::value::Value;
+use crate::value::{PartialValue, Value};
 use kanidm_proto::v1::{OperationError, PluginError};
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
 
 pub struct PasswordImport {}
@@ -47,20 +48,17 @@ impl Plugin for PasswordImport {
                     .map_err(|_| OperationError::Plugin(PluginError::PasswordImport("password_import was unable to convert hash format".to_string())))?;
 
                 // does the entry have a primary cred?
-                match e.get_ava_single_credential("primary_credential") {
-                    Some(_c) => {
-                        Err(
-                            OperationError::Plugin(PluginError::PasswordImport(
-                                "password_import - impossible state, how did you get a credential into a create!?".to_string()))
-                        )
-                    }
-                    None => {
-                        // just set it then!
-                        let c = Credential::new_from_password(pw);
-                        e.set_avas("primary_credential",
-                            vec![Value::new_credential("primary", c)]);
-                        Ok(())
-                    }
+                if e.attribute_pres("primary_credential") {
+                    Err(
+                        OperationError::Plugin(PluginError::PasswordImport(
+                            "password_import - impossible state, how did you get a credential into a create!?".to_string()))
+                    )
+                } else {
+                    // just set it then!
+                    let c = Credential::new_from_password(pw);
+                    e.set_avas("primary_credential",
+                        vec![Value::new_credential("primary", c)]);
+                    Ok(())
                 }
             })
     }
@@ -99,26 +97,20 @@ impl Plugin for PasswordImport {
             })?;
 
             // does the entry have a primary cred?
-            match e.get_ava_single_credential("primary_credential") {
-                Some(c) => {
-                    // This is the major diff to create, we can update in place!
-                    let c = c.update_password(pw);
-                    e.set_avas(
-                        "primary_credential",
-                        vec![Value::new_credential("primary", c)],
-                    );
-                    Ok(())
-                }
-                None => {
-                    // just set it then!
-                    let c = Credential::new_from_password(pw);
-                    e.set_avas(
-                        "primary_credential",
-                        vec![Value::new_credential("primary", c)],
-                    );
-                    Ok(())
-                }
-            }
+            let c = match e.get_ava_credential_tag("primary_credential", "primary") {
+                // This is the major diff to create, we can update in place!
+                Some(c) => c.update_password(pw),
+                // just set it then!
+                None => Credential::new_from_password(pw),
+            };
+            // Any other credentials of the account are kept as they are.
+            let mut creds = e
+                .pop_ava("primary_credential")
+                .unwrap_or_else(BTreeSet::new);
+            creds.remove(&PartialValue::new_credential_tag("primary"));
+            creds.insert(Value::new_credential("primary", c));
+            e.set_avas("primary_credential", creds.into_iter().collect());
+            Ok(())
         })
     }
 }
@@ -126,7 +118,7 @@ impl Plugin for PasswordImport {
 #[cfg(test)]
 mod tests {
     use crate::credential::totp::{TOTP, TOTP_DEFAULT_STEP};
-    use crate::credential::Credential;
+    use crate::credential::{Credential, CryptoPolicy};
     use crate::entry::{Entry, EntryInit, EntryNew};
     use crate::modify::{Modify, ModifyList};
     use crate::server::{QueryServerTransaction, QueryServerWriteTransaction};
@@ -135,7 +127,17 @@ mod tests {
 
     const IMPORT_HASH: &'static str =
         "pbkdf2_sha256$36000$xIEozuZVAoYm$uW1b35DUKyhvQAf1mBqMvoBDcqSD06juzyO/nmyV0+w=";
-    // const IMPORT_PASSWORD: &'static str = "eicieY7ahchaoCh0eeTa";
+    const IMPORT_PASSWORD: &'static str = "eicieY7ahchaoCh0eeTa";
+    // The same password, as the LDAP servers we migrate from would have stored it.
+    const IMPORT_HASHES: [&'static str; 7] = [
+        "{SSHA}Z5e6cTAPcbmOot653L+dj6vv97KhssPU5fYHGA==",
+        "{SSHA512}Rsj8s8/aUTiqREuvEUagZGbYcXaZoGdNbrzQr3yeb0s7276fErdy+iepclRnd1lLidckfpkOHeu3sKpAwIfu5qGyw9Tl9gcY",
+        "{PBKDF2-SHA512}10000$Dx4tPEtaaXiHlqW0w9Lh8A$6yHzDbOst/s79rZmPLdxsGSbyjDWu7nvnl5CLNUUVFXuvpC8zUtEFcE/YKJb7zTDC7tjlgPNSrihZ5Tw5H9HGQ",
+        "{CRYPT}$6$5aOJjJsKn9rAR.Jm$DAf9dc/OdU5TFDB0s3qW.W98uXUR2bZqt4Bg0/WsLrXCb25YYpuzpzSwSzNEKR6iD9HQqXVDx7v.CQlUwH8Nk/",
+        "$6$5aOJjJsKn9rAR.Jm$DAf9dc/OdU5TFDB0s3qW.W98uXUR2bZqt4Bg0/WsLrXCb25YYpuzpzSwSzNEKR6iD9HQqXVDx7v.CQlUwH8Nk/",
+        "{CRYPT}$2b$05$abcdefghijklmnopqrstuusok1ERYMMLnUuWWYxXX8DMcLmYsts4O",
+        "$2b$05$abcdefghijklmnopqrstuusok1ERYMMLnUuWWYxXX8DMcLmYsts4O",
+    ];
 
     #[test]
     fn test_pre_create_password_import_1() {
@@ -208,7 +210,7 @@ mod tests {
         }"#,
         );
 
-        let c = Credential::new_password_only("password");
+        let c = Credential::new_password_only(&CryptoPolicy::minimum(), "password");
         ea.add_ava("primary_credential", &Value::new_credential("primary", c));
 
         let preload = vec![ea];
@@ -244,7 +246,9 @@ mod tests {
         );
 
         let totp = TOTP::generate_secure("test_totp".to_string(), TOTP_DEFAULT_STEP);
-        let c = Credential::new_password_only("password").update_totp(totp);
+        let c = Credential::new_password_only(&CryptoPolicy::minimum(), "password")
+            .add_totp(totp)
+            .expect("failed to add totp");
         ea.add_ava("primary_credential", &Value::new_credential("primary", c));
 
         let preload = vec![ea];
@@ -268,9 +272,57 @@ mod tests {
                 let c = e
                     .get_ava_single_credential("primary_credential")
                     .expect("failed to get primary cred.");
-                assert!(c.totp.is_some());
+                assert!(!c.totp.is_empty());
                 assert!(c.password.is_some());
             }
         );
     }
+
+    #[test]
+    fn test_modify_password_import_4_formats() {
+        let ea: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "valid": null,
+            "state": null,
+            "attrs": {
+                "class": ["account", "person"],
+                "name": ["testperson"],
+                "description": ["testperson"],
+                "displayname": ["testperson"],
+                "uuid": ["d2b496bd-8493-47b7-8142-f568b5cf47ee"]
+            }
+        }"#,
+        );
+
+        for im_hash in IMPORT_HASHES.iter() {
+            let preload = vec![ea.clone()];
+
+            run_modify_test!(
+                Ok(()),
+                preload,
+                filter!(f_eq("name", PartialValue::new_iutf8s("testperson"))),
+                ModifyList::new_list(vec![Modify::Present(
+                    "password_import".to_string(),
+                    Value::from(*im_hash)
+                )]),
+                None,
+                |au: &mut AuditScope, qs: &mut QueryServerWriteTransaction| {
+                    let e = qs
+                        .internal_search_uuid(
+                            au,
+                            &Uuid::parse_str("d2b496bd-8493-47b7-8142-f568b5cf47ee").unwrap(),
+                        )
+                        .expect("failed to get entry");
+                    let pw = e
+                        .get_ava_single_credential("primary_credential")
+                        .and_then(|c| c.password.as_ref())
+                        .expect("failed to get primary cred password.");
+                    assert!(pw.verify(IMPORT_PASSWORD));
+                    assert!(!pw.verify("eicieY7ahchaoCh0eeTb"));
+                    // It will be rehashed on the first login.
+                    assert!(pw.requires_upgrade(&CryptoPolicy::minimum()));
+                }
+            );
+        }
+    }
 }
@@ -24,6 +24,7 @@ lazy_static! {
         // of schema types.
         m.insert("must");
         m.insert("may");
+        m.insert("excludes");
         // Allow modification of some domain info types for local configuration.
         m.insert("domain_ssid");
         m.insert("badlist_password");
@@ -88,6 +89,16 @@ impl Plugin for Protected {
         cand: &mut Vec<Entry<EntryInvalid, EntryCommitted>>,
         me: &ModifyEvent,
     ) -> Result<(), OperationError> {
+        let claim_mod = me.modlist.iter().any(|m| match m {
+            Modify::Present(a, _) => a == "claim",
+            Modify::Removed(a, _) => a == "claim",
+            Modify::Purged(a) => a == "claim",
+        });
+        if claim_mod {
+            audit_log!(au, "Storing claims is NOT ALLOWED");
+            return Err(OperationError::SystemProtectedAttribute);
+        }
+
         if me.event.is_internal() {
             audit_log!(
                 au,
@@ -211,7 +222,7 @@ mod tests {
     use crate::constants::JSON_ADMIN_V1;
     use crate::entry::{Entry, EntryInit, EntryNew};
     use crate::value::{PartialValue, Value};
-    use kanidm_proto::v1::OperationError;
+    use kanidm_proto::v1::{OperationError, SchemaError};
 
     const JSON_ADMIN_ALLOW_ALL: &'static str = r#"{
         "valid": null,
@@ -275,6 +286,60 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_pre_create_claim_deny() {
+        // Claims are phantoms, so schema refuses to store them on create.
+        let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "valid": null,
+            "state": null,
+            "attrs": {
+                "class": ["extensibleobject"],
+                "name": ["testperson"],
+                "claim": ["privileged"]
+            }
+        }"#,
+        );
+
+        let preload: Vec<Entry<EntryInit, EntryNew>> = Vec::new();
+        let create = vec![e.clone()];
+
+        run_create_test!(
+            Err(OperationError::SchemaViolation(
+                SchemaError::PhantomAttribute
+            )),
+            preload,
+            create,
+            None,
+            |_, _| {}
+        );
+    }
+
+    #[test]
+    fn test_pre_modify_claim_deny() {
+        let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+            r#"{
+            "valid": null,
+            "state": null,
+            "attrs": {
+                "class": ["extensibleobject"],
+                "name": ["testperson"]
+            }
+        }"#,
+        );
+
+        let preload = vec![e.clone()];
+
+        run_modify_test!(
+            Err(OperationError::SystemProtectedAttribute),
+            preload,
+            filter!(f_eq("name", PartialValue::new_iutf8s("testperson"))),
+            modlist!([m_pres("claim", &Value::new_iutf8s("privileged"))]),
+            None,
+            |_, _| {}
+        );
+    }
+
     #[test]
     fn test_pre_modify_system_deny() {
         let acp: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(JSON_ADMIN_ALLOW_ALL);
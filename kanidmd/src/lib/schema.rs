@@ -19,13 +19,18 @@
 use crate::audit::AuditScope;
 use crate::constants::*;
 use crate::entry::{Entry, EntryCommitted, EntryInit, EntryNew, EntrySealed};
+use crate::filter::{Filter, FilterValid};
+use crate::server::{QueryServerTransaction, QueryServerWriteTransaction};
 use crate::value::{IndexType, PartialValue, SyntaxType, Value};
 use kanidm_proto::v1::{ConsistencyError, OperationError, SchemaError};
 
 use std::borrow::Borrow;
 use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
 use uuid::Uuid;
 
+use regex::Regex;
+
 use concread::collections::bptree::*;
 use concread::cowcell::*;
 
@@ -39,21 +44,25 @@ use concread::cowcell::*;
 lazy_static! {
     static ref PVCLASS_ATTRIBUTETYPE: PartialValue = PartialValue::new_class("attributetype");
     static ref PVCLASS_CLASSTYPE: PartialValue = PartialValue::new_class("classtype");
+    static ref PVCLASS_UNIQUECONSTRAINT: PartialValue = PartialValue::new_class("uniqueconstraint");
 }
 
 /// Schema stores the set of [`Classes`] and [`Attributes`] that the server will
 /// use to validate [`Entries`], [`Filters`] and [`Modifications`]. Additionally the
 /// schema stores an extracted copy of the current attribute indexing metadata that
-/// is used by the backend during queries.
+/// is used by the backend during queries, and the set of [`UniqueConstraints`] that
+/// span multiple attributes.
 ///
 /// [`Filters`]: ../filter/index.html
 /// [`Modifications`]: ../modify/index.html
 /// [`Entries`]: ../entry/index.html
 /// [`Attributes`]: struct.SchemaAttribute.html
 /// [`Classes`]: struct.SchemaClass.html
+/// [`UniqueConstraints`]: struct.SchemaUniqueConstraint.html
 pub struct Schema {
     classes: BptreeMap<String, SchemaClass>,
     attributes: BptreeMap<String, SchemaAttribute>,
+    uniqueconstraints: BptreeMap<Uuid, SchemaUniqueConstraint>,
     /// This is a copy-on-write cache of the index metadata that has been
     /// extracted from attributes set, in the correct format for the backend
     /// to consume.
@@ -66,6 +75,7 @@ pub struct Schema {
 pub struct SchemaWriteTransaction<'a> {
     classes: BptreeMapWriteTxn<'a, String, SchemaClass>,
     attributes: BptreeMapWriteTxn<'a, String, SchemaAttribute>,
+    uniqueconstraints: BptreeMapWriteTxn<'a, Uuid, SchemaUniqueConstraint>,
     idxmeta: CowCellWriteTxn<'a, BTreeSet<(String, IndexType)>>,
 }
 
@@ -73,12 +83,20 @@ pub struct SchemaWriteTransaction<'a> {
 pub struct SchemaReadTransaction {
     classes: BptreeMapReadTxn<String, SchemaClass>,
     attributes: BptreeMapReadTxn<String, SchemaAttribute>,
+    uniqueconstraints: BptreeMapReadTxn<Uuid, SchemaUniqueConstraint>,
     idxmeta: CowCellReadTxn<BTreeSet<(String, IndexType)>>,
 }
 
 /// An item reperesenting an attribute and the rules that enforce it. These rules enforce if an
 /// attribute on an [`Entry`] may be single or multi value, must be unique amongst all other types
 /// of this attribute, if the attribute should be [`indexed`], and what type of data [`syntax`] it may hold.
+/// Numeric syntaxes may additionally be bounded by an inclusive `minvalue` and `maxvalue`.
+///
+/// Attributes may also carry optional constraints on their content. String values may be
+/// required to match a `regex` and to be within `minlength` and `maxlength` characters. Any
+/// syntax may be limited to an enumeration of `allowedvalue`s, and the number of values
+/// present may be bounded by `mincardinality` and `maxcardinality`. Constraints only apply when
+/// the attribute is present - use a class `must` to require an attribute exists.
 ///
 /// [`Entry`]: ../entry/index.html
 /// [`indexed`]: ../value/enum.IndexType.html
@@ -96,6 +114,39 @@ pub struct SchemaAttribute {
     pub phantom: bool,
     pub index: Vec<IndexType>,
     pub syntax: SyntaxType,
+    pub minvalue: Option<i64>,
+    pub maxvalue: Option<i64>,
+    pub regex: Option<Regex>,
+    pub minlength: Option<u32>,
+    pub maxlength: Option<u32>,
+    pub allowedvalue: Vec<String>,
+    pub mincardinality: Option<u32>,
+    pub maxcardinality: Option<u32>,
+}
+
+// Most attributes have no constraints, so this lets a definition give only the
+// fields it cares about, and take the rest with `..Default::default()`.
+impl Default for SchemaAttribute {
+    fn default() -> Self {
+        SchemaAttribute {
+            name: String::new(),
+            uuid: Uuid::nil(),
+            description: String::new(),
+            multivalue: false,
+            unique: false,
+            phantom: false,
+            index: Vec::new(),
+            syntax: SyntaxType::UTF8STRING,
+            minvalue: None,
+            maxvalue: None,
+            regex: None,
+            minlength: None,
+            maxlength: None,
+            allowedvalue: Vec::new(),
+            mincardinality: None,
+            maxcardinality: None,
+        }
+    }
 }
 
 impl SchemaAttribute {
@@ -163,6 +214,77 @@ impl SchemaAttribute {
                 .cloned()
                 .ok_or_else(|| OperationError::InvalidSchemaState("missing syntax".to_string()))
         );
+        // value bounds, only meaningful for numeric syntaxes.
+        let minvalue = value.get_ava_single_int64("minvalue");
+        let maxvalue = value.get_ava_single_int64("maxvalue");
+        let numeric = match syntax {
+            SyntaxType::INT64 | SyntaxType::UINT32 => true,
+            _ => false,
+        };
+        if !numeric && (minvalue.is_some() || maxvalue.is_some()) {
+            audit_log!(
+                audit,
+                "minvalue and maxvalue can't bound syntax {:?}",
+                syntax
+            );
+            return Err(OperationError::InvalidSchemaState(
+                "minvalue or maxvalue on a non-numeric syntax".to_string(),
+            ));
+        }
+        if let (Some(min), Some(max)) = (minvalue, maxvalue) {
+            if min > max {
+                audit_log!(audit, "minvalue {} is greater than maxvalue {}", min, max);
+                return Err(OperationError::InvalidSchemaState(
+                    "minvalue greater than maxvalue".to_string(),
+                ));
+            }
+        }
+        // content constraints
+        let regex = match value.get_ava_single_str("regex") {
+            Some(r) => Some(try_audit!(
+                audit,
+                Regex::new(r),
+                "Invalid regex -> {:?}",
+                OperationError::InvalidSchemaState("Invalid regex".to_string())
+            )),
+            None => None,
+        };
+        let minlength = value.get_ava_single_uint32("minlength");
+        let maxlength = value.get_ava_single_uint32("maxlength");
+        let mincardinality = value.get_ava_single_uint32("mincardinality");
+        let maxcardinality = value.get_ava_single_uint32("maxcardinality");
+        if let (Some(min), Some(max)) = (minlength, maxlength) {
+            if min > max {
+                audit_log!(audit, "minlength {} is greater than maxlength {}", min, max);
+                return Err(OperationError::InvalidSchemaState(
+                    "minlength greater than maxlength".to_string(),
+                ));
+            }
+        }
+        if let (Some(min), Some(max)) = (mincardinality, maxcardinality) {
+            if min > max {
+                audit_log!(
+                    audit,
+                    "mincardinality {} is greater than maxcardinality {}",
+                    min,
+                    max
+                );
+                return Err(OperationError::InvalidSchemaState(
+                    "mincardinality greater than maxcardinality".to_string(),
+                ));
+            }
+        }
+        // Allowed values are compared in their normalised form, so insensitive
+        // strings must be lowered to match.
+        let allowedvalue = value
+            .get_ava_opt_string("allowedvalue")
+            .ok_or_else(|| OperationError::InvalidSchemaState("Invalid allowedvalue".to_string()))?
+            .into_iter()
+            .map(|av| match syntax {
+                SyntaxType::UTF8STRING_INSENSITIVE => av.to_lowercase(),
+                _ => av,
+            })
+            .collect();
 
         Ok(SchemaAttribute {
             name,
@@ -173,6 +295,14 @@ impl SchemaAttribute {
             phantom,
             index,
             syntax,
+            minvalue,
+            maxvalue,
+            regex,
+            minlength,
+            maxlength,
+            allowedvalue,
+            mincardinality,
+            maxcardinality,
         })
     }
 
@@ -195,6 +325,9 @@ impl SchemaAttribute {
             SyntaxType::SERVICE_PRINCIPLE_NAME => v.is_spn(),
             SyntaxType::UINT32 => v.is_uint32(),
             SyntaxType::CID => v.is_cid(),
+            SyntaxType::INT64 => v.is_int64(),
+            SyntaxType::JWS_KEY => v.is_jws_key(),
+            SyntaxType::SESSION => v.is_session(),
         };
         if r {
             Ok(())
@@ -226,7 +359,7 @@ impl SchemaAttribute {
             return Err(SchemaError::InvalidAttributeSyntax);
         };
         // If syntax, check the type is correct
-        match self.syntax {
+        let r = match self.syntax {
             SyntaxType::BOOLEAN => ava.iter().fold(Ok(()), |acc, v| {
                 acc.and_then(|_| {
                     if v.is_bool() {
@@ -354,7 +487,117 @@ impl SchemaAttribute {
                     }
                 })
             }),
+            SyntaxType::INT64 => ava.iter().fold(Ok(()), |acc, v| {
+                acc.and_then(|_| {
+                    if v.is_int64() {
+                        Ok(())
+                    } else {
+                        Err(SchemaError::InvalidAttributeSyntax)
+                    }
+                })
+            }),
+            SyntaxType::JWS_KEY => ava.iter().fold(Ok(()), |acc, v| {
+                acc.and_then(|_| {
+                    if v.is_jws_key() {
+                        Ok(())
+                    } else {
+                        Err(SchemaError::InvalidAttributeSyntax)
+                    }
+                })
+            }),
+            SyntaxType::SESSION => ava.iter().fold(Ok(()), |acc, v| {
+                acc.and_then(|_| {
+                    if v.is_session() {
+                        Ok(())
+                    } else {
+                        Err(SchemaError::InvalidAttributeSyntax)
+                    }
+                })
+            }),
+        };
+        r?;
+        self.validate_constraints(ava)
+    }
+
+    fn constraint_violation(&self, reason: String) -> SchemaError {
+        debug!("Constraint violation on {} -> {}", self.name, reason);
+        SchemaError::ConstraintViolation(self.name.clone(), reason)
+    }
+
+    fn validate_constraints(&self, ava: &BTreeSet<Value>) -> Result<(), SchemaError> {
+        if let Some(min) = self.mincardinality {
+            if ava.len() < min as usize {
+                return Err(self.constraint_violation(format!(
+                    "{} values present, at least {} required",
+                    ava.len(),
+                    min
+                )));
+            }
         }
+        if let Some(max) = self.maxcardinality {
+            if ava.len() > max as usize {
+                return Err(self.constraint_violation(format!(
+                    "{} values present, at most {} allowed",
+                    ava.len(),
+                    max
+                )));
+            }
+        }
+
+        self.validate_value_bounds(ava)?;
+
+        // Length and regex only have meaning on string syntaxes.
+        ava.iter().filter_map(|v| v.to_str()).try_for_each(|s| {
+            let len = s.chars().count();
+            match (self.minlength, self.maxlength, &self.regex) {
+                (Some(min), _, _) if len < min as usize => Err(self.constraint_violation(format!(
+                    "value is {} characters, at least {} required",
+                    len, min
+                ))),
+                (_, Some(max), _) if len > max as usize => Err(self.constraint_violation(format!(
+                    "value is {} characters, at most {} allowed",
+                    len, max
+                ))),
+                (_, _, Some(re)) if !re.is_match(s) => Err(self.constraint_violation(format!(
+                    "value {} does not match {}",
+                    s,
+                    re.as_str()
+                ))),
+                _ => Ok(()),
+            }
+        })?;
+
+        if self.allowedvalue.is_empty() {
+            Ok(())
+        } else {
+            ava.iter().try_for_each(|v| {
+                let s = v.to_proto_string_clone();
+                if self.allowedvalue.contains(&s) {
+                    Ok(())
+                } else {
+                    Err(self.constraint_violation(format!(
+                        "value {} is not one of the allowed values {:?}",
+                        s, self.allowedvalue
+                    )))
+                }
+            })
+        }
+    }
+
+    fn validate_value_bounds(&self, ava: &BTreeSet<Value>) -> Result<(), SchemaError> {
+        // Bounds are inclusive, and are only checked on syntaxes that have
+        // a numeric interpretation.
+        ava.iter()
+            .filter_map(|v| v.to_int64().or_else(|| v.to_uint32().map(i64::from)))
+            .try_for_each(|i| match (self.minvalue, self.maxvalue) {
+                (Some(min), _) if i < min => Err(self
+                    .constraint_violation(format!("value {} is less than the minimum {}", i, min))),
+                (_, Some(max)) if i > max => Err(self.constraint_violation(format!(
+                    "value {} is greater than the maximum {}",
+                    i, max
+                ))),
+                _ => Ok(()),
+            })
     }
 }
 
@@ -366,15 +609,17 @@ impl SchemaAttribute {
 ///
 /// Classes are additive, meaning that if there are two classes, the `may` rules of both union,
 /// and that if an attribute is `must` on one class, and `may` in another, the `must` rule
-/// takes precedence. It is not possible to combine classes in an incompatible way due to these
-/// rules.
+/// takes precedence.
 ///
-/// That in mind, and entry that has one of every possible class would probably be nonsensical,
-/// but the addition rules make it easy to construct and understand with concepts like [`access`]
-/// controls or accounts and posix extensions.
+/// Which classes may be combined is limited by their [`kind`]. An entry has exactly one
+/// structural class that describes what it *is* (an account, a group, ...), and any number of
+/// auxiliary classes that extend it (posix extensions, memberof, ...). Every structural class
+/// implicitly refines `object`, so an entry that has no other structural class is an `object`.
+/// A class may also list classes in `systemexcludes` and `excludes` that it can never be
+/// combined with.
 ///
 /// [`Entry`]: ../entry/index.html
-/// [`access`]: ../access/index.html
+/// [`kind`]: enum.ClassKind.html
 #[derive(Debug, Clone)]
 pub struct SchemaClass {
     // Is this used?
@@ -387,6 +632,40 @@ pub struct SchemaClass {
     pub may: Vec<String>,
     pub systemmust: Vec<String>,
     pub must: Vec<String>,
+    pub kind: ClassKind,
+    pub systemexcludes: Vec<String>,
+    pub excludes: Vec<String>,
+}
+
+/// The kind of a [`SchemaClass`], which determines how it may be combined with other classes
+/// on an entry. Classes that do not define a kind are auxiliary.
+///
+/// [`SchemaClass`]: struct.SchemaClass.html
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassKind {
+    Structural,
+    Auxiliary,
+}
+
+impl ClassKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ClassKind::Structural => "structural",
+            ClassKind::Auxiliary => "auxiliary",
+        }
+    }
+}
+
+impl TryFrom<&str> for ClassKind {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "structural" => Ok(ClassKind::Structural),
+            "auxiliary" => Ok(ClassKind::Auxiliary),
+            _ => Err(()),
+        }
+    }
 }
 
 impl SchemaClass {
@@ -434,6 +713,23 @@ impl SchemaClass {
         let must = value.get_ava_opt_string("must").ok_or_else(|| {
             OperationError::InvalidSchemaState("Missing or invalid must".to_string())
         })?;
+        let systemexcludes = value.get_ava_opt_string("systemexcludes").ok_or_else(|| {
+            OperationError::InvalidSchemaState("Missing or invalid systemexcludes".to_string())
+        })?;
+        let excludes = value.get_ava_opt_string("excludes").ok_or_else(|| {
+            OperationError::InvalidSchemaState("Missing or invalid excludes".to_string())
+        })?;
+
+        // kind
+        let kind = match value.get_ava_single_str("classkind") {
+            Some(k) => try_audit!(
+                audit,
+                ClassKind::try_from(k),
+                "Invalid classkind -> {:?}",
+                OperationError::InvalidSchemaState("Invalid classkind".to_string())
+            ),
+            None => ClassKind::Auxiliary,
+        };
 
         Ok(SchemaClass {
             name,
@@ -443,6 +739,115 @@ impl SchemaClass {
             systemmust,
             may,
             must,
+            kind,
+            systemexcludes,
+            excludes,
+        })
+    }
+
+    /// Check that a set of classes can be combined on a single entry - that there is exactly
+    /// one structural class, and that no class excludes another in the set.
+    pub fn validate_class_set(classes: &[&SchemaClass]) -> Result<(), SchemaError> {
+        let structural: Vec<&str> = classes
+            .iter()
+            .filter(|c| c.kind == ClassKind::Structural && c.name != "object")
+            .map(|c| c.name.as_str())
+            .collect();
+
+        if structural.len() > 1 {
+            debug!("Multiple structural classes -> {:?}", structural);
+            return Err(SchemaError::MultipleStructuralClasses(
+                structural.into_iter().map(str::to_string).collect(),
+            ));
+        }
+        if structural.is_empty() && !classes.iter().any(|c| c.name == "object") {
+            debug!("No structural class present");
+            return Err(SchemaError::NoStructuralClass);
+        }
+
+        classes.iter().try_for_each(|c| {
+            match c
+                .systemexcludes
+                .iter()
+                .chain(c.excludes.iter())
+                .find(|ex| classes.iter().any(|o| &o.name == *ex))
+            {
+                Some(ex) => {
+                    debug!("Class {} excludes {}", c.name, ex);
+                    Err(SchemaError::ExcludedClass(c.name.clone(), ex.clone()))
+                }
+                None => Ok(()),
+            }
+        })
+    }
+}
+
+/// A uniqueness rule that spans a combination of attributes, such as (`mail`, `domain`).
+/// No two entries may share the same combination of values for all of the attributes
+/// in `attrs`. Entries lacking any one of the attributes are not constrained. When a
+/// `scope` is defined, only entries matching the scope filter are considered, which
+/// allows rules such as unique among active accounts only.
+///
+/// Constraints are stored as entries of class `uniqueconstraint` and are enforced by
+/// the attribute uniqueness plugin.
+#[derive(Debug, Clone)]
+pub struct SchemaUniqueConstraint {
+    pub name: String,
+    pub uuid: Uuid,
+    pub attrs: Vec<String>,
+    pub scope: Option<Filter<FilterValid>>,
+}
+
+impl SchemaUniqueConstraint {
+    pub fn try_from(
+        audit: &mut AuditScope,
+        qs: &mut QueryServerWriteTransaction,
+        value: &Entry<EntrySealed, EntryCommitted>,
+    ) -> Result<Self, OperationError> {
+        if !value.attribute_value_pres("class", &PVCLASS_UNIQUECONSTRAINT) {
+            audit_log!(audit, "class uniqueconstraint not present");
+            return Err(OperationError::InvalidSchemaState(
+                "missing uniqueconstraint".to_string(),
+            ));
+        }
+
+        let uuid = *value.get_uuid();
+
+        let name = try_audit!(
+            audit,
+            value
+                .get_ava_single_string("name")
+                .ok_or_else(|| OperationError::InvalidSchemaState("missing name".to_string()))
+        );
+
+        let attrs = try_audit!(
+            audit,
+            value
+                .get_ava_opt_string("uniqueattr")
+                .filter(|a| !a.is_empty())
+                .ok_or_else(|| OperationError::InvalidSchemaState(
+                    "missing uniqueattr".to_string()
+                ))
+        );
+
+        let scope = match value.get_ava_single_protofilter("uniquescope") {
+            Some(scope_f) => {
+                let scope_i = try_audit!(audit, Filter::from_rw(audit, &scope_f, qs));
+                Some(try_audit!(
+                    audit,
+                    scope_i
+                        .validate(qs.get_schema())
+                        .map_err(OperationError::SchemaViolation)
+                ))
+            }
+            None => None,
+        };
+
+        Ok(SchemaUniqueConstraint {
+            name,
+            uuid,
+            attrs,
+            scope,
         })
     }
 }
@@ -450,6 +855,7 @@ impl SchemaClass {
 pub trait SchemaTransaction {
     fn get_classes(&self) -> BptreeMapReadSnapshot<String, SchemaClass>;
     fn get_attributes(&self) -> BptreeMapReadSnapshot<String, SchemaAttribute>;
+    fn get_uniqueconstraints(&self) -> BptreeMapReadSnapshot<Uuid, SchemaUniqueConstraint>;
     fn get_idxmeta(&self) -> BTreeSet<(String, IndexType)>;
 
     fn validate(&self, _audit: &mut AuditScope) -> Vec<Result<(), ConsistencyError>> {
@@ -489,6 +895,21 @@ pub trait SchemaTransaction {
                     }
                 })
         }); // end for
+
+        // Composite unique constraints must only refer to real attributes.
+        self.get_uniqueconstraints().values().for_each(|uc| {
+            uc.attrs
+                .iter()
+                .filter(|a| !attribute_snapshot.contains_key(a.as_str()))
+                .for_each(|a| {
+                    res.push(Err(
+                        ConsistencyError::SchemaUniqueConstraintMissingAttribute(
+                            uc.name.clone(),
+                            a.clone(),
+                        ),
+                    ))
+                })
+        });
         res
     }
 
@@ -553,11 +974,13 @@ impl<'a> SchemaWriteTransaction<'a> {
         let SchemaWriteTransaction {
             classes,
             attributes,
+            uniqueconstraints,
             idxmeta,
         } = self;
 
         classes.commit();
         attributes.commit();
+        uniqueconstraints.commit();
         idxmeta.commit();
         Ok(())
     }
@@ -592,6 +1015,17 @@ impl<'a> SchemaWriteTransaction<'a> {
         Ok(())
     }
 
+    pub fn update_uniqueconstraints(
+        &mut self,
+        uniqueconstraints: Vec<SchemaUniqueConstraint>,
+    ) -> Result<(), OperationError> {
+        self.uniqueconstraints.clear();
+        uniqueconstraints.into_iter().for_each(|uc| {
+            self.uniqueconstraints.insert(uc.uuid, uc);
+        });
+        Ok(())
+    }
+
     fn reload_idxmeta(&mut self) {
         self.idxmeta.clear();
         self.idxmeta.extend(self.attributes.values().flat_map(|a| {
@@ -621,6 +1055,7 @@ impl<'a> SchemaWriteTransaction<'a> {
             //
             self.classes.clear();
             self.attributes.clear();
+            self.uniqueconstraints.clear();
             self.idxmeta.clear();
             // Bootstrap in definitions of our own schema types
             // First, add all the needed core attributes for schema parsing
@@ -636,6 +1071,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -652,6 +1088,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::UUID,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -668,6 +1105,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![],
                     syntax: SyntaxType::CID,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -682,6 +1120,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -698,6 +1137,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::SERVICE_PRINCIPLE_NAME,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -712,6 +1152,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -726,6 +1167,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -740,6 +1182,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![],
                     syntax: SyntaxType::UTF8STRING,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(String::from("multivalue"), SchemaAttribute {
@@ -751,6 +1194,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                 phantom: false,
                 index: vec![],
                 syntax: SyntaxType::BOOLEAN,
+                ..Default::default()
             });
             self.attributes.insert(String::from("phantom"), SchemaAttribute {
                 name: String::from("phantom"),
@@ -761,6 +1205,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                 phantom: false,
                 index: vec![],
                 syntax: SyntaxType::BOOLEAN,
+                ..Default::default()
             });
             self.attributes.insert(String::from("unique"), SchemaAttribute {
                 name: String::from("unique"),
@@ -771,6 +1216,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                 phantom: false,
                 index: vec![],
                 syntax: SyntaxType::BOOLEAN,
+                ..Default::default()
             });
             self.attributes.insert(
                 String::from("index"),
@@ -786,6 +1232,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![],
                     syntax: SyntaxType::INDEX_ID,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -802,6 +1249,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::SYNTAX_ID,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -818,6 +1266,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -834,6 +1283,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -850,6 +1300,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -866,6 +1317,92 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
+                },
+            );
+            self.attributes.insert(
+                String::from("classkind"),
+                SchemaAttribute {
+                    name: String::from("classkind"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_CLASSKIND)
+                        .expect("unable to parse const uuid"),
+                    description: String::from("Whether this class is structural or auxiliary."),
+                    multivalue: false,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    allowedvalue: vec![String::from("structural"), String::from("auxiliary")],
+                    ..Default::default()
+                },
+            );
+            self.attributes.insert(
+                String::from("systemexcludes"),
+                SchemaAttribute {
+                    name: String::from("systemexcludes"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_SYSTEMEXCLUDES)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "A list of system provided classes that may not be present with this class.",
+                    ),
+                    multivalue: true,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
+                },
+            );
+            self.attributes.insert(
+                String::from("excludes"),
+                SchemaAttribute {
+                    name: String::from("excludes"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_EXCLUDES)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "A user modifiable list of classes that may not be present with this class.",
+                    ),
+                    multivalue: true,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
+                },
+            );
+            // Unique constraint attributes.
+            self.attributes.insert(
+                String::from("uniqueattr"),
+                SchemaAttribute {
+                    name: String::from("uniqueattr"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_UNIQUEATTR)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "The combination of attributes whose values must be unique together.",
+                    ),
+                    multivalue: true,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
+                },
+            );
+            self.attributes.insert(
+                String::from("uniquescope"),
+                SchemaAttribute {
+                    name: String::from("uniquescope"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_UNIQUESCOPE)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "A filter limiting the entries a unique constraint applies to.",
+                    ),
+                    multivalue: false,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::JSON_FILTER,
+                    ..Default::default()
                 },
             );
             // SYSINFO attrs
@@ -882,6 +1419,61 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::BOOLEAN,
+                    ..Default::default()
+                },
+            );
+
+            self.attributes.insert(
+                String::from("acp_deny"),
+                SchemaAttribute {
+                    name: String::from("acp_deny"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_ACP_DENY)
+                        .expect("unable to parse const uuid"),
+                    description: String::from("A flag to determine if this ACP denies what it describes. Denies are evaluated before allows, and override them."),
+                    multivalue: false,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::BOOLEAN,
+                    ..Default::default()
+                },
+            );
+
+            self.attributes.insert(
+                String::from("acp_valid_from"),
+                SchemaAttribute {
+                    name: String::from("acp_valid_from"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_ACP_VALID_FROM)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "The time, in seconds since the unix epoch, from which this ACP is applied.",
+                    ),
+                    multivalue: false,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::INT64,
+                    minvalue: Some(0),
+                    ..Default::default()
+                },
+            );
+
+            self.attributes.insert(
+                String::from("acp_valid_until"),
+                SchemaAttribute {
+                    name: String::from("acp_valid_until"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_ACP_VALID_UNTIL)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "The time, in seconds since the unix epoch, from which this ACP is no longer applied.",
+                    ),
+                    multivalue: false,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::INT64,
+                    minvalue: Some(0),
+                    ..Default::default()
                 },
             );
 
@@ -899,6 +1491,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY, IndexType::SUBSTRING],
                     syntax: SyntaxType::JSON_FILTER,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -915,6 +1508,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY, IndexType::SUBSTRING],
                     syntax: SyntaxType::JSON_FILTER,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -929,6 +1523,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -945,6 +1540,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -961,6 +1557,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
 
@@ -976,6 +1573,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -990,6 +1588,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -1004,6 +1603,37 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
+                },
+            );
+            self.attributes.insert(
+                String::from("acp_modify_presentvalue"),
+                SchemaAttribute {
+                    name: String::from("acp_modify_presentvalue"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_ACP_MODIFY_PRESENTVALUE)
+                        .expect("unable to parse const uuid"),
+                    description: String::from("An attr=value pair. When any are given for an attribute, only those values may be added to it. Only applies to modify::present operations."),
+                    multivalue: true,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::UTF8STRING,
+                    ..Default::default()
+                },
+            );
+            self.attributes.insert(
+                String::from("acp_modify_presentrefer"),
+                SchemaAttribute {
+                    name: String::from("acp_modify_presentrefer"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_ACP_MODIFY_PRESENTREFER)
+                        .expect("unable to parse const uuid"),
+                    description: String::from("An attr=filter pair, where the filter is json. Values added to the reference attribute must refer to entries matching the filter. Only applies to modify::present operations."),
+                    multivalue: true,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::UTF8STRING,
+                    ..Default::default()
                 },
             );
             // MO/Member
@@ -1019,6 +1649,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::REFERENCE_UUID,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -1033,6 +1664,7 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::REFERENCE_UUID,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
@@ -1047,66 +1679,208 @@ impl<'a> SchemaWriteTransaction<'a> {
                     phantom: false,
                     index: vec![IndexType::EQUALITY],
                     syntax: SyntaxType::REFERENCE_UUID,
+                    ..Default::default()
+                },
+            );
+            // Migration related
+            self.attributes.insert(
+                String::from("version"),
+                SchemaAttribute {
+                    name: String::from("version"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_VERSION)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "The systems internal migration version for provided objects",
+                    ),
+                    multivalue: false,
+                    unique: false,
+                    phantom: false,
+                    index: vec![IndexType::EQUALITY],
+                    syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
+                },
+            );
+            // Domain for sysinfo
+            self.attributes.insert(
+                String::from("domain"),
+                SchemaAttribute {
+                    name: String::from("domain"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_DOMAIN)
+                        .expect("unable to parse const uuid"),
+                    description: String::from("A DNS Domain name entry."),
+                    multivalue: true,
+                    unique: false,
+                    phantom: false,
+                    index: vec![IndexType::EQUALITY],
+                    syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
+                },
+            );
+            self.attributes.insert(
+                String::from("claim"),
+                SchemaAttribute {
+                    name: String::from("claim"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_CLAIM)
+                        .expect("unable to parse const uuid"),
+                    description: String::from("The claims of the session an operation is made from. These only exist on the origin of an event, so that access controls can require a claim."),
+                    multivalue: true,
+                    unique: false,
+                    phantom: true,
+                    index: vec![],
+                    syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    ..Default::default()
+                },
+            );
+            self.attributes.insert(
+                String::from("password_import"),
+                SchemaAttribute {
+                    name: String::from("password_import"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_PASSWORD_IMPORT)
+                        .expect("unable to parse const uuid"),
+                    description: String::from("An imported password hash from an external system."),
+                    multivalue: true,
+                    unique: false,
+                    phantom: true,
+                    index: vec![],
+                    syntax: SyntaxType::UTF8STRING,
+                    ..Default::default()
+                },
+            );
+
+            self.attributes.insert(
+                String::from("minvalue"),
+                SchemaAttribute {
+                    name: String::from("minvalue"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_MINVALUE)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "The inclusive lower bound of a numeric attribute's values.",
+                    ),
+                    multivalue: false,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::INT64,
+                    ..Default::default()
+                },
+            );
+            self.attributes.insert(
+                String::from("maxvalue"),
+                SchemaAttribute {
+                    name: String::from("maxvalue"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_MAXVALUE)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "The inclusive upper bound of a numeric attribute's values.",
+                    ),
+                    multivalue: false,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::INT64,
+                    ..Default::default()
+                },
+            );
+            self.attributes.insert(
+                String::from("regex"),
+                SchemaAttribute {
+                    name: String::from("regex"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_REGEX)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "A regular expression that string values of this attribute must match.",
+                    ),
+                    multivalue: false,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::UTF8STRING,
+                    ..Default::default()
+                },
+            );
+            self.attributes.insert(
+                String::from("minlength"),
+                SchemaAttribute {
+                    name: String::from("minlength"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_MINLENGTH)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "The minimum length in characters of string values of this attribute.",
+                    ),
+                    multivalue: false,
+                    unique: false,
+                    phantom: false,
+                    index: vec![],
+                    syntax: SyntaxType::UINT32,
+                    ..Default::default()
                 },
             );
-            // Migration related
             self.attributes.insert(
-                String::from("version"),
+                String::from("maxlength"),
                 SchemaAttribute {
-                    name: String::from("version"),
-                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_VERSION)
+                    name: String::from("maxlength"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_MAXLENGTH)
                         .expect("unable to parse const uuid"),
                     description: String::from(
-                        "The systems internal migration version for provided objects",
+                        "The maximum length in characters of string values of this attribute.",
                     ),
                     multivalue: false,
                     unique: false,
                     phantom: false,
-                    index: vec![IndexType::EQUALITY],
-                    syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    index: vec![],
+                    syntax: SyntaxType::UINT32,
+                    ..Default::default()
                 },
             );
-            // Domain for sysinfo
             self.attributes.insert(
-                String::from("domain"),
+                String::from("allowedvalue"),
                 SchemaAttribute {
-                    name: String::from("domain"),
-                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_DOMAIN)
+                    name: String::from("allowedvalue"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_ALLOWEDVALUE)
                         .expect("unable to parse const uuid"),
-                    description: String::from("A DNS Domain name entry."),
+                    description: String::from(
+                        "If present, the set of values this attribute is limited to.",
+                    ),
                     multivalue: true,
                     unique: false,
                     phantom: false,
-                    index: vec![IndexType::EQUALITY],
-                    syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+                    index: vec![],
+                    syntax: SyntaxType::UTF8STRING,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
-                String::from("claim"),
+                String::from("mincardinality"),
                 SchemaAttribute {
-                    name: String::from("claim"),
-                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_CLAIM)
+                    name: String::from("mincardinality"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_MINCARDINALITY)
                         .expect("unable to parse const uuid"),
-                    description: String::from("The spn of a claim this entry holds"),
-                    multivalue: true,
+                    description: String::from(
+                        "The minimum number of values this attribute must have when present.",
+                    ),
+                    multivalue: false,
                     unique: false,
-                    phantom: true,
+                    phantom: false,
                     index: vec![],
-                    syntax: SyntaxType::SERVICE_PRINCIPLE_NAME,
+                    syntax: SyntaxType::UINT32,
+                    ..Default::default()
                 },
             );
             self.attributes.insert(
-                String::from("password_import"),
+                String::from("maxcardinality"),
                 SchemaAttribute {
-                    name: String::from("password_import"),
-                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_PASSWORD_IMPORT)
+                    name: String::from("maxcardinality"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_ATTR_MAXCARDINALITY)
                         .expect("unable to parse const uuid"),
-                    description: String::from("An imported password hash from an external system."),
-                    multivalue: true,
+                    description: String::from(
+                        "The maximum number of values this attribute may have.",
+                    ),
+                    multivalue: false,
                     unique: false,
-                    phantom: true,
+                    phantom: false,
                     index: vec![],
-                    syntax: SyntaxType::UTF8STRING,
+                    syntax: SyntaxType::UINT32,
+                    ..Default::default()
                 },
             );
 
@@ -1117,7 +1891,18 @@ impl<'a> SchemaWriteTransaction<'a> {
                     uuid: Uuid::parse_str(UUID_SCHEMA_CLASS_ATTRIBUTETYPE)
                         .expect("unable to parse const uuid"),
                     description: String::from("Definition of a schema attribute"),
-                    systemmay: vec![String::from("phantom"), String::from("index")],
+                    systemmay: vec![
+                        String::from("phantom"),
+                        String::from("index"),
+                        String::from("minvalue"),
+                        String::from("maxvalue"),
+                        String::from("regex"),
+                        String::from("minlength"),
+                        String::from("maxlength"),
+                        String::from("allowedvalue"),
+                        String::from("mincardinality"),
+                        String::from("maxcardinality"),
+                    ],
                     may: vec![],
                     systemmust: vec![
                         String::from("class"),
@@ -1128,6 +1913,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                         String::from("description"),
                     ],
                     must: vec![],
+                    kind: ClassKind::Structural,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             self.classes.insert(
@@ -1142,6 +1930,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                         String::from("may"),
                         String::from("systemmust"),
                         String::from("must"),
+                        String::from("classkind"),
+                        String::from("systemexcludes"),
+                        String::from("excludes"),
                     ],
                     may: vec![],
                     systemmust: vec![
@@ -1150,6 +1941,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                         String::from("description"),
                     ],
                     must: vec![],
+                    kind: ClassKind::Structural,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             self.classes.insert(
@@ -1169,6 +1963,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                         String::from("last_modified_cid"),
                     ],
                     must: vec![],
+                    kind: ClassKind::Structural,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             self.classes.insert(
@@ -1185,6 +1982,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                     may: vec![],
                     systemmust: vec![],
                     must: vec![],
+                    kind: ClassKind::Auxiliary,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             self.classes.insert(
@@ -1200,6 +2000,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                     may: vec![],
                     systemmust: vec![],
                     must: vec![],
+                    kind: ClassKind::Auxiliary,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             /* These two classes are core to the entry lifecycle for recycling and tombstoning */
@@ -1213,6 +2016,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                     may: vec![],
                     systemmust: vec![],
                     must: vec![],
+                    kind: ClassKind::Auxiliary,
+                    systemexcludes: vec![String::from("tombstone")],
+                    excludes: vec![],
                 },
             );
             self.classes.insert(
@@ -1228,6 +2034,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                         String::from("uuid"),
                     ],
                     must: vec![],
+                    kind: ClassKind::Auxiliary,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             // sysinfo
@@ -1247,6 +2056,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                         // String::from("hostname"),
                     ],
                     must: vec![],
+                    kind: ClassKind::Structural,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             // ACP
@@ -1257,7 +2069,13 @@ impl<'a> SchemaWriteTransaction<'a> {
                     uuid: Uuid::parse_str(UUID_SCHEMA_CLASS_ACCESS_CONTROL_PROFILE)
                         .expect("unable to parse const uuid"),
                     description: String::from("System Access Control Profile Class"),
-                    systemmay: vec!["acp_enable".to_string(), "description".to_string()],
+                    systemmay: vec![
+                        "acp_enable".to_string(),
+                        "acp_deny".to_string(),
+                        "acp_valid_from".to_string(),
+                        "acp_valid_until".to_string(),
+                        "description".to_string(),
+                    ],
                     may: vec![],
                     systemmust: vec![
                         "acp_receiver".to_string(),
@@ -1265,6 +2083,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                         "name".to_string(),
                     ],
                     must: vec![],
+                    kind: ClassKind::Structural,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             self.classes.insert(
@@ -1278,6 +2099,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                     may: vec![],
                     systemmust: vec!["acp_search_attr".to_string()],
                     must: vec![],
+                    kind: ClassKind::Auxiliary,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             self.classes.insert(
@@ -1291,6 +2115,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                     may: vec![],
                     systemmust: vec![],
                     must: vec![],
+                    kind: ClassKind::Auxiliary,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             self.classes.insert(
@@ -1304,10 +2131,15 @@ impl<'a> SchemaWriteTransaction<'a> {
                         "acp_modify_removedattr".to_string(),
                         "acp_modify_presentattr".to_string(),
                         "acp_modify_class".to_string(),
+                        "acp_modify_presentvalue".to_string(),
+                        "acp_modify_presentrefer".to_string(),
                     ],
                     may: vec![],
                     systemmust: vec![],
                     must: vec![],
+                    kind: ClassKind::Auxiliary,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             self.classes.insert(
@@ -1324,6 +2156,9 @@ impl<'a> SchemaWriteTransaction<'a> {
                     may: vec![],
                     systemmust: vec![],
                     must: vec![],
+                    kind: ClassKind::Auxiliary,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
             self.classes.insert(
@@ -1337,6 +2172,27 @@ impl<'a> SchemaWriteTransaction<'a> {
                     may: vec![],
                     systemmust: vec![],
                     must: vec![],
+                    kind: ClassKind::Auxiliary,
+                    systemexcludes: vec![],
+                    excludes: vec![],
+                },
+            );
+            self.classes.insert(
+                String::from("uniqueconstraint"),
+                SchemaClass {
+                    name: String::from("uniqueconstraint"),
+                    uuid: Uuid::parse_str(UUID_SCHEMA_CLASS_UNIQUECONSTRAINT)
+                        .expect("unable to parse const uuid"),
+                    description: String::from(
+                        "A uniqueness rule spanning a combination of attributes.",
+                    ),
+                    systemmay: vec![String::from("description"), String::from("uniquescope")],
+                    may: vec![],
+                    systemmust: vec![String::from("name"), String::from("uniqueattr")],
+                    must: vec![],
+                    kind: ClassKind::Structural,
+                    systemexcludes: vec![],
+                    excludes: vec![],
                 },
             );
 
@@ -1364,6 +2220,10 @@ impl<'a> SchemaTransaction for SchemaWriteTransaction<'a> {
         self.attributes.to_snapshot()
     }
 
+    fn get_uniqueconstraints(&self) -> BptreeMapReadSnapshot<Uuid, SchemaUniqueConstraint> {
+        self.uniqueconstraints.to_snapshot()
+    }
+
     fn get_idxmeta(&self) -> BTreeSet<(String, IndexType)> {
         self.idxmeta.clone()
     }
@@ -1378,6 +2238,10 @@ impl SchemaTransaction for SchemaReadTransaction {
         self.attributes.to_snapshot()
     }
 
+    fn get_uniqueconstraints(&self) -> BptreeMapReadSnapshot<Uuid, SchemaUniqueConstraint> {
+        self.uniqueconstraints.to_snapshot()
+    }
+
     fn get_idxmeta(&self) -> BTreeSet<(String, IndexType)> {
         (*self.idxmeta).clone()
     }
@@ -1388,6 +2252,7 @@ impl Schema {
         let s = Schema {
             classes: BptreeMap::new(),
             attributes: BptreeMap::new(),
+            uniqueconstraints: BptreeMap::new(),
             idxmeta: CowCell::new(BTreeSet::new()),
         };
         let mut sw = s.write();
@@ -1403,6 +2268,7 @@ impl Schema {
         SchemaReadTransaction {
             classes: self.classes.read(),
             attributes: self.attributes.read(),
+            uniqueconstraints: self.uniqueconstraints.read(),
             idxmeta: self.idxmeta.read(),
         }
     }
@@ -1411,6 +2277,7 @@ impl Schema {
         SchemaWriteTransaction {
             classes: self.classes.write(),
             attributes: self.attributes.write(),
+            uniqueconstraints: self.uniqueconstraints.write(),
             idxmeta: self.idxmeta.write(),
         }
     }
@@ -1424,8 +2291,10 @@ mod tests {
     use kanidm_proto::v1::{ConsistencyError, SchemaError};
     // use crate::filter::{Filter, FilterValid};
     use crate::schema::SchemaTransaction;
-    use crate::schema::{IndexType, Schema, SchemaAttribute, SchemaClass, SyntaxType};
+    use crate::schema::{ClassKind, IndexType, Schema, SchemaAttribute, SchemaClass, SyntaxType};
     use crate::value::{PartialValue, Value};
+    use regex::Regex;
+    use std::collections::BTreeSet;
     use uuid::Uuid;
 
     // use crate::proto_v1::Filter as ProtoFilter;
@@ -1559,6 +2428,112 @@ mod tests {
                 SchemaAttribute
             );
 
+            // Bounds must be ordered.
+            sch_from_entry_err!(
+                audit,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "attributetype"],
+                        "attributename": ["schema_attr_test"],
+                        "uuid": ["66c68b2f-d02c-4243-8013-7946e40fe321"],
+                        "description": ["Test attr parsing"],
+                        "multivalue": ["false"],
+                        "unique": ["false"],
+                        "syntax": ["INT64"],
+                        "minvalue": ["10"],
+                        "maxvalue": ["-10"]
+                    }
+                }"#,
+                SchemaAttribute
+            );
+
+            // Bounds only apply to numeric syntaxes.
+            sch_from_entry_err!(
+                audit,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "attributetype"],
+                        "attributename": ["schema_attr_test"],
+                        "uuid": ["66c68b2f-d02c-4243-8013-7946e40fe321"],
+                        "description": ["Test attr parsing"],
+                        "multivalue": ["false"],
+                        "unique": ["false"],
+                        "syntax": ["UTF8STRING"],
+                        "minvalue": ["1"]
+                    }
+                }"#,
+                SchemaAttribute
+            );
+
+            sch_from_entry_ok!(
+                audit,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "attributetype"],
+                        "attributename": ["schema_attr_test"],
+                        "uuid": ["66c68b2f-d02c-4243-8013-7946e40fe321"],
+                        "description": ["Test attr parsing"],
+                        "multivalue": ["false"],
+                        "unique": ["false"],
+                        "syntax": ["UINT32"],
+                        "minvalue": ["1"],
+                        "maxvalue": ["10"]
+                    }
+                }"#,
+                SchemaAttribute
+            );
+
+            // Regex must compile.
+            sch_from_entry_err!(
+                audit,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "attributetype"],
+                        "attributename": ["schema_attr_test"],
+                        "uuid": ["66c68b2f-d02c-4243-8013-7946e40fe321"],
+                        "description": ["Test attr parsing"],
+                        "multivalue": ["false"],
+                        "unique": ["false"],
+                        "syntax": ["UTF8STRING"],
+                        "regex": ["^(unclosed"]
+                    }
+                }"#,
+                SchemaAttribute
+            );
+
+            // Constraints parse.
+            sch_from_entry_ok!(
+                audit,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "attributetype"],
+                        "attributename": ["schema_attr_test"],
+                        "uuid": ["66c68b2f-d02c-4243-8013-7946e40fe321"],
+                        "description": ["Test attr parsing"],
+                        "multivalue": ["true"],
+                        "unique": ["false"],
+                        "syntax": ["UTF8STRING"],
+                        "regex": ["^[a-z]+$"],
+                        "minlength": ["1"],
+                        "maxlength": ["10"],
+                        "allowedvalue": ["a", "b"],
+                        "mincardinality": ["1"],
+                        "maxcardinality": ["2"]
+                    }
+                }"#,
+                SchemaAttribute
+            );
+
             // Index is allowed to be empty
             sch_from_entry_ok!(
                 audit,
@@ -1716,6 +2691,41 @@ mod tests {
                 }"#,
                 SchemaClass
             );
+
+            // Kinds and exclusions
+            sch_from_entry_ok!(
+                audit,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "classtype"],
+                        "classname": ["schema_class_test"],
+                        "description": ["class test"],
+                        "uuid": ["66c68b2f-d02c-4243-8013-7946e40fe321"],
+                        "classkind": ["structural"],
+                        "excludes": ["a"],
+                        "systemexcludes": ["b"]
+                    }
+                }"#,
+                SchemaClass
+            );
+
+            sch_from_entry_err!(
+                audit,
+                r#"{
+                    "valid": null,
+                    "state": null,
+                    "attrs": {
+                        "class": ["object", "classtype"],
+                        "classname": ["schema_class_test"],
+                        "description": ["class test"],
+                        "uuid": ["66c68b2f-d02c-4243-8013-7946e40fe321"],
+                        "classkind": ["abstract"]
+                    }
+                }"#,
+                SchemaClass
+            );
         });
     }
 
@@ -1734,6 +2744,7 @@ mod tests {
             phantom: false,
             index: vec![IndexType::EQUALITY],
             syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+            ..Default::default()
         };
 
         let r1 = single_value_string.validate_ava(&btreeset![Value::new_iutf8s("test")]);
@@ -1757,6 +2768,7 @@ mod tests {
             phantom: false,
             index: vec![IndexType::EQUALITY],
             syntax: SyntaxType::UTF8STRING,
+            ..Default::default()
         };
 
         let r5 = multi_value_string.validate_ava(&btreeset![
@@ -1775,6 +2787,7 @@ mod tests {
             phantom: false,
             index: vec![IndexType::EQUALITY],
             syntax: SyntaxType::BOOLEAN,
+            ..Default::default()
         };
 
         let r3 = multi_value_boolean.validate_ava(&btreeset![
@@ -1799,6 +2812,7 @@ mod tests {
             phantom: false,
             index: vec![IndexType::EQUALITY],
             syntax: SyntaxType::SYNTAX_ID,
+            ..Default::default()
         };
 
         let r6 =
@@ -1818,6 +2832,7 @@ mod tests {
             phantom: false,
             index: vec![IndexType::EQUALITY],
             syntax: SyntaxType::INDEX_ID,
+            ..Default::default()
         };
         //
         let r8 =
@@ -1828,6 +2843,118 @@ mod tests {
         assert_eq!(r9, Err(SchemaError::InvalidAttributeSyntax));
     }
 
+    #[test]
+    fn test_schema_attribute_int64_bounds() {
+        let bounded_int = SchemaAttribute {
+            name: String::from("bounded_int"),
+            uuid: Uuid::new_v4(),
+            description: String::from(""),
+            multivalue: true,
+            unique: false,
+            phantom: false,
+            index: vec![],
+            syntax: SyntaxType::INT64,
+            minvalue: Some(-10),
+            maxvalue: Some(10),
+            ..Default::default()
+        };
+
+        let r1 = bounded_int.validate_ava(&btreeset![
+            Value::new_int64(-10),
+            Value::new_int64(0),
+            Value::new_int64(10)
+        ]);
+        assert_eq!(r1, Ok(()));
+
+        let r2 = bounded_int.validate_ava(&btreeset![Value::new_int64(-11)]);
+        assert!(match r2 {
+            Err(SchemaError::ConstraintViolation(a, _)) => a == "bounded_int",
+            _ => false,
+        });
+
+        let r3 = bounded_int.validate_ava(&btreeset![Value::new_int64(0), Value::new_int64(11)]);
+        assert!(match r3 {
+            Err(SchemaError::ConstraintViolation(a, _)) => a == "bounded_int",
+            _ => false,
+        });
+
+        // Syntax is still checked before bounds.
+        let r4 = bounded_int.validate_ava(&btreeset![Value::new_uint32(5)]);
+        assert_eq!(r4, Err(SchemaError::InvalidAttributeSyntax));
+    }
+
+    #[test]
+    fn test_schema_attribute_constraints() {
+        let constrained_string = SchemaAttribute {
+            name: String::from("constrained"),
+            uuid: Uuid::new_v4(),
+            description: String::from(""),
+            multivalue: true,
+            unique: false,
+            phantom: false,
+            index: vec![],
+            syntax: SyntaxType::UTF8STRING_INSENSITIVE,
+            regex: Some(Regex::new("^[a-z]+$").unwrap()),
+            minlength: Some(2),
+            maxlength: Some(8),
+            mincardinality: Some(1),
+            maxcardinality: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            constrained_string.validate_ava(&btreeset![Value::new_iutf8s("abc")]),
+            Ok(())
+        );
+
+        let is_violation = |r: Result<(), SchemaError>| match r {
+            Err(SchemaError::ConstraintViolation(a, _)) => a == "constrained",
+            _ => false,
+        };
+
+        // regex
+        assert!(is_violation(
+            constrained_string.validate_ava(&btreeset![Value::new_iutf8s("abc1")])
+        ));
+        // length
+        assert!(is_violation(
+            constrained_string.validate_ava(&btreeset![Value::new_iutf8s("a")])
+        ));
+        assert!(is_violation(
+            constrained_string.validate_ava(&btreeset![Value::new_iutf8s("abcdefghi")])
+        ));
+        // cardinality
+        assert!(is_violation(
+            constrained_string.validate_ava(&BTreeSet::new())
+        ));
+        assert!(is_violation(constrained_string.validate_ava(&btreeset![
+            Value::new_iutf8s("aa"),
+            Value::new_iutf8s("bb"),
+            Value::new_iutf8s("cc")
+        ])));
+
+        let enumerated = SchemaAttribute {
+            name: String::from("constrained"),
+            uuid: Uuid::new_v4(),
+            description: String::from(""),
+            multivalue: false,
+            unique: false,
+            phantom: false,
+            index: vec![],
+            syntax: SyntaxType::UTF8STRING,
+            allowedvalue: vec!["/bin/sh".to_string(), "/bin/zsh".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            enumerated.validate_ava(&btreeset![Value::new_utf8s("/bin/zsh")]),
+            Ok(())
+        );
+        assert!(is_violation(
+            enumerated.validate_ava(&btreeset![Value::new_utf8s("/bin/fish")])
+        ));
+    }
+
     #[test]
     fn test_schema_simple() {
         let mut audit = AuditScope::new("test_schema_simple");
@@ -2005,7 +3132,7 @@ mod tests {
             Entry::unsafe_from_entry_str(
                 r#"{
             "attrs": {
-                "class": ["extensibleobject"],
+                "class": ["object", "extensibleobject"],
                 "name": ["TestPerson"],
                 "syntax": ["utf8string"],
                 "UUID": ["db237e8a-0079-4b8c-8a56-593b22aa44d1"],
@@ -2020,7 +3147,7 @@ mod tests {
             Entry::unsafe_from_entry_str(
                 r#"{
                 "attrs": {
-                    "class": ["extensibleobject"],
+                    "class": ["object", "extensibleobject"],
                     "name": ["testperson"],
                     "syntax": ["UTF8STRING"],
                     "uuid": ["db237e8a-0079-4b8c-8a56-593b22aa44d1"],
@@ -2085,7 +3212,7 @@ mod tests {
             Entry::unsafe_from_entry_str(
                 r#"{
             "attrs": {
-                "class": ["extensibleobject"],
+                "class": ["object", "extensibleobject"],
                 "uuid": ["db237e8a-0079-4b8c-8a56-593b22aa44d1"],
                 "multivalue": ["true"]
             }
@@ -2096,6 +3223,79 @@ mod tests {
 
         /* Is okay because extensible! */
         assert!(e_extensible.validate(&schema).is_ok());
+
+        // But extensible is auxiliary, so it still needs a structural class.
+        let e_extensible_no_structural: Entry<EntryInvalid, EntryNew> = unsafe {
+            Entry::unsafe_from_entry_str(
+                r#"{
+            "attrs": {
+                "class": ["extensibleobject"],
+                "uuid": ["db237e8a-0079-4b8c-8a56-593b22aa44d1"],
+                "multivalue": ["true"]
+            }
+        }"#,
+            )
+            .into_invalid_new()
+        };
+
+        assert_eq!(
+            e_extensible_no_structural.validate(&schema),
+            Err(SchemaError::NoStructuralClass)
+        );
+        println!("{}", audit);
+    }
+
+    #[test]
+    fn test_schema_class_kinds() {
+        let mut audit = AuditScope::new("test_schema_class_kinds");
+        let schema_outer = Schema::new(&mut audit).expect("failed to create schema");
+        let schema = schema_outer.read();
+        let classes = schema.get_classes();
+
+        let object = classes.get("object").expect("missing class");
+        let attributetype = classes.get("attributetype").expect("missing class");
+        let classtype = classes.get("classtype").expect("missing class");
+        let recycled = classes.get("recycled").expect("missing class");
+        let tombstone = classes.get("tombstone").expect("missing class");
+        let memberof = classes.get("memberof").expect("missing class");
+
+        // object alone is the most general structural class.
+        assert_eq!(SchemaClass::validate_class_set(&[object]), Ok(()));
+        // And is refined by any other structural class.
+        assert_eq!(
+            SchemaClass::validate_class_set(&[object, attributetype, memberof]),
+            Ok(())
+        );
+        assert_eq!(SchemaClass::validate_class_set(&[attributetype]), Ok(()));
+        assert_eq!(
+            SchemaClass::validate_class_set(&[memberof]),
+            Err(SchemaError::NoStructuralClass)
+        );
+        assert_eq!(
+            SchemaClass::validate_class_set(&[object, attributetype, classtype]),
+            Err(SchemaError::MultipleStructuralClasses(vec![
+                "attributetype".to_string(),
+                "classtype".to_string()
+            ]))
+        );
+        assert_eq!(
+            SchemaClass::validate_class_set(&[object, tombstone, recycled]),
+            Err(SchemaError::ExcludedClass(
+                "recycled".to_string(),
+                "tombstone".to_string()
+            ))
+        );
+
+        // Admin defined exclusions are honoured too.
+        let mut memberof_excl = memberof.clone();
+        memberof_excl.excludes = vec!["tombstone".to_string()];
+        assert_eq!(
+            SchemaClass::validate_class_set(&[object, &memberof_excl, tombstone]),
+            Err(SchemaError::ExcludedClass(
+                "memberof".to_string(),
+                "tombstone".to_string()
+            ))
+        );
         println!("{}", audit);
     }
 
@@ -2179,6 +3379,9 @@ mod tests {
             may: vec![],
             systemmust: vec![],
             must: vec![],
+            kind: ClassKind::Auxiliary,
+            systemexcludes: vec![],
+            excludes: vec![],
         };
 
         assert!(schema.update_classes(vec![class]).is_ok());
@@ -23,8 +23,8 @@ use crate::entry::{
     Entry, EntryCommitted, EntryInit, EntryInvalid, EntryNew, EntryReduced, EntrySealed, EntryValid,
 };
 use crate::event::{
-    CreateEvent, DeleteEvent, Event, EventOrigin, ExistsEvent, ModifyEvent, ReviveRecycledEvent,
-    SearchEvent,
+    AccessExplainEvent, CreateEvent, DeleteEvent, Event, EventOrigin, ExistsEvent, ModifyEvent,
+    ReviveRecycledEvent, SearchEvent,
 };
 use crate::filter::{f_eq, Filter, FilterInvalid, FilterValid};
 use crate::modify::{Modify, ModifyInvalid, ModifyList, ModifyValid};
@@ -32,14 +32,16 @@ use crate::plugins::Plugins;
 use crate::repl::cid::Cid;
 use crate::schema::{
     Schema, SchemaAttribute, SchemaClass, SchemaReadTransaction, SchemaTransaction,
-    SchemaWriteTransaction,
+    SchemaUniqueConstraint, SchemaWriteTransaction,
 };
+use crate::utils::duration_from_epoch_now;
 use crate::value::{PartialValue, SyntaxType, Value};
-use kanidm_proto::v1::{ConsistencyError, OperationError, SchemaError};
+use kanidm_proto::v1::{AccessExplainResponse, ConsistencyError, OperationError, SchemaError};
 
 lazy_static! {
     static ref PVCLASS_ATTRIBUTETYPE: PartialValue = PartialValue::new_class("attributetype");
     static ref PVCLASS_CLASSTYPE: PartialValue = PartialValue::new_class("classtype");
+    static ref PVCLASS_UNIQUECONSTRAINT: PartialValue = PartialValue::new_class("uniqueconstraint");
     static ref PVCLASS_TOMBSTONE: PartialValue = PartialValue::new_class("tombstone");
     static ref PVCLASS_RECYCLED: PartialValue = PartialValue::new_class("recycled");
     static ref PVCLASS_ACS: PartialValue = PartialValue::new_class("access_control_search");
@@ -48,6 +50,23 @@ lazy_static! {
     static ref PVCLASS_ACC: PartialValue = PartialValue::new_class("access_control_create");
     static ref PVCLASS_ACP: PartialValue = PartialValue::new_class("access_control_profile");
     static ref PVACP_ENABLE_FALSE: PartialValue = PartialValue::new_bool(false);
+    static ref PVUUID_IDM_ACP_EXPLAIN_PRIV: PartialValue =
+        PartialValue::new_refer_s(UUID_IDM_ACP_EXPLAIN_PRIV).expect("unable to parse const uuid");
+}
+
+// Distinguish values that are numeric but out of range from those that are not
+// numbers at all, so that clients can tell the user what was wrong.
+fn int64_syntax_error(value: &str) -> String {
+    if value.parse::<i128>().is_ok() {
+        format!(
+            "Invalid int64 syntax - {} overflows the range {} to {}",
+            value,
+            i64::min_value(),
+            i64::max_value()
+        )
+    } else {
+        format!("Invalid int64 syntax - {} is not an integer", value)
+    }
 }
 
 // This is the core of the server. It implements all
@@ -459,6 +478,47 @@ pub trait QueryServerTransaction {
         }
     }
 
+    /// Check that the requester may ask for access explanations. This must be done before
+    /// any names in the request are resolved, else the denial itself reveals whether they
+    /// exist.
+    fn check_explain_access(
+        &mut self,
+        au: &mut AuditScope,
+        event: &Event,
+    ) -> Result<(), OperationError> {
+        match &event.origin {
+            EventOrigin::Internal => Ok(()),
+            EventOrigin::User(e) => {
+                if e.attribute_value_pres("memberof", &PVUUID_IDM_ACP_EXPLAIN_PRIV) {
+                    Ok(())
+                } else {
+                    audit_log!(au, "Requester is not a member of idm_acp_explain_priv");
+                    Err(OperationError::AccessDenied)
+                }
+            }
+        }
+    }
+
+    /// Explain which access controls allow or deny the identity of an [`AccessExplainEvent`]
+    /// to perform an operation on its target. As this discloses the content of the access
+    /// profiles, the requester must be a member of idm_acp_explain_priv.
+    ///
+    /// [`AccessExplainEvent`]: ../event/struct.AccessExplainEvent.html
+    fn explain_access(
+        &mut self,
+        au: &mut AuditScope,
+        aee: &AccessExplainEvent,
+    ) -> Result<AccessExplainResponse, OperationError> {
+        self.check_explain_access(au, &aee.event)?;
+
+        let mut audit_acp = AuditScope::new("access_control_profiles");
+        let access = self.get_accesscontrols();
+        let res =
+            access.explain_operation(&mut audit_acp, &aee.identity, aee.operation, &aee.target);
+        au.append_scope(audit_acp);
+        res
+    }
+
     /// Do a schema aware conversion from a String:String to String:Value for modification
     /// present.
     fn clone_value(
@@ -528,6 +588,10 @@ pub trait QueryServerTransaction {
                     SyntaxType::UINT32 => Value::new_uint32_str(value)
                         .ok_or_else(|| OperationError::InvalidAttribute("Invalid uint32 syntax".to_string())),
                     SyntaxType::CID => Err(OperationError::InvalidAttribute("CIDs are generated and not able to be set.".to_string())),
+                    SyntaxType::INT64 => Value::new_int64_str(value)
+                        .ok_or_else(|| OperationError::InvalidAttribute(int64_syntax_error(value))),
+                    SyntaxType::JWS_KEY => Err(OperationError::InvalidAttribute("JWS keys are generated and not able to be set.".to_string())),
+                    SyntaxType::SESSION => Err(OperationError::InvalidAttribute("Sessions are generated and not able to be set.".to_string())),
                 }
             }
             None => {
@@ -614,6 +678,14 @@ pub trait QueryServerTransaction {
                     SyntaxType::CID => PartialValue::new_cid_s(value).ok_or_else(|| {
                         OperationError::InvalidAttribute("Invalid Cid syntax".to_string())
                     }),
+                    SyntaxType::INT64 => PartialValue::new_int64_str(value)
+                        .ok_or_else(|| OperationError::InvalidAttribute(int64_syntax_error(value))),
+                    SyntaxType::JWS_KEY => PartialValue::new_jws_key_id_s(value).ok_or_else(|| {
+                        OperationError::InvalidAttribute("Invalid JWS key id syntax".to_string())
+                    }),
+                    SyntaxType::SESSION => PartialValue::new_session_id_s(value).ok_or_else(|| {
+                        OperationError::InvalidAttribute("Invalid session id syntax".to_string())
+                    }),
                 }
             }
             None => {
@@ -712,16 +784,51 @@ impl<'a> QueryServerReadTransaction<'a> {
          */
 
         // Ok BE passed, lets move on to the content.
+        //  * class combinations (req be + sch)
+        let cl_errs = self.verify_entry_classes(&mut audit);
+
         // Most of our checks are in the plugins, so we let them
         // do their job.
 
         // Now, call the plugins verification system.
-        let pl_errs = Plugins::run_verify(&mut audit, self);
+        let mut pl_errs = Plugins::run_verify(&mut audit, self);
+        pl_errs.extend(cl_errs);
 
         // Finish up ...
         au.append_scope(audit);
         pl_errs
     }
+
+    // Entries written before their classes gained kinds or exclusions may now
+    // hold a combination that schema would refuse, so check every live entry.
+    fn verify_entry_classes(&mut self, au: &mut AuditScope) -> Vec<Result<(), ConsistencyError>> {
+        let all_cand = match self.internal_search(au, filter!(f_pres("class"))) {
+            Ok(all_cand) => all_cand,
+            Err(_) => return vec![Err(ConsistencyError::QueryServerSearchFailure)],
+        };
+
+        let class_snapshot = self.get_schema().get_classes();
+
+        all_cand
+            .iter()
+            .filter_map(|e| {
+                let classes: Vec<&SchemaClass> = e
+                    .classes()
+                    .map(|ecs| {
+                        ecs.filter_map(|c| c.as_string().and_then(|s| class_snapshot.get(s)))
+                            .collect()
+                    })
+                    .unwrap_or_else(Vec::new);
+                match SchemaClass::validate_class_set(classes.as_slice()) {
+                    Ok(_) => None,
+                    Err(err) => {
+                        audit_log!(au, "Invalid class set on entry {} -> {:?}", e.get_id(), err);
+                        Some(Err(ConsistencyError::InvalidClassSet(e.get_id())))
+                    }
+                }
+            })
+            .collect()
+    }
 }
 
 pub struct QueryServerWriteTransaction<'a> {
@@ -790,7 +897,9 @@ impl QueryServer {
         QueryServerReadTransaction {
             be_txn: self.be.read(),
             schema: self.schema.read(),
-            accesscontrols: self.accesscontrols.read(),
+            // Read transactions have no cid, so time bounded acps are checked
+            // against the time the transaction began.
+            accesscontrols: self.accesscontrols.read(duration_from_epoch_now()),
         }
     }
 
@@ -813,7 +922,7 @@ impl QueryServer {
             cid,
             be_txn: self.be.write(idxmeta),
             schema: schema_write,
-            accesscontrols: self.accesscontrols.write(),
+            accesscontrols: self.accesscontrols.write(ts),
             changed_schema: false,
             changed_acp: false,
         }
@@ -879,6 +988,11 @@ impl QueryServer {
 }
 
 impl<'a> QueryServerWriteTransaction<'a> {
+    /// The time this transaction began at, which is the time of any changes it makes.
+    pub(crate) fn get_curtime(&self) -> Duration {
+        self.cid.ts
+    }
+
     pub fn create(&mut self, au: &mut AuditScope, ce: &CreateEvent) -> Result<(), OperationError> {
         // The create event is a raw, read only representation of the request
         // that was made to us, including information about the identity
@@ -956,6 +1070,13 @@ impl<'a> QueryServerWriteTransaction<'a> {
 
         try_audit!(au, plug_pre_res, "Create operation failed (plugin), {:?}");
 
+        let acp_res = self.validate_acp_candidates(au, &norm_cand);
+        try_audit!(
+            au,
+            acp_res,
+            "Create operation failed (access control), {:?}"
+        );
+
         let mut audit_be = AuditScope::new("backend_create");
         // We may change from ce.entries later to something else?
         let res = self.be_txn.create(&mut audit_be, norm_cand).map_err(|e| e);
@@ -987,6 +1108,7 @@ impl<'a> QueryServerWriteTransaction<'a> {
             } else {
                 e.attribute_value_pres("class", &PVCLASS_CLASSTYPE)
                     || e.attribute_value_pres("class", &PVCLASS_ATTRIBUTETYPE)
+                    || e.attribute_value_pres("class", &PVCLASS_UNIQUECONSTRAINT)
             }
         });
         self.changed_acp = commit_cand.iter().fold(false, |acc, e| {
@@ -1118,6 +1240,7 @@ impl<'a> QueryServerWriteTransaction<'a> {
             } else {
                 e.attribute_value_pres("class", &PVCLASS_CLASSTYPE)
                     || e.attribute_value_pres("class", &PVCLASS_ATTRIBUTETYPE)
+                    || e.attribute_value_pres("class", &PVCLASS_UNIQUECONSTRAINT)
             }
         });
         self.changed_acp = del_cand.iter().fold(false, |acc, e| {
@@ -1233,6 +1356,33 @@ impl<'a> QueryServerWriteTransaction<'a> {
         res
     }
 
+    pub fn purge_expired_acps(&mut self, au: &mut AuditScope) -> Result<(), OperationError> {
+        // Send every acp that can never apply again to the recycle bin. They
+        // are ignored by access controls already, this just tidies them away,
+        // and they can still be revived if this was a mistake.
+        // The window ends before acp_valid_until, so once it's reached the acp has
+        // expired.
+        let ct = self.cid.ts.as_secs() as i64;
+        let f_expired = filter!(f_and!([
+            f_eq("class", PVCLASS_ACP.clone()),
+            f_lt("acp_valid_until", PartialValue::new_int64(ct + 1)),
+        ]));
+
+        let rc = match self.internal_search(au, f_expired.clone()) {
+            Ok(r) => r,
+            Err(e) => return Err(e),
+        };
+
+        if rc.is_empty() {
+            audit_log!(au, "No expired acps present - purge operation success");
+            return Ok(());
+        }
+
+        let res = self.internal_delete(au, f_expired);
+        audit_log!(au, "Purge expired acps operation result: {:?}", res);
+        res
+    }
+
     // Should this take a revive event?
     pub fn revive_recycled(
         &mut self,
@@ -1363,11 +1513,39 @@ impl<'a> QueryServerWriteTransaction<'a> {
             }
         };
 
+        // Profiles may restrict which entries a reference can be made to, so look up
+        // the targets of any being added. Internal operations skip access checks.
+        let refer_entries = match me.event.origin {
+            EventOrigin::Internal => Vec::new(),
+            EventOrigin::User(_) => {
+                let refer_uuids: BTreeSet<Uuid> = me
+                    .modlist
+                    .iter()
+                    .filter_map(|m| match m {
+                        Modify::Present(_, v) => v.to_ref_uuid().copied(),
+                        _ => None,
+                    })
+                    .collect();
+                if refer_uuids.is_empty() {
+                    Vec::new()
+                } else {
+                    let filt = filter!(f_or(
+                        refer_uuids
+                            .into_iter()
+                            .map(|u| f_eq("uuid", PartialValue::new_uuid(u)))
+                            .collect()
+                    ));
+                    try_audit!(au, self.internal_search(au, filt))
+                }
+            }
+        };
+
         // Are we allowed to make the changes we want to?
         // modify_allow_operation
         let mut audit_acp = AuditScope::new("access_control_profiles");
         let access = self.get_accesscontrols();
-        let acp_res = access.modify_allow_operation(&mut audit_acp, me, &pre_candidates);
+        let acp_res =
+            access.modify_allow_operation(&mut audit_acp, me, &pre_candidates, &refer_entries);
         au.append_scope(audit_acp);
         if !try_audit!(au, acp_res) {
             return Err(OperationError::AccessDenied);
@@ -1416,6 +1594,13 @@ impl<'a> QueryServerWriteTransaction<'a> {
             Err(e) => return Err(OperationError::SchemaViolation(e)),
         };
 
+        let acp_res = self.validate_acp_candidates(au, &norm_cand);
+        try_audit!(
+            au,
+            acp_res,
+            "Modify operation failed (access control), {:?}"
+        );
+
         // Backend Modify
         let mut audit_be = AuditScope::new("backend_modify");
 
@@ -1462,6 +1647,7 @@ impl<'a> QueryServerWriteTransaction<'a> {
                     } else {
                         e.attribute_value_pres("class", &PVCLASS_CLASSTYPE)
                             || e.attribute_value_pres("class", &PVCLASS_ATTRIBUTETYPE)
+                            || e.attribute_value_pres("class", &PVCLASS_UNIQUECONSTRAINT)
                     }
                 });
         self.changed_acp = norm_cand
@@ -1767,6 +1953,19 @@ impl<'a> QueryServerWriteTransaction<'a> {
             JSON_SCHEMA_ATTR_BADLIST_PASSWORD,
             JSON_SCHEMA_ATTR_LOGINSHELL,
             JSON_SCHEMA_ATTR_UNIX_PASSWORD,
+            JSON_SCHEMA_ATTR_MANAGER,
+            JSON_SCHEMA_ATTR_OWNER,
+            JSON_SCHEMA_ATTR_ACCOUNT_VALID_FROM,
+            JSON_SCHEMA_ATTR_ACCOUNT_EXPIRE,
+            JSON_SCHEMA_ATTR_PW_MIN_LENGTH,
+            JSON_SCHEMA_ATTR_PW_MIN_SCORE,
+            JSON_SCHEMA_ATTR_PW_BADLIST,
+            JSON_SCHEMA_ATTR_PW_REQUIRE_MFA,
+            JSON_SCHEMA_ATTR_PW_MAX_AGE,
+            JSON_SCHEMA_ATTR_APP_PASSWORD,
+            JSON_SCHEMA_ATTR_CREDENTIAL_RESET_TOKEN,
+            JSON_SCHEMA_ATTR_JWS_SIGNING_KEY,
+            JSON_SCHEMA_ATTR_SESSION,
             JSON_SCHEMA_CLASS_PERSON,
             JSON_SCHEMA_CLASS_GROUP,
             JSON_SCHEMA_CLASS_ACCOUNT,
@@ -1774,6 +1973,7 @@ impl<'a> QueryServerWriteTransaction<'a> {
             JSON_SCHEMA_CLASS_POSIXACCOUNT,
             JSON_SCHEMA_CLASS_POSIXGROUP,
             JSON_SCHEMA_CLASS_SYSTEM_CONFIG,
+            JSON_SCHEMA_CLASS_PASSWORD_POLICY,
         ];
 
         let mut audit_si = AuditScope::new("start_initialise_schema_idm");
@@ -1860,6 +2060,7 @@ impl<'a> QueryServerWriteTransaction<'a> {
             JSON_IDM_HP_GROUP_MANAGE_PRIV_V1,
             JSON_IDM_HP_GROUP_WRITE_PRIV_V1,
             JSON_IDM_ACP_MANAGE_PRIV_V1,
+            JSON_IDM_ACP_EXPLAIN_PRIV_V1,
             JSON_DOMAIN_ADMINS,
             JSON_IDM_HIGH_PRIVILEGE_V1,
             // Built in access controls.
@@ -1885,6 +2086,10 @@ impl<'a> QueryServerWriteTransaction<'a> {
             JSON_IDM_ACP_HP_GROUP_MANAGE_PRIV_V1,
             JSON_IDM_ACP_SCHEMA_WRITE_ATTRS_PRIV_V1,
             JSON_IDM_ACP_SCHEMA_WRITE_CLASSES_PRIV_V1,
+            JSON_IDM_ACP_SCHEMA_WRITE_UNIQUE_CONSTRAINTS_PRIV_V1,
+            JSON_IDM_ACP_DENY_SYSTEM_ADMINS_MANAGE_V1,
+            JSON_IDM_ACP_MANAGER_WRITE_REPORTS_V1,
+            JSON_IDM_ACP_GROUP_OWNER_MANAGE_MEMBERS_V1,
             JSON_IDM_ACP_ACP_MANAGE_PRIV_V1,
             JSON_IDM_ACP_DOMAIN_ADMIN_PRIV_V1,
             JSON_IDM_ACP_SYSTEM_CONFIG_PRIV_V1,
@@ -1892,6 +2097,7 @@ impl<'a> QueryServerWriteTransaction<'a> {
             JSON_IDM_ACP_GROUP_UNIX_EXTEND_PRIV_V1,
             JSON_IDM_ACP_PEOPLE_ACCOUNT_PASSWORD_IMPORT_PRIV_V1,
             JSON_IDM_ACP_PEOPLE_EXTEND_PRIV_V1,
+            JSON_IDM_ACP_GROUP_PASSWORD_POLICY_PRIV_V1,
         ];
 
         let res: Result<(), _> = idm_entries
@@ -1935,6 +2141,21 @@ impl<'a> QueryServerWriteTransaction<'a> {
 
         try_audit!(audit, self.schema.update_classes(classtypes));
 
+        // find all unique constraints - these need the updated attributes to
+        // validate their scope filters.
+        let filt = filter!(f_eq("class", PVCLASS_UNIQUECONSTRAINT.clone()));
+        let res = try_audit!(audit, self.internal_search(audit, filt));
+        let uniqueconstraints: Result<Vec<_>, _> = res
+            .iter()
+            .map(|e| SchemaUniqueConstraint::try_from(audit, self, e))
+            .collect();
+        let uniqueconstraints = try_audit!(audit, uniqueconstraints);
+
+        try_audit!(
+            audit,
+            self.schema.update_uniqueconstraints(uniqueconstraints)
+        );
+
         // validate.
         let valid_r = self.schema.validate(audit);
 
@@ -1948,6 +2169,52 @@ impl<'a> QueryServerWriteTransaction<'a> {
         }
     }
 
+    /// Parse any access control profiles amongst the written candidates, so that a
+    /// malformed profile (ie a target filter naming an attribute that doesn't exist)
+    /// is rejected with the operation rather than failing the reload at commit.
+    fn validate_acp_candidates<STATE>(
+        &mut self,
+        audit: &mut AuditScope,
+        cands: &[Entry<EntrySealed, STATE>],
+    ) -> Result<(), OperationError> {
+        // A profile may depend on schema written in this same transaction, which
+        // isn't loaded until commit - in that case the reload will catch it there.
+        let schema_pending = self.changed_schema
+            || cands.iter().any(|e| {
+                e.attribute_value_pres("class", &PVCLASS_CLASSTYPE)
+                    || e.attribute_value_pres("class", &PVCLASS_ATTRIBUTETYPE)
+            });
+        if schema_pending {
+            audit_log!(
+                audit,
+                "Schema change pending, deferring ACP validation to commit"
+            );
+            return Ok(());
+        }
+
+        cands
+            .iter()
+            .filter(|e| {
+                e.attribute_value_pres("class", &PVCLASS_ACP)
+                    && !e.attribute_value_pres("acp_enable", &PVACP_ENABLE_FALSE)
+            })
+            .try_for_each(|e| {
+                if e.attribute_value_pres("class", &PVCLASS_ACS) {
+                    AccessControlSearch::try_from(audit, self, e)?;
+                }
+                if e.attribute_value_pres("class", &PVCLASS_ACC) {
+                    AccessControlCreate::try_from(audit, self, e)?;
+                }
+                if e.attribute_value_pres("class", &PVCLASS_ACM) {
+                    AccessControlModify::try_from(audit, self, e)?;
+                }
+                if e.attribute_value_pres("class", &PVCLASS_ACD) {
+                    AccessControlDelete::try_from(audit, self, e)?;
+                }
+                Ok(())
+            })
+    }
+
     fn reload_accesscontrols(&mut self, audit: &mut AuditScope) -> Result<(), OperationError> {
         // supply entries to the writable access controls to reload from.
         // This has to be done in FOUR passes - one for each type!
@@ -2103,15 +2370,24 @@ impl<'a> QueryServerWriteTransaction<'a> {
 
 #[cfg(test)]
 mod tests {
+    use crate::access::AccessControlsTransaction;
     use crate::audit::AuditScope;
-    use crate::constants::{CHANGELOG_MAX_AGE, JSON_ADMIN_V1, RECYCLEBIN_MAX_AGE, UUID_ADMIN};
-    use crate::credential::Credential;
+    use crate::constants::{
+        CHANGELOG_MAX_AGE, JSON_ADMIN_V1, RECYCLEBIN_MAX_AGE, UUID_ADMIN, UUID_ANONYMOUS,
+        UUID_DOES_NOT_EXIST,
+    };
+    use crate::credential::{Credential, CryptoPolicy};
     use crate::entry::{Entry, EntryInit, EntryNew};
-    use crate::event::{CreateEvent, DeleteEvent, ModifyEvent, ReviveRecycledEvent, SearchEvent};
+    use crate::event::{
+        AccessExplainEvent, CreateEvent, DeleteEvent, Event, ModifyEvent, ReviveRecycledEvent,
+        SearchEvent,
+    };
     use crate::modify::{Modify, ModifyList};
     use crate::server::{QueryServerTransaction, QueryServerWriteTransaction};
     use crate::value::{PartialValue, Value};
-    use kanidm_proto::v1::{OperationError, SchemaError};
+    use kanidm_proto::v1::{
+        AccessOperation, AccessProfileDecision, ConsistencyError, OperationError, SchemaError,
+    };
     use std::time::Duration;
     use uuid::Uuid;
 
@@ -2711,6 +2987,171 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_qs_purge_expired_acps() {
+        run_test!(|server: &QueryServer, audit: &mut AuditScope| {
+            let time_p1 = duration_from_epoch_now();
+            let valid_until = time_p1.as_secs() + 60;
+            let time_p2 = Duration::from_secs(valid_until);
+
+            let e1: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(&format!(
+                r#"{{
+                "attrs": {{
+                    "class": ["object", "access_control_profile", "access_control_delete"],
+                    "name": ["acp_temporary"],
+                    "uuid": ["c1b7d4a8-2e5f-4c3d-9a6b-0f8e7d6c5b41"],
+                    "acp_receiver": ["{{\"Eq\":[\"name\",\"admin\"]}}"],
+                    "acp_targetscope": ["{{\"Eq\":[\"name\",\"testperson1\"]}}"],
+                    "acp_valid_until": ["{}"]
+                }}
+            }}"#,
+                valid_until
+            ));
+
+            let filt_acp = filter!(f_eq("name", PartialValue::new_iutf8s("acp_temporary")));
+            let filt_acp_rc = filter_all!(f_and!([
+                f_eq("name", PartialValue::new_iutf8s("acp_temporary")),
+                f_eq("class", PartialValue::new_class("recycled")),
+            ]));
+
+            let mut server_txn = server.write(time_p1);
+            let ce = CreateEvent::new_internal(vec![e1]);
+            assert!(server_txn.create(audit, &ce).is_ok());
+
+            // It's still valid, so the purge leaves it alone.
+            assert!(server_txn.purge_expired_acps(audit).is_ok());
+            let r1 = server_txn
+                .internal_search(audit, filt_acp.clone())
+                .expect("internal search failed");
+            assert!(r1.len() == 1);
+            assert!(server_txn.commit(audit).is_ok());
+
+            // Once its acp_valid_until is reached it has expired, and it's moved to the
+            // recycle bin.
+            let mut server_txn = server.write(time_p2);
+            assert!(server_txn.purge_expired_acps(audit).is_ok());
+            let r2 = server_txn
+                .internal_search(audit, filt_acp)
+                .expect("internal search failed");
+            assert!(r2.is_empty());
+            let r3 = server_txn
+                .internal_search(audit, filt_acp_rc)
+                .expect("internal search failed");
+            assert!(r3.len() == 1);
+
+            // The system acps have no bounds, so they are untouched, and a second
+            // purge has nothing to do.
+            assert!(server_txn.purge_expired_acps(audit).is_ok());
+            assert!(server_txn.commit(audit).is_ok());
+        })
+    }
+
+    #[test]
+    fn test_qs_validate_acp_on_write() {
+        run_test!(|server: &QueryServer, audit: &mut AuditScope| {
+            let mut server_txn = server.write(duration_from_epoch_now());
+
+            // A target filter on an attribute that doesn't exist is rejected on create.
+            let e_bad: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "access_control_profile", "access_control_delete"],
+                    "name": ["acp_invalid"],
+                    "uuid": ["c1b7d4a8-2e5f-4c3d-9a6b-0f8e7d6c5b42"],
+                    "acp_receiver": ["{\"Eq\":[\"name\",\"admin\"]}"],
+                    "acp_targetscope": ["{\"Eq\":[\"not_an_attribute\",\"x\"]}"]
+                }
+            }"#,
+            );
+            let ce = CreateEvent::new_internal(vec![e_bad]);
+            assert!(server_txn.create(audit, &ce).is_err());
+
+            // A valid profile is accepted ...
+            let e_good: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "access_control_profile", "access_control_delete"],
+                    "name": ["acp_valid"],
+                    "uuid": ["c1b7d4a8-2e5f-4c3d-9a6b-0f8e7d6c5b43"],
+                    "acp_receiver": ["{\"Eq\":[\"name\",\"admin\"]}"],
+                    "acp_targetscope": ["{\"Eq\":[\"name\",\"testperson1\"]}"]
+                }
+            }"#,
+            );
+            let ce = CreateEvent::new_internal(vec![e_good]);
+            assert!(server_txn.create(audit, &ce).is_ok());
+
+            // ... but can't then be modified to an invalid one.
+            let r = server_txn.internal_modify(
+                audit,
+                filter!(f_eq("name", PartialValue::new_iutf8s("acp_valid"))),
+                ModifyList::new_purge_and_set(
+                    "acp_targetscope",
+                    Value::new_json_filter("{\"Eq\":[\"not_an_attribute\",\"x\"]}")
+                        .expect("invalid filter"),
+                ),
+            );
+            assert!(r.is_err());
+            assert!(server_txn.commit(audit).is_ok());
+        })
+    }
+
+    #[test]
+    fn test_qs_acp_manager_system_deny() {
+        run_test!(|server: &QueryServer, audit: &mut AuditScope| {
+            let mut server_txn = server.write(duration_from_epoch_now());
+            // Admin manages access profiles through system_admins.
+            let admin = server_txn
+                .internal_search_uuid(audit, &UUID_ADMIN)
+                .expect("failed");
+            let f_deny = filter!(f_eq(
+                "name",
+                PartialValue::new_iutf8s("idm_acp_deny_system_admins_manage")
+            ));
+
+            // Clearing the flag would turn the deny into an allow.
+            let me = unsafe {
+                ModifyEvent::new_impersonate_entry(
+                    admin.clone(),
+                    f_deny.clone(),
+                    ModifyList::new_list(vec![Modify::Purged("acp_deny".to_string())]),
+                )
+            };
+            assert!(server_txn.modify(audit, &me).is_err());
+
+            // Nor can the deny be disabled or deleted.
+            let me = unsafe {
+                ModifyEvent::new_impersonate_entry(
+                    admin.clone(),
+                    f_deny.clone(),
+                    ModifyList::new_purge_and_set("acp_enable", Value::new_bool(false)),
+                )
+            };
+            assert!(server_txn.modify(audit, &me).is_err());
+            let de = unsafe { DeleteEvent::new_impersonate_entry(admin.clone(), f_deny) };
+            assert!(server_txn.delete(audit, &de).is_err());
+
+            // And a new profile can't be a deny.
+            let e: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "access_control_profile", "access_control_delete"],
+                    "name": ["acp_new_deny"],
+                    "acp_deny": ["true"],
+                    "acp_receiver": ["{\"Eq\":[\"name\",\"admin\"]}"],
+                    "acp_targetscope": ["{\"Eq\":[\"name\",\"testperson1\"]}"]
+                }
+            }"#,
+            );
+            let ce = CreateEvent {
+                event: Event::from_impersonate_entry(admin),
+                entries: vec![e],
+            };
+            assert!(server_txn.create(audit, &ce).is_err());
+            assert!(server_txn.commit(audit).is_ok());
+        })
+    }
+
     // The delete test above should be unaffected by recycle anyway
     #[test]
     fn test_qs_recycle_advanced() {
@@ -3117,6 +3558,154 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_qs_dynamic_schema_int64() {
+        run_test!(|server: &QueryServer, audit: &mut AuditScope| {
+            // Attribute definition with bounds
+            let e_ad: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "attributetype"],
+                    "attributename": ["testint"],
+                    "uuid": ["cfcae205-31c3-484b-8ced-667d1709c5e3"],
+                    "description": ["Test Attribute"],
+                    "multivalue": ["false"],
+                    "unique": ["false"],
+                    "syntax": ["INT64"],
+                    "minvalue": ["-100"],
+                    "maxvalue": ["100"]
+                }
+            }"#,
+            );
+
+            let mut server_txn = server.write(duration_from_epoch_now());
+            let ce_attr = CreateEvent::new_internal(vec![e_ad]);
+            assert!(server_txn.create(audit, &ce_attr).is_ok());
+            server_txn.commit(audit).expect("should not fail");
+
+            let mut server_txn = server.write(duration_from_epoch_now());
+
+            // Proto string conversion, with distinct errors on overflow.
+            let r1 = server_txn.clone_value(audit, "testint", "-42");
+            assert!(r1 == Ok(Value::new_int64(-42)));
+            let r2 = server_txn.clone_value(audit, "testint", "99999999999999999999");
+            assert!(match r2 {
+                Err(OperationError::InvalidAttribute(m)) => m.contains("overflows"),
+                _ => false,
+            });
+            let r3 = server_txn.clone_value(audit, "testint", "forty two");
+            assert!(match r3 {
+                Err(OperationError::InvalidAttribute(m)) => m.contains("not an integer"),
+                _ => false,
+            });
+
+            let mk_entry = |u: &str, i: i64| {
+                let mut e: Entry<EntryInit, EntryNew> = Entry::new();
+                e.add_ava("class", &Value::new_class("object"));
+                e.add_ava("class", &Value::new_class("extensibleobject"));
+                e.add_ava("uuid", &Value::new_uuids(u).unwrap());
+                e.add_ava("testint", &Value::new_int64(i));
+                e
+            };
+
+            // Out of bounds is rejected by schema.
+            let ce_fail = CreateEvent::new_internal(vec![mk_entry(
+                "cc8e95b4-c24f-4d68-ba54-8bed76f63930",
+                101,
+            )]);
+            assert!(server_txn.create(audit, &ce_fail).is_err());
+
+            let ce_work = CreateEvent::new_internal(vec![
+                mk_entry("cc8e95b4-c24f-4d68-ba54-8bed76f63930", -100),
+                mk_entry("cc8e95b4-c24f-4d68-ba54-8bed76f63931", -5),
+                mk_entry("cc8e95b4-c24f-4d68-ba54-8bed76f63932", 50),
+            ]);
+            assert!(server_txn.create(audit, &ce_work).is_ok());
+
+            // Range filter, -10 <= testint < 60
+            let filt = filter!(f_and!([
+                f_lt("testint", PartialValue::new_int64(60)),
+                f_andnot(f_lt("testint", PartialValue::new_int64(-10)))
+            ]));
+            let r = server_txn
+                .internal_search(audit, filt)
+                .expect("search failed");
+            assert!(r.len() == 2);
+
+            server_txn.commit(audit).expect("should not fail");
+        })
+    }
+
+    #[test]
+    fn test_qs_verify_class_rules() {
+        run_test!(|server: &QueryServer, audit: &mut AuditScope| {
+            let e_person: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "person", "extensibleobject"],
+                    "name": ["testperson"],
+                    "description": ["testperson"],
+                    "displayname": ["testperson"],
+                    "uuid": ["cc8e95b4-c24f-4d68-ba54-8bed76f63930"]
+                }
+            }"#,
+            );
+
+            let mut server_txn = server.write(duration_from_epoch_now());
+            let ce = CreateEvent::new_internal(vec![e_person]);
+            assert!(server_txn.create(audit, &ce).is_ok());
+
+            // A person is never a group.
+            let e_group: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "person", "group"],
+                    "name": ["testgroup"],
+                    "displayname": ["testgroup"],
+                    "uuid": ["cc8e95b4-c24f-4d68-ba54-8bed76f63931"]
+                }
+            }"#,
+            );
+            let ce = CreateEvent::new_internal(vec![e_group]);
+            assert!(
+                server_txn.create(audit, &ce)
+                    == Err(OperationError::SchemaViolation(SchemaError::ExcludedClass(
+                        "person".to_string(),
+                        "group".to_string()
+                    )))
+            );
+
+            // Excluding a class that entries already hold is allowed, but verify
+            // must now report them.
+            assert!(server_txn
+                .internal_modify(
+                    audit,
+                    filter!(f_eq("classname", PartialValue::new_iutf8s("person"))),
+                    modlist!([m_pres("excludes", &Value::new_class("extensibleobject"))])
+                )
+                .is_ok());
+            server_txn.commit(audit).expect("should not fail");
+
+            let verifications = server.verify(audit);
+            assert!(verifications.len() == 1);
+            assert!(match verifications[0] {
+                Err(ConsistencyError::InvalidClassSet(_)) => true,
+                _ => false,
+            });
+
+            // Repair the schema so the entry is valid again.
+            let mut server_txn = server.write(duration_from_epoch_now());
+            assert!(server_txn
+                .internal_modify(
+                    audit,
+                    filter!(f_eq("classname", PartialValue::new_iutf8s("person"))),
+                    modlist!([m_purge("excludes")])
+                )
+                .is_ok());
+            server_txn.commit(audit).expect("should not fail");
+        })
+    }
+
     #[test]
     fn test_qs_modify_password_only() {
         run_test!(|server: &QueryServer, audit: &mut AuditScope| {
@@ -3139,7 +3728,7 @@ mod tests {
             assert!(cr.is_ok());
 
             // Build the credential.
-            let cred = Credential::new_password_only("test_password");
+            let cred = Credential::new_password_only(&CryptoPolicy::minimum(), "test_password");
             let v_cred = Value::new_credential("primary", cred);
             assert!(v_cred.validate());
 
@@ -3397,4 +3986,321 @@ mod tests {
         })
     }
     */
+
+    #[test]
+    fn test_qs_explain_access() {
+        run_test!(|server: &QueryServer, audit: &mut AuditScope| {
+            let mut server_txn = server.write(duration_from_epoch_now());
+            let admin = server_txn
+                .internal_search_uuid(audit, &UUID_ADMIN)
+                .expect("failed");
+            let anon = server_txn
+                .internal_search_uuid(audit, &UUID_ANONYMOUS)
+                .expect("failed");
+
+            // Admin is in idm_acp_explain_priv through system_admins, so can explain what
+            // anonymous can see of admin.
+            let aee = unsafe {
+                AccessExplainEvent::new_impersonate_entry(
+                    admin.clone(),
+                    anon.clone(),
+                    admin.clone(),
+                    AccessOperation::Search,
+                )
+            };
+            let r = server_txn
+                .explain_access(audit, &aee)
+                .expect("explain failed");
+            assert!(r.allowed);
+            assert!(r
+                .profiles
+                .iter()
+                .any(|p| p.name == "idm_all_acp_read"
+                    && p.decision == AccessProfileDecision::Applied));
+            assert!(r.allowed_attrs.contains(&"name".to_string()));
+            assert!(!r.allowed_attrs.contains(&"acp_receiver".to_string()));
+
+            // Anonymous is not in idm_acp_explain_priv, so may not ask.
+            let aee = unsafe {
+                AccessExplainEvent::new_impersonate_entry(
+                    anon.clone(),
+                    anon.clone(),
+                    admin.clone(),
+                    AccessOperation::Search,
+                )
+            };
+            assert!(
+                server_txn.explain_access(audit, &aee).err() == Some(OperationError::AccessDenied)
+            );
+            assert!(
+                server_txn
+                    .check_explain_access(audit, &Event::from_impersonate_entry(anon.clone()))
+                    .err()
+                    == Some(OperationError::AccessDenied)
+            );
+            drop(server_txn);
+
+            // The identity and target are found as the requester, so one that doesn't
+            // exist is denied the same as one that can't be seen.
+            let mut server_txn = server.read();
+            let r = AccessExplainEvent::from_parts(
+                audit,
+                &mut server_txn,
+                Event::from_impersonate_entry(admin),
+                *UUID_ANONYMOUS,
+                *UUID_DOES_NOT_EXIST,
+                AccessOperation::Search,
+            );
+            assert!(r.err() == Some(OperationError::AccessDenied));
+        })
+    }
+
+    #[test]
+    fn test_qs_self_ref_member_acps() {
+        run_test!(|server: &QueryServer, audit: &mut AuditScope| {
+            let mut server_txn = server.write(duration_from_epoch_now());
+
+            let e_owner: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "person", "account"],
+                    "name": ["owner1"],
+                    "uuid": ["8d2c6a41-5b3e-4f7a-9c1d-2e4f6a8b0c01"],
+                    "displayname": ["owner1"]
+                }
+            }"#,
+            );
+            let e_member: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "person", "account"],
+                    "name": ["member1"],
+                    "uuid": ["8d2c6a41-5b3e-4f7a-9c1d-2e4f6a8b0c02"],
+                    "displayname": ["member1"]
+                }
+            }"#,
+            );
+            let e_other: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "person", "account"],
+                    "name": ["other1"],
+                    "uuid": ["8d2c6a41-5b3e-4f7a-9c1d-2e4f6a8b0c03"],
+                    "displayname": ["other1"]
+                }
+            }"#,
+            );
+            let e_group: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "group"],
+                    "name": ["group1"],
+                    "uuid": ["8d2c6a41-5b3e-4f7a-9c1d-2e4f6a8b0c04"],
+                    "owner": ["8d2c6a41-5b3e-4f7a-9c1d-2e4f6a8b0c01"],
+                    "member": ["8d2c6a41-5b3e-4f7a-9c1d-2e4f6a8b0c02"]
+                }
+            }"#,
+            );
+            let e_acp: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "access_control_profile", "access_control_modify"],
+                    "name": ["acp_owned_group_members"],
+                    "uuid": ["8d2c6a41-5b3e-4f7a-9c1d-2e4f6a8b0c05"],
+                    "acp_receiver": ["{\"Eq\":[\"class\",\"account\"]}"],
+                    "acp_targetscope": ["{\"SelfRefMember\":\"owner\"}"],
+                    "acp_modify_removedattr": ["displayname"],
+                    "acp_modify_presentattr": ["displayname"]
+                }
+            }"#,
+            );
+
+            let ce = CreateEvent::new_internal(vec![e_owner, e_member, e_other, e_group, e_acp]);
+            assert!(server_txn.create(audit, &ce).is_ok());
+            assert!(server_txn.commit(audit).is_ok());
+
+            let mut server_txn = server.write(duration_from_epoch_now());
+            assert!(server_txn
+                .get_accesscontrols()
+                .get_self_ref_member_attrs()
+                .contains("owner"));
+
+            // The groups owned are found as the event is made.
+            let owner_ev = Event::from_rw_request(
+                audit,
+                &mut server_txn,
+                "8d2c6a41-5b3e-4f7a-9c1d-2e4f6a8b0c01",
+            )
+            .expect("failed");
+            let displayname_mod = |name: &str| unsafe {
+                ModifyEvent::new_impersonate(
+                    &owner_ev,
+                    filter!(f_eq("name", PartialValue::new_iutf8s(name))).into_valid(),
+                    filter!(f_eq("name", PartialValue::new_iutf8s(name))).into_valid(),
+                    ModifyList::new_list(vec![
+                        Modify::Purged("displayname".to_string()),
+                        Modify::Present("displayname".to_string(), Value::new_utf8s("changed")),
+                    ])
+                    .into_valid(),
+                )
+            };
+
+            // The owner can update the displayname of a member of their group ...
+            assert!(server_txn
+                .modify(audit, &displayname_mod("member1"))
+                .is_ok());
+            // ... but not of someone outside it.
+            assert!(server_txn
+                .modify(audit, &displayname_mod("other1"))
+                .is_err());
+
+            // Someone who owns no groups has no members to act on.
+            let other_ev = Event::from_rw_request(
+                audit,
+                &mut server_txn,
+                "8d2c6a41-5b3e-4f7a-9c1d-2e4f6a8b0c03",
+            )
+            .expect("failed");
+            assert!(other_ev.get_referrers("owner").unwrap().is_empty());
+            let me = unsafe {
+                ModifyEvent::new_impersonate(
+                    &other_ev,
+                    filter!(f_eq("name", PartialValue::new_iutf8s("member1"))).into_valid(),
+                    filter!(f_eq("name", PartialValue::new_iutf8s("member1"))).into_valid(),
+                    ModifyList::new_list(vec![Modify::Purged("displayname".to_string())])
+                        .into_valid(),
+                )
+            };
+            assert!(server_txn.modify(audit, &me).is_err());
+        })
+    }
+
+    #[test]
+    fn test_qs_self_ref_acps() {
+        run_test!(|server: &QueryServer, audit: &mut AuditScope| {
+            let mut server_txn = server.write(duration_from_epoch_now());
+
+            let e_manager: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "person", "account"],
+                    "name": ["manager1"],
+                    "uuid": ["3f1a7b52-6c0e-4d8f-9b2a-5e4c3d2b1a01"],
+                    "displayname": ["manager1"]
+                }
+            }"#,
+            );
+            let e_report: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "person", "account"],
+                    "name": ["report1"],
+                    "uuid": ["3f1a7b52-6c0e-4d8f-9b2a-5e4c3d2b1a02"],
+                    "displayname": ["report1"],
+                    "manager": ["3f1a7b52-6c0e-4d8f-9b2a-5e4c3d2b1a01"]
+                }
+            }"#,
+            );
+            let e_other: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "person", "account"],
+                    "name": ["other1"],
+                    "uuid": ["3f1a7b52-6c0e-4d8f-9b2a-5e4c3d2b1a03"],
+                    "displayname": ["other1"]
+                }
+            }"#,
+            );
+            let e_group: Entry<EntryInit, EntryNew> = Entry::unsafe_from_entry_str(
+                r#"{
+                "attrs": {
+                    "class": ["object", "group"],
+                    "name": ["group1"],
+                    "uuid": ["3f1a7b52-6c0e-4d8f-9b2a-5e4c3d2b1a04"],
+                    "owner": ["3f1a7b52-6c0e-4d8f-9b2a-5e4c3d2b1a01"]
+                }
+            }"#,
+            );
+
+            let ce = CreateEvent::new_internal(vec![e_manager, e_report, e_other, e_group]);
+            assert!(server_txn.create(audit, &ce).is_ok());
+
+            let manager = server_txn
+                .internal_search_uuid(
+                    audit,
+                    &Uuid::parse_str("3f1a7b52-6c0e-4d8f-9b2a-5e4c3d2b1a01").unwrap(),
+                )
+                .expect("failed");
+
+            let displayname_mod = || {
+                ModifyList::new_list(vec![
+                    Modify::Purged("displayname".to_string()),
+                    Modify::Present("displayname".to_string(), Value::new_utf8s("changed")),
+                ])
+            };
+
+            // The manager can update the displayname of their report ...
+            let me = unsafe {
+                ModifyEvent::new_impersonate_entry(
+                    manager.clone(),
+                    filter!(f_eq("name", PartialValue::new_iutf8s("report1"))),
+                    displayname_mod(),
+                )
+            };
+            assert!(server_txn.modify(audit, &me).is_ok());
+
+            // ... but not of someone who doesn't report to them.
+            let me = unsafe {
+                ModifyEvent::new_impersonate_entry(
+                    manager.clone(),
+                    filter!(f_eq("name", PartialValue::new_iutf8s("other1"))),
+                    displayname_mod(),
+                )
+            };
+            assert!(server_txn.modify(audit, &me).is_err());
+
+            // The owner of a group can change its members.
+            let me = unsafe {
+                ModifyEvent::new_impersonate_entry(
+                    manager.clone(),
+                    filter!(f_eq("name", PartialValue::new_iutf8s("group1"))),
+                    ModifyList::new_list(vec![Modify::Present(
+                        "member".to_string(),
+                        Value::new_refer_s("3f1a7b52-6c0e-4d8f-9b2a-5e4c3d2b1a03").unwrap(),
+                    )]),
+                )
+            };
+            assert!(server_txn.modify(audit, &me).is_ok());
+
+            // Owning the group gives no rights over its members, unless a profile grants
+            // them through SelfRefMember.
+            let me = unsafe {
+                ModifyEvent::new_impersonate_entry(
+                    manager,
+                    filter!(f_eq("name", PartialValue::new_iutf8s("other1"))),
+                    displayname_mod(),
+                )
+            };
+            assert!(server_txn.modify(audit, &me).is_err());
+
+            // And someone who is not the owner can not change the members.
+            let other = server_txn
+                .internal_search_uuid(
+                    audit,
+                    &Uuid::parse_str("3f1a7b52-6c0e-4d8f-9b2a-5e4c3d2b1a03").unwrap(),
+                )
+                .expect("failed");
+            let me = unsafe {
+                ModifyEvent::new_impersonate_entry(
+                    other,
+                    filter!(f_eq("name", PartialValue::new_iutf8s("group1"))),
+                    ModifyList::new_list(vec![Modify::Present(
+                        "member".to_string(),
+                        Value::new_refer_s("3f1a7b52-6c0e-4d8f-9b2a-5e4c3d2b1a02").unwrap(),
+                    )]),
+                )
+            };
+            assert!(server_txn.modify(audit, &me).is_err());
+        })
+    }
 }
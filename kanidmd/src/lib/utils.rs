@@ -1,3 +1,4 @@
+use crate::constants::BACKUP_CODE_COUNT;
 use std::time::{Duration, SystemTime};
 use uuid::{Builder, Uuid};
 
@@ -45,6 +46,19 @@ pub fn readable_password_from_random() -> String {
     )
 }
 
+pub fn backup_codes_from_random() -> Vec<String> {
+    (0..BACKUP_CODE_COUNT)
+        .map(|_| {
+            let trng = thread_rng();
+            format!(
+                "{}-{}",
+                trng.sample_iter(&DistinctAlpha).take(4).collect::<String>(),
+                trng.sample_iter(&DistinctAlpha).take(4).collect::<String>(),
+            )
+        })
+        .collect()
+}
+
 pub fn duration_from_epoch_now() -> Duration {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
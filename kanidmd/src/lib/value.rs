@@ -1,5 +1,7 @@
 use crate::be::dbvalue::{DbCidV1, DbValueCredV1, DbValueTaggedStringV1, DbValueV1};
 use crate::credential::Credential;
+use crate::idm::session::Session;
+use crate::jws::JwsKey;
 use crate::repl::cid::Cid;
 use kanidm_proto::v1::Filter as ProtoFilter;
 
@@ -105,6 +107,9 @@ pub enum SyntaxType {
     SERVICE_PRINCIPLE_NAME,
     UINT32,
     CID,
+    INT64,
+    JWS_KEY,
+    SESSION,
 }
 
 impl TryFrom<&str> for SyntaxType {
@@ -127,6 +132,9 @@ impl TryFrom<&str> for SyntaxType {
             "SERVICE_PRINCIPLE_NAME" => Ok(SyntaxType::SERVICE_PRINCIPLE_NAME),
             "UINT32" => Ok(SyntaxType::UINT32),
             "CID" => Ok(SyntaxType::CID),
+            "INT64" => Ok(SyntaxType::INT64),
+            "JWS_KEY" => Ok(SyntaxType::JWS_KEY),
+            "SESSION" => Ok(SyntaxType::SESSION),
             _ => Err(()),
         }
     }
@@ -151,6 +159,9 @@ impl TryFrom<usize> for SyntaxType {
             11 => Ok(SyntaxType::SERVICE_PRINCIPLE_NAME),
             12 => Ok(SyntaxType::UINT32),
             13 => Ok(SyntaxType::CID),
+            14 => Ok(SyntaxType::INT64),
+            15 => Ok(SyntaxType::JWS_KEY),
+            16 => Ok(SyntaxType::SESSION),
             _ => Err(()),
         }
     }
@@ -173,6 +184,9 @@ impl SyntaxType {
             SyntaxType::SERVICE_PRINCIPLE_NAME => 11,
             SyntaxType::UINT32 => 12,
             SyntaxType::CID => 13,
+            SyntaxType::INT64 => 14,
+            SyntaxType::JWS_KEY => 15,
+            SyntaxType::SESSION => 16,
         }
     }
 }
@@ -197,6 +211,9 @@ impl fmt::Display for SyntaxType {
                 SyntaxType::SERVICE_PRINCIPLE_NAME => "SERVICE_PRINCIPLE_NAME",
                 SyntaxType::UINT32 => "UINT32",
                 SyntaxType::CID => "CID",
+                SyntaxType::INT64 => "INT64",
+                SyntaxType::JWS_KEY => "JWS_KEY",
+                SyntaxType::SESSION => "SESSION",
             }
         )
     }
@@ -207,6 +224,8 @@ pub enum DataValue {
     Cred(Credential),
     SshKey(String),
     RadiusCred(String),
+    JwsKey(JwsKey),
+    Session(Session),
 }
 
 #[derive(Debug, Clone, Eq, Ord, PartialOrd, PartialEq, Deserialize, Serialize)]
@@ -228,6 +247,11 @@ pub enum PartialValue {
     Spn(String, String),
     Uint32(u32),
     Cid(Cid),
+    Int64(i64),
+    // The key id, matches to a DataValue.
+    JwsKey(Uuid),
+    // The session id, matches to a DataValue.
+    Session(Uuid),
 }
 
 impl PartialValue {
@@ -455,6 +479,21 @@ impl PartialValue {
         }
     }
 
+    pub fn new_int64(i: i64) -> Self {
+        PartialValue::Int64(i)
+    }
+
+    pub fn new_int64_str(i: &str) -> Option<Self> {
+        i64::from_str_radix(i, 10).ok().map(PartialValue::Int64)
+    }
+
+    pub fn is_int64(&self) -> bool {
+        match self {
+            PartialValue::Int64(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn new_cid(c: Cid) -> Self {
         PartialValue::Cid(c)
     }
@@ -470,6 +509,36 @@ impl PartialValue {
         }
     }
 
+    pub fn new_jws_key_id(kid: Uuid) -> Self {
+        PartialValue::JwsKey(kid)
+    }
+
+    pub fn new_jws_key_id_s(kid: &str) -> Option<Self> {
+        Uuid::parse_str(kid).map(PartialValue::JwsKey).ok()
+    }
+
+    pub fn is_jws_key(&self) -> bool {
+        match self {
+            PartialValue::JwsKey(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn new_session_id(id: Uuid) -> Self {
+        PartialValue::Session(id)
+    }
+
+    pub fn new_session_id_s(id: &str) -> Option<Self> {
+        Uuid::parse_str(id).map(PartialValue::Session).ok()
+    }
+
+    pub fn is_session(&self) -> bool {
+        match self {
+            PartialValue::Session(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn to_str(&self) -> Option<&str> {
         match self {
             PartialValue::Utf8(s) => Some(s.as_str()),
@@ -494,6 +563,7 @@ impl PartialValue {
         match (self, s) {
             (PartialValue::Cid(c1), PartialValue::Cid(c2)) => c1 < c2,
             (PartialValue::Uint32(u1), PartialValue::Uint32(u2)) => u1 < u2,
+            (PartialValue::Int64(i1), PartialValue::Int64(i2)) => i1 < i2,
             _ => false,
         }
     }
@@ -516,6 +586,11 @@ impl PartialValue {
             PartialValue::Uint32(u) => u.to_string(),
             // This will never work, we don't allow equality searching on Cid's
             PartialValue::Cid(_) => "_".to_string(),
+            PartialValue::Int64(i) => i.to_string(),
+            // Signing keys are never indexed, see generate_idx_eq_keys
+            PartialValue::JwsKey(_) => "_".to_string(),
+            // Nor are sessions, they are only found through their account.
+            PartialValue::Session(_) => "_".to_string(),
         }
     }
 
@@ -948,6 +1023,24 @@ impl Value {
         }
     }
 
+    pub fn new_int64(i: i64) -> Self {
+        Value {
+            pv: PartialValue::new_int64(i),
+            data: None,
+        }
+    }
+
+    pub fn new_int64_str(i: &str) -> Option<Self> {
+        PartialValue::new_int64_str(i).map(|iv| Value { pv: iv, data: None })
+    }
+
+    pub fn is_int64(&self) -> bool {
+        match &self.pv {
+            PartialValue::Int64(_) => true,
+            _ => false,
+        }
+    }
+
     pub fn new_cid(c: Cid) -> Self {
         Value {
             pv: PartialValue::new_cid(c),
@@ -962,6 +1055,54 @@ impl Value {
         }
     }
 
+    pub fn new_jws_key(k: JwsKey) -> Self {
+        Value {
+            pv: PartialValue::new_jws_key_id(*k.kid()),
+            data: Some(DataValue::JwsKey(k)),
+        }
+    }
+
+    pub fn is_jws_key(&self) -> bool {
+        match &self.pv {
+            PartialValue::JwsKey(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn to_jws_key(&self) -> Option<&JwsKey> {
+        match &self.pv {
+            PartialValue::JwsKey(_) => match &self.data {
+                Some(DataValue::JwsKey(k)) => Some(k),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn new_session(s: Session) -> Self {
+        Value {
+            pv: PartialValue::new_session_id(s.id),
+            data: Some(DataValue::Session(s)),
+        }
+    }
+
+    pub fn is_session(&self) -> bool {
+        match &self.pv {
+            PartialValue::Session(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn to_session(&self) -> Option<&Session> {
+        match &self.pv {
+            PartialValue::Session(_) => match &self.data {
+                Some(DataValue::Session(s)) => Some(s),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
     pub fn contains(&self, s: &PartialValue) -> bool {
         self.pv.contains(s)
     }
@@ -1047,6 +1188,18 @@ impl Value {
                 }),
                 data: None,
             }),
+            DbValueV1::IS(i) => Ok(Value {
+                pv: PartialValue::Int64(i),
+                data: None,
+            }),
+            DbValueV1::JK(dk) => Ok(Value {
+                pv: PartialValue::JwsKey(dk.k),
+                data: Some(DataValue::JwsKey(JwsKey::from(dk))),
+            }),
+            DbValueV1::SE(ds) => Ok(Value {
+                pv: PartialValue::Session(ds.u),
+                data: Some(DataValue::Session(Session::from(ds))),
+            }),
         }
     }
 
@@ -1110,6 +1263,15 @@ impl Value {
                 s: c.s_uuid,
                 t: c.ts,
             }),
+            PartialValue::Int64(i) => DbValueV1::IS(*i),
+            PartialValue::JwsKey(_) => match &self.data {
+                Some(DataValue::JwsKey(k)) => DbValueV1::JK(k.to_dbjwskeyv1()),
+                _ => panic!(),
+            },
+            PartialValue::Session(_) => match &self.data {
+                Some(DataValue::Session(s)) => DbValueV1::SE(s.to_dbsessionv1()),
+                _ => panic!(),
+            },
         }
     }
 
@@ -1178,6 +1340,13 @@ impl Value {
         }
     }
 
+    pub fn to_int64(&self) -> Option<i64> {
+        match &self.pv {
+            PartialValue::Int64(v) => Some(*v),
+            _ => None,
+        }
+    }
+
     pub fn to_partialvalue(&self) -> PartialValue {
         // Match on self to become a partialvalue.
         self.pv.clone()
@@ -1226,6 +1395,10 @@ impl Value {
             PartialValue::Spn(n, r) => format!("{}@{}", n, r),
             PartialValue::Uint32(u) => u.to_string(),
             PartialValue::Cid(c) => format!("{:?}_{}_{}", c.ts, c.d_uuid, c.s_uuid),
+            PartialValue::Int64(i) => i.to_string(),
+            // Only the key id is ever shown, never the key.
+            PartialValue::JwsKey(kid) => kid.to_hyphenated_ref().to_string(),
+            PartialValue::Session(id) => id.to_hyphenated_ref().to_string(),
         }
     }
 
@@ -1257,6 +1430,14 @@ impl Value {
                 },
                 None => false,
             },
+            PartialValue::JwsKey(kid) => match &self.data {
+                Some(DataValue::JwsKey(k)) => k.kid() == kid,
+                _ => false,
+            },
+            PartialValue::Session(id) => match &self.data {
+                Some(DataValue::Session(s)) => &s.id == id,
+                _ => false,
+            },
             _ => true,
         }
     }
@@ -1281,6 +1462,9 @@ impl Value {
             PartialValue::Spn(n, r) => vec![format!("{}@{}", n, r)],
             PartialValue::Uint32(u) => vec![u.to_string()],
             PartialValue::Cid(_) => vec![],
+            PartialValue::Int64(i) => vec![i.to_string()],
+            PartialValue::JwsKey(_) => vec![],
+            PartialValue::Session(_) => vec![],
         }
     }
 }
@@ -1411,6 +1595,28 @@ mod tests {
         assert!(idx_key == vidx_key);
     }
 
+    #[test]
+    fn test_value_int64() {
+        assert!(Value::new_int64_str("test").is_none());
+        assert!(Value::new_int64_str("9223372036854775808").is_none());
+        assert!(Value::new_int64_str("-9223372036854775809").is_none());
+
+        let i64v = Value::new_int64_str("-4000").unwrap();
+        let i64pv = PartialValue::new_int64_str("-4000").unwrap();
+
+        let idx_key = i64pv.get_idx_eq_key();
+        let vidx_key = i64v.generate_idx_eq_keys().pop().unwrap();
+
+        assert!(idx_key == vidx_key);
+
+        // Ordering must be numeric, not lexical, and negative values sort first.
+        assert!(PartialValue::new_int64(-10).lessthan(&PartialValue::new_int64(-2)));
+        assert!(PartialValue::new_int64(-1).lessthan(&PartialValue::new_int64(9)));
+        assert!(!PartialValue::new_int64(100).lessthan(&PartialValue::new_int64(20)));
+        // Different syntaxes never compare.
+        assert!(!PartialValue::new_int64(1).lessthan(&PartialValue::new_uint32(2)));
+    }
+
     #[test]
     fn test_value_cid() {
         assert!(PartialValue::new_cid_s("_").is_none());
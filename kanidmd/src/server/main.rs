@@ -29,6 +29,16 @@ struct ServerOpt {
     key_path: Option<PathBuf>,
     #[structopt(short = "b", long = "bindaddr")]
     bind: Option<String>,
+    #[structopt(long = "purge_expired_acp")]
+    purge_expired_acp: bool,
+    #[structopt(long = "origin")]
+    origin: Option<String>,
+    #[structopt(long = "argon2_m_cost")]
+    argon2_m_cost: Option<u32>,
+    #[structopt(long = "argon2_t_cost")]
+    argon2_t_cost: Option<u32>,
+    #[structopt(long = "argon2_p_cost")]
+    argon2_p_cost: Option<u32>,
     #[structopt(flatten)]
     commonopts: CommonOpt,
 }
@@ -122,6 +132,13 @@ fn main() {
             config.update_db_path(&sopt.commonopts.db_path);
             config.update_tls(&sopt.ca_path, &sopt.cert_path, &sopt.key_path);
             config.update_bind(&sopt.bind);
+            config.update_purge_expired_acp(sopt.purge_expired_acp);
+            config.update_origin(&sopt.origin);
+            config.update_crypto_policy(
+                &sopt.argon2_m_cost,
+                &sopt.argon2_t_cost,
+                &sopt.argon2_p_cost,
+            );
 
             let sys = actix::System::new("kanidm-server");
             create_server_core(config);